@@ -0,0 +1,299 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::open_with::{self, OpenRequest};
+use crate::{menu, paths, workspace};
+
+pub const FILE_NAME: &str = "bookmarks.json";
+pub const MENU_PREFIX: &str = "bookmark:";
+
+/// How long a bookmark outlives its file, unlisted, in case it comes back
+/// from a branch switch or an unmounted volume.
+const TOMBSTONE_SECS: u64 = 90 * 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Scope {
+    Global,
+    /// The bookmarks of the window's workspace
+    Workspace,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bookmark {
+    pub id: String,
+    pub path: String,
+    /// 1-based; the top of the file when unset
+    pub line: Option<u32>,
+    pub label: Option<String>,
+    /// Seconds since the epoch when the file was first found missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    missing_since: Option<u64>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Store {
+    global: Vec<Bookmark>,
+    /// By workspace root, as `paths::encode` writes it
+    workspaces: BTreeMap<String, Vec<Bookmark>>,
+}
+
+/// Bookmarks from FILE_NAME in the app data directory. Those of a workspace
+/// are kept there too rather than in its folder, as they're the user's own.
+#[derive(Default)]
+pub struct Bookmarks(Mutex<Store>);
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewBookmark {
+    path: String,
+    line: Option<u32>,
+    label: Option<String>,
+    scope: Option<Scope>,
+}
+
+fn file(app: &AppHandle) -> Result<PathBuf, String> {
+    paths::data_dir(app).map(|dir| dir.join(FILE_NAME))
+}
+
+pub fn load(app: &AppHandle) -> Bookmarks {
+    let store = file(app)
+        .ok()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default();
+    Bookmarks(Mutex::new(store))
+}
+
+fn save(app: &AppHandle, store: &Store) -> Result<(), String> {
+    let path = file(app)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let json = serde_json::to_string_pretty(store).map_err(|e| e.to_string())?;
+    std::fs::write(path, json).map_err(|e| e.to_string())
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Marks the bookmarks whose files went missing, brings back those whose
+/// files returned and forgets the ones missing for longer than
+/// TOMBSTONE_SECS; says whether anything changed.
+fn validate(bookmarks: &mut Vec<Bookmark>, exists: impl Fn(&Path) -> bool, now: u64) -> bool {
+    let mut changed = false;
+    bookmarks.retain_mut(|b| {
+        match (exists(&paths::decode(&b.path)), b.missing_since) {
+            (true, Some(_)) => b.missing_since = None,
+            (false, None) => b.missing_since = Some(now),
+            (false, Some(since)) if now.saturating_sub(since) > TOMBSTONE_SECS => {
+                changed = true;
+                return false;
+            }
+            _ => return true,
+        }
+        changed = true;
+        true
+    });
+    changed
+}
+
+fn root_of(app: &AppHandle, label: Option<&str>) -> Option<String> {
+    label.and_then(|label| workspace::root(app, label)).map(|root| paths::encode(&root))
+}
+
+/// The bookmarks of `scope`, with `root` the workspace's, whose files are
+/// there, in the order they were added. Validating them on the way saves
+/// what changed.
+fn list(app: &AppHandle, scope: Scope, root: Option<String>) -> Vec<Bookmark> {
+    let state = app.state::<Bookmarks>();
+    let mut store = state.0.lock().unwrap();
+    let bookmarks = match scope {
+        Scope::Global => Some(&mut store.global),
+        Scope::Workspace => root.and_then(|root| store.workspaces.get_mut(&root)),
+    };
+    let Some(bookmarks) = bookmarks else { return Vec::new() };
+    let changed = validate(bookmarks, Path::exists, now());
+    let listed = bookmarks.iter().filter(|b| b.missing_since.is_none()).cloned().collect();
+    if changed {
+        if let Err(e) = save(app, &store) {
+            log::warn!("Failed to save {}: {}", FILE_NAME, e);
+        }
+    }
+    listed
+}
+
+/// The global bookmarks, then those of each open workspace, by root.
+pub fn for_menu(app: &AppHandle) -> Vec<(Option<PathBuf>, Vec<Bookmark>)> {
+    let mut roots = workspace::roots(app);
+    roots.sort();
+    roots.dedup();
+    let mut sections = vec![(None, list(app, Scope::Global, None))];
+    for root in roots {
+        let bookmarks = list(app, Scope::Workspace, Some(paths::encode(&root)));
+        sections.push((Some(root), bookmarks));
+    }
+    sections.retain(|(_, bookmarks)| !bookmarks.is_empty());
+    sections
+}
+
+/// Rebuilds the menu for a window's new workspace when it has bookmarks.
+pub fn workspace_opened(app: &AppHandle, root: &Path) {
+    let has_bookmarks = app.state::<Bookmarks>().0.lock().unwrap().workspaces.contains_key(&paths::encode(root));
+    if has_bookmarks {
+        menu::rebuild(app);
+    }
+}
+
+/// What the menu shows: the label, else the file name and line.
+pub fn title(bookmark: &Bookmark) -> String {
+    if let Some(label) = bookmark.label.as_deref().filter(|l| !l.trim().is_empty()) {
+        return label.to_string();
+    }
+    let name = paths::decode(&bookmark.path).file_name().map_or(bookmark.path.clone(), |n| n.to_string_lossy().into_owned());
+    match bookmark.line {
+        Some(line) => format!("{}:{}", name, line),
+        None => name,
+    }
+}
+
+/// Changes the bookmarks and saves them, then rebuilds the menu and tells
+/// windows with `bookmarks-changed`.
+fn update<T>(app: &AppHandle, change: impl FnOnce(&mut Store) -> Result<T, String>) -> Result<T, String> {
+    let result = {
+        let state = app.state::<Bookmarks>();
+        let mut store = state.0.lock().unwrap();
+        let result = change(&mut store)?;
+        save(app, &store)?;
+        result
+    };
+    menu::rebuild(app);
+    let _ = app.emit("bookmarks-changed", ());
+    Ok(result)
+}
+
+fn new_id() -> Result<String, String> {
+    let mut bytes = [0u8; 8];
+    getrandom::fill(&mut bytes).map_err(|e| e.to_string())?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Adds `new` to `bookmarks`, or relabels the one already at its place.
+fn insert(bookmarks: &mut Vec<Bookmark>, new: NewBookmark, id: String) -> Bookmark {
+    let label = new.label.filter(|l| !l.trim().is_empty());
+    if let Some(existing) = bookmarks.iter_mut().find(|b| b.path == new.path && b.line == new.line) {
+        existing.label = label.or(existing.label.take());
+        existing.missing_since = None;
+        return existing.clone();
+    }
+    let bookmark = Bookmark { id, path: new.path, line: new.line, label, missing_since: None };
+    bookmarks.push(bookmark.clone());
+    bookmark
+}
+
+/// Opens the bookmark `id` in the window of its workspace, or in `label`'s.
+pub fn open(app: &AppHandle, label: Option<&str>, id: &str) -> Result<(), String> {
+    let (bookmark, root) = {
+        let state = app.state::<Bookmarks>();
+        let store = state.0.lock().unwrap();
+        let global = store.global.iter().map(|b| (b, None));
+        let scoped = store.workspaces.iter().flat_map(|(root, bookmarks)| bookmarks.iter().map(move |b| (b, Some(root.clone()))));
+        let (bookmark, root) = global.chain(scoped).find(|(b, _)| b.id == id).ok_or("There's no such bookmark")?;
+        (bookmark.clone(), root)
+    };
+    if !paths::decode(&bookmark.path).exists() {
+        return Err(format!("{} isn't there anymore", bookmark.path));
+    }
+    let target = root
+        .and_then(|root| workspace::window_with_root(app, &paths::decode(&root)))
+        .or_else(|| label.map(String::from))
+        .or_else(|| open_with::ready_windows(app).into_iter().next())
+        .ok_or("There's no window to open it in")?;
+    open_with::deliver_to(app, &target, vec![OpenRequest::Open { path: bookmark.path, line: bookmark.line, column: None }]);
+    Ok(())
+}
+
+/// Bookmarks a file, or a line of it, globally unless `scope` says the
+/// window's workspace.
+#[tauri::command]
+pub fn add_bookmark(app: AppHandle, window: tauri::Window, bookmark: NewBookmark) -> Result<Bookmark, String> {
+    if !paths::decode(&bookmark.path).is_absolute() {
+        return Err("Only saved files can be bookmarked".into());
+    }
+    if bookmark.line == Some(0) {
+        return Err("Lines count from 1".into());
+    }
+    let root = root_of(&app, Some(window.label()));
+    let id = new_id()?;
+    update(&app, |store| {
+        let bookmarks = match bookmark.scope.unwrap_or(Scope::Global) {
+            Scope::Global => &mut store.global,
+            Scope::Workspace => store.workspaces.entry(root.ok_or("The window has no workspace open")?).or_default(),
+        };
+        Ok(insert(bookmarks, bookmark, id))
+    })
+}
+
+#[tauri::command]
+pub fn list_bookmarks(app: AppHandle, window: tauri::Window, scope: Scope) -> Vec<Bookmark> {
+    list(&app, scope, root_of(&app, Some(window.label())))
+}
+
+#[tauri::command]
+pub fn remove_bookmark(app: AppHandle, id: String) -> Result<(), String> {
+    update(&app, |store| {
+        store.global.retain(|b| b.id != id);
+        for bookmarks in store.workspaces.values_mut() {
+            bookmarks.retain(|b| b.id != id);
+        }
+        store.workspaces.retain(|_, bookmarks| !bookmarks.is_empty());
+        Ok(())
+    })
+}
+
+#[tauri::command]
+pub fn open_bookmark(app: AppHandle, window: tauri::Window, id: String) -> Result<(), String> {
+    open(&app, Some(window.label()), &id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bookmark(path: &str, missing_since: Option<u64>) -> Bookmark {
+        Bookmark { id: path.into(), path: path.into(), line: None, label: None, missing_since }
+    }
+
+    #[test]
+    fn keeps_a_tombstone_for_missing_files() {
+        let mut bookmarks = vec![bookmark("/here", None), bookmark("/gone", None), bookmark("/back", Some(5)), bookmark("/old", Some(5))];
+        let exists = |path: &Path| matches!(path.to_str(), Some("/here" | "/back"));
+        assert!(validate(&mut bookmarks, exists, 10));
+        assert_eq!(bookmarks, [bookmark("/here", None), bookmark("/gone", Some(10)), bookmark("/back", None), bookmark("/old", Some(5))]);
+        assert!(!validate(&mut bookmarks, exists, 20));
+        assert!(validate(&mut bookmarks, exists, 6 + TOMBSTONE_SECS));
+        assert_eq!(bookmarks.iter().map(|b| b.path.as_str()).collect::<Vec<_>>(), ["/here", "/gone", "/back"]);
+    }
+
+    #[test]
+    fn relabels_a_bookmark_at_the_same_place() {
+        let mut bookmarks = Vec::new();
+        let new = |line, label: Option<&str>| NewBookmark { path: "/notes.md".into(), line, label: label.map(String::from), scope: None };
+        let first = insert(&mut bookmarks, new(Some(3), Some("Intro")), "a".into());
+        insert(&mut bookmarks, new(None, None), "b".into());
+        let again = insert(&mut bookmarks, new(Some(3), Some(" ")), "c".into());
+        assert_eq!(again, first);
+        let renamed = insert(&mut bookmarks, new(Some(3), Some("Usage")), "d".into());
+        assert_eq!((renamed.id.as_str(), renamed.label.as_deref()), ("a", Some("Usage")));
+        assert_eq!(bookmarks.len(), 2);
+        assert_eq!(title(&bookmarks[0]), "Usage");
+        assert_eq!(title(&bookmarks[1]), "notes.md");
+    }
+}
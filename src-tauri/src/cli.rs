@@ -0,0 +1,265 @@
+//! The command line skriv is started with, or a second instance passes on.
+//! clap parses it here, so the window gets `CliArgs`, with every path made
+//! absolute, rather than strings to pick apart. `skriv:` links are for
+//! `deep_link`, and `--ctl` for `control`, before any of this.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use clap::error::{ContextKind, ContextValue, ErrorKind};
+use clap::Parser;
+use serde::Serialize;
+
+use crate::{file_lock, paths};
+
+/// How often `--wait` looks at the files' locks
+const POLL: Duration = Duration::from_millis(250);
+/// How long `--wait` gives skriv to open the files before it stops waiting
+const OPEN_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Parser, Debug)]
+#[command(name = "skriv", version, about = "A text editor", after_help = "skriv --ctl <command> drives a running skriv; skriv --ctl lists the commands.")]
+struct Cli {
+    /// Files and folders to open, or http(s):// and sftp:// links
+    #[arg(value_name = "PATH")]
+    paths: Vec<String>,
+    /// Opens a file at a line, and a column if given
+    #[arg(short, long, value_name = "FILE:LINE[:COLUMN]", value_parser = location)]
+    goto: Option<Location>,
+    /// Opens them in a new window rather than the last used one
+    #[arg(short, long)]
+    new_window: bool,
+    /// Returns once the files are closed again, as $EDITOR has to
+    #[arg(short, long)]
+    wait: bool,
+    /// Compares two files
+    #[arg(short, long, num_args = 2, value_names = ["A", "B"])]
+    diff: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Location {
+    pub path: String,
+    pub line: u32,
+    pub column: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CliArgs {
+    /// Files and folders
+    pub files: Vec<String>,
+    /// `http(s)://` and `sftp://` links
+    pub urls: Vec<String>,
+    pub goto: Option<Location>,
+    /// The two files `--diff` compares
+    pub diff: Option<[String; 2]>,
+    pub new_window: bool,
+    pub wait: bool,
+}
+
+impl CliArgs {
+    /// Whether there's nothing to open.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.urls.is_empty() && self.goto.is_none() && self.diff.is_none()
+    }
+}
+
+/// `file:line` or `file:line:column`, taken from the right so a Windows
+/// drive letter stays in the path.
+fn location(text: &str) -> Result<Location, String> {
+    const USAGE: &str = "expected FILE:LINE[:COLUMN], counting from 1";
+    let number = |text: &str| text.parse::<u32>().ok().filter(|n| *n > 0);
+    let (rest, last) = text.rsplit_once(':').ok_or(USAGE)?;
+    let last = number(last).ok_or(USAGE)?;
+    let (path, line, column) = match rest.rsplit_once(':').and_then(|(path, line)| Some((path, number(line)?))) {
+        Some((path, line)) => (path, line, Some(last)),
+        None => (rest, last, None),
+    };
+    if path.is_empty() {
+        return Err(USAGE.into());
+    }
+    Ok(Location { path: path.to_string(), line, column })
+}
+
+fn is_url(arg: &str) -> bool {
+    let arg = arg.to_ascii_lowercase();
+    ["http://", "https://", "sftp://"].iter().any(|scheme| arg.starts_with(scheme))
+}
+
+/// Parses `args`, the program first, with paths resolved against `cwd`.
+pub fn parse(args: &[String], cwd: &Path) -> Result<CliArgs, clap::Error> {
+    // Older macOS passes a process serial number to apps started from Finder
+    let cli = Cli::try_parse_from(args.iter().filter(|arg| !arg.starts_with("-psn_")))?;
+    let absolute = |arg: &str| paths::encode(&cwd.join(paths::decode(arg)));
+    let (urls, files): (Vec<String>, Vec<String>) = cli.paths.into_iter().filter(|arg| !arg.starts_with("skriv:")).partition(|arg| is_url(arg));
+    Ok(CliArgs {
+        files: files.iter().map(|file| absolute(file)).collect(),
+        urls,
+        goto: cli.goto.map(|goto| Location { path: absolute(&goto.path), ..goto }),
+        diff: cli.diff.map(|pair| [absolute(&pair[0]), absolute(&pair[1])]),
+        new_window: cli.new_window,
+        wait: cli.wait,
+    })
+}
+
+/// `parse` for a launch, which may be from a file manager with nowhere to
+/// show a usage error. An unknown option is left out, and any other mistake
+/// drops the command line, each with a warning for the log rather than
+/// ending skriv. Only help and version, which were asked for, are errors.
+pub fn parse_leniently(args: &[String], cwd: &Path) -> Result<(CliArgs, Vec<String>), clap::Error> {
+    let mut args = args.to_vec();
+    let mut warnings = Vec::new();
+    loop {
+        let error = match parse(&args, cwd) {
+            Ok(cli) => return Ok((cli, warnings)),
+            Err(e) if matches!(e.kind(), ErrorKind::DisplayHelp | ErrorKind::DisplayVersion) => return Err(e),
+            Err(e) => e,
+        };
+        // clap's own first line, without the usage after it
+        let message = error.to_string().lines().next().unwrap_or_default().trim_start_matches("error: ").to_string();
+        let unknown = match (error.kind(), error.get(ContextKind::InvalidArg)) {
+            (ErrorKind::UnknownArgument, Some(ContextValue::String(unknown))) => {
+                args.iter().skip(1).position(|arg| arg == unknown || arg.strip_prefix(unknown.as_str()).is_some_and(|rest| rest.starts_with('=')))
+            }
+            _ => None,
+        };
+        match unknown {
+            Some(at) => {
+                warnings.push(format!("{}, ignored", message));
+                args.remove(at + 1);
+            }
+            None => {
+                warnings.push(format!("{}, so nothing on the command line was opened", message));
+                return Ok((CliArgs::default(), warnings));
+            }
+        }
+    }
+}
+
+/// The command line skriv was started with.
+pub struct LaunchArgs(pub CliArgs);
+
+#[tauri::command]
+pub fn get_cli_args(launch: tauri::State<'_, LaunchArgs>) -> CliArgs {
+    launch.0.clone()
+}
+
+/// Lets a release build on Windows, which has no console of its own, print
+/// to the one it was started from.
+pub fn attach_console() {
+    #[cfg(windows)]
+    unsafe {
+        use windows::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+        let _ = AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+/// Prints the error, or the help or version asked for, and exits.
+pub fn exit(error: clap::Error) -> ! {
+    attach_console();
+    error.exit()
+}
+
+/// The arguments for a skriv opening `args` without waiting.
+fn forwarded(args: &CliArgs) -> Vec<std::ffi::OsString> {
+    let mut forwarded: Vec<std::ffi::OsString> = Vec::new();
+    if paths::portable_root().is_some() {
+        forwarded.push(paths::PORTABLE_FLAG.into());
+    }
+    if args.new_window {
+        forwarded.push("--new-window".into());
+    }
+    if let Some(goto) = &args.goto {
+        let mut location = paths::decode(&goto.path).into_os_string();
+        location.push(format!(":{}", goto.line));
+        if let Some(column) = goto.column {
+            location.push(format!(":{}", column));
+        }
+        forwarded.extend(["--goto".into(), location]);
+    }
+    if let Some([a, b]) = &args.diff {
+        forwarded.extend(["--diff".into(), paths::decode(a).into_os_string(), paths::decode(b).into_os_string()]);
+    }
+    forwarded.push("--".into());
+    forwarded.extend(args.files.iter().chain(&args.urls).map(|arg| paths::decode(arg).into_os_string()));
+    forwarded
+}
+
+/// `--wait`: has skriv open `args`, by starting it again without the flag,
+/// and returns the exit code once the files are closed. Whether a skriv has
+/// a file open is told by its lock, so files it can't lock, like ones that
+/// don't exist yet, are given up on after OPEN_TIMEOUT.
+pub fn wait(args: &CliArgs) -> i32 {
+    attach_console();
+    let started = std::env::current_exe().and_then(|exe| std::process::Command::new(exe).args(forwarded(args)).spawn());
+    let mut skriv = match started {
+        Ok(skriv) => skriv,
+        Err(e) => {
+            eprintln!("skriv: {}", e);
+            return 1;
+        }
+    };
+    let files: Vec<PathBuf> = args.files.iter().chain(args.goto.as_ref().map(|goto| &goto.path)).map(|file| paths::decode(file)).filter(|file| !file.is_dir()).collect();
+    let start = Instant::now();
+    let mut opened = false;
+    loop {
+        // Reaped as it goes, whether it passed the files on or is the skriv they're open in
+        let _ = skriv.try_wait();
+        let held = files.iter().any(|file| file_lock::is_held(file));
+        opened |= held;
+        if !held && (opened || start.elapsed() > OPEN_TIMEOUT) {
+            return 0;
+        }
+        std::thread::sleep(POLL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_line(line: &str) -> Result<CliArgs, clap::Error> {
+        parse(&line.split_whitespace().map(String::from).collect::<Vec<_>>(), &std::env::temp_dir())
+    }
+
+    #[test]
+    fn parses_the_command_line() {
+        let at = |file: &str| paths::encode(&std::env::temp_dir().join(file));
+        let args = parse_line("skriv a.md https://example.com/x.md skriv://open -n --diff old.md new.md --goto src/c.rs:12:3").unwrap();
+        assert_eq!(args.files, [at("a.md")]);
+        assert_eq!(args.urls, ["https://example.com/x.md"]);
+        assert_eq!(args.diff, Some([at("old.md"), at("new.md")]));
+        assert_eq!(args.goto, Some(Location { path: at("src/c.rs"), line: 12, column: Some(3) }));
+        assert!(args.new_window && !args.wait);
+        assert!(parse_line("skriv -psn_0_1234").unwrap().is_empty());
+        for bad in ["skriv --force", "skriv --diff a.md", "skriv --goto a.md", "skriv --goto a.md:0", "skriv --goto :3"] {
+            assert!(parse_line(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn leaves_out_unknown_options_at_launch() {
+        let parse_leniently_line = |line: &str| parse_leniently(&line.split_whitespace().map(String::from).collect::<Vec<_>>(), &std::env::temp_dir());
+        let (args, warnings) = parse_leniently_line("skriv --force a.md --colour=red -n").unwrap();
+        assert_eq!(args.files, [paths::encode(&std::env::temp_dir().join("a.md"))]);
+        assert!(args.new_window);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("--force"), "{}", warnings[0]);
+        let (args, warnings) = parse_leniently_line("skriv a.md --goto a.md").unwrap();
+        assert!(args.is_empty());
+        assert_eq!(warnings.len(), 1);
+        assert!(parse_leniently_line("skriv a.md").unwrap().1.is_empty());
+        assert!(parse_leniently_line("skriv --help").is_err());
+    }
+
+    #[test]
+    fn takes_locations_from_the_right() {
+        assert_eq!(location("a.md:7"), Ok(Location { path: "a.md".into(), line: 7, column: None }));
+        assert_eq!(location(r"C:\notes\a.md:7:2"), Ok(Location { path: r"C:\notes\a.md".into(), line: 7, column: Some(2) }));
+        assert_eq!(location("12:7"), Ok(Location { path: "12".into(), line: 7, column: None }));
+        assert_eq!(location("x:y.md:7"), Ok(Location { path: "x:y.md".into(), line: 7, column: None }));
+        assert!(location(r"C:\notes\a.md").is_err());
+    }
+}
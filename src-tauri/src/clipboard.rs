@@ -0,0 +1,450 @@
+use std::path::{Component, Path, PathBuf};
+
+use serde::Deserialize;
+use tauri::Url;
+
+use crate::paths;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipboardKind {
+    /// `src/lib.rs:42`, relative to `root` when it's inside it
+    PathWithLine,
+    /// `[title](file:///…)`
+    MarkdownLink,
+    /// The file itself, for Finder, Explorer or a mail to paste
+    FileReference,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClipboardPayload {
+    pub path: String,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    /// The link text for a markdown link, else the file name
+    pub title: Option<String>,
+    pub root: Option<String>,
+}
+
+/// `path` as seen from the directory `base`, climbing out with `..` where
+/// needed. Paths on different Windows drives stay absolute.
+pub fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let mut path_parts = path.components().peekable();
+    let mut base_parts = base.components().peekable();
+    if path_parts.peek() != base_parts.peek() {
+        return path.to_path_buf();
+    }
+    while let (Some(a), Some(b)) = (path_parts.peek(), base_parts.peek()) {
+        if a != b {
+            break;
+        }
+        path_parts.next();
+        base_parts.next();
+    }
+    let mut relative: PathBuf = base_parts.filter(|c| matches!(c, Component::Normal(_))).map(|_| Component::ParentDir).collect();
+    relative.extend(path_parts);
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
+
+fn path_with_line(payload: &ClipboardPayload) -> String {
+    let path = paths::decode(&payload.path);
+    let shown = match payload.root.as_deref().map(paths::decode) {
+        Some(root) if path.starts_with(&root) => relative_path(&path, &root),
+        _ => path,
+    };
+    match (payload.line, payload.column) {
+        (Some(line), Some(column)) => format!("{}:{}:{}", shown.display(), line, column),
+        (Some(line), None) => format!("{}:{}", shown.display(), line),
+        _ => shown.display().to_string(),
+    }
+}
+
+fn markdown_link(payload: &ClipboardPayload) -> Result<String, String> {
+    let path = paths::decode(&payload.path);
+    let url = Url::from_file_path(&path).map_err(|_| format!("{} isn't an absolute path", payload.path))?;
+    let title = match &payload.title {
+        Some(title) => title.clone(),
+        None => path.file_name().map_or_else(|| payload.path.clone(), |name| name.to_string_lossy().into_owned()),
+    };
+    let mut escaped = String::with_capacity(title.len());
+    for c in title.chars() {
+        if matches!(c, '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    // Parentheses would end the link early
+    let url = url.as_str().replace('(', "%28").replace(')', "%29");
+    Ok(format!("[{}]({})", escaped, url))
+}
+
+/// Puts one of the richer flavors of a document reference on the system
+/// clipboard. The webview's clipboard can only hold text.
+#[tauri::command]
+pub fn copy_to_clipboard(kind: ClipboardKind, payload: ClipboardPayload) -> Result<(), String> {
+    match kind {
+        ClipboardKind::PathWithLine => platform::write_text(&path_with_line(&payload)),
+        ClipboardKind::MarkdownLink => platform::write_text(&markdown_link(&payload)?),
+        ClipboardKind::FileReference => {
+            let path = paths::decode(&payload.path);
+            if !path.exists() {
+                return Err(format!("{} doesn't exist", payload.path));
+            }
+            platform::write_files(&[path])
+        }
+    }
+}
+
+/// Files on the clipboard, such as ones copied in Finder or Explorer, made
+/// relative to the directory `relative_to` when it's given.
+#[tauri::command]
+pub fn get_clipboard_paths(relative_to: Option<String>) -> Result<Vec<String>, String> {
+    let files = platform::read_files()?;
+    Ok(files
+        .iter()
+        .map(|path| match relative_to.as_deref() {
+            Some(base) => relative_path(path, &paths::decode(base)),
+            None => path.clone(),
+        })
+        .map(|path| paths::encode(&path))
+        .collect())
+}
+
+pub fn write_text(text: &str) -> Result<(), String> {
+    platform::write_text(text)
+}
+
+/// The clipboard's text, if it holds any.
+pub fn read_text() -> Result<Option<String>, String> {
+    platform::read_text()
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::path::PathBuf;
+
+    use objc2::rc::Retained;
+    use objc2::runtime::ProtocolObject;
+    use objc2_app_kit::{NSPasteboard, NSPasteboardTypeFileURL, NSPasteboardTypeString, NSPasteboardWriting};
+    use objc2_foundation::{NSArray, NSString, NSURL};
+
+    pub fn write_text(text: &str) -> Result<(), String> {
+        let pasteboard = NSPasteboard::generalPasteboard();
+        pasteboard.clearContents();
+        // SAFETY: a constant
+        let kind = unsafe { NSPasteboardTypeString };
+        if !pasteboard.setString_forType(&NSString::from_str(text), kind) {
+            return Err("The pasteboard refused the text".into());
+        }
+        Ok(())
+    }
+
+    pub fn read_text() -> Result<Option<String>, String> {
+        // SAFETY: a constant
+        let kind = unsafe { NSPasteboardTypeString };
+        Ok(NSPasteboard::generalPasteboard().stringForType(kind).map(|text| text.to_string()))
+    }
+
+    pub fn write_files(paths: &[PathBuf]) -> Result<(), String> {
+        let urls = paths
+            .iter()
+            .map(|path| NSURL::from_file_path(path).ok_or_else(|| format!("Invalid path {}", path.display())))
+            .collect::<Result<Vec<_>, _>>()?;
+        let objects: Vec<Retained<ProtocolObject<dyn NSPasteboardWriting>>> =
+            urls.into_iter().map(ProtocolObject::from_retained).collect();
+        let pasteboard = NSPasteboard::generalPasteboard();
+        pasteboard.clearContents();
+        if !pasteboard.writeObjects(&NSArray::from_retained_slice(&objects)) {
+            return Err("The pasteboard refused the files".into());
+        }
+        Ok(())
+    }
+
+    pub fn read_files() -> Result<Vec<PathBuf>, String> {
+        let pasteboard = NSPasteboard::generalPasteboard();
+        // SAFETY: a constant
+        let kind = unsafe { NSPasteboardTypeFileURL };
+        let Some(items) = pasteboard.pasteboardItems() else { return Ok(Vec::new()) };
+        Ok(items
+            .iter()
+            .filter_map(|item| item.stringForType(kind))
+            .filter_map(|url| NSURL::URLWithString(&url))
+            // Finder copies file reference URLs, file:///.file/id=…
+            .filter_map(|url| url.filePathURL())
+            .filter_map(|url| url.to_file_path())
+            .collect())
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::os::windows::ffi::{OsStrExt, OsStringExt};
+    use std::path::PathBuf;
+
+    use windows::Win32::Foundation::{HANDLE, HGLOBAL};
+    use windows::Win32::System::DataExchange::{
+        CloseClipboard, EmptyClipboard, GetClipboardData, IsClipboardFormatAvailable, OpenClipboard, SetClipboardData,
+    };
+    use windows::Win32::System::Memory::{GlobalAlloc, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
+
+    const CF_UNICODETEXT: u32 = 13;
+    const CF_HDROP: u32 = 15;
+
+    /// The header of a CF_HDROP block, followed by the NUL-separated paths
+    #[repr(C)]
+    struct DropFiles {
+        files_offset: u32,
+        point: [i32; 2],
+        non_client: i32,
+        wide: i32,
+    }
+
+    /// Open until dropped. Another app may be holding the clipboard for a
+    /// moment, so opening is retried briefly.
+    struct Clipboard;
+
+    impl Clipboard {
+        fn open() -> Result<Self, String> {
+            let mut attempts = 0;
+            // SAFETY: no owner window; closed again on drop
+            while let Err(e) = unsafe { OpenClipboard(None) } {
+                attempts += 1;
+                if attempts == 10 {
+                    return Err(format!("The clipboard is in use: {}", e));
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            Ok(Clipboard)
+        }
+
+        fn set(&self, format: u32, bytes: &[u8]) -> Result<(), String> {
+            // SAFETY: the block is filled while locked and then belongs to the clipboard
+            unsafe {
+                let memory = GlobalAlloc(GMEM_MOVEABLE, bytes.len()).map_err(|e| e.to_string())?;
+                let data = GlobalLock(memory);
+                if data.is_null() {
+                    return Err("Couldn't lock clipboard memory".into());
+                }
+                std::ptr::copy_nonoverlapping(bytes.as_ptr(), data.cast(), bytes.len());
+                let _ = GlobalUnlock(memory);
+                SetClipboardData(format, Some(HANDLE(memory.0))).map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for Clipboard {
+        fn drop(&mut self) {
+            // SAFETY: opened in Clipboard::open
+            let _ = unsafe { CloseClipboard() };
+        }
+    }
+
+    fn wide(text: &str) -> Vec<u8> {
+        text.encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect()
+    }
+
+    pub fn write_text(text: &str) -> Result<(), String> {
+        let clipboard = Clipboard::open()?;
+        // SAFETY: the clipboard is open
+        unsafe { EmptyClipboard() }.map_err(|e| e.to_string())?;
+        clipboard.set(CF_UNICODETEXT, &wide(text))
+    }
+
+    pub fn read_text() -> Result<Option<String>, String> {
+        // SAFETY: a plain query
+        if unsafe { IsClipboardFormatAvailable(CF_UNICODETEXT) }.is_err() {
+            return Ok(None);
+        }
+        let _clipboard = Clipboard::open()?;
+        // SAFETY: the block stays the clipboard's and is only read while
+        // locked, up to the NUL it ends with
+        unsafe {
+            let handle = GetClipboardData(CF_UNICODETEXT).map_err(|e| e.to_string())?;
+            let memory = HGLOBAL(handle.0);
+            let data = GlobalLock(memory).cast::<u16>();
+            if data.is_null() {
+                return Err("Couldn't lock clipboard memory".into());
+            }
+            let mut len = 0;
+            while data.add(len).read_unaligned() != 0 {
+                len += 1;
+            }
+            let units: Vec<u16> = (0..len).map(|i| data.add(i).read_unaligned()).collect();
+            let _ = GlobalUnlock(memory);
+            Ok(Some(String::from_utf16_lossy(&units)))
+        }
+    }
+
+    pub fn write_files(paths: &[PathBuf]) -> Result<(), String> {
+        let header = DropFiles { files_offset: std::mem::size_of::<DropFiles>() as u32, point: [0, 0], non_client: 0, wide: 1 };
+        // SAFETY: a plain repr(C) struct, read as its bytes
+        let mut bytes = unsafe {
+            std::slice::from_raw_parts((&header as *const DropFiles).cast::<u8>(), std::mem::size_of::<DropFiles>())
+        }
+        .to_vec();
+        for path in paths {
+            bytes.extend(path.as_os_str().encode_wide().chain([0]).flat_map(u16::to_le_bytes));
+        }
+        bytes.extend([0, 0]);
+        let text = paths.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join("\r\n");
+        let clipboard = Clipboard::open()?;
+        // SAFETY: the clipboard is open
+        unsafe { EmptyClipboard() }.map_err(|e| e.to_string())?;
+        clipboard.set(CF_HDROP, &bytes)?;
+        clipboard.set(CF_UNICODETEXT, &wide(&text))
+    }
+
+    pub fn read_files() -> Result<Vec<PathBuf>, String> {
+        // SAFETY: a plain query
+        if unsafe { IsClipboardFormatAvailable(CF_HDROP) }.is_err() {
+            return Ok(Vec::new());
+        }
+        let _clipboard = Clipboard::open()?;
+        // SAFETY: the block stays the clipboard's; it's only read while locked,
+        // and within the size the header and the terminating NULs give
+        unsafe {
+            let handle = GetClipboardData(CF_HDROP).map_err(|e| e.to_string())?;
+            let memory = HGLOBAL(handle.0);
+            let data = GlobalLock(memory);
+            if data.is_null() {
+                return Err("Couldn't lock clipboard memory".into());
+            }
+            let header = &*(data as *const DropFiles);
+            let mut paths = Vec::new();
+            if header.wide != 0 {
+                let mut cursor = data.cast::<u8>().add(header.files_offset as usize).cast::<u16>();
+                loop {
+                    let mut len = 0;
+                    while cursor.add(len).read_unaligned() != 0 {
+                        len += 1;
+                    }
+                    if len == 0 {
+                        break;
+                    }
+                    let units: Vec<u16> = (0..len).map(|i| cursor.add(i).read_unaligned()).collect();
+                    paths.push(PathBuf::from(std::ffi::OsString::from_wide(&units)));
+                    cursor = cursor.add(len + 1);
+                }
+            }
+            let _ = GlobalUnlock(memory);
+            Ok(paths)
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::path::PathBuf;
+
+    use gtk::gdk::SELECTION_CLIPBOARD;
+    use gtk::{Clipboard, TargetEntry, TargetFlags};
+    use tauri::Url;
+
+    // The info numbers the clipboard hands back for each target
+    const URI_LIST: u32 = 0;
+    const GNOME_COPIED_FILES: u32 = 1;
+    const TEXT: u32 = 2;
+
+    pub fn write_text(text: &str) -> Result<(), String> {
+        Clipboard::get(&SELECTION_CLIPBOARD).set_text(text);
+        Ok(())
+    }
+
+    pub fn read_text() -> Result<Option<String>, String> {
+        Ok(Clipboard::get(&SELECTION_CLIPBOARD).wait_for_text().map(String::from))
+    }
+
+    pub fn write_files(paths: &[PathBuf]) -> Result<(), String> {
+        let uris = paths
+            .iter()
+            .map(|path| Url::from_file_path(path).map(String::from).map_err(|_| format!("Invalid path {}", path.display())))
+            .collect::<Result<Vec<_>, _>>()?;
+        let text = paths.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join("\n");
+        let targets = [
+            TargetEntry::new("text/uri-list", TargetFlags::empty(), URI_LIST),
+            // What Nautilus and its relatives paste files from
+            TargetEntry::new("x-special/gnome-copied-files", TargetFlags::empty(), GNOME_COPIED_FILES),
+            TargetEntry::new("UTF8_STRING", TargetFlags::empty(), TEXT),
+            TargetEntry::new("text/plain;charset=utf-8", TargetFlags::empty(), TEXT),
+        ];
+        let set = Clipboard::get(&SELECTION_CLIPBOARD).set_with_data(&targets, move |_, selection, info| match info {
+            URI_LIST => {
+                selection.set_uris(&uris.iter().map(String::as_str).collect::<Vec<_>>());
+            }
+            GNOME_COPIED_FILES => {
+                let data = format!("copy\n{}", uris.join("\n"));
+                selection.set(&selection.target(), 8, data.as_bytes());
+            }
+            _ => {
+                selection.set_text(&text);
+            }
+        });
+        if !set {
+            return Err("The clipboard refused the files".into());
+        }
+        Ok(())
+    }
+
+    pub fn read_files() -> Result<Vec<PathBuf>, String> {
+        let uris = Clipboard::get(&SELECTION_CLIPBOARD).wait_for_uris();
+        Ok(uris.iter().filter_map(|uri| Url::parse(uri).ok()?.to_file_path().ok()).collect())
+    }
+}
+
+#[cfg(not(any(target_os = "macos", windows, target_os = "linux")))]
+mod platform {
+    use std::path::PathBuf;
+
+    pub fn write_text(_text: &str) -> Result<(), String> {
+        Err("The clipboard isn't supported on this platform".into())
+    }
+
+    pub fn read_text() -> Result<Option<String>, String> {
+        Ok(None)
+    }
+
+    pub fn write_files(_paths: &[PathBuf]) -> Result<(), String> {
+        Err("The clipboard isn't supported on this platform".into())
+    }
+
+    pub fn read_files() -> Result<Vec<PathBuf>, String> {
+        Ok(Vec::new())
+    }
+}
+
+// The paths are unix ones
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn payload(path: &str) -> ClipboardPayload {
+        ClipboardPayload { path: path.into(), line: None, column: None, title: None, root: None }
+    }
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(relative_path(Path::new("/a/b/c.md"), Path::new("/a/b")), PathBuf::from("c.md"));
+        assert_eq!(relative_path(Path::new("/a/x/c.md"), Path::new("/a/b/d")), PathBuf::from("../../x/c.md"));
+        assert_eq!(relative_path(Path::new("/a/b"), Path::new("/a/b")), PathBuf::from("."));
+    }
+
+    #[test]
+    fn formats_references() {
+        let mut p = payload("/repo/src/lib.rs");
+        p.root = Some("/repo".into());
+        p.line = Some(42);
+        assert_eq!(path_with_line(&p), "src/lib.rs:42");
+        p.root = Some("/elsewhere".into());
+        assert_eq!(path_with_line(&p), "/repo/src/lib.rs:42");
+
+        let mut p = payload("/notes/My (draft).md");
+        assert_eq!(markdown_link(&p).unwrap(), "[My (draft).md](file:///notes/My%20%28draft%29.md)");
+        p.title = Some("[Draft]".into());
+        assert_eq!(markdown_link(&p).unwrap(), "[\\[Draft\\]](file:///notes/My%20%28draft%29.md)");
+    }
+}
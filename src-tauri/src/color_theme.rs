@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tauri::{AppHandle, Emitter, Manager, Theme};
+
+use crate::keybindings::Keybindings;
+use crate::{menu, paths, theme};
+
+pub const DIR_NAME: &str = "themes";
+pub const MENU_PREFIX: &str = "color_theme:";
+
+const BUILTIN: &[(&str, &str)] = &[
+    ("skriv-light", include_str!("themes/skriv-light.json")),
+    ("skriv-dark", include_str!("themes/skriv-dark.json")),
+];
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThemeInfo {
+    /// The file name without `.json`
+    pub id: String,
+    pub name: String,
+    pub kind: Theme,
+    pub builtin: bool,
+    /// Why a user theme can't be used; it's listed anyway so it can be fixed
+    pub error: Option<String>,
+}
+
+/// A token color rule, in Monaco's form: colors without the `#`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rule {
+    token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    foreground: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    background: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    font_style: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ColorTheme {
+    #[serde(flatten)]
+    pub info: ThemeInfo,
+    /// Workbench colors by Monaco's key, like `editor.background`
+    colors: BTreeMap<String, String>,
+    rules: Vec<Rule>,
+}
+
+pub fn dir(app: &AppHandle) -> Result<PathBuf, String> {
+    paths::config_dir(app).map(|dir| dir.join(DIR_NAME))
+}
+
+fn is_color(value: &str) -> bool {
+    value.strip_prefix('#').is_some_and(|hex| matches!(hex.len(), 3 | 4 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+fn color(value: &Value, key: &str) -> Result<String, String> {
+    match value.as_str() {
+        Some(color) if is_color(color) => Ok(color.to_string()),
+        _ => Err(format!("{}: {} isn't a color like #rrggbb", key, value)),
+    }
+}
+
+/// `field` of `object`, which is at `path` in the file; `""` for the top.
+fn string(object: &Map<String, Value>, field: &str, path: &str) -> Result<Option<String>, String> {
+    let key = if path.is_empty() { field.to_string() } else { format!("{}.{}", path, field) };
+    match object.get(field) {
+        None => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value.clone())),
+        Some(other) => Err(format!("{}: {} should be a string", key, other)),
+    }
+}
+
+fn rule(value: &Value, key: &str) -> Result<Rule, String> {
+    let Value::Object(object) = value else { return Err(format!("{}: should be an object", key)) };
+    if let Some(field) = object.keys().find(|f| !["token", "foreground", "background", "fontStyle"].contains(&f.as_str())) {
+        return Err(format!("{}.{}: unknown key", key, field));
+    }
+    let rule_color = |field: &str| {
+        object
+            .get(field)
+            .map(|value| color(value, &format!("{}.{}", key, field)).map(|c| c.trim_start_matches('#').to_string()))
+            .transpose()
+    };
+    Ok(Rule {
+        token: string(object, "token", key)?.ok_or_else(|| format!("{}.token: missing", key))?,
+        foreground: rule_color("foreground")?,
+        background: rule_color("background")?,
+        font_style: string(object, "fontStyle", key)?,
+    })
+}
+
+/// Checks a theme file against the schema, naming the key at fault when it
+/// doesn't fit.
+fn parse(id: &str, text: &str, builtin: bool) -> Result<ColorTheme, String> {
+    let value: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let Value::Object(file) = value else { return Err("The file should hold an object".into()) };
+    if let Some(key) = file.keys().find(|k| !["name", "kind", "colors", "rules"].contains(&k.as_str())) {
+        return Err(format!("{}: unknown key", key));
+    }
+    let name = string(&file, "name", "")?.ok_or("name: missing")?;
+    let kind = match file.get("kind").and_then(Value::as_str) {
+        Some("light") => Theme::Light,
+        Some("dark") => Theme::Dark,
+        _ => return Err("kind: should be \"light\" or \"dark\"".into()),
+    };
+    let colors = match file.get("colors") {
+        None => BTreeMap::new(),
+        Some(Value::Object(colors)) => colors
+            .iter()
+            .map(|(key, value)| Ok((key.clone(), color(value, &format!("colors.{}", key))?)))
+            .collect::<Result<_, String>>()?,
+        Some(_) => return Err("colors: should be an object".into()),
+    };
+    let rules = match file.get("rules") {
+        None => Vec::new(),
+        Some(Value::Array(rules)) => {
+            rules.iter().enumerate().map(|(i, value)| rule(value, &format!("rules[{}]", i))).collect::<Result<_, String>>()?
+        }
+        Some(_) => return Err("rules: should be an array".into()),
+    };
+    let info = ThemeInfo { id: id.to_string(), name, kind, builtin, error: None };
+    Ok(ColorTheme { info, colors, rules })
+}
+
+fn user_themes(app: &AppHandle) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = dir(app).and_then(|dir| std::fs::read_dir(dir).map_err(|e| e.to_string())) else { return Vec::new() };
+    let mut themes: Vec<(String, PathBuf)> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| Some((path.file_stem()?.to_str()?.to_string(), path)))
+        .collect();
+    themes.sort();
+    themes
+}
+
+/// Reads a theme by id. A user theme with a built-in one's id replaces it.
+pub fn read(app: &AppHandle, id: &str) -> Result<ColorTheme, String> {
+    if let Some((_, path)) = user_themes(app).into_iter().find(|(theme, _)| theme == id) {
+        let text = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        return parse(id, &text, false).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+    let (_, text) = BUILTIN.iter().find(|(theme, _)| *theme == id).ok_or_else(|| format!("No theme \"{}\"", id))?;
+    parse(id, text, true)
+}
+
+/// Built-in themes, then those in the themes folder, broken ones included.
+pub fn list(app: &AppHandle) -> Vec<ThemeInfo> {
+    let user = user_themes(app);
+    let mut themes: Vec<ThemeInfo> = BUILTIN
+        .iter()
+        .filter(|(id, _)| !user.iter().any(|(theme, _)| theme == id))
+        .filter_map(|(id, text)| parse(id, text, true).ok())
+        .map(|theme| theme.info)
+        .collect();
+    for (id, _) in user {
+        themes.push(match read(app, &id) {
+            Ok(theme) => theme.info,
+            Err(e) => ThemeInfo { name: id.clone(), id, kind: Theme::Light, builtin: false, error: Some(e) },
+        });
+    }
+    themes
+}
+
+/// Something in the themes folder changed: the Theme menu is rebuilt, and
+/// the theme in use reapplied so edits to it show right away.
+pub fn changed(app: &AppHandle) {
+    let keys = app.state::<Mutex<Keybindings>>().lock().unwrap().clone();
+    match menu::build(app, &keys) {
+        Ok(built) => {
+            let _ = app.set_menu(built);
+        }
+        Err(e) => log::warn!("Failed to rebuild the menu for themes: {}", e),
+    }
+    let _ = app.emit("themes-changed", list(app));
+    theme::mode_changed(app, theme::current_mode(app));
+}
+
+#[tauri::command]
+pub fn list_themes(app: AppHandle) -> Vec<ThemeInfo> {
+    list(&app)
+}
+
+#[tauri::command]
+pub fn read_theme(app: AppHandle, id: String) -> Result<ColorTheme, String> {
+    read(&app, &id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_themes_are_valid() {
+        for (id, text) in BUILTIN {
+            assert!(parse(id, text, true).is_ok(), "{}", id);
+        }
+    }
+
+    #[test]
+    fn reads_rules_in_monaco_form() {
+        let theme = parse(
+            "t",
+            r##"{ "name": "T", "kind": "dark", "rules": [{ "token": "comment", "foreground": "#6a9955", "fontStyle": "italic" }] }"##,
+            false,
+        )
+        .unwrap();
+        assert_eq!(theme.info.kind, Theme::Dark);
+        assert_eq!(theme.rules[0].foreground.as_deref(), Some("6a9955"));
+    }
+
+    #[test]
+    fn names_the_offending_key() {
+        let error = |text: &str| parse("t", text, false).err().unwrap();
+        assert_eq!(error(r#"{ "name": "T", "kind": "dim" }"#), r#"kind: should be "light" or "dark""#);
+        assert!(error(r#"{ "name": "T", "kind": "light", "colors": { "editor.background": "white" } }"#)
+            .starts_with("colors.editor.background:"));
+        assert_eq!(error(r#"{ "name": "T", "kind": "light", "rules": [{ "token": "a" }, {}] }"#), "rules[1].token: missing");
+        assert_eq!(error(r#"{ "name": "T", "kind": "light", "colours": {} }"#), "colours: unknown key");
+    }
+}
@@ -0,0 +1,62 @@
+use std::sync::Mutex;
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tauri::{AppHandle, Manager};
+
+use crate::{color_theme, keybindings, paths, settings};
+
+/// The watcher on the settings folder and its themes, managed so it lives
+/// as long as the app.
+pub struct ConfigWatcher {
+    _watcher: Mutex<RecommendedWatcher>,
+    paths: usize,
+}
+
+impl ConfigWatcher {
+    pub fn paths(&self) -> usize {
+        self.paths
+    }
+}
+
+pub fn start(app: &AppHandle) -> Result<(), String> {
+    let dir = paths::config_dir(app)?;
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let themes_dir = dir.join(color_theme::DIR_NAME);
+    let _ = std::fs::create_dir_all(&themes_dir);
+
+    let handle = app.clone();
+    let themes = themes_dir.clone();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        let Ok(event) = res else { return };
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        let touches = |name: &str| event.paths.iter().any(|p| p.file_name().is_some_and(|f| f == name));
+        if touches(keybindings::FILE_NAME) {
+            let app = handle.clone();
+            let _ = handle.run_on_main_thread(move || {
+                if let Err(e) = keybindings::apply(&app) {
+                    log::warn!("Failed to reload keybindings: {}", e);
+                }
+            });
+        }
+        if touches(settings::FILE_NAME) {
+            let app = handle.clone();
+            let _ = handle.run_on_main_thread(move || settings::reload(&app));
+        }
+        if event.paths.iter().any(|p| p.starts_with(&themes)) {
+            let app = handle.clone();
+            let _ = handle.run_on_main_thread(move || color_theme::changed(&app));
+        }
+    })
+    .map_err(|e| e.to_string())?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive).map_err(|e| e.to_string())?;
+    let mut paths = 1;
+    match watcher.watch(&themes_dir, RecursiveMode::NonRecursive) {
+        Ok(()) => paths += 1,
+        Err(e) => log::warn!("Failed to watch {}: {}", themes_dir.display(), e),
+    }
+
+    app.manage(ConfigWatcher { _watcher: Mutex::new(watcher), paths });
+    Ok(())
+}
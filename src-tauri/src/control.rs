@@ -0,0 +1,631 @@
+//! A local endpoint scripts drive skriv through, on when
+//! `automation.enabled` is set. It's a unix socket in the app data folder,
+//! or a named pipe on Windows, speaking one JSON object per line:
+//!
+//! ```text
+//! {"token": "…", "id": 1, "command": "open", "args": {"path": "/abs/file.md", "line": 10}}
+//! {"id": 1, "ok": true, "result": null}
+//! ```
+//!
+//! The token is written next to the socket, readable only by the user, and
+//! changes each time the endpoint starts. `skriv --ctl` is a client for it.
+//! Commands about tabs are answered by the windows' frontends, through a
+//! `control-request` event and `control_reply`.
+
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::oneshot;
+
+use crate::open_with::{self, OpenRequest};
+use crate::settings::Settings;
+use crate::window::{self, WindowKind};
+use crate::{cli, menu, paths};
+
+pub const FLAG: &str = "--ctl";
+
+const DIR_NAME: &str = "control";
+#[cfg(unix)]
+const SOCKET_NAME: &str = "control.sock";
+const TOKEN_NAME: &str = "control.token";
+/// Longer requests are refused and the connection closed
+const MAX_LINE: usize = 64 * 1024;
+/// How long a window gets to answer
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+const NOT_RUNNING: &str = "skriv isn't running, or \"automation\": { \"enabled\": true } isn't in its settings";
+
+const USAGE: &str = "usage: skriv --ctl <command>
+
+  open <file> [--line N] [--column N]
+  focus [<file>]
+  list-documents
+  save [<file> | --all]
+  run-task <program> [<args>...]
+  get-diagnostics [<file>]";
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The running endpoint, and the windows' answers being waited for.
+#[derive(Default)]
+pub struct Control {
+    server: Mutex<Option<JoinHandle<()>>>,
+    /// What requests must carry; `None` while stopped
+    token: Mutex<Option<String>>,
+    replies: Mutex<HashMap<u64, oneshot::Sender<Result<Value, String>>>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Request {
+    token: String,
+    /// Echoed in the response
+    id: Option<u64>,
+    command: String,
+    args: Option<Value>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(tag = "command", content = "args", rename_all = "kebab-case", deny_unknown_fields)]
+enum Command {
+    Open { path: String, line: Option<u32>, column: Option<u32> },
+    /// Brings the window with `path` open to the front, or the last used one
+    Focus { path: Option<String> },
+    ListDocuments {},
+    /// The active tab of the focused window, unless given a path or `all`
+    Save {
+        path: Option<String>,
+        #[serde(default)]
+        all: bool,
+    },
+    /// Runs in the focused window's task panel
+    RunTask {
+        cmd: String,
+        #[serde(default)]
+        args: Vec<String>,
+        cwd: Option<String>,
+    },
+    GetDiagnostics { path: Option<String> },
+}
+
+#[derive(Clone, Serialize)]
+struct ControlRequest<'a> {
+    id: u64,
+    command: &'a str,
+    args: Value,
+}
+
+fn absolute(path: &str) -> Result<(), String> {
+    if path.contains('\0') || !Path::new(path).is_absolute() {
+        return Err(format!("{} isn't an absolute path", path));
+    }
+    Ok(())
+}
+
+impl Command {
+    fn parse(command: String, args: Option<Value>) -> Result<Command, String> {
+        let request = json!({ "command": command, "args": args.unwrap_or_else(|| json!({})) });
+        let command: Command = serde_json::from_value(request).map_err(|e| format!("Invalid request: {}", e))?;
+        command.validate()?;
+        Ok(command)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            Command::Open { path, line, column } => {
+                absolute(path)?;
+                if *line == Some(0) || *column == Some(0) {
+                    return Err("Lines and columns start at 1".into());
+                }
+                if column.is_some() && line.is_none() {
+                    return Err("A column needs a line".into());
+                }
+                Ok(())
+            }
+            Command::Focus { path } | Command::GetDiagnostics { path } => path.as_deref().map_or(Ok(()), absolute),
+            Command::ListDocuments {} => Ok(()),
+            Command::Save { path, all } => {
+                if *all && path.is_some() {
+                    return Err("Save takes a path or all, not both".into());
+                }
+                path.as_deref().map_or(Ok(()), absolute)
+            }
+            Command::RunTask { cmd, args, cwd } => {
+                if cmd.trim().is_empty() {
+                    return Err("No program to run".into());
+                }
+                if std::iter::once(cmd).chain(args).any(|arg| arg.contains('\0')) {
+                    return Err("Arguments can't hold NUL".into());
+                }
+                match cwd {
+                    Some(cwd) if !Path::new(cwd).is_dir() => Err(format!("{} isn't a folder", cwd)),
+                    Some(cwd) => absolute(cwd),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// Compares without stopping at the first difference, so timing doesn't
+/// give the token away.
+fn same_token(given: &str, expected: &str) -> bool {
+    given.len() == expected.len() && given.bytes().zip(expected.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn new_token() -> Result<String, String> {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes).map_err(|e| e.to_string())?;
+    Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// Named after the token, so only someone who can read it finds the pipe,
+/// and nobody can set one up in its place beforehand.
+#[cfg(windows)]
+fn pipe_name(token: &str) -> String {
+    use sha2::{Digest, Sha256};
+    let digest = Sha256::digest(token.as_bytes());
+    let hex: String = digest.iter().take(16).map(|b| format!("{:02x}", b)).collect();
+    format!(r"\\.\pipe\skriv-control-{}", hex)
+}
+
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+        builder.mode(0o700).create(dir)?;
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+    }
+    #[cfg(not(unix))]
+    builder.create(dir)
+}
+
+fn write_private(path: &Path, text: &str) -> std::io::Result<()> {
+    // A file left from before keeps its permissions otherwise
+    let _ = std::fs::remove_file(path);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(text.as_bytes())
+}
+
+fn response(id: Option<u64>, outcome: Result<Value, String>) -> Value {
+    match outcome {
+        Ok(result) => json!({ "id": id, "ok": true, "result": result }),
+        Err(error) => json!({ "id": id, "ok": false, "error": error }),
+    }
+}
+
+async fn respond(app: &AppHandle, line: &[u8]) -> Value {
+    let request: Request = match serde_json::from_slice(line) {
+        Ok(request) => request,
+        Err(e) => return response(None, Err(format!("Invalid request: {}", e))),
+    };
+    let authorized = app.state::<Control>().token.lock().unwrap().as_deref().is_some_and(|token| same_token(&request.token, token));
+    if !authorized {
+        return response(request.id, Err("Invalid token".into()));
+    }
+    let outcome = match Command::parse(request.command, request.args) {
+        Ok(command) => run(app, command).await,
+        Err(e) => Err(e),
+    };
+    response(request.id, outcome)
+}
+
+/// Answers requests on one connection until it closes.
+async fn serve(app: AppHandle, stream: impl AsyncRead + AsyncWrite) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = tokio::io::BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match (&mut reader).take(MAX_LINE as u64 + 1).read_until(b'\n', &mut line).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        let too_long = line.len() > MAX_LINE;
+        let reply = if too_long { response(None, Err("Request too long".into())) } else { respond(&app, &line).await };
+        let mut reply = reply.to_string();
+        reply.push('\n');
+        if writer.write_all(reply.as_bytes()).await.is_err() || too_long {
+            break;
+        }
+    }
+}
+
+#[cfg(unix)]
+fn listen(app: &AppHandle, dir: &Path, _token: &str) -> Result<JoinHandle<()>, String> {
+    use std::os::unix::fs::PermissionsExt;
+    let path = dir.join(SOCKET_NAME);
+    // Left behind by a skriv that didn't get to clean up
+    let _ = std::fs::remove_file(&path);
+    let listener = std::os::unix::net::UnixListener::bind(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).map_err(|e| e.to_string())?;
+    listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+    let app = app.clone();
+    Ok(tauri::async_runtime::spawn(async move {
+        let listener = match tokio::net::UnixListener::from_std(listener) {
+            Ok(listener) => listener,
+            Err(e) => {
+                log::warn!("Failed to listen on {}: {}", SOCKET_NAME, e);
+                return;
+            }
+        };
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tauri::async_runtime::spawn(serve(app.clone(), stream));
+                }
+                Err(e) => {
+                    log::warn!("Control socket stopped: {}", e);
+                    return;
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(windows)]
+fn listen(app: &AppHandle, _dir: &Path, token: &str) -> Result<JoinHandle<()>, String> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+    let name = pipe_name(token);
+    let app = app.clone();
+    Ok(tauri::async_runtime::spawn(async move {
+        let create = |first: bool| ServerOptions::new().first_pipe_instance(first).reject_remote_clients(true).create(&name);
+        let mut server = match create(true) {
+            Ok(server) => server,
+            Err(e) => {
+                log::warn!("Failed to create the control pipe: {}", e);
+                return;
+            }
+        };
+        loop {
+            // The next client needs an instance waiting before this one is handed off
+            let next = match server.connect().await.and_then(|()| create(false)) {
+                Ok(next) => next,
+                Err(e) => {
+                    log::warn!("Control pipe stopped: {}", e);
+                    return;
+                }
+            };
+            let connected = std::mem::replace(&mut server, next);
+            tauri::async_runtime::spawn(serve(app.clone(), connected));
+        }
+    }))
+}
+
+fn start(app: &AppHandle) -> Result<JoinHandle<()>, String> {
+    let dir = paths::data_dir(app)?.join(DIR_NAME);
+    create_private_dir(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let token = new_token()?;
+    let server = listen(app, &dir, &token)?;
+    if let Err(e) = write_private(&dir.join(TOKEN_NAME), &token) {
+        server.abort();
+        return Err(format!("{}: {}", TOKEN_NAME, e));
+    }
+    *app.state::<Control>().token.lock().unwrap() = Some(token);
+    Ok(server)
+}
+
+/// Closes the endpoint and removes its files; connections still open get
+/// refused from here on.
+pub fn stop(app: &AppHandle) {
+    let control = app.state::<Control>();
+    let Some(server) = control.server.lock().unwrap().take() else { return };
+    server.abort();
+    *control.token.lock().unwrap() = None;
+    if let Ok(dir) = paths::data_dir(app).map(|dir| dir.join(DIR_NAME)) {
+        let _ = std::fs::remove_file(dir.join(TOKEN_NAME));
+        #[cfg(unix)]
+        let _ = std::fs::remove_file(dir.join(SOCKET_NAME));
+    }
+}
+
+/// Starts or stops the endpoint to match `automation.enabled`.
+pub fn apply(app: &AppHandle) {
+    let enabled = app.state::<Mutex<Settings>>().lock().unwrap().automation.enabled;
+    let running = app.state::<Control>().server.lock().unwrap().is_some();
+    if !enabled {
+        stop(app);
+    } else if !running {
+        match start(app) {
+            Ok(server) => *app.state::<Control>().server.lock().unwrap() = Some(server),
+            Err(e) => log::warn!("Failed to start the control socket: {}", e),
+        }
+    }
+}
+
+/// Editor windows that are listening, the focused one first.
+fn windows(app: &AppHandle) -> Result<Vec<String>, String> {
+    let mut labels: Vec<String> = open_with::ready_windows(app).into_iter().filter(|label| window::kind(app, label) == WindowKind::Editor).collect();
+    if let Some(focused) = menu::focused_window(app) {
+        if let Some(at) = labels.iter().position(|label| label == focused.label()) {
+            let focused = labels.remove(at);
+            labels.insert(0, focused);
+        }
+    }
+    if labels.is_empty() {
+        return Err("No window is open".into());
+    }
+    Ok(labels)
+}
+
+/// Has the window `label` carry out `command` and waits for its answer.
+async fn ask(app: AppHandle, label: String, command: &'static str, args: Value) -> Result<Value, String> {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let (sender, receiver) = oneshot::channel();
+    app.state::<Control>().replies.lock().unwrap().insert(id, sender);
+    let answer = match app.emit_to(&label, "control-request", ControlRequest { id, command, args }) {
+        Ok(()) => tokio::time::timeout(REPLY_TIMEOUT, receiver).await,
+        Err(e) => return Err(e.to_string()),
+    };
+    app.state::<Control>().replies.lock().unwrap().remove(&id);
+    match answer {
+        Ok(Ok(outcome)) => outcome,
+        Ok(Err(_)) => Err(format!("Window {} closed", label)),
+        Err(_) => Err(format!("Window {} didn't answer", label)),
+    }
+}
+
+/// Asks every window at once, and gives their answers in the same order.
+async fn ask_all(app: &AppHandle, labels: &[String], command: &'static str, args: Value) -> Result<Vec<Value>, String> {
+    let pending: Vec<_> = labels.iter().map(|label| tauri::async_runtime::spawn(ask(app.clone(), label.clone(), command, args.clone()))).collect();
+    let mut answers = Vec::new();
+    for answer in pending {
+        answers.push(answer.await.map_err(|e| e.to_string())??);
+    }
+    Ok(answers)
+}
+
+fn items(answer: Value) -> Vec<Value> {
+    match answer {
+        Value::Array(items) => items,
+        _ => Vec::new(),
+    }
+}
+
+async fn run(app: &AppHandle, command: Command) -> Result<Value, String> {
+    match command {
+        Command::Open { path, line, column } => {
+            let request = if paths::decode(&path).is_dir() { OpenRequest::Folder { path } } else { OpenRequest::Open { path, line, column } };
+            open_with::deliver(app, vec![request]);
+            Ok(Value::Null)
+        }
+        Command::Focus { path } => {
+            let labels = windows(app)?;
+            let label = match path {
+                Some(path) => {
+                    let answers = ask_all(app, &labels, "focus", json!({ "path": path })).await?;
+                    let at = answers.iter().position(|shown| shown == true).ok_or_else(|| format!("{} isn't open", path))?;
+                    labels[at].clone()
+                }
+                None => labels[0].clone(),
+            };
+            if let Some(window) = app.get_webview_window(&label) {
+                let _ = window.unminimize();
+                let _ = window.set_focus();
+            }
+            Ok(Value::Null)
+        }
+        Command::ListDocuments {} => {
+            let labels = windows(app)?;
+            let answers = ask_all(app, &labels, "list-documents", json!({})).await?;
+            let mut documents = Vec::new();
+            for (label, answer) in labels.iter().zip(answers) {
+                for mut document in items(answer) {
+                    document["window"] = json!(label);
+                    documents.push(document);
+                }
+            }
+            Ok(Value::Array(documents))
+        }
+        Command::Save { path, all } => {
+            let labels = windows(app)?;
+            let labels = if path.is_some() || all { &labels[..] } else { &labels[..1] };
+            let answers = ask_all(app, labels, "save", json!({ "path": path, "all": all })).await?;
+            let saved: Vec<Value> = answers.into_iter().flat_map(items).collect();
+            match path {
+                Some(path) if saved.is_empty() => Err(format!("{} isn't open", path)),
+                _ => Ok(Value::Array(saved)),
+            }
+        }
+        Command::RunTask { cmd, args, cwd } => {
+            let label = windows(app)?.swap_remove(0);
+            ask(app.clone(), label, "run-task", json!({ "cmd": cmd, "args": args, "cwd": cwd })).await
+        }
+        Command::GetDiagnostics { path } => {
+            let label = windows(app)?.swap_remove(0);
+            let mut diagnostics = items(ask(app.clone(), label, "get-diagnostics", json!({})).await?);
+            if let Some(path) = path {
+                diagnostics.retain(|d| d["path"] == json!(path));
+            }
+            Ok(Value::Array(diagnostics))
+        }
+    }
+}
+
+/// A window's answer to a `control-request`.
+#[tauri::command]
+pub fn control_reply(control: tauri::State<'_, Control>, id: u64, result: Option<Value>, error: Option<String>) {
+    if let Some(sender) = control.replies.lock().unwrap().remove(&id) {
+        let _ = sender.send(error.map_or_else(|| Ok(result.unwrap_or(Value::Null)), Err));
+    }
+}
+
+/// The request `skriv --ctl <args>` stands for, with paths made absolute
+/// against `cwd`.
+fn request_for(args: &[String], cwd: &Path) -> Result<Value, String> {
+    let Some((command, rest)) = args.split_first() else { return Err(USAGE.into()) };
+    let cwd_text = paths::encode(cwd);
+    if command == "run-task" {
+        // Everything after the program is its own
+        let (cmd, args) = rest.split_first().ok_or(USAGE)?;
+        return Ok(json!({ "command": command, "args": { "cmd": cmd, "args": args, "cwd": cwd_text } }));
+    }
+    let mut files = Vec::new();
+    let (mut line, mut column, mut all) = (None, None, false);
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--line" | "--column" => {
+                let number = rest.next().and_then(|n| n.parse::<u32>().ok()).ok_or_else(|| format!("{} takes a number", arg))?;
+                if arg == "--line" {
+                    line = Some(number);
+                } else {
+                    column = Some(number);
+                }
+            }
+            "--all" => all = true,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}\n\n{}", arg, USAGE)),
+            _ => files.push(paths::encode(&cwd.join(paths::decode(arg)))),
+        }
+    }
+    let position = line.is_some() || column.is_some();
+    let file = files.first();
+    let args = match command.as_str() {
+        "open" if files.len() == 1 && !all => json!({ "path": file, "line": line, "column": column }),
+        "focus" | "get-diagnostics" if files.len() <= 1 && !position && !all => json!({ "path": file }),
+        "save" if files.len() <= 1 && !position && !(all && file.is_some()) => json!({ "path": file, "all": all }),
+        "list-documents" if files.is_empty() && !position && !all => json!({}),
+        _ => return Err(USAGE.into()),
+    };
+    Ok(json!({ "command": command, "args": args }))
+}
+
+#[cfg(unix)]
+fn connect(dir: &Path, _token: &str) -> std::io::Result<std::os::unix::net::UnixStream> {
+    std::os::unix::net::UnixStream::connect(dir.join(SOCKET_NAME))
+}
+
+#[cfg(windows)]
+fn connect(_dir: &Path, token: &str) -> std::io::Result<std::fs::File> {
+    OpenOptions::new().read(true).write(true).open(pipe_name(token))
+}
+
+fn exchange(mut stream: impl Read + Write, line: &str) -> std::io::Result<String> {
+    stream.write_all(line.as_bytes())?;
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    Ok(response)
+}
+
+fn send(identifier: &str, mut request: Value) -> Result<Value, String> {
+    let dir = paths::data_dir_for(identifier).ok_or(NOT_RUNNING)?.join(DIR_NAME);
+    let token = std::fs::read_to_string(dir.join(TOKEN_NAME)).map_err(|_| NOT_RUNNING)?;
+    let token = token.trim();
+    request["token"] = json!(token);
+    let stream = connect(&dir, token).map_err(|_| NOT_RUNNING)?;
+    let response = exchange(stream, &format!("{}\n", request)).map_err(|e| e.to_string())?;
+    let response: Value = serde_json::from_str(&response).map_err(|e| format!("Invalid response: {}", e))?;
+    if response["ok"] == true {
+        Ok(response["result"].clone())
+    } else {
+        Err(response["error"].as_str().unwrap_or("The request failed").to_string())
+    }
+}
+
+/// `skriv --ctl …`: sends one command to the running skriv and prints the
+/// answer. Returns the exit code, 2 for a command line it doesn't take.
+pub fn client(identifier: &str, args: &[String]) -> i32 {
+    cli::attach_console();
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let request = match request_for(args, &cwd) {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    match send(identifier, request) {
+        Ok(Value::Null) => 0,
+        Ok(Value::String(text)) => {
+            println!("{}", text);
+            0
+        }
+        Ok(result) => {
+            println!("{}", serde_json::to_string_pretty(&result).unwrap_or_default());
+            0
+        }
+        Err(e) => {
+            eprintln!("skriv: {}", e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(request: Value) -> Result<Command, String> {
+        let request: Request = serde_json::from_value(request).map_err(|e| e.to_string())?;
+        Command::parse(request.command, request.args)
+    }
+
+    #[test]
+    fn takes_only_well_formed_requests() {
+        let file = std::env::temp_dir().join("a.md").to_string_lossy().into_owned();
+        let open = json!({ "token": "t", "id": 3, "command": "open", "args": { "path": file, "line": 10 } });
+        assert_eq!(parse(open), Ok(Command::Open { path: file.clone(), line: Some(10), column: None }));
+        assert_eq!(parse(json!({ "token": "t", "command": "list-documents" })), Ok(Command::ListDocuments {}));
+        assert_eq!(parse(json!({ "token": "t", "command": "save", "args": { "all": true } })), Ok(Command::Save { path: None, all: true }));
+        for bad in [
+            json!({ "token": "t", "command": "open", "args": { "path": "a.md" } }),
+            json!({ "token": "t", "command": "open", "args": { "path": file, "line": 0 } }),
+            json!({ "token": "t", "command": "open", "args": { "path": file, "column": 2 } }),
+            json!({ "token": "t", "command": "open", "args": { "path": file, "mode": "x" } }),
+            json!({ "token": "t", "command": "open", "args": { "path": format!("{}\0", file) } }),
+            json!({ "token": "t", "command": "save", "args": { "path": file, "all": true } }),
+            json!({ "token": "t", "command": "run-task", "args": { "cmd": " " } }),
+            json!({ "token": "t", "command": "list-documents", "args": { "window": "main" } }),
+            json!({ "token": "t", "command": "eval", "args": {} }),
+            json!({ "token": "t", "command": "focus", "extra": 1 }),
+            json!({ "token": "t", "id": "x", "command": "focus" }),
+            json!({ "command": "focus" }),
+        ] {
+            assert!(parse(bad.clone()).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn compares_tokens_exactly() {
+        assert!(same_token("abc", "abc"));
+        assert!(!same_token("abd", "abc"));
+        assert!(!same_token("ab", "abc"));
+        assert!(!same_token("", "abc"));
+    }
+
+    #[test]
+    fn builds_requests_from_the_command_line() {
+        let cwd = std::env::temp_dir();
+        let args = |line: &str| line.split_whitespace().map(String::from).collect::<Vec<_>>();
+        let file = cwd.join("file.md").to_string_lossy().into_owned();
+        assert_eq!(
+            request_for(&args("open file.md --line 10"), &cwd),
+            Ok(json!({ "command": "open", "args": { "path": file, "line": 10, "column": null } }))
+        );
+        assert_eq!(request_for(&args("save --all"), &cwd), Ok(json!({ "command": "save", "args": { "path": null, "all": true } })));
+        // The program's own options aren't skriv's
+        assert_eq!(
+            request_for(&args("run-task cargo test --all"), &cwd),
+            Ok(json!({ "command": "run-task", "args": { "cmd": "cargo", "args": ["test", "--all"], "cwd": cwd.to_string_lossy() } }))
+        );
+        for bad in ["", "open", "open a b", "focus --line 3", "save a --all", "list-documents x", "open a --line x", "open a --force", "eval save_all"] {
+            assert!(request_for(&args(bad), &cwd).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Crash reports. A panic anywhere writes one to `crashes/` in the data
+//! folder, with the backtrace and the last lines logged, and leaves a marker
+//! behind so that the next launch offers it to the first window that's ready.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::{diagnostics, logging, paths};
+
+const DIR: &str = "crashes";
+/// Reports kept; older ones are deleted as new ones are written.
+const KEPT: usize = 10;
+/// Names the report the next launch should offer, until it has.
+const MARKER: &str = "unreported.json";
+const SUMMARY_LEN: usize = 200;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CrashReport {
+    path: String,
+    summary: String,
+}
+
+/// The report from the last run, until a window has been offered it.
+#[derive(Default)]
+pub struct PendingCrash(Mutex<Option<CrashReport>>);
+
+/// The first line of the panic, short enough for a dialog.
+fn summarize(message: &str, location: Option<String>) -> String {
+    let first = message.lines().next().unwrap_or_default();
+    let summary = match location {
+        Some(location) => format!("{} at {}", first, location),
+        None => first.to_string(),
+    };
+    summary.chars().take(SUMMARY_LEN).collect()
+}
+
+fn render(version: &str, summary: &str, message: &str, backtrace: &str, lines: &[String]) -> String {
+    let os = diagnostics::os_version().unwrap_or_else(|| std::env::consts::OS.to_string());
+    let thread = std::thread::current().name().unwrap_or("unnamed").to_string();
+    let mut report = format!(
+        "skriv {} crashed at {}\n\nOS: {} ({})\nThread: {}\nPanic: {}\n\n{}\n\nBacktrace:\n{}\n\nLast {} log lines:\n",
+        version,
+        chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+        os,
+        std::env::consts::ARCH,
+        thread,
+        summary,
+        message,
+        backtrace,
+        lines.len(),
+    );
+    for line in lines {
+        report.push_str(line);
+        report.push('\n');
+    }
+    report
+}
+
+/// Deletes all but the `KEPT` newest reports; their names sort by time.
+fn prune(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    let mut reports: Vec<PathBuf> = entries.filter_map(Result::ok).map(|e| e.path()).filter(|p| p.extension().is_some_and(|ext| ext == "txt")).collect();
+    reports.sort();
+    let excess = reports.len().saturating_sub(KEPT);
+    for report in &reports[..excess] {
+        let _ = std::fs::remove_file(report);
+    }
+}
+
+fn write(dir: &Path, summary: &str, text: &str) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{}.txt", chrono::Local::now().format("%Y-%m-%d-%H%M%S")));
+    std::fs::write(&path, text)?;
+    let report = CrashReport { path: path.to_string_lossy().into_owned(), summary: summary.to_string() };
+    std::fs::write(dir.join(MARKER), serde_json::to_string(&report)?)?;
+    prune(dir);
+    Ok(())
+}
+
+pub fn dir(app: &AppHandle) -> Result<PathBuf, String> {
+    paths::data_dir(app).map(|dir| dir.join(DIR))
+}
+
+/// The report the last run left unoffered, if it's still there.
+fn take_unreported(dir: &Path) -> Option<CrashReport> {
+    let marker = dir.join(MARKER);
+    let text = std::fs::read_to_string(&marker).ok()?;
+    let _ = std::fs::remove_file(&marker);
+    let report: CrashReport = serde_json::from_str(&text).ok()?;
+    Path::new(&report.path).is_file().then_some(report)
+}
+
+/// Installs the panic hook and picks up the last run's report. The hook
+/// writes the report, then does what the default one did.
+pub fn install(app: &AppHandle) {
+    let dir = match dir(app) {
+        Ok(dir) => dir,
+        Err(e) => {
+            log::warn!("Not writing crash reports, since {}", e);
+            app.manage(PendingCrash::default());
+            return;
+        }
+    };
+    let unreported = take_unreported(&dir);
+    if let Some(report) = &unreported {
+        log::info!("The last run crashed: {}", report.summary);
+    }
+    app.manage(PendingCrash(Mutex::new(unreported)));
+    let version = app.package_info().version.to_string();
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let payload = info.payload();
+        let message = payload.downcast_ref::<&str>().copied().or_else(|| payload.downcast_ref::<String>().map(String::as_str)).unwrap_or("Box<dyn Any>");
+        let summary = summarize(message, info.location().map(|l| l.to_string()));
+        let backtrace = std::backtrace::Backtrace::force_capture().to_string();
+        // Not logged: the panic may have come from inside the logger
+        let text = render(&version, &summary, message, &backtrace, &logging::recent_lines());
+        if let Err(e) = write(&dir, &summary, &text) {
+            eprintln!("Failed to write a crash report: {}", e);
+        }
+        previous(info);
+    }));
+}
+
+/// Offers the last run's report to the window that's just become ready.
+pub fn window_ready(app: &AppHandle, label: &str) {
+    if let Some(report) = app.state::<PendingCrash>().0.lock().unwrap().take() {
+        let _ = app.emit_to(label, "crash-report-available", report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_newest_reports_and_offers_the_last_once() {
+        let dir = std::env::temp_dir().join(format!("skriv-crash-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for day in 10..22 {
+            std::fs::write(dir.join(format!("2026-01-{}-120000.txt", day)), "").unwrap();
+        }
+        write(&dir, "boom at src/lib.rs:1:1", "report").unwrap();
+        let mut kept: Vec<String> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        kept.sort();
+        assert_eq!(kept.len(), KEPT + 1);
+        assert_eq!(kept[0], "2026-01-13-120000.txt");
+        assert_eq!(kept[KEPT], MARKER);
+        let report = take_unreported(&dir).unwrap();
+        assert_eq!(report.summary, "boom at src/lib.rs:1:1");
+        assert_eq!(std::fs::read_to_string(&report.path).unwrap(), "report");
+        assert!(take_unreported(&dir).is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
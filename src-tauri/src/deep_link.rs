@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::path::{Component, PathBuf};
+
+use tauri::{AppHandle, Url};
+use tauri_plugin_dialog::{DialogExt, MessageDialogKind};
+
+use crate::open_with::{self, OpenRequest};
+use crate::paths;
+
+pub const SCHEME: &str = "skriv";
+
+// Limits that no honest link gets near
+const MAX_LINK_BYTES: usize = 96 * 1024;
+const MAX_CONTENT_BYTES: usize = 64 * 1024;
+const MAX_QUERY_BYTES: usize = 1024;
+const MAX_PATH_BYTES: usize = 4096;
+
+/// What a `skriv://` link asks for:
+///
+/// - `skriv://open?path=/abs/file.md&line=12&column=3`
+/// - `skriv://new?content=text`
+/// - `skriv://search?query=text`
+#[derive(Debug, PartialEq)]
+pub enum Link {
+    Open { path: PathBuf, line: Option<u32>, column: Option<u32> },
+    New { content: Option<String> },
+    Search { query: String },
+}
+
+fn position(params: &HashMap<String, String>, key: &str) -> Result<Option<u32>, String> {
+    match params.get(key) {
+        None => Ok(None),
+        Some(value) => match value.parse() {
+            Ok(n) if n > 0 => Ok(Some(n)),
+            _ => Err(format!("\"{}\" isn't a valid {}", value, key)),
+        },
+    }
+}
+
+fn path(value: &str) -> Result<PathBuf, String> {
+    if value.len() > MAX_PATH_BYTES {
+        return Err("The path is too long".into());
+    }
+    // Another scheme in the path, or a network share that would be contacted
+    // just by looking
+    if value.contains("://") || value.to_ascii_lowercase().starts_with("file:") {
+        return Err("Links can only open plain file paths".into());
+    }
+    if value.starts_with("\\\\") || value.starts_with("//") {
+        return Err("Links can't open files on network shares".into());
+    }
+    let path = PathBuf::from(value);
+    if !path.is_absolute() {
+        return Err(format!("{} isn't an absolute path", value));
+    }
+    if path.components().any(|c| c == Component::ParentDir) {
+        return Err(format!("{} mustn't contain \"..\"", value));
+    }
+    Ok(path)
+}
+
+/// Checks a link without touching the file system.
+pub fn parse(text: &str) -> Result<Link, String> {
+    if text.len() > MAX_LINK_BYTES {
+        return Err("The link is too long".into());
+    }
+    let url = Url::parse(text).map_err(|e| format!("Not a valid link: {}", e))?;
+    if url.scheme() != SCHEME {
+        return Err(format!("Not a {}:// link", SCHEME));
+    }
+    if !url.username().is_empty() || url.password().is_some() || url.port().is_some() {
+        return Err("The link has parts skriv doesn't use".into());
+    }
+    // skriv://open?… has the action as its host, skriv:open?… as its path
+    let action = match url.host_str() {
+        Some(host) if url.path().trim_matches('/').is_empty() => host.to_string(),
+        Some(_) => return Err("The link has parts skriv doesn't use".into()),
+        None => url.path().trim_matches('/').to_string(),
+    };
+    let mut params = HashMap::new();
+    for (key, value) in url.query_pairs() {
+        if key.contains('\0') || value.contains('\0') {
+            return Err("The link contains a NUL character".into());
+        }
+        if params.insert(key.to_string(), value.into_owned()).is_some() {
+            return Err(format!("\"{}\" is given more than once", key));
+        }
+    }
+    let allowed: &[&str] = match action.as_str() {
+        "open" => &["path", "line", "column"],
+        "new" => &["content"],
+        "search" => &["query"],
+        _ => return Err(format!("Unknown link action \"{}\"", action)),
+    };
+    if let Some(key) = params.keys().find(|key| !allowed.contains(&key.as_str())) {
+        return Err(format!("Unknown link parameter \"{}\"", key));
+    }
+    match action.as_str() {
+        "open" => Ok(Link::Open {
+            path: path(params.get("path").ok_or("The link has no path")?)?,
+            line: position(&params, "line")?,
+            column: position(&params, "column")?,
+        }),
+        "new" => {
+            let content = params.remove("content");
+            if content.as_ref().is_some_and(|c| c.len() > MAX_CONTENT_BYTES) {
+                return Err(format!("The content is over {} KB", MAX_CONTENT_BYTES / 1024));
+            }
+            Ok(Link::New { content })
+        }
+        _ => {
+            let query = params.remove("query").filter(|q| !q.is_empty()).ok_or("The link has no query")?;
+            if query.len() > MAX_QUERY_BYTES {
+                return Err("The query is too long".into());
+            }
+            Ok(Link::Search { query })
+        }
+    }
+}
+
+fn request(link: Link) -> Result<OpenRequest, String> {
+    Ok(match link {
+        Link::Open { path, line, column } => {
+            if !path.is_file() {
+                return Err(format!("{} doesn't exist", path.display()));
+            }
+            OpenRequest::Open { path: paths::encode(&path), line, column }
+        }
+        Link::New { content } => OpenRequest::New { content: content.unwrap_or_default() },
+        Link::Search { query } => OpenRequest::Search { query },
+    })
+}
+
+/// Carries out a `skriv://` link, or says why it won't.
+pub fn handle(app: &AppHandle, text: &str) {
+    match parse(text).and_then(request) {
+        Ok(request) => open_with::deliver(app, vec![request]),
+        Err(e) => {
+            log::warn!("Rejected link: {}", e);
+            app.dialog().message(e).title("Can't Open Link").kind(MessageDialogKind::Warning).show(|_| {});
+        }
+    }
+}
+
+/// Links in a command line, as Windows and Linux pass them.
+pub fn handle_args(app: &AppHandle, args: &[String]) {
+    let prefix = format!("{}:", SCHEME);
+    for arg in args.iter().skip(1).filter(|arg| arg.starts_with(&prefix)) {
+        handle(app, arg);
+    }
+}
+
+/// Registers skriv for the scheme for the current user. The macOS bundle
+/// declares it in Info.plist instead.
+pub fn register() {
+    // A dev build shouldn't take links from the installed app
+    if cfg!(debug_assertions) {
+        return;
+    }
+    std::thread::spawn(|| {
+        if let Err(e) = register_scheme() {
+            log::warn!("Failed to register the {}:// scheme: {}", SCHEME, e);
+        }
+    });
+}
+
+#[cfg(windows)]
+fn register_scheme() -> Result<(), String> {
+    use crate::default_apps::reg;
+
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    let key = format!(r"HKCU\Software\Classes\{}", SCHEME);
+    let open = format!("\"{}\" \"%1\"", exe.display());
+    let command_key = format!(r"{}\shell\open\command", key);
+    let current = reg(&["query", &command_key, "/ve"])?;
+    if current.status.success() && String::from_utf8_lossy(&current.stdout).contains(&open) {
+        return Ok(());
+    }
+    let url_name = format!("URL:{}", SCHEME);
+    for args in [
+        vec!["add", &key, "/ve", "/d", &url_name, "/f"],
+        vec!["add", &key, "/v", "URL Protocol", "/d", "", "/f"],
+        vec!["add", &command_key, "/ve", "/d", &open, "/f"],
+    ] {
+        let output = reg(&args)?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn register_scheme() -> Result<(), String> {
+    use crate::default_apps::{write_desktop_entry, xdg_mime_default};
+
+    let name = format!("{}-url-handler.desktop", SCHEME);
+    let handler = format!("x-scheme-handler/{}", SCHEME);
+    if write_desktop_entry(&name, "%u", std::slice::from_ref(&handler))? {
+        xdg_mime_default(&name, &handler)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn register_scheme() -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn parses_links() {
+        assert_eq!(
+            parse("skriv://open?path=/Users/me/My%20Notes/todo.md&line=12").unwrap(),
+            Link::Open { path: "/Users/me/My Notes/todo.md".into(), line: Some(12), column: None }
+        );
+        assert_eq!(parse("skriv:new?content=a+b%0Ac").unwrap(), Link::New { content: Some("a b\nc".into()) });
+        assert_eq!(parse("skriv://new").unwrap(), Link::New { content: None });
+        assert_eq!(parse("skriv://search/?query=TODO").unwrap(), Link::Search { query: "TODO".into() });
+    }
+
+    #[test]
+    fn rejects_suspicious_links() {
+        for link in [
+            "https://open?path=/etc/hosts",
+            "skriv://open?path=notes/todo.md",
+            "skriv://open?path=file:///etc/passwd",
+            "skriv://open?path=/tmp/../etc/passwd",
+            "skriv://open?path=//server/share/x",
+            "skriv://open?path=/tmp/a%00b",
+            "skriv://open?path=/tmp/a&path=/tmp/b",
+            "skriv://open?path=/tmp/a&line=0",
+            "skriv://open?path=/tmp/a&exec=1",
+            "skriv://user@open?path=/tmp/a",
+            "skriv://delete?path=/tmp/a",
+            "skriv://search?query=",
+        ] {
+            assert!(parse(link).is_err(), "{}", link);
+        }
+        let long = format!("skriv://new?content={}", "a".repeat(MAX_CONTENT_BYTES + 1));
+        assert!(parse(&long).is_err());
+    }
+}
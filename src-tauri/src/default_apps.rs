@@ -0,0 +1,325 @@
+use serde::Serialize;
+use tauri::AppHandle;
+
+use crate::paths;
+
+/// Who opens files with an extension now.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DefaultHandler {
+    pub extension: String,
+    /// A bundle id on macOS, a ProgId on Windows, a desktop file on Linux
+    pub handler: Option<String>,
+    pub is_skriv: bool,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Registration {
+    Registered,
+    /// Windows keeps a choice the user made until they confirm the change in
+    /// its own Open With dialog
+    NeedsConfirmation,
+    Failed { error: String },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationResult {
+    pub extension: String,
+    #[serde(flatten)]
+    pub registration: Registration,
+}
+
+/// `.md` or `md` as `md`, refusing anything that isn't a plain extension
+/// before it ends up in a registry key or a command line.
+fn extension(text: &str) -> Result<String, String> {
+    let ext = text.trim().trim_start_matches('.').to_ascii_lowercase();
+    let plain = !ext.is_empty() && ext.len() <= 32 && ext.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'));
+    plain.then_some(ext).ok_or_else(|| format!("\"{}\" isn't a file extension", text))
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::ffi::c_void;
+
+    use objc2::rc::Retained;
+    use objc2_foundation::NSString;
+
+    type CFStringRef = *const c_void;
+    // kLSRolesAll
+    const ROLES_ALL: u32 = 0xFFFF_FFFF;
+
+    #[link(name = "CoreServices", kind = "framework")]
+    extern "C" {
+        fn UTTypeCreatePreferredIdentifierForTag(tag_class: CFStringRef, tag: CFStringRef, conforming_to: CFStringRef) -> CFStringRef;
+        fn LSCopyDefaultRoleHandlerForContentType(content_type: CFStringRef, role: u32) -> CFStringRef;
+        fn LSSetDefaultRoleHandlerForContentType(content_type: CFStringRef, role: u32, handler: CFStringRef) -> i32;
+    }
+
+    // NSString and CFString are the same object
+    fn cf(string: &NSString) -> CFStringRef {
+        (string as *const NSString).cast()
+    }
+
+    /// Takes over a CFString returned under the Create or Copy rule.
+    unsafe fn owned(string: CFStringRef) -> Option<Retained<NSString>> {
+        Retained::from_raw(string as *mut NSString)
+    }
+
+    fn content_type(ext: &str) -> Result<Retained<NSString>, String> {
+        let tag_class = NSString::from_str("public.filename-extension");
+        let tag = NSString::from_str(ext);
+        // SAFETY: valid strings in; the result is ours to release
+        unsafe { owned(UTTypeCreatePreferredIdentifierForTag(cf(&tag_class), cf(&tag), std::ptr::null())) }
+            .ok_or_else(|| format!("No content type is known for .{}", ext))
+    }
+
+    pub fn handler(ext: &str) -> Result<Option<String>, String> {
+        let uti = content_type(ext)?;
+        // SAFETY: as above
+        let handler = unsafe { owned(LSCopyDefaultRoleHandlerForContentType(cf(&uti), ROLES_ALL)) };
+        Ok(handler.map(|id| id.to_string()))
+    }
+
+    pub fn register(ext: &str, bundle_id: &str) -> Result<bool, String> {
+        let uti = content_type(ext)?;
+        let id = NSString::from_str(bundle_id);
+        // SAFETY: valid strings in, a plain status out
+        let status = unsafe { LSSetDefaultRoleHandlerForContentType(cf(&uti), ROLES_ALL, cf(&id)) };
+        if status != 0 {
+            return Err(format!("Launch Services refused with status {}", status));
+        }
+        Ok(true)
+    }
+
+    pub fn is_skriv(handler: &str, bundle_id: &str) -> bool {
+        handler.eq_ignore_ascii_case(bundle_id)
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::process::Output;
+
+    const PROG_ID: &str = "skriv.document";
+
+    pub(crate) fn reg(args: &[&str]) -> Result<Output, String> {
+        use std::os::windows::process::CommandExt;
+        std::process::Command::new("reg").args(args).creation_flags(0x0800_0000).output().map_err(|e| e.to_string())
+    }
+
+    /// A string value from `reg query`, which prints `name  REG_SZ  value`.
+    fn query(key: &str, value: Option<&str>) -> Option<String> {
+        let mut args = vec!["query", key];
+        match value {
+            Some(value) => args.extend(["/v", value]),
+            None => args.push("/ve"),
+        }
+        let output = reg(&args).ok().filter(|o| o.status.success())?;
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        let line = stdout.lines().find(|line| line.contains("REG_SZ"))?;
+        let value = line.split_once("REG_SZ")?.1.trim();
+        (!value.is_empty()).then(|| value.to_string())
+    }
+
+    fn user_choice(ext: &str) -> Option<String> {
+        let key = format!(r"HKCU\Software\Microsoft\Windows\CurrentVersion\Explorer\FileExts\.{}\UserChoice", ext);
+        query(&key, Some("ProgId"))
+    }
+
+    pub fn handler(ext: &str) -> Result<Option<String>, String> {
+        Ok(user_choice(ext).or_else(|| query(&format!(r"HKCR\.{}", ext), None)))
+    }
+
+    /// Per user, so no elevation. Returns false when a choice the user made
+    /// still wins, which only they can change.
+    pub fn register(ext: &str, _bundle_id: &str) -> Result<bool, String> {
+        let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+        let prog_key = format!(r"HKCU\Software\Classes\{}", PROG_ID);
+        let command_key = format!(r"{}\shell\open\command", prog_key);
+        let open = format!("\"{}\" \"%1\"", exe.display());
+        let ext_key = format!(r"HKCU\Software\Classes\.{}", ext);
+        let progids_key = format!(r"{}\OpenWithProgids", ext_key);
+        for args in [
+            vec!["add", &prog_key, "/ve", "/d", "skriv document", "/f"],
+            vec!["add", &command_key, "/ve", "/d", &open, "/f"],
+            vec!["add", &ext_key, "/ve", "/d", PROG_ID, "/f"],
+            vec!["add", &progids_key, "/v", PROG_ID, "/d", "", "/f"],
+        ] {
+            let output = reg(&args)?;
+            if !output.status.success() {
+                return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+            }
+        }
+        Ok(user_choice(ext).map_or(true, |choice| choice.eq_ignore_ascii_case(PROG_ID)))
+    }
+
+    pub fn is_skriv(handler: &str, _bundle_id: &str) -> bool {
+        handler.eq_ignore_ascii_case(PROG_ID)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    const DESKTOP_FILE: &str = "skriv-open.desktop";
+
+    fn data_home() -> Result<PathBuf, String> {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+            .ok_or_else(|| "No home directory".to_string())
+    }
+
+    /// Writes a hidden desktop entry handling `mime_types`, keeping the types
+    /// an earlier version of it handled. `arg` is the Exec field code, like
+    /// `%F` for files. Returns whether anything changed.
+    pub(crate) fn write_desktop_entry(name: &str, arg: &str, mime_types: &[String]) -> Result<bool, String> {
+        // An AppImage runs from a new mount each time; the image itself stays put
+        let exe = match std::env::var_os("APPIMAGE") {
+            Some(image) => PathBuf::from(image),
+            None => std::env::current_exe().map_err(|e| e.to_string())?,
+        };
+        let file = data_home()?.join("applications").join(name);
+        let existing = std::fs::read_to_string(&file).unwrap_or_default();
+        let mut types: Vec<String> = existing
+            .lines()
+            .find_map(|line| line.strip_prefix("MimeType="))
+            .map(|list| list.split(';').filter(|t| !t.is_empty()).map(str::to_string).collect())
+            .unwrap_or_default();
+        for mime in mime_types {
+            if !types.contains(mime) {
+                types.push(mime.clone());
+            }
+        }
+        let quoted: String = exe
+            .to_string_lossy()
+            .chars()
+            .flat_map(|c| matches!(c, '"' | '`' | '$' | '\\').then_some('\\').into_iter().chain(std::iter::once(c)))
+            .collect();
+        let entry = format!(
+            "[Desktop Entry]\nType=Application\nName=skriv\nExec=\"{}\" {}\nNoDisplay=true\nMimeType={};\n",
+            quoted,
+            arg,
+            types.join(";")
+        );
+        if existing == entry {
+            return Ok(false);
+        }
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(&file, entry).map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+
+    pub(crate) fn xdg_mime_default(name: &str, mime: &str) -> Result<(), String> {
+        let status = Command::new("xdg-mime").args(["default", name, mime]).status().map_err(|e| e.to_string())?;
+        if !status.success() {
+            return Err(format!("xdg-mime exited with {}", status));
+        }
+        Ok(())
+    }
+
+    /// The MIME type shared-mime-info gives `*.ext`, by the highest weight.
+    fn mime_type(ext: &str) -> Result<String, String> {
+        let dirs = std::env::var("XDG_DATA_DIRS").unwrap_or_else(|_| "/usr/local/share:/usr/share".into());
+        let pattern = format!("*.{}", ext);
+        let mut best: Option<(u32, String)> = None;
+        for dir in std::iter::once(data_home().ok()).flatten().chain(dirs.split(':').map(PathBuf::from)) {
+            let Ok(globs) = std::fs::read_to_string(dir.join("mime/globs2")) else { continue };
+            for line in globs.lines().filter(|line| !line.starts_with('#')) {
+                let mut fields = line.splitn(4, ':');
+                let (Some(weight), Some(mime), Some(glob)) = (fields.next(), fields.next(), fields.next()) else { continue };
+                let weight = weight.parse().unwrap_or(50);
+                if glob.eq_ignore_ascii_case(&pattern) && best.as_ref().map_or(true, |(w, _)| weight > *w) {
+                    best = Some((weight, mime.to_string()));
+                }
+            }
+        }
+        best.map(|(_, mime)| mime).ok_or_else(|| format!("No MIME type is known for .{}", ext))
+    }
+
+    pub fn handler(ext: &str) -> Result<Option<String>, String> {
+        let mime = mime_type(ext)?;
+        let output = Command::new("xdg-mime").args(["query", "default", &mime]).output().map_err(|e| e.to_string())?;
+        let handler = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Ok((!handler.is_empty()).then_some(handler))
+    }
+
+    pub fn register(ext: &str, _bundle_id: &str) -> Result<bool, String> {
+        let mime = mime_type(ext)?;
+        write_desktop_entry(DESKTOP_FILE, "%F", std::slice::from_ref(&mime))?;
+        xdg_mime_default(DESKTOP_FILE, &mime)?;
+        Ok(true)
+    }
+
+    pub fn is_skriv(handler: &str, _bundle_id: &str) -> bool {
+        handler == DESKTOP_FILE || handler == "skriv.desktop"
+    }
+}
+
+#[cfg(not(any(target_os = "macos", windows, target_os = "linux")))]
+mod platform {
+    pub fn handler(_ext: &str) -> Result<Option<String>, String> {
+        Ok(None)
+    }
+
+    pub fn register(_ext: &str, _bundle_id: &str) -> Result<bool, String> {
+        Err("Not supported on this platform".into())
+    }
+
+    pub fn is_skriv(_handler: &str, _bundle_id: &str) -> bool {
+        false
+    }
+}
+
+#[cfg(windows)]
+pub(crate) use platform::reg;
+#[cfg(target_os = "linux")]
+pub(crate) use platform::{write_desktop_entry, xdg_mime_default};
+
+/// What currently opens each of `extensions`.
+#[tauri::command]
+pub async fn get_default_handler_status(app: AppHandle, extensions: Vec<String>) -> Result<Vec<DefaultHandler>, String> {
+    let bundle_id = paths::bundle_identifier(&app);
+    tauri::async_runtime::spawn_blocking(move || {
+        extensions
+            .iter()
+            .map(|text| {
+                let extension = extension(text)?;
+                let handler = platform::handler(&extension)?;
+                let is_skriv = handler.as_deref().is_some_and(|h| platform::is_skriv(h, &bundle_id));
+                Ok(DefaultHandler { extension, handler, is_skriv })
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Makes skriv the default app for each of `extensions`, for the current
+/// user only. One failing doesn't stop the rest.
+#[tauri::command]
+pub async fn register_as_default(app: AppHandle, extensions: Vec<String>) -> Result<Vec<RegistrationResult>, String> {
+    let bundle_id = paths::bundle_identifier(&app);
+    tauri::async_runtime::spawn_blocking(move || {
+        extensions
+            .iter()
+            .map(|text| {
+                let registered = extension(text).and_then(|ext| Ok((platform::register(&ext, &bundle_id)?, ext)));
+                let (extension, registration) = match registered {
+                    Ok((true, ext)) => (ext, Registration::Registered),
+                    Ok((false, ext)) => (ext, Registration::NeedsConfirmation),
+                    Err(error) => (text.clone(), Registration::Failed { error }),
+                };
+                RegistrationResult { extension, registration }
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
@@ -0,0 +1,170 @@
+//! What the backend is holding on to, for bug reports about memory or
+//! sluggishness. Each module counts its own registry; this only gathers.
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+use crate::config_watcher::ConfigWatcher;
+use crate::directory::Listings;
+use crate::git::{GitCache, GitCacheUsage};
+use crate::highlight::{self, Highlights, SessionUsage};
+use crate::plugins::Plugins;
+use crate::search::Searches;
+use crate::snippets::SnippetWatcher;
+use crate::templates::TemplateWatcher;
+use crate::workers::{WorkerPool, WorkerPoolStats};
+use crate::{clipboard, document_watcher, large_file, lsp, pty, workspace};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WatcherUsage {
+    owner: &'static str,
+    paths: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeDiagnostics {
+    /// None where the platform won't say
+    resident_bytes: Option<u64>,
+    large_files: usize,
+    large_file_bytes: u64,
+    watchers: Vec<WatcherUsage>,
+    highlight_sessions: Vec<SessionUsage>,
+    git_cache: GitCacheUsage,
+    /// Finished searches kept for export, and their matches
+    search_results: usize,
+    search_matches: usize,
+    directory_listings: usize,
+    ptys: usize,
+    language_servers: Vec<String>,
+    worker_pool: WorkerPoolStats,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DiagnosticsReport {
+    version: String,
+    tauri: &'static str,
+    os: &'static str,
+    os_version: Option<String>,
+    arch: &'static str,
+    /// Autosave timers live in the window that asked
+    pending_auto_saves: usize,
+    runtime: RuntimeDiagnostics,
+}
+
+#[cfg(target_os = "linux")]
+fn resident_bytes() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf only reads a system constant
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * u64::try_from(page_size).ok()?)
+}
+
+#[cfg(target_os = "macos")]
+fn resident_bytes() -> Option<u64> {
+    let mut info = std::mem::MaybeUninit::<libc::proc_taskinfo>::zeroed();
+    let size = std::mem::size_of::<libc::proc_taskinfo>() as libc::c_int;
+    // SAFETY: the buffer is as big as it's said to be; the info is only read once filled
+    let filled = unsafe { libc::proc_pidinfo(std::process::id() as libc::c_int, libc::PROC_PIDTASKINFO, 0, info.as_mut_ptr().cast(), size) };
+    // SAFETY: proc_pidinfo wrote all of it
+    (filled == size).then(|| unsafe { info.assume_init() }.pti_resident_size)
+}
+
+#[cfg(windows)]
+fn resident_bytes() -> Option<u64> {
+    use windows::Win32::System::ProcessStatus::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS};
+    use windows::Win32::System::Threading::GetCurrentProcess;
+
+    let mut counters = PROCESS_MEMORY_COUNTERS::default();
+    let size = std::mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32;
+    // SAFETY: the pseudo handle needn't be closed, and the counters are as big as said
+    unsafe { GetProcessMemoryInfo(GetCurrentProcess(), &mut counters, size) }.ok()?;
+    Some(counters.WorkingSetSize as u64)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn resident_bytes() -> Option<u64> {
+    None
+}
+
+pub fn os_version() -> Option<String> {
+    if cfg!(target_os = "linux") {
+        let release = std::fs::read_to_string("/etc/os-release").ok()?;
+        let name = release.lines().find_map(|line| line.strip_prefix("PRETTY_NAME="))?;
+        return Some(name.trim_matches('"').to_string());
+    }
+    let (cmd, args): (&str, &[&str]) = if cfg!(target_os = "macos") { ("sw_vers", &["-productVersion"]) } else { ("cmd", &["/C", "ver"]) };
+    let output = std::process::Command::new(cmd).args(args).output().ok()?;
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !version.is_empty()).then_some(version)
+}
+
+fn watchers(app: &AppHandle, git: &GitCacheUsage) -> Vec<WatcherUsage> {
+    let mut watchers = Vec::new();
+    // The settings folder and its themes
+    if let Some(config) = app.try_state::<ConfigWatcher>() {
+        watchers.push(WatcherUsage { owner: "config", paths: config.paths() });
+    }
+    if app.state::<SnippetWatcher>().is_running() {
+        watchers.push(WatcherUsage { owner: "snippets", paths: 1 });
+    }
+    if app.state::<TemplateWatcher>().is_running() {
+        watchers.push(WatcherUsage { owner: "templates", paths: 1 });
+    }
+    if app.state::<Plugins>().is_watching() {
+        watchers.push(WatcherUsage { owner: "plugins", paths: 1 });
+    }
+    if let Some(paths) = document_watcher::watched_paths(app) {
+        watchers.push(WatcherUsage { owner: "documents", paths });
+    }
+    if let Some(paths) = git.watched {
+        watchers.push(WatcherUsage { owner: "git", paths });
+    }
+    watchers.extend(workspace::watched_paths(app).into_iter().map(|paths| WatcherUsage { owner: "workspace", paths }));
+    watchers
+}
+
+/// Memory, handles, watchers and processes the backend has open now.
+#[tauri::command]
+pub fn get_runtime_diagnostics(app: AppHandle) -> RuntimeDiagnostics {
+    let (large_files, large_file_bytes) = large_file::usage(&app);
+    let git_cache = app.state::<GitCache>().usage();
+    let (search_results, search_matches) = app.state::<Searches>().usage();
+    RuntimeDiagnostics {
+        resident_bytes: resident_bytes(),
+        large_files,
+        large_file_bytes,
+        watchers: watchers(&app, &git_cache),
+        highlight_sessions: highlight::usage(&app.state::<Highlights>()),
+        git_cache,
+        search_results,
+        search_matches,
+        directory_listings: app.state::<Listings>().len(),
+        ptys: pty::count(&app),
+        language_servers: lsp::running(&app),
+        worker_pool: app.state::<WorkerPool>().stats(),
+    }
+}
+
+/// The runtime diagnostics with the versions, as pretty JSON.
+pub fn report(app: AppHandle, pending_auto_saves: usize) -> Result<String, String> {
+    let report = DiagnosticsReport {
+        version: app.package_info().version.to_string(),
+        tauri: tauri::VERSION,
+        os: std::env::consts::OS,
+        os_version: os_version(),
+        arch: std::env::consts::ARCH,
+        pending_auto_saves,
+        runtime: get_runtime_diagnostics(app),
+    };
+    serde_json::to_string_pretty(&report).map_err(|e| e.to_string())
+}
+
+/// The hidden Copy Diagnostics, to paste into a bug report.
+#[tauri::command]
+pub fn copy_diagnostics(app: AppHandle, pending_auto_saves: usize) -> Result<(), String> {
+    clipboard::write_text(&report(app, pending_auto_saves)?)
+}
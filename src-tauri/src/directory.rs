@@ -0,0 +1,244 @@
+//! Directory listings a page at a time, for folders with more entries than
+//! the webview wants in one go. The first page reads and sorts the whole
+//! folder once; later pages come from that snapshot, so entries don't shift
+//! between pages while the folder changes underneath.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+use std::sync::atomic::{AtomicU32, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::paths;
+use crate::workers::{Priority, WorkerPool};
+
+const DEFAULT_PAGE_SIZE: usize = 500;
+/// Entries past which a folder is better searched than browsed.
+const HUGE: usize = 10_000;
+/// How long a listing is kept after its last page was asked for.
+const LISTING_TTL: Duration = Duration::from_secs(5 * 60);
+/// The error for a token whose listing has expired; the listing has to
+/// start over.
+pub const EXPIRED: &str = "listing_expired";
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum SortKey {
+    /// Numbers in names compare by value, so `img2` comes before `img10`
+    #[default]
+    Name,
+    Size,
+    Modified,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct DirectorySort {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryEntry {
+    name: String,
+    path: String,
+    is_dir: bool,
+    is_symlink: bool,
+    /// 0 for folders
+    size: u64,
+    /// Milliseconds since the epoch
+    modified: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryPage {
+    entries: Vec<DirectoryEntry>,
+    /// For the page after this one; none on the last page
+    next_page_token: Option<String>,
+    total: usize,
+    huge: bool,
+}
+
+struct Listing {
+    entries: Arc<Vec<DirectoryEntry>>,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+pub struct Listings(Mutex<HashMap<u32, Listing>>);
+
+impl Listings {
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+}
+
+static NEXT_LISTING: AtomicU32 = AtomicU32::new(1);
+
+fn take_number(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+    digits
+}
+
+/// Case-insensitive, with runs of digits compared as numbers.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut left, mut right) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        let order = match (left.peek(), right.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (x, y) = (take_number(&mut left), take_number(&mut right));
+                let (short_x, short_y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                short_x.len().cmp(&short_y.len()).then_with(|| short_x.cmp(short_y)).then_with(|| x.len().cmp(&y.len()))
+            }
+            (Some(&x), Some(&y)) => {
+                left.next();
+                right.next();
+                x.to_lowercase().cmp(y.to_lowercase())
+            }
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+}
+
+/// Folders first, then by `sort`, name breaking ties.
+fn compare(a: &DirectoryEntry, b: &DirectoryEntry, sort: DirectorySort) -> Ordering {
+    let by_key = match sort.key {
+        SortKey::Name => Ordering::Equal,
+        SortKey::Size => a.size.cmp(&b.size),
+        SortKey::Modified => a.modified.cmp(&b.modified),
+    };
+    let order = by_key.then_with(|| natural_cmp(&a.name, &b.name));
+    b.is_dir.cmp(&a.is_dir).then(if sort.descending { order.reverse() } else { order })
+}
+
+fn read(dir: &Path, sort: DirectorySort) -> Result<Vec<DirectoryEntry>, String> {
+    let mut entries: Vec<DirectoryEntry> = std::fs::read_dir(dir)
+        .map_err(|e| e.to_string())?
+        .filter_map(Result::ok)
+        .map(|entry| {
+            let path = entry.path();
+            let is_symlink = entry.file_type().is_ok_and(|kind| kind.is_symlink());
+            // A link is shown as what it points at
+            let metadata = std::fs::metadata(&path).or_else(|_| entry.metadata()).ok();
+            let is_dir = metadata.as_ref().is_some_and(|m| m.is_dir());
+            let modified = metadata.as_ref().and_then(|m| m.modified().ok()).and_then(|t| t.duration_since(UNIX_EPOCH).ok());
+            DirectoryEntry {
+                name: paths::encode(Path::new(&entry.file_name())),
+                path: paths::encode(&path),
+                is_dir,
+                is_symlink,
+                size: metadata.filter(|_| !is_dir).map_or(0, |m| m.len()),
+                modified: modified.map(|d| d.as_millis() as u64),
+            }
+        })
+        .collect();
+    entries.sort_by(|a, b| compare(a, b, sort));
+    Ok(entries)
+}
+
+fn page(listing: u32, entries: &[DirectoryEntry], offset: usize, size: usize) -> DirectoryPage {
+    let end = (offset + size).min(entries.len());
+    DirectoryPage {
+        entries: entries[offset.min(end)..end].to_vec(),
+        next_page_token: (end < entries.len()).then(|| format!("{}:{}", listing, end)),
+        total: entries.len(),
+        huge: entries.len() > HUGE,
+    }
+}
+
+fn parse_token(token: &str) -> Option<(u32, usize)> {
+    let (listing, offset) = token.split_once(':')?;
+    Some((listing.parse().ok()?, offset.parse().ok()?))
+}
+
+/// A page of `path`'s entries. Without `page_token` the folder is read and
+/// sorted by `sort`; with one, the listing it came from continues, and
+/// `path` and `sort` are those it was started with.
+#[tauri::command]
+pub async fn read_directory_page(
+    app: tauri::AppHandle,
+    path: String,
+    page_token: Option<String>,
+    page_size: Option<usize>,
+    sort: Option<DirectorySort>,
+) -> Result<DirectoryPage, String> {
+    let size = page_size.unwrap_or(DEFAULT_PAGE_SIZE).max(1);
+    let listings = app.state::<Listings>();
+    {
+        let mut listings = listings.0.lock().unwrap();
+        listings.retain(|_, listing| listing.last_used.elapsed() < LISTING_TTL);
+        if let Some(token) = page_token {
+            let (id, offset) = parse_token(&token).ok_or_else(|| format!("Invalid page token \"{}\"", token))?;
+            let listing = listings.get_mut(&id).ok_or(EXPIRED)?;
+            listing.last_used = Instant::now();
+            return Ok(page(id, &listing.entries, offset, size));
+        }
+    }
+    let dir = paths::decode(&path);
+    let sort = sort.unwrap_or_default();
+    let entries = app.state::<WorkerPool>().run(Priority::Interactive, move || read(&dir, sort)).await??;
+    let id = NEXT_LISTING.fetch_add(1, AtomicOrdering::Relaxed);
+    let first = page(id, &entries, 0, size);
+    // A folder that fits in one page needn't be kept
+    if first.next_page_token.is_some() {
+        listings.0.lock().unwrap().insert(id, Listing { entries: Arc::new(entries), last_used: Instant::now() });
+    }
+    Ok(first)
+}
+
+/// Lets go of a listing before it expires, once the view is done with it.
+#[tauri::command]
+pub fn close_directory_listing(listings: tauri::State<'_, Listings>, page_token: String) {
+    if let Some((id, _)) = parse_token(&page_token) {
+        listings.0.lock().unwrap().remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_numbers_by_value() {
+        let mut names = vec!["img10.png", "IMG2.png", "img1.png", "img02.png", "a", "B"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, ["a", "B", "img1.png", "IMG2.png", "img02.png", "img10.png"]);
+    }
+
+    #[test]
+    fn pages_through_a_sorted_snapshot() {
+        let dir = std::env::temp_dir().join(format!("skriv-directory-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        for (name, len) in [("b.txt", 3), ("a.txt", 1), ("c.txt", 2)] {
+            std::fs::write(dir.join(name), "x".repeat(len)).unwrap();
+        }
+        let sort = DirectorySort { key: SortKey::Size, descending: true };
+        let entries = read(&dir, sort).unwrap();
+        let first = page(7, &entries, 0, 3);
+        let names: Vec<&str> = first.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["sub", "b.txt", "c.txt"]);
+        assert_eq!((first.total, first.huge), (4, false));
+        let (id, offset) = parse_token(first.next_page_token.as_deref().unwrap()).unwrap();
+        assert_eq!(id, 7);
+        let last = page(id, &entries, offset, 3);
+        assert_eq!(last.entries[0].name, "a.txt");
+        assert!(last.next_page_token.is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
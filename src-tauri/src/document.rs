@@ -0,0 +1,958 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use encoding_rs::Encoding;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::editorconfig::{EditorConfig, EditorConfigs};
+use crate::document_watcher;
+use crate::encryption::{self, Key};
+use crate::file_lock::{self, LockedBy};
+use crate::git;
+use crate::highlight::{self, LineTokens, Prehighlight};
+use crate::languages;
+use crate::menu;
+use crate::menu_state;
+use crate::paths;
+use crate::power;
+use crate::settings::Settings;
+
+pub const ENCODING_PREFIX: &str = "encoding:";
+const UTF8: &str = "UTF-8";
+const UTF16LE: &str = "UTF-16LE";
+const UTF16BE: &str = "UTF-16BE";
+// encoding_rs follows WHATWG and treats Latin-1 as Windows-1252, so it's handled here
+const LATIN1: &str = "ISO-8859-1";
+// Saves this big keep the system awake while they are written
+const LARGE_SAVE_BYTES: usize = 64 * 1024 * 1024;
+// Characters in a line that's long, unless files.longLineThreshold says otherwise
+const LONG_LINE_CHARS: usize = 10_000;
+
+/// Encodings offered in File > Reopen with Encoding, as (label, display name).
+pub const ENCODINGS: &[(&str, &str)] = &[
+    (UTF8, "UTF-8"),
+    (UTF16LE, "UTF-16 LE"),
+    (UTF16BE, "UTF-16 BE"),
+    (LATIN1, "Latin-1 (ISO-8859-1)"),
+    ("windows-1252", "Western (Windows-1252)"),
+    ("ISO-8859-15", "Western (ISO-8859-15)"),
+    ("windows-1250", "Central European (Windows-1250)"),
+    ("windows-1251", "Cyrillic (Windows-1251)"),
+    ("KOI8-R", "Cyrillic (KOI8-R)"),
+    ("Shift_JIS", "Japanese (Shift-JIS)"),
+    ("EUC-JP", "Japanese (EUC-JP)"),
+    ("GBK", "Chinese Simplified (GBK)"),
+    ("gb18030", "Chinese Simplified (GB18030)"),
+    ("Big5", "Chinese Traditional (Big5)"),
+    ("EUC-KR", "Korean (EUC-KR)"),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    /// Both kinds were found on read; the file is saved as the editor has it.
+    Mixed,
+}
+
+/// Check items in File > Line Endings. Mixed is an indicator only.
+pub const LINE_ENDING_ITEMS: &[(&str, LineEnding)] = &[
+    ("line_ending_lf", LineEnding::Lf),
+    ("line_ending_crlf", LineEnding::Crlf),
+    ("line_ending_mixed", LineEnding::Mixed),
+];
+
+struct DocumentInfo {
+    encoding: String,
+    /// Picked with Reopen with Encoding rather than detected
+    encoding_chosen: bool,
+    bom: bool,
+    line_ending: LineEnding,
+    /// The bytes on disk as of the last read or save, before encryption
+    baseline: Baseline,
+    /// For an age file, the passphrase its saves are encrypted with
+    key: Option<Arc<Key>>,
+    /// Has edits not yet saved, as the frontend last said
+    dirty: bool,
+}
+
+/// What the file held when skriv last read or wrote it, so a save that would
+/// write the same bytes again can be skipped.
+#[derive(PartialEq)]
+struct Baseline {
+    hash: u64,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Baseline {
+    fn new(path: &Path, bytes: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        let modified = std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+        Baseline { hash: hasher.finish(), len: bytes.len() as u64, modified }
+    }
+}
+
+/// Whether the file at `path` is as skriv last left it and holds `bytes`
+/// already, so writing them would only touch it. Unless `force`.
+fn holds_already(info: &DocumentInfo, path: &Path, bytes: &[u8], force: bool) -> bool {
+    !force && info.baseline == Baseline::new(path, bytes)
+}
+
+/// Per-document state the save path needs, keyed by file path.
+#[derive(Default)]
+pub struct DocumentRegistry(Mutex<HashMap<PathBuf, DocumentInfo>>);
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentContent {
+    path: String,
+    content: String,
+    encoding: String,
+    line_ending: LineEnding,
+    /// Some bytes weren't valid in the encoding and became U+FFFD.
+    had_errors: bool,
+    editorconfig: EditorConfig,
+    /// Tokens for the first lines, when asked for with `prehighlight`
+    first_screen: Option<Vec<LineTokens>>,
+    /// Read from an age file, which saves encrypt again
+    encrypted: bool,
+    /// As `detect_language` gives it for this path and text
+    language: String,
+    /// Some line is past `files.longLineThreshold`, as in minified files,
+    /// and the document is better read with `read_document_wrapped`
+    has_long_lines: bool,
+    /// In characters
+    longest_line: usize,
+    /// Who else has the file open, in which case it should open read-only
+    /// unless the user says otherwise
+    locked_by: Option<LockedBy>,
+}
+
+/// A line of wrapped text that continues the one before it, to take
+/// positions back to the file.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WrapSegment {
+    /// 1-based, in the wrapped text
+    line: usize,
+    /// 1-based, in the file
+    original_line: usize,
+    /// Where in the original line this one starts, 1-based in UTF-16 code
+    /// units like the editor's columns
+    original_column: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WrappedDocument {
+    #[serde(flatten)]
+    document: DocumentContent,
+    /// Only the lines that were broken up, in order
+    segments: Vec<WrapSegment>,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SaveOutcome {
+    Written,
+    /// The file already held exactly these bytes and was left alone
+    Unchanged,
+}
+
+/// A file read again because another program changed it, for a tab
+/// without unsaved edits to show.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reverted {
+    path: String,
+    content: String,
+    /// Milliseconds since the epoch
+    mtime: Option<u64>,
+    encoding: String,
+    line_ending: LineEnding,
+}
+
+/// A file another program changed or deleted, for the tab to ask about.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangedOnDisk {
+    path: String,
+    deleted: bool,
+    dirty: bool,
+}
+
+pub enum DiskChange {
+    Reverted(Reverted),
+    Changed(ChangedOnDisk),
+}
+
+/// What was written, for the frontend to catch up with.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Saved {
+    outcome: SaveOutcome,
+    /// The text as saved, when .editorconfig rules changed it
+    content: Option<String>,
+    encoding: String,
+    line_ending: LineEnding,
+    /// The save set the execute bits on a script
+    made_executable: bool,
+}
+
+fn lookup(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("Unknown encoding \"{}\"", label))
+}
+
+fn decode(bytes: &[u8], label: &str) -> Result<(String, bool), String> {
+    if label.eq_ignore_ascii_case(LATIN1) {
+        return Ok((bytes.iter().map(|&b| b as char).collect(), false));
+    }
+    let (text, had_errors) = lookup(label)?.decode_with_bom_removal(bytes);
+    Ok((text.into_owned(), had_errors))
+}
+
+/// Decodes a slice from the middle of a file, where a BOM can't be.
+pub fn decode_part(bytes: &[u8], label: &str) -> Result<(String, bool), String> {
+    if label.eq_ignore_ascii_case(LATIN1) {
+        return Ok((bytes.iter().map(|&b| b as char).collect(), false));
+    }
+    let (text, had_errors) = lookup(label)?.decode_without_bom_handling(bytes);
+    Ok((text.into_owned(), had_errors))
+}
+
+/// How many bytes `text` takes in the encoding, without a BOM. Characters
+/// it can't hold count as whatever the encoder puts in their place.
+pub fn encoded_len(text: &str, label: &str) -> Result<usize, String> {
+    if label.eq_ignore_ascii_case(UTF8) {
+        Ok(text.len())
+    } else if label.eq_ignore_ascii_case(UTF16LE) || label.eq_ignore_ascii_case(UTF16BE) {
+        Ok(text.encode_utf16().count() * 2)
+    } else if label.eq_ignore_ascii_case(LATIN1) {
+        Ok(text.chars().count())
+    } else {
+        Ok(lookup(label)?.encode(text).0.len())
+    }
+}
+
+fn encode(text: &str, label: &str, bom: bool) -> Result<Vec<u8>, String> {
+    let unencodable = || format!("The document contains characters that can't be saved as {}", label);
+    let utf16 = |to_bytes: fn(u16) -> [u8; 2]| -> Vec<u8> {
+        bom.then_some(0xFEFF).into_iter().chain(text.encode_utf16()).flat_map(to_bytes).collect()
+    };
+    if label.eq_ignore_ascii_case(UTF8) {
+        let bom: &[u8] = if bom { b"\xEF\xBB\xBF" } else { b"" };
+        Ok([bom, text.as_bytes()].concat())
+    } else if label.eq_ignore_ascii_case(UTF16LE) {
+        Ok(utf16(u16::to_le_bytes))
+    } else if label.eq_ignore_ascii_case(UTF16BE) {
+        Ok(utf16(u16::to_be_bytes))
+    } else if label.eq_ignore_ascii_case(LATIN1) {
+        text.chars().map(|c| u8::try_from(c).map_err(|_| unencodable())).collect()
+    } else {
+        let (bytes, _, unmappable) = lookup(label)?.encode(text);
+        if unmappable {
+            return Err(unencodable());
+        }
+        Ok(bytes.into_owned())
+    }
+}
+
+// Files without any line break count as LF.
+fn detect_line_ending(text: &str) -> LineEnding {
+    let crlf = text.matches("\r\n").count();
+    let lf = text.matches('\n').count() - crlf;
+    match (lf, crlf) {
+        (_, 0) => LineEnding::Lf,
+        (0, _) => LineEnding::Crlf,
+        _ => LineEnding::Mixed,
+    }
+}
+
+fn convert_line_endings(text: &str, target: LineEnding) -> String {
+    let lf = text.replace("\r\n", "\n");
+    match target {
+        LineEnding::Crlf => lf.replace('\n', "\r\n"),
+        _ => lf,
+    }
+}
+
+// BOM first, then strict UTF-8, then a statistical guess.
+fn detect(bytes: &[u8]) -> (&'static str, bool) {
+    detect_start(bytes, true)
+}
+
+/// Like `detect`; unless `whole`, `bytes` are only the start of the file and
+/// a character cut off at the end still counts as UTF-8.
+pub fn detect_start(bytes: &[u8], whole: bool) -> (&'static str, bool) {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return (encoding.name(), true);
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(_) => return (UTF8, false),
+        Err(e) if !whole && e.error_len().is_none() => return (UTF8, false),
+        Err(_) => {}
+    }
+    let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+    detector.feed(bytes, whole);
+    (detector.guess(None, Utf8Detection::Deny).name(), false)
+}
+
+/// The encoding the document at `path` was last read in, if it's open.
+pub fn known_encoding(registry: &DocumentRegistry, path: &Path) -> Option<String> {
+    registry.0.lock().unwrap().get(path).map(|info| info.encoding.clone())
+}
+
+/// Decodes bytes as the document at `path` was last read, or as detected if it
+/// isn't open. Returns the text, the encoding and whether any bytes were invalid.
+pub fn decode_like(registry: &DocumentRegistry, path: &Path, bytes: &[u8]) -> Result<(String, String, bool), String> {
+    let encoding = known_encoding(registry, path).unwrap_or_else(|| detect(bytes).0.to_string());
+    let (text, had_errors) = decode(bytes, &encoding)?;
+    Ok((text, encoding, had_errors))
+}
+
+/// Decodes bytes that aren't a file on disk, in `charset` when their source
+/// named one, else as detected. Returns the text, the encoding, the line
+/// ending and whether any bytes were invalid.
+pub fn decode_detached(bytes: &[u8], charset: Option<&str>) -> Result<(String, String, LineEnding, bool), String> {
+    let named = charset.and_then(|charset| Encoding::for_label(charset.trim().as_bytes())).map(Encoding::name);
+    let encoding = named.unwrap_or_else(|| detect(bytes).0);
+    let (text, had_errors) = decode(bytes, encoding)?;
+    let line_ending = detect_line_ending(&text);
+    Ok((text, encoding.to_string(), line_ending, had_errors))
+}
+
+/// The bytes for text that's saved somewhere other than a local file, in
+/// `line_ending` unless it's mixed.
+pub fn encode_detached(text: &str, encoding: &str, line_ending: LineEnding) -> Result<Vec<u8>, String> {
+    match line_ending {
+        LineEnding::Mixed => encode(text, encoding, false),
+        _ => encode(&convert_line_endings(text, line_ending), encoding, false),
+    }
+}
+
+/// The characters in the longest line.
+fn longest_line(text: &str) -> usize {
+    text.split('\n').map(|line| line.trim_end_matches('\r').chars().count()).max().unwrap_or(0)
+}
+
+/// Breaks lines longer than `column` characters, after a space, comma or
+/// semicolon near the end where there is one so tokens stay whole. Line
+/// breaks added are `\n`, whatever the file uses.
+fn wrap_long_lines(text: &str, column: usize) -> (String, Vec<WrapSegment>) {
+    let column = column.max(1);
+    let mut wrapped = String::with_capacity(text.len() + text.len() / column);
+    let mut segments = Vec::new();
+    let mut line = 1;
+    for (index, raw) in text.split_inclusive('\n').enumerate() {
+        let body = raw.trim_end_matches(['\n', '\r']);
+        let (mut rest, mut utf16) = (body, 0);
+        while let Some((limit, _)) = rest.char_indices().nth(column) {
+            let cut = rest[..limit].rfind([' ', ',', ';']).map(|i| i + 1).filter(|cut| *cut >= limit * 3 / 4).unwrap_or(limit);
+            wrapped.push_str(&rest[..cut]);
+            wrapped.push('\n');
+            utf16 += rest[..cut].encode_utf16().count();
+            line += 1;
+            segments.push(WrapSegment { line, original_line: index + 1, original_column: utf16 + 1 });
+            rest = &rest[cut..];
+        }
+        wrapped.push_str(rest);
+        wrapped.push_str(&raw[body.len()..]);
+        line += 1;
+    }
+    (wrapped, segments)
+}
+
+/// The encoding label and BOM for an .editorconfig charset.
+fn charset_encoding(charset: &str) -> Option<(&'static str, bool)> {
+    match charset {
+        "latin1" => Some((LATIN1, false)),
+        "utf-8" => Some((UTF8, false)),
+        "utf-8-bom" => Some((UTF8, true)),
+        "utf-16le" => Some((UTF16LE, true)),
+        "utf-16be" => Some((UTF16BE, true)),
+        _ => None,
+    }
+}
+
+/// An age file is decrypted with the key the document already has, else
+/// with a passphrase asked of `window`.
+fn read(app: &AppHandle, window: Option<&str>, path: &Path, label: Option<&str>) -> Result<DocumentContent, String> {
+    let (registry, configs) = (app.state::<DocumentRegistry>(), app.state::<EditorConfigs>());
+    let raw = std::fs::read(path).map_err(|e| e.to_string())?;
+    let (bytes, key) = match encryption::detect(&raw) {
+        None => (raw, None),
+        Some(_) => {
+            let known = registry.0.lock().unwrap().get(path).and_then(|info| info.key.clone());
+            match known.and_then(|key| Some((encryption::decrypt_with(&key, &raw)?, key))) {
+                Some((plaintext, key)) => (plaintext, Some(key)),
+                None => {
+                    let (plaintext, key) = encryption::unlock(app, window, path, raw)?;
+                    (plaintext, Some(Arc::new(key)))
+                }
+            }
+        }
+    };
+    let (encoding, bom) = match label {
+        Some(label) => (label.to_string(), Encoding::for_bom(&bytes).is_some()),
+        None => {
+            let (label, bom) = detect(&bytes);
+            (label.to_string(), bom)
+        }
+    };
+    let (content, had_errors) = decode(&bytes, &encoding)?;
+    let line_ending = detect_line_ending(&content);
+    let longest_line = longest_line(&content);
+    let threshold = app.state::<Mutex<Settings>>().lock().unwrap().files.long_line_threshold.unwrap_or(LONG_LINE_CHARS);
+    log::debug!("Read {}: {} bytes as {}{}", path.display(), bytes.len(), encoding, if had_errors { ", with invalid bytes" } else { "" });
+    let baseline = Baseline::new(path, &bytes);
+    let encrypted = key.is_some();
+    let info = DocumentInfo { encoding: encoding.clone(), encoding_chosen: label.is_some(), bom, line_ending, baseline, key, dirty: false };
+    registry.0.lock().unwrap().insert(path.to_path_buf(), info);
+    let editorconfig = configs.resolve(path);
+    let path = paths::encode(path);
+    let language = languages::detect_for(app, &path, &content);
+    let has_long_lines = longest_line > threshold;
+    Ok(DocumentContent { path, content, encoding, line_ending, had_errors, editorconfig, first_screen: None, encrypted, language, has_long_lines, longest_line, locked_by: None })
+}
+
+/// With `prehighlight`, the first lines come tokenized too, so that a big
+/// file shows highlighted before its highlight session is ready. Runs off
+/// the main thread, since an age file waits for its passphrase.
+#[tauri::command]
+pub async fn read_document(app: AppHandle, window: tauri::Window, path: String, prehighlight: Option<Prehighlight>) -> Result<DocumentContent, String> {
+    let label = window.label().to_string();
+    tauri::async_runtime::spawn_blocking(move || {
+        let path = paths::decode(&path);
+        let mut doc = read(&app, Some(&label), &path, None)?;
+        document_watcher::watch(&app, &label, &path);
+        doc.locked_by = file_lock::acquire(&app, &label, &path);
+        // Tokenized as the detected language, which knows more than the file name
+        let prehighlight = prehighlight.map(|prehighlight| Prehighlight { language: doc.language.clone(), ..prehighlight });
+        // A minified line is left for the editor, which tokenizes only so much of it
+        let prehighlight = prehighlight.filter(|_| !doc.has_long_lines);
+        doc.first_screen = prehighlight.and_then(|prehighlight| highlight::first_screen(&prehighlight, &doc.content));
+        Ok(doc)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+pub async fn read_document_with_encoding(app: AppHandle, window: tauri::Window, path: String, encoding: String) -> Result<DocumentContent, String> {
+    let label = window.label().to_string();
+    tauri::async_runtime::spawn_blocking(move || {
+        let path = paths::decode(&path);
+        let mut doc = read(&app, Some(&label), &path, Some(&encoding))?;
+        document_watcher::watch(&app, &label, &path);
+        doc.locked_by = file_lock::acquire(&app, &label, &path);
+        Ok(doc)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// The document with lines past `soft_wrap_column` characters broken up, to
+/// show read-only. Its segments take positions in it back to the file.
+#[tauri::command]
+pub async fn read_document_wrapped(app: AppHandle, window: tauri::Window, path: String, soft_wrap_column: usize) -> Result<WrappedDocument, String> {
+    let label = window.label().to_string();
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut document = read(&app, Some(&label), &paths::decode(&path), None)?;
+        let (content, segments) = wrap_long_lines(&document.content, soft_wrap_column);
+        document.content = content;
+        Ok(WrappedDocument { document, segments })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Lines `start..start + count` (1-based) of a file, decoded like the rest of
+/// it would be, so a view of a big file only takes in what it shows.
+#[tauri::command]
+pub async fn read_document_lines(app: AppHandle, path: String, start: u32, count: u32) -> Result<String, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let path = &paths::decode(&path);
+        let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+        let (text, _, _) = decode_like(&app.state::<DocumentRegistry>(), path, &bytes)?;
+        Ok(text.split_inclusive('\n').skip(start.saturating_sub(1) as usize).take(count as usize).collect())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// What a save needs of a document's `DocumentInfo`, copied out so the
+/// registry isn't held while the save encodes, encrypts and writes.
+struct Recorded {
+    encoding: String,
+    encoding_chosen: bool,
+    bom: bool,
+    line_ending: LineEnding,
+    key: Option<Arc<Key>>,
+}
+
+/// Writes through a temporary file, so an interrupted save leaves the old
+/// file whole. A symlink is written through to its file, which keeps its mode.
+fn write_document(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let target = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let permissions = std::fs::metadata(&target).map(|metadata| metadata.permissions()).ok();
+    paths::write_atomic_with(&target, |file| {
+        if let Some(permissions) = permissions {
+            file.set_permissions(permissions)?;
+        }
+        file.write_all(bytes)
+    })
+}
+
+/// Writes the document in its recorded encoding and line endings (UTF-8 and
+/// as-is for files we haven't read). Explicit arguments replace the recorded
+/// ones, and .editorconfig rules replace whatever was only detected on read.
+/// With `make_executable_if_shebang`, or the setting when it's not given, a
+/// script starting with `#!` is also made executable. Unless `force`, a file
+/// that already holds the bytes it would get isn't touched. Runs off the
+/// main thread, since encrypting and writing a big file take a while.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn save_document(
+    app: AppHandle,
+    window: tauri::Window,
+    path: String,
+    content: String,
+    encoding: Option<String>,
+    line_ending: Option<LineEnding>,
+    make_executable_if_shebang: Option<bool>,
+    force: Option<bool>,
+) -> Result<Saved, String> {
+    let window = window.label().to_string();
+    tauri::async_runtime::spawn_blocking(move || {
+        let path = paths::decode(&path);
+        let config = app.state::<EditorConfigs>().resolve(&path);
+        let registry = app.state::<DocumentRegistry>();
+        let recorded = registry.0.lock().unwrap().get(&path).map(|info| Recorded {
+            encoding: info.encoding.clone(),
+            encoding_chosen: info.encoding_chosen,
+            bom: info.bom,
+            line_ending: info.line_ending,
+            key: info.key.clone(),
+        });
+        let recorded = recorded.as_ref();
+        // The frontend passes back what was read; only a change from that counts
+        // as the user's choice
+        let encoding = encoding.filter(|label| recorded.map_or(true, |info| info.encoding_chosen || !info.encoding.eq_ignore_ascii_case(label)));
+        let line_ending = line_ending.filter(|ending| recorded.map_or(true, |info| info.line_ending != *ending));
+        let encoding_chosen = encoding.is_some() || recorded.is_some_and(|info| info.encoding_chosen);
+        let recorded_bom = recorded.is_some_and(|info| info.bom);
+        let (label, bom) = match (encoding, config.charset.as_deref().and_then(charset_encoding)) {
+            (Some(label), _) => (label, recorded_bom),
+            (None, Some((label, bom))) => (label.to_string(), bom),
+            (None, None) => (recorded.map_or_else(|| UTF8.to_string(), |info| info.encoding.clone()), recorded_bom),
+        };
+        let line_ending = line_ending.or(config.end_of_line).or(recorded.map(|info| info.line_ending)).unwrap_or(LineEnding::Mixed);
+        let normalized = config.normalize(&content);
+        let text = normalized.as_deref().unwrap_or(&content);
+        let converted = match line_ending {
+            LineEnding::Mixed => None,
+            target => Some(convert_line_endings(text, target)),
+        };
+        let bytes = encode(converted.as_deref().unwrap_or(text), &label, bom)?;
+        let make_executable = || {
+            make_executable_if_shebang.unwrap_or_else(|| app.state::<Mutex<Settings>>().lock().unwrap().make_scripts_executable)
+                && text.starts_with("#!")
+                && set_execute_bits(&path, true).unwrap_or_else(|e| {
+                    log::warn!("Failed to make {} executable: {}", path.display(), e);
+                    false
+                })
+        };
+        // Only while the file is as it was left; another program may have written it since
+        if let Some(info) = registry.0.lock().unwrap().get_mut(&path).filter(|info| holds_already(info, &path, &bytes, force.unwrap_or(false))) {
+            (info.encoding, info.encoding_chosen, info.bom, info.line_ending, info.dirty) = (label.clone(), encoding_chosen, bom, line_ending, false);
+            log::debug!("Left {} as it was; it already holds these bytes", path.display());
+            // Its mode is still the save's to set
+            let made_executable = make_executable();
+            return Ok(Saved { outcome: SaveOutcome::Unchanged, content: normalized, encoding: label, line_ending, made_executable });
+        }
+        let key = recorded.and_then(|info| info.key.clone());
+        if key.is_none() && is_age_path(&path) {
+            return Err(format!("{} has no passphrase to encrypt with; set one before saving", path.display()));
+        }
+        let _awake = (bytes.len() >= LARGE_SAVE_BYTES).then(|| power::hold(&app, "Saving a large file"));
+        let encrypted = key.as_deref().map(|key| encryption::encrypt(key, &bytes)).transpose()?;
+        write_document(&path, encrypted.as_deref().unwrap_or(&bytes)).map_err(|e| {
+            log::warn!("Failed to save {}: {}", path.display(), e);
+            e.to_string()
+        })?;
+        log::info!("Saved {}: {} bytes as {}", path.display(), bytes.len(), label);
+        let made_executable = make_executable();
+        git::file_saved(&app, &path);
+        let baseline = Baseline::new(&path, &bytes);
+        let info = DocumentInfo { encoding: label.clone(), encoding_chosen, bom, line_ending, baseline, key, dirty: false };
+        registry.0.lock().unwrap().insert(path.clone(), info);
+        document_watcher::watch(&app, &window, &path);
+        // Saved under a new name; a lock someone else holds was already told of
+        let _ = file_lock::acquire(&app, &window, &path);
+        Ok(Saved { outcome: SaveOutcome::Written, content: normalized, encoding: label, line_ending, made_executable })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// How an open document's file differs from what skriv last read or wrote,
+/// `None` when only its times do, as after skriv's own save. With
+/// `revert_when_clean`, a document without unsaved edits is read again in
+/// the encoding it has.
+pub fn disk_change(registry: &DocumentRegistry, path: &Path, revert_when_clean: bool) -> Option<DiskChange> {
+    let raw = std::fs::read(path);
+    let mut docs = registry.0.lock().unwrap();
+    let info = docs.get_mut(path)?;
+    let changed = |deleted| Some(DiskChange::Changed(ChangedOnDisk { path: paths::encode(path), deleted, dirty: info.dirty }));
+    let raw = match raw {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return changed(true),
+        Err(e) => {
+            log::warn!("Failed to read {} after it changed: {}", path.display(), e);
+            return None;
+        }
+    };
+    let bytes = match &info.key {
+        Some(key) => encryption::decrypt_with(key, &raw)?,
+        None => raw,
+    };
+    let baseline = Baseline::new(path, &bytes);
+    if (baseline.hash, baseline.len) == (info.baseline.hash, info.baseline.len) {
+        info.baseline = baseline;
+        return None;
+    }
+    if !revert_when_clean || info.dirty {
+        return changed(false);
+    }
+    let (content, had_errors) = decode(&bytes, &info.encoding).ok()?;
+    log::info!("Reverted {} to what's on disk{}", path.display(), if had_errors { ", with invalid bytes" } else { "" });
+    let mtime = baseline.modified.and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok()).map(|since| since.as_millis() as u64);
+    info.line_ending = detect_line_ending(&content);
+    info.baseline = baseline;
+    let (encoding, line_ending) = (info.encoding.clone(), info.line_ending);
+    Some(DiskChange::Reverted(Reverted { path: paths::encode(path), content, mtime, encoding, line_ending }))
+}
+
+/// Whether the tab of `path` has edits not yet saved, which are never
+/// reverted for a change on disk.
+#[tauri::command]
+pub fn set_document_dirty(registry: tauri::State<'_, DocumentRegistry>, path: String, dirty: bool) {
+    if let Some(info) = registry.0.lock().unwrap().get_mut(&paths::decode(&path)) {
+        info.dirty = dirty;
+    }
+}
+
+/// Watches and locks the file of a tab that wasn't read with
+/// `read_document`, as one restored from the session, taking the file as it
+/// is now for what the tab last saw. Returns who else has it open.
+#[tauri::command]
+pub async fn watch_document(app: AppHandle, window: tauri::Window, path: String) -> Option<LockedBy> {
+    let label = window.label().to_string();
+    tauri::async_runtime::spawn_blocking(move || {
+        let path = paths::decode(&path);
+        let registry = app.state::<DocumentRegistry>();
+        if !registry.0.lock().unwrap().contains_key(&path) {
+            // An age file waits for its passphrase until it's read
+            let Ok(bytes) = std::fs::read(&path) else { return None };
+            if encryption::detect(&bytes).is_some() {
+                return None;
+            }
+            let (encoding, bom) = detect(&bytes);
+            let line_ending = decode(&bytes, encoding).map_or(LineEnding::Mixed, |(text, _)| detect_line_ending(&text));
+            let baseline = Baseline::new(&path, &bytes);
+            let info = DocumentInfo { encoding: encoding.to_string(), encoding_chosen: false, bom, line_ending, baseline, key: None, dirty: false };
+            registry.0.lock().unwrap().entry(path.clone()).or_insert(info);
+        }
+        document_watcher::watch(&app, &label, &path);
+        file_lock::acquire(&app, &label, &path)
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// An `.age` name promises encryption, so it's never saved as plain text.
+fn is_age_path(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("age"))
+}
+
+/// Encrypts `path` with a new passphrase asked of the window, or re-encrypts
+/// it when it's an age file already open. A file that doesn't exist yet
+/// only gets the passphrase, for its first save to use.
+#[tauri::command]
+pub async fn change_document_passphrase(app: AppHandle, window: tauri::Window, path: String) -> Result<(), String> {
+    let label = window.label().to_string();
+    tauri::async_runtime::spawn_blocking(move || {
+        let path = paths::decode(&path);
+        let registry = app.state::<DocumentRegistry>();
+        let current = registry.0.lock().unwrap().get(&path).and_then(|info| info.key.clone());
+        let raw = match std::fs::read(&path) {
+            Ok(bytes) => Some(bytes),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.to_string()),
+        };
+        let armored = raw.as_deref().and_then(encryption::detect);
+        let plaintext = match (raw, armored, &current) {
+            (Some(raw), Some(_), Some(key)) => {
+                Some(encryption::decrypt_with(key, &raw).ok_or_else(|| format!("{} changed on disk and no longer opens with its passphrase", path.display()))?)
+            }
+            (Some(_), Some(_), None) => return Err(format!("Open {} before changing its passphrase", path.display())),
+            (raw, _, _) => raw,
+        };
+        let key = Arc::new(encryption::new_key(&app, Some(&label), &path, armored.unwrap_or(false))?);
+        if let Some(plaintext) = &plaintext {
+            write_document(&path, &encryption::encrypt(&key, plaintext)?).map_err(|e| e.to_string())?;
+            log::info!("Encrypted {} with a new passphrase", path.display());
+        }
+        let baseline = Baseline::new(&path, plaintext.as_deref().unwrap_or_default());
+        let mut docs = registry.0.lock().unwrap();
+        match docs.get_mut(&path) {
+            Some(info) => (info.key, info.baseline) = (Some(key), baseline),
+            None => {
+                let info = DocumentInfo { encoding: UTF8.into(), encoding_chosen: false, bom: false, line_ending: LineEnding::Mixed, baseline, key: Some(key), dirty: false };
+                docs.insert(path, info);
+            }
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Drops what's known about an encrypted document once its tab is closed,
+/// passphrase and all.
+#[tauri::command]
+pub fn forget_document_passphrase(registry: tauri::State<'_, DocumentRegistry>, path: String) {
+    let mut docs = registry.0.lock().unwrap();
+    let path = paths::decode(&path);
+    if docs.get(&path).is_some_and(|info| info.key.is_some()) {
+        docs.remove(&path);
+    }
+}
+
+/// Execute permission for whoever may read the file, or for no one.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn execute_mode(mode: u32, executable: bool) -> u32 {
+    if executable {
+        mode | (mode & 0o444) >> 2
+    } else {
+        mode & !0o111
+    }
+}
+
+/// Returns whether the mode changed. Windows has no execute bits; the
+/// extension decides there.
+#[cfg(unix)]
+fn set_execute_bits(path: &Path, executable: bool) -> Result<bool, String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = std::fs::metadata(path).map_err(|e| e.to_string())?.permissions().mode();
+    let changed = execute_mode(mode, executable);
+    if changed == mode {
+        return Ok(false);
+    }
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(changed)).map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+fn set_execute_bits(_path: &Path, _executable: bool) -> Result<bool, String> {
+    Ok(false)
+}
+
+#[tauri::command]
+pub fn set_executable(path: String, executable: bool) -> Result<bool, String> {
+    set_execute_bits(&paths::decode(&path), executable)
+}
+
+/// Clears the quarantine flag macOS puts on downloads, so an edited script
+/// can run. Returns false if the file had none. Only ever done when asked.
+#[tauri::command]
+pub fn remove_quarantine(path: String) -> Result<bool, String> {
+    #[cfg(target_os = "macos")]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = std::ffi::CString::new(paths::decode(&path).as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+        // SAFETY: both strings are NUL-terminated
+        if unsafe { libc::removexattr(path.as_ptr(), c"com.apple.quarantine".as_ptr(), 0) } == 0 {
+            return Ok(true);
+        }
+        let error = std::io::Error::last_os_error();
+        if error.raw_os_error() == Some(libc::ENOATTR) {
+            return Ok(false);
+        }
+        Err(error.to_string())
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = path;
+        Ok(false)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Transform {
+    LineEndings { target: LineEnding },
+}
+
+/// Whole-document rewrites that are too slow to do in the webview for large
+/// files, and so run off the main thread too.
+#[tauri::command]
+pub async fn transform_document(content: String, transform: Transform) -> Result<String, String> {
+    tauri::async_runtime::spawn_blocking(move || match transform {
+        Transform::LineEndings { target: LineEnding::Mixed } => content,
+        Transform::LineEndings { target } => convert_line_endings(&content, target),
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Re-reads the active document in the chosen encoding (File > Reopen with Encoding).
+pub fn reopen_active(app: &AppHandle, label: &str) {
+    let Some(path) = menu_state::current(app).path else { return };
+    let app = app.clone();
+    let label = label.to_string();
+    tauri::async_runtime::spawn_blocking(move || {
+        let window = menu::focused_window(&app).map(|window| window.label().to_string());
+        match read(&app, window.as_deref(), &paths::decode(&path), Some(&label)) {
+            Ok(doc) => menu::emit_to_focused(&app, "document-reopened", doc),
+            Err(e) => log::warn!("Failed to reopen {} as {}: {}", path, label, e),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn execute_follows_read() {
+        assert_eq!(execute_mode(0o100644, true), 0o100755);
+        assert_eq!(execute_mode(0o100600, true), 0o100700);
+        assert_eq!(execute_mode(0o100640, true), 0o100750);
+        assert_eq!(execute_mode(0o100755, false), 0o100644);
+    }
+
+    #[test]
+    fn utf16_keeps_its_bom_or_lack_of_one() {
+        for (label, to_bytes) in [(UTF16LE, u16::to_le_bytes as fn(u16) -> [u8; 2]), (UTF16BE, u16::to_be_bytes)] {
+            let bare: Vec<u8> = "h\u{e9}\u{1f600}".encode_utf16().flat_map(to_bytes).collect();
+            let marked = [to_bytes(0xFEFF).as_slice(), &bare].concat();
+            assert_eq!(encode("h\u{e9}\u{1f600}", label, false).unwrap(), bare);
+            assert_eq!(encode("h\u{e9}\u{1f600}", label, true).unwrap(), marked);
+            assert_eq!(decode(&bare, label).unwrap(), ("h\u{e9}\u{1f600}".to_string(), false));
+            assert_eq!(decode(&marked, label).unwrap(), ("h\u{e9}\u{1f600}".to_string(), false));
+            assert_eq!(detect(&marked), (label, true));
+        }
+        assert_eq!(encode("x", UTF8, true).unwrap(), b"\xEF\xBB\xBFx");
+        assert_eq!(decode(b"caf\xe9", LATIN1).unwrap(), ("caf\u{e9}".to_string(), false));
+        assert!(encode("\u{1f600}", LATIN1, false).is_err());
+    }
+
+    #[test]
+    fn detects_and_converts_line_endings() {
+        assert!(detect_line_ending("a\nb\n") == LineEnding::Lf);
+        assert!(detect_line_ending("a\r\nb\r\n") == LineEnding::Crlf);
+        assert!(detect_line_ending("a\r\nb\n") == LineEnding::Mixed);
+        // No line break at all, or a lone CR, counts as LF
+        assert!(detect_line_ending("a") == LineEnding::Lf);
+        assert!(detect_line_ending("a\rb") == LineEnding::Lf);
+        assert_eq!(convert_line_endings("a\r\nb\nc", LineEnding::Crlf), "a\r\nb\r\nc");
+        assert_eq!(convert_line_endings("a\r\nb\n", LineEnding::Lf), "a\nb\n");
+    }
+
+    #[test]
+    fn skips_rewriting_only_what_the_file_holds_already() {
+        let path = std::env::temp_dir().join(format!("skriv-unchanged-{}.txt", std::process::id()));
+        std::fs::write(&path, b"same\n").unwrap();
+        let info = DocumentInfo {
+            encoding: UTF8.into(),
+            encoding_chosen: false,
+            bom: false,
+            line_ending: LineEnding::Lf,
+            baseline: Baseline::new(&path, b"same\n"),
+            key: None,
+            dirty: true,
+        };
+        assert!(holds_already(&info, &path, b"same\n", false));
+        assert!(!holds_already(&info, &path, b"same\n", true));
+        assert!(!holds_already(&info, &path, b"edited\n", false));
+        // Written by another program since, even if back to the same bytes
+        std::fs::write(&path, b"same\n").unwrap();
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        assert!(!holds_already(&info, &path, b"same\n", false));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_through_symlinks_keeping_the_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("skriv-write-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (file, link) = (dir.join("run.sh"), dir.join("link.sh"));
+        std::fs::write(&file, "old").unwrap();
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o750)).unwrap();
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+        write_document(&link, b"new").unwrap();
+        assert!(std::fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "new");
+        assert_eq!(std::fs::metadata(&file).unwrap().permissions().mode() & 0o777, 0o750);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn wraps_long_lines_with_a_map_back() {
+        let (wrapped, segments) = wrap_long_lines("ok\r\nf(a,b);g(c)\u{1f600}xyz\nend", 4);
+        assert_eq!(wrapped, "ok\r\nf(a,\nb);\ng(c)\n\u{1f600}xyz\nend");
+        let segment = |line, original_line, original_column| WrapSegment { line, original_line, original_column };
+        assert_eq!(segments, [segment(3, 2, 5), segment(4, 2, 8), segment(5, 2, 12)]);
+        assert_eq!(longest_line("short\r\nf(a,b);g(c)\u{1f600}xyz\nend"), 15);
+    }
+
+    #[test]
+    fn reverts_only_real_changes_to_clean_documents() {
+        let path = std::env::temp_dir().join(format!("skriv-disk-change-{}.txt", std::process::id()));
+        std::fs::write(&path, b"caf\xe9\n").unwrap();
+        let registry = DocumentRegistry::default();
+        let info = DocumentInfo {
+            encoding: LATIN1.into(),
+            encoding_chosen: true,
+            bom: false,
+            line_ending: LineEnding::Lf,
+            baseline: Baseline::new(&path, b"caf\xe9\n"),
+            key: None,
+            dirty: true,
+        };
+        registry.0.lock().unwrap().insert(path.clone(), info);
+        // Touched, as a save of the same bytes does
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        assert!(disk_change(&registry, &path, true).is_none());
+        std::fs::write(&path, b"th\xe9\r\n").unwrap();
+        assert!(matches!(disk_change(&registry, &path, true), Some(DiskChange::Changed(ChangedOnDisk { deleted: false, dirty: true, .. }))));
+        registry.0.lock().unwrap().get_mut(&path).unwrap().dirty = false;
+        assert!(matches!(disk_change(&registry, &path, false), Some(DiskChange::Changed(ChangedOnDisk { dirty: false, .. }))));
+        match disk_change(&registry, &path, true) {
+            Some(DiskChange::Reverted(reverted)) => {
+                assert_eq!((reverted.content.as_str(), reverted.encoding.as_str()), ("th\u{e9}\r\n", LATIN1));
+                assert!(reverted.line_ending == LineEnding::Crlf);
+            }
+            _ => panic!("not reverted"),
+        }
+        assert!(disk_change(&registry, &path, true).is_none());
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(disk_change(&registry, &path, true), Some(DiskChange::Changed(ChangedOnDisk { deleted: true, .. }))));
+    }
+}
@@ -0,0 +1,161 @@
+//! Watches the files of open documents for other programs changing them.
+//! Each file's folder is watched rather than the file, since a program that
+//! saves by renaming a new file into place would leave a watch on the file
+//! with the old one. What a change means for the document is up to
+//! `document::disk_change`; it reaches the windows with the file open as
+//! `document-reverted` or `document-changed-on-disk`.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::document::{self, DiskChange, DocumentRegistry};
+use crate::file_events::{self, FileChange};
+use crate::paths;
+use crate::settings::{AutoRevert, Settings};
+
+/// How long a file has to be left alone before it's read, so that a program
+/// writing it in several goes, or saving twice, causes one revert.
+const SETTLE: Duration = Duration::from_millis(300);
+
+#[derive(Default)]
+pub struct DocumentWatcher(Mutex<Watching>);
+
+#[derive(Default)]
+struct Watching {
+    watcher: Option<RecommendedWatcher>,
+    /// The windows each file is open in
+    files: HashMap<PathBuf, HashSet<String>>,
+    /// The latest change to each file not yet handled, by number
+    pending: HashMap<PathBuf, u64>,
+    changes: u64,
+}
+
+impl Watching {
+    fn watches_dir(&self, dir: &Path) -> bool {
+        self.files.keys().any(|file| file.parent() == Some(dir))
+    }
+
+    /// The open files a change is about.
+    fn files_of(&self, change: &FileChange) -> Vec<PathBuf> {
+        match change {
+            FileChange::Bulk { root } => self.files.keys().filter(|file| file.starts_with(root)).cloned().collect(),
+            change => change.paths().into_iter().filter(|path| self.files.contains_key(*path)).map(Path::to_path_buf).collect(),
+        }
+    }
+}
+
+fn changed(app: &AppHandle, changes: Vec<FileChange>) {
+    let watching = app.state::<DocumentWatcher>();
+    let mut watching = watching.0.lock().unwrap();
+    let paths: HashSet<PathBuf> = changes.iter().flat_map(|change| watching.files_of(change)).collect();
+    for path in paths {
+        watching.changes += 1;
+        let change = watching.changes;
+        watching.pending.insert(path.clone(), change);
+        let app = app.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(SETTLE);
+            settled(&app, &path, change);
+        });
+    }
+}
+
+/// Reads the file unless it changed again since `change`.
+fn settled(app: &AppHandle, path: &Path, change: u64) {
+    let labels = {
+        let watching = app.state::<DocumentWatcher>();
+        let mut watching = watching.0.lock().unwrap();
+        if watching.pending.get(path) != Some(&change) {
+            return;
+        }
+        watching.pending.remove(path);
+        match watching.files.get(path) {
+            Some(labels) => labels.clone(),
+            None => return,
+        }
+    };
+    let auto_revert = app.state::<Mutex<Settings>>().lock().unwrap().files.auto_revert;
+    if auto_revert == AutoRevert::Off {
+        return;
+    }
+    match document::disk_change(&app.state::<DocumentRegistry>(), path, auto_revert == AutoRevert::WhenClean) {
+        Some(DiskChange::Reverted(reverted)) => {
+            for label in &labels {
+                let _ = app.emit_to(label, "document-reverted", &reverted);
+            }
+        }
+        Some(DiskChange::Changed(changed)) => {
+            for label in &labels {
+                let _ = app.emit_to(label, "document-changed-on-disk", &changed);
+            }
+        }
+        None => {}
+    }
+}
+
+/// The folders of open documents being watched; none when the watcher
+/// isn't running.
+pub fn watched_paths(app: &AppHandle) -> Option<usize> {
+    let watching = app.state::<DocumentWatcher>();
+    let watching = watching.0.lock().unwrap();
+    let dirs: HashSet<&Path> = watching.files.keys().filter_map(|file| file.parent()).collect();
+    watching.watcher.is_some().then_some(dirs.len())
+}
+
+/// Starts watching `path` for the window, once it's read or saved there.
+pub fn watch(app: &AppHandle, label: &str, path: &Path) {
+    let watching = app.state::<DocumentWatcher>();
+    let mut watching = watching.0.lock().unwrap();
+    let Some(dir) = path.parent().map(Path::to_path_buf) else { return };
+    let new_dir = !watching.watches_dir(&dir);
+    watching.files.entry(path.to_path_buf()).or_default().insert(label.to_string());
+    if !new_dir {
+        return;
+    }
+    if watching.watcher.is_none() {
+        let handle = app.clone();
+        match notify::recommended_watcher(file_events::debounce(Vec::new, move |changes| changed(&handle, changes))) {
+            Ok(watcher) => watching.watcher = Some(watcher),
+            Err(e) => return log::warn!("Failed to watch open documents: {}", e),
+        }
+    }
+    if let Some(Err(e)) = watching.watcher.as_mut().map(|watcher| watcher.watch(&dir, RecursiveMode::NonRecursive)) {
+        log::warn!("Failed to watch {}: {}", dir.display(), e);
+    }
+}
+
+fn forget(watching: &mut Watching, path: &Path, label: &str) {
+    let Some(labels) = watching.files.get_mut(path) else { return };
+    labels.remove(label);
+    if !labels.is_empty() {
+        return;
+    }
+    watching.files.remove(path);
+    watching.pending.remove(path);
+    let Some(dir) = path.parent() else { return };
+    if !watching.watches_dir(dir) {
+        if let Some(watcher) = watching.watcher.as_mut() {
+            let _ = watcher.unwatch(dir);
+        }
+    }
+}
+
+pub fn window_closed(app: &AppHandle, label: &str) {
+    let watching = app.state::<DocumentWatcher>();
+    let mut watching = watching.0.lock().unwrap();
+    let paths: Vec<PathBuf> = watching.files.keys().cloned().collect();
+    for path in paths {
+        forget(&mut watching, &path, label);
+    }
+}
+
+/// Stops watching the file of a tab that closed.
+#[tauri::command]
+pub fn unwatch_document(watcher: tauri::State<'_, DocumentWatcher>, window: tauri::Window, path: String) {
+    forget(&mut watcher.0.lock().unwrap(), &paths::decode(&path), window.label());
+}
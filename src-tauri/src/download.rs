@@ -0,0 +1,333 @@
+//! File > Download to Folder: a URL saved to disk rather than opened. It
+//! streams into `<name>.part` next to the destination and is renamed into
+//! place once complete, so a half-finished download never looks finished. A
+//! `.part` left by an interrupted download is resumed with a Range request
+//! when the server still has the same file.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use reqwest::header::{CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE, USER_AGENT};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use tauri::{AppHandle, Emitter, Url};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+
+use crate::{network, operations, paths, power};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Between chunks, not for the whole download
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_REDIRECTS: usize = 10;
+/// Downloads beyond this wait for one to finish
+const MAX_CONCURRENT: usize = 3;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct Downloads(Semaphore);
+
+impl Default for Downloads {
+    fn default() -> Self {
+        Downloads(Semaphore::new(MAX_CONCURRENT))
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum DownloadError {
+    InvalidUrl { message: String },
+    /// The expected hash isn't one this knows how to check
+    InvalidChecksum { message: String },
+    Network { message: String },
+    Timeout,
+    Status { status: u16, reason: Option<String> },
+    Io { message: String },
+    ChecksumMismatch { expected: String, actual: String },
+    Cancelled,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Progress {
+    id: u64,
+    received: u64,
+    total: Option<u64>,
+}
+
+#[derive(Clone, Serialize)]
+struct Finished {
+    id: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Downloaded {
+    path: String,
+    size: u64,
+    /// Whether an earlier `.part` was carried on from
+    resumed: bool,
+}
+
+/// What a `.part` was downloaded from, kept beside it as `.part.json` so a
+/// resume asks for the rest of the same file.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct PartInfo {
+    url: String,
+    /// The ETag, else the Last-Modified date, for If-Range
+    validator: String,
+}
+
+enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(bytes),
+            Hasher::Sha512(hasher) => hasher.update(bytes),
+        }
+    }
+
+    fn hex(self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Sha512(hasher) => format!("{:x}", hasher.finalize()),
+        }
+    }
+}
+
+/// A hash to check against, as `sha256:<hex>`, `sha512:<hex>` or bare hex,
+/// whose length tells the two apart. Returns the hasher and the hex.
+fn checksum(expected: &str) -> Result<(Hasher, String), DownloadError> {
+    let expected = expected.trim();
+    let (algorithm, hex) = expected.split_once(':').unwrap_or(("", expected));
+    let hex = hex.trim().to_ascii_lowercase();
+    let invalid = |message: String| Err(DownloadError::InvalidChecksum { message });
+    if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return invalid(format!("{} isn't a hex digest", expected));
+    }
+    let hasher = match (algorithm.to_ascii_lowercase().as_str(), hex.len()) {
+        ("sha256" | "", 64) => Hasher::Sha256(Sha256::new()),
+        ("sha512" | "", 128) => Hasher::Sha512(Sha512::new()),
+        ("sha256" | "sha512" | "", _) => return invalid(format!("{} has the wrong length", expected)),
+        (other, _) => return invalid(format!("{} hashes aren't supported", other)),
+    };
+    Ok((hasher, hex))
+}
+
+fn part_paths(dest: &Path) -> (PathBuf, PathBuf) {
+    let mut part = dest.as_os_str().to_owned();
+    part.push(".part");
+    let mut info = part.clone();
+    info.push(".json");
+    (PathBuf::from(part), PathBuf::from(info))
+}
+
+/// How much of the download an existing `.part` holds, and what to send as
+/// If-Range. Nothing when there's no `.part` or it's from another URL.
+fn resumable(part: &Path, info: &Path, url: &str) -> Option<(u64, String)> {
+    let info: PartInfo = serde_json::from_str(&fs::read_to_string(info).ok()?).ok()?;
+    let size = fs::metadata(part).ok()?.len();
+    (info.url == url && size > 0).then_some((size, info.validator))
+}
+
+/// The first byte a 206 starts at, from `bytes <start>-<end>/<total>`.
+fn range_start(content_range: &str) -> Option<u64> {
+    content_range.strip_prefix("bytes ")?.split('-').next()?.trim().parse().ok()
+}
+
+fn io_error(e: std::io::Error) -> DownloadError {
+    DownloadError::Io { message: e.to_string() }
+}
+
+fn network_error(error: reqwest::Error) -> DownloadError {
+    if error.is_timeout() {
+        DownloadError::Timeout
+    } else {
+        DownloadError::Network { message: network::describe(&error) }
+    }
+}
+
+/// Feeds what an earlier run already wrote to the hasher.
+fn hash_file(path: &Path, mut hasher: Hasher) -> std::io::Result<Hasher> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hasher);
+        }
+        hasher.update(&buf[..n]);
+    }
+}
+
+/// Downloads `url` to `dest_path` through the configured proxy. Progress
+/// goes to the window as `download-progress`, the first event as soon as
+/// the operation exists so the window has its id for `cancel_operation`,
+/// and `download-finished` follows however it ends.
+/// With `expected_hash`, a download that doesn't match is kept as `.part`
+/// and reported, never renamed into place. A cancelled or failed download
+/// leaves its `.part` for the next try to resume.
+#[tauri::command]
+pub async fn download_file(
+    app: AppHandle,
+    window: tauri::Window,
+    downloads: tauri::State<'_, Downloads>,
+    url: String,
+    dest_path: String,
+    expected_hash: Option<String>,
+) -> Result<Downloaded, DownloadError> {
+    let target = Url::parse(url.trim()).map_err(|e| DownloadError::InvalidUrl { message: e.to_string() })?;
+    if !matches!(target.scheme(), "http" | "https") {
+        return Err(DownloadError::InvalidUrl { message: format!("{} isn't an http(s) URL", url) });
+    }
+    let check = expected_hash.as_deref().map(checksum).transpose()?;
+    let operation = operations::begin(&app);
+    let emit = |received, total| {
+        let _ = app.emit_to(window.label(), "download-progress", Progress { id: operation.id, received, total });
+    };
+    emit(0, None);
+    let result = fetch(&app, &downloads, &operation, &emit, target, paths::decode(&dest_path), check).await;
+    let _ = app.emit_to(window.label(), "download-finished", Finished { id: operation.id });
+    result
+}
+
+async fn fetch(
+    app: &AppHandle,
+    downloads: &Downloads,
+    operation: &operations::Operation,
+    emit: &(dyn Fn(u64, Option<u64>) + Sync),
+    target: Url,
+    dest: PathBuf,
+    mut check: Option<(Hasher, String)>,
+) -> Result<Downloaded, DownloadError> {
+    let (part, info) = part_paths(&dest);
+    let _permit = downloads.0.acquire().await.map_err(|e| DownloadError::Io { message: e.to_string() })?;
+    if operation.is_cancelled() {
+        return Err(DownloadError::Cancelled);
+    }
+    let _awake = power::hold(app, "Downloading a file");
+    let builder = reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .read_timeout(READ_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::limited(MAX_REDIRECTS));
+    let client = network::configure(builder, &network::route(app)).build().map_err(|e| DownloadError::Network { message: e.to_string() })?;
+
+    let previous = resumable(&part, &info, target.as_str());
+    let mut request = client.get(target.clone()).header(USER_AGENT, "skriv");
+    if let Some((size, validator)) = &previous {
+        request = request.header(RANGE, format!("bytes={}-", size)).header(IF_RANGE, validator);
+    }
+    let mut response = request.send().await.map_err(network_error)?;
+    let status = response.status();
+    if !status.is_success() {
+        log::warn!("{} answered {}", target, status);
+        return Err(DownloadError::Status { status: status.as_u16(), reason: status.canonical_reason().map(str::to_string) });
+    }
+    // A 200 to a Range request means the file changed or ranges aren't
+    // supported; either way it starts over
+    let content_range = response.headers().get(CONTENT_RANGE).and_then(|value| value.to_str().ok());
+    let offset = match &previous {
+        Some((size, _)) if status == StatusCode::PARTIAL_CONTENT && content_range.and_then(range_start) == Some(*size) => *size,
+        _ if status == StatusCode::PARTIAL_CONTENT => {
+            return Err(DownloadError::Network { message: format!("{} sent a range that wasn't asked for", target) });
+        }
+        _ => 0,
+    };
+    let validator = [ETAG, LAST_MODIFIED].iter().find_map(|name| response.headers().get(name)?.to_str().ok().map(str::to_string));
+    let total = response.content_length().map(|length| length + offset);
+
+    let mut file = if offset > 0 {
+        log::info!("Resuming {} at {} bytes", target, offset);
+        if let Some((hasher, expected)) = check.take() {
+            let part = part.clone();
+            let hashed = tauri::async_runtime::spawn_blocking(move || hash_file(&part, hasher)).await;
+            let hasher = hashed.map_err(|e| DownloadError::Io { message: e.to_string() })?.map_err(io_error)?;
+            check = Some((hasher, expected));
+        }
+        tokio::fs::OpenOptions::new().append(true).open(&part).await.map_err(io_error)?
+    } else {
+        log::info!("Downloading {} to {}", target, dest.display());
+        tokio::fs::File::create(&part).await.map_err(io_error)?
+    };
+    match &validator {
+        Some(validator) => {
+            let json = serde_json::to_string(&PartInfo { url: target.to_string(), validator: validator.clone() }).unwrap_or_default();
+            tokio::fs::write(&info, json).await.map_err(io_error)?;
+        }
+        // Without one there's no telling a later resume is the same file
+        None => {
+            let _ = tokio::fs::remove_file(&info).await;
+        }
+    }
+
+    let mut received = offset;
+    let mut last = Instant::now();
+    while let Some(chunk) = response.chunk().await.map_err(network_error)? {
+        if operation.is_cancelled() {
+            log::info!("Cancelled downloading {} at {} bytes", target, received);
+            return Err(DownloadError::Cancelled);
+        }
+        file.write_all(&chunk).await.map_err(io_error)?;
+        if let Some((hasher, _)) = check.as_mut() {
+            hasher.update(&chunk);
+        }
+        received += chunk.len() as u64;
+        if last.elapsed() >= PROGRESS_INTERVAL {
+            last = Instant::now();
+            emit(received, total);
+        }
+    }
+    file.sync_all().await.map_err(io_error)?;
+    drop(file);
+    emit(received, total);
+
+    if let Some((hasher, expected)) = check {
+        let actual = hasher.hex();
+        if actual != expected {
+            log::warn!("{} doesn't match its checksum", target);
+            let _ = tokio::fs::remove_file(&info).await;
+            return Err(DownloadError::ChecksumMismatch { expected, actual });
+        }
+    }
+    tokio::fs::rename(&part, &dest).await.map_err(io_error)?;
+    let _ = tokio::fs::remove_file(&info).await;
+    log::info!("Downloaded {} ({} bytes)", dest.display(), received);
+    Ok(Downloaded { path: paths::encode(&dest), size: received, resumed: offset > 0 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_checksums_with_or_without_the_algorithm() {
+        let sha256 = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let (hasher, hex) = checksum(&format!("SHA256:{}", sha256.to_uppercase())).unwrap();
+        assert_eq!((hasher.hex(), hex.as_str()), (sha256.to_string(), sha256));
+        assert!(matches!(checksum(&"a".repeat(128)), Ok((Hasher::Sha512(_), _))));
+        assert!(matches!(checksum("md5:d41d8cd98f00b204e9800998ecf8427e"), Err(DownloadError::InvalidChecksum { .. })));
+        assert!(matches!(checksum("sha256:abc"), Err(DownloadError::InvalidChecksum { .. })));
+        assert!(matches!(checksum("not hex"), Err(DownloadError::InvalidChecksum { .. })));
+    }
+
+    #[test]
+    fn resumes_only_a_part_from_the_same_url() {
+        let dir = std::env::temp_dir().join(format!("skriv-download-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (part, info) = part_paths(&dir.join("big.iso"));
+        assert_eq!(part.file_name().unwrap(), "big.iso.part");
+        fs::write(&part, b"12345").unwrap();
+        fs::write(&info, serde_json::to_string(&PartInfo { url: "https://x/big.iso".into(), validator: "\"abc\"".into() }).unwrap()).unwrap();
+        assert_eq!(resumable(&part, &info, "https://x/big.iso"), Some((5, "\"abc\"".into())));
+        assert_eq!(resumable(&part, &info, "https://y/big.iso"), None);
+        assert_eq!(range_start("bytes 5-99/100"), Some(5));
+        assert_eq!(range_start("bytes */100"), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
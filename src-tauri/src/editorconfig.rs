@@ -0,0 +1,445 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use regex::Regex;
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+use crate::document::LineEnding;
+use crate::paths;
+
+const FILE_NAME: &str = ".editorconfig";
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndentStyle {
+    Tab,
+    Space,
+}
+
+/// The properties `.editorconfig` files set for one path. Unset, unknown and
+/// invalid values are `None`.
+#[derive(Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EditorConfig {
+    pub indent_style: Option<IndentStyle>,
+    pub indent_size: Option<u32>,
+    pub tab_width: Option<u32>,
+    pub end_of_line: Option<LineEnding>,
+    /// As written: latin1, utf-8, utf-8-bom, utf-16be or utf-16le
+    pub charset: Option<String>,
+    pub trim_trailing_whitespace: Option<bool>,
+    pub insert_final_newline: Option<bool>,
+    pub max_line_length: Option<u32>,
+}
+
+impl EditorConfig {
+    fn from_properties(properties: &HashMap<String, String>) -> Self {
+        let get = |key: &str| properties.get(key).map(String::as_str);
+        let number = |key: &str| get(key).and_then(|v| v.parse().ok());
+        let flag = |key: &str| match get(key) {
+            Some("true") => Some(true),
+            Some("false") => Some(false),
+            _ => None,
+        };
+        let indent_style = match get("indent_style") {
+            Some("tab") => Some(IndentStyle::Tab),
+            Some("space") => Some(IndentStyle::Space),
+            _ => None,
+        };
+        // indent_size = tab means "as wide as a tab", and each defaults to the other
+        let tab_width = number("tab_width").or_else(|| number("indent_size"));
+        let indent_size = match get("indent_size") {
+            Some("tab") => tab_width,
+            _ => number("indent_size").or(if indent_style == Some(IndentStyle::Tab) { tab_width } else { None }),
+        };
+        let end_of_line = match get("end_of_line") {
+            Some("lf") => Some(LineEnding::Lf),
+            Some("crlf") => Some(LineEnding::Crlf),
+            _ => None,
+        };
+        let charset = get("charset").filter(|c| ["latin1", "utf-8", "utf-8-bom", "utf-16be", "utf-16le"].contains(c)).map(Into::into);
+        EditorConfig {
+            indent_style,
+            indent_size,
+            tab_width,
+            end_of_line,
+            charset,
+            trim_trailing_whitespace: flag("trim_trailing_whitespace"),
+            insert_final_newline: flag("insert_final_newline"),
+            max_line_length: number("max_line_length"),
+        }
+    }
+
+    /// `text` with trailing whitespace and the final newline made to match,
+    /// or `None` when it already does.
+    pub fn normalize(&self, text: &str) -> Option<String> {
+        let mut out = String::with_capacity(text.len() + 2);
+        if self.trim_trailing_whitespace == Some(true) {
+            for line in text.split_inclusive('\n') {
+                let (body, ending) = match line.strip_suffix("\r\n") {
+                    Some(body) => (body, "\r\n"),
+                    None => line.strip_suffix('\n').map_or((line, ""), |body| (body, "\n")),
+                };
+                out.push_str(body.trim_end_matches([' ', '\t']));
+                out.push_str(ending);
+            }
+        } else {
+            out.push_str(text);
+        }
+        match self.insert_final_newline {
+            Some(true) if !out.is_empty() && !out.ends_with('\n') => {
+                out.push_str(if text.contains("\r\n") { "\r\n" } else { "\n" });
+            }
+            Some(false) => {
+                let trimmed = out.trim_end_matches(['\r', '\n']).len();
+                out.truncate(trimmed);
+            }
+            _ => {}
+        }
+        (out != text).then_some(out)
+    }
+}
+
+struct Section {
+    pattern: Regex,
+    /// Bounds for each `{n..m}` in the glob, checked against its capture
+    ranges: Vec<(i64, i64)>,
+    properties: Vec<(String, String)>,
+}
+
+impl Section {
+    fn matches(&self, relative: &str) -> bool {
+        let Some(captures) = self.pattern.captures(relative) else { return false };
+        self.ranges.iter().enumerate().all(|(i, (low, high))| {
+            captures.get(i + 1).and_then(|m| m.as_str().parse::<i64>().ok()).is_some_and(|n| (*low..=*high).contains(&n))
+        })
+    }
+}
+
+#[derive(Default)]
+struct ConfigFile {
+    root: bool,
+    sections: Vec<Section>,
+}
+
+/// The closing brace of a `{...}` opened just before `start`, if any.
+fn closing_brace(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Splits a brace group's contents on its top-level commas.
+fn alternatives(chars: &[char]) -> Vec<&[char]> {
+    let (mut parts, mut depth, mut start, mut i) = (Vec::new(), 0, 0, 0);
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&chars[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    parts.push(&chars[start..]);
+    parts
+}
+
+fn numeric_range(chars: &[char]) -> Option<(i64, i64)> {
+    let text: String = chars.iter().collect();
+    let (low, high) = text.split_once("..")?;
+    let (low, high) = (low.parse::<i64>().ok()?, high.parse::<i64>().ok()?);
+    Some((low.min(high), low.max(high)))
+}
+
+/// Translates an EditorConfig glob into `out`, a regex over `/`-separated paths.
+fn translate(chars: &[char], out: &mut String, ranges: &mut Vec<(i64, i64)>) {
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                out.push_str(".*");
+                i += 1;
+            }
+            '*' => out.push_str("[^/]*"),
+            '?' => out.push_str("[^/]"),
+            '[' => match chars[i + 1..].iter().position(|c| *c == ']').map(|end| i + 1 + end) {
+                Some(end) if !chars[i + 1..end].contains(&'/') => {
+                    out.push('[');
+                    let mut class = &chars[i + 1..end];
+                    if let Some(rest) = class.strip_prefix(&['!']) {
+                        out.push('^');
+                        class = rest;
+                    }
+                    for c in class {
+                        if matches!(c, '\\' | '[' | ']' | '^' | '&' | '~') {
+                            out.push('\\');
+                        }
+                        out.push(*c);
+                    }
+                    out.push(']');
+                    i = end;
+                }
+                _ => out.push_str("\\["),
+            },
+            '{' => match closing_brace(chars, i + 1) {
+                Some(end) => {
+                    let inner = &chars[i + 1..end];
+                    let parts = alternatives(inner);
+                    if let Some(range) = numeric_range(inner) {
+                        out.push_str("([+-]?\\d+)");
+                        ranges.push(range);
+                    } else if parts.len() > 1 {
+                        out.push_str("(?:");
+                        for (n, part) in parts.iter().enumerate() {
+                            if n > 0 {
+                                out.push('|');
+                            }
+                            translate(part, out, ranges);
+                        }
+                        out.push(')');
+                    } else {
+                        // A single choice is just text in braces
+                        out.push_str("\\{");
+                        translate(inner, out, ranges);
+                        out.push_str("\\}");
+                    }
+                    i = end;
+                }
+                None => out.push_str("\\{"),
+            },
+            '\\' if i + 1 < chars.len() => {
+                out.push_str(&regex::escape(&chars[i + 1].to_string()));
+                i += 1;
+            }
+            c => out.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+}
+
+/// A section glob as a regex over paths relative to the file's directory.
+/// Globs without a slash match file names in any subdirectory.
+fn section(glob: &str) -> Option<Section> {
+    let (pattern, ranges) = compile(glob, "")?;
+    Some(Section { pattern, ranges, properties: Vec::new() })
+}
+
+fn compile(glob: &str, flags: &str) -> Option<(Regex, Vec<(i64, i64)>)> {
+    let (anchored, glob) = match glob.strip_prefix('/') {
+        Some(rest) => (true, rest),
+        None => (glob.contains('/'), glob),
+    };
+    let chars: Vec<char> = glob.chars().collect();
+    let mut pattern = format!("{}{}", flags, if anchored { "^" } else { "^(?:.*/)?" });
+    let mut ranges = Vec::new();
+    translate(&chars, &mut pattern, &mut ranges);
+    pattern.push('$');
+    Some((Regex::new(&pattern).ok()?, ranges))
+}
+
+/// An EditorConfig-style glob for use elsewhere, matched against
+/// `/`-separated paths. Numeric ranges match any number.
+pub(crate) fn glob(glob: &str, ignore_case: bool) -> Option<Regex> {
+    compile(glob, if ignore_case { "(?i)" } else { "" }).map(|(pattern, _)| pattern)
+}
+
+fn parse(text: &str) -> ConfigFile {
+    let mut file = ConfigFile::default();
+    // Sections whose glob doesn't compile are skipped with their properties
+    let mut current: Option<Option<Section>> = None;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        if let Some(glob) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            if let Some(Some(section)) = current.take() {
+                file.sections.push(section);
+            }
+            current = Some(section(glob));
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else { continue };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim().to_string();
+        match &mut current {
+            None if key == "root" => file.root = value.eq_ignore_ascii_case("true"),
+            None => {}
+            Some(None) => {}
+            Some(Some(section)) => section.properties.push((key, value)),
+        }
+    }
+    if let Some(Some(section)) = current {
+        file.sections.push(section);
+    }
+    file
+}
+
+/// The path as the `.editorconfig` in `dir` sees it.
+fn relative_to(path: &Path, dir: &Path) -> Option<String> {
+    let relative = path.strip_prefix(dir).ok()?;
+    let parts: Vec<String> = relative.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+    Some(parts.join("/"))
+}
+
+/// Parsed `.editorconfig` files by directory, `None` where there's none.
+/// Directories are watched once consulted, so edits show up on the next
+/// resolve.
+#[derive(Default)]
+pub struct EditorConfigs {
+    files: Arc<Mutex<HashMap<PathBuf, Option<Arc<ConfigFile>>>>>,
+    watcher: Mutex<Option<RecommendedWatcher>>,
+    watched: Mutex<HashSet<PathBuf>>,
+}
+
+impl EditorConfigs {
+    fn file_in(&self, dir: &Path) -> Option<Arc<ConfigFile>> {
+        if let Some(file) = self.files.lock().unwrap().get(dir) {
+            return file.clone();
+        }
+        if let Err(e) = self.watch(dir) {
+            log::warn!("Failed to watch {} for .editorconfig changes: {}", dir.display(), e);
+        }
+        let file = std::fs::read_to_string(dir.join(FILE_NAME)).ok().map(|text| Arc::new(parse(&text)));
+        self.files.lock().unwrap().insert(dir.to_path_buf(), file.clone());
+        file
+    }
+
+    fn watch(&self, dir: &Path) -> Result<(), String> {
+        let mut watched = self.watched.lock().unwrap();
+        if watched.contains(dir) {
+            return Ok(());
+        }
+        let mut watcher = self.watcher.lock().unwrap();
+        if watcher.is_none() {
+            let files = self.files.clone();
+            *watcher = Some(
+                notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+                    let Ok(event) = res else { return };
+                    if matches!(event.kind, EventKind::Access(_)) {
+                        return;
+                    }
+                    let mut files = files.lock().unwrap();
+                    for path in event.paths.iter().filter(|p| p.file_name().is_some_and(|f| f == FILE_NAME)) {
+                        if let Some(dir) = path.parent() {
+                            files.remove(dir);
+                        }
+                    }
+                })
+                .map_err(|e| e.to_string())?,
+            );
+        }
+        let watcher = watcher.as_mut().expect("watcher was just created");
+        watcher.watch(dir, RecursiveMode::NonRecursive).map_err(|e| e.to_string())?;
+        watched.insert(dir.to_path_buf());
+        Ok(())
+    }
+
+    /// The merged properties for `path`, from the `.editorconfig` files in
+    /// its directory and above, up to one marked `root = true`. Nearer files
+    /// and later sections win.
+    pub fn resolve(&self, path: &Path) -> EditorConfig {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let mut chain = Vec::new();
+        for dir in path.ancestors().skip(1) {
+            if let Some(file) = self.file_in(dir) {
+                let root = file.root;
+                chain.push((dir, file));
+                if root {
+                    break;
+                }
+            }
+        }
+        let mut properties = HashMap::new();
+        for (dir, file) in chain.iter().rev() {
+            let Some(relative) = relative_to(&path, dir) else { continue };
+            for section in file.sections.iter().filter(|s| s.matches(&relative)) {
+                for (key, value) in &section.properties {
+                    properties.insert(key.clone(), value.to_ascii_lowercase());
+                }
+            }
+        }
+        properties.retain(|_, value| value != "unset");
+        EditorConfig::from_properties(&properties)
+    }
+}
+
+/// The `.editorconfig` properties that apply to `path`.
+#[tauri::command]
+pub async fn resolve_editorconfig(app: AppHandle, path: String) -> Result<EditorConfig, String> {
+    tauri::async_runtime::spawn_blocking(move || app.state::<EditorConfigs>().resolve(&paths::decode(&path))).await.map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(glob: &str, path: &str) -> bool {
+        section(glob).unwrap().matches(path)
+    }
+
+    #[test]
+    fn matches_globs() {
+        assert!(matches("*", "a.txt"));
+        assert!(matches("*.js", "src/lib/a.js"));
+        assert!(!matches("/*.js", "src/a.js"));
+        assert!(matches("src/*.js", "src/a.js"));
+        assert!(!matches("src/*.js", "src/lib/a.js"));
+        assert!(matches("src/**.js", "src/lib/a.js"));
+        assert!(matches("*.{js,ts}", "a.ts"));
+        assert!(!matches("*.{js,ts}", "a.rs"));
+        assert!(matches("{package.json,.travis.yml}", "package.json"));
+        assert!(matches("file[0-9].txt", "file3.txt"));
+        assert!(!matches("file[!0-9].txt", "file3.txt"));
+        assert!(matches("a{1..3}.txt", "a2.txt"));
+        assert!(!matches("a{1..3}.txt", "a4.txt"));
+        assert!(matches("{a}", "{a}"));
+        assert!(matches("a\\*b", "a*b"));
+        assert!(!matches("a\\*b", "axb"));
+    }
+
+    #[test]
+    fn parses_and_merges_sections() {
+        let file = parse("root = true\n\n[*]\nindent_style = space\nindent_size = 4\n\n# comment\n[*.md]\ntrim_trailing_whitespace = false\nindent_size = unset\n");
+        assert!(file.root);
+        assert_eq!(file.sections.len(), 2);
+        let mut properties = HashMap::new();
+        for section in file.sections.iter().filter(|s| s.matches("docs/README.md")) {
+            for (key, value) in &section.properties {
+                properties.insert(key.clone(), value.to_ascii_lowercase());
+            }
+        }
+        properties.retain(|_, value| value != "unset");
+        let config = EditorConfig::from_properties(&properties);
+        assert_eq!(config.indent_style, Some(IndentStyle::Space));
+        assert_eq!(config.indent_size, None);
+        assert_eq!(config.trim_trailing_whitespace, Some(false));
+    }
+
+    #[test]
+    fn normalizes_whitespace_and_final_newline() {
+        let config = EditorConfig { trim_trailing_whitespace: Some(true), insert_final_newline: Some(true), ..Default::default() };
+        assert_eq!(config.normalize("a  \r\nb\t"), Some("a\r\nb\r\n".into()));
+        assert_eq!(config.normalize("a\n"), None);
+        let no_newline = EditorConfig { insert_final_newline: Some(false), ..Default::default() };
+        assert_eq!(no_newline.normalize("a\n\n"), Some("a".into()));
+    }
+}
@@ -0,0 +1,225 @@
+//! Documents encrypted at rest as age files (https://age-encryption.org)
+//! with a passphrase, binary or armored. Reading one asks the window for the
+//! passphrase with `passphrase-required` and waits for `provide_passphrase`,
+//! which checks it and answers with a retry-able error while attempts are
+//! left. The passphrase then stays in memory, as a secret that's wiped when
+//! dropped, for saves to encrypt with; it's never logged or written anywhere.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use age::secrecy::{ExposeSecret, SecretString};
+use age::DecryptError;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::paths;
+
+const BINARY_HEADER: &[u8] = b"age-encryption.org/v1\n";
+const ARMOR_HEADER: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+/// Wrong passphrases before the read gives up
+const ATTEMPTS: u32 = 5;
+/// How long a read waits for the passphrase
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The passphrase a document is encrypted with, and how its file is laid out.
+pub struct Key {
+    passphrase: SecretString,
+    armored: bool,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Purpose {
+    /// The passphrase an existing file was encrypted with
+    Unlock,
+    /// A new one to encrypt with
+    New,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PassphraseRequired {
+    request_id: u64,
+    path: String,
+    purpose: Purpose,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum PassphraseError {
+    /// The read is still waiting when `remaining` isn't 0
+    WrongPassphrase { attempts: u32, remaining: u32 },
+    Empty,
+    /// Nothing is waiting for this request any more
+    NoRequest,
+    Other { message: String },
+}
+
+struct Pending {
+    /// What to check an unlock passphrase against; none for a new one
+    ciphertext: Option<Arc<Vec<u8>>>,
+    attempts: u32,
+    sender: mpsc::Sender<Result<Unlocked, String>>,
+}
+
+struct Unlocked {
+    plaintext: Vec<u8>,
+    passphrase: SecretString,
+}
+
+/// Reads waiting on a passphrase, by request id.
+#[derive(Default)]
+pub struct Passphrases {
+    next: AtomicU64,
+    waiting: Mutex<HashMap<u64, Pending>>,
+}
+
+/// Whether `bytes` are an age file, and whether armored.
+pub fn detect(bytes: &[u8]) -> Option<bool> {
+    if bytes.starts_with(BINARY_HEADER) {
+        return Some(false);
+    }
+    let start = bytes.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(bytes.len());
+    bytes[start..].starts_with(ARMOR_HEADER).then_some(true)
+}
+
+fn decrypt(passphrase: &SecretString, ciphertext: &[u8]) -> Result<Vec<u8>, DecryptError> {
+    age::decrypt(&age::scrypt::Identity::new(passphrase.clone()), ciphertext)
+}
+
+pub fn encrypt(key: &Key, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let recipient = age::scrypt::Recipient::new(key.passphrase.clone());
+    let encrypted = if key.armored { age::encrypt_and_armor(&recipient, plaintext).map(String::into_bytes) } else { age::encrypt(&recipient, plaintext) };
+    encrypted.map_err(|e| format!("Failed to encrypt: {}", e))
+}
+
+/// Opens `ciphertext` with the key a document already has, say to reload it
+/// after another program wrote it, without asking again.
+pub fn decrypt_with(key: &Key, ciphertext: &[u8]) -> Option<Vec<u8>> {
+    decrypt(&key.passphrase, ciphertext).ok()
+}
+
+/// Asks the window and waits. `Err` is cancelling, running out of attempts
+/// or the prompt timing out.
+fn ask(app: &AppHandle, window: Option<&str>, path: &Path, purpose: Purpose, ciphertext: Option<Vec<u8>>) -> Result<Unlocked, String> {
+    let passphrases = app.state::<Passphrases>();
+    let request_id = passphrases.next.fetch_add(1, Ordering::Relaxed) + 1;
+    let (sender, receiver) = mpsc::channel();
+    let pending = Pending { ciphertext: ciphertext.map(Arc::new), attempts: 0, sender };
+    passphrases.waiting.lock().unwrap().insert(request_id, pending);
+    let required = PassphraseRequired { request_id, path: paths::encode(path), purpose };
+    let emitted = match window {
+        Some(window) => app.emit_to(window, "passphrase-required", required),
+        None => app.emit("passphrase-required", required),
+    };
+    if let Err(e) = emitted {
+        passphrases.waiting.lock().unwrap().remove(&request_id);
+        return Err(e.to_string());
+    }
+    let answer = receiver.recv_timeout(PROMPT_TIMEOUT);
+    passphrases.waiting.lock().unwrap().remove(&request_id);
+    answer.map_err(|_| format!("No passphrase was given for {}", path.display()))?
+}
+
+/// The plaintext of an age file and the key to save it with again.
+pub fn unlock(app: &AppHandle, window: Option<&str>, path: &Path, ciphertext: Vec<u8>) -> Result<(Vec<u8>, Key), String> {
+    let armored = detect(&ciphertext).unwrap_or(false);
+    let unlocked = ask(app, window, path, Purpose::Unlock, Some(ciphertext))?;
+    log::info!("Decrypted {}", path.display());
+    Ok((unlocked.plaintext, Key { passphrase: unlocked.passphrase, armored }))
+}
+
+/// A new passphrase for `path`, keeping its layout when it was armored.
+pub fn new_key(app: &AppHandle, window: Option<&str>, path: &Path, armored: bool) -> Result<Key, String> {
+    let unlocked = ask(app, window, path, Purpose::New, None)?;
+    Ok(Key { passphrase: unlocked.passphrase, armored })
+}
+
+/// Answers a `passphrase-required`, or cancels it with `None`. An unlock
+/// passphrase is checked here, so a wrong one comes back to the prompt
+/// rather than failing the read.
+#[tauri::command]
+pub async fn provide_passphrase(app: AppHandle, request_id: u64, secret: Option<String>) -> Result<(), PassphraseError> {
+    let passphrases = app.state::<Passphrases>();
+    let Some(secret) = secret.map(SecretString::from) else {
+        if let Some(pending) = passphrases.waiting.lock().unwrap().remove(&request_id) {
+            let _ = pending.sender.send(Err("Cancelled".into()));
+        }
+        return Ok(());
+    };
+    if secret.expose_secret().is_empty() {
+        return Err(PassphraseError::Empty);
+    }
+    let ciphertext = match passphrases.waiting.lock().unwrap().get(&request_id) {
+        Some(pending) => pending.ciphertext.clone(),
+        None => return Err(PassphraseError::NoRequest),
+    };
+    let Some(ciphertext) = ciphertext else {
+        if let Some(pending) = passphrases.waiting.lock().unwrap().remove(&request_id) {
+            let _ = pending.sender.send(Ok(Unlocked { plaintext: Vec::new(), passphrase: secret }));
+        }
+        return Ok(());
+    };
+    // scrypt takes about a second on purpose
+    let check = secret.clone();
+    let result = tauri::async_runtime::spawn_blocking(move || decrypt(&check, &ciphertext))
+        .await
+        .map_err(|e| PassphraseError::Other { message: e.to_string() })?;
+    let mut waiting = passphrases.waiting.lock().unwrap();
+    let Some(pending) = waiting.get_mut(&request_id) else { return Err(PassphraseError::NoRequest) };
+    match result {
+        Ok(plaintext) => {
+            let pending = waiting.remove(&request_id).unwrap();
+            let _ = pending.sender.send(Ok(Unlocked { plaintext, passphrase: secret }));
+            Ok(())
+        }
+        Err(DecryptError::DecryptionFailed | DecryptError::NoMatchingKeys) => {
+            pending.attempts += 1;
+            let (attempts, remaining) = (pending.attempts, ATTEMPTS.saturating_sub(pending.attempts));
+            log::info!("Wrong passphrase, {} attempts left", remaining);
+            if remaining == 0 {
+                let pending = waiting.remove(&request_id).unwrap();
+                let _ = pending.sender.send(Err(format!("The passphrase was wrong {} times", attempts)));
+            }
+            Err(PassphraseError::WrongPassphrase { attempts, remaining })
+        }
+        // A broken file or one needing too much work stays that way, however often it's asked
+        Err(e) => {
+            let message = e.to_string();
+            let pending = waiting.remove(&request_id).unwrap();
+            let _ = pending.sender.send(Err(message.clone()));
+            Err(PassphraseError::Other { message })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(passphrase: &str, armored: bool) -> Key {
+        Key { passphrase: SecretString::from(passphrase.to_string()), armored }
+    }
+
+    #[test]
+    fn round_trips_binary_and_armored() {
+        for armored in [false, true] {
+            let encrypted = encrypt(&key("correct horse", armored), "Dear diary\n".as_bytes()).unwrap();
+            assert_eq!(detect(&encrypted), Some(armored));
+            assert_eq!(decrypt_with(&key("correct horse", armored), &encrypted).as_deref(), Some("Dear diary\n".as_bytes()));
+            assert!(matches!(decrypt(&SecretString::from("wrong".to_string()), &encrypted), Err(DecryptError::DecryptionFailed)));
+        }
+    }
+
+    #[test]
+    fn tells_age_files_from_text() {
+        assert_eq!(detect(b"  \n-----BEGIN AGE ENCRYPTED FILE-----\nYWdl"), Some(true));
+        assert_eq!(detect(b"age-encryption.org/v1\n-> scrypt"), Some(false));
+        assert_eq!(detect(b"age-encryption.org is where the format is described"), None);
+        assert_eq!(detect(b""), None);
+    }
+}
@@ -0,0 +1,270 @@
+//! Turns the flood of events a watcher sees during `git checkout` or a build
+//! into a few changes. Events are gathered for a short window and then
+//! handed on together, with what happened to each path in the meantime
+//! folded into one change, and a root with too many changes reported as a
+//! whole.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind};
+use serde::Serialize;
+
+/// How long events are gathered after the first one.
+const WINDOW: Duration = Duration::from_millis(100);
+/// Changed paths under one root past which it's reported as a bulk change.
+const BULK_LIMIT: usize = 200;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum FileChange {
+    Created { path: PathBuf },
+    Modified { path: PathBuf },
+    Removed { path: PathBuf },
+    Renamed { from: PathBuf, to: PathBuf },
+    /// Too much changed under `root` to list; whatever shows it should be
+    /// read again
+    Bulk { root: PathBuf },
+}
+
+impl FileChange {
+    /// The paths it's about, the new name first for a rename.
+    pub fn paths(&self) -> Vec<&Path> {
+        match self {
+            FileChange::Created { path } | FileChange::Modified { path } | FileChange::Removed { path } => vec![path],
+            FileChange::Renamed { from, to } => vec![to, from],
+            FileChange::Bulk { root } => vec![root],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Seen {
+    Created,
+    Modified,
+    Removed,
+}
+
+/// The events of one window, by path.
+#[derive(Default)]
+struct Batch {
+    /// Paths in the order first seen; some may have dropped out of `seen`
+    order: Vec<PathBuf>,
+    seen: HashMap<PathBuf, Seen>,
+    renames: Vec<(PathBuf, PathBuf)>,
+    /// The old name of a rename, until the event with the new one
+    pending_from: Option<(Option<usize>, PathBuf)>,
+}
+
+impl Batch {
+    fn note(&mut self, path: PathBuf, now: Seen) {
+        let merged = match (self.seen.get(&path), now) {
+            (None, now) => Some(now),
+            // A temporary file, gone again
+            (Some(Seen::Created), Seen::Removed) => None,
+            (Some(Seen::Created), _) => Some(Seen::Created),
+            // Deleted and written again, as some programs save
+            (Some(Seen::Removed), Seen::Created | Seen::Modified) => Some(Seen::Modified),
+            (Some(_), now) => Some(now),
+        };
+        match merged {
+            Some(seen) => {
+                if self.seen.insert(path.clone(), seen).is_none() {
+                    self.order.push(path);
+                }
+            }
+            None => {
+                self.seen.remove(&path);
+            }
+        }
+    }
+
+    fn rename(&mut self, from: PathBuf, to: PathBuf) {
+        if self.seen.get(&from) == Some(&Seen::Created) {
+            // Written under a temporary name and moved into place
+            self.seen.remove(&from);
+            self.note(to, Seen::Modified);
+        } else if let Some(earlier) = self.renames.iter_mut().find(|(_, name)| *name == from) {
+            earlier.1 = to;
+        } else {
+            self.renames.push((from, to));
+        }
+    }
+
+    /// An old name whose new one never came was moved out of sight.
+    fn settle_rename(&mut self) {
+        if let Some((_, from)) = self.pending_from.take() {
+            self.note(from, Seen::Removed);
+        }
+    }
+
+    fn push(&mut self, event: Event) {
+        let tracker = event.attrs.tracker();
+        let mut paths = event.paths.into_iter();
+        match event.kind {
+            EventKind::Access(_) => {}
+            EventKind::Create(_) => paths.for_each(|path| self.note(path, Seen::Created)),
+            EventKind::Remove(_) => paths.for_each(|path| self.note(path, Seen::Removed)),
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                if let (Some(from), Some(to)) = (paths.next(), paths.next()) {
+                    self.rename(from, to);
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                self.settle_rename();
+                self.pending_from = paths.next().map(|path| (tracker, path));
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                for to in paths {
+                    match self.pending_from.take() {
+                        Some((from_tracker, from)) if from_tracker == tracker => self.rename(from, to),
+                        unpaired => {
+                            self.pending_from = unpaired;
+                            self.settle_rename();
+                            self.note(to, Seen::Created);
+                        }
+                    }
+                }
+            }
+            // FSEvents doesn't pair a rename's names; the one there now is the new one
+            EventKind::Modify(ModifyKind::Name(_)) => {
+                for path in paths {
+                    let seen = if path.exists() { Seen::Created } else { Seen::Removed };
+                    self.note(path, seen);
+                }
+            }
+            _ => paths.for_each(|path| self.note(path, Seen::Modified)),
+        }
+    }
+
+    /// The window's changes, leaving the batch empty. A path is counted
+    /// against the deepest of `roots` holding it, or its own folder.
+    fn drain(&mut self, roots: &[PathBuf]) -> Vec<FileChange> {
+        self.settle_rename();
+        let mut seen = std::mem::take(&mut self.seen);
+        let mut changes: Vec<FileChange> = std::mem::take(&mut self.order)
+            .into_iter()
+            .filter_map(|path| {
+                let change = match seen.remove(&path)? {
+                    Seen::Created => FileChange::Created { path },
+                    Seen::Modified => FileChange::Modified { path },
+                    Seen::Removed => FileChange::Removed { path },
+                };
+                Some(change)
+            })
+            .collect();
+        changes.extend(self.renames.drain(..).map(|(from, to)| FileChange::Renamed { from, to }));
+        let root_of = |change: &FileChange| -> PathBuf {
+            let path = change.paths()[0];
+            let root = roots.iter().filter(|root| path.starts_with(root)).max_by_key(|root| root.as_os_str().len());
+            root.cloned().unwrap_or_else(|| path.parent().unwrap_or(path).to_path_buf())
+        };
+        let mut counts: HashMap<PathBuf, usize> = HashMap::new();
+        for change in &changes {
+            *counts.entry(root_of(change)).or_default() += 1;
+        }
+        let mut reported = HashSet::new();
+        let mut coalesced = Vec::new();
+        for change in changes {
+            let root = root_of(&change);
+            if counts[&root] <= BULK_LIMIT {
+                coalesced.push(change);
+            } else if reported.insert(root.clone()) {
+                coalesced.push(FileChange::Bulk { root });
+            }
+        }
+        coalesced
+    }
+}
+
+fn run(events: Receiver<notify::Result<Event>>, roots: impl Fn() -> Vec<PathBuf>, mut handler: impl FnMut(Vec<FileChange>)) {
+    let mut batch = Batch::default();
+    while let Ok(first) = events.recv() {
+        let deadline = Instant::now() + WINDOW;
+        let mut next = first;
+        loop {
+            if let Ok(event) = next {
+                batch.push(event);
+            }
+            next = match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+            };
+        }
+        let changes = batch.drain(&roots());
+        if !changes.is_empty() {
+            handler(changes);
+        }
+    }
+}
+
+/// An event handler for a watcher that passes `handler` the changes each
+/// window gathers. `roots` names the folders a bulk change is reported for.
+/// The thread doing it ends with the watcher.
+pub fn debounce(
+    roots: impl Fn() -> Vec<PathBuf> + Send + 'static,
+    handler: impl FnMut(Vec<FileChange>) + Send + 'static,
+) -> Sender<notify::Result<Event>> {
+    let (sender, events) = mpsc::channel();
+    std::thread::spawn(move || run(events, roots, handler));
+    sender
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, DataChange, RemoveKind};
+
+    fn event(kind: EventKind, paths: &[&str]) -> Event {
+        paths.iter().fold(Event::new(kind), |event, path| event.add_path(PathBuf::from(path)))
+    }
+
+    fn drained(events: Vec<Event>) -> Vec<FileChange> {
+        let mut batch = Batch::default();
+        events.into_iter().for_each(|e| batch.push(e));
+        batch.drain(&[PathBuf::from("/repo")])
+    }
+
+    #[test]
+    fn folds_each_paths_events() {
+        let modify = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+        let changes = drained(vec![
+            event(EventKind::Create(CreateKind::File), &["/repo/a.tmp"]),
+            event(modify, &["/repo/b"]),
+            event(EventKind::Remove(RemoveKind::File), &["/repo/a.tmp"]),
+            event(modify, &["/repo/b"]),
+            event(EventKind::Create(CreateKind::File), &["/repo/c.swp"]),
+            event(EventKind::Modify(ModifyKind::Name(RenameMode::Both)), &["/repo/c.swp", "/repo/c"]),
+            event(EventKind::Modify(ModifyKind::Name(RenameMode::From)), &["/repo/d"]),
+            event(EventKind::Modify(ModifyKind::Name(RenameMode::To)), &["/repo/e"]),
+        ]);
+        let path = |p: &str| PathBuf::from(p);
+        assert_eq!(
+            changes,
+            [
+                FileChange::Modified { path: path("/repo/b") },
+                FileChange::Modified { path: path("/repo/c") },
+                FileChange::Renamed { from: path("/repo/d"), to: path("/repo/e") },
+            ]
+        );
+    }
+
+    #[test]
+    fn bounds_a_flood_of_events() {
+        let (sender, events) = mpsc::channel();
+        for i in 0..10_000 {
+            let kind = if i % 2 == 0 { EventKind::Create(CreateKind::File) } else { EventKind::Modify(ModifyKind::Any) };
+            sender.send(Ok(event(kind, &[&format!("/repo/src/file{}.rs", i % 5_000)]))).unwrap();
+        }
+        sender.send(Ok(event(EventKind::Modify(ModifyKind::Any), &["/elsewhere/notes.md"]))).unwrap();
+        drop(sender);
+        let mut emitted = Vec::new();
+        run(events, || vec![PathBuf::from("/repo")], |changes| emitted.extend(changes));
+        assert!(emitted.len() <= 2, "{} changes emitted", emitted.len());
+        assert!(emitted.contains(&FileChange::Bulk { root: PathBuf::from("/repo") }));
+        assert!(emitted.contains(&FileChange::Modified { path: PathBuf::from("/elsewhere/notes.md") }));
+    }
+}
@@ -0,0 +1,279 @@
+//! Advisory locks on the files of open documents, so two skriv instances,
+//! like an installed and a portable one, don't edit a file unawares. The
+//! lock is the OS's: flock on Unix, and on Windows a byte range far past
+//! the end, which other programs reading or writing the file never touch.
+//! The OS doesn't say who holds a lock, so the holder also leaves a record
+//! in the temp folder. On filesystems without locks, a `.skriv.lock`
+//! sidecar beside the file is the lock and the record both, and is broken
+//! once the process that made it is gone.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::paths;
+
+const SIDECAR_SUFFIX: &str = ".skriv.lock";
+/// Where the Windows lock sits, past the end of any real file.
+#[cfg(windows)]
+const LOCK_OFFSET: u64 = 1 << 62;
+
+/// Who has a file open for editing.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockedBy {
+    /// `None` when a program other than skriv holds the lock
+    pub pid: Option<u32>,
+    pub app: Option<String>,
+    /// Another window of this skriv has it
+    #[serde(default)]
+    pub other_window: bool,
+}
+
+enum Lock {
+    Os { _file: File, record: PathBuf },
+    Sidecar(PathBuf),
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let (Lock::Os { record: path, .. } | Lock::Sidecar(path)) = self;
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+struct Held {
+    _lock: Lock,
+    /// The windows with the file open
+    windows: HashSet<String>,
+}
+
+#[derive(Default)]
+pub struct FileLocks(Mutex<HashMap<PathBuf, Held>>);
+
+#[cfg(unix)]
+fn os_lock(file: &File) -> std::io::Result<bool> {
+    use std::os::fd::AsRawFd;
+    // SAFETY: the descriptor is `file`'s, open for the whole call
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let e = std::io::Error::last_os_error();
+    if e.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        Ok(false)
+    } else {
+        Err(e)
+    }
+}
+
+#[cfg(windows)]
+fn os_lock(file: &File) -> std::io::Result<bool> {
+    use std::os::windows::io::AsRawHandle;
+    use windows::Win32::Foundation::{ERROR_LOCK_VIOLATION, HANDLE};
+    use windows::Win32::Storage::FileSystem::{LockFileEx, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY};
+    use windows::Win32::System::IO::OVERLAPPED;
+    let mut overlapped = OVERLAPPED::default();
+    overlapped.Anonymous.Anonymous.Offset = LOCK_OFFSET as u32;
+    overlapped.Anonymous.Anonymous.OffsetHigh = (LOCK_OFFSET >> 32) as u32;
+    let flags = LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY;
+    // SAFETY: the handle is `file`'s, open for the whole call, and with
+    // LOCKFILE_FAIL_IMMEDIATELY the call returns before `overlapped` goes
+    match unsafe { LockFileEx(HANDLE(file.as_raw_handle()), flags, None, 1, 0, &mut overlapped) } {
+        Ok(()) => Ok(true),
+        Err(e) if e.code() == ERROR_LOCK_VIOLATION.to_hresult() => Ok(false),
+        Err(e) => Err(std::io::Error::other(e)),
+    }
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    // Someone else's process can't be signalled, but it's there
+    // SAFETY: signal 0 only checks the pid; nothing is sent
+    let signalled = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+    signalled || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(windows)]
+fn is_running(pid: u32) -> bool {
+    use windows::Win32::Foundation::{CloseHandle, STILL_ACTIVE};
+    use windows::Win32::System::Threading::{GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION};
+    // SAFETY: a handle only to query, closed below; `code` outlives the call
+    let Ok(process) = (unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }) else { return false };
+    let mut code = 0;
+    let running = unsafe { GetExitCodeProcess(process, &mut code) }.is_ok() && code == STILL_ACTIVE.0 as u32;
+    // SAFETY: `process` was opened above and isn't used after this
+    let _ = unsafe { CloseHandle(process) };
+    running
+}
+
+/// This skriv, as a record names it.
+fn owner(app: &AppHandle) -> LockedBy {
+    let name = app.config().product_name.clone().unwrap_or_else(|| "skriv".into());
+    let app = if paths::portable_root().is_some() { format!("{} (portable)", name) } else { name };
+    LockedBy { pid: Some(std::process::id()), app: Some(app), other_window: false }
+}
+
+fn record_path(path: &Path) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()).hash(&mut hasher);
+    paths::temp_dir().join("skriv-locks").join(format!("{:016x}.json", hasher.finish()))
+}
+
+fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(SIDECAR_SUFFIX);
+    path.with_file_name(name)
+}
+
+fn read_record(path: &Path) -> Option<LockedBy> {
+    serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+}
+
+/// Written whole, so `is_held` in another process never reads half a record.
+fn write_record(path: &Path, owner: &LockedBy) {
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    let _ = paths::write_atomic(path, serde_json::to_vec(owner).unwrap_or_default());
+}
+
+/// The sidecar lock, breaking one whose process is gone. `Err(None)` when
+/// the folder can't take one either.
+fn lock_sidecar(path: &Path, owner: &LockedBy) -> Result<Lock, Option<LockedBy>> {
+    let sidecar = sidecar_path(path);
+    for _ in 0..2 {
+        match OpenOptions::new().write(true).create_new(true).open(&sidecar) {
+            Ok(mut file) => {
+                let _ = file.write_all(&serde_json::to_vec(owner).unwrap_or_default());
+                return Ok(Lock::Sidecar(sidecar));
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => match read_record(&sidecar) {
+                Some(by) if by.pid.is_some_and(is_running) => return Err(Some(by)),
+                _ => {
+                    log::info!("Broke the stale lock {}", sidecar.display());
+                    let _ = std::fs::remove_file(&sidecar);
+                }
+            },
+            Err(_) => break,
+        }
+    }
+    Err(None)
+}
+
+/// Locks `path`, or says who has. `Err(None)` when it can't be locked at
+/// all, as when it doesn't exist.
+fn lock(path: &Path, owner: &LockedBy) -> Result<Lock, Option<LockedBy>> {
+    let file = File::open(path).map_err(|_| None)?;
+    let record = record_path(path);
+    match os_lock(&file) {
+        Ok(true) => {
+            write_record(&record, owner);
+            Ok(Lock::Os { _file: file, record })
+        }
+        Ok(false) => Err(Some(read_record(&record).unwrap_or(LockedBy { pid: None, app: None, other_window: false }))),
+        Err(e) => {
+            log::debug!("No lock for {}, so a sidecar: {}", path.display(), e);
+            lock_sidecar(path, owner)
+        }
+    }
+}
+
+/// Whether a skriv has `path` open, going by the record its lock leaves.
+pub fn is_held(path: &Path) -> bool {
+    [record_path(path), sidecar_path(path)].iter().filter_map(|record| read_record(record)).any(|by| by.pid.is_some_and(is_running))
+}
+
+/// Takes the lock for a document opened in the window, or says who has the
+/// file open already. Reading it again in the same window is fine.
+pub fn acquire(app: &AppHandle, label: &str, path: &Path) -> Option<LockedBy> {
+    let locks = app.state::<FileLocks>();
+    let mut locks = locks.0.lock().unwrap();
+    if let Some(held) = locks.get_mut(path) {
+        let elsewhere = held.windows.iter().any(|window| window != label);
+        held.windows.insert(label.to_string());
+        return elsewhere.then(|| LockedBy { other_window: true, ..owner(app) });
+    }
+    match lock(path, &owner(app)) {
+        Ok(lock) => {
+            locks.insert(path.to_path_buf(), Held { _lock: lock, windows: HashSet::from([label.to_string()]) });
+            None
+        }
+        Err(by) => by,
+    }
+}
+
+fn release(locks: &mut HashMap<PathBuf, Held>, label: &str, path: &Path) {
+    let Some(held) = locks.get_mut(path) else { return };
+    held.windows.remove(label);
+    if held.windows.is_empty() {
+        locks.remove(path);
+    }
+}
+
+pub fn window_closed(app: &AppHandle, label: &str) {
+    let locks = app.state::<FileLocks>();
+    let mut locks = locks.0.lock().unwrap();
+    let paths: Vec<PathBuf> = locks.keys().cloned().collect();
+    for path in paths {
+        release(&mut locks, label, &path);
+    }
+}
+
+/// Lets go of the file of a tab that closed.
+#[tauri::command]
+pub fn unlock_document(locks: tauri::State<'_, FileLocks>, window: tauri::Window, path: String) {
+    release(&mut locks.0.lock().unwrap(), window.label(), &paths::decode(&path));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pid(by: Result<Lock, Option<LockedBy>>) -> Option<u32> {
+        by.err().flatten().and_then(|by| by.pid)
+    }
+
+    #[test]
+    fn second_lock_names_the_first() {
+        let path = std::env::temp_dir().join(format!("skriv-lock-{}.md", std::process::id()));
+        std::fs::write(&path, "x").unwrap();
+        let me = LockedBy { pid: Some(std::process::id()), app: Some("skriv".into()), other_window: false };
+        let first = lock(&path, &me).ok().unwrap();
+        assert_eq!(pid(lock(&path, &me)), Some(std::process::id()));
+        // Other programs still read it
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "x");
+        drop(first);
+        assert!(lock(&path, &me).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn breaks_stale_sidecars() {
+        let path = std::env::temp_dir().join(format!("skriv-sidecar-{}.md", std::process::id()));
+        let gone = LockedBy { pid: Some(u32::MAX / 2), app: Some("skriv".into()), other_window: false };
+        std::fs::write(sidecar_path(&path), serde_json::to_vec(&gone).unwrap()).unwrap();
+        let me = LockedBy { pid: Some(std::process::id()), ..gone };
+        let held = lock_sidecar(&path, &me).ok().unwrap();
+        assert_eq!(pid(lock_sidecar(&path, &me)), Some(std::process::id()));
+        drop(held);
+        assert!(!sidecar_path(&path).exists());
+    }
+
+    #[test]
+    fn rewrites_records_whole() {
+        let record = std::env::temp_dir().join(format!("skriv-record-{}", std::process::id())).join("a.json");
+        let me = LockedBy { pid: Some(std::process::id()), app: Some("skriv".into()), other_window: false };
+        write_record(&record, &LockedBy { pid: Some(1), ..me.clone() });
+        write_record(&record, &me);
+        assert_eq!(read_record(&record), Some(me));
+        assert!(!paths::temp_beside(&record).exists());
+        std::fs::remove_dir_all(record.parent().unwrap()).unwrap();
+    }
+}
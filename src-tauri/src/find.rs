@@ -0,0 +1,17 @@
+/// Shares the search string with other apps through the macOS find pasteboard.
+#[tauri::command]
+pub fn set_find_pasteboard(text: String) {
+    #[cfg(target_os = "macos")]
+    {
+        use objc2_app_kit::{NSPasteboard, NSPasteboardNameFind, NSPasteboardTypeString};
+        use objc2_foundation::NSString;
+
+        // SAFETY: both are immutable AppKit string constants
+        let (name, kind) = unsafe { (NSPasteboardNameFind, NSPasteboardTypeString) };
+        let pasteboard = NSPasteboard::pasteboardWithName(name);
+        pasteboard.clearContents();
+        pasteboard.setString_forType(&NSString::from_str(&text), kind);
+    }
+    #[cfg(not(target_os = "macos"))]
+    let _ = text;
+}
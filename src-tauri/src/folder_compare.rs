@@ -0,0 +1,335 @@
+//! Compares two folders entry by entry. Results reach the window in batches
+//! as `folder-compare-progress` while the trees are walked, the first as
+//! soon as the operation exists so the window has its id for
+//! `cancel_operation`; the command returns the totals. A pair that differs
+//! can be looked at with `diff_files`.
+
+use std::collections::{BTreeSet, HashSet};
+use std::fs::{File, Metadata};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use git2::Repository;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Emitter};
+
+use crate::editorconfig;
+use crate::operations::{self, Operation};
+use crate::paths;
+
+/// Entries per progress event.
+const BATCH: usize = 500;
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CompareOptions {
+    /// Skips what each tree's git repository ignores, and `.git` itself
+    pub respect_gitignore: bool,
+    /// Globs of `/`-separated relative paths to skip, like `*.bak` or
+    /// `/build`; a glob without a slash matches at any depth
+    pub ignore: Vec<String>,
+    /// Files of the same size that were modified at different times count
+    /// as identical, as after a copy that didn't keep the times
+    pub ignore_modified: bool,
+    /// Reads files of the same size that were modified at different times
+    /// and compares their hashes
+    pub compare_contents: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Status {
+    OnlyInA,
+    OnlyInB,
+    Identical,
+    Different,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Reason {
+    /// A file on one side and a folder on the other
+    Kind,
+    Size,
+    Modified,
+    Contents,
+    /// A link leads back into a folder already compared on one side
+    SymlinkLoop,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    /// `/`-separated, relative to both folders
+    pub path: String,
+    pub status: Status,
+    /// A folder only on one side stands for everything in it
+    pub is_dir: bool,
+    pub reason: Option<Reason>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Progress {
+    id: u64,
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompareSummary {
+    pub only_in_a: usize,
+    pub only_in_b: usize,
+    pub identical: usize,
+    pub different: usize,
+    /// Entries that couldn't be read, with why
+    pub errors: Vec<String>,
+    pub cancelled: bool,
+}
+
+/// One side of the comparison.
+struct Tree {
+    root: PathBuf,
+    repo: Option<Repository>,
+    /// Canonical folders walked into, so a link back up isn't walked again
+    visited: HashSet<PathBuf>,
+}
+
+impl Tree {
+    fn new(root: &Path, opts: &CompareOptions) -> Self {
+        let repo = if opts.respect_gitignore { Repository::discover(root).ok() } else { None };
+        Tree { root: root.to_path_buf(), repo, visited: HashSet::new() }
+    }
+
+    fn is_ignored(&self, path: &Path) -> bool {
+        let Some(repo) = &self.repo else { return false };
+        if path.file_name().is_some_and(|name| name == ".git") {
+            return true;
+        }
+        let Some(relative) = repo.workdir().and_then(|workdir| path.strip_prefix(workdir).ok()) else { return false };
+        repo.is_path_ignored(relative).unwrap_or(false)
+    }
+
+    /// The names in `relative` that aren't ignored.
+    fn names(&self, relative: &str, skip: &[Regex]) -> Result<BTreeSet<String>, String> {
+        let dir = self.root.join(paths::decode(relative));
+        let entries = std::fs::read_dir(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        Ok(entries
+            .filter_map(Result::ok)
+            .filter(|entry| !self.is_ignored(&entry.path()))
+            .map(|entry| paths::encode(Path::new(&entry.file_name())))
+            .filter(|name| !skip.iter().any(|glob| glob.is_match(&join(relative, name))))
+            .collect())
+    }
+
+    /// False when the folder was walked already, through a link.
+    fn enter(&mut self, relative: &str) -> bool {
+        let path = self.root.join(paths::decode(relative));
+        let canonical = std::fs::canonicalize(&path).unwrap_or(path);
+        self.visited.insert(canonical)
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() { name.to_string() } else { format!("{}/{}", dir, name) }
+}
+
+fn hash(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hasher.finalize().to_vec());
+        }
+        hasher.update(&buf[..n]);
+    }
+}
+
+fn modified(metadata: &Metadata) -> Option<SystemTime> {
+    metadata.modified().ok()
+}
+
+/// Same size and time is taken for identical without reading either file.
+fn compare_files(a: &Path, b: &Path, (meta_a, meta_b): (&Metadata, &Metadata), opts: &CompareOptions) -> Result<Option<Reason>, String> {
+    if meta_a.len() != meta_b.len() {
+        return Ok(Some(Reason::Size));
+    }
+    if modified(meta_a) == modified(meta_b) || opts.ignore_modified {
+        return Ok(None);
+    }
+    if !opts.compare_contents {
+        return Ok(Some(Reason::Modified));
+    }
+    let read = |path: &Path| hash(path).map_err(|e| format!("{}: {}", path.display(), e));
+    Ok((read(a)? != read(b)?).then_some(Reason::Contents))
+}
+
+struct Walk<'a> {
+    a: Tree,
+    b: Tree,
+    skip: Vec<Regex>,
+    opts: &'a CompareOptions,
+    operation: Option<&'a Operation>,
+    summary: CompareSummary,
+    pending: Vec<Entry>,
+    emit: &'a dyn Fn(Vec<Entry>),
+}
+
+impl Walk<'_> {
+    fn push(&mut self, path: String, status: Status, is_dir: bool, reason: Option<Reason>) {
+        match status {
+            Status::OnlyInA => self.summary.only_in_a += 1,
+            Status::OnlyInB => self.summary.only_in_b += 1,
+            Status::Identical => self.summary.identical += 1,
+            Status::Different => self.summary.different += 1,
+        }
+        self.pending.push(Entry { path, status, is_dir, reason });
+        if self.pending.len() >= BATCH {
+            (self.emit)(std::mem::take(&mut self.pending));
+        }
+    }
+
+    fn cancelled(&mut self) -> bool {
+        self.summary.cancelled = self.summary.cancelled || self.operation.is_some_and(Operation::is_cancelled);
+        self.summary.cancelled
+    }
+
+    /// Compares the folder `relative` of both trees, depth first.
+    fn folder(&mut self, relative: &str) {
+        let names = match (self.a.names(relative, &self.skip), self.b.names(relative, &self.skip)) {
+            (Ok(a), Ok(b)) => a.union(&b).cloned().collect::<BTreeSet<_>>().into_iter().map(|name| (a.contains(&name), b.contains(&name), name)).collect::<Vec<_>>(),
+            (Err(e), _) | (_, Err(e)) => return self.summary.errors.push(e),
+        };
+        for (in_a, in_b, name) in names {
+            if self.cancelled() {
+                return;
+            }
+            let path = join(relative, &name);
+            // Links are followed, to what they point at
+            let meta_a = in_a.then(|| std::fs::metadata(self.a.root.join(paths::decode(&path))));
+            let meta_b = in_b.then(|| std::fs::metadata(self.b.root.join(paths::decode(&path))));
+            match (meta_a, meta_b) {
+                (Some(Err(e)), _) | (_, Some(Err(e))) => self.summary.errors.push(format!("{}: {}", path, e)),
+                (Some(Ok(a)), None) => self.push(path, Status::OnlyInA, a.is_dir(), None),
+                (None, Some(Ok(b))) => self.push(path, Status::OnlyInB, b.is_dir(), None),
+                (Some(Ok(a)), Some(Ok(b))) if a.is_dir() && b.is_dir() => {
+                    // Both are entered, so each side's record of where it's been stays whole
+                    let (fresh_a, fresh_b) = (self.a.enter(&path), self.b.enter(&path));
+                    match (fresh_a, fresh_b) {
+                        (true, true) => self.folder(&path),
+                        (false, false) => {}
+                        _ => self.push(path, Status::Different, true, Some(Reason::SymlinkLoop)),
+                    }
+                }
+                (Some(Ok(a)), Some(Ok(b))) if a.is_dir() != b.is_dir() => self.push(path, Status::Different, false, Some(Reason::Kind)),
+                (Some(Ok(a)), Some(Ok(b))) => match compare_files(&self.a.root.join(paths::decode(&path)), &self.b.root.join(paths::decode(&path)), (&a, &b), self.opts) {
+                    Ok(None) => self.push(path, Status::Identical, false, None),
+                    Ok(reason) => self.push(path, Status::Different, false, reason),
+                    Err(e) => self.summary.errors.push(e),
+                },
+                (None, None) => {}
+            }
+        }
+    }
+}
+
+fn compare(a: &Path, b: &Path, opts: &CompareOptions, operation: Option<&Operation>, emit: &dyn Fn(Vec<Entry>)) -> Result<CompareSummary, String> {
+    for root in [a, b] {
+        if !root.is_dir() {
+            return Err(format!("{} isn't a folder", root.display()));
+        }
+    }
+    let skip = opts.ignore.iter().map(|g| editorconfig::glob(g, cfg!(any(windows, target_os = "macos"))).ok_or_else(|| format!("Invalid glob \"{}\"", g))).collect::<Result<Vec<_>, _>>()?;
+    let mut walk = Walk { a: Tree::new(a, opts), b: Tree::new(b, opts), skip, opts, operation, summary: CompareSummary::default(), pending: Vec::new(), emit };
+    walk.a.enter("");
+    walk.b.enter("");
+    walk.folder("");
+    if !walk.pending.is_empty() {
+        emit(std::mem::take(&mut walk.pending));
+    }
+    Ok(walk.summary)
+}
+
+/// Compares the folders `a` and `b` and everything in them.
+#[tauri::command]
+pub async fn compare_directories(app: AppHandle, window: tauri::Window, a: String, b: String, opts: Option<CompareOptions>) -> Result<CompareSummary, String> {
+    let label = window.label().to_string();
+    tauri::async_runtime::spawn_blocking(move || {
+        let operation = operations::begin(&app);
+        let emit = |entries: Vec<Entry>| {
+            let _ = app.emit_to(&label, "folder-compare-progress", Progress { id: operation.id, entries });
+        };
+        emit(Vec::new());
+        compare(&paths::decode(&a), &paths::decode(&b), &opts.unwrap_or_default(), Some(&operation), &emit)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn run(a: &Path, b: &Path, opts: &CompareOptions) -> (Vec<(String, Status, Option<Reason>)>, CompareSummary) {
+        let found = RefCell::new(Vec::new());
+        let summary = compare(a, b, opts, None, &|entries| found.borrow_mut().extend(entries.into_iter().map(|e| (e.path, e.status, e.reason)))).unwrap();
+        (found.into_inner(), summary)
+    }
+
+    fn tree(root: &Path, files: &[(&str, &str)]) {
+        for (path, content) in files {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+    }
+
+    #[test]
+    fn classifies_both_trees() {
+        let root = std::env::temp_dir().join(format!("skriv-folder-compare-{}", std::process::id()));
+        let (a, b) = (root.join("a"), root.join("b"));
+        tree(&a, &[("same.md", "x"), ("notes/changed.md", "one"), ("notes/longer.md", "a"), ("gone.md", ""), ("old/x.md", ""), ("skip.bak", "")]);
+        tree(&b, &[("same.md", "x"), ("notes/changed.md", "two"), ("notes/longer.md", "ab"), ("new.md", "")]);
+        let stamp = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        for side in [&a, &b] {
+            File::options().write(true).open(side.join("same.md")).unwrap().set_modified(stamp).unwrap();
+        }
+        File::options().write(true).open(a.join("notes/changed.md")).unwrap().set_modified(stamp).unwrap();
+        let opts = CompareOptions { ignore: vec!["*.bak".into()], ..Default::default() };
+        let (found, summary) = run(&a, &b, &opts);
+        assert_eq!(found, [
+            ("gone.md".into(), Status::OnlyInA, None),
+            ("new.md".into(), Status::OnlyInB, None),
+            ("notes/changed.md".into(), Status::Different, Some(Reason::Modified)),
+            ("notes/longer.md".into(), Status::Different, Some(Reason::Size)),
+            ("old".into(), Status::OnlyInA, None),
+            ("same.md".into(), Status::Identical, None),
+        ]);
+        assert_eq!((summary.only_in_a, summary.only_in_b, summary.identical, summary.different), (2, 1, 1, 2));
+        let (found, _) = run(&a, &b, &CompareOptions { compare_contents: true, ..Default::default() });
+        assert!(found.contains(&("notes/changed.md".into(), Status::Different, Some(Reason::Contents))));
+        let (found, _) = run(&a, &b, &CompareOptions { ignore_modified: true, ..Default::default() });
+        assert!(found.contains(&("notes/changed.md".into(), Status::Identical, None)));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stops_at_symlink_loops() {
+        let root = std::env::temp_dir().join(format!("skriv-folder-loop-{}", std::process::id()));
+        let (a, b) = (root.join("a"), root.join("b"));
+        tree(&a, &[("sub/f.md", "x")]);
+        tree(&b, &[("sub/f.md", "x"), ("sub/up/f.md", "x")]);
+        std::os::unix::fs::symlink(&a, a.join("sub/up")).unwrap();
+        let (found, summary) = run(&a, &b, &CompareOptions { ignore_modified: true, ..Default::default() });
+        assert_eq!(found, [("sub/f.md".into(), Status::Identical, None), ("sub/up".into(), Status::Different, Some(Reason::SymlinkLoop))]);
+        assert!(summary.errors.is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
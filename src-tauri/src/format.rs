@@ -0,0 +1,186 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+
+use crate::{paths, workspace};
+use crate::workspace_trust::{self, UNTRUSTED};
+#[cfg(target_os = "macos")]
+use crate::tasks;
+
+const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+
+/// An external formatter for one language, from `formatters` in settings.json.
+/// `${file}` in the arguments is replaced with the document's path.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Formatter {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum FormatOutcome {
+    Formatted { content: String },
+    Failed { stderr: String, code: Option<i32> },
+    TimedOut,
+}
+
+#[cfg(windows)]
+const EXTENSIONS: &[&str] = &[".exe", ".cmd", ".bat", ""];
+#[cfg(not(windows))]
+const EXTENSIONS: &[&str] = &[""];
+
+fn find_in(dir: &Path, tool: &str) -> Option<PathBuf> {
+    EXTENSIONS.iter().map(|ext| dir.join(format!("{}{}", tool, ext))).find(|path| path.is_file())
+}
+
+/// The project's own copy in a node_modules/.bin above `cwd` wins over PATH,
+/// so the version the project pins is the one that runs. That's only looked
+/// for once the folder is trusted, as it's the folder's code.
+pub(crate) fn resolve(app: &AppHandle, tool: &str, cwd: Option<&Path>) -> PathBuf {
+    lookup(tool, cwd.filter(|cwd| workspace_trust::is_trusted(app, cwd)))
+}
+
+/// `tool` in a node_modules/.bin above `project`, if given, or else on PATH.
+fn lookup(tool: &str, project: Option<&Path>) -> PathBuf {
+    if tool.contains(['/', '\\']) {
+        return paths::decode(tool);
+    }
+    let local = project
+        .into_iter()
+        .flat_map(Path::ancestors)
+        .find_map(|dir| find_in(&dir.join("node_modules").join(".bin"), tool));
+    #[cfg(target_os = "macos")]
+    let path = tasks::login_path().map(Into::into).or_else(|| std::env::var_os("PATH"));
+    #[cfg(not(target_os = "macos"))]
+    let path = std::env::var_os("PATH");
+    local
+        .or_else(|| path.and_then(|path| std::env::split_paths(&path).find_map(|dir| find_in(&dir, tool))))
+        .unwrap_or_else(|| PathBuf::from(tool))
+}
+
+fn run_formatter(app: &AppHandle, tool: &str, args: &[String], content: String, cwd: Option<&Path>, timeout: Duration) -> Result<FormatOutcome, String> {
+    let mut command = Command::new(resolve(app, tool, cwd));
+    command.args(args).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    if let Some(cwd) = cwd {
+        command.current_dir(cwd);
+    }
+    #[cfg(target_os = "macos")]
+    if let Some(path) = tasks::login_path() {
+        command.env("PATH", path);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // CREATE_NO_WINDOW
+        command.creation_flags(0x0800_0000);
+    }
+    let mut child = command.spawn().map_err(|e| format!("Failed to run {}: {}", tool, e))?;
+
+    // Written and read on their own threads, so a big buffer can't deadlock
+    // against a formatter that streams its output
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let writer = std::thread::spawn(move || {
+        let _ = stdin.write_all(content.as_bytes());
+    });
+    let read = |mut source: Box<dyn Read + Send>| {
+        std::thread::spawn(move || {
+            let mut bytes = Vec::new();
+            let _ = source.read_to_end(&mut bytes);
+            bytes
+        })
+    };
+    let stdout = read(Box::new(child.stdout.take().expect("stdout is piped")));
+    let stderr = read(Box::new(child.stderr.take().expect("stderr is piped")));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break status;
+        }
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(FormatOutcome::TimedOut);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    let _ = writer.join();
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = String::from_utf8_lossy(&stderr.join().unwrap_or_default()).into_owned();
+    if !status.success() {
+        return Ok(FormatOutcome::Failed { stderr, code: status.code() });
+    }
+    match String::from_utf8(stdout) {
+        Ok(content) => Ok(FormatOutcome::Formatted { content }),
+        Err(_) => Ok(FormatOutcome::Failed { stderr: format!("{} printed invalid UTF-8", tool), code: status.code() }),
+    }
+}
+
+/// Runs `tool` with the buffer on stdin and takes stdout as the formatted
+/// text. A tool still running after `timeout_ms` is killed.
+#[tauri::command]
+pub async fn format_via_external(
+    app: AppHandle,
+    tool: String,
+    args: Vec<String>,
+    content: String,
+    cwd: Option<String>,
+    timeout_ms: Option<u64>,
+) -> Result<FormatOutcome, String> {
+    let timeout = Duration::from_millis(timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS));
+    tauri::async_runtime::spawn_blocking(move || run_formatter(&app, &tool, &args, content, cwd.as_deref().map(Path::new), timeout))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// The formatter configured for `language`, with `${file}` filled in and
+/// the command resolved as `format_via_external` will, so a project's own
+/// copy only counts once its folder is trusted. One only an untrusted
+/// workspace configures, or at a path relative to an untrusted folder, is
+/// UNTRUSTED.
+#[tauri::command]
+pub fn formatter_for(app: AppHandle, window: tauri::Window, language: String, path: Option<String>) -> Result<Option<Formatter>, String> {
+    let settings = workspace::effective(&app, window.label());
+    let Some(formatter) = settings.formatters.get(&language) else {
+        if workspace::withheld(&app, window.label(), "formatters").is_some_and(|formatters| formatters.get(&language).is_some()) {
+            return Err(UNTRUSTED.into());
+        }
+        return Ok(None);
+    };
+    let file = path.unwrap_or_default();
+    let document = paths::decode(&file);
+    let dir = document.parent().filter(|dir| !dir.as_os_str().is_empty());
+    // `./fmt` runs from the document's folder, so it's that folder's code
+    let relative = formatter.command.contains(['/', '\\']) && Path::new(&formatter.command).is_relative();
+    if relative && dir.is_some_and(|dir| !workspace_trust::is_trusted(&app, dir)) {
+        return Err(UNTRUSTED.into());
+    }
+    let command = paths::encode(&resolve(&app, &formatter.command, dir));
+    let args = formatter.args.iter().map(|arg| arg.replace("${file}", &file)).collect();
+    Ok(Some(Formatter { command, args }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn project_tools_are_only_found_when_asked_for() {
+        let root = std::env::temp_dir().join(format!("skriv-format-{}", std::process::id()));
+        let bin = root.join("node_modules").join(".bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        let tool = format!("skriv-formatter{}", EXTENSIONS[0]);
+        std::fs::write(bin.join(&tool), "").unwrap();
+        let sub = root.join("src");
+        assert_eq!(lookup("skriv-formatter", Some(&sub)), bin.join(&tool));
+        assert_eq!(lookup("skriv-formatter", None), PathBuf::from("skriv-formatter"));
+        assert_eq!(lookup("./fmt", Some(&sub)), PathBuf::from("./fmt"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,1357 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use encoding_rs::Encoding;
+use git2::build::CheckoutBuilder;
+use git2::{
+    ApplyLocation, BlameOptions, BranchType, Diff, DiffOptions, ErrorCode, Oid, Patch, Repository, Status, StatusOptions,
+};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::document::{self, DocumentRegistry};
+use crate::file_events::{self, FileChange};
+use crate::paths;
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    NotInRepo,
+    Untracked,
+    Tracked,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HunkKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+/// A changed range of the buffer, in 1-based inclusive lines. Deleted hunks
+/// cover no lines: `start` is the line the removed ones followed (0 for the
+/// top of the file) and `end` equals it. `old_start` and `old_lines` give the
+/// range in HEAD the same way, so the hunk can be turned back into a patch.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Hunk {
+    pub kind: HunkKind,
+    pub start: u32,
+    pub end: u32,
+    pub old_start: u32,
+    pub old_lines: u32,
+    /// The HEAD text the hunk replaced, for modified and deleted hunks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct FileDiff {
+    pub status: FileStatus,
+    pub hunks: Vec<Hunk>,
+}
+
+impl FileDiff {
+    fn not_in_repo() -> Self {
+        FileDiff { status: FileStatus::NotInRepo, hunks: Vec::new() }
+    }
+}
+
+fn line_count(text: &[u8]) -> u32 {
+    let newlines = text.iter().filter(|b| **b == b'\n').count();
+    let trailing = !text.is_empty() && !text.ends_with(b"\n");
+    (newlines + trailing as usize) as u32
+}
+
+/// Lines `start..start + count` (1-based) of `text`.
+fn lines(text: &str, start: u32, count: u32) -> String {
+    text.split_inclusive('\n').skip(start.saturating_sub(1) as usize).take(count as usize).collect()
+}
+
+/// Diffs `buffer` against `head`, the path's content at HEAD.
+pub fn diff(head: &[u8], buffer: &[u8]) -> Result<Vec<Hunk>, git2::Error> {
+    // HEAD holds LF when autocrlf put CRLF in the working tree; that alone
+    // shouldn't mark every line
+    let normalized;
+    let buffer = if !head.windows(2).any(|w| w == b"\r\n") && buffer.windows(2).any(|w| w == b"\r\n") {
+        normalized = String::from_utf8_lossy(buffer).replace("\r\n", "\n").into_bytes();
+        &normalized[..]
+    } else {
+        buffer
+    };
+    let mut opts = DiffOptions::new();
+    opts.context_lines(0);
+    let patch = Patch::from_buffers(head, None, buffer, None, Some(&mut opts))?;
+    let original = String::from_utf8_lossy(head);
+    let mut hunks = Vec::with_capacity(patch.num_hunks());
+    for i in 0..patch.num_hunks() {
+        let (hunk, _) = patch.hunk(i)?;
+        let (old_lines, new_start, new_lines) = (hunk.old_lines(), hunk.new_start(), hunk.new_lines());
+        let (kind, end) = match (old_lines, new_lines) {
+            (0, _) => (HunkKind::Added, new_start + new_lines - 1),
+            (_, 0) => (HunkKind::Deleted, new_start),
+            _ => (HunkKind::Modified, new_start + new_lines - 1),
+        };
+        hunks.push(Hunk {
+            kind,
+            start: new_start,
+            end,
+            old_start: hunk.old_start(),
+            old_lines,
+            original: (kind != HunkKind::Added).then(|| lines(&original, hunk.old_start(), old_lines)),
+        });
+    }
+    Ok(hunks)
+}
+
+/// A file inside a repository's working tree.
+struct Located {
+    repo: Repository,
+    root: PathBuf,
+    path: PathBuf,
+    relative: PathBuf,
+}
+
+fn locate(cache: &GitCache, path: &Path) -> Option<Located> {
+    let RepoPaths { root, .. } = cache.discover(path.parent().unwrap_or(path))?;
+    let repo = Repository::open(&root).ok()?;
+    // Both sides canonical, so symlinked checkouts (/tmp on macOS) still match
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let relative = path.strip_prefix(&root).ok()?.to_path_buf();
+    Some(Located { repo, root, path, relative })
+}
+
+/// Where a directory's repository lives, all canonical.
+#[derive(Clone, PartialEq)]
+struct RepoPaths {
+    root: PathBuf,
+    git_dir: PathBuf,
+    /// Differs from `git_dir` in a linked worktree
+    common_dir: PathBuf,
+}
+
+// Discovery resolves worktrees to their own HEAD and index, and a path in a
+// submodule to the submodule; bare repositories have no files to edit
+fn discover_dir(dir: &Path) -> Option<RepoPaths> {
+    let repo = Repository::discover(dir).ok()?;
+    let canonical = |p: &Path| p.canonicalize().ok();
+    Some(RepoPaths {
+        root: canonical(repo.workdir()?)?,
+        git_dir: canonical(repo.path())?,
+        common_dir: canonical(repo.commondir())?,
+    })
+}
+
+#[derive(Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscoveredRepo {
+    pub root: String,
+    pub git_dir: String,
+    pub common_dir: String,
+    pub ignored: bool,
+}
+
+fn discovered_repo(cache: &GitCache, path: &Path) -> Option<DiscoveredRepo> {
+    let found = cache.discover(path.parent().unwrap_or(path))?;
+    let ignored = locate(cache, path).and_then(|l| l.repo.is_path_ignored(&l.relative).ok());
+    Some(DiscoveredRepo {
+        root: paths::encode(&found.root),
+        git_dir: paths::encode(&found.git_dir),
+        common_dir: paths::encode(&found.common_dir),
+        ignored: ignored.unwrap_or(false),
+    })
+}
+
+fn discover_repository_for(app: &AppHandle, path: &Path) -> Option<DiscoveredRepo> {
+    let cache = app.state::<GitCache>();
+    let repo = discovered_repo(&cache, path);
+    let requested = paths::encode(path);
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    // `git init` (or deleting .git) in any directory up to the repo root moves
+    // the file to another repo. Home and above aren't watched, since on macOS
+    // that means every change on the disk
+    let home = app.path().home_dir().ok().and_then(|home| home.canonicalize().ok());
+    let stop = repo.as_ref().map(|repo| paths::decode(&repo.root));
+    let mut dirs = Vec::new();
+    for dir in path.ancestors().skip(1) {
+        if home.as_deref().is_some_and(|home| home.starts_with(dir)) {
+            break;
+        }
+        dirs.push((dir, RecursiveMode::NonRecursive));
+        if stop.as_deref() == Some(dir) {
+            break;
+        }
+    }
+    if let Err(e) = cache.watch(app, &dirs) {
+        log::warn!("Failed to watch {} for repository changes: {}", path.display(), e);
+    }
+    cache.state.lock().unwrap().documents.insert(path, (requested, repo.clone()));
+    repo
+}
+
+/// The repository containing `path`, or `None` outside one.
+/// `document-repo-changed` fires when that changes later.
+#[tauri::command]
+pub async fn discover_repository(app: AppHandle, path: String) -> Result<Option<DiscoveredRepo>, String> {
+    tauri::async_runtime::spawn_blocking(move || discover_repository_for(&app, &paths::decode(&path))).await.map_err(|e| e.to_string())
+}
+
+/// The HEAD commit, if it has the path. An unborn HEAD or a path missing from
+/// it means the file is new.
+fn head_with(repo: &Repository, relative: &Path) -> Option<(Oid, git2::TreeEntry<'static>)> {
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+    let entry = commit.tree().ok()?.get_path(relative).ok()?;
+    Some((commit.id(), entry))
+}
+
+fn file_diff(cache: &GitCache, path: &Path, buffer: Option<String>) -> Result<FileDiff, String> {
+    let Some(Located { repo, path, relative, .. }) = locate(cache, path) else {
+        return Ok(FileDiff::not_in_repo());
+    };
+    let buffer = match buffer {
+        Some(text) => text.into_bytes(),
+        None => std::fs::read(&path).map_err(|e| e.to_string())?,
+    };
+    let Some((_, entry)) = head_with(&repo, &relative) else {
+        let lines = line_count(&buffer);
+        let hunks = if lines == 0 {
+            Vec::new()
+        } else {
+            vec![Hunk { kind: HunkKind::Added, start: 1, end: lines, old_start: 0, old_lines: 0, original: None }]
+        };
+        return Ok(FileDiff { status: FileStatus::Untracked, hunks });
+    };
+    let blob = entry.to_object(&repo).and_then(|o| o.peel_to_blob()).map_err(|e| e.to_string())?;
+    let hunks = diff(blob.content(), &buffer).map_err(|e| e.to_string())?;
+    Ok(FileDiff { status: FileStatus::Tracked, hunks })
+}
+
+/// Changes in the file against HEAD, for the gutter. Diffs `buffer_content`
+/// when given so unsaved edits show, otherwise the file on disk.
+#[tauri::command]
+pub async fn git_file_diff(app: AppHandle, path: String, buffer_content: Option<String>) -> Result<FileDiff, String> {
+    tauri::async_runtime::spawn_blocking(move || file_diff(&app.state::<GitCache>(), &paths::decode(&path), buffer_content))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepoStatus {
+    pub root: String,
+    /// The branch name, or the short commit id when HEAD is detached
+    pub branch: String,
+    pub detached: bool,
+    pub upstream: Option<String>,
+    pub ahead: usize,
+    pub behind: usize,
+    pub staged: usize,
+    pub modified: usize,
+    pub untracked: usize,
+}
+
+fn compute_status(repo: &Repository, root: &Path) -> Result<RepoStatus, git2::Error> {
+    let detached = repo.head_detached().unwrap_or(false);
+    let head = repo.head().ok();
+    let branch = match &head {
+        Some(head) if detached => match head.target() {
+            Some(id) => repo.find_object(id, None)?.short_id()?.as_str().unwrap_or_default().to_string(),
+            None => String::new(),
+        },
+        Some(head) => head.shorthand().unwrap_or_default().to_string(),
+        // Unborn: HEAD already names the branch the first commit will start
+        None => repo
+            .find_reference("HEAD")?
+            .symbolic_target()
+            .and_then(|target| target.strip_prefix("refs/heads/"))
+            .unwrap_or_default()
+            .to_string(),
+    };
+
+    let (mut upstream, mut ahead, mut behind) = (None, 0, 0);
+    if let Some(head) = head.as_ref().filter(|_| !detached) {
+        let tracking = head.shorthand().and_then(|name| repo.find_branch(name, BranchType::Local).ok()).and_then(|b| b.upstream().ok());
+        if let Some(tracking) = tracking {
+            if let (Some(local), Some(remote)) = (head.target(), tracking.get().target()) {
+                (ahead, behind) = repo.graph_ahead_behind(local, remote)?;
+            }
+            upstream = tracking.name().ok().flatten().map(String::from);
+        }
+    }
+
+    let mut opts = StatusOptions::new();
+    opts.include_untracked(true).recurse_untracked_dirs(false);
+    let (mut staged, mut modified, mut untracked) = (0, 0, 0);
+    let index_changes = Status::INDEX_NEW | Status::INDEX_MODIFIED | Status::INDEX_DELETED | Status::INDEX_RENAMED | Status::INDEX_TYPECHANGE;
+    let worktree_changes = Status::WT_MODIFIED | Status::WT_DELETED | Status::WT_RENAMED | Status::WT_TYPECHANGE | Status::CONFLICTED;
+    for entry in repo.statuses(Some(&mut opts))?.iter() {
+        let status = entry.status();
+        staged += status.intersects(index_changes) as usize;
+        modified += status.intersects(worktree_changes) as usize;
+        untracked += status.contains(Status::WT_NEW) as usize;
+    }
+    Ok(RepoStatus {
+        root: paths::encode(root),
+        branch,
+        detached,
+        upstream,
+        ahead,
+        behind,
+        staged,
+        modified,
+        untracked,
+    })
+}
+
+fn repo_status(app: &AppHandle, path: &Path) -> Result<Option<RepoStatus>, String> {
+    let start = if path.is_dir() { path } else { path.parent().unwrap_or(path) };
+    let cache = app.state::<GitCache>();
+    let Some(RepoPaths { root, git_dir, common_dir }) = cache.discover(start) else { return Ok(None) };
+    if let Some(entry) = cache.state.lock().unwrap().statuses.iter().find(|e| e.git_dir == git_dir) {
+        return Ok(Some(entry.status.clone()));
+    }
+
+    let repo = Repository::open(&root).map_err(|e| e.to_string())?;
+    let status = compute_status(&repo, &root).map_err(|e| e.to_string())?;
+    let refs = common_dir.join("refs");
+    let dirs = [
+        (git_dir.as_path(), RecursiveMode::NonRecursive),
+        (common_dir.as_path(), RecursiveMode::NonRecursive),
+        (refs.as_path(), RecursiveMode::Recursive),
+    ];
+    if let Err(e) = cache.watch(app, &dirs) {
+        log::warn!("Failed to watch {} for status: {}", git_dir.display(), e);
+        return Ok(Some(status));
+    }
+    let entry = CachedStatus { root, git_dir, common_dir, status: status.clone() };
+    cache.state.lock().unwrap().statuses.push(entry);
+    Ok(Some(status))
+}
+
+/// Branch, upstream ahead/behind and change counts for the repository
+/// containing `root_or_path`, or `None` outside one. `repo-status-changed`
+/// fires when it goes stale.
+#[tauri::command]
+pub async fn git_repo_status(app: AppHandle, root_or_path: String) -> Result<Option<RepoStatus>, String> {
+    tauri::async_runtime::spawn_blocking(move || repo_status(&app, &paths::decode(&root_or_path)))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Returned in place of a git error when another process holds the index.
+pub const INDEX_LOCKED: &str = "index_locked";
+pub const NOT_IN_REPO: &str = "not_in_repo";
+
+fn git_error(e: git2::Error) -> String {
+    if e.code() == ErrorCode::Locked {
+        INDEX_LOCKED.to_string()
+    } else {
+        e.to_string()
+    }
+}
+
+/// Locates the file for a command that changes the index or working tree,
+/// refusing while a git process is running there.
+fn locate_for_write(cache: &GitCache, path: &Path) -> Result<Located, String> {
+    let located = locate(cache, path).ok_or(NOT_IN_REPO)?;
+    if located.repo.path().join("index.lock").exists() {
+        return Err(INDEX_LOCKED.into());
+    }
+    Ok(located)
+}
+
+fn stage(cache: &GitCache, path: &Path) -> Result<PathBuf, String> {
+    let Located { repo, root, path, relative } = locate_for_write(cache, path)?;
+    let mut index = repo.index().map_err(git_error)?;
+    let staged = if path.exists() { index.add_path(&relative) } else { index.remove_path(&relative) };
+    staged.and_then(|_| index.write()).map_err(git_error)?;
+    Ok(root)
+}
+
+fn unstage(cache: &GitCache, path: &Path) -> Result<PathBuf, String> {
+    let Located { repo, root, relative, .. } = locate_for_write(cache, path)?;
+    match repo.head().and_then(|head| head.peel_to_commit()) {
+        Ok(commit) => repo.reset_default(Some(commit.as_object()), [relative.as_path()]).map_err(git_error)?,
+        // Before the first commit there's nothing to reset to
+        Err(_) => {
+            let mut index = repo.index().map_err(git_error)?;
+            index.remove_path(&relative).and_then(|_| index.write()).map_err(git_error)?;
+        }
+    }
+    Ok(root)
+}
+
+#[derive(Serialize)]
+pub struct Discarded {
+    /// The file as it was, so it can be reopened in a new buffer
+    pub content: String,
+    pub encoding: String,
+}
+
+fn discard_file(registry: &DocumentRegistry, cache: &GitCache, path: &Path) -> Result<(PathBuf, Discarded), String> {
+    let Located { repo, root, path: canonical, relative } = locate_for_write(cache, path)?;
+    if head_with(&repo, &relative).is_none() {
+        return Err("The file isn't in HEAD, so there's nothing to go back to".into());
+    }
+    // Captured before anything is touched; a deleted file discards nothing
+    let bytes = match std::fs::read(&canonical) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.to_string()),
+    };
+    let (content, encoding, _) = document::decode_like(registry, path, &bytes)?;
+    let mut checkout = CheckoutBuilder::new();
+    checkout.force().disable_pathspec_match(true).path(&relative);
+    repo.checkout_head(Some(&mut checkout)).map_err(git_error)?;
+    Ok((root, Discarded { content, encoding }))
+}
+
+/// A zero-context patch for one hunk of `relative`.
+fn hunk_patch(relative: &Path, old: (u32, u32, &str), new: (u32, u32, &str)) -> String {
+    let name = relative.to_string_lossy().replace('\\', "/");
+    let mut patch = format!("diff --git a/{0} b/{0}\n--- a/{0}\n+++ b/{0}\n@@ -{1},{2} +{3},{4} @@\n", name, old.0, old.1, new.0, new.1);
+    for (prefix, text) in [('-', old.2), ('+', new.2)] {
+        for line in text.split_inclusive('\n') {
+            patch.push(prefix);
+            patch.push_str(line);
+            if !line.ends_with('\n') {
+                patch.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    patch
+}
+
+/// Applies a gutter hunk (HEAD to the file on disk). Forward to the index
+/// stages it; reversed it unstages from the index, or discards from the
+/// working tree. The index must match HEAD, or the file, around the hunk.
+fn apply_hunk(cache: &GitCache, path: &Path, hunk: &Hunk, reverse: bool, cached: bool) -> Result<PathBuf, String> {
+    let Located { repo, root, path, relative } = locate_for_write(cache, path)?;
+    let current = std::fs::read(&path).map_err(|e| e.to_string())?;
+    let current = String::from_utf8_lossy(&current);
+    let new_lines = if hunk.kind == HunkKind::Deleted { 0 } else { hunk.end + 1 - hunk.start };
+    let new_text = lines(&current, hunk.start, new_lines);
+    let old = (hunk.old_start, hunk.old_lines, hunk.original.as_deref().unwrap_or_default());
+    let new = (hunk.start, new_lines, new_text.as_str());
+    let patch = if reverse { hunk_patch(&relative, new, old) } else { hunk_patch(&relative, old, new) };
+    let diff = Diff::from_buffer(patch.as_bytes()).map_err(git_error)?;
+    let location = if cached { ApplyLocation::Index } else { ApplyLocation::WorkDir };
+    repo.apply(&diff, location, None).map_err(git_error)?;
+    Ok(root)
+}
+
+#[tauri::command]
+pub async fn git_stage(app: AppHandle, path: String) -> Result<(), String> {
+    let handle = app.clone();
+    let root = tauri::async_runtime::spawn_blocking(move || stage(&handle.state::<GitCache>(), &paths::decode(&path)))
+        .await
+        .map_err(|e| e.to_string())??;
+    status_changed(&app, &root);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn git_unstage(app: AppHandle, path: String) -> Result<(), String> {
+    let handle = app.clone();
+    let root = tauri::async_runtime::spawn_blocking(move || unstage(&handle.state::<GitCache>(), &paths::decode(&path)))
+        .await
+        .map_err(|e| e.to_string())??;
+    status_changed(&app, &root);
+    Ok(())
+}
+
+/// Restores the file from HEAD, returning what was discarded.
+#[tauri::command]
+pub async fn git_discard_file(app: AppHandle, path: String) -> Result<Discarded, String> {
+    let handle = app.clone();
+    let (root, discarded) = tauri::async_runtime::spawn_blocking(move || {
+        discard_file(&handle.state::<DocumentRegistry>(), &handle.state::<GitCache>(), &paths::decode(&path))
+            .inspect(|_| log::info!("Discarded the changes to {}", path))
+    })
+    .await
+        .map_err(|e| e.to_string())??;
+    status_changed(&app, &root);
+    Ok(discarded)
+}
+
+#[tauri::command]
+pub async fn git_apply_hunk(app: AppHandle, path: String, hunk: Hunk, reverse: bool, cached: bool) -> Result<(), String> {
+    let handle = app.clone();
+    let root = tauri::async_runtime::spawn_blocking(move || apply_hunk(&handle.state::<GitCache>(), &paths::decode(&path), &hunk, reverse, cached))
+        .await
+        .map_err(|e| e.to_string())??;
+    status_changed(&app, &root);
+    Ok(())
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CommitOptions {
+    pub amend: bool,
+    pub signoff: bool,
+    /// Stages every modified or deleted tracked file first, like `git commit -a`
+    pub stage_all: bool,
+    /// Runs `git commit` so the repository's hooks run. Otherwise libgit2
+    /// commits directly, which never runs hooks.
+    pub use_hooks: bool,
+}
+
+#[derive(Serialize)]
+pub struct Committed {
+    pub id: String,
+    pub summary: String,
+}
+
+/// The configured identity; libgit2 would otherwise fail with a config lookup
+/// error nobody can act on.
+fn signature(repo: &Repository) -> Result<git2::Signature<'static>, String> {
+    let config = repo.config().map_err(git_error)?;
+    let get = |key: &str| config.get_string(key).ok().filter(|value| !value.trim().is_empty());
+    match (get("user.name"), get("user.email")) {
+        (Some(name), Some(email)) => git2::Signature::now(&name, &email).map_err(git_error),
+        (None, _) => Err("Set user.name in your git config before committing".into()),
+        (_, None) => Err("Set user.email in your git config before committing".into()),
+    }
+}
+
+/// Strips comments and surrounding blank lines the way `git commit` does.
+fn clean_message(message: &str, signoff: Option<&git2::Signature>) -> Result<String, String> {
+    let mut message = git2::message_prettify(message, Some(b'#')).map_err(git_error)?;
+    if message.trim().is_empty() {
+        return Err("The commit message is empty".into());
+    }
+    if let Some(sig) = signoff {
+        let trailer = format!("Signed-off-by: {} <{}>", sig.name().unwrap_or_default(), sig.email().unwrap_or_default());
+        if !message.lines().any(|line| line == trailer) {
+            message = format!("{}\n{}\n", message, trailer);
+        }
+    }
+    Ok(message)
+}
+
+fn commit(cache: &GitCache, root: &Path, message: &str, opts: &CommitOptions) -> Result<(PathBuf, Committed), String> {
+    let RepoPaths { root, .. } = cache.discover(root).ok_or(NOT_IN_REPO)?;
+    let repo = Repository::open(&root).map_err(git_error)?;
+    if repo.path().join("index.lock").exists() {
+        return Err(INDEX_LOCKED.into());
+    }
+    let sig = signature(&repo)?;
+    let message = clean_message(message, opts.signoff.then_some(&sig))?;
+    let head = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+    if opts.amend && head.is_none() {
+        return Err("There's no commit to amend yet".into());
+    }
+
+    if opts.use_hooks {
+        commit_with_git(&root, &message, opts)?;
+    } else {
+        let mut index = repo.index().map_err(git_error)?;
+        if opts.stage_all {
+            index.update_all(["*"], None).and_then(|_| index.write()).map_err(git_error)?;
+        }
+        let tree = repo.find_tree(index.write_tree().map_err(git_error)?).map_err(git_error)?;
+        match &head {
+            Some(head) if opts.amend => {
+                // Like git, amending keeps the original author
+                head.amend(Some("HEAD"), None, Some(&sig), None, Some(&message), Some(&tree)).map_err(git_error)?;
+            }
+            _ => {
+                if head.as_ref().map_or(tree.is_empty(), |head| head.tree_id() == tree.id()) {
+                    return Err("Nothing is staged to commit".into());
+                }
+                let parents: Vec<&git2::Commit> = head.iter().collect();
+                repo.commit(Some("HEAD"), &sig, &sig, &message, &tree, &parents).map_err(git_error)?;
+            }
+        }
+    }
+
+    let new_head = repo.head().and_then(|head| head.peel_to_commit()).map_err(git_error)?;
+    let committed = Committed {
+        id: new_head.id().to_string(),
+        summary: new_head.summary().unwrap_or_default().to_string(),
+    };
+    Ok((root, committed))
+}
+
+/// Runs `git commit` non-interactively: the message is passed on the command
+/// line, stdin is closed and no editor or terminal prompt can open.
+fn commit_with_git(root: &Path, message: &str, opts: &CommitOptions) -> Result<(), String> {
+    let mut command = std::process::Command::new("git");
+    command
+        .current_dir(root)
+        .args(["commit", "--cleanup=verbatim", "-m", message])
+        .env("GIT_EDITOR", "true")
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(std::process::Stdio::null());
+    if opts.amend {
+        command.arg("--amend");
+    }
+    if opts.stage_all {
+        command.arg("--all");
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // CREATE_NO_WINDOW, so no console flashes up
+        command.creation_flags(0x0800_0000);
+    }
+    let output = command.output().map_err(|e| format!("Failed to run git: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Err(if stderr.trim().is_empty() { stdout } else { stderr }.trim().to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn git_commit(app: AppHandle, root: String, message: String, opts: Option<CommitOptions>) -> Result<Committed, String> {
+    let handle = app.clone();
+    let (root, committed) = tauri::async_runtime::spawn_blocking(move || {
+        commit(&handle.state::<GitCache>(), &paths::decode(&root), &message, &opts.unwrap_or_default())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .inspect_err(|e| log::warn!("Commit failed: {}", e))?;
+    log::info!("Committed {} in {}", committed.id, root.display());
+    status_changed(&app, &root);
+    Ok(committed)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeadVersion {
+    pub status: FileStatus,
+    /// `None` outside a repo, for untracked files and for binary blobs
+    pub content: Option<String>,
+    pub encoding: Option<String>,
+    pub binary: bool,
+    pub had_errors: bool,
+}
+
+fn head_version(registry: &DocumentRegistry, cache: &GitCache, path: &Path) -> Result<HeadVersion, String> {
+    let empty = |status| HeadVersion { status, content: None, encoding: None, binary: false, had_errors: false };
+    let Some(Located { repo, relative, .. }) = locate(cache, path) else {
+        return Ok(empty(FileStatus::NotInRepo));
+    };
+    let Some((_, entry)) = head_with(&repo, &relative) else {
+        return Ok(empty(FileStatus::Untracked));
+    };
+    let blob = entry.to_object(&repo).and_then(|o| o.peel_to_blob()).map_err(|e| e.to_string())?;
+    // UTF-16 text is full of NULs, which libgit2 takes for binary
+    if blob.is_binary() && Encoding::for_bom(blob.content()).is_none() {
+        return Ok(HeadVersion { binary: true, ..empty(FileStatus::Tracked) });
+    }
+    // Same encoding as the open buffer, so the two sides compare like for like
+    let (content, encoding, had_errors) = document::decode_like(registry, path, blob.content())?;
+    Ok(HeadVersion { status: FileStatus::Tracked, content: Some(content), encoding: Some(encoding), binary: false, had_errors })
+}
+
+/// The file as of HEAD, decoded like the open document, for Compare with HEAD.
+#[tauri::command]
+pub async fn git_show_head_version(app: AppHandle, path: String) -> Result<HeadVersion, String> {
+    tauri::async_runtime::spawn_blocking(move || head_version(&app.state::<DocumentRegistry>(), &app.state::<GitCache>(), &paths::decode(&path)))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct DiffStringsOptions {
+    /// Also render a unified diff with this many lines of context
+    pub unified: Option<u32>,
+    /// File name for the unified diff's headers
+    pub path: Option<String>,
+    pub ignore_whitespace: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunKind {
+    Equal,
+    Insert,
+    Delete,
+}
+
+/// `count` lines starting at 1-based `old_start` and `new_start`. A run only
+/// spans lines on its own side; the other start is where it sits there.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Run {
+    pub kind: RunKind,
+    pub old_start: u32,
+    pub new_start: u32,
+    pub count: u32,
+}
+
+#[derive(Serialize)]
+pub struct StringDiff {
+    pub runs: Vec<Run>,
+    pub unified: Option<String>,
+}
+
+fn diff_runs(old: &str, new: &str, opts: &DiffStringsOptions) -> Result<StringDiff, git2::Error> {
+    let path = opts.path.as_deref().map(Path::new);
+    let options = |context| {
+        let mut options = DiffOptions::new();
+        options.context_lines(context).force_text(true).ignore_whitespace(opts.ignore_whitespace);
+        options
+    };
+    let patch = Patch::from_buffers(old.as_bytes(), path, new.as_bytes(), path, Some(&mut options(0)))?;
+    let mut runs = Vec::new();
+    let (mut old_pos, mut new_pos) = (1, 1);
+    let mut push = |kind, old_start, new_start, count| {
+        if count > 0 {
+            runs.push(Run { kind, old_start, new_start, count });
+        }
+    };
+    for i in 0..patch.num_hunks() {
+        let (hunk, _) = patch.hunk(i)?;
+        // Pure insertions report the old line they follow
+        let first_old = if hunk.old_lines() == 0 { hunk.old_start() + 1 } else { hunk.old_start() };
+        let equal = first_old - old_pos;
+        push(RunKind::Equal, old_pos, new_pos, equal);
+        old_pos += equal;
+        new_pos += equal;
+        push(RunKind::Delete, old_pos, new_pos, hunk.old_lines());
+        old_pos += hunk.old_lines();
+        push(RunKind::Insert, old_pos, new_pos, hunk.new_lines());
+        new_pos += hunk.new_lines();
+    }
+    push(RunKind::Equal, old_pos, new_pos, (line_count(old.as_bytes()) + 1).saturating_sub(old_pos));
+
+    let unified = match opts.unified {
+        Some(context) => {
+            let mut patch = Patch::from_buffers(old.as_bytes(), path, new.as_bytes(), path, Some(&mut options(context)))?;
+            Some(String::from_utf8_lossy(&patch.to_buf()?).into_owned())
+        }
+        None => None,
+    };
+    Ok(StringDiff { runs, unified })
+}
+
+/// Line diff of two texts for the diff view. Runs carry line numbers only,
+/// since the view already has both texts.
+#[tauri::command]
+pub async fn diff_strings(old: String, new: String, opts: Option<DiffStringsOptions>) -> Result<StringDiff, String> {
+    tauri::async_runtime::spawn_blocking(move || diff_runs(&old, &new, &opts.unwrap_or_default()).map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffAlgorithm {
+    #[default]
+    Myers,
+    Patience,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct DiffFilesOptions {
+    pub algorithm: DiffAlgorithm,
+    pub ignore_whitespace: bool,
+    pub ignore_case: bool,
+    /// Lines of context in the unified diff, 3 when unset
+    pub context: Option<u32>,
+}
+
+/// Changed lines without context, as 1-based line ranges. An empty side
+/// starts at the line it follows, like in a unified diff.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffHunk {
+    pub old_start: u32,
+    pub old_lines: u32,
+    pub new_start: u32,
+    pub new_lines: u32,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case", rename_all_fields = "camelCase")]
+pub enum FilesDiff {
+    /// Also when the files only differ in what the options ignore
+    Identical,
+    /// One side is binary, so equality is all that's compared
+    BinaryDiffer,
+    Text { hunks: Vec<DiffHunk>, unified: String, old_lines: u32, new_lines: u32 },
+}
+
+// How far git looks for a NUL to call a file binary
+const BINARY_PROBE: usize = 8000;
+
+pub fn is_binary(bytes: &[u8]) -> bool {
+    // UTF-16 text is full of NULs too
+    Encoding::for_bom(bytes).is_none() && bytes[..bytes.len().min(BINARY_PROBE)].contains(&0)
+}
+
+/// A unified diff quoting the lines of `old` and `new` themselves, since
+/// `patch` may have compared lowercased copies.
+fn unified_diff(patch: &Patch, old: &str, new: &str, old_name: &str, new_name: &str) -> Result<String, git2::Error> {
+    let old: Vec<&str> = old.split_inclusive('\n').collect();
+    let new: Vec<&str> = new.split_inclusive('\n').collect();
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    for i in 0..patch.num_hunks() {
+        let (hunk, count) = patch.hunk(i)?;
+        out.push_str(&format!("@@ -{},{} +{},{} @@\n", hunk.old_start(), hunk.old_lines(), hunk.new_start(), hunk.new_lines()));
+        for j in 0..count {
+            let line = patch.line_in_hunk(i, j)?;
+            let origin = line.origin();
+            // libgit2's own end-of-file markers; the line itself gets one below
+            if !matches!(origin, ' ' | '-' | '+') {
+                continue;
+            }
+            let text = match (line.old_lineno(), line.new_lineno()) {
+                (Some(n), _) if origin != '+' => old.get(n as usize - 1),
+                (_, Some(n)) => new.get(n as usize - 1),
+                _ => None,
+            };
+            let text = text.copied().unwrap_or_default();
+            out.push(origin);
+            out.push_str(text);
+            if !text.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn diff_texts(old: &str, new: &str, names: (&str, &str), opts: &DiffFilesOptions) -> Result<FilesDiff, git2::Error> {
+    let (old_cmp, new_cmp) = if opts.ignore_case { (old.to_lowercase(), new.to_lowercase()) } else { (old.to_string(), new.to_string()) };
+    let options = |context| {
+        let mut options = DiffOptions::new();
+        options
+            .context_lines(context)
+            .force_text(true)
+            .ignore_whitespace(opts.ignore_whitespace)
+            .patience(matches!(opts.algorithm, DiffAlgorithm::Patience));
+        options
+    };
+    let patch = Patch::from_buffers(old_cmp.as_bytes(), None, new_cmp.as_bytes(), None, Some(&mut options(0)))?;
+    if patch.num_hunks() == 0 {
+        return Ok(FilesDiff::Identical);
+    }
+    let hunks = (0..patch.num_hunks())
+        .map(|i| {
+            let (hunk, _) = patch.hunk(i)?;
+            Ok(DiffHunk { old_start: hunk.old_start(), old_lines: hunk.old_lines(), new_start: hunk.new_start(), new_lines: hunk.new_lines() })
+        })
+        .collect::<Result<_, git2::Error>>()?;
+    let context = opts.context.unwrap_or(3);
+    let patch = Patch::from_buffers(old_cmp.as_bytes(), None, new_cmp.as_bytes(), None, Some(&mut options(context)))?;
+    let unified = unified_diff(&patch, old, new, names.0, names.1)?;
+    Ok(FilesDiff::Text { hunks, unified, old_lines: line_count(old.as_bytes()), new_lines: line_count(new.as_bytes()) })
+}
+
+fn diff_paths(registry: &DocumentRegistry, a: &Path, b: &Path, opts: &DiffFilesOptions) -> Result<FilesDiff, String> {
+    let read = |path: &Path| std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e));
+    let (old, new) = (read(a)?, read(b)?);
+    if old == new {
+        return Ok(FilesDiff::Identical);
+    }
+    if is_binary(&old) || is_binary(&new) {
+        return Ok(FilesDiff::BinaryDiffer);
+    }
+    let (old, _, _) = document::decode_like(registry, a, &old)?;
+    let (new, _, _) = document::decode_like(registry, b, &new)?;
+    let names = (a.to_string_lossy(), b.to_string_lossy());
+    diff_texts(&old, &new, (&names.0, &names.1), opts).map_err(|e| e.to_string())
+}
+
+/// Line diff of two files on disk, decoded like open documents are. Hunks
+/// carry line ranges only; a view of large files reads the lines it shows
+/// through `read_document_lines`.
+#[tauri::command]
+pub async fn diff_files(app: AppHandle, a: String, b: String, opts: Option<DiffFilesOptions>) -> Result<FilesDiff, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        diff_paths(&app.state::<DocumentRegistry>(), &paths::decode(&a), &paths::decode(&b), &opts.unwrap_or_default())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case", rename_all_fields = "camelCase")]
+pub enum SavedDiff {
+    Unchanged,
+    /// The file is gone from disk, so there's nothing to compare with
+    Missing,
+    Changed { hunks: Vec<DiffHunk>, unified: String, added: u32, removed: u32 },
+}
+
+fn diff_saved(registry: &DocumentRegistry, path: &Path, buffer: &str) -> Result<SavedDiff, String> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(SavedDiff::Missing),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    let (saved, _, _) = document::decode_like(registry, path, &bytes)?;
+    // The buffer's line endings are applied on save, so they aren't a change
+    let (saved, buffer) = (saved.replace("\r\n", "\n"), buffer.replace("\r\n", "\n"));
+    let name = path.to_string_lossy();
+    let names = (name.as_ref(), &*format!("{} (unsaved)", name));
+    match diff_texts(&saved, &buffer, names, &DiffFilesOptions::default()).map_err(|e| e.to_string())? {
+        FilesDiff::Text { hunks, unified, .. } => {
+            let added = hunks.iter().map(|h| h.new_lines).sum();
+            let removed = hunks.iter().map(|h| h.old_lines).sum();
+            Ok(SavedDiff::Changed { hunks, unified, added, removed })
+        }
+        _ => Ok(SavedDiff::Unchanged),
+    }
+}
+
+/// Diff of the file on disk against the unsaved `buffer_content` of its tab.
+#[tauri::command]
+pub async fn compare_with_saved(app: AppHandle, path: String, buffer_content: String) -> Result<SavedDiff, String> {
+    tauri::async_runtime::spawn_blocking(move || diff_saved(&app.state::<DocumentRegistry>(), &paths::decode(&path), &buffer_content))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum LineBlame {
+    Committed {
+        commit: String,
+        author: String,
+        email: String,
+        /// Seconds since the Unix epoch
+        timestamp: i64,
+        summary: String,
+    },
+    /// Changed on disk since HEAD, or in an untracked file
+    NotCommitted,
+}
+
+#[derive(Serialize)]
+pub struct BlameLine {
+    pub line: u32,
+    #[serde(flatten)]
+    pub blame: LineBlame,
+}
+
+#[derive(Serialize)]
+pub struct FileBlame {
+    pub status: FileStatus,
+    pub lines: Vec<BlameLine>,
+}
+
+struct CachedBlame {
+    head: Oid,
+    git_dir: PathBuf,
+    lines: Vec<LineBlame>,
+}
+
+struct CachedStatus {
+    root: PathBuf,
+    git_dir: PathBuf,
+    common_dir: PathBuf,
+    status: RepoStatus,
+}
+
+// Enough directories for a large session
+const DISCOVERY_CAPACITY: usize = 256;
+
+#[derive(Default)]
+struct CacheState {
+    blames: HashMap<PathBuf, CachedBlame>,
+    statuses: Vec<CachedStatus>,
+    /// Discovery per directory, least recently used first
+    discovered: Vec<(PathBuf, Option<RepoPaths>)>,
+    /// The path as the frontend knows it and what `discover_repository` last
+    /// reported, per canonical document path
+    documents: HashMap<PathBuf, (String, Option<DiscoveredRepo>)>,
+}
+
+#[derive(Clone, Serialize)]
+struct RepoChanged {
+    root: String,
+}
+
+/// Too much changed under `root` at once to say what; views of it should
+/// read it again.
+#[derive(Clone, Serialize)]
+struct BulkChange {
+    root: String,
+}
+
+#[derive(Clone, Serialize)]
+struct DocumentRepoChanged {
+    path: String,
+    repo: Option<DiscoveredRepo>,
+}
+
+/// Blame per file, status per repository and discovery per directory. Entries
+/// are dropped when the watcher sees their inputs change, so repeated queries
+/// in between are cheap: blame for the file or HEAD, status for anything in the
+/// git dir (HEAD, the index, refs), discovery for a `.git` appearing or going.
+#[derive(Default)]
+pub struct GitCache {
+    state: Arc<Mutex<CacheState>>,
+    watcher: Mutex<Option<RecommendedWatcher>>,
+    watched: Mutex<HashSet<PathBuf>>,
+}
+
+/// Entries in the cache, for diagnostics.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GitCacheUsage {
+    blames: usize,
+    statuses: usize,
+    discovered: usize,
+    documents: usize,
+    /// Folders the watcher is on; none when it isn't running
+    pub watched: Option<usize>,
+}
+
+impl GitCache {
+    pub fn usage(&self) -> GitCacheUsage {
+        let watching = self.watcher.lock().unwrap().is_some();
+        let state = self.state.lock().unwrap();
+        GitCacheUsage {
+            blames: state.blames.len(),
+            statuses: state.statuses.len(),
+            discovered: state.discovered.len(),
+            documents: state.documents.len(),
+            watched: watching.then(|| self.watched.lock().unwrap().len()),
+        }
+    }
+
+    fn discover(&self, dir: &Path) -> Option<RepoPaths> {
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(i) = state.discovered.iter().position(|(d, _)| *d == dir) {
+                let entry = state.discovered.remove(i);
+                let paths = entry.1.clone();
+                state.discovered.push(entry);
+                return paths;
+            }
+        }
+        let paths = discover_dir(&dir);
+        let mut state = self.state.lock().unwrap();
+        if state.discovered.len() >= DISCOVERY_CAPACITY {
+            state.discovered.remove(0);
+        }
+        state.discovered.push((dir, paths.clone()));
+        paths
+    }
+
+    // Directories rather than files, since saving by rename replaces the file
+    fn watch(&self, app: &AppHandle, dirs: &[(&Path, RecursiveMode)]) -> Result<(), String> {
+        let mut watcher = self.watcher.lock().unwrap();
+        if watcher.is_none() {
+            let state = self.state.clone();
+            let (app, roots) = (app.clone(), app.clone());
+            *watcher = Some(
+                notify::recommended_watcher(file_events::debounce(
+                    move || roots.state::<GitCache>().watched.lock().unwrap().iter().cloned().collect(),
+                    move |changes| files_changed(&app, &state, changes),
+                ))
+                .map_err(|e| e.to_string())?,
+            );
+        }
+        let watcher = watcher.as_mut().expect("watcher was just created");
+        let mut watched = self.watched.lock().unwrap();
+        for (dir, mode) in dirs {
+            if !watched.contains(*dir) {
+                watcher.watch(dir, *mode).map_err(|e| e.to_string())?;
+                watched.insert(dir.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Drops what the watcher saw change from the cache.
+fn files_changed(app: &AppHandle, state: &Mutex<CacheState>, changes: Vec<FileChange>) {
+    // Git writes through lock files; the rename into place is what counts
+    let paths: Vec<&Path> = changes.iter().flat_map(FileChange::paths).filter(|p| p.extension().map_or(true, |e| e != "lock")).collect();
+    let bulk: Vec<&Path> =
+        changes.iter().filter_map(|change| if let FileChange::Bulk { root } = change { Some(root.as_path()) } else { None }).collect();
+    // A bulk change stands for anything below its root
+    let touched = |path: &Path| paths.contains(&path) || bulk.iter().any(|root| path.starts_with(root));
+    let within = |dir: &Path| paths.iter().any(|p| p.starts_with(dir)) || bulk.iter().any(|root| root.starts_with(dir) || dir.starts_with(root));
+    let mut state = state.lock().unwrap();
+    // Everything below a directory that gained or lost a repo
+    let mut moved: Vec<&Path> = paths.iter().filter(|p| p.ends_with(".git")).filter_map(|p| p.parent()).collect();
+    moved.extend(&bulk);
+    state.discovered.retain(|(dir, _)| !moved.iter().any(|m| dir.starts_with(m)));
+    let documents: Vec<PathBuf> = state.documents.keys().filter(|doc| moved.iter().any(|m| doc.starts_with(m))).cloned().collect();
+    state.blames.retain(|path, entry| !touched(path) && !touched(&entry.git_dir.join("HEAD")));
+    state.statuses.retain(|entry| {
+        let stale = within(&entry.git_dir) || within(&entry.common_dir);
+        if stale {
+            let _ = app.emit("repo-status-changed", RepoChanged { root: entry.status.root.clone() });
+        }
+        !stale
+    });
+    drop(state);
+    for root in bulk {
+        let _ = app.emit("bulk-change", BulkChange { root: paths::encode(root) });
+    }
+    if !documents.is_empty() {
+        let app = app.clone();
+        // Off the watcher thread, which discovery would hold up
+        std::thread::spawn(move || documents.into_iter().for_each(|doc| document_repo_changed(&app, doc)));
+    }
+}
+
+fn document_repo_changed(app: &AppHandle, path: PathBuf) {
+    let cache = app.state::<GitCache>();
+    let repo = discovered_repo(&cache, &path);
+    let mut state = cache.state.lock().unwrap();
+    let Some((requested, previous)) = state.documents.get_mut(&path) else { return };
+    if *previous != repo {
+        *previous = repo.clone();
+        let _ = app.emit("document-repo-changed", DocumentRepoChanged { path: requested.clone(), repo });
+    }
+}
+
+/// Drops the cached status for the repository at `root` and tells the frontend.
+fn status_changed(app: &AppHandle, root: &Path) {
+    let cache = app.state::<GitCache>();
+    cache.state.lock().unwrap().statuses.retain(|entry| entry.root != root);
+    let _ = app.emit("repo-status-changed", RepoChanged { root: paths::encode(root) });
+}
+
+/// Refreshes the status of the repository containing `path` after skriv wrote
+/// to it, since the watcher only covers the git dir.
+pub fn file_saved(app: &AppHandle, path: &Path) {
+    if let Some(located) = locate(&app.state::<GitCache>(), path) {
+        status_changed(app, &located.root);
+    }
+}
+
+fn blame_lines(repo: &Repository, relative: &Path, head: Oid, contents: &[u8]) -> Result<Vec<LineBlame>, git2::Error> {
+    let mut opts = BlameOptions::new();
+    opts.newest_commit(head);
+    let committed = repo.blame_file(relative, Some(&mut opts))?;
+    // Against the file on disk, lines changed since HEAD get the zero oid
+    let blame = committed.blame_buffer(contents)?;
+    let mut summaries: HashMap<Oid, String> = HashMap::new();
+    let mut lines = Vec::new();
+    for hunk in blame.iter() {
+        let id = hunk.final_commit_id();
+        let record = if id.is_zero() {
+            LineBlame::NotCommitted
+        } else {
+            let summary = summaries
+                .entry(id)
+                .or_insert_with(|| repo.find_commit(id).ok().and_then(|c| c.summary().map(String::from)).unwrap_or_default())
+                .clone();
+            let signature = hunk.final_signature();
+            LineBlame::Committed {
+                commit: id.to_string(),
+                author: signature.name().unwrap_or_default().to_string(),
+                email: signature.email().unwrap_or_default().to_string(),
+                timestamp: signature.when().seconds(),
+                summary,
+            }
+        };
+        lines.resize(lines.len() + hunk.lines_in_hunk(), record);
+    }
+    Ok(lines)
+}
+
+/// Numbers the lines in `range` (1-based, inclusive; all when `None`).
+fn in_range(lines: &[LineBlame], range: Option<(u32, u32)>) -> Vec<BlameLine> {
+    let (start, end) = range.unwrap_or((1, u32::MAX));
+    let start = start.max(1);
+    let end = end.min(lines.len() as u32);
+    (start..=end).map(|line| BlameLine { line, blame: lines[line as usize - 1].clone() }).collect()
+}
+
+fn file_blame(app: &AppHandle, path: &Path, range: Option<(u32, u32)>) -> Result<FileBlame, String> {
+    let cache = app.state::<GitCache>();
+    let Some(Located { repo, path, relative, .. }) = locate(&cache, path) else {
+        return Ok(FileBlame { status: FileStatus::NotInRepo, lines: Vec::new() });
+    };
+    let Some((head, _)) = head_with(&repo, &relative) else {
+        let contents = std::fs::read(&path).map_err(|e| e.to_string())?;
+        let lines = vec![LineBlame::NotCommitted; line_count(&contents) as usize];
+        return Ok(FileBlame { status: FileStatus::Untracked, lines: in_range(&lines, range) });
+    };
+    if let Some(entry) = cache.state.lock().unwrap().blames.get(&path).filter(|e| e.head == head) {
+        return Ok(FileBlame { status: FileStatus::Tracked, lines: in_range(&entry.lines, range) });
+    }
+
+    let contents = std::fs::read(&path).map_err(|e| e.to_string())?;
+    let lines = blame_lines(&repo, &relative, head, &contents).map_err(|e| e.to_string())?;
+    let result = FileBlame { status: FileStatus::Tracked, lines: in_range(&lines, range) };
+    let git_dir = repo.path().canonicalize().map_err(|e| e.to_string())?;
+    let dirs = [(path.parent().unwrap_or(&path), RecursiveMode::NonRecursive), (&git_dir, RecursiveMode::NonRecursive)];
+    if let Err(e) = cache.watch(app, &dirs) {
+        // Without a watcher the entry could go stale, so don't keep it
+        log::warn!("Failed to watch {} for blame: {}", path.display(), e);
+        return Ok(result);
+    }
+    cache.state.lock().unwrap().blames.insert(path, CachedBlame { head, git_dir, lines });
+    Ok(result)
+}
+
+/// Who last changed each line in `line_range` (1-based, inclusive), as of HEAD.
+/// Lines changed on disk since then are reported as not committed.
+#[tauri::command]
+pub async fn git_blame(app: AppHandle, path: String, line_range: Option<(u32, u32)>) -> Result<FileBlame, String> {
+    tauri::async_runtime::spawn_blocking(move || file_blame(&app, &paths::decode(&path), line_range))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(count: usize) -> String {
+        (1..=count).map(|i| format!("line {}\n", i)).collect()
+    }
+
+    #[test]
+    fn tags_added_modified_and_deleted_hunks() {
+        let head = "a\nb\nc\nd\n";
+        let hunks = diff(head.as_bytes(), b"a\nB\nc\nnew\n").unwrap();
+        let summary: Vec<_> = hunks.iter().map(|h| (h.kind, h.start, h.end, h.original.as_deref())).collect();
+        assert_eq!(summary, [(HunkKind::Modified, 2, 2, Some("b\n")), (HunkKind::Modified, 4, 4, Some("d\n"))]);
+
+        let hunks = diff(head.as_bytes(), b"a\nb\nx\ny\nc\nd\n").unwrap();
+        assert_eq!((hunks[0].kind, hunks[0].start, hunks[0].end), (HunkKind::Added, 3, 4));
+
+        let hunks = diff(head.as_bytes(), b"c\nd\n").unwrap();
+        assert_eq!((hunks[0].kind, hunks[0].start, hunks[0].original.as_deref()), (HunkKind::Deleted, 0, Some("a\nb\n")));
+    }
+
+    #[test]
+    fn ignores_crlf_checkouts() {
+        assert!(diff(b"a\nb\n", b"a\r\nb\r\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn cleans_commit_messages() {
+        assert!(clean_message("\n# Only a comment\n  \n", None).is_err());
+        assert_eq!(clean_message("Fix it\n\n# comment\n", None).unwrap(), "Fix it\n");
+        let sig = git2::Signature::now("Ada", "ada@example.com").unwrap();
+        let signed = clean_message("Fix it", Some(&sig)).unwrap();
+        assert_eq!(signed, "Fix it\n\nSigned-off-by: Ada <ada@example.com>\n");
+        assert_eq!(clean_message(&signed, Some(&sig)).unwrap(), signed);
+    }
+
+    #[test]
+    fn splits_texts_into_runs() {
+        let run = |kind, old_start, new_start, count| Run { kind, old_start, new_start, count };
+        let diff = diff_runs("a\nb\nc\nd\n", "a\nB\nc\nd\ne\n", &DiffStringsOptions::default()).unwrap();
+        assert_eq!(diff.runs, [
+            run(RunKind::Equal, 1, 1, 1),
+            run(RunKind::Delete, 2, 2, 1),
+            run(RunKind::Insert, 3, 2, 1),
+            run(RunKind::Equal, 3, 3, 2),
+            run(RunKind::Insert, 5, 5, 1),
+        ]);
+        assert!(diff.unified.is_none());
+
+        let opts = DiffStringsOptions { unified: Some(1), path: Some("x.txt".into()), ..Default::default() };
+        let unified = diff_runs("a\nb\n", "a\nc\n", &opts).unwrap().unified.unwrap();
+        assert!(unified.contains("--- a/x.txt\n+++ b/x.txt\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n"));
+    }
+
+    #[test]
+    fn diffs_texts_with_options() {
+        let names = ("a.txt", "b.txt");
+        let opts = DiffFilesOptions::default();
+        let FilesDiff::Text { hunks, unified, .. } = diff_texts("a\nb\nc\n", "a\nB\nc", names, &opts).unwrap() else { panic!() };
+        assert_eq!(hunks, vec![DiffHunk { old_start: 2, old_lines: 2, new_start: 2, new_lines: 2 }]);
+        assert_eq!(unified, "--- a.txt\n+++ b.txt\n@@ -1,3 +1,3 @@\n a\n-b\n-c\n+B\n+c\n\\ No newline at end of file\n");
+
+        let ignore_case = DiffFilesOptions { ignore_case: true, ..Default::default() };
+        assert!(matches!(diff_texts("a\nb\n", "A\nB\n", names, &ignore_case).unwrap(), FilesDiff::Identical));
+        let FilesDiff::Text { unified, .. } = diff_texts("a\nb\n", "A\nc\n", names, &ignore_case).unwrap() else { panic!() };
+        assert!(unified.ends_with(" a\n-b\n+c\n"));
+    }
+
+    #[test]
+    fn compares_buffer_with_saved() {
+        let dir = std::env::temp_dir().join(format!("skriv-saved-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.txt");
+        std::fs::write(&path, "one\r\ntwo\r\nthree\r\n").unwrap();
+        let registry = DocumentRegistry::default();
+
+        assert!(matches!(diff_saved(&registry, &path, "one\ntwo\nthree\n").unwrap(), SavedDiff::Unchanged));
+        let SavedDiff::Changed { hunks, added, removed, .. } = diff_saved(&registry, &path, "one\n2\nthree\nfour\n").unwrap() else {
+            panic!()
+        };
+        assert_eq!(hunks.len(), 2);
+        assert_eq!((added, removed), (2, 1));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(diff_saved(&registry, &path, "one\n").unwrap(), SavedDiff::Missing));
+    }
+
+    #[test]
+    fn detects_binary() {
+        assert!(is_binary(b"PK\x03\x04\x00\x00"));
+        assert!(!is_binary(b"\xFF\xFEa\x00b\x00"));
+        assert!(!is_binary(b"plain text"));
+    }
+
+    #[test]
+    fn numbers_blame_in_a_range() {
+        let committed = |summary: &str| LineBlame::Committed {
+            commit: "c0ffee".into(),
+            author: "Ada".into(),
+            email: "ada@example.com".into(),
+            timestamp: 0,
+            summary: summary.into(),
+        };
+        let lines = [committed("one"), LineBlame::NotCommitted, committed("three")];
+        let numbered = |range| in_range(&lines, range).into_iter().map(|l| l.line).collect::<Vec<_>>();
+        assert_eq!(numbered(None), [1, 2, 3]);
+        assert_eq!(numbered(Some((2, 2))), [2]);
+        // Clamped to the lines there are
+        assert_eq!(numbered(Some((0, 99))), [1, 2, 3]);
+        assert!(numbered(Some((3, 2))).is_empty());
+        assert!(in_range(&[], None).is_empty());
+        assert!(matches!(&in_range(&lines, Some((3, 3)))[0].blame, LineBlame::Committed { summary, .. } if summary == "three"));
+    }
+
+    #[test]
+    fn blames_lines_changed_since_head_as_not_committed() {
+        let dir = std::env::temp_dir().join(format!("skriv-blame-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let repo = Repository::init(&dir).unwrap();
+        std::fs::write(dir.join("notes.txt"), "a\nb\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("notes.txt")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("Ada", "ada@example.com").unwrap();
+        let head = repo.commit(Some("HEAD"), &sig, &sig, "Add notes\n\nWith a body", &tree, &[]).unwrap();
+
+        let lines = blame_lines(&repo, Path::new("notes.txt"), head, b"a\nB\nc\n").unwrap();
+        assert_eq!(lines.len(), 3);
+        let LineBlame::Committed { commit, author, summary, .. } = &lines[0] else { panic!() };
+        assert_eq!((commit.as_str(), author.as_str(), summary.as_str()), (head.to_string().as_str(), "Ada", "Add notes"));
+        assert!(matches!(lines[1], LineBlame::NotCommitted));
+        assert!(matches!(lines[2], LineBlame::NotCommitted));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // cargo test --release -- --ignored --nocapture
+    #[test]
+    #[ignore = "benchmark"]
+    fn diff_10k_lines() {
+        let head = numbered(10_000);
+        let buffer = head.replace("line 5000\n", "changed\n").replace("line 9000\n", "");
+        let start = std::time::Instant::now();
+        let runs = 100;
+        for _ in 0..runs {
+            assert_eq!(diff(head.as_bytes(), buffer.as_bytes()).unwrap().len(), 2);
+        }
+        println!("10k-line diff: {:?} per call", start.elapsed() / runs);
+    }
+}
@@ -0,0 +1,697 @@
+//! Highlighting for documents too big for the webview to tokenize while
+//! scrolling. Each document is parsed with tree-sitter on a worker thread.
+//! An edit is applied to the tree at once and re-parsed incrementally in the
+//! background, so a query never waits for the parser. Queries run the
+//! grammar's highlight query over only the lines they ask for, and folds
+//! come from the nodes of the same tree.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use streaming_iterator::StreamingIterator;
+use tokio::sync::watch;
+use tree_sitter::{InputEdit, Language, Node, ParseOptions, ParseState, Parser, Point, Query, QueryCursor, Tree};
+
+use crate::workers::{Priority, WorkerPool};
+
+/// Text held across all sessions. The least recently used go first.
+const MAX_BYTES: usize = 128 * 1024 * 1024;
+/// Lines a first screen can ask for; more than any window shows.
+const MAX_FIRST_SCREEN: usize = 1000;
+/// How long a re-parse runs before other jobs get a turn.
+const PARSE_STEP: Duration = Duration::from_millis(20);
+
+/// Capture names by prefix and the token type they show as, most specific
+/// first. The types are Monaco's semantic token types.
+const TOKEN_TYPES: &[(&str, &str)] = &[
+    ("comment", "comment"),
+    ("string.special.key", "property"),
+    ("string.special", "regexp"),
+    ("string", "string"),
+    ("escape", "string"),
+    ("number", "number"),
+    ("boolean", "keyword"),
+    ("constant.builtin", "keyword"),
+    ("constant", "variable"),
+    ("keyword", "keyword"),
+    ("operator", "operator"),
+    ("function", "function"),
+    ("constructor", "type"),
+    ("type", "type"),
+    ("attribute", "property"),
+    ("property", "property"),
+    ("label", "property"),
+    ("variable.parameter", "parameter"),
+    ("variable", "variable"),
+    ("punctuation.special", "keyword"),
+    ("text.title", "keyword"),
+    ("text.literal", "string"),
+    ("text.uri", "string"),
+    ("text.reference", "type"),
+];
+
+/// A tree-sitter grammar and its highlight query.
+struct Grammar {
+    /// Monaco's language id
+    id: &'static str,
+    language: Language,
+    highlights: Query,
+    /// Per capture of `highlights`, 1 + its index in `TOKEN_TYPES`, or 0
+    /// for one that doesn't show
+    types: Vec<u8>,
+    /// Markdown's inline grammar, which parses each `inline` node of the
+    /// block grammar's tree
+    inline: Option<Box<Grammar>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Span {
+    /// A semantic token type from `TOKEN_TYPES`
+    pub scope: &'static str,
+    /// Byte offsets in the line
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LineTokens {
+    /// 0-based
+    pub line: usize,
+    pub spans: Vec<Span>,
+}
+
+/// 0-based lines, both included.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FoldingRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A change from the editor. Positions are 0-based, with columns counted in
+/// UTF-16 units as Monaco counts them, and refer to the text before it.
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HighlightEdit {
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    pub text: String,
+}
+
+struct Session {
+    grammar: &'static Grammar,
+    /// Each with its line break, but for the last. Shared with a re-parse
+    /// running meanwhile, so that it needn't hold the session.
+    lines: Arc<Vec<String>>,
+    /// Edited along with the text, and re-parsed after. `None` until the
+    /// first parse is done.
+    tree: Option<Tree>,
+    /// Edits that came before the first parse was done
+    early: Vec<HighlightEdit>,
+    /// Edits since a running re-parse took the text, for its tree to take in
+    pending: Vec<InputEdit>,
+    parsing: bool,
+    bytes: usize,
+    last_used: Instant,
+    /// Bumped by each edit, so that a re-parse knows to go again
+    generation: u64,
+}
+
+/// A session, and whether its first parse is done for queries to wait on.
+struct Open {
+    session: Mutex<Session>,
+    parsed: watch::Sender<bool>,
+}
+
+#[derive(Default)]
+pub struct Highlights(Mutex<HashMap<String, Arc<Open>>>);
+
+/// Asks `read_document` for the tokens of the lines a new tab shows first.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Prehighlight {
+    pub language: String,
+    pub viewport_lines: usize,
+}
+
+/// What a session holds, for diagnostics.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionUsage {
+    doc_id: String,
+    syntax: String,
+    lines: usize,
+    bytes: usize,
+    /// Edits are waiting to be parsed
+    parsing: bool,
+}
+
+fn token_type(capture: &str) -> u8 {
+    let matches = |prefix: &str| capture.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('.'));
+    TOKEN_TYPES.iter().position(|(prefix, _)| matches(prefix)).map_or(0, |i| i as u8 + 1)
+}
+
+fn grammar(id: &'static str, language: impl Into<Language>, query: &str) -> Option<Grammar> {
+    let language = language.into();
+    if let Err(e) = Parser::new().set_language(&language) {
+        log::warn!("Failed to load the {} grammar: {}", id, e);
+        return None;
+    }
+    let highlights = Query::new(&language, query).map_err(|e| log::warn!("Failed to load the {} highlight query: {}", id, e)).ok()?;
+    let types = highlights.capture_names().iter().map(|name| token_type(name)).collect();
+    Some(Grammar { id, language, highlights, types, inline: None })
+}
+
+/// Monaco's language ids with a grammar.
+fn grammars() -> &'static [Grammar] {
+    static GRAMMARS: OnceLock<Vec<Grammar>> = OnceLock::new();
+    GRAMMARS.get_or_init(|| {
+        let javascript = [tree_sitter_javascript::HIGHLIGHT_QUERY, tree_sitter_javascript::JSX_HIGHLIGHT_QUERY].concat();
+        // TypeScript's query only adds to JavaScript's, and comes first so that its patterns win
+        let typescript = [tree_sitter_typescript::HIGHLIGHTS_QUERY, tree_sitter_javascript::HIGHLIGHT_QUERY].concat();
+        let markdown = grammar("markdown", tree_sitter_md::LANGUAGE, tree_sitter_md::HIGHLIGHT_QUERY_BLOCK).map(|markdown| Grammar {
+            inline: grammar("markdown", tree_sitter_md::INLINE_LANGUAGE, tree_sitter_md::HIGHLIGHT_QUERY_INLINE).map(Box::new),
+            ..markdown
+        });
+        [
+            markdown,
+            grammar("json", tree_sitter_json::LANGUAGE, tree_sitter_json::HIGHLIGHTS_QUERY),
+            grammar("rust", tree_sitter_rust::LANGUAGE, tree_sitter_rust::HIGHLIGHTS_QUERY),
+            grammar("javascript", tree_sitter_javascript::LANGUAGE, &javascript),
+            grammar("typescript", tree_sitter_typescript::LANGUAGE_TYPESCRIPT, &typescript),
+            grammar("toml", tree_sitter_toml_ng::LANGUAGE, tree_sitter_toml_ng::HIGHLIGHTS_QUERY),
+            grammar("yaml", tree_sitter_yaml::LANGUAGE, tree_sitter_yaml::HIGHLIGHTS_QUERY),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    })
+}
+
+fn grammar_for(language: &str) -> Option<&'static Grammar> {
+    grammars().iter().find(|grammar| grammar.id == language)
+}
+
+fn split_lines(content: &str) -> Vec<String> {
+    content.split_inclusive('\n').map(String::from).collect()
+}
+
+/// The byte index of a UTF-16 column in `line`.
+pub fn byte_index(line: &str, column: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= column {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+fn new_parser(language: &Language) -> Parser {
+    let mut parser = Parser::new();
+    // `grammar` already tried it
+    let _ = parser.set_language(language);
+    parser
+}
+
+/// Parses `lines`, reusing what `old` has of them, until done or past
+/// `deadline`. A parser stopped by its deadline picks up where it left off
+/// when called again with the same text.
+fn parse(parser: &mut Parser, lines: &[String], old: Option<&Tree>, deadline: Option<Instant>) -> Option<Tree> {
+    let mut read = |_: usize, at: Point| lines.get(at.row).map_or(&b""[..], |line| line.as_bytes().get(at.column..).unwrap_or_default());
+    let mut stop = |_: &ParseState| deadline.is_some_and(|deadline| Instant::now() >= deadline);
+    parser.parse_with_options(&mut read, old, Some(ParseOptions::new().progress_callback(&mut stop)))
+}
+
+/// The text of `node` a line at a time, for the predicates of a query.
+fn node_text<'a>(lines: &'a [String], node: Node) -> impl Iterator<Item = &'a [u8]> {
+    let (start, end) = (node.start_position(), node.end_position());
+    (start.row..=end.row).filter_map(move |row| {
+        let line = lines.get(row)?.as_bytes();
+        let to = if row == end.row { end.column.min(line.len()) } else { line.len() };
+        let from = if row == start.row { start.column.min(to) } else { 0 };
+        Some(&line[from..to])
+    })
+}
+
+/// The nodes of `kind` in `node` that reach into `rows`.
+fn nodes_of_kind<'t>(node: Node<'t>, kind: &str, rows: &Range<usize>, found: &mut Vec<Node<'t>>) {
+    if node.kind() == kind {
+        found.push(node);
+        return;
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        if child.start_position().row < rows.end && child.end_position().row >= rows.start {
+            nodes_of_kind(child, kind, rows, found);
+        }
+    }
+}
+
+/// Marks each byte of the lines from `first` on with the token type of the
+/// captures over it. Inner nodes win over the ones around them, and for the
+/// same node the first pattern wins, as in tree-sitter's own highlighter.
+fn paint(painted: &mut [Vec<u8>], first: usize, grammar: &Grammar, tree: &Tree, lines: &[String]) {
+    if painted.is_empty() {
+        return;
+    }
+    let last = first + painted.len() - 1;
+    let mut cursor = QueryCursor::new();
+    cursor.set_point_range(Point::new(first, 0)..Point::new(last + 1, 0));
+    let mut captures = Vec::new();
+    let mut matches = cursor.matches(&grammar.highlights, tree.root_node(), |node: Node| node_text(lines, node));
+    while let Some(found) = matches.next() {
+        for capture in found.captures {
+            let kind = grammar.types[capture.index as usize];
+            if kind > 0 {
+                let node = capture.node;
+                captures.push((node.byte_range().len(), found.pattern_index, node.start_position(), node.end_position(), kind));
+            }
+        }
+    }
+    captures.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)));
+    for (_, _, start, end, kind) in captures {
+        for row in start.row.max(first)..=end.row.min(last) {
+            let line = &mut painted[row - first];
+            let to = if row == end.row { end.column.min(line.len()) } else { line.len() };
+            let from = if row == start.row { start.column.min(to) } else { 0 };
+            line[from..to].fill(kind);
+        }
+    }
+}
+
+/// Runs of bytes with the same token type.
+fn spans(types: &[u8]) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+    for (i, &kind) in types.iter().enumerate() {
+        if kind == 0 {
+            continue;
+        }
+        let scope = TOKEN_TYPES[kind as usize - 1].1;
+        match spans.last_mut() {
+            Some(last) if last.end == i && last.scope == scope => last.end = i + 1,
+            _ => spans.push(Span { scope, start: i, end: i + 1 }),
+        }
+    }
+    spans
+}
+
+impl Session {
+    fn new(grammar: &'static Grammar, bytes: usize) -> Self {
+        Session {
+            grammar,
+            lines: Arc::default(),
+            tree: None,
+            early: Vec::new(),
+            pending: Vec::new(),
+            parsing: false,
+            bytes,
+            last_used: Instant::now(),
+            generation: 0,
+        }
+    }
+
+    /// A session for `content`, parsed on the calling thread.
+    fn parsed(grammar: &'static Grammar, content: &str) -> Self {
+        let mut session = Session::new(grammar, content.len());
+        let lines = split_lines(content);
+        let tree = parse(&mut new_parser(&grammar.language), &lines, None, None);
+        session.load(lines, tree);
+        session
+    }
+
+    /// Takes in the first parse, and the edits made meanwhile.
+    fn load(&mut self, lines: Vec<String>, tree: Option<Tree>) {
+        self.lines = Arc::new(lines);
+        self.tree = tree;
+        for edit in std::mem::take(&mut self.early) {
+            self.edit(&edit);
+        }
+    }
+
+    fn highlights(&mut self, range: Range<usize>) -> Vec<LineTokens> {
+        self.last_used = Instant::now();
+        let Some(tree) = &self.tree else { return Vec::new() };
+        let lines = self.lines.as_slice();
+        let range = range.start.min(lines.len())..range.end.min(lines.len());
+        let mut painted: Vec<Vec<u8>> = lines[range.clone()].iter().map(|line| vec![0; line.trim_end_matches(['\n', '\r']).len()]).collect();
+        paint(&mut painted, range.start, self.grammar, tree, lines);
+        if let Some(inline) = &self.grammar.inline {
+            let mut found = Vec::new();
+            nodes_of_kind(tree.root_node(), "inline", &range, &mut found);
+            let mut parser = new_parser(&inline.language);
+            for node in found {
+                if parser.set_included_ranges(&[node.range()]).is_err() {
+                    continue;
+                }
+                if let Some(tree) = parse(&mut parser, lines, None, None) {
+                    paint(&mut painted, range.start, inline, &tree, lines);
+                }
+            }
+        }
+        painted.iter().enumerate().map(|(i, types)| LineTokens { line: range.start + i, spans: spans(types) }).collect()
+    }
+
+    fn edit(&mut self, edit: &HighlightEdit) {
+        self.last_used = Instant::now();
+        self.generation += 1;
+        let Some(tree) = &mut self.tree else { return self.early.push(edit.clone()) };
+        let lines = Arc::make_mut(&mut self.lines);
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+        let last = lines.len() - 1;
+        let (start_line, end_line) = (edit.start_line.min(last), edit.end_line.min(last));
+        let start = byte_index(&lines[start_line], edit.start_column);
+        let end = byte_index(&lines[end_line], edit.end_column);
+        let offset: usize = lines[..start_line].iter().map(String::len).sum();
+        let old_end = offset + lines[start_line..end_line].iter().map(String::len).sum::<usize>() + end;
+        let new_end_position = match edit.text.rfind('\n') {
+            Some(i) => Point::new(start_line + edit.text.matches('\n').count(), edit.text.len() - i - 1),
+            None => Point::new(start_line, start + edit.text.len()),
+        };
+        let text = format!("{}{}{}", &lines[start_line][..start], edit.text, &lines[end_line][end..]);
+        let replaced: usize = lines[start_line..=end_line].iter().map(String::len).sum();
+        self.bytes = self.bytes + text.len() - replaced;
+        lines.splice(start_line..=end_line, split_lines(&text));
+        let edit = InputEdit {
+            start_byte: offset + start,
+            old_end_byte: old_end,
+            new_end_byte: offset + start + edit.text.len(),
+            start_position: Point::new(start_line, start),
+            old_end_position: Point::new(end_line, end),
+            new_end_position,
+        };
+        tree.edit(&edit);
+        if self.parsing {
+            self.pending.push(edit);
+        }
+    }
+
+    /// Spans of the nodes that start on one line and end on a later one.
+    fn folding_ranges(&mut self) -> Vec<FoldingRange> {
+        self.last_used = Instant::now();
+        let Some(tree) = &self.tree else { return Vec::new() };
+        let mut ranges: Vec<FoldingRange> = Vec::new();
+        let mut cursor = tree.walk();
+        // Every node below the root, depth first
+        let mut more = cursor.goto_first_child();
+        while more {
+            let node = cursor.node();
+            let (start, end) = (node.start_position().row, node.end_position());
+            // A node that ends with its line break ends on the line before
+            let end = if end.column == 0 { end.row.saturating_sub(1) } else { end.row };
+            if node.is_named() && end > start {
+                ranges.push(FoldingRange { start, end });
+            }
+            // Only a node spanning lines holds one that does
+            if end > start && cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    more = false;
+                    break;
+                }
+            }
+        }
+        // Nested nodes often share a first line; the widest one is the fold
+        ranges.sort_by(|a, b| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+        ranges.dedup_by_key(|range| range.start);
+        ranges
+    }
+}
+
+/// Re-parses after edits in the background, a step at a time so that other
+/// jobs get a turn, and again for edits made meanwhile.
+fn reparse(pool: &WorkerPool, open: Weak<Open>, grammar: &'static Grammar) {
+    let mut parser = new_parser(&grammar.language);
+    let mut running: Option<(Arc<Vec<String>>, Tree, u64)> = None;
+    pool.spawn_steps(Priority::Bulk, move || {
+        let Some(open) = open.upgrade() else { return false };
+        if running.is_none() {
+            let mut session = open.session.lock().unwrap();
+            let Some(tree) = session.tree.clone() else { return false };
+            session.pending.clear();
+            running = Some((session.lines.clone(), tree, session.generation));
+        }
+        let Some((lines, old, generation)) = &running else { return false };
+        let Some(mut tree) = parse(&mut parser, lines, Some(old), Some(Instant::now() + PARSE_STEP)) else { return true };
+        let generation = *generation;
+        running = None;
+        let mut session = open.session.lock().unwrap();
+        for edit in session.pending.drain(..) {
+            tree.edit(&edit);
+        }
+        session.tree = Some(tree);
+        session.parsing = session.generation != generation;
+        session.parsing
+    });
+}
+
+/// Starts a re-parse unless one is running, which goes again by itself.
+fn reparse_edits(pool: &WorkerPool, open: &Arc<Open>, session: &mut Session) {
+    if session.tree.is_some() && !session.parsing {
+        session.parsing = true;
+        reparse(pool, Arc::downgrade(open), session.grammar);
+    }
+}
+
+/// Tokens for the first `viewport_lines` of `content`, parsed as a session
+/// would parse them, without reading the lines below; none without a grammar.
+pub fn first_screen(prehighlight: &Prehighlight, content: &str) -> Option<Vec<LineTokens>> {
+    let grammar = grammar_for(&prehighlight.language)?;
+    let lines = prehighlight.viewport_lines.min(MAX_FIRST_SCREEN);
+    let end = content.match_indices('\n').nth(lines.saturating_sub(1)).map_or(content.len(), |(i, _)| i + 1);
+    Some(Session::parsed(grammar, &content[..end]).highlights(0..lines))
+}
+
+pub fn usage(highlights: &Highlights) -> Vec<SessionUsage> {
+    let sessions = highlights.0.lock().unwrap();
+    sessions
+        .iter()
+        .map(|(doc_id, open)| {
+            let session = open.session.lock().unwrap();
+            let (lines, bytes, parsing) = (session.lines.len(), session.bytes, session.parsing);
+            SessionUsage { doc_id: doc_id.clone(), syntax: session.grammar.id.to_string(), lines, bytes, parsing }
+        })
+        .collect()
+}
+
+/// The session for `doc_id` once its first parse is done.
+async fn parsed(highlights: &Highlights, doc_id: &str) -> Result<Arc<Open>, String> {
+    let open = highlights.0.lock().unwrap().get(doc_id).cloned().ok_or_else(|| format!("No highlight session for {}", doc_id))?;
+    let mut parsed = open.parsed.subscribe();
+    parsed.wait_for(|parsed| *parsed).await.map_err(|e| e.to_string())?;
+    Ok(open)
+}
+
+/// Starts highlighting a document in the backend; false when there's no
+/// grammar for `language`, and the webview had better keep tokenizing it.
+/// The document is parsed on a worker thread. Edits can follow at once,
+/// and queries wait for the parse.
+#[tauri::command]
+pub fn open_highlight_session(highlights: tauri::State<'_, Highlights>, pool: tauri::State<'_, WorkerPool>, doc_id: String, language: String, content: String) -> Result<bool, String> {
+    let Some(grammar) = grammar_for(&language) else { return Ok(false) };
+    if content.len() > MAX_BYTES {
+        return Err("The document is too large to highlight".into());
+    }
+    let open = Arc::new(Open { session: Mutex::new(Session::new(grammar, content.len())), parsed: watch::Sender::new(false) });
+    let mut sessions = highlights.0.lock().unwrap();
+    sessions.remove(&doc_id);
+    let mut held: usize = sessions.values().map(|open| open.session.lock().unwrap().bytes).sum();
+    while held + content.len() > MAX_BYTES {
+        let oldest = sessions.iter().min_by_key(|(_, open)| open.session.lock().unwrap().last_used).map(|(id, _)| id.clone());
+        let Some(open) = oldest.and_then(|id| sessions.remove(&id)) else { break };
+        held -= open.session.lock().unwrap().bytes;
+    }
+    sessions.insert(doc_id, open.clone());
+    let handle = pool.inner().clone();
+    pool.spawn(Priority::Interactive, move || {
+        let lines = split_lines(&content);
+        drop(content);
+        let tree = parse(&mut new_parser(&grammar.language), &lines, None, None);
+        let mut session = open.session.lock().unwrap();
+        session.load(lines, tree);
+        if session.generation > 0 {
+            reparse_edits(&handle, &open, &mut session);
+        }
+        drop(session);
+        open.parsed.send_replace(true);
+    });
+    Ok(true)
+}
+
+#[tauri::command]
+pub fn apply_highlight_edit(highlights: tauri::State<'_, Highlights>, pool: tauri::State<'_, WorkerPool>, doc_id: String, edit: HighlightEdit) -> Result<(), String> {
+    let open = highlights.0.lock().unwrap().get(&doc_id).cloned().ok_or_else(|| format!("No highlight session for {}", doc_id))?;
+    let mut session = open.session.lock().unwrap();
+    session.edit(&edit);
+    reparse_edits(&pool, &open, &mut session);
+    Ok(())
+}
+
+/// Token spans for the lines in `line_range`, 0-based and end excluded.
+#[tauri::command]
+pub async fn get_highlights(
+    highlights: tauri::State<'_, Highlights>,
+    pool: tauri::State<'_, WorkerPool>,
+    doc_id: String,
+    line_range: Range<usize>,
+) -> Result<Vec<LineTokens>, String> {
+    let open = parsed(&highlights, &doc_id).await?;
+    pool.run(Priority::Interactive, move || open.session.lock().unwrap().highlights(line_range)).await
+}
+
+#[tauri::command]
+pub async fn get_folding_ranges(highlights: tauri::State<'_, Highlights>, pool: tauri::State<'_, WorkerPool>, doc_id: String) -> Result<Vec<FoldingRange>, String> {
+    let open = parsed(&highlights, &doc_id).await?;
+    pool.run(Priority::Interactive, move || open.session.lock().unwrap().folding_ranges()).await
+}
+
+/// Drops the session when its tab closes.
+#[tauri::command]
+pub fn close_highlight_session(highlights: tauri::State<'_, Highlights>, doc_id: String) {
+    highlights.0.lock().unwrap().remove(&doc_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scopes(tokens: &LineTokens, line: &str) -> Vec<(String, &'static str)> {
+        tokens.spans.iter().map(|s| (line[s.start..s.end].to_string(), s.scope)).collect()
+    }
+
+    fn edit(line: usize, column: usize, text: &str) -> HighlightEdit {
+        HighlightEdit { start_line: line, start_column: column, end_line: line, end_column: column, text: text.into() }
+    }
+
+    #[test]
+    fn has_every_grammar() {
+        for id in ["markdown", "json", "rust", "javascript", "typescript", "toml", "yaml"] {
+            assert!(grammar_for(id).is_some(), "{}", id);
+        }
+        assert!(grammar_for("markdown").unwrap().inline.is_some());
+        assert!(grammar_for("plaintext").is_none());
+    }
+
+    #[test]
+    fn highlights_only_the_lines_asked_for() {
+        let text = "// hi\nfn main() {\n    let x = \"s\";\n}\n";
+        let mut session = Session::parsed(grammar_for("rust").unwrap(), text);
+        let tokens = session.highlights(2..3);
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].line, 2);
+        let found = scopes(&tokens[0], "    let x = \"s\";");
+        assert!(found.contains(&("let".into(), "keyword")));
+        assert!(found.contains(&("\"s\"".into(), "string")));
+    }
+
+    #[test]
+    fn highlights_each_language() {
+        let cases = [
+            ("toml", "[package]\nname = \"skriv\" # x\n", 1, "# x", "comment"),
+            ("typescript", "let a: number = 1;\n", 0, "number", "type"),
+            ("yaml", "a:\n  b: true\n", 1, "true", "keyword"),
+            ("json", "{\"key\": 12}\n", 0, "12", "number"),
+            // Inline markdown comes from the second grammar
+            ("markdown", "# Title\n\nSome `code` here\n", 2, "`code`", "string"),
+        ];
+        for (language, text, line, token, scope) in cases {
+            let mut session = Session::parsed(grammar_for(language).unwrap(), text);
+            let tokens = session.highlights(line..line + 1);
+            let found = scopes(&tokens[0], text.lines().nth(line).unwrap());
+            assert!(found.contains(&(token.into(), scope)), "{}: {:?}", language, found);
+        }
+    }
+
+    #[test]
+    fn picks_up_from_an_edit() {
+        let text: String = (0..300).map(|i| format!("let a{} = {};\n", i, i)).collect();
+        let grammar = grammar_for("javascript").unwrap();
+        let mut session = Session::parsed(grammar, &text);
+        // A block comment around lines 100 to 200
+        session.edit(&edit(200, 0, "*/\n"));
+        session.edit(&edit(100, 0, "/*\n"));
+        let tree = parse(&mut new_parser(&grammar.language), &session.lines, session.tree.as_ref(), None);
+        session.tree = tree;
+        let tokens = session.highlights(150..151);
+        assert!(tokens[0].spans.iter().all(|s| s.scope == "comment"));
+        let edited: String = session.lines.concat();
+        assert_eq!(session.highlights(0..302), Session::parsed(grammar, &edited).highlights(0..302));
+    }
+
+    #[test]
+    fn reparses_edits_in_the_background() {
+        let pool = WorkerPool::new(1);
+        let grammar = grammar_for("rust").unwrap();
+        let open = Arc::new(Open { session: Mutex::new(Session::parsed(grammar, "fn a() {}\n")), parsed: watch::Sender::new(true) });
+        for (i, text) in ["// ", "x"].into_iter().enumerate() {
+            let mut session = open.session.lock().unwrap();
+            session.edit(&edit(0, i * 3, text));
+            reparse_edits(&pool, &open, &mut session);
+        }
+        let started = Instant::now();
+        while open.session.lock().unwrap().parsing {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let mut session = open.session.lock().unwrap();
+        assert_eq!(session.lines.as_slice(), ["// xfn a() {}\n"]);
+        assert_eq!(session.highlights(0..1)[0].spans, [Span { scope: "comment", start: 0, end: 13 }]);
+    }
+
+    #[test]
+    fn keeps_edits_made_before_the_first_parse() {
+        let grammar = grammar_for("json").unwrap();
+        let mut session = Session::new(grammar, 9);
+        session.edit(&edit(0, 1, "\"a\": 1"));
+        assert!(session.highlights(0..1).is_empty());
+        let lines = split_lines("{}\n");
+        let tree = parse(&mut new_parser(&grammar.language), &lines, None, None);
+        session.load(lines, tree);
+        assert_eq!(session.lines.as_slice(), ["{\"a\": 1}\n"]);
+        session.tree = parse(&mut new_parser(&grammar.language), &session.lines, session.tree.as_ref(), None);
+        assert!(scopes(&session.highlights(0..1)[0], "{\"a\": 1}").contains(&("1".into(), "number")));
+    }
+
+    #[test]
+    fn first_screen_matches_the_whole_session() {
+        // A comment opened on the first screen and closed on it
+        let text: String = (0..300)
+            .map(|i| match i {
+                10 => "/* open\n".to_string(),
+                260 => "*/\n".to_string(),
+                _ => format!("let a{} = \"{}\";\n", i, i),
+            })
+            .collect();
+        let prehighlight = Prehighlight { language: "javascript".into(), viewport_lines: 280 };
+        let first = first_screen(&prehighlight, &text).unwrap();
+        let mut session = Session::parsed(grammar_for("javascript").unwrap(), &text);
+        assert_eq!(first, session.highlights(0..280));
+        assert!(first_screen(&Prehighlight { language: "plaintext".into(), viewport_lines: 10 }, &text).is_none());
+    }
+
+    #[test]
+    fn edits_count_utf16_columns() {
+        let mut session = Session::parsed(grammar_for("json").unwrap(), "{\"å😀\": 1}\n");
+        session.edit(&edit(0, 4, "b"));
+        assert_eq!(session.lines.as_slice(), ["{\"å😀b\": 1}\n"]);
+        assert_eq!(session.bytes, session.lines[0].len());
+    }
+
+    #[test]
+    fn folds_blocks() {
+        let text = "fn a() {\n    1\n}\n\nfn b() {}\n";
+        let mut session = Session::parsed(grammar_for("rust").unwrap(), text);
+        assert_eq!(session.folding_ranges(), [FoldingRange { start: 0, end: 2 }]);
+        let mut session = Session::parsed(grammar_for("markdown").unwrap(), "# A\ntext\n# B\nmore\n");
+        assert_eq!(session.folding_ranges(), [FoldingRange { start: 0, end: 1 }, FoldingRange { start: 2, end: 3 }]);
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use tauri::{AppHandle, Manager};
+
+use crate::keybindings::Keybindings;
+use crate::menu;
+use crate::settings::{self, Settings};
+#[cfg(target_os = "macos")]
+use crate::window;
+
+pub const FALLBACK: &str = "en";
+
+/// Bundled menu translations, as flat key -> label maps.
+const LOCALES: &[(&str, &str)] = &[
+    ("en", include_str!("locales/en.json")),
+    ("de", include_str!("locales/de.json")),
+    ("fr", include_str!("locales/fr.json")),
+    ("sv", include_str!("locales/sv.json")),
+];
+
+fn parse(code: &str) -> HashMap<String, String> {
+    let (_, json) = LOCALES.iter().find(|(c, _)| *c == code).expect("bundled locale");
+    serde_json::from_str(json).expect("bundled translations are valid")
+}
+
+fn fallback() -> &'static HashMap<String, String> {
+    static EN: OnceLock<HashMap<String, String>> = OnceLock::new();
+    EN.get_or_init(|| parse(FALLBACK))
+}
+
+pub struct Translations {
+    locale: String,
+    strings: HashMap<String, String>,
+}
+
+impl Translations {
+    fn new(locale: &str) -> Self {
+        Translations { locale: locale.to_string(), strings: parse(locale) }
+    }
+
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    /// The label for `key`, falling back to English and then to the key itself.
+    pub fn t(&self, key: &str) -> String {
+        self.strings
+            .get(key)
+            .or_else(|| fallback().get(key))
+            .cloned()
+            .unwrap_or_else(|| key.to_string())
+    }
+}
+
+/// Maps a locale tag like "de-CH", "sv_SE.UTF-8" or "fr" to a bundled locale.
+fn resolve(tag: &str) -> Option<&'static str> {
+    let language = tag.split(['-', '_', '.', '@']).next()?.to_ascii_lowercase();
+    LOCALES.iter().map(|(c, _)| *c).find(|c| *c == language)
+}
+
+#[cfg(target_os = "macos")]
+pub fn os_locales() -> Vec<String> {
+    objc2_foundation::NSLocale::preferredLanguages().iter().map(|l| l.to_string()).collect()
+}
+
+#[cfg(windows)]
+pub fn os_locales() -> Vec<String> {
+    use windows::Win32::Globalization::GetUserDefaultLocaleName;
+
+    // LOCALE_NAME_MAX_LENGTH
+    let mut buf = [0u16; 85];
+    // SAFETY: the buffer is writable and its length is passed along
+    let len = unsafe { GetUserDefaultLocaleName(&mut buf) };
+    match usize::try_from(len) {
+        Ok(len) if len > 1 => vec![String::from_utf16_lossy(&buf[..len - 1])],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+pub fn os_locales() -> Vec<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .filter(|v| !v.is_empty() && v != "C" && v != "POSIX")
+        .collect()
+}
+
+/// The settings override if there is one, else the first OS language skriv
+/// has translations for.
+fn pick(settings: &Settings) -> &'static str {
+    settings
+        .locale
+        .as_deref()
+        .and_then(resolve)
+        .or_else(|| os_locales().iter().find_map(|l| resolve(l)))
+        .unwrap_or(FALLBACK)
+}
+
+pub fn load(app: &AppHandle) -> Translations {
+    let settings = app.state::<Mutex<Settings>>();
+    let locale = pick(&settings.lock().unwrap());
+    Translations::new(locale)
+}
+
+/// Overrides the OS locale (`None` follows the OS again) and rebuilds the menus
+/// in the new language. Returns the locale now in use.
+#[tauri::command]
+pub fn set_app_locale(app: AppHandle, locale: Option<String>) -> Result<String, String> {
+    if let Some(tag) = &locale {
+        resolve(tag).ok_or_else(|| format!("Unsupported locale \"{}\"", tag))?;
+    }
+    settings::modify(&app, |settings| settings.locale = locale)?;
+    refresh(&app)
+}
+
+/// Rebuilds the menus in the language the settings now pick, and returns
+/// its locale.
+pub fn refresh(app: &AppHandle) -> Result<String, String> {
+    let translations = load(app);
+    let code = translations.locale().to_string();
+    *app.state::<Mutex<Translations>>().lock().unwrap() = translations;
+
+    let keys = app.state::<Mutex<Keybindings>>().lock().unwrap().clone();
+    let menu = menu::build(app, &keys).map_err(|e| e.to_string())?;
+    app.set_menu(menu).map_err(|e| e.to_string())?;
+    #[cfg(target_os = "macos")]
+    window::localize_dock_menu(app);
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+
+    /// Every Rust source file under `dir`, but this one, whose tests use
+    /// made-up keys.
+    fn sources(dir: &Path) -> Vec<(PathBuf, String)> {
+        let mut found = Vec::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                found.extend(sources(&path));
+            } else if path.extension().is_some_and(|ext| ext == "rs") && !path.ends_with("i18n.rs") {
+                let text = std::fs::read_to_string(&path).unwrap();
+                found.push((path, text));
+            }
+        }
+        found
+    }
+
+    // Everything that builds menu labels passes literal keys to `t`, or to the
+    // menu.rs helpers that take the item id as its key
+    #[test]
+    fn menu_keys_exist_in_english() {
+        let re = regex::Regex::new(r#"(?:\bt|item)\("([a-z_]+)""#).unwrap();
+        let en = fallback();
+        let sources = sources(&Path::new(env!("CARGO_MANIFEST_DIR")).join("src"));
+        assert!(sources.iter().any(|(path, _)| path.ends_with("menu.rs")));
+        let missing: Vec<String> = sources
+            .iter()
+            .flat_map(|(path, src)| re.captures_iter(src).map(move |c| (path, c.get(1).unwrap().as_str())))
+            .filter(|(_, key)| !en.contains_key(*key))
+            .map(|(path, key)| format!("{} in {}", key, path.display()))
+            .collect();
+        assert!(missing.is_empty(), "missing from en.json: {:?}", missing);
+    }
+
+    #[test]
+    fn translations_only_use_english_keys() {
+        let en = fallback();
+        for (code, _) in LOCALES {
+            let unknown: Vec<String> = parse(code).into_keys().filter(|k| !en.contains_key(k)).collect();
+            assert!(unknown.is_empty(), "{}.json has keys not in en.json: {:?}", code, unknown);
+        }
+    }
+
+    #[test]
+    fn missing_keys_fall_back_to_english() {
+        let mut translations = Translations::new("de");
+        translations.strings.remove("save_file");
+        assert_eq!(translations.t("save_file"), "Save");
+        assert_eq!(translations.t("no_such_key"), "no_such_key");
+    }
+
+    #[test]
+    fn resolves_os_locale_tags() {
+        assert_eq!(resolve("de-CH"), Some("de"));
+        assert_eq!(resolve("sv_SE.UTF-8"), Some("sv"));
+        assert_eq!(resolve("FR"), Some("fr"));
+        assert_eq!(resolve("ja-JP"), None);
+    }
+}
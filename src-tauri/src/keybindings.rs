@@ -0,0 +1,436 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use muda::accelerator::Accelerator;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::{menu, paths};
+
+pub const FILE_NAME: &str = "keybindings.json";
+
+// Menu items whose accelerator can be overridden, with the built-in defaults.
+const DEFAULTS: &[(&str, Option<&str>)] = &[
+    ("new_tab", Some("CmdOrCtrl+N")),
+    ("new_window", Some("CmdOrCtrl+Shift+N")),
+    ("open_file", Some("CmdOrCtrl+O")),
+    ("save_file", Some("CmdOrCtrl+S")),
+    ("save_file_as", Some("CmdOrCtrl+Shift+S")),
+    ("compare_with_saved", None),
+    ("line_ending_lf", None),
+    ("line_ending_crlf", None),
+    ("open_terminal_here", None),
+    ("export_html", None),
+    ("export_pdf", None),
+    ("print", Some("CmdOrCtrl+P")),
+    ("toggle_comment", Some("CmdOrCtrl+Shift+C")),
+    ("format_document", Some("CmdOrCtrl+Shift+F")),
+    ("column_selection", None),
+    ("copy_path_with_line", None),
+    ("paste_relative_path", None),
+    ("open_snippets_folder", None),
+    ("open_templates_folder", None),
+    ("move_line_up", Some("Alt+Up")),
+    ("move_line_down", Some("Alt+Down")),
+    ("duplicate_line", Some("CmdOrCtrl+Shift+D")),
+    ("join_lines", None),
+    ("sort_lines_ascending", None),
+    ("sort_lines_descending", None),
+    ("delete_duplicate_lines", None),
+    ("find_open", Some("CmdOrCtrl+F")),
+    ("find_next", Some("CmdOrCtrl+G")),
+    ("find_previous", Some("CmdOrCtrl+Shift+G")),
+    ("find_use_selection", Some("CmdOrCtrl+E")),
+    ("find_replace", Some("CmdOrCtrl+Alt+F")),
+    // CmdOrCtrl+Shift+F has long been Format Document
+    ("find_in_files", None),
+    ("command_palette", Some("CmdOrCtrl+Shift+P")),
+    ("split_view", Some("CmdOrCtrl+\\")),
+    ("word_wrap", Some("Alt+Z")),
+    ("theme_system", None),
+    ("theme_light", None),
+    ("theme_dark", None),
+];
+
+/// One binding of the keymap. `key` is normalized: key presses separated by
+/// spaces, modifiers in a fixed order and named for this platform.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Binding {
+    pub key: String,
+    /// A menu item id, or a command the webview runs
+    pub command: String,
+    pub when: Option<String>,
+    /// From keybindings.json rather than the defaults
+    pub user: bool,
+}
+
+/// Commands bound to the same key in the same context. The last one listed
+/// keeps the key; the others lose it.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Conflict {
+    pub key: String,
+    pub when: Option<String>,
+    pub commands: Vec<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Keybindings {
+    /// Menu accelerators by item id
+    pub bindings: BTreeMap<String, Option<String>>,
+    /// Everything bound, menu items included
+    pub keymap: Vec<Binding>,
+    pub warnings: Vec<String>,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl Keybindings {
+    pub fn accelerator(&self, id: &str) -> Option<&str> {
+        self.bindings.get(id).and_then(|a| a.as_deref())
+    }
+}
+
+/// An entry of keybindings.json, as VS Code writes them. A command starting
+/// with `-` removes that command's binding instead.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    #[serde(default)]
+    key: String,
+    command: String,
+    when: Option<String>,
+}
+
+pub fn is_menu_item(id: &str) -> bool {
+    DEFAULTS.iter().any(|(d, _)| *d == id)
+}
+
+/// One key press like `ctrl+shift+p`, in the form menus take. Cmd/Super only
+/// exists as a menu modifier on macOS; on Windows it's the Windows key,
+/// which the OS swallows, so elsewhere a `builtin` default's means Ctrl. A
+/// user's own binding gets the key they named.
+fn normalize_press(press: &str, builtin: bool) -> Result<String, String> {
+    let mac = cfg!(target_os = "macos");
+    let parts: Vec<&str> = press.split('+').map(str::trim).collect();
+    let Some((key, modifiers)) = parts.split_last().filter(|(key, _)| !key.is_empty()) else {
+        return Err("no key after the modifiers".into());
+    };
+    let (mut cmd, mut ctrl, mut alt, mut shift) = (false, false, false, false);
+    for modifier in modifiers {
+        match modifier.to_ascii_lowercase().as_str() {
+            "cmdorctrl" | "commandorcontrol" if mac => cmd = true,
+            "cmdorctrl" | "commandorcontrol" => ctrl = true,
+            "cmd" | "command" | "meta" | "super" | "win" if mac || !builtin => cmd = true,
+            "cmd" | "command" | "meta" | "super" | "win" => ctrl = true,
+            "ctrl" | "control" => ctrl = true,
+            "alt" | "option" => alt = true,
+            "shift" => shift = true,
+            other => return Err(format!("unknown modifier \"{}\"", other)),
+        }
+    }
+    let mut chars = key.chars();
+    let key: String = match chars.next() {
+        Some(first) if key.chars().count() == 1 => first.to_uppercase().collect(),
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => unreachable!(),
+    };
+    let names = [(cmd, if mac { "Cmd" } else { "Super" }), (ctrl, "Ctrl"), (alt, "Alt"), (shift, "Shift")];
+    let normalized: Vec<&str> = names.iter().filter(|(on, _)| *on).map(|(_, name)| *name).chain([key.as_str()]).collect();
+    let normalized = normalized.join("+");
+    normalized.parse::<Accelerator>().map_err(|e| e.to_string())?;
+    Ok(normalized)
+}
+
+fn normalize_with(key: &str, builtin: bool) -> Result<String, String> {
+    let presses: Vec<&str> = key.split_whitespace().collect();
+    if presses.is_empty() || presses.len() > 2 {
+        return Err("should be one key press, or two separated by a space".into());
+    }
+    presses.into_iter().map(|press| normalize_press(press, builtin)).collect::<Result<Vec<_>, _>>().map(|presses| presses.join(" "))
+}
+
+/// A user's key like `ctrl+k ctrl+c`: one or two presses, as in VS Code.
+pub fn normalize(key: &str) -> Result<String, String> {
+    normalize_with(key, false)
+}
+
+fn defaults() -> Vec<Binding> {
+    DEFAULTS
+        .iter()
+        .filter_map(|(id, key)| {
+            let key = normalize_with(key.as_ref()?, true).expect("default keybindings are valid");
+            Some(Binding { key, command: id.to_string(), when: None, user: false })
+        })
+        .collect()
+}
+
+/// The defaults with the file's entries applied in order, then conflicts
+/// settled in favour of the later binding.
+fn resolve(entries: Vec<Entry>, mut warnings: Vec<String>) -> Keybindings {
+    let mut keymap = defaults();
+    for entry in entries {
+        let when = entry.when.map(|w| w.trim().to_string()).filter(|w| !w.is_empty());
+        if let Some(command) = entry.command.strip_prefix('-') {
+            let key = match entry.key.trim() {
+                "" => None,
+                key => match normalize(key) {
+                    Ok(key) => Some(key),
+                    Err(e) => {
+                        warnings.push(format!("Invalid key \"{}\" for \"{}\": {}", entry.key, entry.command, e));
+                        continue;
+                    }
+                },
+            };
+            keymap.retain(|b| {
+                !(b.command == command && b.when == when && key.as_ref().map_or(true, |k| k.eq_ignore_ascii_case(&b.key)))
+            });
+            continue;
+        }
+        let key = match normalize(&entry.key) {
+            Ok(key) => key,
+            Err(e) => {
+                warnings.push(format!("Invalid key \"{}\" for \"{}\": {}", entry.key, entry.command, e));
+                continue;
+            }
+        };
+        if is_menu_item(&entry.command) {
+            if key.contains(' ') {
+                warnings.push(format!("\"{}\" for menu item \"{}\": menus take a single key press", key, entry.command));
+                continue;
+            }
+            if when.is_some() {
+                warnings.push(format!("Ignoring the \"when\" of menu item \"{}\"; menu shortcuts always apply", entry.command));
+            }
+            // A menu item shows one shortcut, so it replaces the default
+            keymap.retain(|b| b.command != entry.command);
+            keymap.push(Binding { key, command: entry.command, when: None, user: true });
+        } else {
+            keymap.push(Binding { key, command: entry.command, when, user: true });
+        }
+    }
+
+    let mut conflicts = Vec::new();
+    let mut shadowed = vec![false; keymap.len()];
+    for i in 0..keymap.len() {
+        if shadowed[i] {
+            continue;
+        }
+        let same: Vec<usize> = (i..keymap.len())
+            .filter(|&j| keymap[j].key.eq_ignore_ascii_case(&keymap[i].key) && keymap[j].when == keymap[i].when)
+            .collect();
+        if let Some((_, losers)) = same.split_last().filter(|(_, losers)| !losers.is_empty()) {
+            conflicts.push(Conflict {
+                key: keymap[i].key.clone(),
+                when: keymap[i].when.clone(),
+                commands: same.iter().map(|&j| keymap[j].command.clone()).collect(),
+            });
+            for &j in losers {
+                shadowed[j] = true;
+            }
+        }
+    }
+    let mut shadowed = shadowed.into_iter();
+    keymap.retain(|_| !shadowed.next().unwrap_or_default());
+
+    let bindings = DEFAULTS
+        .iter()
+        .map(|(id, _)| (id.to_string(), keymap.iter().find(|b| b.command == *id).map(|b| b.key.clone())))
+        .collect();
+    Keybindings { bindings, keymap, warnings, conflicts }
+}
+
+/// The file's entries. The older form, an object of menu item ids to
+/// accelerators, is still read.
+fn parse_file(text: &str, warnings: &mut Vec<String>) -> Vec<Entry> {
+    let value: Value = match serde_json::from_str(text) {
+        Ok(value) => value,
+        Err(e) => {
+            warnings.push(format!("{}: {}", FILE_NAME, e));
+            return Vec::new();
+        }
+    };
+    match value {
+        Value::Array(items) => items
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, item)| {
+                serde_json::from_value(item).inspect_err(|e| warnings.push(format!("{} entry {}: {}", FILE_NAME, i + 1, e))).ok()
+            })
+            .collect(),
+        Value::Object(items) => items
+            .into_iter()
+            .filter_map(|(command, key)| {
+                if !is_menu_item(&command) {
+                    warnings.push(format!("Unknown menu item \"{}\"", command));
+                    return None;
+                }
+                let Value::String(key) = key else {
+                    warnings.push(format!("The accelerator for \"{}\" should be a string", command));
+                    return None;
+                };
+                Some(Entry { key, command, when: None })
+            })
+            .collect(),
+        _ => {
+            warnings.push(format!("{} should hold an array of keybindings", FILE_NAME));
+            Vec::new()
+        }
+    }
+}
+
+fn path(app: &AppHandle) -> Result<PathBuf, String> {
+    paths::config_dir(app).map(|dir| dir.join(FILE_NAME))
+}
+
+pub fn load(app: &AppHandle) -> Keybindings {
+    let mut warnings = Vec::new();
+    // A missing file just means no overrides
+    let entries = match path(app).ok().and_then(|p| std::fs::read_to_string(p).ok()) {
+        Some(text) => parse_file(&text, &mut warnings),
+        None => Vec::new(),
+    };
+    resolve(entries, warnings)
+}
+
+pub fn apply(app: &AppHandle) -> Result<Keybindings, String> {
+    let keys = load(app);
+    let menu = menu::build(app, &keys).map_err(|e| e.to_string())?;
+    app.set_menu(menu).map_err(|e| e.to_string())?;
+    if !keys.warnings.is_empty() {
+        let _ = app.emit("keybindings-warning", &keys.warnings);
+    }
+    if !keys.conflicts.is_empty() {
+        let _ = app.emit("keybinding-conflicts", &keys.conflicts);
+    }
+    let _ = app.emit("keybindings-changed", &keys);
+    *app.state::<Mutex<Keybindings>>().lock().unwrap() = keys.clone();
+    Ok(keys)
+}
+
+/// Adds `entries` at the end of keybindings.json, where they win over what's
+/// there, and applies the result. A file in the older form is rewritten as
+/// an array.
+pub fn append(app: &AppHandle, entries: Vec<Value>) -> Result<Keybindings, String> {
+    let path = path(app)?;
+    let mut items = match std::fs::read_to_string(&path) {
+        Ok(text) => match serde_json::from_str(&text).map_err(|e| format!("{} isn't valid JSON: {}", FILE_NAME, e))? {
+            Value::Array(items) => items,
+            Value::Object(items) => {
+                items.into_iter().map(|(command, key)| serde_json::json!({ "key": key, "command": command })).collect()
+            }
+            _ => return Err(format!("{} should hold an array of keybindings", FILE_NAME)),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.to_string()),
+    };
+    items.extend(entries);
+    let text = serde_json::to_string_pretty(&items).map_err(|e| e.to_string())?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    std::fs::write(&path, text + "\n").map_err(|e| e.to_string())?;
+    apply(app)
+}
+
+#[tauri::command]
+pub fn reload_keybindings(app: AppHandle) -> Result<Keybindings, String> {
+    apply(&app)
+}
+
+#[tauri::command]
+pub fn get_keybindings(state: tauri::State<'_, Mutex<Keybindings>>) -> Keybindings {
+    state.lock().unwrap().clone()
+}
+
+/// Puts keybindings.json aside as keybindings.json.bak and goes back to the
+/// defaults.
+#[tauri::command]
+pub fn reset_keybindings(app: AppHandle) -> Result<Keybindings, String> {
+    let path = path(&app)?;
+    if path.exists() {
+        let mut backup = path.clone().into_os_string();
+        backup.push(".bak");
+        std::fs::rename(&path, backup).map_err(|e| e.to_string())?;
+    }
+    apply(&app)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: &str, command: &str, when: Option<&str>) -> Entry {
+        Entry { key: key.into(), command: command.into(), when: when.map(String::from) }
+    }
+
+    #[test]
+    fn normalizes_chords() {
+        assert_eq!(normalize("shift+ctrl+p").as_deref(), Ok("Ctrl+Shift+P"));
+        assert_eq!(normalize("ctrl+k  ctrl+c").as_deref(), Ok("Ctrl+K Ctrl+C"));
+        let cmd = if cfg!(target_os = "macos") { "Cmd+Alt+Up" } else { "Super+Alt+Up" };
+        assert_eq!(normalize("meta+option+up").as_deref(), Ok(cmd));
+        // Only the built-in defaults take Super for Ctrl off macOS
+        let builtin = if cfg!(target_os = "macos") { "Cmd+Alt+Up" } else { "Ctrl+Alt+Up" };
+        assert_eq!(normalize_with("super+alt+up", true).as_deref(), Ok(builtin));
+        assert_eq!(normalize("cmdorctrl+alt+up").as_deref(), Ok(builtin));
+        assert!(normalize("hyper+x").unwrap_err().contains("hyper"));
+        assert!(normalize("ctrl+").is_err());
+        assert!(normalize("ctrl+nosuchkey").is_err());
+        assert!(normalize("a b c").is_err());
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        let keys = resolve(Vec::new(), Vec::new());
+        assert!(keys.conflicts.is_empty(), "{:?}", keys.conflicts);
+        assert_eq!(keys.keymap.len(), DEFAULTS.iter().filter(|(_, key)| key.is_some()).count());
+    }
+
+    #[test]
+    fn reports_conflicts_and_keeps_the_later_binding() {
+        let keys = resolve(
+            vec![
+                entry("ctrl+shift+p", "print", None),
+                entry("ctrl+k ctrl+z", "skriv.zen", Some("editorTextFocus")),
+                entry("ctrl+k ctrl+z", "skriv.zoom", Some("editorTextFocus")),
+                entry("ctrl+k ctrl+z", "skriv.other", None),
+            ],
+            Vec::new(),
+        );
+        let commands: Vec<&[String]> = keys.conflicts.iter().map(|c| c.commands.as_slice()).collect();
+        assert_eq!(commands, [&["command_palette".to_string(), "print".into()][..], &["skriv.zen".to_string(), "skriv.zoom".into()][..]]);
+        assert_eq!(keys.accelerator("print"), Some("Ctrl+Shift+P"));
+        assert_eq!(keys.accelerator("command_palette"), None);
+        assert!(keys.keymap.iter().any(|b| b.command == "skriv.other"));
+        assert!(!keys.keymap.iter().any(|b| b.command == "skriv.zen"));
+    }
+
+    #[test]
+    fn removes_and_rejects_bindings() {
+        let keys = resolve(
+            vec![
+                entry("", "-word_wrap", None),
+                entry("ctrl+k ctrl+s", "save_file", None),
+                entry("ctrl+q", "duplicate_line", Some("editorTextFocus")),
+            ],
+            Vec::new(),
+        );
+        assert_eq!(keys.accelerator("word_wrap"), None);
+        assert!(keys.accelerator("save_file").is_some());
+        assert_eq!(keys.accelerator("duplicate_line"), Some("Ctrl+Q"));
+        assert_eq!(keys.warnings.len(), 2);
+    }
+
+    #[test]
+    fn reads_the_older_object_form() {
+        let mut warnings = Vec::new();
+        let entries = parse_file(r#"{ "print": "Ctrl+Alt+P", "nope": "Ctrl+1" }"#, &mut warnings);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(warnings, ["Unknown menu item \"nope\""]);
+        let mut warnings = Vec::new();
+        let entries = parse_file(r#"[{ "key": "ctrl+1", "command": "a" }, { "key": "ctrl+2" }]"#, &mut warnings);
+        assert_eq!(entries.len(), 1);
+        assert!(warnings[0].starts_with("keybindings.json entry 2"));
+    }
+}
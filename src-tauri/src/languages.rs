@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::editorconfig;
+use crate::paths;
+use crate::settings::Settings;
+
+pub const FILE_NAME: &str = "languages.json";
+pub const PLAIN_TEXT: &str = "plaintext";
+
+const BUILTIN: &str = include_str!("languages.json");
+
+/// Lines at either end of a file searched for an Emacs or Vim modeline
+const MODELINE_LINES: usize = 5;
+/// How much of the start of a file the content heuristic looks at
+const SNIFF_BYTES: usize = 4096;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Language {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// Globs over the file name, as `Dockerfile*`, matched ignoring case
+    #[serde(default)]
+    pub filenames: Vec<String>,
+    #[serde(default)]
+    pub first_line: Vec<String>,
+    /// Other names for the language in shebangs and modelines, as `node`
+    /// for JavaScript. The id and name always count.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+pub struct LanguageRegistry {
+    languages: Vec<Language>,
+    filenames: Vec<(Regex, String)>,
+    first_line: Vec<(Regex, String)>,
+}
+
+impl LanguageRegistry {
+    fn new(languages: Vec<Language>) -> Self {
+        let (mut filenames, mut first_line) = (Vec::new(), Vec::new());
+        for lang in &languages {
+            for pattern in &lang.filenames {
+                match editorconfig::glob(pattern, true) {
+                    Some(re) => filenames.push((re, lang.id.clone())),
+                    None => log::warn!("Invalid file name pattern for {}: {}", lang.id, pattern),
+                }
+            }
+            for pattern in &lang.first_line {
+                match Regex::new(pattern) {
+                    Ok(re) => first_line.push((re, lang.id.clone())),
+                    Err(e) => log::warn!("Invalid first-line pattern for {}: {}", lang.id, e),
+                }
+            }
+        }
+        LanguageRegistry { languages, filenames, first_line }
+    }
+
+    pub fn languages(&self) -> &[Language] {
+        &self.languages
+    }
+
+    /// The language for a file, from the first of: the user's
+    /// `files.associations`, a modeline, a file name pattern, the
+    /// extension, the shebang's interpreter, a first-line pattern, and
+    /// a guess from the content. Anything else is plain text.
+    pub fn detect<'a>(&'a self, path: &str, content: Option<&str>, associations: &'a HashMap<String, String>) -> &'a str {
+        let path = path.replace('\\', "/");
+        let name = path.rsplit('/').next().unwrap_or(&path);
+        if let Some(id) = associated(&path, associations) {
+            return self.resolve(id).unwrap_or(id);
+        }
+        let content = content.unwrap_or("");
+        if let Some(id) = modeline(content).and_then(|mode| self.resolve(&mode)) {
+            return id;
+        }
+        if let Some((_, id)) = self.filenames.iter().find(|(re, _)| re.is_match(name)) {
+            return id;
+        }
+        if let Some((_, ext)) = name.rsplit_once('.') {
+            if let Some(lang) = self.languages.iter().find(|l| l.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext))) {
+                return &lang.id;
+            }
+        }
+        let first_line = content.lines().next().unwrap_or("");
+        if let Some(id) = interpreter(first_line).and_then(|name| self.resolve(&name)) {
+            return id;
+        }
+        if let Some((_, id)) = self.first_line.iter().find(|(re, _)| re.is_match(first_line)) {
+            return id;
+        }
+        guess(content).unwrap_or(PLAIN_TEXT)
+    }
+
+    /// The id of the language going by `name`, its id or one of its aliases.
+    fn resolve(&self, name: &str) -> Option<&str> {
+        let name = name.trim();
+        self.languages
+            .iter()
+            .find(|l| l.id.eq_ignore_ascii_case(name) || l.name.eq_ignore_ascii_case(name) || l.aliases.iter().any(|a| a.eq_ignore_ascii_case(name)))
+            .map(|l| l.id.as_str())
+    }
+}
+
+/// The language `files.associations` gives the path. Globs without a slash
+/// match the file name; when several match, the one naming a path wins,
+/// then the longer glob, so that the answer doesn't depend on map order.
+fn associated<'a>(path: &str, associations: &'a HashMap<String, String>) -> Option<&'a str> {
+    let mut matching: Vec<(&String, &String)> = associations
+        .iter()
+        .filter(|(glob, _)| editorconfig::glob(glob, false).is_some_and(|re| re.is_match(path)))
+        .collect();
+    matching.sort_by(|(a, _), (b, _)| (b.contains('/'), b.len()).cmp(&(a.contains('/'), a.len())).then(a.cmp(b)));
+    matching.first().map(|(_, id)| id.as_str())
+}
+
+/// The interpreter a `#!` line runs, without its directory or version, so
+/// `#!/usr/bin/env -S python3.12 -u` gives `python`.
+fn interpreter(first_line: &str) -> Option<String> {
+    let mut words = first_line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|w| !w.starts_with('-') && !w.contains('='))?;
+    }
+    let program = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    (!program.is_empty()).then(|| program.to_string())
+}
+
+/// The mode an Emacs `-*- mode: python -*-` or Vim `vim: set ft=python:`
+/// modeline names, in the first or last few lines.
+fn modeline(content: &str) -> Option<String> {
+    let head = content.lines().take(MODELINE_LINES);
+    let tail = content.lines().rev().take(MODELINE_LINES);
+    head.chain(tail).find_map(|line| emacs_mode(line).or_else(|| vim_filetype(line)))
+}
+
+fn emacs_mode(line: &str) -> Option<String> {
+    let (_, rest) = line.split_once("-*-")?;
+    let (vars, _) = rest.split_once("-*-")?;
+    let mode = if vars.contains(':') {
+        vars.split(';').find_map(|var| {
+            let (key, value) = var.split_once(':')?;
+            key.trim().eq_ignore_ascii_case("mode").then_some(value)
+        })?
+    } else {
+        vars
+    };
+    let mode = mode.trim();
+    let mode = mode.strip_suffix("-mode").unwrap_or(mode);
+    (!mode.is_empty()).then(|| mode.to_string())
+}
+
+fn vim_filetype(line: &str) -> Option<String> {
+    static MODELINE: OnceLock<Regex> = OnceLock::new();
+    let re = MODELINE.get_or_init(|| Regex::new(r"(?:^|\s)(?:vi|vim|ex)(?:[<=>]?\d+)?:\s*(.*)").unwrap());
+    let options = re.captures(line)?.get(1)?.as_str();
+    // The `set` form ends at the next colon, the other runs to the end of the line
+    let options = match options.strip_prefix("set ").or_else(|| options.strip_prefix("se ")) {
+        Some(rest) => rest.split(':').next().unwrap_or(rest),
+        None => options,
+    };
+    options.split(|c: char| c.is_whitespace() || c == ':').find_map(|option| {
+        let (key, value) = option.split_once('=')?;
+        (matches!(key, "ft" | "filetype" | "syn" | "syntax") && !value.is_empty()).then(|| value.to_string())
+    })
+}
+
+/// A last guess from how the text starts, for files nothing else placed.
+fn guess(content: &str) -> Option<&'static str> {
+    let mut end = content.len().min(SNIFF_BYTES);
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    let head = content[..end].trim_start_matches('\u{feff}').trim_start();
+    let first = head.lines().next().unwrap_or("").trim_end();
+    let after = |open: char| head[open.len_utf8()..].trim_start().chars().next();
+    if head.starts_with('{') && matches!(after('{'), Some('"' | '}')) {
+        return Some("json");
+    }
+    if head.starts_with('[') {
+        if first.len() > 2 && first.ends_with(']') && first[1..first.len() - 1].chars().all(|c| c.is_alphanumeric() || " ._-\"".contains(c)) && !first.starts_with("[\"") {
+            return Some("ini");
+        }
+        if matches!(after('['), Some('{' | '[' | '"' | ']' | '-' | '0'..='9')) {
+            return Some("json");
+        }
+    }
+    if first == "---" {
+        return Some("yaml");
+    }
+    if head.starts_with("<!--") || (head.starts_with('<') && after('<').is_some_and(|c| c.is_ascii_alphabetic())) {
+        return Some("xml");
+    }
+    None
+}
+
+/// Built-in languages, with user entries from the config dir added on top.
+/// A user entry with the same id as a built-in one replaces it.
+pub fn load(app: &AppHandle) -> LanguageRegistry {
+    let mut languages: Vec<Language> = serde_json::from_str(BUILTIN).expect("built-in languages.json is valid");
+    let user = paths::config_dir(app)
+        .ok()
+        .and_then(|dir| std::fs::read_to_string(dir.join(FILE_NAME)).ok());
+    if let Some(text) = user {
+        match serde_json::from_str::<Vec<Language>>(&text) {
+            Ok(extra) => {
+                for lang in extra {
+                    match languages.iter_mut().find(|l| l.id == lang.id) {
+                        Some(existing) => *existing = lang,
+                        None => languages.push(lang),
+                    }
+                }
+            }
+            Err(e) => log::warn!("Ignoring {}: {}", FILE_NAME, e),
+        }
+    }
+    LanguageRegistry::new(languages)
+}
+
+/// The language `read_document` would give the file, with `content` as
+/// much of the text as is at hand.
+pub fn detect_for(app: &AppHandle, path: &str, content: &str) -> String {
+    let associations = app.state::<Mutex<Settings>>().lock().unwrap().files.associations.clone();
+    app.state::<LanguageRegistry>().detect(path, Some(content), &associations).to_string()
+}
+
+#[tauri::command]
+pub fn detect_language(app: AppHandle, path: String, content: Option<String>) -> String {
+    detect_for(&app, &path, content.as_deref().unwrap_or(""))
+}
+
+#[tauri::command]
+pub fn list_languages(registry: tauri::State<'_, LanguageRegistry>) -> Vec<Language> {
+    registry.languages().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builtin() -> LanguageRegistry {
+        LanguageRegistry::new(serde_json::from_str(BUILTIN).unwrap())
+    }
+
+    fn detect(path: &str, content: &str) -> String {
+        builtin().detect(path, Some(content), &HashMap::new()).to_string()
+    }
+
+    #[test]
+    fn goes_by_extension() {
+        assert_eq!(detect("/src/main.RS", ""), "rust");
+        assert_eq!(detect("C:\\notes\\todo.md", ""), "markdown");
+        assert_eq!(detect("/tmp/unknown.xyz", ""), PLAIN_TEXT);
+    }
+
+    #[test]
+    fn matches_file_name_patterns() {
+        assert_eq!(detect("/app/Dockerfile", ""), "dockerfile");
+        assert_eq!(detect("/app/dockerfile.dev", ""), "dockerfile");
+        assert_eq!(detect("/app/.env.production", ""), "ini");
+        assert_eq!(detect("/app/.env", ""), "ini");
+        assert_eq!(detect("/app/Gemfile", ""), "ruby");
+    }
+
+    #[test]
+    fn maps_shebang_interpreters() {
+        assert_eq!(detect("/bin/tool", "#!/usr/bin/env python3\nprint()"), "python");
+        assert_eq!(detect("/bin/tool", "#!/usr/bin/python3.12 -u"), "python");
+        assert_eq!(detect("/bin/tool", "#!/usr/bin/env -S deno run --allow-net"), "typescript");
+        assert_eq!(detect("/bin/tool", "#!/usr/bin/env LC_ALL=C node"), "javascript");
+        assert_eq!(detect("/bin/tool", "#!/bin/bash\nset -e"), "shell");
+        assert_eq!(detect("/bin/tool", "#!/usr/bin/env perl"), PLAIN_TEXT);
+        // The extension says more than the shebang
+        assert_eq!(detect("/bin/tool.rb", "#!/usr/bin/env python3"), "ruby");
+    }
+
+    #[test]
+    fn reads_emacs_modelines() {
+        assert_eq!(detect("/x/build", "# -*- mode: python; coding: utf-8 -*-\n"), "python");
+        assert_eq!(detect("/x/build", "/* -*- C++ -*- */\n"), "cpp");
+        assert_eq!(detect("/x/build", "# -*- mode: shell-script -*-"), "shell");
+        assert_eq!(detect("/x/build", "# -*- coding: utf-8 -*-"), PLAIN_TEXT);
+    }
+
+    #[test]
+    fn reads_vim_modelines_at_either_end() {
+        assert_eq!(detect("/x/notes.txt", "# vim: set ft=yaml ts=2 :\na: 1"), "yaml");
+        let tail = format!("{}// vim: filetype=rust\n", "line\n".repeat(50));
+        assert_eq!(detect("/x/notes.txt", &tail), "rust");
+        // Only the first and last few lines count
+        let middle = format!("{}vim: ft=rust\n{}", "line\n".repeat(10), "line\n".repeat(10));
+        assert_eq!(detect("/x/notes.txt", &middle), "plaintext");
+        // Not a modeline without a space before it
+        assert_eq!(detect("/x/notes", "see gvim:ft=python"), PLAIN_TEXT);
+    }
+
+    #[test]
+    fn applies_first_line_patterns() {
+        assert_eq!(detect("/x/page", "<?php echo 1;"), "php");
+        assert_eq!(detect("/x/page", "<!DOCTYPE html>\n<html>"), "html");
+        assert_eq!(detect("/x/feed", "<?xml version=\"1.0\"?>"), "xml");
+    }
+
+    #[test]
+    fn guesses_from_the_content_last() {
+        assert_eq!(detect("/x/data", "\u{feff}{\n  \"name\": 1\n}"), "json");
+        assert_eq!(detect("/x/data", "[{\"a\": 1}]"), "json");
+        assert_eq!(detect("/x/config", "[core]\nbare = false"), "ini");
+        assert_eq!(detect("/x/list", "---\nname: x"), "yaml");
+        assert_eq!(detect("/x/doc", "<root><a/></root>"), "xml");
+        assert_eq!(detect("/x/notes", "just some words"), PLAIN_TEXT);
+    }
+
+    #[test]
+    fn associations_come_first() {
+        let associations = HashMap::from([
+            ("*.tpl".to_string(), "html".to_string()),
+            ("Dockerfile*".to_string(), "shell".to_string()),
+            ("**/config/*.tpl".to_string(), "yaml".to_string()),
+            ("*.r".to_string(), "r".to_string()),
+        ]);
+        let registry = builtin();
+        let detect = |path: &str, content: &str| registry.detect(path, Some(content), &associations).to_string();
+        assert_eq!(detect("/site/page.tpl", "-*- mode: python -*-"), "html");
+        assert_eq!(detect("/app/Dockerfile", ""), "shell");
+        // A glob naming a path wins over one for the file name
+        assert_eq!(detect("/site/config/app.tpl", ""), "yaml");
+        // Ids the registry doesn't know are passed on for the editor
+        assert_eq!(detect("/stats/model.r", ""), "r");
+        // Case matters as in VS Code
+        assert_eq!(detect("/app/dockerfile", ""), "dockerfile");
+    }
+
+    #[test]
+    fn user_languages_resolve_by_alias() {
+        let mut languages: Vec<Language> = serde_json::from_str(BUILTIN).unwrap();
+        languages.push(serde_json::from_value(serde_json::json!({ "id": "perl", "name": "Perl", "aliases": ["cperl"] })).unwrap());
+        let registry = LanguageRegistry::new(languages);
+        assert_eq!(registry.detect("/bin/tool", Some("#!/usr/bin/perl -w"), &HashMap::new()), "perl");
+        assert_eq!(registry.detect("/bin/tool", Some("# -*- cperl -*-"), &HashMap::new()), "perl");
+    }
+}
@@ -0,0 +1,264 @@
+//! Read-only views of files too big to hand to the editor whole. Opening one
+//! only reads enough to pick the encoding; the lines are then found a chunk
+//! at a time in the background, and any query that gets ahead of that scans
+//! as far as it needs first.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::SystemTime;
+
+use serde::Serialize;
+use tauri::Manager;
+
+use crate::document::{self, DocumentRegistry};
+use crate::paths;
+use crate::workers::{Priority, WorkerPool};
+
+/// Bytes scanned for line breaks at a time.
+const CHUNK: u64 = 4 * 1024 * 1024;
+/// Bytes read to pick the encoding.
+const SAMPLE: u64 = 64 * 1024;
+/// The error once the file has changed on disk since it was opened.
+pub const STALE: &str = "stale_handle";
+
+struct LargeFile {
+    path: PathBuf,
+    encoding: String,
+    /// The bytes a line break is written as
+    line_break: &'static [u8],
+    /// Where each line found so far starts, in bytes
+    starts: Vec<u64>,
+    /// Bytes scanned for line breaks so far
+    indexed: u64,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+#[derive(Default)]
+pub struct LargeFiles(Mutex<HashMap<u32, Arc<Mutex<LargeFile>>>>);
+
+static NEXT_HANDLE: AtomicU32 = AtomicU32::new(1);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LargeFileInfo {
+    handle: u32,
+    encoding: String,
+    size: u64,
+}
+
+fn stamp(path: &Path) -> Result<(u64, Option<SystemTime>), String> {
+    let metadata = std::fs::metadata(path).map_err(|e| e.to_string())?;
+    Ok((metadata.len(), metadata.modified().ok()))
+}
+
+impl LargeFile {
+    fn open(path: PathBuf, known_encoding: Option<String>) -> Result<Self, String> {
+        let (size, modified) = stamp(&path)?;
+        let mut sample = Vec::new();
+        File::open(&path).and_then(|file| file.take(SAMPLE).read_to_end(&mut sample)).map_err(|e| e.to_string())?;
+        let (detected, _) = document::detect_start(&sample, size <= SAMPLE);
+        let encoding = known_encoding.unwrap_or_else(|| detected.to_string());
+        let (line_break, bom): (&[u8], &[u8]) = match encoding.to_ascii_uppercase().as_str() {
+            "UTF-16LE" => (b"\n\0", b"\xFF\xFE"),
+            "UTF-16BE" => (b"\0\n", b"\xFE\xFF"),
+            _ => (b"\n", b"\xEF\xBB\xBF"),
+        };
+        let first = if sample.starts_with(bom) { bom.len() as u64 } else { 0 };
+        Ok(LargeFile { path, encoding, line_break, starts: vec![first], indexed: first, size, modified })
+    }
+
+    fn done(&self) -> bool {
+        self.indexed >= self.size
+    }
+
+    /// The file, checked to be the one the lines were found in.
+    fn file(&self) -> Result<File, String> {
+        if stamp(&self.path)? != (self.size, self.modified) {
+            return Err(STALE.into());
+        }
+        File::open(&self.path).map_err(|e| e.to_string())
+    }
+
+    /// Scans the next chunk for line breaks.
+    fn advance(&mut self, file: &mut File) -> Result<(), String> {
+        let unit = self.line_break.len() as u64;
+        let mut chunk = Vec::new();
+        file.seek(SeekFrom::Start(self.indexed)).map_err(|e| e.to_string())?;
+        file.take(CHUNK).read_to_end(&mut chunk).map_err(|e| e.to_string())?;
+        // A file that shrank mid-scan; the next query reports it stale
+        if chunk.is_empty() {
+            self.indexed = self.size;
+            return Ok(());
+        }
+        for (i, unit_bytes) in chunk.chunks_exact(unit as usize).enumerate() {
+            if unit_bytes == self.line_break {
+                self.starts.push(self.indexed + (i as u64 + 1) * unit);
+            }
+        }
+        // An odd byte at the end of UTF-16 is left for the decoder to flag
+        self.indexed = (self.indexed + chunk.len() as u64).min(self.size);
+        Ok(())
+    }
+
+    /// Scans until the lines starting at or before `offset` are all found.
+    fn index_to(&mut self, offset: u64) -> Result<File, String> {
+        let mut file = self.file()?;
+        while !self.done() && self.indexed <= offset {
+            self.advance(&mut file)?;
+        }
+        Ok(file)
+    }
+
+    fn line_count(&mut self) -> Result<usize, String> {
+        self.index_to(u64::MAX)?;
+        Ok(self.starts.len())
+    }
+
+    /// Lines `first..first + count`, 0-based, without their line breaks.
+    fn lines(&mut self, first: usize, count: usize) -> Result<Vec<String>, String> {
+        let mut file = self.file()?;
+        while !self.done() && self.starts.len() <= first + count {
+            self.advance(&mut file)?;
+        }
+        let Some(&start) = self.starts.get(first) else { return Ok(Vec::new()) };
+        let end = self.starts.get(first + count).copied().unwrap_or(self.size);
+        let mut bytes = Vec::new();
+        file.seek(SeekFrom::Start(start)).map_err(|e| e.to_string())?;
+        file.take(end - start).read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+        let (text, _) = document::decode_part(&bytes, &self.encoding)?;
+        let mut lines: Vec<String> = text.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line).to_string()).collect();
+        // The break after the last line asked for leaves an empty piece
+        lines.truncate(count.min(self.starts.len() - first));
+        Ok(lines)
+    }
+
+    /// The 0-based line holding the byte at `offset`.
+    fn line_of_offset(&mut self, offset: u64) -> Result<usize, String> {
+        if offset > self.size {
+            return Err(format!("Offset {} is past the end of the file", offset));
+        }
+        self.index_to(offset)?;
+        Ok(self.starts.partition_point(|&start| start <= offset).saturating_sub(1))
+    }
+}
+
+/// Finds the rest of the lines while nothing else needs the file.
+fn index_in_background(pool: &WorkerPool, view: Weak<Mutex<LargeFile>>) {
+    let Some(mut file) = view.upgrade().and_then(|view| view.lock().unwrap().file().ok()) else { return };
+    pool.spawn_steps(Priority::Bulk, move || {
+        let Some(view) = view.upgrade() else { return false };
+        let mut view = view.lock().unwrap();
+        !view.done() && view.advance(&mut file).is_ok()
+    });
+}
+
+fn view(app: &tauri::AppHandle, handle: u32) -> Result<Arc<Mutex<LargeFile>>, String> {
+    app.state::<LargeFiles>().0.lock().unwrap().get(&handle).cloned().ok_or_else(|| format!("No open file with handle {}", handle))
+}
+
+async fn with_view<T: Send + 'static>(app: tauri::AppHandle, handle: u32, f: impl FnOnce(&mut LargeFile) -> Result<T, String> + Send + 'static) -> Result<T, String> {
+    let view = view(&app, handle)?;
+    app.state::<WorkerPool>().run(Priority::Interactive, move || f(&mut view.lock().unwrap())).await?
+}
+
+/// Lines `first..first + count` of an open file, for commands that read
+/// one without loading it.
+pub fn lines(app: &tauri::AppHandle, handle: u32, first: usize, count: usize) -> Result<Vec<String>, String> {
+    view(app, handle)?.lock().unwrap().lines(first, count)
+}
+
+/// How many files are open and their size together.
+pub fn usage(app: &tauri::AppHandle) -> (usize, u64) {
+    let files = app.state::<LargeFiles>();
+    let files = files.0.lock().unwrap();
+    (files.len(), files.values().map(|view| view.lock().unwrap().size).sum())
+}
+
+/// The size and encoding of an open file.
+pub fn describe(app: &tauri::AppHandle, handle: u32) -> Result<(u64, String), String> {
+    let view = view(app, handle)?;
+    let view = view.lock().unwrap();
+    Ok((view.size, view.encoding.clone()))
+}
+
+#[tauri::command]
+pub async fn open_large_file(app: tauri::AppHandle, path: String) -> Result<LargeFileInfo, String> {
+    let path = paths::decode(&path);
+    let known = document::known_encoding(&app.state::<DocumentRegistry>(), &path);
+    let pool = app.state::<WorkerPool>();
+    let shown = path.display().to_string();
+    let view = pool.run(Priority::Interactive, move || LargeFile::open(path, known)).await??;
+    log::info!("Opened {} as a large file: {} bytes as {}", shown, view.size, view.encoding);
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+    let info = LargeFileInfo { handle, encoding: view.encoding.clone(), size: view.size };
+    let view = Arc::new(Mutex::new(view));
+    index_in_background(&pool, Arc::downgrade(&view));
+    app.state::<LargeFiles>().0.lock().unwrap().insert(handle, view);
+    Ok(info)
+}
+
+#[tauri::command]
+pub fn close_large_file(files: tauri::State<'_, LargeFiles>, handle: u32) {
+    files.0.lock().unwrap().remove(&handle);
+}
+
+/// Up to `count` lines from `first_line`, 0-based, as the editor shows them.
+#[tauri::command]
+pub async fn get_line_range(app: tauri::AppHandle, handle: u32, first_line: usize, count: usize) -> Result<Vec<String>, String> {
+    with_view(app, handle, move |view| view.lines(first_line, count)).await
+}
+
+/// Waits for the whole file to be scanned the first time; it's a lookup after.
+#[tauri::command]
+pub async fn get_line_count(app: tauri::AppHandle, handle: u32) -> Result<usize, String> {
+    with_view(app, handle, LargeFile::line_count).await
+}
+
+/// For jumping to a search match given as a byte offset into the file.
+#[tauri::command]
+pub async fn find_line_of_offset(app: tauri::AppHandle, handle: u32, byte_offset: u64) -> Result<usize, String> {
+    with_view(app, handle, move |view| view.line_of_offset(byte_offset)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("skriv-large-{}-{}", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_lines_by_number() {
+        let path = write("lines", b"\xEF\xBB\xBFone\r\ntw\xC3\xB6\nthree\n\nfive");
+        let mut view = LargeFile::open(path.clone(), None).unwrap();
+        assert_eq!(view.lines(1, 2).unwrap(), ["twö", "three"]);
+        assert_eq!(view.lines(3, 10).unwrap(), ["", "five"]);
+        assert_eq!(view.lines(0, 1).unwrap(), ["one"]);
+        assert!(view.lines(5, 1).unwrap().is_empty());
+        assert_eq!(view.line_count().unwrap(), 5);
+        assert_eq!(view.line_of_offset(8).unwrap(), 1);
+        assert_eq!(view.line_of_offset(9).unwrap(), 1);
+        assert_eq!(view.line_of_offset(13).unwrap(), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn indexes_utf16_and_notices_changes() {
+        let text: Vec<u8> = "\u{FEFF}a\nb\u{0A0A}\nc".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let path = write("utf16", &text);
+        let mut view = LargeFile::open(path.clone(), None).unwrap();
+        assert_eq!(view.encoding, "UTF-16LE");
+        assert_eq!(view.lines(0, 3).unwrap(), ["a", "b\u{0A0A}", "c"]);
+        std::fs::write(&path, b"shorter").unwrap();
+        assert_eq!(view.lines(0, 1).unwrap_err(), STALE);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,17 +1,93 @@
 use std::sync::Mutex;
-use tauri::menu::{Menu, MenuItem, PredefinedMenuItem, Submenu};
-use tauri::{Emitter, Manager};
+use tauri::Manager;
+
+mod bookmarks;
+mod cli;
+mod clipboard;
+mod color_theme;
+mod config_watcher;
+mod control;
+mod crash;
+mod deep_link;
+mod diagnostics;
+mod diagnostics_bundle;
+mod default_apps;
+mod directory;
+mod document;
+mod document_watcher;
+mod download;
+mod editorconfig;
+mod encryption;
+mod file_events;
+mod file_lock;
+mod find;
+mod folder_compare;
+mod format;
+mod git;
+mod highlight;
+mod i18n;
+mod keybindings;
+mod languages;
+mod large_file;
+mod lines;
+mod lint;
+mod logging;
+mod lsp;
+mod markdown;
+mod menu;
+mod menu_state;
+mod network;
+mod notifications;
+mod open_with;
+mod operations;
+mod paths;
+mod plugins;
+mod power;
+mod print;
+mod recent_workspaces;
+mod recents;
+mod pty;
+mod quick_note;
+mod search;
+mod services;
+mod settings;
+mod settings_validation;
+mod secrets;
+mod sftp;
+mod snippets;
+mod share;
+mod share_online;
+mod spell;
+mod stats;
+mod structured;
+mod tabular;
+mod tasks;
+mod templates;
+mod terminal;
+mod theme;
+mod touchbar;
+mod transform;
+mod trash;
+mod updates;
+mod url_document;
+mod vscode;
+mod window;
+mod workers;
+mod words;
+mod workspace;
+mod workspace_trust;
 
 #[tauri::command]
 fn install_cli() -> Result<String, String> {
     #[cfg(target_os = "macos")]
     {
         let path = "/usr/local/bin/skriv";
-        let script_content = "#!/bin/sh\\n/Applications/skriv.app/Contents/MacOS/app \\\"$@\\\" &\\n";
+        // `--ctl` prints an answer and exits with a status, so it isn't sent to the background
+        let script_content = "#!/bin/sh\\nif [ \\\"$1\\\" = --ctl ]; then exec /Applications/skriv.app/Contents/MacOS/app \\\"$@\\\"; fi\\n/Applications/skriv.app/Contents/MacOS/app \\\"$@\\\" &\\n";
 
         // Check if already installed correctly
         if let Ok(contents) = std::fs::read_to_string(path) {
-            if contents == "#!/bin/sh\n/Applications/skriv.app/Contents/MacOS/app \"$@\" &\n" {
+            if contents == "#!/bin/sh\nif [ \"$1\" = --ctl ]; then exec /Applications/skriv.app/Contents/MacOS/app \"$@\"; fi\n/Applications/skriv.app/Contents/MacOS/app \"$@\" &\n" {
                 return Ok("already_installed".into());
             }
         }
@@ -42,25 +118,36 @@ fn install_cli() -> Result<String, String> {
     }
 }
 
-struct CliArgs {
-    args: Vec<String>,
-    cwd: String,
-}
-
-#[tauri::command]
-fn get_cli_args(state: tauri::State<'_, Mutex<CliArgs>>) -> (Vec<String>, String) {
-    let cli = state.lock().unwrap();
-    (cli.args.clone(), cli.cwd.clone())
-}
-
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    let mut context = tauri::generate_context!();
+    paths::isolate_instance(&mut context);
+    let args = paths::strip_flags(paths::args());
+    if args.get(1).is_some_and(|arg| arg == control::FLAG) {
+        std::process::exit(control::client(&context.config().identifier, &args[2..]));
+    }
+    let (cli, warnings) = cli::parse_leniently(&args, &std::env::current_dir().unwrap_or_default()).unwrap_or_else(|e| cli::exit(e));
+    if !warnings.is_empty() {
+        cli::attach_console();
+        for warning in &warnings {
+            eprintln!("skriv: {}", warning);
+        }
+    }
+    if cli.wait {
+        std::process::exit(cli::wait(&cli));
+    }
     tauri::Builder::default()
         .plugin(tauri_plugin_single_instance::init(|app, args, cwd| {
-            let _ = app.emit("open-files", (args, cwd));
-            if let Some(w) = app.get_webview_window("main") {
-                let _ = w.unminimize();
-                let _ = w.set_focus();
+            let args = paths::strip_flags(args);
+            deep_link::handle_args(app, &args);
+            match cli::parse_leniently(&args, &paths::decode(&cwd)) {
+                Ok((cli, warnings)) => {
+                    for warning in warnings {
+                        log::warn!("Command line of a second instance: {}", warning);
+                    }
+                    open_with::open_args(app, cli);
+                }
+                Err(e) => log::warn!("Ignored the command line of a second instance: {}", e),
             }
         }))
         .plugin(tauri_plugin_fs::init())
@@ -68,97 +155,279 @@ pub fn run() {
         .plugin(tauri_plugin_updater::Builder::new().build())
         .plugin(tauri_plugin_process::init())
         .plugin(tauri_plugin_opener::init())
-        .manage(Mutex::new(CliArgs {
-            args: std::env::args().collect(),
-            cwd: std::env::current_dir()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .into_owned(),
-        }))
-        .invoke_handler(tauri::generate_handler![get_cli_args, install_cli])
-        .setup(|app| {
-            let app_menu = Submenu::with_items(app, "skriv", true, &[
-                &PredefinedMenuItem::about(app, Some("About skriv"), None)?,
-                &PredefinedMenuItem::separator(app)?,
-                &PredefinedMenuItem::services(app, None)?,
-                &PredefinedMenuItem::separator(app)?,
-                &PredefinedMenuItem::hide(app, None)?,
-                &PredefinedMenuItem::hide_others(app, None)?,
-                &PredefinedMenuItem::show_all(app, None)?,
-                &PredefinedMenuItem::separator(app)?,
-                &PredefinedMenuItem::quit(app, None)?,
-            ])?;
-            let file_menu = Submenu::with_items(app, "File", true, &[
-                &MenuItem::with_id(app, "new_tab", "New Tab", true, Some("CmdOrCtrl+N"))?,
-                &MenuItem::with_id(app, "open_file", "Open...", true, Some("CmdOrCtrl+O"))?,
-                &PredefinedMenuItem::separator(app)?,
-                &MenuItem::with_id(app, "save_file", "Save", true, Some("CmdOrCtrl+S"))?,
-                &MenuItem::with_id(app, "save_file_as", "Save As...", true, Some("CmdOrCtrl+Shift+S"))?,
-                &PredefinedMenuItem::separator(app)?,
-                &PredefinedMenuItem::close_window(app, None)?,
-            ])?;
-            let edit_menu = Submenu::with_items(app, "Edit", true, &[
-                &PredefinedMenuItem::undo(app, None)?,
-                &PredefinedMenuItem::redo(app, None)?,
-                &PredefinedMenuItem::separator(app)?,
-                &PredefinedMenuItem::cut(app, None)?,
-                &PredefinedMenuItem::copy(app, None)?,
-                &PredefinedMenuItem::paste(app, None)?,
-                &PredefinedMenuItem::select_all(app, None)?,
-                &PredefinedMenuItem::separator(app)?,
-                &MenuItem::with_id(app, "toggle_comment", "Toggle Comment", true, Some("CmdOrCtrl+Shift+C"))?,
-                &MenuItem::with_id(app, "format_document", "Format Document", true, Some("CmdOrCtrl+Shift+F"))?,
-                &MenuItem::with_id(app, "column_selection", "Column Selection", true, None::<&str>)?,
-            ])?;
-            let command_palette = MenuItem::with_id(app, "command_palette", "Command Palette", true, Some("Super+Shift+P"))?;
-            let word_wrap = MenuItem::with_id(app, "word_wrap", "Word Wrap", true, Some("Alt+Z"))?;
-            let toggle_theme = MenuItem::with_id(app, "toggle_theme", "Toggle Theme", true, None::<&str>)?;
-            let split_view = MenuItem::with_id(app, "split_view", "Split View", true, Some("CmdOrCtrl+\\"))?;
-            let view_menu = Submenu::with_items(app, "View", true, &[
-                &command_palette,
-                &PredefinedMenuItem::separator(app)?,
-                &split_view,
-                &PredefinedMenuItem::separator(app)?,
-                &word_wrap,
-                &toggle_theme,
-                &PredefinedMenuItem::separator(app)?,
-                &PredefinedMenuItem::fullscreen(app, None)?,
-            ])?;
-            let window_menu = Submenu::with_items(app, "Window", true, &[
-                &PredefinedMenuItem::minimize(app, None)?,
-                &PredefinedMenuItem::maximize(app, None)?,
-            ])?;
-            let help_menu = Submenu::with_items(app, "Help", true, &[])?;
-            let menu = Menu::with_items(app, &[&app_menu, &file_menu, &edit_menu, &view_menu, &window_menu, &help_menu])?;
-            app.set_menu(menu)?;
-
-            app.on_menu_event(|app, event| {
-                let id = event.id();
-                match id.as_ref() {
-                    "command_palette" => { let _ = app.emit("menu-command-palette", ()); }
-                    "word_wrap" => { let _ = app.emit("menu-word-wrap", ()); }
-                    "toggle_comment" => { let _ = app.emit("menu-toggle-comment", ()); }
-                    "new_tab" => { let _ = app.emit("menu-new-tab", ()); }
-                    "open_file" => { let _ = app.emit("menu-open-file", ()); }
-                    "save_file" => { let _ = app.emit("menu-save-file", ()); }
-                    "save_file_as" => { let _ = app.emit("menu-save-file-as", ()); }
-                    "format_document" => { let _ = app.emit("menu-format-document", ()); }
-                    "column_selection" => { let _ = app.emit("menu-column-selection", ()); }
-                    "toggle_theme" => { let _ = app.emit("menu-toggle-theme", ()); }
-                    "split_view" => { let _ = app.emit("menu-split-view", ()); }
-                    _ => {}
-                }
-            });
-
-            if cfg!(debug_assertions) {
-                app.handle().plugin(
-                    tauri_plugin_log::Builder::default()
-                        .level(log::LevelFilter::Info)
-                        .build(),
-                )?;
+        .plugin(tauri_plugin_global_shortcut::Builder::new().build())
+        .manage(open_with::PendingRequests::default())
+        .invoke_handler(tauri::generate_handler![
+            paths::get_data_dir,
+            cli::get_cli_args,
+            install_cli,
+            keybindings::reload_keybindings,
+            keybindings::get_keybindings,
+            keybindings::reset_keybindings,
+            theme::set_theme_mode,
+            theme::get_theme,
+            theme::set_color_theme,
+            color_theme::list_themes,
+            color_theme::read_theme,
+            theme::get_system_appearance,
+            i18n::set_app_locale,
+            languages::detect_language,
+            languages::list_languages,
+            menu_state::update_menu_state,
+            find::set_find_pasteboard,
+            lines::line_ops,
+            transform::text_transform,
+            structured::structured_format,
+            structured::structured_validate,
+            structured::forget_structured_diagnostics,
+            tabular::csv_inspect,
+            tabular::csv_get_rows,
+            tabular::csv_close,
+            document::read_document,
+            document::change_document_passphrase,
+            document::forget_document_passphrase,
+            encryption::provide_passphrase,
+            document::read_document_with_encoding,
+            document::read_document_lines,
+            document::read_document_wrapped,
+            large_file::open_large_file,
+            large_file::close_large_file,
+            large_file::get_line_range,
+            large_file::get_line_count,
+            large_file::find_line_of_offset,
+            document::save_document,
+            document::set_document_dirty,
+            document::watch_document,
+            document_watcher::unwatch_document,
+            file_lock::unlock_document,
+            trash::trash_paths,
+            trash::restore_trashed,
+            search::find_in_files,
+            search::export_search_results,
+            search::copy_search_results,
+            document::transform_document,
+            editorconfig::resolve_editorconfig,
+            window::new_window,
+            print::print_document,
+            print::export_pdf,
+            markdown::export_markdown,
+            share::share_document,
+            share_online::create_share,
+            share_online::delete_share,
+            share_online::set_share_token,
+            services::notes_ready,
+            open_with::frontend_ready,
+            git::git_file_diff,
+            git::git_blame,
+            git::git_repo_status,
+            git::git_show_head_version,
+            git::diff_strings,
+            git::diff_files,
+            folder_compare::compare_directories,
+            git::compare_with_saved,
+            git::git_stage,
+            git::git_unstage,
+            git::git_discard_file,
+            git::git_apply_hunk,
+            git::git_commit,
+            git::discover_repository,
+            highlight::open_highlight_session,
+            highlight::apply_highlight_edit,
+            highlight::get_highlights,
+            highlight::get_folding_ranges,
+            highlight::close_highlight_session,
+            words::open_word_index,
+            words::update_word_index,
+            words::complete_word,
+            words::close_word_index,
+            tasks::run_task,
+            tasks::kill_task,
+            pty::create_pty,
+            pty::write_pty,
+            pty::resize_pty,
+            pty::kill_pty,
+            lsp::start_language_server,
+            lsp::send_lsp_message,
+            lsp::stop_language_server,
+            format::format_via_external,
+            format::formatter_for,
+            terminal::open_external_terminal,
+            lint::run_linter,
+            lint::linter_profiles,
+            notifications::notify,
+            quick_note::set_quick_note_hotkey,
+            quick_note::get_quick_note_hotkey,
+            quick_note::open_quick_note,
+            updates::check_for_update,
+            updates::download_update,
+            updates::cancel_update_download,
+            updates::install_and_restart,
+            updates::get_update_channel,
+            updates::set_update_channel,
+            updates::skip_version,
+            updates::snooze_updates,
+            updates::get_update_prompt_state,
+            updates::clear_update_prompt_state,
+            updates::set_install_updates_on_quit,
+            updates::session_flushed,
+            network::test_network,
+            url_document::open_url_document,
+            download::download_file,
+            operations::cancel_operation,
+            sftp::sftp_connect,
+            sftp::sftp_answer,
+            sftp::sftp_read,
+            sftp::sftp_write,
+            sftp::sftp_list,
+            settings::get_settings,
+            settings::update_settings,
+            settings::replay_settings_notices,
+            settings::validate_settings_text,
+            vscode::import_from_vscode,
+            vscode::apply_import,
+            snippets::get_snippets,
+            snippets::resolve_snippet_variables,
+            snippets::open_snippets_folder,
+            templates::list_templates,
+            templates::instantiate_template,
+            templates::open_templates_folder,
+            plugins::list_plugins,
+            plugins::run_plugin,
+            plugins::open_plugins_folder,
+            workspace::set_workspace,
+            workspace::get_effective_settings,
+            workspace_trust::get_workspace_trust,
+            workspace_trust::set_workspace_trust,
+            workspace::get_workspace_settings_problems,
+            clipboard::copy_to_clipboard,
+            clipboard::get_clipboard_paths,
+            document::set_executable,
+            document::remove_quarantine,
+            recent_workspaces::get_recent_workspaces,
+            recent_workspaces::pin_workspace,
+            recent_workspaces::remove_recent_workspace,
+            recent_workspaces::clear_recent_workspaces,
+            recent_workspaces::open_workspace,
+            bookmarks::add_bookmark,
+            bookmarks::list_bookmarks,
+            bookmarks::remove_bookmark,
+            bookmarks::open_bookmark,
+            recents::note_file_opened,
+            recents::clear_recent_documents,
+            recents::restores_windows,
+            power::begin_power_activity,
+            power::end_power_activity,
+            power::get_power_assertions,
+            quick_note::hide_quick_note,
+            default_apps::get_default_handler_status,
+            default_apps::register_as_default,
+            directory::read_directory_page,
+            directory::close_directory_listing,
+            spell::spellcheck,
+            spell::spelling_suggestions,
+            spell::add_to_user_dictionary,
+            spell::ignore_word,
+            stats::document_stats,
+            stats::forget_document_stats,
+            workers::get_worker_pool_stats,
+            diagnostics::get_runtime_diagnostics,
+            diagnostics::copy_diagnostics,
+            diagnostics_bundle::create_diagnostics_bundle,
+            logging::get_log_config,
+            logging::set_log_level,
+            logging::log_from_frontend,
+            logging::open_logs_folder,
+            logging::clear_logs,
+            touchbar::set_touchbar_context,
+            control::control_reply,
+        ])
+        .setup(move |app| {
+            // First, so that the rest of setup is logged
+            app.handle().plugin(logging::plugin(app.handle()))?;
+            crash::install(app.handle());
+            let (settings, notices) = settings::load(app.handle());
+            logging::apply(&settings.logging);
+            app.manage(workers::WorkerPool::new(workers::thread_count(&settings)));
+            app.manage(Mutex::new(settings));
+            app.manage(notices);
+            app.manage(logging::WebviewThrottle::default());
+            app.manage(languages::load(app.handle()));
+            app.manage(Mutex::new(i18n::load(app.handle())));
+            app.manage(Mutex::new(menu_state::MenuStates::default()));
+            app.manage(menu::MenuItems::default());
+            app.manage(window::WindowKinds::default());
+            window::set_kind(app.handle(), "main", window::WindowKind::Editor);
+            app.manage(document::DocumentRegistry::default());
+            app.manage(document_watcher::DocumentWatcher::default());
+            app.manage(file_lock::FileLocks::default());
+            app.manage(trash::Trash::default());
+            app.manage(search::Searches::default());
+            app.manage(encryption::Passphrases::default());
+            app.manage(directory::Listings::default());
+            app.manage(large_file::LargeFiles::default());
+            app.manage(editorconfig::EditorConfigs::default());
+            app.manage(services::PendingNotes::default());
+            app.manage(git::GitCache::default());
+            app.manage(highlight::Highlights::default());
+            app.manage(words::WordIndexes::default());
+            app.manage(tasks::Tasks::default());
+            app.manage(lint::Linters::default());
+            app.manage(control::Control::default());
+            app.manage(structured::Validations::default());
+            app.manage(tabular::CsvTables::default());
+            app.manage(notifications::Notifications::default());
+            app.manage(quick_note::QuickNote::default());
+            app.manage(power::Activities::default());
+            app.manage(updates::UpdateState::default());
+            app.manage(pty::Ptys::default());
+            app.manage(download::Downloads::default());
+            app.manage(operations::Operations::default());
+            app.manage(sftp::Connections::default());
+            app.manage(sftp::Prompts::default());
+            app.manage(lsp::LanguageServers::default());
+            app.manage(workspace::Workspaces::default());
+            app.manage(recent_workspaces::load(app.handle()));
+            app.manage(workspace_trust::load(app.handle()));
+            app.manage(bookmarks::load(app.handle()));
+            app.manage(vscode::PendingImports::default());
+            app.manage(snippets::SnippetWatcher::default());
+            snippets::watch(app.handle());
+            app.manage(templates::TemplateWatcher::default());
+            templates::watch(app.handle());
+            app.manage(plugins::Plugins::default());
+            plugins::load(app.handle());
+            plugins::watch(app.handle());
+            app.manage(Mutex::new(spell::load(app.handle())));
+            app.manage(stats::StatsRequests::default());
+            #[cfg(target_os = "macos")]
+            app.manage(share::ShareMenu::default());
+            let keys = keybindings::load(app.handle());
+            app.set_menu(menu::build(app.handle(), &keys)?)?;
+            app.manage(Mutex::new(keys));
+            quick_note::setup(app.handle());
+            app.manage(theme::LastAppearance::default());
+            theme::apply(app.handle(), theme::current_mode(app.handle()));
+            theme::watch(app.handle());
+            app.on_menu_event(menu::handle_event);
+            #[cfg(target_os = "macos")]
+            window::install_dock_menu(app.handle());
+            #[cfg(target_os = "macos")]
+            services::register(app.handle());
+            deep_link::register();
+            deep_link::handle_args(app.handle(), &args);
+            for warning in &warnings {
+                log::warn!("Command line: {}", warning);
+            }
+            app.manage(cli::LaunchArgs(cli.clone()));
+            open_with::open_args(app.handle(), cli);
+            if let Err(e) = config_watcher::start(app.handle()) {
+                log::warn!("Failed to watch config directory: {}", e);
             }
+            control::apply(app.handle());
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .on_window_event(window::handle_event)
+        .build(context)
+        .expect("error while building tauri application")
+        .run(window::handle_run_event);
 }
@@ -0,0 +1,349 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::workers::{Priority, WorkerPool};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LineOp {
+    Sort,
+    Unique,
+    Reverse,
+    Shuffle,
+    Number,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Compare {
+    #[default]
+    Lexicographic,
+    /// Runs of digits compare as numbers, so `file2` comes before `file10`
+    Natural,
+    /// By the number each line starts with; lines without one go first
+    Numeric,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Keep {
+    #[default]
+    First,
+    Last,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LineOpOptions {
+    pub descending: bool,
+    pub case_insensitive: bool,
+    pub compare: Compare,
+    /// Sorts by this field (1-based) rather than the whole line
+    pub column: Option<usize>,
+    /// What separates fields for `column`; whitespace when unset
+    pub delimiter: Option<String>,
+    /// Which of the repeated lines `Unique` keeps
+    pub keep: Keep,
+    /// For a shuffle that can be repeated
+    pub seed: Option<u64>,
+    /// The first line's number, 1 when unset
+    pub start: Option<i64>,
+    /// Between the number and the line, a space when unset
+    pub separator: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LineOpResult {
+    text: String,
+    lines: usize,
+    /// Lines `Unique` dropped
+    removed: usize,
+    /// The seed a shuffle used, to repeat it
+    seed: Option<u64>,
+}
+
+// Splits off a single trailing newline so transforms don't move it around,
+// and the `\r` of CRLF lines so moving lines doesn't mix the endings. Lines
+// are joined again with the ending of the first one.
+fn split_lines(content: &str) -> (Vec<&str>, &'static str, &'static str) {
+    let (body, trailing) = match content.strip_suffix("\r\n") {
+        Some(body) => (body, "\r\n"),
+        None => match content.strip_suffix('\n') {
+            Some(body) => (body, "\n"),
+            None => (content, ""),
+        },
+    };
+    let lines: Vec<&str> = body.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line)).collect();
+    let separator = match body.find('\n') {
+        Some(i) if body[..i].ends_with('\r') => "\r\n",
+        Some(_) => "\n",
+        None if trailing == "\r\n" => "\r\n",
+        None => "\n",
+    };
+    (lines, separator, trailing)
+}
+
+fn join_lines<S: AsRef<str>>(lines: &[S], separator: &str, trailing: &str) -> String {
+    let mut out = String::with_capacity(lines.iter().map(|line| line.as_ref().len() + separator.len()).sum());
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            out.push_str(separator);
+        }
+        out.push_str(line.as_ref());
+    }
+    out.push_str(trailing);
+    out
+}
+
+// The part of a line that decides its place in a sort
+fn sort_key<'a>(line: &'a str, opts: &LineOpOptions) -> Cow<'a, str> {
+    let field = match (opts.column, opts.delimiter.as_deref().filter(|d| !d.is_empty())) {
+        (None, _) => line,
+        (Some(column), Some(delimiter)) => line.split(delimiter).nth(column.saturating_sub(1)).unwrap_or(""),
+        (Some(column), None) => line.split_whitespace().nth(column.saturating_sub(1)).unwrap_or(""),
+    };
+    if opts.case_insensitive {
+        Cow::Owned(field.to_lowercase())
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+// Compares digit runs by value, then by length so `01` sorts after `1`
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_end = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+                let b_end = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+                let (a_digits, b_digits) = (&a[..a_end], &b[..b_end]);
+                let (a_value, b_value) = (a_digits.trim_start_matches('0'), b_digits.trim_start_matches('0'));
+                let order = a_value.len().cmp(&b_value.len()).then_with(|| a_value.cmp(b_value)).then_with(|| a_digits.len().cmp(&b_digits.len()));
+                if order != Ordering::Equal {
+                    return order;
+                }
+                (a, b) = (&a[a_end..], &b[b_end..]);
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+            }
+        }
+    }
+}
+
+// The number a line starts with, after any leading spaces
+fn leading_number(line: &str) -> Option<f64> {
+    let line = line.trim_start();
+    let end = line
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || c == '.' || (i == 0 && (c == '-' || c == '+'))))
+        .map_or(line.len(), |(i, _)| i);
+    line[..end].parse().ok()
+}
+
+fn compare(a: &str, b: &str, how: Compare) -> Ordering {
+    match how {
+        Compare::Lexicographic => a.cmp(b),
+        Compare::Natural => natural_cmp(a, b),
+        Compare::Numeric => match (leading_number(a), leading_number(b)) {
+            (Some(x), Some(y)) => x.total_cmp(&y),
+            (x, y) => x.is_some().cmp(&y.is_some()),
+        },
+    }
+}
+
+// Stable in both directions, so equal lines keep their relative order
+fn sort(lines: &mut [&str], opts: &LineOpOptions) {
+    let mut keyed: Vec<(Cow<str>, &str)> = lines.iter().map(|&line| (sort_key(line, opts), line)).collect();
+    keyed.sort_by(|a, b| {
+        let order = compare(&a.0, &b.0, opts.compare);
+        if opts.descending {
+            order.reverse()
+        } else {
+            order
+        }
+    });
+    for (slot, (_, line)) in lines.iter_mut().zip(keyed) {
+        *slot = line;
+    }
+}
+
+// Keeps the first or the last of each set of repeated lines, where it was
+fn unique<'a>(lines: Vec<&'a str>, opts: &LineOpOptions) -> Vec<&'a str> {
+    let mut seen = HashSet::new();
+    let mut first = |line: &&'a str| seen.insert(if opts.case_insensitive { Cow::Owned(line.to_lowercase()) } else { Cow::Borrowed(*line) });
+    match opts.keep {
+        Keep::First => lines.into_iter().filter(|line| first(line)).collect(),
+        Keep::Last => {
+            let mut kept: Vec<&str> = lines.into_iter().rev().filter(|line| first(line)).collect();
+            kept.reverse();
+            kept
+        }
+    }
+}
+
+// splitmix64: small, and the same sequence for a seed everywhere
+fn shuffle(lines: &mut [&str], seed: u64) {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+    for i in (1..lines.len()).rev() {
+        lines.swap(i, (next() % (i as u64 + 1)) as usize);
+    }
+}
+
+// Right-aligned, so the lines themselves start in one column
+fn number(lines: &[&str], opts: &LineOpOptions) -> Vec<String> {
+    let start = opts.start.unwrap_or(1);
+    let separator = opts.separator.as_deref().unwrap_or(" ");
+    let last = start + lines.len().saturating_sub(1) as i64;
+    let width = start.to_string().len().max(last.to_string().len());
+    lines.iter().enumerate().map(|(i, line)| format!("{:>width$}{}{}", start + i as i64, separator, line)).collect()
+}
+
+fn apply(content: &str, op: LineOp, opts: &LineOpOptions) -> LineOpResult {
+    let (mut lines, separator, trailing) = split_lines(content);
+    let before = lines.len();
+    let mut seed = None;
+    let text = match op {
+        LineOp::Sort => {
+            sort(&mut lines, opts);
+            join_lines(&lines, separator, trailing)
+        }
+        LineOp::Unique => {
+            lines = unique(lines, opts);
+            join_lines(&lines, separator, trailing)
+        }
+        LineOp::Reverse => {
+            lines.reverse();
+            join_lines(&lines, separator, trailing)
+        }
+        LineOp::Shuffle => {
+            let used = opts.seed.unwrap_or_else(|| {
+                std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos() as u64)
+            });
+            shuffle(&mut lines, used);
+            seed = Some(used);
+            join_lines(&lines, separator, trailing)
+        }
+        LineOp::Number => join_lines(&number(&lines, opts), separator, trailing),
+    };
+    LineOpResult { text, lines: lines.len(), removed: before - lines.len(), seed }
+}
+
+/// Sorts, dedupes, reverses, shuffles or numbers the lines of `content` on
+/// a worker, for selections too big to do in the webview.
+#[tauri::command]
+pub async fn line_ops(app: AppHandle, content: String, op: LineOp, opts: Option<LineOpOptions>) -> Result<LineOpResult, String> {
+    let opts = opts.unwrap_or_default();
+    app.state::<WorkerPool>().run(Priority::Interactive, move || apply(&content, op, &opts)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(content: &str, op: LineOp, opts: LineOpOptions) -> String {
+        apply(content, op, &opts).text
+    }
+
+    #[test]
+    fn splits_and_joins_lines_keeping_endings() {
+        assert_eq!(split_lines("a\nb\n"), (vec!["a", "b"], "\n", "\n"));
+        assert_eq!(split_lines("a\r\nb\nc"), (vec!["a", "b", "c"], "\r\n", ""));
+        assert_eq!(split_lines("a\r\n"), (vec!["a"], "\r\n", "\r\n"));
+        assert_eq!(split_lines(""), (vec![""], "\n", ""));
+        for content in ["a\nb\n", "a\r\nb", "one", "\n\n", ""] {
+            let (lines, separator, trailing) = split_lines(content);
+            assert_eq!(join_lines(&lines, separator, trailing), content);
+        }
+    }
+
+    #[test]
+    fn sorts_stably_in_both_directions() {
+        let opts = || LineOpOptions { case_insensitive: true, ..Default::default() };
+        assert_eq!(text("b\nA\na\nB\n", LineOp::Sort, opts()), "A\na\nb\nB\n");
+        assert_eq!(text("b\nA\na\nB\n", LineOp::Sort, LineOpOptions { descending: true, ..opts() }), "b\nB\nA\na\n");
+        assert_eq!(text("b\nA\na\nB", LineOp::Sort, LineOpOptions::default()), "A\nB\na\nb");
+    }
+
+    #[test]
+    fn sorts_naturally_numerically_and_by_column() {
+        let natural = LineOpOptions { compare: Compare::Natural, ..Default::default() };
+        assert_eq!(text("file10\nfile2\nfile1\nfile01", LineOp::Sort, natural), "file1\nfile01\nfile2\nfile10");
+        let numeric = LineOpOptions { compare: Compare::Numeric, ..Default::default() };
+        assert_eq!(text("10 ten\n-2 minus\n3.5 x\nnone\n2 two", LineOp::Sort, numeric), "none\n-2 minus\n2 two\n3.5 x\n10 ten");
+        let by_second = LineOpOptions { column: Some(2), delimiter: Some(";".into()), ..Default::default() };
+        assert_eq!(text("a;z\nb;x\nc", LineOp::Sort, by_second), "c\nb;x\na;z");
+        let by_whitespace = LineOpOptions { column: Some(2), compare: Compare::Numeric, ..Default::default() };
+        assert_eq!(text("x   30\ny 4\nz\t100", LineOp::Sort, by_whitespace), "y 4\nx   30\nz\t100");
+    }
+
+    #[test]
+    fn keeps_the_trailing_newline_as_it_was() {
+        for op in [LineOp::Sort, LineOp::Unique, LineOp::Reverse, LineOp::Shuffle, LineOp::Number] {
+            let opts = || LineOpOptions { seed: Some(7), ..Default::default() };
+            assert!(text("b\na\nb\n", op, opts()).ends_with('\n'), "{:?}", op);
+            assert!(!text("b\na\nb", op, opts()).ends_with('\n'), "{:?}", op);
+            assert!(text("b\r\na\r\nb\r\n", op, opts()).ends_with("\r\n"), "{:?}", op);
+        }
+        assert_eq!(text("", LineOp::Sort, LineOpOptions::default()), "");
+        assert_eq!(text("\n", LineOp::Reverse, LineOpOptions::default()), "\n");
+    }
+
+    #[test]
+    fn moving_crlf_lines_keeps_every_ending() {
+        assert_eq!(text("c\r\nb\r\na", LineOp::Sort, LineOpOptions::default()), "a\r\nb\r\nc");
+        assert_eq!(text("1\r\n2\r\n3\r\n", LineOp::Reverse, LineOpOptions::default()), "3\r\n2\r\n1\r\n");
+    }
+
+    #[test]
+    fn removes_duplicates_keeping_first_or_last() {
+        let first = apply("a\nb\nA\na\nc\nb\n", LineOp::Unique, &LineOpOptions::default());
+        assert_eq!((first.text.as_str(), first.lines, first.removed), ("a\nb\nA\nc\n", 4, 2));
+        let last = LineOpOptions { keep: Keep::Last, case_insensitive: true, ..Default::default() };
+        let kept = apply("a\nb\nA\na\nc\nb\n", LineOp::Unique, &last);
+        assert_eq!((kept.text.as_str(), kept.removed), ("a\nc\nb\n", 3));
+    }
+
+    #[test]
+    fn shuffles_the_same_way_for_a_seed() {
+        let lines: Vec<String> = (0..50).map(|i| i.to_string()).collect();
+        let content = lines.join("\n");
+        let seeded = || LineOpOptions { seed: Some(42), ..Default::default() };
+        let once = apply(&content, LineOp::Shuffle, &seeded());
+        assert_eq!(once.text, text(&content, LineOp::Shuffle, seeded()));
+        assert_eq!(once.seed, Some(42));
+        assert_ne!(once.text, content);
+        let mut sorted: Vec<&str> = once.text.split('\n').collect();
+        sorted.sort_by_key(|line| line.parse::<u32>().unwrap());
+        assert_eq!(sorted.join("\n"), content);
+    }
+
+    #[test]
+    fn numbers_lines_aligned() {
+        let content = (1..=10).map(|i| format!("l{}", i)).collect::<Vec<_>>().join("\n") + "\n";
+        let numbered = text(&content, LineOp::Number, LineOpOptions::default());
+        assert!(numbered.starts_with(" 1 l1\n 2 l2\n"));
+        assert!(numbered.ends_with("10 l10\n"));
+        let from_zero = LineOpOptions { start: Some(0), separator: Some(". ".into()), ..Default::default() };
+        assert_eq!(text("a\nb", LineOp::Number, from_zero), "0. a\n1. b");
+    }
+}
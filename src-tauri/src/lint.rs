@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::format;
+use crate::paths;
+use crate::tasks::{self, TaskOptions};
+use crate::workspace;
+use crate::workspace_trust::UNTRUSTED;
+
+// Problems found so far are sent at most this often
+const BATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A linter from `linters` in settings.json, by the name it's run with.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LintProfile {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub matcher: Matcher,
+}
+
+/// How a linter's output turns into problems: one of the built-in formats, or
+/// `{ "pattern": regex }` with named groups `file`, `line`, `message` and
+/// optionally `column`, `endLine`, `endColumn`, `severity` and `code`.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Matcher {
+    /// rustc's `--error-format=json`, or cargo's `--message-format=json`
+    RustcJson,
+    /// eslint's default formatter
+    EslintStylish,
+    /// `file:line:col: message`, as gcc, go vet and most others print
+    #[default]
+    Generic,
+    Pattern(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+    Hint,
+}
+
+impl Severity {
+    fn parse(text: &str) -> Option<Severity> {
+        let text = text.to_ascii_lowercase();
+        if text.starts_with("error") || text.starts_with("fatal") {
+            Some(Severity::Error)
+        } else if text.starts_with("warn") {
+            Some(Severity::Warning)
+        } else if text.starts_with("note") || text.starts_with("info") {
+            Some(Severity::Info)
+        } else if text.starts_with("help") || text.starts_with("hint") {
+            Some(Severity::Hint)
+        } else {
+            None
+        }
+    }
+}
+
+/// One problem, with 1-based lines and columns and an absolute path.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostic {
+    pub path: String,
+    pub line: u32,
+    pub column: u32,
+    pub end_line: Option<u32>,
+    pub end_column: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+    pub code: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DiagnosticsUpdated<'a> {
+    source: &'a str,
+    items: &'a [Diagnostic],
+    running: bool,
+}
+
+/// The task id of each profile's current run. A run that has been replaced
+/// stops reporting.
+#[derive(Default)]
+pub struct Linters(Mutex<HashMap<String, u32>>);
+
+const GENERIC: &str = r"^(?P<file>(?:[A-Za-z]:)?[^:\s][^:]*):(?P<line>\d+)(?::(?P<column>\d+))?:\s*(?:(?P<severity>error|warning|note|info|hint|help)(?:\[(?P<code>[^\]]+)\])?:\s*)?(?P<message>.+)$";
+
+#[derive(Clone)]
+enum Parser {
+    Rustc,
+    /// The file whose problems the following lines list
+    Stylish(Option<PathBuf>),
+    Pattern(Regex),
+}
+
+/// `file` against `cwd`, without the `./` parts tools like to print.
+fn absolute(cwd: &Path, file: &str) -> String {
+    let joined = cwd.join(file.trim());
+    let path: PathBuf = joined.components().filter(|c| *c != Component::CurDir).collect();
+    path.display().to_string()
+}
+
+impl Parser {
+    fn new(matcher: &Matcher) -> Result<Parser, String> {
+        let pattern = match matcher {
+            Matcher::RustcJson => return Ok(Parser::Rustc),
+            Matcher::EslintStylish => return Ok(Parser::Stylish(None)),
+            Matcher::Generic => GENERIC,
+            Matcher::Pattern(pattern) => pattern,
+        };
+        let regex = Regex::new(pattern).map_err(|e| format!("Invalid problem pattern: {}", e))?;
+        let names: Vec<&str> = regex.capture_names().flatten().collect();
+        if let Some(missing) = ["file", "line", "message"].into_iter().find(|name| !names.contains(name)) {
+            return Err(format!("The problem pattern needs a (?P<{}>…) group", missing));
+        }
+        Ok(Parser::Pattern(regex))
+    }
+
+    fn line(&mut self, line: &str, cwd: &Path) -> Option<Diagnostic> {
+        match self {
+            Parser::Rustc => rustc_line(line, cwd),
+            Parser::Stylish(file) => stylish_line(file, line, cwd),
+            Parser::Pattern(regex) => {
+                let captures = regex.captures(line)?;
+                let text = |name: &str| captures.name(name).map(|m| m.as_str()).filter(|s| !s.is_empty());
+                let number = |name: &str| text(name).and_then(|s| s.parse().ok());
+                Some(Diagnostic {
+                    path: absolute(cwd, text("file")?),
+                    line: number("line")?,
+                    column: number("column").unwrap_or(1),
+                    end_line: number("endLine"),
+                    end_column: number("endColumn"),
+                    severity: text("severity").and_then(Severity::parse).unwrap_or(Severity::Error),
+                    message: text("message")?.trim().to_string(),
+                    code: text("code").map(str::to_string),
+                })
+            }
+        }
+    }
+}
+
+fn rustc_line(line: &str, cwd: &Path) -> Option<Diagnostic> {
+    let value: serde_json::Value = serde_json::from_str(line.trim()).ok()?;
+    // cargo wraps each diagnostic; rustc prints it bare
+    let message = match value.get("reason") {
+        Some(reason) if reason.as_str() == Some("compiler-message") => value.get("message")?,
+        Some(_) => return None,
+        None => &value,
+    };
+    // Summaries like "aborting due to 2 previous errors" point nowhere
+    let spans = message.get("spans")?.as_array()?;
+    let span = spans.iter().find(|s| s.get("is_primary") == Some(&serde_json::Value::Bool(true)))?;
+    let number = |key: &str| span.get(key).and_then(|v| v.as_u64()).map(|n| n as u32);
+    Some(Diagnostic {
+        path: absolute(cwd, span.get("file_name")?.as_str()?),
+        line: number("line_start")?,
+        column: number("column_start").unwrap_or(1),
+        end_line: number("line_end"),
+        end_column: number("column_end"),
+        severity: message.get("level").and_then(|v| v.as_str()).and_then(Severity::parse).unwrap_or(Severity::Error),
+        message: message.get("message")?.as_str()?.to_string(),
+        code: message.pointer("/code/code").and_then(|v| v.as_str()).map(str::to_string),
+    })
+}
+
+fn stylish_line(file: &mut Option<PathBuf>, line: &str, cwd: &Path) -> Option<Diagnostic> {
+    use std::sync::OnceLock;
+    static PROBLEM: OnceLock<Regex> = OnceLock::new();
+    let problem = PROBLEM.get_or_init(|| {
+        Regex::new(r"^\s+(\d+):(\d+)\s+(error|warning)\s+(.*?)(?:\s{2,}(\S+))?\s*$").expect("valid regex")
+    });
+    if line.trim().is_empty() {
+        return None;
+    }
+    if !line.starts_with(char::is_whitespace) {
+        // A file name, or the "✖ 3 problems" summary that ends the list
+        *file = (!line.starts_with('✖')).then(|| PathBuf::from(absolute(cwd, line)));
+        return None;
+    }
+    let path = file.as_ref()?;
+    let captures = problem.captures(line)?;
+    Some(Diagnostic {
+        path: path.display().to_string(),
+        line: captures[1].parse().ok()?,
+        column: captures[2].parse().ok()?,
+        end_line: None,
+        end_column: None,
+        severity: Severity::parse(&captures[3]).unwrap_or(Severity::Error),
+        message: captures[4].to_string(),
+        code: captures.get(5).map(|m| m.as_str().to_string()),
+    })
+}
+
+fn read_lines(source: impl Read, stream: usize, sender: mpsc::Sender<(usize, String)>) {
+    let mut reader = BufReader::new(source);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&buf).trim_end_matches(['\r', '\n']).to_string();
+                if sender.send((stream, line)).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Emits the problems, unless a newer run of the same profile took over.
+fn report(app: &AppHandle, source: &str, task_id: u32, items: &[Diagnostic], running: bool) {
+    let linters = app.state::<Linters>();
+    let runs = linters.0.lock().unwrap();
+    if runs.get(source) == Some(&task_id) {
+        let _ = app.emit("diagnostics-updated", DiagnosticsUpdated { source, items, running });
+    }
+}
+
+/// Emits problems found other than by running a linter, all of `source`'s.
+pub(crate) fn publish(app: &AppHandle, source: &str, items: &[Diagnostic]) {
+    let _ = app.emit("diagnostics-updated", DiagnosticsUpdated { source, items, running: false });
+}
+
+fn run(app: AppHandle, source: String, profile: LintProfile, cwd: PathBuf) -> Result<u32, String> {
+    let parser = Parser::new(&profile.matcher)?;
+    let tool = format::resolve(&app, &profile.command, Some(&cwd));
+    let opts = TaskOptions { cwd: Some(cwd.display().to_string()), ..Default::default() };
+    let mut child = tasks::command(&tool.to_string_lossy(), &profile.args, &opts)
+        .spawn()
+        .map_err(|e| format!("Failed to run {}: {}", profile.command, e))?;
+    let task_id = tasks::track(&app, child.id());
+    let previous = app.state::<Linters>().0.lock().unwrap().insert(source.clone(), task_id);
+    if let Some(previous) = previous {
+        let _ = tasks::kill(&app, previous);
+    }
+    report(&app, &source, task_id, &[], true);
+
+    let (sender, receiver) = mpsc::channel();
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let stderr_sender = sender.clone();
+    std::thread::spawn(move || read_lines(stdout, 0, sender));
+    std::thread::spawn(move || read_lines(stderr, 1, stderr_sender));
+    std::thread::spawn(move || {
+        // Each stream gets its own parser, since stylish output spans lines
+        let mut parsers = [parser.clone(), parser];
+        let mut items: Vec<Diagnostic> = Vec::new();
+        let mut changed = false;
+        let mut last_report = Instant::now();
+        loop {
+            match receiver.recv_timeout(BATCH_INTERVAL) {
+                Ok((stream, line)) => {
+                    // cargo reports a problem once per target it's in
+                    if let Some(item) = parsers[stream].line(&line, &cwd).filter(|item| !items.contains(item)) {
+                        items.push(item);
+                        changed = true;
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+            if changed && last_report.elapsed() >= BATCH_INTERVAL {
+                report(&app, &source, task_id, &items, true);
+                changed = false;
+                last_report = Instant::now();
+            }
+        }
+        let _ = child.wait();
+        tasks::untrack(&app, task_id);
+        report(&app, &source, task_id, &items, false);
+        let linters = app.state::<Linters>();
+        let mut runs = linters.0.lock().unwrap();
+        if runs.get(&source) == Some(&task_id) {
+            runs.remove(&source);
+        }
+    });
+    Ok(task_id)
+}
+
+/// Runs the linter `profile` in `cwd`, replacing a run of it still going.
+/// Problems arrive as `diagnostics-updated` events, each with all found so
+/// far, and the last with `running` false. One only an untrusted workspace
+/// defines is UNTRUSTED.
+#[tauri::command]
+pub async fn run_linter(app: AppHandle, window: tauri::Window, profile: String, cwd: String) -> Result<u32, String> {
+    let lint = workspace::effective(&app, window.label()).linters.get(&profile).cloned();
+    let lint = match lint {
+        Some(lint) => lint,
+        None if workspace::withheld(&app, window.label(), "linters").is_some_and(|linters| linters.get(&profile).is_some()) => return Err(UNTRUSTED.into()),
+        None => return Err(format!("No linter named \"{}\" in settings", profile)),
+    };
+    tauri::async_runtime::spawn_blocking(move || run(app, profile, lint, paths::decode(&cwd)))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Names of the linters in settings and the window's workspace, for the
+/// command palette.
+#[tauri::command]
+pub fn linter_profiles(app: AppHandle, window: tauri::Window) -> Vec<String> {
+    let mut names: Vec<String> = workspace::effective(&app, window.label()).linters.keys().cloned().collect();
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(matcher: &Matcher, output: &str) -> Vec<Diagnostic> {
+        let mut parser = Parser::new(matcher).unwrap();
+        output.lines().filter_map(|line| parser.line(line, Path::new("/work"))).collect()
+    }
+
+    #[test]
+    fn parses_cargo_json() {
+        let output = concat!(
+            r#"{"reason":"compiler-artifact","target":{}}"#,
+            "\n",
+            r#"{"reason":"compiler-message","message":{"message":"unused variable: `x`","code":{"code":"unused_variables"},"level":"warning","spans":[{"file_name":"src/main.rs","line_start":2,"line_end":2,"column_start":9,"column_end":10,"is_primary":true}]}}"#,
+            "\n",
+            r#"{"$message_type":"diagnostic","message":"aborting due to 1 previous error","code":null,"level":"error","spans":[]}"#,
+        );
+        let items = parse(&Matcher::RustcJson, output);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].path, "/work/src/main.rs");
+        assert_eq!((items[0].line, items[0].column, items[0].end_column), (2, 9, Some(10)));
+        assert_eq!(items[0].severity, Severity::Warning);
+        assert_eq!(items[0].code.as_deref(), Some("unused_variables"));
+    }
+
+    #[test]
+    fn parses_eslint_stylish() {
+        let output = "\n/app/src/index.js\n  1:10  error    'foo' is defined but never used  no-unused-vars\n  3:1   warning  Unexpected console statement     no-console\n\n✖ 2 problems (1 error, 1 warning)\n";
+        let items = parse(&Matcher::EslintStylish, output);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].path, "/app/src/index.js");
+        assert_eq!(items[0].message, "'foo' is defined but never used");
+        assert_eq!(items[0].code.as_deref(), Some("no-unused-vars"));
+        assert_eq!((items[1].line, items[1].severity), (3, Severity::Warning));
+    }
+
+    #[test]
+    fn parses_generic_and_custom_patterns() {
+        let items = parse(&Matcher::Generic, "./main.c:4:7: error: expected ';'\nIn file included from x\nutil.go:12: unreachable code");
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].path, "/work/main.c");
+        assert_eq!((items[0].column, items[0].severity), (7, Severity::Error));
+        assert_eq!(items[0].message, "expected ';'");
+        assert_eq!((items[1].line, items[1].column), (12, 1));
+
+        let custom = Matcher::Pattern(r"^(?P<severity>\w+) (?P<file>\S+) line (?P<line>\d+): (?P<message>.*)$".into());
+        let items = parse(&custom, "warning lib/a.py line 3: too long");
+        assert_eq!((items[0].path.as_str(), items[0].severity), ("/work/lib/a.py", Severity::Warning));
+        assert!(Parser::new(&Matcher::Pattern(r"(?P<file>\S+)".into())).is_err());
+    }
+}
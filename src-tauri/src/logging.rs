@@ -0,0 +1,438 @@
+//! The log, written in every build to `skriv.log` in the logs folder and
+//! rotated by size, and to stdout as well in debug builds. Each line names
+//! the module it came from as `skriv::git`, `skriv::updates` and so on, so
+//! grepping for one shows that area's story. skriv's own lines and those of
+//! its dependencies have separate levels, and any module can be given its
+//! own; `logging` in the settings keeps them, and they change while running.
+//! The last lines are kept in memory too, for crash reports. The webview's
+//! errors and console come in under the `webview` target, throttled.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use log::{Level, LevelFilter, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::plugin::TauriPlugin;
+use tauri::{AppHandle, Manager, Wry};
+use tauri_plugin_log::{fern, RotationStrategy, Target, TargetKind};
+use tauri_plugin_opener::OpenerExt;
+
+use crate::paths;
+use crate::settings::{self, Settings};
+
+const FILE_NAME: &str = "skriv";
+const MAX_FILE_SIZE: u128 = 2 * 1024 * 1024;
+/// Files kept, the one being written included.
+const KEPT_FILES: usize = 5;
+/// What `log` names this crate's modules after.
+const CRATE: &str = env!("CARGO_CRATE_NAME");
+/// What they're called in the log instead.
+const PREFIX: &str = "skriv";
+/// Lines from the webview; counted as skriv's own.
+const WEBVIEW: &str = "webview";
+/// The same webview line this often a second at most, as from a render loop
+const REPEATS_PER_SECOND: u32 = 5;
+/// And this many webview lines a second in all
+const WEBVIEW_LINES_PER_SECOND: u32 = 50;
+const RECENT_LINES: usize = 200;
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+const DEPENDENCY_LEVEL: LevelFilter = LevelFilter::Warn;
+
+/// Help > Troubleshooting's presets for skriv's own lines; Info is the
+/// default, so it clears what the others set.
+pub const MENU_ITEMS: [(&str, Option<LogLevel>); 3] = [("log_level_info", None), ("log_level_debug", Some(LogLevel::Debug)), ("log_level_trace", Some(LogLevel::Trace))];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => LevelFilter::Off,
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+impl From<LevelFilter> for LogLevel {
+    fn from(level: LevelFilter) -> Self {
+        match level {
+            LevelFilter::Off => LogLevel::Off,
+            LevelFilter::Error => LogLevel::Error,
+            LevelFilter::Warn => LogLevel::Warn,
+            LevelFilter::Info => LogLevel::Info,
+            LevelFilter::Debug => LogLevel::Debug,
+            LevelFilter::Trace => LogLevel::Trace,
+        }
+    }
+}
+
+/// Levels over the defaults, kept until they're cleared.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LogSettings {
+    /// For skriv's own lines; Info when unset
+    pub level: Option<LogLevel>,
+    /// By target, like `skriv::git` or a dependency's `notify`, each
+    /// covering the modules inside it
+    pub targets: HashMap<String, LogLevel>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogConfig {
+    level: LogLevel,
+    default_level: LogLevel,
+    dependency_level: LogLevel,
+    targets: HashMap<String, LogLevel>,
+    log_dir: Option<String>,
+}
+
+struct Levels {
+    own: LevelFilter,
+    dependencies: LevelFilter,
+    /// Longest first, so that the closest one decides
+    targets: Vec<(String, LevelFilter)>,
+}
+
+static LEVELS: RwLock<Levels> = RwLock::new(Levels { own: DEFAULT_LEVEL, dependencies: DEPENDENCY_LEVEL, targets: Vec::new() });
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// `target` is the module `module` or one inside it.
+fn within(target: &str, module: &str) -> bool {
+    target == module || target.strip_prefix(module).is_some_and(|rest| rest.starts_with("::"))
+}
+
+/// A target of skriv's own, as opposed to a dependency's.
+fn is_own(target: &str) -> bool {
+    within(target, CRATE) || within(target, PREFIX) || target == WEBVIEW
+}
+
+/// `app_lib::git` as `skriv::git`.
+fn display_target(target: &str) -> String {
+    match target.strip_prefix(CRATE) {
+        Some(rest) if rest.is_empty() || rest.starts_with("::") => format!("{}{}", PREFIX, rest),
+        _ => target.to_string(),
+    }
+}
+
+fn level_for(levels: &Levels, target: &str) -> LevelFilter {
+    if !levels.targets.is_empty() {
+        let shown = display_target(target);
+        if let Some((_, level)) = levels.targets.iter().find(|(module, _)| within(&shown, module)) {
+            return *level;
+        }
+    }
+    if is_own(target) {
+        levels.own
+    } else {
+        levels.dependencies
+    }
+}
+
+fn enabled(metadata: &Metadata) -> bool {
+    let Ok(levels) = LEVELS.read() else { return true };
+    metadata.level() <= level_for(&levels, metadata.target())
+}
+
+/// Errors from the webview reach crash reports whatever the level.
+fn remembered(metadata: &Metadata) -> bool {
+    enabled(metadata) || (metadata.target() == WEBVIEW && metadata.level() == Level::Error)
+}
+
+fn remember(record: &Record) {
+    let Ok(mut recent) = RECENT.lock() else { return };
+    if recent.len() == RECENT_LINES {
+        recent.pop_front();
+    }
+    recent.push_back(record.args().to_string());
+}
+
+/// The last lines logged, oldest first. None if logging is what panicked.
+pub fn recent_lines() -> Vec<String> {
+    RECENT.try_lock().map(|recent| recent.iter().cloned().collect()).unwrap_or_default()
+}
+
+fn levels(settings: &LogSettings) -> Levels {
+    let mut targets: Vec<(String, LevelFilter)> = settings.targets.iter().map(|(target, level)| (display_target(target.trim()), (*level).into())).collect();
+    targets.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+    Levels { own: settings.level.map_or(DEFAULT_LEVEL, LevelFilter::from), dependencies: DEPENDENCY_LEVEL, targets }
+}
+
+/// Uses the levels in `settings` from now on.
+pub fn apply(settings: &LogSettings) {
+    let levels = levels(settings);
+    // Lines below every level are dropped before they're even formatted
+    log::set_max_level(levels.targets.iter().map(|(_, level)| *level).fold(levels.own.max(levels.dependencies), Ord::max));
+    *LEVELS.write().unwrap() = levels;
+}
+
+/// The log plugin, writing to the logs folder when it can be found.
+pub fn plugin(app: &AppHandle) -> TauriPlugin<Wry> {
+    let mut builder = tauri_plugin_log::Builder::default()
+        .clear_targets()
+        // The filters decide; this only lets everything reach them
+        .level(LevelFilter::Trace)
+        .max_file_size(MAX_FILE_SIZE)
+        .rotation_strategy(RotationStrategy::KeepSome(KEPT_FILES - 1))
+        .format(|out, message, record| {
+            let time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
+            out.finish(format_args!("[{}][{}][{}] {}", time, record.level(), display_target(record.target()), message))
+        });
+    match paths::log_dir(app) {
+        Ok(path) => builder = builder.target(Target::new(TargetKind::Folder { path, file_name: Some(FILE_NAME.into()) }).filter(enabled)),
+        Err(e) => eprintln!("Logging to stdout only, since {}", e),
+    }
+    if cfg!(debug_assertions) {
+        builder = builder.target(Target::new(TargetKind::Stdout).filter(enabled));
+    }
+    let memory = fern::Dispatch::new().chain(fern::Output::call(remember));
+    builder = builder.target(Target::new(TargetKind::Dispatch(memory)).filter(remembered));
+    builder.build()
+}
+
+/// Webview lines in the current second, so a flood is dropped and counted.
+pub struct WebviewThrottle(Mutex<Throttle>);
+
+impl Default for WebviewThrottle {
+    fn default() -> Self {
+        WebviewThrottle(Mutex::new(Throttle::new(Instant::now())))
+    }
+}
+
+struct Throttle {
+    started: Instant,
+    admitted: u32,
+    dropped: u32,
+    repeats: HashMap<String, u32>,
+}
+
+impl Throttle {
+    fn new(started: Instant) -> Self {
+        Throttle { started, admitted: 0, dropped: 0, repeats: HashMap::new() }
+    }
+
+    /// Whether the line `key` may be logged at `now`, and how many the
+    /// second before dropped, once it's over.
+    fn admit(&mut self, key: &str, now: Instant) -> (bool, Option<u32>) {
+        let mut dropped = None;
+        if now.duration_since(self.started) >= Duration::from_secs(1) {
+            dropped = Some(self.dropped).filter(|n| *n > 0);
+            *self = Throttle::new(now);
+        }
+        let admitted = self.admitted < WEBVIEW_LINES_PER_SECOND && {
+            let repeats = self.repeats.entry(key.to_string()).or_insert(0);
+            *repeats += 1;
+            *repeats <= REPEATS_PER_SECOND
+        };
+        if admitted {
+            self.admitted += 1;
+        } else {
+            self.dropped += 1;
+        }
+        (admitted, dropped)
+    }
+}
+
+/// An error or console line from the webview, which otherwise only its
+/// devtools would see, logged with the window and the version.
+#[tauri::command]
+pub fn log_from_frontend(app: AppHandle, window: tauri::Window, level: LogLevel, message: String, context: Option<Value>) {
+    let Some(level) = LevelFilter::from(level).to_level() else { return };
+    let (admitted, dropped) = app.state::<WebviewThrottle>().0.lock().unwrap().admit(&format!("{}:{}:{}", window.label(), level, message), Instant::now());
+    let version = &app.package_info().version;
+    if let Some(dropped) = dropped {
+        log::warn!(target: WEBVIEW, "[{}][{}] Dropped {} lines in a second", window.label(), version, dropped);
+    }
+    if admitted {
+        let context = context.filter(|context| !context.is_null()).map(|context| format!(" {}", context)).unwrap_or_default();
+        log::log!(target: WEBVIEW, level, "[{}][{}] {}{}", window.label(), version, message, context);
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClearedLogs {
+    files: usize,
+    bytes_freed: u64,
+}
+
+/// The logs folder, made if it's missing, with links resolved so that what's
+/// in it can be checked to be.
+fn folder(app: &AppHandle) -> Result<PathBuf, String> {
+    let dir = paths::log_dir(app)?;
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    dir.canonicalize().map_err(|e| e.to_string())
+}
+
+/// One of the log's files, right in the canonical `dir`: not a link, nor
+/// anything else that lives there.
+fn is_log_file(dir: &Path, path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else { return false };
+    let ours = name.starts_with(FILE_NAME) && (name.ends_with(".log") || name.ends_with(".log.bak"));
+    ours && std::fs::symlink_metadata(path).is_ok_and(|m| m.is_file()) && path.canonicalize().is_ok_and(|p| p.parent() == Some(dir))
+}
+
+/// Deletes the rotated files and empties the one being written. The logger
+/// appends to that one, so it carries on at the start, and emptying it in
+/// place works while it's open, where Windows wouldn't let it be deleted.
+fn clear(dir: &Path) -> Result<ClearedLogs, String> {
+    let active = dir.join(format!("{}.log", FILE_NAME));
+    let mut cleared = ClearedLogs { files: 0, bytes_freed: 0 };
+    for entry in std::fs::read_dir(dir).map_err(|e| e.to_string())?.filter_map(Result::ok) {
+        let path = entry.path();
+        if !is_log_file(dir, &path) {
+            continue;
+        }
+        let size = entry.metadata().map_or(0, |m| m.len());
+        let done = if path == active {
+            std::fs::OpenOptions::new().write(true).open(&path).and_then(|file| file.set_len(0))
+        } else {
+            std::fs::remove_file(&path)
+        };
+        match done {
+            Ok(()) => {
+                cleared.files += 1;
+                cleared.bytes_freed += size;
+            }
+            Err(e) => log::warn!("Failed to clear {}: {}", path.display(), e),
+        }
+    }
+    Ok(cleared)
+}
+
+/// Help > Troubleshooting > Open Logs Folder.
+#[tauri::command]
+pub fn open_logs_folder(app: AppHandle) -> Result<(), String> {
+    let dir = folder(&app)?;
+    app.opener().open_path(dir.to_string_lossy(), None::<&str>).map_err(|e| e.to_string())
+}
+
+/// Help > Troubleshooting > Clear Logs; says how much it freed.
+#[tauri::command]
+pub fn clear_logs(app: AppHandle) -> Result<ClearedLogs, String> {
+    let cleared = clear(&folder(&app)?)?;
+    log::info!("Cleared {} log files, {} bytes", cleared.files, cleared.bytes_freed);
+    Ok(cleared)
+}
+
+#[tauri::command]
+pub fn get_log_config(app: AppHandle) -> LogConfig {
+    let settings = app.state::<Mutex<Settings>>().lock().unwrap().logging.clone();
+    LogConfig {
+        level: settings.level.unwrap_or(DEFAULT_LEVEL.into()),
+        default_level: DEFAULT_LEVEL.into(),
+        dependency_level: DEPENDENCY_LEVEL.into(),
+        targets: settings.targets,
+        log_dir: paths::log_dir(&app).ok().map(|dir| dir.to_string_lossy().into_owned()),
+    }
+}
+
+/// Sets the level of skriv's own lines, or with `target` of that module and
+/// those inside it; no level goes back to the default. It's kept in the
+/// settings, so it holds after a restart until it's cleared.
+#[tauri::command]
+pub fn set_log_level(app: AppHandle, target: Option<String>, level: Option<LogLevel>) -> Result<LogConfig, String> {
+    let target = target.map(|target| target.trim().to_string());
+    if target.as_deref() == Some("") {
+        return Err("The target is empty".into());
+    }
+    let settings = settings::modify(&app, |settings| match (&target, level) {
+        (None, level) => settings.logging.level = level,
+        (Some(target), Some(level)) => {
+            settings.logging.targets.insert(target.clone(), level);
+        }
+        (Some(target), None) => {
+            settings.logging.targets.remove(target);
+        }
+    })?;
+    apply(&settings.logging);
+    log::info!("Log level of {} set to {:?}", target.as_deref().unwrap_or(PREFIX), level.unwrap_or(DEFAULT_LEVEL.into()));
+    Ok(get_log_config(app))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_own_modules_after_skriv() {
+        assert_eq!(display_target(&format!("{}::git", CRATE)), "skriv::git");
+        assert_eq!(display_target(CRATE), "skriv");
+        assert_eq!(display_target("tao::platform"), "tao::platform");
+        assert!(is_own(&format!("{}::updates", CRATE)));
+        assert!(!is_own("reqwest::connect"));
+        assert!(!is_own(&format!("{}_helper", CRATE)));
+        assert!(is_own(WEBVIEW));
+    }
+
+    #[test]
+    fn clears_only_the_logs_own_files() {
+        let root = std::env::temp_dir().join(format!("skriv-logs-{}", std::process::id()));
+        let dir = root.join("logs");
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        for (name, len) in [("skriv.log", 10), ("skriv_2026-10-01_12-00-00.log", 20), ("skriv_2026-10-01_12-00-00.log.bak", 5), ("notes.txt", 7)] {
+            std::fs::write(dir.join(name), "x".repeat(len)).unwrap();
+        }
+        let outside = root.join("skriv_outside.log");
+        std::fs::write(&outside, "keep").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&outside, dir.join("skriv_link.log")).unwrap();
+        assert_eq!(clear(&dir).unwrap(), ClearedLogs { files: 3, bytes_freed: 35 });
+        let mut left: Vec<String> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        left.sort();
+        let expected: &[&str] = if cfg!(unix) { &["notes.txt", "skriv.log", "skriv_link.log"] } else { &["notes.txt", "skriv.log"] };
+        assert_eq!(left, expected);
+        assert_eq!(std::fs::metadata(dir.join("skriv.log")).unwrap().len(), 0);
+        assert_eq!(std::fs::read_to_string(&outside).unwrap(), "keep");
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn drops_and_counts_a_flood_of_webview_lines() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(start);
+        let admitted = (0..REPEATS_PER_SECOND + 2).filter(|_| throttle.admit("main:ERROR:boom", start).0).count();
+        assert_eq!(admitted as u32, REPEATS_PER_SECOND);
+        assert_eq!(throttle.admit("main:ERROR:other", start), (true, None));
+        let others = (0..WEBVIEW_LINES_PER_SECOND).filter(|i| throttle.admit(&i.to_string(), start).0).count() as u32;
+        assert_eq!(others, WEBVIEW_LINES_PER_SECOND - REPEATS_PER_SECOND - 1);
+        let dropped = 2 + REPEATS_PER_SECOND + 1;
+        assert_eq!(throttle.admit("main:ERROR:boom", start + Duration::from_secs(1)), (true, Some(dropped)));
+    }
+
+    #[test]
+    fn the_closest_target_decides() {
+        let settings = LogSettings {
+            level: Some(LogLevel::Debug),
+            targets: HashMap::from([
+                (format!("{}::git", CRATE), LogLevel::Error),
+                ("skriv::git::watch".to_string(), LogLevel::Trace),
+                ("notify".to_string(), LogLevel::Info),
+            ]),
+        };
+        let levels = levels(&settings);
+        let level = |target: &str| level_for(&levels, target);
+        assert_eq!(level(&format!("{}::git::watch::poll", CRATE)), LevelFilter::Trace);
+        assert_eq!(level(&format!("{}::git::blame", CRATE)), LevelFilter::Error);
+        assert_eq!(level(&format!("{}::gitignore", CRATE)), LevelFilter::Debug);
+        assert_eq!(level("notify::inotify"), LevelFilter::Info);
+        assert_eq!(level("tao"), DEPENDENCY_LEVEL);
+    }
+}
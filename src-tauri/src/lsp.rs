@@ -0,0 +1,320 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+
+#[cfg(target_os = "macos")]
+use crate::tasks;
+
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+// A server that crashes this often within the window stays down
+const MAX_RESTARTS: usize = 5;
+const RESTART_WINDOW: Duration = Duration::from_secs(180);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+const EXIT_TIMEOUT: Duration = Duration::from_secs(2);
+// The request id skriv's own `shutdown` goes out with; its reply isn't forwarded
+const SHUTDOWN_ID: &str = "skriv-shutdown";
+
+#[derive(Clone)]
+struct Spec {
+    cmd: String,
+    args: Vec<String>,
+    cwd: Option<String>,
+}
+
+struct Server {
+    /// Tells this process apart from the ones it was restarted from
+    generation: u64,
+    spec: Spec,
+    child: Child,
+    stdin: Arc<Mutex<ChildStdin>>,
+    /// When it was restarted after crashes, within `RESTART_WINDOW`
+    restarts: Vec<Instant>,
+    /// Set while shutting down, to hear the reply to `shutdown`
+    shutdown_ack: Option<mpsc::Sender<()>>,
+}
+
+#[derive(Default)]
+struct State {
+    running: HashMap<String, Server>,
+    /// Crashed servers waiting to be restarted
+    restarting: HashSet<String>,
+}
+
+/// Language servers by the id the frontend started them with, usually one per
+/// language.
+#[derive(Default)]
+pub struct LanguageServers(Mutex<State>);
+
+#[derive(Clone, Serialize)]
+struct LspMessage<'a> {
+    id: &'a str,
+    /// The JSON-RPC message as the server sent it
+    payload: String,
+}
+
+#[derive(Clone, Serialize)]
+struct LspExited<'a> {
+    id: &'a str,
+    code: Option<i32>,
+    /// Whether it crashed and will be started again
+    restarting: bool,
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads one base protocol message, or `None` at the end of the stream.
+fn read_message(reader: &mut impl BufRead) -> std::io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end_matches(['\r', '\n']);
+        if header.is_empty() {
+            match length {
+                Some(_) => break,
+                None => return Err(invalid("Message without Content-Length")),
+            }
+        }
+        // Content-Type is the only other header, and its default is all there is
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = Some(value.trim().parse::<usize>().map_err(|_| invalid("Bad Content-Length"))?);
+            }
+        }
+    }
+    let mut body = vec![0; length.unwrap_or_default()];
+    reader.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn write_message(writer: &mut impl Write, payload: &str) -> std::io::Result<()> {
+    write!(writer, "Content-Length: {}\r\n\r\n{}", payload.len(), payload)?;
+    writer.flush()
+}
+
+fn is_shutdown_reply(payload: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(payload)
+        .is_ok_and(|message| message.get("id").and_then(|id| id.as_str()) == Some(SHUTDOWN_ID))
+}
+
+fn spawn(app: &AppHandle, id: &str, spec: Spec, restarts: Vec<Instant>) -> Result<(), String> {
+    let mut command = Command::new(&spec.cmd);
+    command.args(&spec.args).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    if let Some(cwd) = &spec.cwd {
+        command.current_dir(cwd);
+    }
+    #[cfg(target_os = "macos")]
+    if let Some(path) = tasks::login_path() {
+        command.env("PATH", path);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // CREATE_NO_WINDOW
+        command.creation_flags(0x0800_0000);
+    }
+    let mut child = command.spawn().map_err(|e| format!("Failed to start {}: {}", spec.cmd, e))?;
+    let stdin = child.stdin.take().expect("stdin is piped");
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+    let server = Server { generation, spec, child, stdin: Arc::new(Mutex::new(stdin)), restarts, shutdown_ack: None };
+    app.state::<LanguageServers>().0.lock().unwrap().running.insert(id.to_string(), server);
+
+    // Servers log to stderr
+    let name = id.to_string();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            log::info!("[{}] {}", name, line);
+        }
+    });
+    let app = app.clone();
+    let id = id.to_string();
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(stdout);
+        loop {
+            match read_message(&mut reader) {
+                Ok(Some(payload)) => {
+                    let ack = app.state::<LanguageServers>().0.lock().unwrap().running.get(&id).and_then(|s| s.shutdown_ack.clone());
+                    if let Some(ack) = ack.filter(|_| is_shutdown_reply(&payload)) {
+                        let _ = ack.send(());
+                        continue;
+                    }
+                    let payload = String::from_utf8_lossy(&payload).into_owned();
+                    let _ = app.emit("lsp-message", LspMessage { id: &id, payload });
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("[{}] Unreadable message, stopping: {}", id, e);
+                    break;
+                }
+            }
+        }
+        exited(&app, &id, generation);
+    });
+    Ok(())
+}
+
+/// Reaps the server once its output has ended, and restarts it if it crashed.
+fn exited(app: &AppHandle, id: &str, generation: u64) {
+    let servers = app.state::<LanguageServers>();
+    let mut state = servers.0.lock().unwrap();
+    if state.running.get(id).map(|s| s.generation) != Some(generation) {
+        return;
+    }
+    let mut server = state.running.remove(id).expect("server was just found");
+    let crashed = server.shutdown_ack.is_none();
+    if crashed {
+        state.restarting.insert(id.to_string());
+    }
+    drop(state);
+
+    // Closing stdout usually means the process is on its way out
+    let deadline = Instant::now() + EXIT_TIMEOUT;
+    while matches!(server.child.try_wait(), Ok(None)) && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let _ = server.child.kill();
+    let code = server.child.wait().ok().and_then(|status| status.code());
+
+    let now = Instant::now();
+    server.restarts.retain(|at| now.duration_since(*at) < RESTART_WINDOW);
+    let restarting = crashed && server.restarts.len() < MAX_RESTARTS;
+    let _ = app.emit("lsp-exited", LspExited { id, code, restarting });
+    if !crashed {
+        return;
+    }
+    if !restarting {
+        servers.0.lock().unwrap().restarting.remove(id);
+        log::warn!("[{}] Crashed {} times, not restarting", id, MAX_RESTARTS);
+        return;
+    }
+    // Backs off a little more with each crash
+    std::thread::sleep(Duration::from_secs(1 + server.restarts.len() as u64));
+    // Stopped in the meantime
+    if !servers.0.lock().unwrap().restarting.remove(id) {
+        return;
+    }
+    server.restarts.push(Instant::now());
+    if let Err(e) = spawn(app, id, server.spec, server.restarts) {
+        log::warn!("[{}] {}", id, e);
+        let _ = app.emit("lsp-exited", LspExited { id, code: None, restarting: false });
+    }
+}
+
+/// Starts a language server under `id`. Its messages arrive as `lsp-message`
+/// events; `lsp-exited` reports when it stops, and whether it's restarted.
+#[tauri::command]
+pub fn start_language_server(app: AppHandle, id: String, cmd: String, args: Vec<String>, cwd: Option<String>) -> Result<(), String> {
+    {
+        let state = app.state::<LanguageServers>();
+        let state = state.0.lock().unwrap();
+        if state.running.contains_key(&id) || state.restarting.contains(&id) {
+            return Err(format!("{} is already running", id));
+        }
+    }
+    spawn(&app, &id, Spec { cmd, args, cwd }, Vec::new())
+}
+
+/// Sends a JSON-RPC message to the server.
+#[tauri::command]
+pub fn send_lsp_message(app: AppHandle, id: String, payload: String) -> Result<(), String> {
+    let stdin = app.state::<LanguageServers>().0.lock().unwrap().running.get(&id).map(|s| s.stdin.clone());
+    let stdin = stdin.ok_or_else(|| format!("{} isn't running", id))?;
+    let mut stdin = stdin.lock().unwrap();
+    write_message(&mut *stdin, &payload).map_err(|e| e.to_string())
+}
+
+/// `shutdown`, then `exit`, then a kill for a server that ignores both.
+fn stop(app: &AppHandle, id: &str) {
+    let servers = app.state::<LanguageServers>();
+    let (ack, stdin, generation) = {
+        let mut state = servers.0.lock().unwrap();
+        state.restarting.remove(id);
+        let Some(server) = state.running.get_mut(id) else { return };
+        let (sender, receiver) = mpsc::channel();
+        server.shutdown_ack = Some(sender);
+        (receiver, server.stdin.clone(), server.generation)
+    };
+    let request = format!(r#"{{"jsonrpc":"2.0","id":"{}","method":"shutdown"}}"#, SHUTDOWN_ID);
+    if write_message(&mut *stdin.lock().unwrap(), &request).is_ok() && ack.recv_timeout(SHUTDOWN_TIMEOUT).is_err() {
+        log::warn!("[{}] No reply to shutdown", id);
+    }
+    let _ = write_message(&mut *stdin.lock().unwrap(), r#"{"jsonrpc":"2.0","method":"exit"}"#);
+
+    let is_running = || servers.0.lock().unwrap().running.get(id).is_some_and(|s| s.generation == generation);
+    let deadline = Instant::now() + EXIT_TIMEOUT;
+    while is_running() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let mut state = servers.0.lock().unwrap();
+    if let Some(server) = state.running.get_mut(id).filter(|s| s.generation == generation) {
+        log::warn!("[{}] Didn't exit, killing it", id);
+        let _ = server.child.kill();
+    }
+}
+
+#[tauri::command]
+pub async fn stop_language_server(app: AppHandle, id: String) -> Result<(), String> {
+    tauri::async_runtime::spawn_blocking(move || stop(&app, &id)).await.map_err(|e| e.to_string())
+}
+
+/// The ids of the servers running now.
+pub fn running(app: &AppHandle) -> Vec<String> {
+    app.state::<LanguageServers>().0.lock().unwrap().running.keys().cloned().collect()
+}
+
+/// Shuts every server down, in parallel, as the app quits.
+pub fn shutdown_all(app: &AppHandle) {
+    let ids: Vec<String> = app.state::<LanguageServers>().0.lock().unwrap().running.keys().cloned().collect();
+    let threads: Vec<_> = ids
+        .into_iter()
+        .map(|id| {
+            let app = app.clone();
+            std::thread::spawn(move || stop(&app, &id))
+        })
+        .collect();
+    for thread in threads {
+        let _ = thread.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_framed_messages() {
+        let input = "Content-Length: 2\r\n\r\n{}content-length:7\r\nContent-Type: application/vscode-jsonrpc; charset=utf-8\r\n\r\n{\"a\":1}";
+        let mut reader = input.as_bytes();
+        assert_eq!(read_message(&mut reader).unwrap().unwrap(), b"{}");
+        assert_eq!(read_message(&mut reader).unwrap().unwrap(), b"{\"a\":1}");
+        assert!(read_message(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_messages_without_a_length() {
+        assert!(read_message(&mut "Content-Type: x\r\n\r\n{}".as_bytes()).is_err());
+        assert!(read_message(&mut "Content-Length: ten\r\n\r\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn round_trips_multibyte_payloads() {
+        let mut framed = Vec::new();
+        write_message(&mut framed, r#"{"text":"åäö"}"#).unwrap();
+        let payload = read_message(&mut framed.as_slice()).unwrap().unwrap();
+        assert_eq!(payload, r#"{"text":"åäö"}"#.as_bytes());
+    }
+}
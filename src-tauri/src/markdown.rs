@@ -0,0 +1,414 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{mpsc, OnceLock};
+use std::time::Duration;
+
+use base64::Engine;
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd};
+use serde::Deserialize;
+use syntect::highlighting::ThemeSet;
+use syntect::html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+use tauri::webview::PageLoadEvent;
+use tauri::{AppHandle, WebviewUrl, WebviewWindowBuilder};
+
+use crate::notifications;
+use crate::paths;
+use crate::power;
+use crate::print::{self, PrintOptions};
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+const LOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Matches the editor's own colours in both themes
+const BASE_CSS: &str = r#"
+body { margin: 0 auto; padding: 2rem; max-width: 48rem; font: 16px/1.6 -apple-system, BlinkMacSystemFont, "Segoe UI", Roboto, Oxygen, Ubuntu, sans-serif; }
+body.light { background: #ffffff; color: #24292e; }
+body.dark { background: #1e1e1e; color: #d4d4d4; }
+a { color: #0366d6; }
+.dark a { color: #4fa8ff; }
+h1, h2 { padding-bottom: 0.3em; border-bottom: 1px solid #e1e4e8; }
+.dark h1, .dark h2 { border-color: #3c3c3c; }
+img { max-width: 100%; }
+code, pre { font-family: ui-monospace, SFMono-Regular, Menlo, Consolas, monospace; font-size: 0.875em; }
+code { padding: 0.15em 0.3em; border-radius: 4px; background: #f6f8fa; }
+.dark code { background: #2d2d2d; }
+pre { padding: 1em; overflow: auto; border-radius: 6px; background: #f6f8fa; }
+.dark pre { background: #252526; }
+pre code { padding: 0; background: none; }
+.dark pre code { background: none; }
+blockquote { margin: 0; padding: 0 1em; color: #6a737d; border-left: 4px solid #dfe2e5; }
+.dark blockquote { color: #9d9d9d; border-color: #3c3c3c; }
+table { border-collapse: collapse; }
+th, td { padding: 6px 13px; border: 1px solid #dfe2e5; }
+.dark th, .dark td { border-color: #3c3c3c; }
+li:has(> input[type=checkbox]) { list-style: none; }
+li > input[type=checkbox] { margin: 0 0.4em 0 -1.4em; }
+.footnote-definition { font-size: 0.875em; }
+.footnote-definition p { display: inline; }
+@media print { body { padding: 0; max-width: none; } pre { white-space: pre-wrap; } }
+"#;
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Html,
+    Pdf,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Light,
+    Dark,
+}
+
+/// What happens to images the document links to on disk.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Images {
+    /// Inlined as data URLs, so the HTML stands alone
+    #[default]
+    Embed,
+    /// Copied into a folder next to the HTML
+    Copy,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ExportOptions {
+    /// Relative image paths are resolved against this file's folder
+    pub document_path: Option<String>,
+    pub title: Option<String>,
+    pub theme: Theme,
+    /// Ignored for PDF, which always embeds
+    pub images: Images,
+    pub page: PrintOptions,
+}
+
+pub fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn highlight_css(theme: Theme) -> String {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    let themes = THEMES.get_or_init(ThemeSet::load_defaults);
+    let name = if theme == Theme::Dark { "base16-ocean.dark" } else { "InspiredGitHub" };
+    css_for_theme_with_class_style(&themes.themes[name], CLASS_STYLE).unwrap_or_default()
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    html::push_html(&mut escaped, std::iter::once(Event::Text(text.into())));
+    escaped
+}
+
+fn highlight(code: &str, language: &str) -> String {
+    let syntaxes = syntaxes();
+    // The bundled syntaxes have no TypeScript, which JavaScript covers well enough
+    let token = match language {
+        "ts" | "typescript" | "tsx" => "js",
+        other => other,
+    };
+    let class = if language.is_empty() { String::new() } else { format!(" class=\"language-{}\"", escape(language)) };
+    let syntax = if token.is_empty() { None } else { syntaxes.find_syntax_by_token(token) };
+    let body = match syntax {
+        Some(syntax) => {
+            let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, syntaxes, CLASS_STYLE);
+            let parsed = LinesWithEndings::from(code).try_for_each(|line| generator.parse_html_for_line_which_includes_newline(line));
+            match parsed {
+                Ok(()) => generator.finalize(),
+                Err(_) => escape(code),
+            }
+        }
+        None => escape(code),
+    };
+    format!("<pre><code{}>{}</code></pre>\n", class, body)
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "ico" => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+/// The file on disk an image destination points at, if it's a local one.
+fn local_image(dest: &str, base: &Path) -> Option<PathBuf> {
+    if dest.is_empty() || dest.starts_with('#') || dest.starts_with("//") || dest.contains("://") || dest.starts_with("data:") {
+        return None;
+    }
+    let path = dest.split(['?', '#']).next().unwrap_or(dest);
+    Some(base.join(percent_decode(path)))
+}
+
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok()).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+enum Assets<'a> {
+    Embed,
+    /// Copied into `dir`, which pages refer to as `href`
+    Copy { dir: &'a Path, href: &'a str, copied: HashMap<PathBuf, String> },
+}
+
+impl Assets<'_> {
+    /// Where the exported page should load the image from. Images that can't
+    /// be read keep their original link.
+    fn place(&mut self, source: &Path) -> Option<String> {
+        match self {
+            Assets::Embed => {
+                let bytes = std::fs::read(source).ok()?;
+                Some(format!("data:{};base64,{}", mime_type(source), base64::engine::general_purpose::STANDARD.encode(bytes)))
+            }
+            Assets::Copy { dir, href, copied } => {
+                if let Some(name) = copied.get(source) {
+                    return Some(format!("{}/{}", href, name));
+                }
+                let stem = source.file_stem()?.to_string_lossy().into_owned();
+                let extension = source.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+                // Same-named images from different folders get a number
+                let name = (1..)
+                    .map(|n| if n == 1 { format!("{}{}", stem, extension) } else { format!("{}-{}{}", stem, n, extension) })
+                    .find(|name| !copied.values().any(|taken| taken == name))?;
+                std::fs::create_dir_all(&dir).ok()?;
+                std::fs::copy(source, dir.join(&name)).ok()?;
+                copied.insert(source.to_path_buf(), name.clone());
+                Some(format!("{}/{}", href, name))
+            }
+        }
+    }
+}
+
+fn render_body(content: &str, base: Option<&Path>, assets: &mut Assets) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES | Options::ENABLE_TASKLISTS | Options::ENABLE_STRIKETHROUGH;
+    let mut events = Vec::new();
+    // Language and text of the fenced block being collected
+    let mut code: Option<(String, String)> = None;
+    for event in Parser::new_ext(content, options) {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => info.split([' ', ',', '{']).next().unwrap_or_default().to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code = Some((language, String::new()));
+            }
+            Event::Text(text) if code.is_some() => code.as_mut().unwrap().1.push_str(&text),
+            Event::End(TagEnd::CodeBlock) => {
+                let (language, text) = code.take().unwrap_or_default();
+                events.push(Event::Html(highlight(&text, &language).into()));
+            }
+            Event::Start(Tag::Image { link_type, dest_url, title, id }) => {
+                let placed = base.and_then(|base| local_image(&dest_url, base)).and_then(|source| {
+                    let placed = assets.place(&source);
+                    if placed.is_none() {
+                        log::warn!("Failed to include image {}", source.display());
+                    }
+                    placed
+                });
+                let dest_url = placed.map(CowStr::from).unwrap_or(dest_url);
+                events.push(Event::Start(Tag::Image { link_type, dest_url, title, id }));
+            }
+            other => events.push(other),
+        }
+    }
+    let mut body = String::with_capacity(content.len() * 3 / 2);
+    html::push_html(&mut body, events.into_iter());
+    body
+}
+
+fn safe_url(url: &str) -> bool {
+    let scheme = url.split_once(':').map(|(scheme, _)| scheme.to_ascii_lowercase());
+    matches!(scheme.as_deref(), Some("https" | "http" | "mailto"))
+}
+
+/// Release notes from the network as HTML that's safe to put in the page:
+/// raw HTML is shown as text and only web and mail links are kept.
+pub fn notes_html(content: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_TASKLISTS | Options::ENABLE_STRIKETHROUGH;
+    // Images are left as their alt text
+    let events = Parser::new_ext(content, options).filter_map(|event| match event {
+        Event::Html(text) | Event::InlineHtml(text) => Some(Event::Text(text)),
+        Event::Start(Tag::Link { link_type, dest_url, title, id }) => {
+            let dest_url = if safe_url(&dest_url) { dest_url } else { CowStr::from("") };
+            Some(Event::Start(Tag::Link { link_type, dest_url, title, id }))
+        }
+        Event::Start(Tag::Image { .. }) | Event::End(TagEnd::Image) => None,
+        other => Some(other),
+    });
+    let mut body = String::with_capacity(content.len() * 3 / 2);
+    html::push_html(&mut body, events);
+    body
+}
+
+/// A standalone page for `content`, with its stylesheet inlined.
+fn render_page(content: &str, opts: &ExportOptions, assets: &mut Assets) -> String {
+    let document = opts.document_path.as_deref().map(Path::new);
+    let base = document.and_then(Path::parent);
+    let title = opts
+        .title
+        .clone()
+        .or_else(|| document.and_then(Path::file_stem).map(|stem| stem.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    let body = render_body(content, base, assets);
+    let theme = if opts.theme == Theme::Dark { "dark" } else { "light" };
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n<title>{}</title>\n<style>{}\n{}</style>\n</head>\n<body class=\"{}\">\n{}</body>\n</html>\n",
+        escape(&title),
+        BASE_CSS,
+        highlight_css(opts.theme),
+        theme,
+        body
+    )
+}
+
+fn export_html(content: &str, opts: &ExportOptions, target: &Path) -> Result<(), String> {
+    let stem = target.file_stem().ok_or("Invalid export path")?.to_string_lossy().into_owned();
+    let href = format!("{}_files", stem);
+    let dir = target.with_file_name(&href);
+    let mut assets = match opts.images {
+        Images::Embed => Assets::Embed,
+        Images::Copy => Assets::Copy { dir: &dir, href: &href, copied: HashMap::new() },
+    };
+    let page = render_page(content, opts, &mut assets);
+    paths::write_atomic(target, page).map_err(|e| e.to_string())
+}
+
+/// Prints the page from a hidden window through the same pipeline as the
+/// editor's own PDF export.
+async fn export_pdf(app: &AppHandle, content: &str, opts: &ExportOptions, target: &Path) -> Result<(), String> {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let page_path = paths::temp_dir().join(format!("skriv-export-{}-{}.html", std::process::id(), id));
+    std::fs::write(&page_path, render_page(content, opts, &mut Assets::Embed)).map_err(|e| e.to_string())?;
+    let url = tauri::Url::from_file_path(&page_path).map_err(|_| "Invalid export path".to_string())?;
+
+    let (loaded, on_load) = mpsc::channel();
+    let label = format!("markdown-export-{}", id);
+    let built = WebviewWindowBuilder::new(app, &label, WebviewUrl::External(url))
+        .visible(false)
+        .on_page_load(move |_, payload| {
+            if matches!(payload.event(), PageLoadEvent::Finished) {
+                let _ = loaded.send(());
+            }
+        })
+        .build();
+    let window = match built {
+        Ok(window) => window,
+        Err(e) => {
+            let _ = std::fs::remove_file(&page_path);
+            return Err(e.to_string());
+        }
+    };
+    let tmp = paths::temp_beside(target);
+    let result = async {
+        tauri::async_runtime::spawn_blocking(move || on_load.recv_timeout(LOAD_TIMEOUT))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|_| "Timed out laying out the document".to_string())?;
+        print::render(app, &label, &tmp, opts.page).await?;
+        print::finish(&tmp, target)
+    }
+    .await;
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    let _ = window.destroy();
+    let _ = std::fs::remove_file(&page_path);
+    result
+}
+
+/// Renders markdown to a standalone HTML page or a PDF at `output_path`, and
+/// returns the path written. The file only appears once it's complete.
+#[tauri::command]
+pub async fn export_markdown(
+    app: AppHandle,
+    window: tauri::Window,
+    content: String,
+    format: ExportFormat,
+    options: Option<ExportOptions>,
+    output_path: String,
+) -> Result<String, String> {
+    let opts = options.unwrap_or_default();
+    let target = paths::decode(&output_path);
+    let _awake = power::hold(&app, "Exporting markdown");
+    match format {
+        ExportFormat::Html => {
+            let handle = tauri::async_runtime::spawn_blocking(move || export_html(&content, &opts, &target));
+            handle.await.map_err(|e| e.to_string())??;
+        }
+        ExportFormat::Pdf => export_pdf(&app, &content, &opts, &target).await?,
+    }
+    notifications::completed(&app, window.label(), "Export finished", &format!("Saved {}", output_path));
+    Ok(output_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_extensions() {
+        let body = render_body("| a |\n|---|\n| b |\n\n- [x] done\n\nNote[^1]\n\n[^1]: Here\n", None, &mut Assets::Embed);
+        assert!(body.contains("<table>"));
+        assert!(body.contains("type=\"checkbox\""));
+        assert!(body.contains("footnote-definition"));
+    }
+
+    #[test]
+    fn highlights_fenced_code() {
+        let body = render_body("```rust\nfn main() {}\n```\n", None, &mut Assets::Embed);
+        assert!(body.contains("class=\"language-rust\""));
+        assert!(body.contains("hl-"));
+        let plain = render_body("```nonsense\n<b>\n```\n", None, &mut Assets::Embed);
+        assert!(plain.contains("&lt;b&gt;"));
+    }
+
+    #[test]
+    fn resolves_local_images_only() {
+        let base = Path::new("/docs");
+        assert_eq!(local_image("img/a%20b.png", base), Some(PathBuf::from("/docs/img/a b.png")));
+        assert_eq!(local_image("https://example.com/a.png", base), None);
+        assert_eq!(local_image("data:image/png;base64,AA==", base), None);
+    }
+
+    #[test]
+    fn sanitizes_notes() {
+        let html = notes_html("<script>alert(1)</script>\n\n[ok](https://skriv.app) [bad](javascript:alert(1)) ![x](https://e.com/a.png) <b>hi</b>\n");
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(html.contains("href=\"https://skriv.app\""));
+        assert!(!html.contains("javascript:"));
+        assert!(!html.contains("<img"));
+        assert!(html.contains("&lt;b&gt;hi"));
+    }
+}
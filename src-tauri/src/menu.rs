@@ -0,0 +1,486 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::menu::{CheckMenuItem, IsMenuItem, Menu, MenuEvent, MenuItem, MenuItemKind, PredefinedMenuItem, Submenu};
+use tauri::{AppHandle, Emitter, Manager, WebviewWindow, Wry};
+
+use crate::bookmarks;
+use crate::clipboard;
+use crate::color_theme;
+use crate::document::{self, LineEnding, ENCODINGS, ENCODING_PREFIX, LINE_ENDING_ITEMS};
+use crate::i18n::Translations;
+use crate::keybindings::Keybindings;
+use crate::logging;
+use crate::languages::LanguageRegistry;
+use crate::menu_state::{self, LANGUAGE_PREFIX};
+use crate::print;
+use crate::recent_workspaces;
+#[cfg(target_os = "macos")]
+use crate::share;
+use crate::snippets;
+use crate::share::SHARE_PREFIX;
+use crate::templates;
+use crate::theme;
+use crate::transform;
+use crate::window;
+
+/// Every item of the menu last built, by id, so that syncing its state
+/// doesn't walk the tree for each one.
+#[derive(Default)]
+pub struct MenuItems(Mutex<HashMap<String, MenuItemKind<Wry>>>);
+
+pub fn build(app: &AppHandle, keys: &Keybindings) -> tauri::Result<Menu<Wry>> {
+    let state = menu_state::current(app);
+    let translations = app.state::<Mutex<Translations>>();
+    let tr = translations.lock().unwrap();
+    // Items are labelled by the translation keyed by their id
+    let item = |id: &str| MenuItem::with_id(app, id, tr.t(id), state.enabled(id), keys.accelerator(id));
+    let mode = theme::current_mode(app);
+    let color_theme = theme::chosen_color_theme(app);
+    let theme_item = |id: &str| {
+        let checked = color_theme.is_none() && theme::MENU_ITEMS.iter().any(|(i, m)| *i == id && *m == mode);
+        CheckMenuItem::with_id(app, id, tr.t(id), true, checked, keys.accelerator(id))
+    };
+    // A broken theme stays listed, greyed out, until its file is fixed
+    let color_theme_items = color_theme::list(app)
+        .into_iter()
+        .filter(|info| !info.builtin)
+        .map(|info| {
+            let id = format!("{}{}", color_theme::MENU_PREFIX, info.id);
+            let checked = color_theme.as_deref() == Some(info.id.as_str());
+            CheckMenuItem::with_id(app, id, &info.name, info.error.is_none(), checked, None::<&str>)
+        })
+        .collect::<tauri::Result<Vec<_>>>()?;
+    let separator = PredefinedMenuItem::separator(app)?;
+    let mut theme_refs: Vec<&dyn IsMenuItem<Wry>> = Vec::new();
+    let mode_items = [theme_item("theme_system")?, theme_item("theme_light")?, theme_item("theme_dark")?];
+    theme_refs.extend(mode_items.iter().map(|i| i as &dyn IsMenuItem<Wry>));
+    if !color_theme_items.is_empty() {
+        theme_refs.push(&separator);
+        theme_refs.extend(color_theme_items.iter().map(|i| i as &dyn IsMenuItem<Wry>));
+    }
+    let language_items = app
+        .state::<LanguageRegistry>()
+        .languages()
+        .iter()
+        .map(|lang| {
+            let id = format!("{}{}", LANGUAGE_PREFIX, lang.id);
+            let checked = state.language.as_deref() == Some(lang.id.as_str());
+            CheckMenuItem::with_id(app, id, &lang.name, true, checked, None::<&str>)
+        })
+        .collect::<tauri::Result<Vec<_>>>()?;
+    let language_refs: Vec<&dyn IsMenuItem<Wry>> = language_items.iter().map(|i| i as &dyn IsMenuItem<Wry>).collect();
+    let encoding_items = ENCODINGS
+        .iter()
+        .map(|(label, name)| {
+            let id = format!("{}{}", ENCODING_PREFIX, label);
+            let checked = state.encoding.as_deref().is_some_and(|e| e.eq_ignore_ascii_case(label));
+            CheckMenuItem::with_id(app, id, *name, true, checked, None::<&str>)
+        })
+        .collect::<tauri::Result<Vec<_>>>()?;
+    let line_ending_item = |id: &str| {
+        let checked = LINE_ENDING_ITEMS.iter().any(|(i, e)| *i == id && state.line_ending == Some(*e));
+        CheckMenuItem::with_id(app, id, tr.t(id), id != "line_ending_mixed", checked, keys.accelerator(id))
+    };
+    let encoding_refs: Vec<&dyn IsMenuItem<Wry>> = encoding_items.iter().map(|i| i as &dyn IsMenuItem<Wry>).collect();
+    // Like color themes, a template that can't be read stays listed, greyed out
+    let template_items = templates::list(app)
+        .into_iter()
+        .map(|info| MenuItem::with_id(app, format!("{}{}", templates::MENU_PREFIX, info.id), &info.name, info.error.is_none(), None::<&str>))
+        .collect::<tauri::Result<Vec<_>>>()?;
+    // Pinned workspaces, then the rest; those on a volume that isn't mounted are greyed out
+    let recent = recent_workspaces::list(app);
+    let recent_items = recent
+        .iter()
+        .map(|w| MenuItem::with_id(app, format!("{}{}", recent_workspaces::MENU_PREFIX, w.path), &w.name, w.available, None::<&str>))
+        .collect::<tauri::Result<Vec<_>>>()?;
+    let recent_header = MenuItem::with_id(app, "recent_workspaces", tr.t("recent_workspaces"), false, None::<&str>)?;
+    let (pins_separator, recent_separator, clear_recent) =
+        (PredefinedMenuItem::separator(app)?, PredefinedMenuItem::separator(app)?, item("clear_recent_workspaces")?);
+    let mut recent_refs: Vec<&dyn IsMenuItem<Wry>> = vec![&recent_header];
+    for (i, item) in recent_items.iter().enumerate() {
+        if i > 0 && recent[i - 1].pinned && !recent[i].pinned {
+            recent_refs.push(&pins_separator);
+        }
+        recent_refs.push(item);
+    }
+    recent_refs.push(&recent_separator);
+    recent_refs.push(&clear_recent);
+    // Global bookmarks, then those of each open workspace under its name
+    let bookmark_sections = bookmarks::for_menu(app);
+    let bookmark_headers = bookmark_sections
+        .iter()
+        .filter_map(|(root, _)| root.as_ref())
+        .map(|root| {
+            let name = root.file_name().map_or(root.to_string_lossy(), |n| n.to_string_lossy());
+            MenuItem::new(app, name, false, None::<&str>)
+        })
+        .collect::<tauri::Result<Vec<_>>>()?;
+    let bookmark_items = bookmark_sections
+        .iter()
+        .map(|(_, section)| {
+            section
+                .iter()
+                .map(|b| MenuItem::with_id(app, format!("{}{}", bookmarks::MENU_PREFIX, b.id), bookmarks::title(b), true, None::<&str>))
+                .collect::<tauri::Result<Vec<_>>>()
+        })
+        .collect::<tauri::Result<Vec<_>>>()?;
+    let bookmark_separators = (0..bookmark_sections.len()).map(|_| PredefinedMenuItem::separator(app)).collect::<tauri::Result<Vec<_>>>()?;
+    let (add_bookmark, add_workspace_bookmark) = (item("add_bookmark")?, item("add_workspace_bookmark")?);
+    let mut bookmark_refs: Vec<&dyn IsMenuItem<Wry>> = vec![&add_bookmark, &add_workspace_bookmark];
+    let mut headers = bookmark_headers.iter();
+    for (((root, _), items), separator) in bookmark_sections.iter().zip(&bookmark_items).zip(&bookmark_separators) {
+        bookmark_refs.push(separator);
+        if let Some(header) = root.as_ref().and(headers.next()) {
+            bookmark_refs.push(header);
+        }
+        bookmark_refs.extend(items.iter().map(|i| i as &dyn IsMenuItem<Wry>));
+    }
+    let (template_separator, open_templates_folder) = (PredefinedMenuItem::separator(app)?, item("open_templates_folder")?);
+    let mut template_refs: Vec<&dyn IsMenuItem<Wry>> = template_items.iter().map(|i| i as &dyn IsMenuItem<Wry>).collect();
+    template_refs.push(&template_separator);
+    template_refs.push(&open_templates_folder);
+
+    let app_menu = Submenu::with_items(app, "skriv", true, &[
+        &PredefinedMenuItem::about(app, Some(&tr.t("about")), None)?,
+        &PredefinedMenuItem::separator(app)?,
+        &item("open_snippets_folder")?,
+        &PredefinedMenuItem::separator(app)?,
+        &PredefinedMenuItem::services(app, Some(&tr.t("services")))?,
+        &PredefinedMenuItem::separator(app)?,
+        &PredefinedMenuItem::hide(app, Some(&tr.t("hide")))?,
+        &PredefinedMenuItem::hide_others(app, Some(&tr.t("hide_others")))?,
+        &PredefinedMenuItem::show_all(app, Some(&tr.t("show_all")))?,
+        &PredefinedMenuItem::separator(app)?,
+        &PredefinedMenuItem::quit(app, Some(&tr.t("quit")))?,
+    ])?;
+    let file_menu = Submenu::with_items(app, tr.t("menu_file"), true, &[
+        &item("new_tab")?,
+        &item("new_window")?,
+        &Submenu::with_id_and_items(app, "new_from_template", tr.t("new_from_template"), true, &template_refs)?,
+        &item("open_file")?,
+        &Submenu::with_id_and_items(app, "open_recent", tr.t("open_recent"), true, &recent_refs)?,
+        &Submenu::with_id_and_items(app, "bookmarks", tr.t("bookmarks"), true, &bookmark_refs)?,
+        &item("open_url_from_clipboard")?,
+        &item("download_url_from_clipboard")?,
+        &PredefinedMenuItem::separator(app)?,
+        &item("save_file")?,
+        &item("save_file_as")?,
+        &item("compare_with_saved")?,
+        &item("compare_folders")?,
+        &PredefinedMenuItem::separator(app)?,
+        &Submenu::with_id_and_items(
+            app,
+            "reopen_with_encoding",
+            tr.t("reopen_with_encoding"),
+            state.enabled("reopen_with_encoding"),
+            &encoding_refs,
+        )?,
+        &Submenu::with_id_and_items(app, "line_endings", tr.t("line_endings"), state.enabled("line_endings"), &[
+            &line_ending_item("line_ending_lf")?,
+            &line_ending_item("line_ending_crlf")?,
+            &line_ending_item("line_ending_mixed")?,
+        ])?,
+        &PredefinedMenuItem::separator(app)?,
+        &item("open_terminal_here")?,
+        #[cfg(target_os = "macos")]
+        &share::build_submenu(app, &tr.t("menu_share"), &state)?,
+        &Submenu::with_items(app, tr.t("menu_share_online"), true, &[
+            &item("create_secret_gist")?,
+            &item("create_public_gist")?,
+            &item("create_paste")?,
+            &PredefinedMenuItem::separator(app)?,
+            &item("set_gist_token")?,
+        ])?,
+        &item("export_html")?,
+        &item("export_pdf")?,
+        &item("print")?,
+        &PredefinedMenuItem::separator(app)?,
+        &PredefinedMenuItem::close_window(app, Some(&tr.t("close_window")))?,
+    ])?;
+    let transform_items = transform::MENU_ITEMS.iter().map(|(id, _)| item(id)).collect::<tauri::Result<Vec<_>>>()?;
+    let transform_refs: Vec<&dyn IsMenuItem<Wry>> = transform_items.iter().map(|i| i as &dyn IsMenuItem<Wry>).collect();
+    let edit_menu = Submenu::with_items(app, tr.t("menu_edit"), true, &[
+        &PredefinedMenuItem::undo(app, Some(&tr.t("undo")))?,
+        &PredefinedMenuItem::redo(app, Some(&tr.t("redo")))?,
+        &PredefinedMenuItem::separator(app)?,
+        &PredefinedMenuItem::cut(app, Some(&tr.t("cut")))?,
+        &PredefinedMenuItem::copy(app, Some(&tr.t("copy")))?,
+        &PredefinedMenuItem::paste(app, Some(&tr.t("paste")))?,
+        &PredefinedMenuItem::select_all(app, Some(&tr.t("select_all")))?,
+        &PredefinedMenuItem::separator(app)?,
+        &item("copy_path_with_line")?,
+        &item("paste_relative_path")?,
+        &PredefinedMenuItem::separator(app)?,
+        &item("toggle_comment")?,
+        &item("format_document")?,
+        &item("column_selection")?,
+        &PredefinedMenuItem::separator(app)?,
+        &Submenu::with_id_and_items(app, "transform", tr.t("transform"), state.enabled("transform"), &transform_refs)?,
+    ])?;
+    let selection_menu = Submenu::with_items(app, tr.t("menu_selection"), true, &[
+        &item("move_line_up")?,
+        &item("move_line_down")?,
+        &item("duplicate_line")?,
+        &item("join_lines")?,
+        &PredefinedMenuItem::separator(app)?,
+        &item("sort_lines_ascending")?,
+        &item("sort_lines_descending")?,
+        &item("delete_duplicate_lines")?,
+        &item("reverse_lines")?,
+        &item("shuffle_lines")?,
+        &item("number_lines")?,
+    ])?;
+    let find_menu = Submenu::with_items(app, tr.t("menu_find"), true, &[
+        &item("find_open")?,
+        &item("find_next")?,
+        &item("find_previous")?,
+        &item("find_use_selection")?,
+        &PredefinedMenuItem::separator(app)?,
+        &item("find_replace")?,
+        &PredefinedMenuItem::separator(app)?,
+        &item("find_in_files")?,
+    ])?;
+    let view_menu = Submenu::with_items(app, tr.t("menu_view"), true, &[
+        &item("command_palette")?,
+        &PredefinedMenuItem::separator(app)?,
+        &item("split_view")?,
+        &PredefinedMenuItem::separator(app)?,
+        &Submenu::with_items(app, tr.t("menu_syntax"), true, &language_refs)?,
+        &PredefinedMenuItem::separator(app)?,
+        &item("word_wrap")?,
+        &Submenu::with_items(app, tr.t("menu_theme"), true, &theme_refs)?,
+        &PredefinedMenuItem::separator(app)?,
+        &PredefinedMenuItem::fullscreen(app, Some(&tr.t("fullscreen")))?,
+    ])?;
+    // AppKit calls it Zoom
+    let maximize = if cfg!(target_os = "macos") { tr.t("zoom") } else { tr.t("maximize") };
+    let window_menu = Submenu::with_items(app, tr.t("menu_window"), true, &[
+        &PredefinedMenuItem::minimize(app, Some(&tr.t("minimize")))?,
+        &PredefinedMenuItem::maximize(app, Some(&maximize))?,
+    ])?;
+    let level_items = logging::MENU_ITEMS.iter().map(|(id, _)| item(id)).collect::<tauri::Result<Vec<_>>>()?;
+    let (logs_separator, open_logs, clear_logs) = (PredefinedMenuItem::separator(app)?, item("open_logs_folder")?, item("clear_logs")?);
+    let mut troubleshooting_refs: Vec<&dyn IsMenuItem<Wry>> = level_items.iter().map(|i| i as &dyn IsMenuItem<Wry>).collect();
+    troubleshooting_refs.extend([&logs_separator as &dyn IsMenuItem<Wry>, &open_logs, &clear_logs]);
+    // Not in the keybindings; they're for bug reports
+    let help_menu = Submenu::with_items(app, tr.t("menu_help"), true, &[
+        &item("create_diagnostics_bundle")?,
+        &item("copy_diagnostics")?,
+        &PredefinedMenuItem::separator(app)?,
+        &Submenu::with_items(app, tr.t("menu_troubleshooting"), true, &troubleshooting_refs)?,
+    ])?;
+    let menu = Menu::with_items(app, &[&app_menu, &file_menu, &edit_menu, &selection_menu, &find_menu, &view_menu, &window_menu, &help_menu])?;
+    let mut items = HashMap::new();
+    index(menu.items()?, &mut items);
+    *app.state::<MenuItems>().0.lock().unwrap() = items;
+    Ok(menu)
+}
+
+pub fn handle_event(app: &AppHandle, event: MenuEvent) {
+    let id = event.id();
+    match id.as_ref() {
+        "command_palette" => emit_to_focused(app, "menu-command-palette", ()),
+        "word_wrap" => emit_to_focused(app, "menu-word-wrap", ()),
+        "toggle_comment" => emit_to_focused(app, "menu-toggle-comment", ()),
+        "new_tab" => emit_to_focused(app, "menu-new-tab", ()),
+        "open_file" => emit_to_focused(app, "menu-open-file", ()),
+        // The window says so if it isn't a URL
+        "create_secret_gist" => emit_to_focused(app, "menu-create-share", CreateSharePayload { service: "gist", visibility: "secret" }),
+        "create_public_gist" => emit_to_focused(app, "menu-create-share", CreateSharePayload { service: "gist", visibility: "public" }),
+        "create_paste" => emit_to_focused(app, "menu-create-share", CreateSharePayload { service: "paste", visibility: "secret" }),
+        "set_gist_token" => emit_to_focused(app, "menu-set-gist-token", ()),
+        "open_url_from_clipboard" => emit_to_focused(app, "menu-open-url", clipboard::read_text().ok().flatten().unwrap_or_default()),
+        "download_url_from_clipboard" => emit_to_focused(app, "menu-download-url", clipboard::read_text().ok().flatten().unwrap_or_default()),
+        "save_file" => emit_to_focused(app, "menu-save-file", ()),
+        "save_file_as" => emit_to_focused(app, "menu-save-file-as", ()),
+        "format_document" => emit_to_focused(app, "menu-format-document", ()),
+        "column_selection" => emit_to_focused(app, "menu-column-selection", ()),
+        "split_view" => emit_to_focused(app, "menu-split-view", ()),
+        "move_line_up" => emit_to_focused(app, "menu-move-line-up", ()),
+        "move_line_down" => emit_to_focused(app, "menu-move-line-down", ()),
+        "duplicate_line" => emit_to_focused(app, "menu-duplicate-line", ()),
+        "join_lines" => emit_to_focused(app, "menu-join-lines", ()),
+        "sort_lines_ascending" => emit_to_focused(app, "menu-sort-lines-ascending", ()),
+        "sort_lines_descending" => emit_to_focused(app, "menu-sort-lines-descending", ()),
+        "delete_duplicate_lines" => emit_to_focused(app, "menu-delete-duplicate-lines", ()),
+        "reverse_lines" => emit_to_focused(app, "menu-reverse-lines", ()),
+        "shuffle_lines" => emit_to_focused(app, "menu-shuffle-lines", ()),
+        "number_lines" => emit_to_focused(app, "menu-number-lines", ()),
+        "find_open" => emit_to_focused(app, "menu-find-open", ()),
+        "find_next" => emit_to_focused(app, "menu-find-next", ()),
+        "find_previous" => emit_to_focused(app, "menu-find-previous", ()),
+        "find_use_selection" => emit_to_focused(app, "menu-find-use-selection", ()),
+        "find_replace" => emit_to_focused(app, "menu-find-replace", ()),
+        "find_in_files" => emit_to_focused(app, "menu-find-in-files", ()),
+        "export_html" => emit_to_focused(app, "menu-export-html", ()),
+        "open_terminal_here" => emit_to_focused(app, "menu-open-terminal-here", ()),
+        "compare_with_saved" => emit_to_focused(app, "menu-compare-with-saved", ()),
+        "compare_folders" => emit_to_focused(app, "menu-compare-folders", ()),
+        "copy_path_with_line" => emit_to_focused(app, "menu-copy-path-with-line", ()),
+        "paste_relative_path" => emit_to_focused(app, "menu-paste-relative-path", ()),
+        "export_pdf" => emit_to_focused(app, "menu-export-pdf", ()),
+        "create_diagnostics_bundle" => emit_to_focused(app, "menu-create-diagnostics-bundle", ()),
+        "copy_diagnostics" => emit_to_focused(app, "menu-copy-diagnostics", ()),
+        "clear_logs" => emit_to_focused(app, "menu-clear-logs", ()),
+        "print" => {
+            if let Some(window) = focused_window(app) {
+                if let Err(e) = print::print(app, window.label()) {
+                    log::warn!("Failed to print: {}", e);
+                }
+            }
+        }
+        "open_logs_folder" => {
+            if let Err(e) = logging::open_logs_folder(app.clone()) {
+                log::warn!("Failed to open the logs folder: {}", e);
+            }
+        }
+        "open_snippets_folder" => {
+            if let Err(e) = snippets::open_folder(app) {
+                log::warn!("Failed to open the snippets folder: {}", e);
+            }
+        }
+        "add_bookmark" => emit_to_focused(app, "menu-add-bookmark", "global"),
+        "add_workspace_bookmark" => emit_to_focused(app, "menu-add-bookmark", "workspace"),
+        "clear_recent_workspaces" => {
+            if let Err(e) = recent_workspaces::clear_recent_workspaces(app.clone()) {
+                log::warn!("Failed to clear the recent workspaces: {}", e);
+            }
+        }
+        "open_templates_folder" => {
+            if let Err(e) = templates::open_folder(app) {
+                log::warn!("Failed to open the templates folder: {}", e);
+            }
+        }
+        "new_window" => {
+            if let Err(e) = window::open_new(app) {
+                log::warn!("Failed to open window: {}", e);
+            }
+        }
+        other => {
+            if let Some(service) = other.strip_prefix(SHARE_PREFIX) {
+                emit_to_focused(app, "menu-share", SharePayload { service: service.to_string() });
+            } else if let Some(label) = other.strip_prefix(ENCODING_PREFIX) {
+                menu_state::sync(app);
+                document::reopen_active(app, label);
+            } else if let Some(language) = other.strip_prefix(LANGUAGE_PREFIX) {
+                // Undo the native check toggle; the frontend confirms via update_menu_state
+                menu_state::sync(app);
+                emit_to_focused(app, "set-document-language", LanguagePayload { id: language.to_string() });
+            } else if let Some((_, target)) = LINE_ENDING_ITEMS.iter().find(|(i, _)| *i == other) {
+                // Like Syntax, the checkmark follows the document once the frontend converts it
+                menu_state::sync(app);
+                if *target != LineEnding::Mixed {
+                    emit_to_focused(app, "convert-line-endings", LineEndingPayload { target: *target });
+                }
+            } else if let Some(path) = other.strip_prefix(recent_workspaces::MENU_PREFIX) {
+                // Option-clicked, it opens in a new window
+                let focused = focused_window(app);
+                let label = focused.as_ref().map(|w| w.label());
+                if let Err(e) = recent_workspaces::open(app, label, path.to_string(), recent_workspaces::alternate_click()) {
+                    log::warn!("Failed to open workspace {}: {}", path, e);
+                }
+            } else if let Some(id) = other.strip_prefix(bookmarks::MENU_PREFIX) {
+                let focused = focused_window(app);
+                if let Err(e) = bookmarks::open(app, focused.as_ref().map(|w| w.label()), id) {
+                    log::warn!("Failed to open bookmark {}: {}", id, e);
+                }
+            } else if let Some(id) = other.strip_prefix(templates::MENU_PREFIX) {
+                match templates::instantiate(app, id, Default::default()) {
+                    Ok(instantiated) => emit_to_focused(app, "new-from-template", instantiated),
+                    Err(e) => log::warn!("Failed to fill in template {}: {}", id, e),
+                }
+            } else if let Some(id) = other.strip_prefix(color_theme::MENU_PREFIX) {
+                if let Err(e) = theme::pick_color_theme(app, Some(id.to_string())) {
+                    log::warn!("Failed to set color theme: {}", e);
+                    theme::mode_changed(app, theme::current_mode(app));
+                }
+            } else if let Some((_, level)) = logging::MENU_ITEMS.iter().find(|(i, _)| *i == other) {
+                if let Err(e) = logging::set_log_level(app.clone(), None, *level) {
+                    log::warn!("Failed to set the log level: {}", e);
+                }
+            } else if let Some((_, mode)) = theme::MENU_ITEMS.iter().find(|(i, _)| *i == other) {
+                if let Err(e) = theme::set_mode(app, *mode) {
+                    log::warn!("Failed to set theme mode: {}", e);
+                }
+            } else if let Some((_, op)) = transform::MENU_ITEMS.iter().find(|(i, _)| *i == other) {
+                emit_to_focused(app, "menu-transform", *op);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct LanguagePayload {
+    id: String,
+}
+
+#[derive(Clone, Serialize)]
+struct SharePayload {
+    service: String,
+}
+
+#[derive(Clone, Serialize)]
+struct CreateSharePayload {
+    service: &'static str,
+    visibility: &'static str,
+}
+
+#[derive(Clone, Serialize)]
+struct LineEndingPayload {
+    target: LineEnding,
+}
+
+/// Builds the menu again for lists it shows that changed, like templates.
+pub fn rebuild(app: &AppHandle) {
+    let keys = app.state::<Mutex<Keybindings>>().lock().unwrap().clone();
+    match build(app, &keys) {
+        Ok(built) => {
+            let _ = app.set_menu(built);
+        }
+        Err(e) => log::warn!("Failed to rebuild the menu: {}", e),
+    }
+}
+
+pub fn focused_window(app: &AppHandle) -> Option<WebviewWindow> {
+    app.webview_windows().into_values().find(|w| w.is_focused().unwrap_or(false))
+}
+
+/// Emits to the focused window, or to every window when none has focus.
+pub fn emit_to_focused<S: Serialize + Clone>(app: &AppHandle, event: &str, payload: S) {
+    let _ = match focused_window(app) {
+        Some(window) => app.emit_to(window.label(), event, payload),
+        None => app.emit(event, payload),
+    };
+}
+
+fn index(items: Vec<MenuItemKind<Wry>>, found: &mut HashMap<String, MenuItemKind<Wry>>) {
+    for item in items {
+        let submenu = item.as_submenu().cloned();
+        found.entry(item.id().0.clone()).or_insert(item);
+        if let Some(submenu) = submenu {
+            index(submenu.items().unwrap_or_default(), found);
+        }
+    }
+}
+
+/// Looks up an item anywhere in the app menu, including nested submenus.
+pub fn find_item(app: &AppHandle, id: &str) -> Option<MenuItemKind<Wry>> {
+    app.try_state::<MenuItems>()?.0.lock().unwrap().get(id).cloned()
+}
+
+pub fn set_enabled(app: &AppHandle, id: &str, enabled: bool) {
+    let _ = match find_item(app, id) {
+        Some(MenuItemKind::MenuItem(item)) => item.set_enabled(enabled),
+        Some(MenuItemKind::Check(item)) => item.set_enabled(enabled),
+        Some(MenuItemKind::Submenu(item)) => item.set_enabled(enabled),
+        _ => Ok(()),
+    };
+}
+
+pub fn set_checked(app: &AppHandle, id: &str, checked: bool) {
+    if let Some(MenuItemKind::Check(item)) = find_item(app, id) {
+        let _ = item.set_checked(checked);
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::Deserialize;
+use tauri::{AppHandle, Manager};
+
+use crate::document::{LineEnding, ENCODINGS, ENCODING_PREFIX, LINE_ENDING_ITEMS};
+use crate::languages::LanguageRegistry;
+use crate::menu;
+use crate::window::{self, WindowKind};
+
+pub const LANGUAGE_PREFIX: &str = "language:";
+
+// Items that act on the editor, so they're off while a non-editor window has focus.
+// Everything in the lists below needs an editor too.
+const EDITOR_ITEMS: &[&str] = &["save_file", "save_file_as", "toggle_comment", "format_document", "column_selection"];
+
+// Items that only make sense with an open document.
+const DOCUMENT_ITEMS: &[&str] = &[
+    "find_open",
+    "find_next",
+    "find_previous",
+    "find_use_selection",
+    "find_replace",
+    "move_line_up",
+    "move_line_down",
+    "duplicate_line",
+    "join_lines",
+    "sort_lines_ascending",
+    "sort_lines_descending",
+    "delete_duplicate_lines",
+    "reverse_lines",
+    "shuffle_lines",
+    "number_lines",
+    "line_endings",
+    "transform",
+    "export_pdf",
+    "print",
+    "share",
+    "create_secret_gist",
+    "create_public_gist",
+    "create_paste",
+];
+// Items that need a document backed by a file on disk.
+const FILE_ITEMS: &[&str] = &["reopen_with_encoding", "open_terminal_here", "copy_path_with_line", "paste_relative_path", "add_bookmark"];
+// Items that need a file-backed document with unsaved changes.
+const DIRTY_FILE_ITEMS: &[&str] = &["compare_with_saved"];
+// Items for markdown documents only.
+const MARKDOWN_ITEMS: &[&str] = &["export_html"];
+// Items that need an open workspace folder.
+const WORKSPACE_ITEMS: &[&str] = &["find_in_files", "add_workspace_bookmark"];
+
+/// What a window's frontend reports about its active document, so stateful
+/// menu items can reflect it.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct MenuState {
+    /// Set from the focused window's kind rather than reported by the frontend.
+    #[serde(skip)]
+    pub editor: bool,
+    pub language: Option<String>,
+    pub path: Option<String>,
+    pub encoding: Option<String>,
+    pub line_ending: Option<LineEnding>,
+    pub has_document: bool,
+    pub has_workspace: bool,
+    /// The document has unsaved changes
+    pub dirty: bool,
+}
+
+impl MenuState {
+    pub fn enabled(&self, id: &str) -> bool {
+        if EDITOR_ITEMS.contains(&id) {
+            self.editor
+        } else if !self.editor && [DOCUMENT_ITEMS, FILE_ITEMS, DIRTY_FILE_ITEMS, MARKDOWN_ITEMS, WORKSPACE_ITEMS].iter().any(|items| items.contains(&id)) {
+            false
+        } else if DOCUMENT_ITEMS.contains(&id) {
+            self.has_document
+        } else if FILE_ITEMS.contains(&id) {
+            self.path.is_some()
+        } else if DIRTY_FILE_ITEMS.contains(&id) {
+            self.path.is_some() && self.dirty
+        } else if MARKDOWN_ITEMS.contains(&id) {
+            self.has_document && self.language.as_deref() == Some("markdown")
+        } else if WORKSPACE_ITEMS.contains(&id) {
+            self.has_workspace
+        } else {
+            true
+        }
+    }
+}
+
+/// Each window's last reported state; the menu shows the focused window's.
+#[derive(Default)]
+pub struct MenuStates {
+    windows: HashMap<String, MenuState>,
+    focused: Option<String>,
+}
+
+pub fn current(app: &AppHandle) -> MenuState {
+    let states = app.state::<Mutex<MenuStates>>();
+    let states = states.lock().unwrap();
+    let Some(label) = &states.focused else { return MenuState::default() };
+    let mut state = states.windows.get(label).cloned().unwrap_or_default();
+    state.editor = window::kind(app, label) == WindowKind::Editor;
+    state
+}
+
+/// The window whose active document is `path`, if any.
+pub fn window_showing(app: &AppHandle, path: &str) -> Option<String> {
+    let states = app.try_state::<Mutex<MenuStates>>()?;
+    let states = states.lock().unwrap();
+    states.windows.iter().find(|(_, state)| state.path.as_deref() == Some(path)).map(|(label, _)| label.clone())
+}
+
+pub fn focus_changed(app: &AppHandle, label: &str) {
+    app.state::<Mutex<MenuStates>>().lock().unwrap().focused = Some(label.to_string());
+    sync(app);
+}
+
+pub fn window_destroyed(app: &AppHandle, label: &str) {
+    {
+        let states = app.state::<Mutex<MenuStates>>();
+        let mut states = states.lock().unwrap();
+        states.windows.remove(label);
+        if states.focused.as_deref() == Some(label) {
+            states.focused = None;
+        }
+    }
+    sync(app);
+}
+
+pub fn sync(app: &AppHandle) {
+    let state = current(app);
+    for (id, ending) in LINE_ENDING_ITEMS {
+        menu::set_checked(app, id, state.line_ending == Some(*ending));
+    }
+    for id in EDITOR_ITEMS.iter().chain(DOCUMENT_ITEMS).chain(FILE_ITEMS).chain(DIRTY_FILE_ITEMS).chain(MARKDOWN_ITEMS).chain(WORKSPACE_ITEMS) {
+        menu::set_enabled(app, id, state.enabled(id));
+    }
+    for lang in app.state::<LanguageRegistry>().languages() {
+        let id = format!("{}{}", LANGUAGE_PREFIX, lang.id);
+        menu::set_checked(app, &id, state.language.as_deref() == Some(lang.id.as_str()));
+    }
+    #[cfg(target_os = "macos")]
+    crate::share::refresh(app, &state);
+    for (label, _) in ENCODINGS {
+        let id = format!("{}{}", ENCODING_PREFIX, label);
+        menu::set_checked(app, &id, state.encoding.as_deref().is_some_and(|e| e.eq_ignore_ascii_case(label)));
+    }
+}
+
+#[tauri::command]
+pub fn update_menu_state(app: AppHandle, window: tauri::Window, state: MenuState) {
+    let label = window.label().to_string();
+    let focused = {
+        let states = app.state::<Mutex<MenuStates>>();
+        let mut states = states.lock().unwrap();
+        // Until a focus event arrives, assume the reporting window is in front
+        let focused = states.focused.get_or_insert_with(|| label.clone()) == &label;
+        states.windows.insert(label, state);
+        focused
+    };
+    if focused {
+        sync(&app);
+    }
+}
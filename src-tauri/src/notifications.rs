@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::menu;
+use crate::settings::Settings;
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+// Longer tags are rejected by Windows
+const MAX_ID_BYTES: usize = 64;
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct NotifyOptions {
+    /// A later notification with the same id replaces this one rather than
+    /// stacking up
+    pub id: Option<String>,
+    /// The window a click focuses; the calling one by default
+    pub window: Option<String>,
+    /// Only shown if `notifyWhenDone` is on and no skriv window is focused,
+    /// as for the notices skriv sends itself
+    pub when_unfocused: bool,
+}
+
+#[derive(Clone, Serialize)]
+struct Clicked<'a> {
+    id: &'a str,
+}
+
+/// The window each notification belongs to, by id.
+#[derive(Default)]
+pub struct Notifications(Mutex<HashMap<String, String>>);
+
+/// Shows a system notification and returns its id.
+pub fn send(app: &AppHandle, title: &str, body: &str, id: Option<String>, label: &str) -> Result<String, String> {
+    let id = match id {
+        Some(id) if id.is_empty() || id.len() > MAX_ID_BYTES => {
+            return Err(format!("A notification id must be 1 to {} bytes", MAX_ID_BYTES));
+        }
+        Some(id) => id,
+        None => format!("skriv-{}", NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+    };
+    platform::supported()?;
+    app.state::<Notifications>().0.lock().unwrap().insert(id.clone(), label.to_string());
+    let (handle, shown, title, body) = (app.clone(), id.clone(), title.to_string(), body.to_string());
+    // The native APIs all want the main thread
+    app.run_on_main_thread(move || {
+        if let Err(e) = platform::show(&handle, &shown, &title, &body) {
+            log::warn!("Failed to show a notification: {}", e);
+        }
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(id)
+}
+
+fn wanted_now(app: &AppHandle) -> bool {
+    app.state::<Mutex<Settings>>().lock().unwrap().notify_when_done
+        && !app.webview_windows().values().any(|w| w.is_focused().unwrap_or(false))
+}
+
+/// Tells the user something finished while they were in another app, if
+/// they've asked for that.
+pub fn completed(app: &AppHandle, label: &str, title: &str, body: &str) {
+    if !wanted_now(app) {
+        return;
+    }
+    if let Err(e) = send(app, title, body, None, label) {
+        log::warn!("Failed to show a notification: {}", e);
+    }
+}
+
+/// Focuses the notification's window and tells it which one was clicked.
+pub fn clicked(app: &AppHandle, id: &str) {
+    let label = app.state::<Notifications>().0.lock().unwrap().remove(id);
+    let window = label
+        .and_then(|label| app.get_webview_window(&label))
+        .or_else(|| menu::focused_window(app))
+        .or_else(|| app.webview_windows().into_values().next());
+    let Some(window) = window else { return };
+    let _ = window.unminimize();
+    let _ = window.show();
+    let _ = window.set_focus();
+    let _ = app.emit_to(window.label(), "notification-clicked", Clicked { id });
+}
+
+/// Shows a notification; clicking it emits `notification-clicked` to the
+/// window. Returns the id, or `None` when `whenUnfocused` held it back.
+#[tauri::command]
+pub fn notify(
+    app: AppHandle,
+    window: tauri::Window,
+    title: String,
+    body: String,
+    opts: Option<NotifyOptions>,
+) -> Result<Option<String>, String> {
+    let opts = opts.unwrap_or_default();
+    if opts.when_unfocused && !wanted_now(&app) {
+        return Ok(None);
+    }
+    let label = opts.window.unwrap_or_else(|| window.label().to_string());
+    send(&app, &title, &body, opts.id, &label).map(Some)
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::cell::RefCell;
+
+    use block2::{DynBlock, RcBlock};
+    use objc2::rc::Retained;
+    use objc2::runtime::{Bool, NSObject, NSObjectProtocol, ProtocolObject};
+    use objc2::{define_class, msg_send, AllocAnyThread, DefinedClass};
+    use objc2_foundation::{NSBundle, NSError, NSString};
+    use objc2_user_notifications::{
+        UNAuthorizationOptions, UNMutableNotificationContent, UNNotification, UNNotificationPresentationOptions,
+        UNNotificationRequest, UNNotificationResponse, UNNotificationSound, UNUserNotificationCenter,
+        UNUserNotificationCenterDelegate,
+    };
+    use tauri::AppHandle;
+
+    define_class!(
+        // SAFETY: NSObject has no subclassing requirements and the class has no Drop impl
+        #[unsafe(super(NSObject))]
+        #[name = "SkrivNotificationDelegate"]
+        #[ivars = AppHandle]
+        struct Delegate;
+
+        unsafe impl NSObjectProtocol for Delegate {}
+
+        unsafe impl UNUserNotificationCenterDelegate for Delegate {
+            #[unsafe(method(userNotificationCenter:willPresentNotification:withCompletionHandler:))]
+            fn will_present(
+                &self,
+                _center: &UNUserNotificationCenter,
+                _notification: &UNNotification,
+                handler: &DynBlock<dyn Fn(UNNotificationPresentationOptions)>,
+            ) {
+                // Otherwise hidden while skriv is the active app
+                handler.call((UNNotificationPresentationOptions::Banner | UNNotificationPresentationOptions::List,));
+            }
+
+            #[unsafe(method(userNotificationCenter:didReceiveNotificationResponse:withCompletionHandler:))]
+            fn did_receive(
+                &self,
+                _center: &UNUserNotificationCenter,
+                response: &UNNotificationResponse,
+                handler: &DynBlock<dyn Fn()>,
+            ) {
+                let id = response.notification().request().identifier().to_string();
+                super::clicked(self.ivars(), &id);
+                handler.call(());
+            }
+        }
+    );
+
+    thread_local! {
+        // The notification center doesn't retain its delegate
+        static DELEGATE: RefCell<Option<Retained<Delegate>>> = const { RefCell::new(None) };
+    }
+
+    /// Outside a bundle, as with `tauri dev`, the notification center throws.
+    pub fn supported() -> Result<(), String> {
+        match NSBundle::mainBundle().bundleIdentifier() {
+            Some(_) => Ok(()),
+            None => Err("Notifications need skriv to run from its app bundle".into()),
+        }
+    }
+
+    pub fn show(app: &AppHandle, id: &str, title: &str, body: &str) -> Result<(), String> {
+        let center = UNUserNotificationCenter::currentNotificationCenter();
+        if DELEGATE.with_borrow(|d| d.is_none()) {
+            let delegate = Delegate::alloc().set_ivars(app.clone());
+            let delegate: Retained<Delegate> = unsafe { msg_send![super(delegate), init] };
+            center.setDelegate(Some(ProtocolObject::from_ref(&*delegate)));
+            DELEGATE.set(Some(delegate));
+        }
+        let content = UNMutableNotificationContent::new();
+        content.setTitle(&NSString::from_str(title));
+        content.setBody(&NSString::from_str(body));
+        content.setSound(Some(&UNNotificationSound::defaultSound()));
+        // A request with an identifier already shown replaces that notification
+        let request = UNNotificationRequest::requestWithIdentifier_content_trigger(&NSString::from_str(id), &content, None);
+        // Asks the first time only; later calls report the user's answer
+        let handler = RcBlock::new(move |granted: Bool, _error: *mut NSError| {
+            if granted.as_bool() {
+                UNUserNotificationCenter::currentNotificationCenter().addNotificationRequest_withCompletionHandler(&request, None);
+            } else {
+                log::info!("Notifications aren't allowed for skriv");
+            }
+        });
+        center.requestAuthorizationWithOptions_completionHandler(
+            UNAuthorizationOptions::Alert | UNAuthorizationOptions::Sound,
+            &handler,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use tauri::AppHandle;
+    use windows::core::{IInspectable, Ref, HSTRING};
+    use windows::Data::Xml::Dom::XmlDocument;
+    use windows::Foundation::TypedEventHandler;
+    use windows::UI::Notifications::{ToastNotification, ToastNotificationManager};
+
+    fn escape(text: &str) -> String {
+        text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+    }
+
+    pub fn supported() -> Result<(), String> {
+        Ok(())
+    }
+
+    fn toast(app: &AppHandle, id: &str, title: &str, body: &str) -> windows::core::Result<()> {
+        let xml = format!(
+            r#"<toast><visual><binding template="ToastGeneric"><text>{}</text><text>{}</text></binding></visual></toast>"#,
+            escape(title),
+            escape(body)
+        );
+        let document = XmlDocument::new()?;
+        document.LoadXml(&HSTRING::from(xml))?;
+        let toast = ToastNotification::CreateToastNotification(&document)?;
+        // A toast with a tag already shown replaces that one
+        toast.SetTag(&HSTRING::from(id))?;
+        let (handle, clicked) = (app.clone(), id.to_string());
+        toast.Activated(&TypedEventHandler::new(move |_: Ref<ToastNotification>, _: Ref<IInspectable>| {
+            super::clicked(&handle, &clicked);
+            Ok(())
+        }))?;
+        // The installer's shortcut carries the identifier as its AppUserModelID
+        let notifier = ToastNotificationManager::CreateToastNotifierWithId(&HSTRING::from(crate::paths::bundle_identifier(app)))?;
+        notifier.Show(&toast)
+    }
+
+    pub fn show(app: &AppHandle, id: &str, title: &str, body: &str) -> Result<(), String> {
+        toast(app, id, title, body).map_err(|e| e.message())
+    }
+}
+
+/// Talks to the desktop's notification server over D-Bus, as libnotify does.
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    use gtk::gio::{self, BusType, DBusCallFlags, DBusConnection, DBusSignalFlags};
+    use gtk::glib::{ToVariant, Variant, VariantTy};
+    use tauri::AppHandle;
+
+    const SERVICE: &str = "org.freedesktop.Notifications";
+    const OBJECT: &str = "/org/freedesktop/Notifications";
+
+    thread_local! {
+        static CONNECTION: RefCell<Option<DBusConnection>> = const { RefCell::new(None) };
+        // The server's number for each notification, to replace it by
+        static SHOWN: RefCell<HashMap<String, u32>> = RefCell::new(HashMap::new());
+    }
+
+    pub fn supported() -> Result<(), String> {
+        Ok(())
+    }
+
+    fn connection(app: &AppHandle) -> Result<DBusConnection, String> {
+        if let Some(connection) = CONNECTION.with_borrow(|c| c.clone()) {
+            return Ok(connection);
+        }
+        let connection = gio::bus_get_sync(BusType::Session, gio::Cancellable::NONE).map_err(|e| e.to_string())?;
+        let app = app.clone();
+        connection.signal_subscribe(
+            Some(SERVICE),
+            Some(SERVICE),
+            Some("ActionInvoked"),
+            Some(OBJECT),
+            None,
+            DBusSignalFlags::NONE,
+            move |_, _, _, _, _, params| {
+                let Some((number, _action)) = params.get::<(u32, String)>() else { return };
+                let id = SHOWN.with_borrow(|shown| shown.iter().find(|(_, n)| **n == number).map(|(id, _)| id.clone()));
+                if let Some(id) = id {
+                    super::clicked(&app, &id);
+                }
+            },
+        );
+        CONNECTION.set(Some(connection.clone()));
+        Ok(connection)
+    }
+
+    pub fn show(app: &AppHandle, id: &str, title: &str, body: &str) -> Result<(), String> {
+        let connection = connection(app)?;
+        let replaces = SHOWN.with_borrow(|shown| shown.get(id).copied()).unwrap_or(0);
+        // "default" is the action for a click on the notification itself
+        let actions = vec!["default".to_string(), "Open".to_string()];
+        let hints: HashMap<String, Variant> = HashMap::new();
+        let params = ("skriv", replaces, "", title, body, actions, hints, -1i32).to_variant();
+        let id = id.to_string();
+        connection.call(
+            Some(SERVICE),
+            OBJECT,
+            SERVICE,
+            "Notify",
+            Some(&params),
+            VariantTy::new("(u)").ok(),
+            DBusCallFlags::NONE,
+            -1,
+            gio::Cancellable::NONE,
+            move |reply| match reply.map(|reply| reply.get::<(u32,)>()) {
+                Ok(Some((number,))) => {
+                    SHOWN.with_borrow_mut(|shown| shown.insert(id, number));
+                }
+                Ok(None) => {}
+                Err(e) => log::warn!("Failed to show a notification: {}", e),
+            },
+        );
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "macos", windows, target_os = "linux")))]
+mod platform {
+    use tauri::AppHandle;
+
+    pub fn supported() -> Result<(), String> {
+        Err("Notifications aren't supported on this platform".into())
+    }
+
+    pub fn show(_app: &AppHandle, _id: &str, _title: &str, _body: &str) -> Result<(), String> {
+        Ok(())
+    }
+}
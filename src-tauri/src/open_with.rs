@@ -0,0 +1,257 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::cli::CliArgs;
+use crate::{crash, menu, menu_state, paths, window};
+
+/// Something skriv was asked to open: files and folders from Finder, the
+/// Dock or a drop on a window, or a `skriv://` link.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OpenRequest {
+    Open { path: String, line: Option<u32>, column: Option<u32> },
+    /// A command line, from launch or a second instance
+    Args(CliArgs),
+    /// Opened as the window's workspace
+    Folder { path: String },
+    New { content: String },
+    Search { query: String },
+}
+
+/// Requests that arrived before any window was ready to take them. Managed
+/// from the start, since at launch they can arrive before setup runs.
+#[derive(Default)]
+pub struct PendingRequests {
+    requests: Mutex<Vec<OpenRequest>>,
+    /// Requests for one window, like one just opened for them
+    for_window: Mutex<HashMap<String, Vec<OpenRequest>>>,
+    ready: Mutex<HashSet<String>>,
+    /// Set once setup has made the first window
+    launched: AtomicBool,
+}
+
+impl PendingRequests {
+    /// The ready window `pick` chooses for `requests`, with them; without
+    /// one they're kept. Picking and keeping happen under the same lock as
+    /// `mark_ready`, so a window getting ready meanwhile can't miss them.
+    fn route(&self, requests: Vec<OpenRequest>, pick: impl FnOnce(&HashSet<String>) -> Option<String>) -> Option<(String, Vec<OpenRequest>)> {
+        let ready = self.ready.lock().unwrap();
+        match pick(&ready) {
+            Some(label) => Some((label, requests)),
+            None => {
+                self.requests.lock().unwrap().extend(requests);
+                None
+            }
+        }
+    }
+
+    /// Whether `label` is ready for `requests`; if not, they're kept for it.
+    fn route_to(&self, label: &str, requests: Vec<OpenRequest>) -> Option<Vec<OpenRequest>> {
+        let ready = self.ready.lock().unwrap();
+        if ready.contains(label) {
+            return Some(requests);
+        }
+        self.for_window.lock().unwrap().entry(label.to_string()).or_default().extend(requests);
+        None
+    }
+
+    /// Marks `label` ready and hands back the requests kept until then.
+    fn mark_ready(&self, label: &str) -> Vec<OpenRequest> {
+        let mut ready = self.ready.lock().unwrap();
+        ready.insert(label.to_string());
+        let mut requests = std::mem::take(&mut *self.requests.lock().unwrap());
+        requests.extend(self.for_window.lock().unwrap().remove(label).unwrap_or_default());
+        requests
+    }
+}
+
+/// Opens folders as folders and everything else as files.
+fn requests_for(paths: Vec<PathBuf>) -> Vec<OpenRequest> {
+    paths
+        .into_iter()
+        .map(|path| {
+            let is_dir = path.is_dir();
+            let path = paths::encode(&path);
+            if is_dir {
+                OpenRequest::Folder { path }
+            } else {
+                OpenRequest::Open { path, line: None, column: None }
+            }
+        })
+        .collect()
+}
+
+/// Files, folders and `skriv://` links from Finder, the Dock and the URL
+/// scheme. Any other URLs are skipped.
+#[cfg(target_os = "macos")]
+pub fn open(app: &AppHandle, urls: Vec<tauri::Url>) {
+    let mut paths = Vec::new();
+    for url in urls {
+        match url.scheme() {
+            "file" => match url.to_file_path() {
+                Ok(path) => paths.push(path),
+                Err(()) => log::info!("Ignoring a request to open {}", url),
+            },
+            crate::deep_link::SCHEME => crate::deep_link::handle(app, url.as_str()),
+            _ => log::info!("Ignoring a request to open {}", url),
+        }
+    }
+    if !paths.is_empty() {
+        deliver(app, requests_for(paths));
+    }
+}
+
+/// Files and folders dropped on a window, opened there in one batch.
+pub fn dropped(app: &AppHandle, label: &str, paths: Vec<PathBuf>) {
+    let requests = requests_for(paths);
+    if requests.is_empty() {
+        return;
+    }
+    let pending = app.state::<PendingRequests>();
+    if pending.ready.lock().unwrap().contains(label) {
+        let _ = app.emit_to(label, "open-requests", requests);
+    } else {
+        deliver(app, requests);
+    }
+}
+
+/// Hands the requests to the window showing the file they open, else the
+/// focused window if it's ready, else any ready one. Without one they're
+/// stashed (opening a window if the app is running without any) until a
+/// window calls `frontend_ready`.
+pub fn deliver(app: &AppHandle, requests: Vec<OpenRequest>) {
+    let pending = app.state::<PendingRequests>();
+    let showing = requests.iter().find_map(|request| match request {
+        OpenRequest::Open { path, .. } => menu_state::window_showing(app, path),
+        _ => None,
+    });
+    let routed = pending.route(requests, |ready| {
+        let showing = showing.filter(|label| ready.contains(label)).and_then(|label| app.get_webview_window(&label));
+        let focused = menu::focused_window(app).filter(|w| ready.contains(w.label()));
+        let target = showing.or(focused).or_else(|| ready.iter().find_map(|label| app.get_webview_window(label)));
+        target.map(|window| window.label().to_string())
+    });
+    match routed {
+        Some((label, requests)) => {
+            if let Some(window) = app.get_webview_window(&label) {
+                let _ = window.unminimize();
+                let _ = window.set_focus();
+            }
+            let _ = app.emit_to(&label, "open-requests", requests);
+        }
+        // At launch the main window is still to come
+        None if pending.launched.load(Ordering::Relaxed) && app.webview_windows().is_empty() => {
+            if let Err(e) = window::open_new(app) {
+                log::warn!("Failed to open window: {}", e);
+            }
+        }
+        None => {}
+    }
+}
+
+/// Hands the requests to the window `label`, once it's ready.
+pub fn deliver_to(app: &AppHandle, label: &str, requests: Vec<OpenRequest>) {
+    if let Some(requests) = app.state::<PendingRequests>().route_to(label, requests) {
+        if let Some(window) = app.get_webview_window(label) {
+            let _ = window.unminimize();
+            let _ = window.set_focus();
+        }
+        let _ = app.emit_to(label, "open-requests", requests);
+    }
+}
+
+/// The command line skriv was started with, or a second instance passed on.
+/// `--new-window` only counts once skriv is running, since at launch the
+/// main window is new.
+pub fn open_args(app: &AppHandle, args: CliArgs) {
+    if args.new_window && app.state::<PendingRequests>().launched.load(Ordering::Relaxed) {
+        match window::open_new(app) {
+            Ok(_) if args.is_empty() => {}
+            Ok(label) => deliver_to(app, &label, vec![OpenRequest::Args(args)]),
+            Err(e) => log::warn!("Failed to open window: {}", e),
+        }
+    } else if !args.is_empty() {
+        deliver(app, vec![OpenRequest::Args(args)]);
+    }
+}
+
+pub fn launched(app: &AppHandle) {
+    app.state::<PendingRequests>().launched.store(true, Ordering::Relaxed);
+}
+
+/// Called by each window once it listens for `open-requests`, which then
+/// brings it the requests that arrived before; later ones go straight there.
+/// The first one is offered the last run's crash report as well.
+#[tauri::command]
+pub fn frontend_ready(app: AppHandle, window_label: String) {
+    let requests = app.state::<PendingRequests>().mark_ready(&window_label);
+    if !requests.is_empty() {
+        let _ = app.emit_to(&window_label, "open-requests", requests);
+    }
+    crash::window_ready(&app, &window_label);
+}
+
+/// The windows whose frontends are listening, by label.
+pub fn ready_windows(app: &AppHandle) -> Vec<String> {
+    let mut labels: Vec<String> = app.state::<PendingRequests>().ready.lock().unwrap().iter().cloned().collect();
+    labels.sort();
+    labels
+}
+
+pub fn window_closed(app: &AppHandle, label: &str) {
+    let pending = app.state::<PendingRequests>();
+    pending.ready.lock().unwrap().remove(label);
+    pending.for_window.lock().unwrap().remove(label);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn search(i: usize) -> OpenRequest {
+        OpenRequest::Search { query: i.to_string() }
+    }
+
+    #[test]
+    fn keeps_requests_that_arrive_before_a_window_is_ready() {
+        let pending = Arc::new(PendingRequests::default());
+        let any_ready = |ready: &HashSet<String>| ready.iter().next().cloned();
+        assert!(pending.route(vec![search(0)], any_ready).is_none());
+        let sender = pending.clone();
+        // The window gets ready partway through a burst of requests
+        let sending = std::thread::spawn(move || {
+            (1..2000).filter_map(|i| sender.route(vec![search(i)], any_ready)).flat_map(|(_, requests)| requests).collect::<Vec<_>>()
+        });
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        let mut received = pending.mark_ready("main");
+        received.extend(sending.join().unwrap());
+        assert_eq!(received.len(), 2000);
+        let mut queries: Vec<usize> = received
+            .iter()
+            .map(|r| match r {
+                OpenRequest::Search { query } => query.parse().unwrap(),
+                _ => unreachable!(),
+            })
+            .collect();
+        queries.sort_unstable();
+        assert_eq!(queries, (0..2000).collect::<Vec<_>>());
+        assert!(pending.mark_ready("other").is_empty());
+    }
+
+    #[test]
+    fn keeps_requests_for_a_window_until_it_is_ready() {
+        let pending = PendingRequests::default();
+        pending.mark_ready("main");
+        assert!(pending.route_to("window-1", vec![search(1)]).is_none());
+        assert!(pending.route(vec![search(2)], |_| None).is_none());
+        assert_eq!(pending.mark_ready("window-2"), [search(2)]);
+        assert_eq!(pending.mark_ready("window-1"), [search(1)]);
+        assert_eq!(pending.route_to("window-1", vec![search(3)]), Some(vec![search(3)]));
+    }
+}
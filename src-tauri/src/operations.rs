@@ -0,0 +1,59 @@
+//! Cancellation for long backend work. An operation is registered for as
+//! long as its guard lives, and `cancel_operation` flags it; the work checks
+//! the flag between steps and stops at the next one.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tauri::{AppHandle, Manager};
+
+#[derive(Default)]
+pub struct Operations(Mutex<State>);
+
+#[derive(Default)]
+struct State {
+    next: u64,
+    open: HashMap<u64, Arc<AtomicBool>>,
+}
+
+/// Unregisters its operation when dropped, however the work ends.
+pub struct Operation {
+    app: AppHandle,
+    pub id: u64,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Operation {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        self.app.state::<Operations>().0.lock().unwrap().open.remove(&self.id);
+    }
+}
+
+pub fn begin(app: &AppHandle) -> Operation {
+    let operations = app.state::<Operations>();
+    let mut state = operations.0.lock().unwrap();
+    state.next += 1;
+    let id = state.next;
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state.open.insert(id, cancelled.clone());
+    Operation { app: app.clone(), id, cancelled }
+}
+
+/// Returns false when the operation already finished.
+#[tauri::command]
+pub fn cancel_operation(operations: tauri::State<'_, Operations>, id: u64) -> bool {
+    match operations.0.lock().unwrap().open.get(&id) {
+        Some(cancelled) => {
+            cancelled.store(true, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
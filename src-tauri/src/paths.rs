@@ -0,0 +1,325 @@
+//! Where skriv keeps its files. Everything that reads or writes outside the
+//! user's documents asks here, so that portable mode can move it all.
+//!
+//! skriv is portable when a `portable` file sits beside the executable (on
+//! macOS, beside `skriv.app`), or when started with `--portable`. Config,
+//! data and logs then live in a `data` folder next to it. The
+//! webview's own storage isn't moved; it only holds a hint for the theme at
+//! startup.
+
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::OsString;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use tauri::path::PathResolver;
+use tauri::{AppHandle, Manager, Wry};
+
+pub const PORTABLE_FLAG: &str = "--portable";
+const MARKER: &str = "portable";
+const DATA_DIR: &str = "data";
+
+/// The identifier from tauri.conf.json, before a portable copy changed it.
+static BUNDLE_IDENTIFIER: OnceLock<String> = OnceLock::new();
+
+/// The folder holding the executable, or the app bundle around it.
+fn install_dir() -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    let dir = exe.parent()?;
+    #[cfg(target_os = "macos")]
+    if let Some(bundle) = dir.ancestors().find(|d| d.extension().is_some_and(|ext| ext == "app")) {
+        return bundle.parent().map(Path::to_path_buf);
+    }
+    Some(dir.to_path_buf())
+}
+
+/// The `data` folder when running portable.
+pub fn portable_root() -> Option<&'static Path> {
+    static ROOT: OnceLock<Option<PathBuf>> = OnceLock::new();
+    ROOT.get_or_init(|| {
+        let dir = install_dir()?;
+        let portable = std::env::args_os().skip(1).any(|arg| arg == PORTABLE_FLAG) || dir.join(MARKER).is_file();
+        portable.then(|| dir.join(DATA_DIR))
+    })
+    .as_deref()
+}
+
+type PlatformDir = fn(&PathResolver<Wry>) -> tauri::Result<PathBuf>;
+
+/// `sub` of the portable `data` folder, else the platform's folder.
+fn resolve(app: &AppHandle, sub: Option<&str>, platform: PlatformDir) -> Result<PathBuf, String> {
+    match portable_root() {
+        Some(root) => Ok(sub.map_or_else(|| root.to_path_buf(), |sub| root.join(sub))),
+        None => platform(app.path()).map_err(|e| e.to_string()),
+    }
+}
+
+/// Settings, keybindings, languages, themes and snippets.
+pub fn config_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    resolve(app, None, |path| path.app_config_dir())
+}
+
+/// Sessions, unsaved buffers and the user dictionary.
+pub fn data_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    resolve(app, None, |path| path.app_data_dir())
+}
+
+/// `data_dir` for a process that isn't running the app, like `--ctl`, with
+/// only the identifier to go by.
+pub fn data_dir_for(identifier: &str) -> Option<PathBuf> {
+    match portable_root() {
+        Some(root) => Some(root.to_path_buf()),
+        None => dirs::data_dir().map(|dir| dir.join(identifier)),
+    }
+}
+
+pub fn log_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    resolve(app, Some("logs"), |path| path.app_log_dir())
+}
+
+/// VS Code's `User` folder, where it keeps settings.json and
+/// keybindings.json.
+pub fn vscode_user_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path().config_dir().map(|dir| dir.join("Code").join("User")).map_err(|e| e.to_string())
+}
+
+/// Scratch files that are gone once used. They stay in the system's temp
+/// folder even for a portable copy.
+pub fn temp_dir() -> PathBuf {
+    std::env::temp_dir()
+}
+
+/// A portable copy gets an identifier of its own, derived from its data
+/// folder, so the single-instance lock and the webview's storage don't
+/// collide with an installed skriv or another portable copy.
+pub fn isolate_instance(context: &mut tauri::Context<Wry>) {
+    let config = context.config_mut();
+    BUNDLE_IDENTIFIER.get_or_init(|| config.identifier.clone());
+    if let Some(root) = portable_root() {
+        let mut hasher = DefaultHasher::new();
+        root.hash(&mut hasher);
+        config.identifier = format!("{}.portable{:016x}", config.identifier, hasher.finish());
+    }
+}
+
+/// The identifier the OS knows the installed app by, for registering it as
+/// a handler or naming it in notifications.
+pub fn bundle_identifier(app: &AppHandle) -> String {
+    BUNDLE_IDENTIFIER.get().cloned().unwrap_or_else(|| app.config().identifier.clone())
+}
+
+/// The arguments without the ones that only pick the data folder.
+pub fn strip_flags(args: Vec<String>) -> Vec<String> {
+    args.into_iter().filter(|arg| arg != PORTABLE_FLAG).collect()
+}
+
+/// The command line, with arguments that aren't valid Unicode kept as
+/// `encode` writes them, where `std::env::args` would panic.
+pub fn args() -> Vec<String> {
+    std::env::args_os().map(|arg| encode(Path::new(&arg))).collect()
+}
+
+/// The characters `encode` stands bytes, or on Windows UTF-16 units, in for:
+/// the end of the last private use plane, where a file name is unlikely to
+/// hold anything of its own.
+#[cfg(unix)]
+const ESCAPE_BASE: u32 = 0x10FF00;
+#[cfg(windows)]
+const ESCAPE_BASE: u32 = 0x10F800;
+
+fn is_escape(c: char) -> bool {
+    #[cfg(unix)]
+    let first = ESCAPE_BASE + 0x80;
+    #[cfg(windows)]
+    let first = ESCAPE_BASE;
+    c as u32 >= first
+}
+
+/// A path as a string for the webview, which gets back exactly the path
+/// from `decode`. A path that's valid Unicode is itself. In one that isn't,
+/// like a Latin-1 name on Linux or one with a lone surrogate on Windows,
+/// each byte or unit that doesn't fit becomes an escape character, and so
+/// do any escape characters really in the name; they show as unknown
+/// glyphs.
+#[cfg(unix)]
+pub fn encode(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    let bytes = path.as_os_str().as_bytes();
+    let escape = |out: &mut String, byte: u8| out.push(char::from_u32(ESCAPE_BASE + byte as u32).unwrap_or(char::REPLACEMENT_CHARACTER));
+    let mut out = String::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let (valid, invalid) = match std::str::from_utf8(rest) {
+            Ok(valid) => (valid, 0),
+            Err(e) => (std::str::from_utf8(&rest[..e.valid_up_to()]).unwrap_or_default(), e.error_len().unwrap_or(rest.len() - e.valid_up_to())),
+        };
+        for c in valid.chars() {
+            if is_escape(c) {
+                c.encode_utf8(&mut [0; 4]).bytes().for_each(|byte| escape(&mut out, byte));
+            } else {
+                out.push(c);
+            }
+        }
+        let (bad, after) = rest[valid.len()..].split_at(invalid);
+        bad.iter().for_each(|&byte| escape(&mut out, byte));
+        rest = after;
+    }
+    out
+}
+
+#[cfg(windows)]
+pub fn encode(path: &Path) -> String {
+    use std::os::windows::ffi::OsStrExt;
+    let escape = |out: &mut String, unit: u16| out.push(char::from_u32(ESCAPE_BASE + (unit as u32 - 0xD800)).unwrap_or(char::REPLACEMENT_CHARACTER));
+    let mut out = String::new();
+    for c in char::decode_utf16(path.as_os_str().encode_wide()) {
+        match c {
+            Ok(c) if is_escape(c) => c.encode_utf16(&mut [0; 2]).iter().for_each(|&unit| escape(&mut out, unit)),
+            Ok(c) => out.push(c),
+            Err(e) => escape(&mut out, e.unpaired_surrogate()),
+        }
+    }
+    out
+}
+
+/// The path a string from `encode` stands for. Any other string is the
+/// path it spells.
+#[cfg(unix)]
+pub fn decode(text: &str) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    if !text.chars().any(is_escape) {
+        return PathBuf::from(text);
+    }
+    let mut bytes = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        if is_escape(c) {
+            bytes.push((c as u32 - ESCAPE_BASE) as u8);
+        } else {
+            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+    }
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(windows)]
+pub fn decode(text: &str) -> PathBuf {
+    use std::os::windows::ffi::OsStringExt;
+    if !text.chars().any(is_escape) {
+        return PathBuf::from(text);
+    }
+    let mut units = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        if is_escape(c) {
+            units.push((c as u32 - ESCAPE_BASE + 0xD800) as u16);
+        } else {
+            units.extend_from_slice(c.encode_utf16(&mut [0; 2]));
+        }
+    }
+    PathBuf::from(OsString::from_wide(&units))
+}
+
+/// The temporary file beside `path` that a write goes to before it's moved
+/// over `path`.
+pub fn temp_beside(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    PathBuf::from(tmp)
+}
+
+/// Writes `bytes` to `path` through a temporary file beside it, so a crash
+/// or a full disk never leaves half a file.
+pub fn write_atomic(path: &Path, bytes: impl AsRef<[u8]>) -> io::Result<()> {
+    write_atomic_with(path, |file| file.write_all(bytes.as_ref()))
+}
+
+/// `write_atomic` for a file `write` produces bit by bit. The temporary
+/// file is removed if anything fails.
+pub fn write_atomic_with(path: &Path, write: impl FnOnce(&mut File) -> io::Result<()>) -> io::Result<()> {
+    let tmp = temp_beside(path);
+    let written = File::create(&tmp)
+        .and_then(|mut file| {
+            write(&mut file)?;
+            file.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&tmp, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    written
+}
+
+/// Where the frontend keeps sessions and unsaved buffers.
+#[tauri::command]
+pub fn get_data_dir(app: AppHandle) -> Result<String, String> {
+    data_dir(&app).map(|dir| encode(&dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn round_trips_names_that_arent_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+        // Latin-1 é, a cut-off sequence, and a real character from the escapes
+        let raw = Path::new(std::ffi::OsStr::from_bytes(b"/music/caf\xe9 \xe2\x82 \xf4\x8f\xbf\xbf.mp3"));
+        let encoded = encode(raw);
+        assert!(encoded.starts_with("/music/caf") && encoded.ends_with(".mp3"));
+        assert_eq!(decode(&encoded), raw);
+        assert_eq!(encode(Path::new("/music/café.mp3")), "/music/café.mp3");
+        assert_eq!(decode("/music/café.mp3"), Path::new("/music/café.mp3"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn opens_files_named_in_latin1() {
+        use std::os::unix::ffi::OsStrExt;
+        let dir = std::env::temp_dir().join(format!("skriv-latin1-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(std::ffi::OsStr::from_bytes(b"r\xe9sum\xe9.txt"));
+        if std::fs::write(&file, "x").is_err() {
+            // The filesystem insists on UTF-8 names, as on macOS
+            return std::fs::remove_dir_all(&dir).unwrap();
+        }
+        let listed = std::fs::read_dir(&dir).unwrap().map(|entry| encode(&entry.unwrap().path())).collect::<Vec<_>>();
+        assert_eq!(std::fs::read_to_string(decode(&listed[0])).unwrap(), "x");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_atomically() {
+        let dir = std::env::temp_dir().join(format!("skriv-atomic-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("settings.json");
+        write_atomic(&path, "first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+        // A failed write leaves the old file and no temporary one
+        let failed = write_atomic_with(&path, |file| {
+            file.write_all(b"half")?;
+            Err(io::Error::other("disk full"))
+        });
+        assert!(failed.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+        assert!(!temp_beside(&path).exists());
+        assert!(write_atomic(&dir.join("missing").join("a.txt"), "x").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn round_trips_names_with_lone_surrogates() {
+        use std::os::windows::ffi::OsStringExt;
+        // A lone high surrogate, and a real character from the escapes
+        let mut units: Vec<u16> = r"C:\a".encode_utf16().collect();
+        units.push(0xD83D);
+        units.extend("b\u{10F800}.txt".encode_utf16());
+        let raw = PathBuf::from(OsString::from_wide(&units));
+        assert_eq!(decode(&encode(&raw)), raw);
+        assert_eq!(encode(Path::new(r"C:\café.txt")), r"C:\café.txt");
+    }
+}
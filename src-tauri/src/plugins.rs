@@ -0,0 +1,413 @@
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_opener::OpenerExt;
+
+#[cfg(target_os = "macos")]
+use crate::tasks;
+use crate::workspace_trust::{self, UNTRUSTED};
+use crate::{format, paths, workspace};
+
+pub const DIR_NAME: &str = "plugins";
+
+const UNTRUSTED_ERROR: &str = "Plugins in a workspace only run once it's trusted";
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// The most text a plugin is given, and takes back
+const MAX_INPUT: usize = 16 * 1024 * 1024;
+const MAX_OUTPUT: usize = 16 * 1024 * 1024;
+/// Of stderr, for the error message
+const MAX_ERROR: usize = 4 * 1024;
+
+/// What the tool reads on stdin.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Input {
+    #[default]
+    None,
+    Selection,
+    Document,
+}
+
+/// What becomes of what it prints.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Output {
+    /// In place of the input
+    Replace,
+    /// At the cursor
+    Insert,
+    /// In a new tab
+    #[default]
+    Show,
+}
+
+/// A `plugins/*.json` file. `${file}` in the arguments is replaced with
+/// the document's path.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    id: String,
+    title: String,
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    input: Input,
+    #[serde(default)]
+    output: Output,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginInfo {
+    /// The file name when the manifest couldn't be read
+    id: String,
+    title: String,
+    input: Input,
+    output: Output,
+    file: String,
+    /// From the workspace's `.skriv/plugins` rather than the user's folder
+    workspace: bool,
+    /// Why it can't run; it's listed anyway so it can be fixed
+    error: Option<String>,
+}
+
+#[derive(Clone)]
+struct Plugin {
+    info: PluginInfo,
+    manifest: Option<Manifest>,
+}
+
+/// The text a plugin runs on, and the document it's from.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginPayload {
+    /// The selection or the whole document, as the manifest's `input` says
+    text: Option<String>,
+    path: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginOutput {
+    output: Output,
+    text: String,
+}
+
+/// The user's plugins as last read, and the watcher on their folder.
+#[derive(Default)]
+pub struct Plugins {
+    loaded: Mutex<Vec<Plugin>>,
+    watcher: Mutex<Option<RecommendedWatcher>>,
+}
+
+impl Plugins {
+    pub fn is_watching(&self) -> bool {
+        self.watcher.lock().unwrap().is_some()
+    }
+}
+
+pub fn dir(app: &AppHandle) -> Result<PathBuf, String> {
+    paths::config_dir(app).map(|dir| dir.join(DIR_NAME))
+}
+
+fn validate(manifest: Manifest) -> Result<Manifest, String> {
+    if manifest.id.is_empty() || manifest.id.len() > 64 || !manifest.id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+        return Err(format!("\"{}\" isn't a valid id; use letters, digits, '-', '_' and '.'", manifest.id));
+    }
+    if manifest.title.trim().is_empty() {
+        return Err("The title is empty".into());
+    }
+    if manifest.command.trim().is_empty() {
+        return Err("The command is empty".into());
+    }
+    if manifest.output == Output::Replace && manifest.input == Input::None {
+        return Err("\"output\": \"replace\" needs an input to replace".into());
+    }
+    Ok(manifest)
+}
+
+fn parse(file: &str, text: Result<String, String>, workspace: bool) -> Plugin {
+    let manifest = text.and_then(|text| serde_json::from_str::<Manifest>(&text).map_err(|e| e.to_string())).and_then(validate);
+    let stem = file.strip_suffix(".json").unwrap_or(file).to_string();
+    let info = match &manifest {
+        Ok(m) => PluginInfo { id: m.id.clone(), title: m.title.clone(), input: m.input, output: m.output, file: file.into(), workspace, error: None },
+        Err(e) => PluginInfo { id: stem.clone(), title: stem, input: Input::None, output: Output::Show, file: file.into(), workspace, error: Some(e.clone()) },
+    };
+    Plugin { info, manifest: manifest.ok() }
+}
+
+/// The manifests in `dir`, by file name. Each one that's wrong is listed
+/// with why, and the rest still load.
+fn read(dir: &Path, workspace: bool) -> Vec<Plugin> {
+    let Ok(entries) = std::fs::read_dir(dir) else { return Vec::new() };
+    let mut files: Vec<(String, PathBuf)> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            (name.ends_with(".json") && !name.starts_with('.')).then(|| (name, entry.path()))
+        })
+        .collect();
+    files.sort();
+    files.into_iter().map(|(name, path)| parse(&name, std::fs::read_to_string(path).map_err(|e| e.to_string()), workspace)).collect()
+}
+
+/// Marks a plugin whose id an earlier one took, and the workspace's unless
+/// it's `trusted`.
+fn check(mut plugins: Vec<Plugin>, trusted: bool) -> Vec<Plugin> {
+    let mut seen = HashSet::new();
+    for plugin in plugins.iter_mut().filter(|p| p.info.error.is_none()) {
+        if !seen.insert(plugin.info.id.clone()) {
+            plugin.info.error = Some(format!("Another plugin already has the id \"{}\"", plugin.info.id));
+        } else if plugin.info.workspace && !trusted {
+            plugin.info.error = Some(UNTRUSTED_ERROR.into());
+        }
+        if plugin.info.error.is_some() {
+            plugin.manifest = None;
+        }
+    }
+    plugins
+}
+
+/// Reads the user's plugins again.
+pub fn load(app: &AppHandle) {
+    let plugins = dir(app).map(|dir| read(&dir, false)).unwrap_or_default();
+    *app.state::<Plugins>().loaded.lock().unwrap() = plugins;
+}
+
+fn workspace_dir(root: &Path) -> PathBuf {
+    root.join(workspace::DIR).join(DIR_NAME)
+}
+
+/// Whether the folder has plugins of its own.
+pub fn in_workspace(root: &Path) -> bool {
+    !read(&workspace_dir(root), true).is_empty()
+}
+
+/// The user's plugins, then those of the window's workspace.
+fn all(app: &AppHandle, label: &str) -> Vec<Plugin> {
+    let mut plugins = app.state::<Plugins>().loaded.lock().unwrap().clone();
+    let root = workspace::root(app, label);
+    if let Some(root) = &root {
+        plugins.extend(read(&workspace_dir(root), true));
+    }
+    check(plugins, root.is_some_and(|root| workspace_trust::is_trusted(app, &root)))
+}
+
+/// Something in the plugins folder changed; windows hear with `plugins-changed`.
+fn changed(app: &AppHandle) {
+    load(app);
+    let _ = app.emit("plugins-changed", ());
+}
+
+/// Starts watching the plugins folder if it's there and not yet watched.
+pub fn watch(app: &AppHandle) {
+    let plugins = app.state::<Plugins>();
+    let mut watcher = plugins.watcher.lock().unwrap();
+    let Ok(dir) = dir(app) else { return };
+    if watcher.is_some() || !dir.is_dir() {
+        return;
+    }
+    let handle = app.clone();
+    let started = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        let Ok(event) = res else { return };
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        let app = handle.clone();
+        let _ = handle.run_on_main_thread(move || changed(&app));
+    })
+    .and_then(|mut started| started.watch(&dir, RecursiveMode::NonRecursive).map(|_| started));
+    match started {
+        Ok(started) => *watcher = Some(started),
+        Err(e) => log::warn!("Failed to watch {}: {}", dir.display(), e),
+    }
+}
+
+/// Reads up to `limit` bytes, then throws the rest away so the tool doesn't
+/// block on a full pipe. `over` is set when there was more.
+fn read_capped(source: impl Read + Send + 'static, limit: usize, over: Arc<AtomicBool>) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let mut source = source.take(limit as u64 + 1);
+        let _ = source.read_to_end(&mut bytes);
+        if bytes.len() > limit {
+            bytes.truncate(limit);
+            over.store(true, Ordering::Relaxed);
+            let _ = std::io::copy(&mut source.into_inner(), &mut std::io::sink());
+        }
+        bytes
+    })
+}
+
+fn execute(tool: &Path, manifest: &Manifest, input: Option<String>, file: Option<&str>, cwd: Option<&Path>) -> Result<String, String> {
+    let args: Vec<String> = manifest.args.iter().map(|arg| arg.replace("${file}", file.unwrap_or_default())).collect();
+    let mut command = Command::new(tool);
+    command
+        .args(&args)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(cwd) = cwd {
+        command.current_dir(cwd);
+    }
+    #[cfg(target_os = "macos")]
+    if let Some(path) = tasks::login_path() {
+        command.env("PATH", path);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // CREATE_NO_WINDOW
+        command.creation_flags(0x0800_0000);
+    }
+    let mut child = command.spawn().map_err(|e| format!("Failed to run {}: {}", manifest.command, e))?;
+
+    let writer = input.zip(child.stdin.take()).map(|(input, mut stdin)| {
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(input.as_bytes());
+        })
+    });
+    let too_much = Arc::new(AtomicBool::new(false));
+    let stdout = read_capped(child.stdout.take().expect("stdout is piped"), MAX_OUTPUT, too_much.clone());
+    let stderr = read_capped(child.stderr.take().expect("stderr is piped"), MAX_ERROR, Arc::default());
+
+    let deadline = Instant::now() + TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break status;
+        }
+        if too_much.load(Ordering::Relaxed) || Instant::now() > deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(if too_much.load(Ordering::Relaxed) {
+                format!("{} printed more than {} MB", manifest.title, MAX_OUTPUT / 1024 / 1024)
+            } else {
+                format!("{} didn't finish within {} seconds", manifest.title, TIMEOUT.as_secs())
+            });
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = String::from_utf8_lossy(&stderr.join().unwrap_or_default()).trim().to_string();
+    if too_much.load(Ordering::Relaxed) {
+        return Err(format!("{} printed more than {} MB", manifest.title, MAX_OUTPUT / 1024 / 1024));
+    }
+    if !status.success() {
+        let code = status.code().map_or_else(|| "was killed".to_string(), |code| format!("exited with {}", code));
+        return Err(if stderr.is_empty() { format!("{} {}", manifest.title, code) } else { format!("{} {}: {}", manifest.title, code, stderr) });
+    }
+    String::from_utf8(stdout).map_err(|_| format!("{} printed invalid UTF-8", manifest.title))
+}
+
+/// The user's plugins and those of the window's workspace, each with what's
+/// wrong with it, if anything.
+#[tauri::command]
+pub fn list_plugins(app: AppHandle, window: tauri::Window) -> Vec<PluginInfo> {
+    all(&app, window.label()).into_iter().map(|plugin| plugin.info).collect()
+}
+
+/// Runs the plugin with `payload.text` on stdin, in the workspace or else
+/// the document's folder, and returns what it printed.
+#[tauri::command]
+pub async fn run_plugin(app: AppHandle, window: tauri::Window, id: String, payload: PluginPayload) -> Result<PluginOutput, String> {
+    let plugin = all(&app, window.label()).into_iter().find(|p| p.info.id == id).ok_or_else(|| format!("No plugin \"{}\"", id))?;
+    if plugin.info.error.as_deref() == Some(UNTRUSTED_ERROR) {
+        return Err(UNTRUSTED.into());
+    }
+    let manifest = match (plugin.manifest, plugin.info.error) {
+        (Some(manifest), None) => manifest,
+        (_, error) => return Err(error.unwrap_or_default()),
+    };
+    let input = match manifest.input {
+        Input::None => None,
+        Input::Selection | Input::Document => Some(payload.text.unwrap_or_default()),
+    };
+    if input.as_ref().is_some_and(|text| text.len() > MAX_INPUT) {
+        return Err(format!("The text is too big to give to {}", manifest.title));
+    }
+    let cwd = workspace::root(&app, window.label()).or_else(|| payload.path.as_deref().and_then(|p| paths::decode(p).parent().map(Path::to_path_buf)));
+    let tool = format::resolve(&app, &manifest.command, cwd.as_deref());
+    let output = manifest.output;
+    let text = tauri::async_runtime::spawn_blocking(move || execute(&tool, &manifest, input, payload.path.as_deref(), cwd.as_deref()))
+        .await
+        .map_err(|e| e.to_string())??;
+    Ok(PluginOutput { output, text })
+}
+
+#[tauri::command]
+pub fn open_plugins_folder(app: AppHandle) -> Result<(), String> {
+    let dir = dir(&app)?;
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    watch(&app);
+    app.opener().open_path(dir.to_string_lossy(), None::<&str>).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(json: &str) -> Plugin {
+        parse("p.json", Ok(json.to_string()), false)
+    }
+
+    #[test]
+    fn reports_each_bad_manifest() {
+        let good = manifest(r#"{ "id": "jq", "title": "Pretty JSON", "command": "jq", "args": ["."], "input": "document", "output": "replace" }"#);
+        assert_eq!(good.info.error, None);
+        assert_eq!(good.manifest.unwrap().args, ["."]);
+        for (json, error) in [
+            (r#"{ "id": "x", "title": "X", "command": "x", "stdin": true }"#, "unknown field"),
+            (r#"{ "id": "a b", "title": "X", "command": "x" }"#, "valid id"),
+            (r#"{ "id": "x", "title": " ", "command": "x" }"#, "title"),
+            (r#"{ "id": "x", "title": "X", "command": "x", "output": "replace" }"#, "needs an input"),
+            (r#"{ "id": "x", "title": "X", "command": "x", "input": "clipboard" }"#, "unknown variant"),
+            ("{", "EOF"),
+        ] {
+            let plugin = manifest(json);
+            assert!(plugin.manifest.is_none());
+            assert!(plugin.info.error.as_deref().is_some_and(|e| e.contains(error)), "{}: {:?}", json, plugin.info.error);
+            assert_eq!(plugin.info.id, "p");
+        }
+    }
+
+    #[test]
+    fn keeps_the_first_of_an_id_and_gates_workspace_plugins() {
+        let plugins = check(vec![
+            parse("a.json", Ok(r#"{ "id": "x", "title": "A", "command": "a" }"#.into()), false),
+            parse("b.json", Ok(r#"{ "id": "x", "title": "B", "command": "b" }"#.into()), false),
+            parse("c.json", Ok(r#"{ "id": "y", "title": "C", "command": "c" }"#.into()), true),
+        ], false);
+        assert!(plugins[0].manifest.is_some());
+        assert!(plugins[1].info.error.as_deref().is_some_and(|e| e.contains("already")));
+        assert!(plugins[2].info.error.as_deref().is_some_and(|e| e.contains("trusted")));
+        assert!(plugins[1].manifest.is_none() && plugins[2].manifest.is_none());
+        let trusted = check(vec![parse("c.json", Ok(r#"{ "id": "y", "title": "C", "command": "c" }"#.into()), true)], true);
+        assert!(trusted[0].manifest.is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pipes_the_text_through() {
+        let run = |json: &str, input: Option<&str>| {
+            let manifest = manifest(json).manifest.unwrap();
+            execute(Path::new(&manifest.command), &manifest, input.map(String::from), Some("/tmp/x.txt"), None)
+        };
+        assert_eq!(run(r#"{ "id": "t", "title": "T", "command": "tr", "args": ["a-z", "A-Z"], "input": "selection" }"#, Some("hi")), Ok("HI".into()));
+        assert_eq!(run(r#"{ "id": "e", "title": "E", "command": "echo", "args": ["${file}"] }"#, None), Ok("/tmp/x.txt\n".into()));
+        let failed = run(r#"{ "id": "f", "title": "F", "command": "sh", "args": ["-c", "echo bad >&2; exit 3"] }"#, None);
+        assert_eq!(failed, Err("F exited with 3: bad".into()));
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+/// Long operations in progress. The system sees a single power assertion,
+/// taken with the first activity and released with the last.
+#[derive(Default)]
+pub struct Activities(Mutex<State>);
+
+#[derive(Default)]
+struct State {
+    next: u64,
+    open: BTreeMap<u64, Activity>,
+    assertion: Option<platform::Assertion>,
+}
+
+struct Activity {
+    reason: String,
+    /// The window whose frontend started it, released if that window closes
+    window: Option<String>,
+    started: Instant,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeldActivity {
+    pub token: u64,
+    pub reason: String,
+    pub window: Option<String>,
+    pub seconds: u64,
+}
+
+/// Keeps the system awake until the returned token is ended. Failing to take
+/// the assertion doesn't stop the operation; the next activity tries again.
+pub fn begin_activity(app: &AppHandle, reason: &str, window: Option<&str>) -> u64 {
+    let activities = app.state::<Activities>();
+    let mut state = activities.0.lock().unwrap();
+    state.next += 1;
+    let token = state.next;
+    state.open.insert(token, Activity { reason: reason.to_string(), window: window.map(str::to_string), started: Instant::now() });
+    if state.assertion.is_none() {
+        match platform::Assertion::new(&format!("skriv: {}", reason)) {
+            Ok(assertion) => state.assertion = Some(assertion),
+            Err(e) => log::warn!("Failed to keep the system awake: {}", e),
+        }
+    }
+    token
+}
+
+/// Returns false for a token that isn't open, so ending one twice is harmless.
+pub fn end_activity(app: &AppHandle, token: u64) -> bool {
+    let activities = app.state::<Activities>();
+    let mut state = activities.0.lock().unwrap();
+    let ended = state.open.remove(&token).is_some();
+    if state.open.is_empty() {
+        state.assertion = None;
+    }
+    ended
+}
+
+/// Ends its activity when dropped, so an early return or an error can't leave
+/// sleep blocked.
+pub struct Guard {
+    app: AppHandle,
+    token: u64,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        end_activity(&self.app, self.token);
+    }
+}
+
+pub fn hold(app: &AppHandle, reason: &str) -> Guard {
+    Guard { app: app.clone(), token: begin_activity(app, reason, None) }
+}
+
+/// Ends whatever the closed window's frontend didn't get to.
+pub fn window_closed(app: &AppHandle, label: &str) {
+    let activities = app.state::<Activities>();
+    let mut state = activities.0.lock().unwrap();
+    state.open.retain(|_, activity| activity.window.as_deref() != Some(label));
+    if state.open.is_empty() {
+        state.assertion = None;
+    }
+}
+
+#[tauri::command]
+pub fn begin_power_activity(app: AppHandle, window: tauri::Window, reason: String) -> u64 {
+    begin_activity(&app, &reason, Some(window.label()))
+}
+
+#[tauri::command]
+pub fn end_power_activity(app: AppHandle, token: u64) -> bool {
+    end_activity(&app, token)
+}
+
+/// What's keeping the system awake, oldest first.
+#[tauri::command]
+pub fn get_power_assertions(activities: tauri::State<'_, Activities>) -> Vec<HeldActivity> {
+    let state = activities.0.lock().unwrap();
+    state
+        .open
+        .iter()
+        .map(|(token, activity)| HeldActivity {
+            token: *token,
+            reason: activity.reason.clone(),
+            window: activity.window.clone(),
+            seconds: activity.started.elapsed().as_secs(),
+        })
+        .collect()
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::ffi::c_void;
+
+    use objc2_foundation::NSString;
+
+    type CFStringRef = *const c_void;
+    // kIOPMAssertionLevelOn
+    const LEVEL_ON: u32 = 255;
+
+    #[link(name = "IOKit", kind = "framework")]
+    extern "C" {
+        fn IOPMAssertionCreateWithName(kind: CFStringRef, level: u32, name: CFStringRef, id: *mut u32) -> i32;
+        fn IOPMAssertionRelease(id: u32) -> i32;
+    }
+
+    // NSString and CFString are the same object
+    fn cf(string: &NSString) -> CFStringRef {
+        (string as *const NSString).cast()
+    }
+
+    pub struct Assertion(u32);
+
+    impl Assertion {
+        pub fn new(reason: &str) -> Result<Self, String> {
+            // Unlike PreventUserIdleSystemSleep this also covers a closed lid,
+            // though only on power
+            let kind = NSString::from_str("PreventSystemSleep");
+            let name = NSString::from_str(reason);
+            let mut id = 0;
+            // SAFETY: valid strings in; the id is only read on success
+            let status = unsafe { IOPMAssertionCreateWithName(cf(&kind), LEVEL_ON, cf(&name), &mut id) };
+            if status != 0 {
+                return Err(format!("IOKit refused the power assertion with status {:#x}", status));
+            }
+            Ok(Assertion(id))
+        }
+    }
+
+    impl Drop for Assertion {
+        fn drop(&mut self) {
+            // SAFETY: the id came from IOPMAssertionCreateWithName and is released once
+            unsafe { IOPMAssertionRelease(self.0) };
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::sync::mpsc;
+
+    use windows::Win32::System::Power::{SetThreadExecutionState, ES_CONTINUOUS, ES_SYSTEM_REQUIRED};
+
+    /// The execution state belongs to a thread, so one is kept parked with it
+    /// until the sender is dropped.
+    pub struct Assertion {
+        _release: mpsc::Sender<()>,
+    }
+
+    impl Assertion {
+        pub fn new(_reason: &str) -> Result<Self, String> {
+            let (release, released) = mpsc::channel::<()>();
+            let (ready, taken) = mpsc::channel();
+            std::thread::spawn(move || {
+                // SAFETY: plain flags in, the previous state out (zero on failure)
+                let previous = unsafe { SetThreadExecutionState(ES_CONTINUOUS | ES_SYSTEM_REQUIRED) };
+                let _ = ready.send(previous.0 != 0);
+                if previous.0 != 0 {
+                    let _ = released.recv();
+                    // SAFETY: as above
+                    unsafe { SetThreadExecutionState(ES_CONTINUOUS) };
+                }
+            });
+            match taken.recv() {
+                Ok(true) => Ok(Assertion { _release: release }),
+                _ => Err("SetThreadExecutionState failed".into()),
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::process::{Child, Command, Stdio};
+
+    /// A systemd-inhibit holding a logind lock while `cat` runs. `cat` ends
+    /// when its stdin closes, which also happens if skriv dies.
+    pub struct Assertion(Child);
+
+    impl Assertion {
+        pub fn new(reason: &str) -> Result<Self, String> {
+            let child = Command::new("systemd-inhibit")
+                .args(["--what=sleep:idle", "--who=skriv", "--mode=block"])
+                .arg(format!("--why={}", reason))
+                .arg("cat")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(|e| format!("Couldn't run systemd-inhibit: {}", e))?;
+            Ok(Assertion(child))
+        }
+    }
+
+    impl Drop for Assertion {
+        fn drop(&mut self) {
+            drop(self.0.stdin.take());
+            let _ = self.0.wait();
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "macos", windows, target_os = "linux")))]
+mod platform {
+    pub struct Assertion;
+
+    impl Assertion {
+        pub fn new(_reason: &str) -> Result<Self, String> {
+            Ok(Assertion)
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, WebviewWindow};
+
+use crate::notifications;
+use crate::paths;
+use crate::power;
+
+type Done = Sender<Result<(), String>>;
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PageSize {
+    #[default]
+    A4,
+    Letter,
+    Legal,
+}
+
+impl PageSize {
+    /// Width and height in millimetres.
+    fn dimensions(self) -> (f64, f64) {
+        match self {
+            PageSize::A4 => (210.0, 297.0),
+            PageSize::Letter => (215.9, 279.4),
+            PageSize::Legal => (215.9, 355.6),
+        }
+    }
+}
+
+/// Page margins in millimetres.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Margins {
+    pub top: f64,
+    pub right: f64,
+    pub bottom: f64,
+    pub left: f64,
+}
+
+impl Default for Margins {
+    fn default() -> Self {
+        Margins { top: 15.0, right: 15.0, bottom: 15.0, left: 15.0 }
+    }
+}
+
+/// Sent to the webview as `print-stylesheet` before printing so the print
+/// view is laid out to match.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PrintOptions {
+    pub page_size: PageSize,
+    pub margins: Margins,
+    pub line_numbers: bool,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Stage {
+    Rendering,
+    Done,
+    Failed,
+}
+
+#[derive(Clone, Serialize)]
+struct Progress<'a> {
+    path: &'a str,
+    stage: Stage,
+    error: Option<&'a str>,
+}
+
+fn window(app: &AppHandle, label: &str) -> Result<WebviewWindow, String> {
+    app.get_webview_window(label).ok_or_else(|| format!("No window \"{}\"", label))
+}
+
+pub fn print(app: &AppHandle, label: &str) -> Result<(), String> {
+    let window = window(app, label)?;
+    let _ = app.emit_to(label, "print-stylesheet", PrintOptions::default());
+    window.print().map_err(|e| e.to_string())
+}
+
+/// Opens the native print dialog for the window's document.
+#[tauri::command]
+pub fn print_document(app: AppHandle, window_label: String) -> Result<(), String> {
+    print(&app, &window_label)
+}
+
+/// Renders the window's document to a PDF at `path`. The file is written next
+/// to the target first and only moved into place once it has content.
+#[tauri::command]
+pub async fn export_pdf(
+    app: AppHandle,
+    window_label: String,
+    path: String,
+    opts: Option<PrintOptions>,
+) -> Result<(), String> {
+    let opts = opts.unwrap_or_default();
+    let _awake = power::hold(&app, "Exporting a PDF");
+    let progress = |stage, error: Option<&str>| {
+        let _ = app.emit_to(&window_label, "pdf-export-progress", Progress { path: &path, stage, error });
+    };
+    progress(Stage::Rendering, None);
+    let target = paths::decode(&path);
+    let tmp = paths::temp_beside(&target);
+    let result = match render(&app, &window_label, &tmp, opts).await {
+        Ok(()) => finish(&tmp, &target),
+        Err(e) => Err(e),
+    };
+    match &result {
+        Ok(()) => {
+            progress(Stage::Done, None);
+            notifications::completed(&app, &window_label, "Export finished", &format!("Saved {}", path));
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&tmp);
+            progress(Stage::Failed, Some(e));
+        }
+    }
+    result
+}
+
+pub fn finish(tmp: &Path, target: &Path) -> Result<(), String> {
+    let len = std::fs::metadata(tmp).map(|m| m.len()).unwrap_or(0);
+    if len == 0 {
+        return Err("The webview produced an empty PDF".into());
+    }
+    std::fs::rename(tmp, target).map_err(|e| e.to_string())
+}
+
+pub async fn render(app: &AppHandle, label: &str, path: &Path, opts: PrintOptions) -> Result<(), String> {
+    let window = window(app, label)?;
+    let _ = app.emit_to(label, "print-stylesheet", opts);
+    let (done, rx) = mpsc::channel();
+    let path = path.to_path_buf();
+    window
+        .with_webview(move |webview| {
+            if let Err(e) = platform::render(webview, &path, &opts, done.clone()) {
+                let _ = done.send(Err(e));
+            }
+        })
+        .map_err(|e| e.to_string())?;
+    tauri::async_runtime::spawn_blocking(move || rx.recv())
+        .await
+        .map_err(|e| e.to_string())?
+        .unwrap_or_else(|_| Err("PDF export was interrupted".into()))
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::cell::RefCell;
+    use std::ffi::c_void;
+    use std::path::Path;
+
+    use objc2::rc::Retained;
+    use objc2::runtime::{AnyObject, Bool, NSObject};
+    use objc2::{define_class, msg_send, sel, DefinedClass, MainThreadOnly};
+    use objc2_app_kit::{NSPrintInfo, NSPrintJobSavingURL, NSPrintOperation, NSPrintSaveJob, NSWindow};
+    use objc2_foundation::{MainThreadMarker, NSSize, NSURL};
+    use objc2_web_kit::WKWebView;
+    use tauri::webview::PlatformWebview;
+
+    use super::{Done, PrintOptions};
+
+    const POINTS_PER_MM: f64 = 72.0 / 25.4;
+
+    pub struct Ivars {
+        done: RefCell<Option<Done>>,
+    }
+
+    define_class!(
+        // SAFETY: NSObject has no subclassing requirements and the class has no Drop impl
+        #[unsafe(super(NSObject))]
+        #[thread_kind = MainThreadOnly]
+        #[name = "SkrivPdfExportDelegate"]
+        #[ivars = Ivars]
+        struct ExportDelegate;
+
+        impl ExportDelegate {
+            #[unsafe(method(printOperationDidRun:success:contextInfo:))]
+            fn did_run(&self, _operation: &NSPrintOperation, success: Bool, _context: *mut c_void) {
+                if let Some(done) = self.ivars().done.take() {
+                    let _ = done.send(if success.as_bool() { Ok(()) } else { Err("Printing to PDF failed".into()) });
+                }
+            }
+        }
+    );
+
+    impl ExportDelegate {
+        fn new(mtm: MainThreadMarker, done: Done) -> Retained<Self> {
+            let this = Self::alloc(mtm).set_ivars(Ivars { done: RefCell::new(Some(done)) });
+            unsafe { msg_send![super(this), init] }
+        }
+
+        fn running(&self) -> bool {
+            self.ivars().done.borrow().is_some()
+        }
+    }
+
+    thread_local! {
+        // AppKit doesn't retain print delegates, so the running export's is kept here
+        static DELEGATE: RefCell<Option<Retained<ExportDelegate>>> = const { RefCell::new(None) };
+    }
+
+    pub fn render(webview: PlatformWebview, path: &Path, opts: &PrintOptions, done: Done) -> Result<(), String> {
+        let mtm = MainThreadMarker::new().ok_or("PDF export must run on the main thread")?;
+        if DELEGATE.with_borrow(|d| d.as_ref().is_some_and(|d| d.running())) {
+            return Err("A PDF export is already running".into());
+        }
+        let url = NSURL::from_file_path(path).ok_or("Invalid PDF path")?;
+        // SAFETY: Tauri hands out the live WKWebView and NSWindow of this webview
+        let (webview, window) = unsafe { (&*(webview.inner() as *const WKWebView), &*(webview.ns_window() as *const NSWindow)) };
+
+        let info = NSPrintInfo::new();
+        let (width, height) = opts.page_size.dimensions();
+        info.setPaperSize(NSSize::new(width * POINTS_PER_MM, height * POINTS_PER_MM));
+        info.setTopMargin(opts.margins.top * POINTS_PER_MM);
+        info.setRightMargin(opts.margins.right * POINTS_PER_MM);
+        info.setBottomMargin(opts.margins.bottom * POINTS_PER_MM);
+        info.setLeftMargin(opts.margins.left * POINTS_PER_MM);
+        let url: &AnyObject = &url;
+        // SAFETY: the attribute constants are immutable, and NSPrintJobSavingURL takes an NSURL
+        unsafe {
+            info.setJobDisposition(NSPrintSaveJob);
+            info.dictionary().insert(NSPrintJobSavingURL, url);
+        }
+
+        let operation = unsafe { webview.printOperationWithPrintInfo(&info) };
+        operation.setShowsPrintPanel(false);
+        operation.setShowsProgressPanel(false);
+        let delegate = ExportDelegate::new(mtm, done);
+        // SAFETY: the selector matches `did_run`'s signature and the delegate is kept alive in DELEGATE
+        unsafe {
+            operation.runOperationModalForWindow_delegate_didRunSelector_contextInfo(
+                window,
+                Some(&delegate),
+                Some(sel!(printOperationDidRun:success:contextInfo:)),
+                std::ptr::null_mut(),
+            );
+        }
+        DELEGATE.set(Some(delegate));
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::path::Path;
+
+    use tauri::webview::PlatformWebview;
+    use webview2_com::Microsoft::Web::WebView2::Win32::{ICoreWebView2Environment6, ICoreWebView2_7};
+    use webview2_com::PrintToPdfCompletedHandler;
+    use windows::core::{Interface, HSTRING};
+
+    use super::{Done, PrintOptions};
+
+    const MM_PER_INCH: f64 = 25.4;
+
+    pub fn render(webview: PlatformWebview, path: &Path, opts: &PrintOptions, done: Done) -> Result<(), String> {
+        let (width, height) = opts.page_size.dimensions();
+        let margins = opts.margins;
+        // SAFETY: plain WebView2 COM calls on the webview's own thread
+        unsafe {
+            let core: ICoreWebView2_7 = webview.controller().CoreWebView2().and_then(|c| c.cast()).map_err(|e| e.to_string())?;
+            let env: ICoreWebView2Environment6 = webview.environment().cast().map_err(|e| e.to_string())?;
+            let settings = env.CreatePrintSettings().map_err(|e| e.to_string())?;
+            settings.SetPageWidth(width / MM_PER_INCH).map_err(|e| e.to_string())?;
+            settings.SetPageHeight(height / MM_PER_INCH).map_err(|e| e.to_string())?;
+            settings.SetMarginTop(margins.top / MM_PER_INCH).map_err(|e| e.to_string())?;
+            settings.SetMarginRight(margins.right / MM_PER_INCH).map_err(|e| e.to_string())?;
+            settings.SetMarginBottom(margins.bottom / MM_PER_INCH).map_err(|e| e.to_string())?;
+            settings.SetMarginLeft(margins.left / MM_PER_INCH).map_err(|e| e.to_string())?;
+            settings.SetShouldPrintHeaderAndFooter(false).map_err(|e| e.to_string())?;
+            let handler = PrintToPdfCompletedHandler::create(Box::new(move |result, success| {
+                let _ = done.send(match result {
+                    Ok(()) if success => Ok(()),
+                    Ok(()) => Err("Printing to PDF failed".into()),
+                    Err(e) => Err(e.to_string()),
+                });
+                Ok(())
+            }));
+            core.PrintToPdf(&HSTRING::from(path.as_os_str()), &settings, &handler).map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::cell::RefCell;
+    use std::path::Path;
+    use std::rc::Rc;
+
+    use gtk::{PageSetup, PaperSize, PrintSettings, Unit};
+    use tauri::webview::PlatformWebview;
+    use webkit2gtk::{PrintOperation, PrintOperationExt};
+
+    use super::{Done, PrintOptions};
+
+    pub fn render(webview: PlatformWebview, path: &Path, opts: &PrintOptions, done: Done) -> Result<(), String> {
+        let uri = gtk::glib::filename_to_uri(path, None).map_err(|e| e.to_string())?;
+        let settings = PrintSettings::new();
+        settings.set_printer("Print to File");
+        settings.set(gtk::PRINT_SETTINGS_OUTPUT_FILE_FORMAT, Some("pdf"));
+        settings.set(gtk::PRINT_SETTINGS_OUTPUT_URI, Some(&uri));
+
+        let (width, height) = opts.page_size.dimensions();
+        let setup = PageSetup::new();
+        setup.set_paper_size(&PaperSize::new_custom("skriv", "skriv", width, height, Unit::Mm));
+        setup.set_top_margin(opts.margins.top, Unit::Mm);
+        setup.set_right_margin(opts.margins.right, Unit::Mm);
+        setup.set_bottom_margin(opts.margins.bottom, Unit::Mm);
+        setup.set_left_margin(opts.margins.left, Unit::Mm);
+
+        let operation = PrintOperation::new(&webview.inner());
+        operation.set_print_settings(&settings);
+        operation.set_page_setup(&setup);
+        // `failed` is followed by `finished`, so only the first report counts
+        let done = Rc::new(RefCell::new(Some(done)));
+        let on_failed = done.clone();
+        operation.connect_failed(move |_, e| {
+            if let Some(done) = on_failed.take() {
+                let _ = done.send(Err(e.to_string()));
+            }
+        });
+        operation.connect_finished(move |_| {
+            if let Some(done) = done.take() {
+                let _ = done.send(Ok(()));
+            }
+        });
+        operation.print();
+        Ok(())
+    }
+}
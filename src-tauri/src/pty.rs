@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use portable_pty::{native_pty_system, ChildKiller, CommandBuilder, MasterPty, PtySize};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+
+#[cfg(target_os = "macos")]
+use crate::tasks;
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+struct Pty {
+    /// Label of the window the terminal is shown in
+    owner: String,
+    master: Box<dyn MasterPty + Send>,
+    // Separately locked, since a write blocks while the terminal's input is full
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    killer: Box<dyn ChildKiller + Send + Sync>,
+}
+
+#[derive(Default)]
+pub struct Ptys(Mutex<HashMap<u32, Pty>>);
+
+#[derive(Clone, Serialize)]
+struct PtyData {
+    id: u32,
+    bytes: Vec<u8>,
+}
+
+#[derive(Clone, Serialize)]
+struct PtyExited {
+    id: u32,
+    code: u32,
+}
+
+fn size(cols: u16, rows: u16) -> PtySize {
+    PtySize { rows, cols, pixel_width: 0, pixel_height: 0 }
+}
+
+fn create(app: &AppHandle, owner: String, shell: Option<String>, cwd: Option<String>, cols: u16, rows: u16) -> Result<u32, String> {
+    let pair = native_pty_system().openpty(size(cols, rows)).map_err(|e| e.to_string())?;
+    // The default program is $SHELL, or the shell registered for the user
+    // (COMSPEC on Windows)
+    let mut command = match shell {
+        Some(shell) => CommandBuilder::new(shell),
+        None => CommandBuilder::new_default_prog(),
+    };
+    command.env("TERM", "xterm-256color");
+    command.env("COLORTERM", "truecolor");
+    #[cfg(target_os = "macos")]
+    if let Some(path) = tasks::login_path() {
+        command.env("PATH", path);
+    }
+    match cwd {
+        Some(cwd) => command.cwd(cwd),
+        None => {
+            if let Ok(home) = app.path().home_dir() {
+                command.cwd(home);
+            }
+        }
+    }
+
+    let mut child = pair.slave.spawn_command(command).map_err(|e| e.to_string())?;
+    // Only the child keeps the slave open, so reads end when it exits
+    drop(pair.slave);
+    let mut reader = pair.master.try_clone_reader().map_err(|e| e.to_string())?;
+    let writer = pair.master.take_writer().map_err(|e| e.to_string())?;
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let pty = Pty { owner, master: pair.master, writer: Arc::new(Mutex::new(writer)), killer: child.clone_killer() };
+    app.state::<Ptys>().0.lock().unwrap().insert(id, pty);
+
+    let handle = app.clone();
+    std::thread::spawn(move || {
+        let mut buf = [0; 8192];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let _ = handle.emit("pty-data", PtyData { id, bytes: buf[..n].to_vec() });
+                }
+            }
+        }
+    });
+    let handle = app.clone();
+    std::thread::spawn(move || {
+        let code = child.wait().map(|status| status.exit_code()).unwrap_or(1);
+        // Dropping the master too ends the reader on Windows, where ConPTY
+        // keeps it open after the child exits
+        handle.state::<Ptys>().0.lock().unwrap().remove(&id);
+        let _ = handle.emit("pty-exited", PtyExited { id, code });
+    });
+    Ok(id)
+}
+
+/// Starts `shell` (the user's shell when `None`) in a new terminal and returns
+/// its id. Output arrives as `pty-data` events and the exit as `pty-exited`.
+#[tauri::command]
+pub async fn create_pty(
+    app: AppHandle,
+    window: tauri::Window,
+    shell: Option<String>,
+    cwd: Option<String>,
+    cols: u16,
+    rows: u16,
+) -> Result<u32, String> {
+    let owner = window.label().to_string();
+    // Blocking, since the first terminal on macOS waits for the login shell's PATH
+    tauri::async_runtime::spawn_blocking(move || create(&app, owner, shell, cwd, cols, rows))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+pub fn write_pty(ptys: tauri::State<'_, Ptys>, id: u32, data: String) -> Result<(), String> {
+    let writer = ptys.0.lock().unwrap().get(&id).map(|pty| pty.writer.clone()).ok_or("No such terminal")?;
+    let mut writer = writer.lock().unwrap();
+    writer.write_all(data.as_bytes()).and_then(|_| writer.flush()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn resize_pty(ptys: tauri::State<'_, Ptys>, id: u32, cols: u16, rows: u16) -> Result<(), String> {
+    let ptys = ptys.0.lock().unwrap();
+    let pty = ptys.get(&id).ok_or("No such terminal")?;
+    pty.master.resize(size(cols, rows)).map_err(|e| e.to_string())
+}
+
+/// Kills the terminal's process. The exit is still reported through
+/// `pty-exited` once it has been reaped.
+#[tauri::command]
+pub fn kill_pty(ptys: tauri::State<'_, Ptys>, id: u32) -> Result<(), String> {
+    let mut killer = match ptys.0.lock().unwrap().get(&id) {
+        Some(pty) => pty.killer.clone_killer(),
+        None => return Ok(()),
+    };
+    // Outside the lock, since killing waits a moment for the process to go
+    killer.kill().map_err(|e| e.to_string())
+}
+
+/// How many terminals are open, in every window.
+pub fn count(app: &AppHandle) -> usize {
+    app.state::<Ptys>().0.lock().unwrap().len()
+}
+
+/// Kills the terminals shown in a window that was closed.
+pub fn window_closed(app: &AppHandle, label: &str) {
+    let mut killers: Vec<_> =
+        app.state::<Ptys>().0.lock().unwrap().values().filter(|pty| pty.owner == label).map(|pty| pty.killer.clone_killer()).collect();
+    for killer in &mut killers {
+        let _ = killer.kill();
+    }
+}
@@ -0,0 +1,234 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use muda::accelerator::Accelerator;
+use tauri::{AppHandle, Emitter, Manager, WebviewUrl, WebviewWindowBuilder};
+use tauri_plugin_dialog::{DialogExt, MessageDialogKind};
+use tauri_plugin_fs::FsExt;
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
+
+use crate::keybindings::Keybindings;
+use crate::paths;
+use crate::settings::{self, Settings};
+
+pub const LABEL: &str = "quick-note";
+pub const DEFAULT_HOTKEY: &str = "Ctrl+Alt+Space";
+
+/// The folder under the data folder the frontend keeps the note in.
+const TEMP_DIR: &str = "temp";
+
+const WIDTH: f64 = 420.0;
+const HEIGHT: f64 = 260.0;
+
+#[derive(Default)]
+pub struct QuickNote {
+    /// Whether the quick note was summoned while another app was in front,
+    /// so hiding it should hand focus back.
+    elsewhere: AtomicBool,
+    /// The shortcut registered now.
+    shortcut: Mutex<Option<Shortcut>>,
+}
+
+/// The configured shortcut; `None` when it's turned off.
+fn configured(settings: &Settings) -> Option<String> {
+    match settings.quick_note_hotkey.as_deref() {
+        None => Some(DEFAULT_HOTKEY.to_string()),
+        Some("") => None,
+        Some(accel) => Some(accel.to_string()),
+    }
+}
+
+fn parse(accel: &str) -> Result<(Accelerator, Shortcut), String> {
+    let invalid = |e: &dyn std::fmt::Display| format!("Invalid shortcut \"{}\": {}", accel, e);
+    let accelerator = accel.parse::<Accelerator>().map_err(|e| invalid(&e))?;
+    let shortcut = accel.parse::<Shortcut>().map_err(|e| invalid(&e))?;
+    if shortcut.mods.is_empty() {
+        return Err(format!("{}: a global shortcut needs at least one modifier", accel));
+    }
+    Ok((accelerator, shortcut))
+}
+
+/// Fails if one of skriv's own menu items already has the shortcut.
+fn check_menus(app: &AppHandle, accel: &str, accelerator: &Accelerator) -> Result<(), String> {
+    let keys = app.state::<Mutex<Keybindings>>();
+    let keys = keys.lock().unwrap();
+    let taken = keys.bindings.iter().find(|(_, bound)| {
+        bound.as_deref().and_then(|bound| bound.parse::<Accelerator>().ok()).as_ref() == Some(accelerator)
+    });
+    match taken {
+        Some((id, _)) => Err(format!("{} is already the shortcut for the \"{}\" menu item", accel, id)),
+        None => Ok(()),
+    }
+}
+
+/// Swaps the registered shortcut for `accel`. The old one is only let go
+/// once the new one is in place.
+fn register(app: &AppHandle, accel: Option<&str>) -> Result<(), String> {
+    let shortcut = match accel {
+        Some(accel) => {
+            let (accelerator, shortcut) = parse(accel)?;
+            check_menus(app, accel, &accelerator)?;
+            Some(shortcut)
+        }
+        None => None,
+    };
+    let state = app.state::<QuickNote>();
+    let mut current = state.shortcut.lock().unwrap();
+    if shortcut == *current {
+        return Ok(());
+    }
+    let global = app.global_shortcut();
+    if let (Some(accel), Some(shortcut)) = (accel, shortcut) {
+        global
+            .on_shortcut(shortcut, |app, _, event| {
+                if event.state == ShortcutState::Pressed {
+                    toggle(app);
+                }
+            })
+            .map_err(|e| format!("{}: {}", accel, e))?;
+    }
+    if let Some(old) = current.take() {
+        if let Err(e) = global.unregister(old) {
+            log::warn!("Failed to unregister the old quick note shortcut: {}", e);
+        }
+    }
+    *current = shortcut;
+    Ok(())
+}
+
+/// Registers the configured shortcut at startup. A failure is shown rather
+/// than leaving the shortcut quietly dead.
+pub fn setup(app: &AppHandle) {
+    // The quick note's capability only reaches the platform's data folder
+    if paths::portable_root().is_some() {
+        if let Ok(dir) = paths::data_dir(app) {
+            let _ = app.fs_scope().allow_directory(dir.join(TEMP_DIR), false);
+        }
+    }
+    let accel = configured(&app.state::<Mutex<Settings>>().lock().unwrap());
+    let Some(accel) = accel else { return };
+    if let Err(e) = register(app, Some(&accel)) {
+        log::warn!("Failed to register the quick note shortcut: {}", e);
+        app.dialog()
+            .message(format!("{}\n\nChoose another one for Quick Note.", e))
+            .title("Quick Note Shortcut Unavailable")
+            .kind(MessageDialogKind::Warning)
+            .show(|_| {});
+    }
+}
+
+fn show(app: &AppHandle) -> tauri::Result<()> {
+    let elsewhere = !app.webview_windows().values().any(|w| w.is_focused().unwrap_or(false));
+    app.state::<QuickNote>().elsewhere.store(elsewhere, Ordering::Relaxed);
+    let window = match app.get_webview_window(LABEL) {
+        Some(window) => window,
+        None => WebviewWindowBuilder::new(app, LABEL, WebviewUrl::default())
+            .title("Quick Note")
+            .inner_size(WIDTH, HEIGHT)
+            .decorations(false)
+            .always_on_top(true)
+            .skip_taskbar(true)
+            .center()
+            .build()?,
+    };
+    window.unminimize()?;
+    window.show()?;
+    window.set_focus()?;
+    let _ = app.emit_to(LABEL, "quick-note-shown", ());
+    Ok(())
+}
+
+pub fn hide(app: &AppHandle) {
+    let Some(window) = app.get_webview_window(LABEL) else { return };
+    let _ = window.hide();
+    #[cfg(target_os = "macos")]
+    if app.state::<QuickNote>().elsewhere.swap(false, Ordering::Relaxed) {
+        // Back to the app that was in front
+        let _ = app.hide();
+    }
+}
+
+/// The shortcut's action: brings the quick note up, or hides it if it's
+/// already in front.
+pub fn toggle(app: &AppHandle) {
+    let window = app.get_webview_window(LABEL);
+    if window.is_some_and(|w| w.is_visible().unwrap_or(false) && w.is_focused().unwrap_or(false)) {
+        hide(app);
+    } else if let Err(e) = show(app) {
+        log::warn!("Failed to open the quick note: {}", e);
+    }
+}
+
+/// Without editor windows skriv quits, except on macOS; the hidden quick
+/// note mustn't keep it running.
+pub fn editors_closed(app: &AppHandle) {
+    if cfg!(not(target_os = "macos")) {
+        if let Some(window) = app.get_webview_window(LABEL) {
+            let _ = window.destroy();
+        }
+    }
+}
+
+/// Changes the shortcut, `None` or `""` turning it off, and returns the one
+/// now in effect. If it can't be registered the old one stays.
+#[tauri::command]
+pub fn set_quick_note_hotkey(app: AppHandle, accel: Option<String>) -> Result<Option<String>, String> {
+    let accel = accel.map(|a| a.trim().to_string()).filter(|a| !a.is_empty());
+    register(&app, accel.as_deref())?;
+    settings::modify(&app, |settings| settings.quick_note_hotkey = Some(accel.clone().unwrap_or_default()))?;
+    Ok(accel)
+}
+
+/// Registers the shortcut the settings now have, after an edit to the file.
+/// If it can't be, none is left registered.
+pub fn hotkey_changed(app: &AppHandle) {
+    let accel = configured(&app.state::<Mutex<Settings>>().lock().unwrap());
+    if let Err(e) = register(app, accel.as_deref()) {
+        log::warn!("Failed to register the quick note shortcut: {}", e);
+        let _ = register(app, None);
+    }
+}
+
+#[tauri::command]
+pub fn get_quick_note_hotkey(settings: tauri::State<'_, Mutex<Settings>>) -> Option<String> {
+    configured(&settings.lock().unwrap())
+}
+
+#[tauri::command]
+pub fn open_quick_note(app: AppHandle) -> Result<(), String> {
+    show(&app).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn hide_quick_note(app: AppHandle) {
+    hide(&app);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_shortcuts_with_a_modifier() {
+        let (accelerator, shortcut) = parse(DEFAULT_HOTKEY).unwrap();
+        assert_eq!(accelerator, "Ctrl+Alt+Space".parse::<Accelerator>().unwrap());
+        assert!(!shortcut.mods.is_empty());
+        assert!(parse("CmdOrCtrl+Shift+N").is_ok());
+    }
+
+    #[test]
+    fn rejects_shortcuts_without_a_modifier() {
+        assert!(parse("F5").unwrap_err().contains("modifier"));
+        assert!(parse("Ctrl+Nonsense").unwrap_err().starts_with("Invalid shortcut"));
+    }
+
+    #[test]
+    fn an_empty_setting_turns_the_shortcut_off() {
+        let mut settings = Settings::default();
+        assert_eq!(configured(&settings).as_deref(), Some(DEFAULT_HOTKEY));
+        settings.quick_note_hotkey = Some(String::new());
+        assert_eq!(configured(&settings), None);
+        settings.quick_note_hotkey = Some("Alt+Q".into());
+        assert_eq!(configured(&settings).as_deref(), Some("Alt+Q"));
+    }
+}
@@ -0,0 +1,303 @@
+use std::path::{Path, PathBuf, MAIN_SEPARATOR_STR};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::open_with::{self, OpenRequest};
+use crate::{menu, paths, window};
+
+pub const FILE_NAME: &str = "recent-workspaces.json";
+pub const MENU_PREFIX: &str = "recent_workspace:";
+
+/// Unpinned workspaces kept; older ones age out. Pinned ones always stay.
+const LIMIT: usize = 10;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    /// As `paths::encode` writes it
+    path: String,
+    #[serde(default)]
+    pinned: bool,
+    /// Seconds since the epoch
+    #[serde(default)]
+    last_opened: u64,
+}
+
+/// Folders opened as workspaces, most recent first, from FILE_NAME in the
+/// app data directory.
+#[derive(Default)]
+pub struct RecentWorkspaces(Mutex<Vec<Entry>>);
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecentWorkspace {
+    pub path: String,
+    /// The folder's name, with as much of its parent as tells it apart
+    /// from another of the same name
+    pub name: String,
+    pub pinned: bool,
+    /// False while the volume it's on isn't mounted
+    pub available: bool,
+    pub last_opened: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Presence {
+    Here,
+    /// Its parent is missing too, as when the volume isn't mounted or the
+    /// share is offline; it's kept for when it's back
+    Unavailable,
+    /// The folder alone went away, deleted or moved
+    Gone,
+}
+
+fn presence(path: &Path) -> Presence {
+    if path.is_dir() {
+        Presence::Here
+    } else if path.parent().is_some_and(Path::is_dir) {
+        Presence::Gone
+    } else {
+        Presence::Unavailable
+    }
+}
+
+fn file(app: &AppHandle) -> Result<PathBuf, String> {
+    paths::data_dir(app).map(|dir| dir.join(FILE_NAME))
+}
+
+pub fn load(app: &AppHandle) -> RecentWorkspaces {
+    let entries = file(app)
+        .ok()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default();
+    RecentWorkspaces(Mutex::new(entries))
+}
+
+fn save(app: &AppHandle, entries: &[Entry]) -> Result<(), String> {
+    let path = file(app)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let json = serde_json::to_string_pretty(entries).map_err(|e| e.to_string())?;
+    std::fs::write(path, json).map_err(|e| e.to_string())
+}
+
+fn normalize(path: &str) -> String {
+    let trimmed = path.trim_end_matches(['/', '\\']);
+    if trimmed.is_empty() { path.to_string() } else { trimmed.to_string() }
+}
+
+/// Moves `path` to the front, then ages out the unpinned entries past LIMIT.
+fn record(entries: &mut Vec<Entry>, path: &str, now: u64) {
+    let path = normalize(path);
+    let pinned = entries.iter().any(|e| e.path == path && e.pinned);
+    entries.retain(|e| e.path != path);
+    entries.insert(0, Entry { path, pinned, last_opened: now });
+    let mut unpinned = 0;
+    entries.retain(|e| {
+        unpinned += usize::from(!e.pinned);
+        e.pinned || unpinned <= LIMIT
+    });
+}
+
+/// Forgets the folders that are gone for good; says whether there were any.
+fn prune(entries: &mut Vec<Entry>, presence: impl Fn(&Path) -> Presence) -> bool {
+    let before = entries.len();
+    entries.retain(|e| presence(&paths::decode(&e.path)) != Presence::Gone);
+    entries.len() != before
+}
+
+/// Folder names, each with as many parent folders as it takes to tell it
+/// apart from the others of the same name.
+fn display_names(paths: &[&str]) -> Vec<String> {
+    let parts: Vec<Vec<&str>> = paths.iter().map(|p| p.split(['/', '\\']).filter(|s| !s.is_empty()).rev().collect()).collect();
+    let suffix = |parts: &[&str], n: usize| parts[..n.min(parts.len())].iter().rev().copied().collect::<Vec<_>>().join(MAIN_SEPARATOR_STR);
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, mine)| {
+            if mine.is_empty() {
+                return paths[i].to_string();
+            }
+            let mut n = 1;
+            while n < mine.len() && parts.iter().enumerate().any(|(j, other)| j != i && suffix(other, n) == suffix(mine, n)) {
+                n += 1;
+            }
+            suffix(mine, n)
+        })
+        .collect()
+}
+
+/// Pinned workspaces first, then the rest by when they were last opened.
+/// Folders that were deleted are forgotten on the way.
+pub fn list(app: &AppHandle) -> Vec<RecentWorkspace> {
+    let state = app.state::<RecentWorkspaces>();
+    let mut entries = state.0.lock().unwrap();
+    if prune(&mut entries, presence) {
+        if let Err(e) = save(app, &entries) {
+            log::warn!("Failed to save {}: {}", FILE_NAME, e);
+        }
+    }
+    let ordered: Vec<&Entry> = entries.iter().filter(|e| e.pinned).chain(entries.iter().filter(|e| !e.pinned)).collect();
+    let names = display_names(&ordered.iter().map(|e| e.path.as_str()).collect::<Vec<_>>());
+    ordered
+        .into_iter()
+        .zip(names)
+        .map(|(e, name)| RecentWorkspace {
+            path: e.path.clone(),
+            name,
+            pinned: e.pinned,
+            available: presence(&paths::decode(&e.path)) == Presence::Here,
+            last_opened: e.last_opened,
+        })
+        .collect()
+}
+
+/// Changes the list and saves it, then rebuilds File > Open Recent and
+/// tells windows with `recent-workspaces-changed`.
+fn update(app: &AppHandle, change: impl FnOnce(&mut Vec<Entry>)) -> Result<(), String> {
+    {
+        let state = app.state::<RecentWorkspaces>();
+        let mut entries = state.0.lock().unwrap();
+        change(&mut entries);
+        save(app, &entries)?;
+    }
+    menu::rebuild(app);
+    let _ = app.emit("recent-workspaces-changed", list(app));
+    Ok(())
+}
+
+/// A window opened `root` as its workspace.
+pub fn opened(app: &AppHandle, root: &Path) {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    if let Err(e) = update(app, |entries| record(entries, &paths::encode(root), now)) {
+        log::warn!("Failed to save {}: {}", FILE_NAME, e);
+    }
+}
+
+/// Opens `path` as the workspace of the window `label`, or of a new window
+/// when `new_window` is set or there's no such window.
+pub fn open(app: &AppHandle, label: Option<&str>, path: String, new_window: bool) -> Result<(), String> {
+    if presence(&paths::decode(&path)) != Presence::Here {
+        return Err(format!("{} isn't available", path));
+    }
+    let request = OpenRequest::Folder { path };
+    match label.filter(|_| !new_window) {
+        Some(label) => open_with::deliver_to(app, label, vec![request]),
+        None => {
+            let label = window::open_new(app).map_err(|e| e.to_string())?;
+            open_with::deliver_to(app, &label, vec![request]);
+        }
+    }
+    Ok(())
+}
+
+/// Whether the menu item was chosen with Option held, asking for a new
+/// window. Other platforms' menus don't pass modifiers on.
+pub fn alternate_click() -> bool {
+    #[cfg(target_os = "macos")]
+    {
+        use objc2_app_kit::{NSEvent, NSEventModifierFlags};
+        NSEvent::modifierFlags_class().contains(NSEventModifierFlags::Option)
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        false
+    }
+}
+
+#[tauri::command]
+pub fn get_recent_workspaces(app: AppHandle) -> Vec<RecentWorkspace> {
+    list(&app)
+}
+
+#[tauri::command]
+pub fn pin_workspace(app: AppHandle, path: String, pinned: Option<bool>) -> Result<(), String> {
+    let path = normalize(&path);
+    update(&app, |entries| {
+        for entry in entries.iter_mut().filter(|e| e.path == path) {
+            entry.pinned = pinned.unwrap_or(true);
+        }
+    })
+}
+
+#[tauri::command]
+pub fn remove_recent_workspace(app: AppHandle, path: String) -> Result<(), String> {
+    let path = normalize(&path);
+    update(&app, |entries| entries.retain(|e| e.path != path))
+}
+
+/// Forgets every recent workspace that isn't pinned.
+#[tauri::command]
+pub fn clear_recent_workspaces(app: AppHandle) -> Result<(), String> {
+    update(&app, |entries| entries.retain(|e| e.pinned))
+}
+
+/// Async so window creation doesn't deadlock the webview on Windows.
+#[tauri::command]
+pub async fn open_workspace(app: AppHandle, window: tauri::Window, path: String, new_window: Option<bool>) -> Result<(), String> {
+    open(&app, Some(window.label()), path, new_window.unwrap_or(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, pinned: bool) -> Entry {
+        Entry { path: path.into(), pinned, last_opened: 0 }
+    }
+
+    #[test]
+    fn ages_out_unpinned_workspaces() {
+        let mut entries = vec![entry("/pinned", true)];
+        for i in 0..LIMIT + 3 {
+            record(&mut entries, &format!("/w{}/", i), i as u64);
+        }
+        assert_eq!(entries.len(), LIMIT + 1);
+        assert_eq!(entries[0].path, format!("/w{}", LIMIT + 2));
+        assert!(entries.iter().any(|e| e.path == "/pinned"));
+        assert!(!entries.iter().any(|e| e.path == "/w2"));
+        // Opening a pinned one again keeps it pinned
+        record(&mut entries, "/pinned", 99);
+        assert_eq!(entries[0], Entry { path: "/pinned".into(), pinned: true, last_opened: 99 });
+        assert_eq!(entries.len(), LIMIT + 1);
+    }
+
+    #[test]
+    fn keeps_workspaces_on_missing_volumes() {
+        let mut entries = vec![entry("/Volumes/USB/notes", false), entry("/home/me/deleted", true), entry("/home/me/here", false)];
+        let fake = |path: &Path| match path.to_str().unwrap() {
+            "/home/me/here" => Presence::Here,
+            "/home/me/deleted" => Presence::Gone,
+            _ => Presence::Unavailable,
+        };
+        assert!(prune(&mut entries, fake));
+        let left: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(left, ["/Volumes/USB/notes", "/home/me/here"]);
+        assert!(!prune(&mut entries, fake));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn round_trips_roots_that_arent_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+        let root = Path::new(std::ffi::OsStr::from_bytes(b"/home/me/caf\xe9"));
+        let mut entries = Vec::new();
+        record(&mut entries, &paths::encode(root), 1);
+        let saved: Vec<Entry> = serde_json::from_str(&serde_json::to_string(&entries).unwrap()).unwrap();
+        assert_eq!(paths::decode(&saved[0].path), root);
+        // Opening it again finds the same entry
+        record(&mut entries, &paths::encode(root), 2);
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[test]
+    fn tells_folders_of_the_same_name_apart() {
+        let sep = MAIN_SEPARATOR_STR;
+        let names = display_names(&["/work/a/site", "/work/b/site", "/home/me/notes", "C:\\dev\\b\\site", "/"]);
+        assert_eq!(names, [format!("a{}site", sep), format!("work{0}b{0}site", sep), "notes".to_string(), format!("dev{0}b{0}site", sep), "/".to_string()]);
+    }
+}
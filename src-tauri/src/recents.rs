@@ -0,0 +1,69 @@
+use tauri::AppHandle;
+
+use crate::paths;
+
+/// Adds a file the user opened to the system's recent documents, which macOS
+/// shows under Apple menu > Recent Items and in the Dock menu.
+#[tauri::command]
+pub fn note_file_opened(app: AppHandle, path: String) {
+    platform::note_opened(&app, &paths::decode(&path));
+}
+
+#[tauri::command]
+pub fn clear_recent_documents(app: AppHandle) {
+    platform::clear(&app);
+}
+
+/// Whether the system wants the last session's windows back at launch. On
+/// macOS that's no while "Close windows when quitting an application" is on.
+#[tauri::command]
+pub fn restores_windows() -> bool {
+    platform::restores_windows()
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::path::Path;
+
+    use objc2::MainThreadMarker;
+    use objc2_app_kit::NSDocumentController;
+    use objc2_foundation::{NSString, NSUserDefaults, NSURL};
+    use tauri::AppHandle;
+
+    pub fn note_opened(app: &AppHandle, path: &Path) {
+        let path = path.to_path_buf();
+        let _ = app.run_on_main_thread(move || {
+            let Some(mtm) = MainThreadMarker::new() else { return };
+            let Some(url) = NSURL::from_file_path(&path) else { return };
+            NSDocumentController::sharedDocumentController(mtm).noteNewRecentDocumentURL(&url);
+        });
+    }
+
+    pub fn clear(app: &AppHandle) {
+        let _ = app.run_on_main_thread(|| {
+            let Some(mtm) = MainThreadMarker::new() else { return };
+            // SAFETY: the sender is only passed along to the action
+            unsafe { NSDocumentController::sharedDocumentController(mtm).clearRecentDocuments(None) };
+        });
+    }
+
+    pub fn restores_windows() -> bool {
+        // Unset is the default, with the checkbox on
+        NSUserDefaults::standardUserDefaults().boolForKey(&NSString::from_str("NSQuitAlwaysKeepsWindows"))
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+mod platform {
+    use std::path::Path;
+
+    use tauri::AppHandle;
+
+    pub fn note_opened(_app: &AppHandle, _path: &Path) {}
+
+    pub fn clear(_app: &AppHandle) {}
+
+    pub fn restores_windows() -> bool {
+        true
+    }
+}
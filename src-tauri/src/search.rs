@@ -0,0 +1,468 @@
+//! Find in Files, and exporting what it found. Matches reach the window in
+//! batches as `find-in-files-progress`, the first as soon as the operation
+//! exists so the window has its id for `cancel_operation`. The last few
+//! searches are kept by that id, so an export can name one rather than send
+//! its matches back; one stopped at its limit is run again uncapped first,
+//! with `search-export-progress` while it runs.
+
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use git2::Repository;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::operations::{self, Operation};
+use crate::{clipboard, paths};
+
+/// Matches per progress event.
+const BATCH: usize = 200;
+/// Searches kept for export.
+const KEPT: usize = 10;
+/// Files past this size aren't searched.
+const MAX_FILE_BYTES: u64 = 64 * 1024 * 1024;
+/// How far into a file to look for a NUL, which marks it as binary.
+const BINARY_SNIFF: usize = 8000;
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SearchOptions {
+    pub regex: bool,
+    pub match_case: bool,
+    pub whole_word: bool,
+    /// Skips what the folder's git repository ignores, and `.git` itself
+    pub respect_gitignore: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchMatch {
+    pub path: String,
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in UTF-16 code units as the editor counts them
+    pub column: usize,
+    pub text: String,
+    /// The lines around it, for context
+    #[serde(default)]
+    pub before: Option<String>,
+    #[serde(default)]
+    pub after: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResults {
+    pub root: String,
+    pub query: String,
+    pub options: SearchOptions,
+    pub matches: Vec<SearchMatch>,
+    /// Stopped at its limit or cancelled, so `matches` isn't all there is
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+#[derive(Default)]
+pub struct Searches(Mutex<VecDeque<(u64, SearchResults)>>);
+
+impl Searches {
+    /// The searches kept and the matches they hold, for diagnostics.
+    pub fn usage(&self) -> (usize, usize) {
+        let searches = self.0.lock().unwrap();
+        (searches.len(), searches.iter().map(|(_, results)| results.matches.len()).sum())
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Progress {
+    id: u64,
+    matches: Vec<SearchMatch>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportProgress {
+    id: u64,
+    /// The search being run again
+    search: u64,
+    matches: usize,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchSummary {
+    pub id: u64,
+    pub matches: usize,
+    pub files: usize,
+    pub truncated: bool,
+    pub cancelled: bool,
+    /// Files and folders that couldn't be read, with why
+    pub errors: Vec<String>,
+}
+
+/// What to export: a kept search by its operation id, or results the
+/// window has.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SearchSource {
+    Operation(u64),
+    Results(SearchResults),
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExportFormat {
+    /// `path:line:column: text`, as grep and quickfix lists read it
+    Text,
+    /// Grouped by file, each line in a fence with the lines around it
+    Markdown,
+    Json,
+}
+
+fn pattern(query: &str, options: &SearchOptions) -> Result<Regex, String> {
+    let source = if options.regex { query.to_string() } else { regex::escape(query) };
+    let source = if options.whole_word { format!(r"\b(?:{})\b", source) } else { source };
+    RegexBuilder::new(&source).case_insensitive(!options.match_case).build().map_err(|e| e.to_string())
+}
+
+/// The matches in one file's text.
+fn search_text(pattern: &Regex, path: &str, text: &str) -> Vec<SearchMatch> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut found = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        for m in pattern.find_iter(line).filter(|m| !m.is_empty()) {
+            found.push(SearchMatch {
+                path: path.to_string(),
+                line: i + 1,
+                column: line[..m.start()].encode_utf16().count() + 1,
+                text: line.to_string(),
+                before: i.checked_sub(1).map(|before| lines[before].to_string()),
+                after: lines.get(i + 1).map(|after| after.to_string()),
+            });
+        }
+    }
+    found
+}
+
+struct Walk<'a> {
+    pattern: Regex,
+    repo: Option<Repository>,
+    /// Canonical folders walked into, so a link back up isn't walked again
+    visited: HashSet<PathBuf>,
+    limit: Option<usize>,
+    operation: Option<&'a Operation>,
+    summary: SearchSummary,
+    matches: Vec<SearchMatch>,
+    /// Of `matches`, how many were emitted
+    emitted: usize,
+    emit: &'a dyn Fn(&[SearchMatch]),
+}
+
+impl Walk<'_> {
+    fn is_ignored(&self, path: &Path) -> bool {
+        let Some(repo) = &self.repo else { return false };
+        if path.file_name().is_some_and(|name| name == ".git") {
+            return true;
+        }
+        let Some(relative) = repo.workdir().and_then(|workdir| path.strip_prefix(workdir).ok()) else { return false };
+        repo.is_path_ignored(relative).unwrap_or(false)
+    }
+
+    fn done(&mut self) -> bool {
+        self.summary.cancelled = self.summary.cancelled || self.operation.is_some_and(Operation::is_cancelled);
+        self.summary.cancelled || self.summary.truncated
+    }
+
+    fn flush(&mut self) {
+        if self.emitted < self.matches.len() {
+            (self.emit)(&self.matches[self.emitted..]);
+            self.emitted = self.matches.len();
+        }
+    }
+
+    fn file(&mut self, path: &Path, size: u64) {
+        if size > MAX_FILE_BYTES {
+            return;
+        }
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => return self.summary.errors.push(format!("{}: {}", path.display(), e)),
+        };
+        if bytes[..bytes.len().min(BINARY_SNIFF)].contains(&0) {
+            return;
+        }
+        let mut found = search_text(&self.pattern, &paths::encode(path), &String::from_utf8_lossy(&bytes));
+        if let Some(limit) = self.limit.filter(|limit| self.matches.len() + found.len() > *limit) {
+            found.truncate(limit - self.matches.len());
+            self.summary.truncated = true;
+        }
+        if found.is_empty() {
+            return;
+        }
+        self.summary.files += 1;
+        self.matches.extend(found);
+        if self.matches.len() - self.emitted >= BATCH {
+            self.flush();
+        }
+    }
+
+    /// Searches the folder, depth first in name order.
+    fn folder(&mut self, dir: &Path) {
+        let canonical = std::fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        if !self.visited.insert(canonical) {
+            return;
+        }
+        let mut entries: Vec<PathBuf> = match std::fs::read_dir(dir) {
+            Ok(entries) => entries.filter_map(Result::ok).map(|entry| entry.path()).filter(|path| !self.is_ignored(path)).collect(),
+            Err(e) => return self.summary.errors.push(format!("{}: {}", dir.display(), e)),
+        };
+        entries.sort();
+        for path in entries {
+            if self.done() {
+                return;
+            }
+            match std::fs::metadata(&path) {
+                Ok(meta) if meta.is_dir() => self.folder(&path),
+                Ok(meta) => self.file(&path, meta.len()),
+                Err(e) => self.summary.errors.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+    }
+}
+
+/// Searches everything under `root`, stopping after `limit` matches.
+fn search(
+    root: &Path,
+    query: &str,
+    options: &SearchOptions,
+    limit: Option<usize>,
+    operation: Option<&Operation>,
+    emit: &dyn Fn(&[SearchMatch]),
+) -> Result<(SearchSummary, Vec<SearchMatch>), String> {
+    if !root.is_dir() {
+        return Err(format!("{} isn't a folder", root.display()));
+    }
+    let repo = if options.respect_gitignore { Repository::discover(root).ok() } else { None };
+    let mut walk = Walk {
+        pattern: pattern(query, options)?,
+        repo,
+        visited: HashSet::new(),
+        limit,
+        operation,
+        summary: SearchSummary::default(),
+        matches: Vec::new(),
+        emitted: 0,
+        emit,
+    };
+    walk.folder(root);
+    walk.flush();
+    walk.summary.matches = walk.matches.len();
+    Ok((walk.summary, walk.matches))
+}
+
+fn keep(app: &AppHandle, id: u64, results: SearchResults) {
+    let searches = app.state::<Searches>();
+    let mut searches = searches.0.lock().unwrap();
+    searches.retain(|(kept, _)| *kept != id);
+    searches.push_back((id, results));
+    if searches.len() > KEPT {
+        searches.pop_front();
+    }
+}
+
+/// Searches the files under `root` for `query`, up to `limit` matches.
+#[tauri::command]
+pub async fn find_in_files(
+    app: AppHandle,
+    window: tauri::Window,
+    root: String,
+    query: String,
+    options: Option<SearchOptions>,
+    limit: Option<usize>,
+) -> Result<SearchSummary, String> {
+    let label = window.label().to_string();
+    tauri::async_runtime::spawn_blocking(move || {
+        let operation = operations::begin(&app);
+        let emit = |matches: &[SearchMatch]| {
+            let _ = app.emit_to(&label, "find-in-files-progress", Progress { id: operation.id, matches: matches.to_vec() });
+        };
+        emit(&[]);
+        let options = options.unwrap_or_default();
+        let (mut summary, matches) = search(&paths::decode(&root), &query, &options, limit, Some(&operation), &emit)?;
+        summary.id = operation.id;
+        let truncated = summary.truncated || summary.cancelled;
+        keep(&app, operation.id, SearchResults { root, query, options, matches, truncated });
+        Ok(summary)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// The whole result set of `source`, running a kept search again when it
+/// didn't get everything.
+fn full_results(app: &AppHandle, source: SearchSource) -> Result<SearchResults, String> {
+    let id = match source {
+        SearchSource::Results(results) => return Ok(results),
+        SearchSource::Operation(id) => id,
+    };
+    let kept = app.state::<Searches>().0.lock().unwrap().iter().find(|(kept, _)| *kept == id).map(|(_, results)| results.clone());
+    let kept = kept.ok_or("That search is no longer kept; search again")?;
+    if !kept.truncated {
+        return Ok(kept);
+    }
+    let operation = operations::begin(app);
+    let found = std::cell::Cell::new(0);
+    let emit = |matches: &[SearchMatch]| {
+        found.set(found.get() + matches.len());
+        let _ = app.emit("search-export-progress", ExportProgress { id: operation.id, search: id, matches: found.get() });
+    };
+    emit(&[]);
+    let (summary, matches) = search(&paths::decode(&kept.root), &kept.query, &kept.options, None, Some(&operation), &emit)?;
+    if summary.cancelled {
+        return Err("Cancelled".into());
+    }
+    let results = SearchResults { matches, truncated: false, ..kept };
+    keep(app, id, results.clone());
+    Ok(results)
+}
+
+fn describe(options: &SearchOptions) -> String {
+    let flags = [(options.regex, "regex"), (options.match_case, "match case"), (options.whole_word, "whole word"), (options.respect_gitignore, "respecting .gitignore")];
+    let on: Vec<&str> = flags.iter().filter(|(on, _)| *on).map(|(_, name)| *name).collect();
+    if on.is_empty() { "literal".to_string() } else { on.join(", ") }
+}
+
+/// A fence longer than any run of backticks in `lines`.
+fn fence<'a>(lines: impl Iterator<Item = &'a str>) -> String {
+    let longest = lines.flat_map(|line| line.split(|c| c != '`')).map(str::len).max().unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+fn format_results(results: &SearchResults, format: ExportFormat, searched: &str) -> Result<String, String> {
+    let root = paths::decode(&results.root);
+    let mut out = String::new();
+    match format {
+        ExportFormat::Text => {
+            out += &format!("# \"{}\" in {} ({}), {}\n", results.query, results.root, describe(&results.options), searched);
+            for m in &results.matches {
+                out += &format!("{}:{}:{}: {}\n", m.path, m.line, m.column, m.text);
+            }
+        }
+        ExportFormat::Markdown => {
+            out += &format!("# Search results for `{}`\n\n", results.query.replace('`', "\\`"));
+            out += &format!("- Folder: {}\n- Options: {}\n- Searched: {}\n", results.root, describe(&results.options), searched);
+            let files = results.matches.iter().map(|m| &m.path).collect::<HashSet<_>>().len();
+            out += &format!("- {} matches in {} files\n", results.matches.len(), files);
+            let mut file = None;
+            let mut line = 0;
+            for m in &results.matches {
+                if file != Some(&m.path) {
+                    let relative = paths::decode(&m.path).strip_prefix(&root).map_or_else(|_| m.path.clone(), |path| paths::encode(path).replace('\\', "/"));
+                    out += &format!("\n## {}\n", relative);
+                    (file, line) = (Some(&m.path), 0);
+                }
+                // Each line once, however often it matched
+                if m.line == line {
+                    continue;
+                }
+                line = m.line;
+                let context: Vec<(usize, &str)> =
+                    [(m.line - 1, m.before.as_deref()), (m.line, Some(m.text.as_str())), (m.line + 1, m.after.as_deref())].into_iter().filter_map(|(n, text)| Some((n, text?))).collect();
+                let fence = fence(context.iter().map(|(_, text)| *text));
+                out += &format!("\nLine {}:\n\n{}\n", m.line, fence);
+                for (n, text) in context {
+                    out += &format!("{:>5}  {}\n", n, text);
+                }
+                out += &format!("{}\n", fence);
+            }
+        }
+        ExportFormat::Json => {
+            let export = serde_json::json!({
+                "query": results.query,
+                "root": results.root,
+                "options": results.options,
+                "searched": searched,
+                "matches": results.matches,
+            });
+            out = serde_json::to_string_pretty(&export).map_err(|e| e.to_string())?;
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+fn export_text(app: &AppHandle, source: SearchSource, format: ExportFormat) -> Result<(String, usize), String> {
+    let results = full_results(app, source)?;
+    let searched = chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%:z").to_string();
+    Ok((format_results(&results, format, &searched)?, results.matches.len()))
+}
+
+/// Writes the results to `path` through a temporary file beside it, so a
+/// failed export leaves nothing half written. Returns how many matches it
+/// has.
+#[tauri::command]
+pub async fn export_search_results(app: AppHandle, source: SearchSource, format: ExportFormat, path: String) -> Result<usize, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let (text, count) = export_text(&app, source, format)?;
+        let path = paths::decode(&path);
+        if path.file_name().is_none() {
+            return Err("Not a file path".into());
+        }
+        paths::write_atomic(&path, text).map_err(|e| e.to_string())?;
+        log::info!("Exported {} search results to {}", count, path.display());
+        Ok(count)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Puts the results on the clipboard, formatted as for a file.
+#[tauri::command]
+pub async fn copy_search_results(app: AppHandle, source: SearchSource, format: ExportFormat) -> Result<usize, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let (text, count) = export_text(&app, source, format)?;
+        clipboard::write_text(&text)?;
+        Ok(count)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results(matches: Vec<SearchMatch>) -> SearchResults {
+        SearchResults { root: "/w".into(), query: "x".into(), options: SearchOptions::default(), matches, truncated: false }
+    }
+
+    #[test]
+    fn searches_up_to_the_limit() {
+        let root = std::env::temp_dir().join(format!("skriv-search-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("a.md"), "Fox fox\nfoxes\n").unwrap();
+        std::fs::write(root.join("sub/b.md"), "—fox\n").unwrap();
+        std::fs::write(root.join("bin"), b"fox\0").unwrap();
+        let run = |options: &SearchOptions, limit| {
+            let (summary, matches) = search(&root, "fox", options, limit, None, &|_| {}).unwrap();
+            (summary.truncated, matches.into_iter().map(|m| (m.line, m.column)).collect::<Vec<_>>())
+        };
+        assert_eq!(run(&SearchOptions::default(), None), (false, vec![(1, 1), (1, 5), (2, 1), (1, 2)]));
+        assert_eq!(run(&SearchOptions { match_case: true, whole_word: true, ..Default::default() }, None), (false, vec![(1, 5), (1, 2)]));
+        assert_eq!(run(&SearchOptions::default(), Some(2)), (true, vec![(1, 1), (1, 5)]));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn formats_exports() {
+        let found = search_text(&pattern("x", &SearchOptions::default()).unwrap(), "/w/src/a.rs", "one\nx = x\n```\n");
+        let text = format_results(&results(found.clone()), ExportFormat::Text, "T").unwrap();
+        assert_eq!(text, "# \"x\" in /w (literal), T\n/w/src/a.rs:2:1: x = x\n/w/src/a.rs:2:5: x = x\n");
+        let markdown = format_results(&results(found), ExportFormat::Markdown, "T").unwrap();
+        assert!(markdown.contains("- 2 matches in 1 files\n\n## src/a.rs\n\nLine 2:\n\n````\n    1  one\n    2  x = x\n    3  ```\n````\n"), "{}", markdown);
+        assert_eq!(markdown.matches("Line 2").count(), 1);
+    }
+}
@@ -0,0 +1,32 @@
+//! Tokens kept in the OS keychain: the Keychain on macOS, the Credential
+//! Manager on Windows and the Secret Service on Linux. Nothing here is ever
+//! logged or written to settings; errors name the account, not the secret.
+
+use keyring::{Entry, Error};
+
+const SERVICE: &str = "net.feryla.skriv";
+
+fn entry(account: &str) -> Result<Entry, String> {
+    Entry::new(SERVICE, account).map_err(|e| format!("The keychain is unavailable: {}", e))
+}
+
+/// The secret for `account`, if one is stored.
+pub fn get(account: &str) -> Result<Option<String>, String> {
+    match entry(account)?.get_password() {
+        Ok(secret) => Ok(Some(secret)),
+        Err(Error::NoEntry) => Ok(None),
+        Err(e) => Err(format!("Failed to read {} from the keychain: {}", account, e)),
+    }
+}
+
+pub fn set(account: &str, secret: &str) -> Result<(), String> {
+    entry(account)?.set_password(secret).map_err(|e| format!("Failed to store {} in the keychain: {}", account, e))
+}
+
+/// Forgets the secret for `account`; there being none is fine.
+pub fn delete(account: &str) -> Result<(), String> {
+    match entry(account)?.delete_credential() {
+        Ok(()) | Err(Error::NoEntry) => Ok(()),
+        Err(e) => Err(format!("Failed to remove {} from the keychain: {}", account, e)),
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use tauri::{AppHandle, Manager};
+#[cfg(target_os = "macos")]
+use tauri::Emitter;
+
+#[cfg(target_os = "macos")]
+use crate::{menu, window};
+
+/// Text handed to skriv through Services > New skriv Note with Selection
+/// before any window was ready to take it.
+#[derive(Default)]
+pub struct PendingNotes {
+    texts: Mutex<Vec<String>>,
+    ready: Mutex<HashSet<String>>,
+}
+
+#[cfg(target_os = "macos")]
+#[derive(Clone, serde::Serialize)]
+struct NotePayload {
+    text: String,
+}
+
+/// Opens `text` as a new untitled tab, in the focused window if it's ready,
+/// otherwise in any ready one. Without one it's stashed (opening a window if
+/// there is none) until a window calls `notes_ready`.
+#[cfg(target_os = "macos")]
+pub fn new_note(app: &AppHandle, text: String) {
+    let _ = app.show();
+    let pending = app.state::<PendingNotes>();
+    let ready = pending.ready.lock().unwrap();
+    let focused = menu::focused_window(app).filter(|w| ready.contains(w.label()));
+    let target = focused.or_else(|| ready.iter().find_map(|label| app.get_webview_window(label)));
+    match target {
+        Some(window) => {
+            let _ = window.set_focus();
+            let _ = app.emit_to(window.label(), "new-note", NotePayload { text });
+        }
+        None => {
+            pending.texts.lock().unwrap().push(text);
+            if app.webview_windows().is_empty() {
+                if let Err(e) = window::open_new(app) {
+                    log::warn!("Failed to open window: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Called by each window once it can open tabs. Returns the notes that
+/// arrived before then.
+#[tauri::command]
+pub fn notes_ready(app: AppHandle, window: tauri::Window) -> Vec<String> {
+    let pending = app.state::<PendingNotes>();
+    pending.ready.lock().unwrap().insert(window.label().to_string());
+    let texts = std::mem::take(&mut *pending.texts.lock().unwrap());
+    texts
+}
+
+pub fn window_closed(app: &AppHandle, label: &str) {
+    app.state::<PendingNotes>().ready.lock().unwrap().remove(label);
+}
+
+/// Registers the handler for the NSServices entry declared in Info.plist.
+#[cfg(target_os = "macos")]
+pub fn register(app: &AppHandle) {
+    use std::cell::RefCell;
+
+    use objc2::rc::Retained;
+    use objc2::runtime::NSObject;
+    use objc2::{define_class, msg_send, DefinedClass, MainThreadOnly};
+    use objc2_app_kit::{NSApplication, NSPasteboard, NSPasteboardTypeString, NSUpdateDynamicServices};
+    use objc2_foundation::{MainThreadMarker, NSString};
+
+    pub struct Ivars {
+        app: AppHandle,
+    }
+
+    define_class!(
+        // SAFETY: NSObject has no subclassing requirements and the class has no Drop impl
+        #[unsafe(super(NSObject))]
+        #[thread_kind = MainThreadOnly]
+        #[name = "SkrivServicesProvider"]
+        #[ivars = Ivars]
+        struct ServicesProvider;
+
+        impl ServicesProvider {
+            // NSMessage "newNoteWithSelection" in Info.plist
+            #[unsafe(method(newNoteWithSelection:userData:error:))]
+            fn new_note_with_selection(&self, pboard: &NSPasteboard, _user_data: Option<&NSString>, _error: *mut *mut NSString) {
+                // SAFETY: immutable AppKit string constant
+                let kind = unsafe { NSPasteboardTypeString };
+                if let Some(text) = pboard.stringForType(kind) {
+                    new_note(&self.ivars().app, text.to_string());
+                }
+            }
+        }
+    );
+
+    thread_local! {
+        // NSApp doesn't retain its services provider
+        static PROVIDER: RefCell<Option<Retained<ServicesProvider>>> = const { RefCell::new(None) };
+    }
+
+    let Some(mtm) = MainThreadMarker::new() else { return };
+    let provider = ServicesProvider::alloc(mtm).set_ivars(Ivars { app: app.clone() });
+    let provider: Retained<ServicesProvider> = unsafe { msg_send![super(provider), init] };
+    // SAFETY: the provider implements the message the service declares
+    unsafe { NSApplication::sharedApplication(mtm).setServicesProvider(Some(&provider)) };
+    NSUpdateDynamicServices();
+    PROVIDER.set(Some(provider));
+}
@@ -0,0 +1,538 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::format::Formatter;
+use crate::lint::LintProfile;
+use crate::logging::{self, LogSettings};
+use crate::network::NetworkSettings;
+use crate::updates::UpdateChannel;
+use crate::settings_validation::{self, SettingsProblem};
+use crate::{control, i18n, paths, quick_note, theme, workers};
+
+pub const FILE_NAME: &str = "settings.json";
+/// The layout of the file this build writes. Bumped with each migration.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Each one takes a file from the version at its index to the next.
+const MIGRATIONS: [fn(&mut Map<String, Value>); SCHEMA_VERSION as usize] = [theme_mode_to_appearance];
+
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemeMode {
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Appearance {
+    pub mode: ThemeMode,
+    /// Id of a color theme from the themes folder, over the mode's own
+    pub color_theme: Option<String>,
+}
+
+/// Editing defaults for files without an `.editorconfig` saying otherwise.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct EditorSettings {
+    pub tab_size: Option<u32>,
+    pub insert_spaces: Option<bool>,
+    pub font_size: Option<u32>,
+    /// A CSS font-family list
+    pub font_family: Option<String>,
+    /// Wraps long lines in every window, whatever View > Word Wrap was left at
+    pub word_wrap: Option<bool>,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AutoSave {
+    #[default]
+    Off,
+    /// Once typing stops for `autoSaveDelay`
+    AfterDelay,
+    /// When the window loses focus
+    OnFocusChange,
+}
+
+/// What happens to an open document when another program changes its file.
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AutoRevert {
+    /// Changes on disk go unnoticed
+    Off,
+    /// Read again without asking, but for documents with unsaved edits
+    WhenClean,
+    /// Each change asks whether to read the file again
+    #[default]
+    #[serde(alias = "always-prompt")]
+    AlwaysPrompt,
+}
+
+/// When edits are written to files on disk without a Save, and when changes
+/// on disk are read back.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct FileSettings {
+    pub auto_save: AutoSave,
+    /// Milliseconds, 1000 when unset
+    pub auto_save_delay: Option<u32>,
+    /// Language ids by glob, as `"*.tpl": "html"`, ahead of any detection.
+    /// Globs without a slash match the file name.
+    pub associations: HashMap<String, String>,
+    pub auto_revert: AutoRevert,
+    /// Characters past which a line counts as long, as in minified files;
+    /// 10000 when unset
+    pub long_line_threshold: Option<usize>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SearchSettings {
+    /// Globs of paths searches leave out, relative to the workspace
+    pub exclude: Vec<String>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PerformanceSettings {
+    /// Threads for background work like highlighting and indexing; one
+    /// less than the cores when unset
+    pub worker_threads: Option<usize>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct AutomationSettings {
+    /// Listens for `skriv --ctl` and other local scripts
+    pub enabled: bool,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Settings {
+    /// The layout the file was written in; see `SCHEMA_VERSION`
+    pub schema_version: u32,
+    pub appearance: Appearance,
+    /// Overrides the OS locale for the menus
+    pub locale: Option<String>,
+    /// Your name, for `{{author}}` in file templates
+    pub author: Option<String>,
+    /// External formatters by language id, used by Format Document
+    pub formatters: HashMap<String, Formatter>,
+    /// Terminal for Open Terminal Here, by app or command name
+    pub terminal: Option<String>,
+    /// Linters by name, run with Lint: Run
+    pub linters: HashMap<String, LintProfile>,
+    /// Sends a system notification when an export or update finishes while
+    /// skriv is in the background
+    pub notify_when_done: bool,
+    /// System-wide shortcut for the quick note window, Ctrl+Alt+Space when
+    /// unset; empty turns it off
+    pub quick_note_hotkey: Option<String>,
+    /// Saving a file that starts with `#!` makes it executable
+    pub make_scripts_executable: bool,
+    /// Which releases Check for Updates looks at
+    pub update_channel: UpdateChannel,
+    /// A release the launch check no longer offers
+    pub skipped_update: Option<String>,
+    /// Unix time in seconds until which the launch check stays quiet
+    pub updates_snoozed_until: Option<u64>,
+    /// A downloaded update waits for skriv to quit instead of asking to restart
+    pub install_updates_on_quit: bool,
+    /// Set while an update is installed on quit. Still there at launch with
+    /// an older version running means the install didn't finish.
+    pub interrupted_update: Option<String>,
+    /// Proxy override for update checks and other downloads
+    pub network: NetworkSettings,
+    /// Indentation and other editing defaults; a workspace can set these too
+    pub editor: EditorSettings,
+    pub search: SearchSettings,
+    pub files: FileSettings,
+    pub performance: PerformanceSettings,
+    /// Log levels set from Help > Troubleshooting or `set_log_level`
+    pub logging: LogSettings,
+    /// The control socket scripts drive skriv through; off by default
+    pub automation: AutomationSettings,
+    /// Keys this build doesn't know, as from a newer release, kept as they are
+    #[serde(flatten)]
+    pub unknown: Map<String, Value>,
+}
+
+fn path(app: &AppHandle) -> Result<PathBuf, String> {
+    paths::config_dir(app).map(|dir| dir.join(FILE_NAME))
+}
+
+/// What happened to the file at startup, told to the first window that
+/// listens, as none is yet.
+enum Notice {
+    Warning(String),
+    Migrated { from: u32, to: u32 },
+    Problems(Vec<SettingsProblem>),
+}
+
+#[derive(Default)]
+pub struct Notices(Mutex<Vec<Notice>>);
+
+/// Where a change came from: the user's own file, or a workspace's
+/// `.skriv/settings.json`, which only its window hears about.
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    User,
+    Workspace,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsChanged {
+    pub changed_keys: Vec<String>,
+    pub scope: Scope,
+    /// What was left out of the file just read, if anything
+    pub problems: Vec<SettingsProblem>,
+}
+
+#[derive(Clone, Serialize)]
+struct SettingsWarning {
+    message: String,
+}
+
+#[derive(Clone, Serialize)]
+struct SettingsMigrated {
+    from: u32,
+    to: u32,
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// 0 to 1: `themeMode` moved into `appearance.mode`.
+fn theme_mode_to_appearance(file: &mut Map<String, Value>) {
+    if let Some(mode) = file.remove("themeMode") {
+        file.insert("appearance".into(), serde_json::json!({ "mode": mode }));
+    }
+}
+
+/// Reads a file of any version, bringing an older one up to this build's
+/// layout, and returns the version it was in. A newer one is read as far as
+/// this build understands it. Keys that don't fit the schema are left out
+/// and listed; the rest apply.
+fn parse(text: &str) -> Result<(Settings, u32, Vec<SettingsProblem>), serde_json::Error> {
+    let mut value: Value = serde_json::from_str(text)?;
+    let version = value.get("schemaVersion").and_then(Value::as_u64).map_or(0, |v| u32::try_from(v).unwrap_or(u32::MAX));
+    let mut problems = Vec::new();
+    if let Value::Object(file) = &mut value {
+        for migration in MIGRATIONS.iter().skip(version as usize) {
+            migration(file);
+        }
+        let accepted;
+        (accepted, problems) = settings_validation::validate(text, std::mem::take(file), version);
+        *file = accepted;
+    }
+    let mut settings: Settings = serde_json::from_value(value)?;
+    settings.schema_version = version.max(SCHEMA_VERSION);
+    Ok((settings, version, problems))
+}
+
+/// Keeps a copy of the file as it was, as `settings.json.v0.bak` and so on,
+/// then rewrites it in the current layout.
+fn upgrade(app: &AppHandle, path: &Path, settings: &Settings, from: u32) -> Result<(), String> {
+    let backup = with_suffix(path, &format!(".v{}.bak", from));
+    std::fs::copy(path, &backup).map_err(|e| format!("Couldn't back up {} before updating it: {}", FILE_NAME, e))?;
+    save(app, settings)
+}
+
+/// The settings at startup, with defaults for anything missing. A file that
+/// doesn't parse is moved aside as `settings.json.invalid` rather than
+/// overwritten by the next save, and skriv starts on defaults.
+pub fn load(app: &AppHandle) -> (Settings, Notices) {
+    let mut notices = Vec::new();
+    let settings = read(app, &mut notices);
+    (settings, Notices(Mutex::new(notices)))
+}
+
+fn read(app: &AppHandle, notices: &mut Vec<Notice>) -> Settings {
+    let Ok(path) = path(app) else { return Settings::default() };
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Settings::default(),
+        Err(e) => {
+            notices.push(Notice::Warning(format!("Couldn't read {}: {}. Using the default settings.", FILE_NAME, e)));
+            return Settings::default();
+        }
+    };
+    match parse(&text) {
+        Ok((settings, from, problems)) => {
+            if from < SCHEMA_VERSION {
+                match upgrade(app, &path, &settings, from) {
+                    Ok(()) => notices.push(Notice::Migrated { from, to: SCHEMA_VERSION }),
+                    Err(e) => notices.push(Notice::Warning(e)),
+                }
+            }
+            if !problems.is_empty() {
+                for problem in &problems {
+                    log::warn!("{}: {}", FILE_NAME, problem);
+                }
+                notices.push(Notice::Problems(problems));
+            }
+            settings
+        }
+        Err(e) => {
+            let invalid = with_suffix(&path, ".invalid");
+            let kept = match std::fs::rename(&path, &invalid) {
+                Ok(()) => format!("It was moved to {}.", invalid.display()),
+                Err(e) => format!("It couldn't be moved aside: {}.", e),
+            };
+            log::warn!("Invalid {}: {}", FILE_NAME, e);
+            notices.push(Notice::Warning(format!("{} isn't valid ({}) so the default settings are in use. {}", FILE_NAME, e, kept)));
+            Settings::default()
+        }
+    }
+}
+
+/// Writes through a temporary file, so a crash can't leave half a file.
+pub fn save(app: &AppHandle, settings: &Settings) -> Result<(), String> {
+    let path = path(app)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let mut value = serde_json::to_value(settings).map_err(|e| e.to_string())?;
+    // A file from a newer release keeps its version, since it keeps its keys
+    value["schemaVersion"] = settings.schema_version.max(SCHEMA_VERSION).into();
+    let json = serde_json::to_string_pretty(&value).map_err(|e| e.to_string())?;
+    paths::write_atomic(&path, json).map_err(|e| e.to_string())
+}
+
+/// Applies a JSON merge patch (RFC 7396): objects merge key by key and a
+/// null resets the key to its default.
+pub fn merge(target: &mut Value, patch: Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let Value::Object(target) = target else { return };
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(&key);
+        } else {
+            merge(target.entry(key).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// The top-level keys whose values differ, as they're spelled in the file.
+pub fn changed_keys(old: &Settings, new: &Settings) -> Vec<String> {
+    let (Ok(Value::Object(old)), Ok(Value::Object(new))) = (serde_json::to_value(old), serde_json::to_value(new)) else {
+        return Vec::new();
+    };
+    new.iter().filter(|(key, value)| old.get(*key) != Some(*value)).map(|(key, _)| key.clone()).collect()
+}
+
+fn announce(app: &AppHandle, old: &Settings, new: &Settings, problems: Vec<SettingsProblem>) -> Vec<String> {
+    let changed_keys = changed_keys(old, new);
+    if !changed_keys.is_empty() || !problems.is_empty() {
+        let _ = app.emit("settings-changed", SettingsChanged { changed_keys: changed_keys.clone(), scope: Scope::User, problems });
+    }
+    changed_keys
+}
+
+/// Changes the settings, saves them and tells every window which keys
+/// changed. Acting on the change is up to the caller.
+pub fn modify(app: &AppHandle, change: impl FnOnce(&mut Settings)) -> Result<Settings, String> {
+    let (old, new) = {
+        let state = app.state::<Mutex<Settings>>();
+        let mut settings = state.lock().unwrap();
+        let old = settings.clone();
+        change(&mut settings);
+        (old, settings.clone())
+    };
+    save(app, &new)?;
+    announce(app, &old, &new, Vec::new());
+    Ok(new)
+}
+
+/// Brings the parts that only read settings when they start up to date,
+/// after a change from a patch or from the file.
+fn react(app: &AppHandle, changed_keys: &[String]) {
+    let changed = |key: &str| changed_keys.iter().any(|k| k == key);
+    if changed("appearance") {
+        theme::mode_changed(app, theme::current_mode(app));
+    }
+    if changed("locale") {
+        if let Err(e) = i18n::refresh(app) {
+            log::warn!("Failed to switch the menus' language: {}", e);
+        }
+    }
+    if changed("quickNoteHotkey") {
+        quick_note::hotkey_changed(app);
+    }
+    if changed("performance") {
+        workers::settings_changed(app);
+    }
+    if changed("logging") {
+        logging::apply(&app.state::<Mutex<Settings>>().lock().unwrap().logging);
+    }
+    if changed("automation") {
+        control::apply(app);
+    }
+}
+
+/// Picks up an edit made outside skriv. A file that doesn't parse is left
+/// for the user to fix, with a warning, and the settings in use stay.
+pub fn reload(app: &AppHandle) {
+    let Ok(path) = path(app) else { return };
+    let Ok(text) = std::fs::read_to_string(&path) else { return };
+    // Editors that truncate before writing show an empty file in between
+    if text.trim().is_empty() {
+        return;
+    }
+    let (new, problems) = match parse(&text) {
+        Ok((settings, from, problems)) => {
+            // Like an older file put back from a backup
+            if from < SCHEMA_VERSION {
+                if let Err(e) = upgrade(app, &path, &settings, from) {
+                    log::warn!("{}", e);
+                } else {
+                    let _ = app.emit("settings-migrated", SettingsMigrated { from, to: SCHEMA_VERSION });
+                }
+            }
+            (settings, problems)
+        }
+        Err(e) => {
+            let message = format!("{} isn't valid ({}). The settings in use are unchanged.", FILE_NAME, e);
+            let _ = app.emit("settings-warning", SettingsWarning { message });
+            return;
+        }
+    };
+    let old = std::mem::replace(&mut *app.state::<Mutex<Settings>>().lock().unwrap(), new.clone());
+    let changed_keys = announce(app, &old, &new, problems);
+    react(app, &changed_keys);
+}
+
+#[tauri::command]
+pub fn get_settings(settings: tauri::State<'_, Mutex<Settings>>) -> Settings {
+    settings.lock().unwrap().clone()
+}
+
+/// Deep-merges `patch` into the settings and returns the result. A patch the
+/// settings can't take, like a string for a flag, changes nothing.
+#[tauri::command]
+pub fn update_settings(app: AppHandle, patch: Value) -> Result<Settings, String> {
+    let (old, new) = {
+        let state = app.state::<Mutex<Settings>>();
+        let mut settings = state.lock().unwrap();
+        let mut value = serde_json::to_value(&*settings).map_err(|e| e.to_string())?;
+        merge(&mut value, patch);
+        let new: Settings = serde_json::from_value(value).map_err(|e| format!("Invalid settings: {}", e))?;
+        (std::mem::replace(&mut *settings, new.clone()), new)
+    };
+    save(&app, &new)?;
+    let changed_keys = announce(&app, &old, &new, Vec::new());
+    react(&app, &changed_keys);
+    Ok(new)
+}
+
+/// What's wrong with `text` as a settings file, for linting it while it's
+/// edited.
+#[tauri::command]
+pub fn validate_settings_text(text: String) -> Vec<SettingsProblem> {
+    match parse(&text) {
+        Ok((_, _, problems)) => problems,
+        Err(e) => vec![SettingsProblem::syntax(&e)],
+    }
+}
+
+/// Sends the window what happened to the settings file at startup, as
+/// `settings-warning`, `settings-migrated` and a `settings-changed` with the
+/// problems found. Only the first window to ask hears about it.
+#[tauri::command]
+pub fn replay_settings_notices(app: AppHandle, window: tauri::Window, notices: tauri::State<'_, Notices>) {
+    for notice in notices.0.lock().unwrap().drain(..) {
+        let _ = match notice {
+            Notice::Warning(message) => app.emit_to(window.label(), "settings-warning", SettingsWarning { message }),
+            Notice::Migrated { from, to } => app.emit_to(window.label(), "settings-migrated", SettingsMigrated { from, to }),
+            Notice::Problems(problems) => {
+                let changed = SettingsChanged { changed_keys: Vec::new(), scope: Scope::User, problems };
+                app.emit_to(window.label(), "settings-changed", changed)
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merges_patches() {
+        let mut value = json!({ "locale": "de", "network": { "proxy": "p:1", "noProxy": "local" }, "formatters": {} });
+        merge(&mut value, json!({ "locale": null, "network": { "proxy": "q:2" }, "formatters": { "rust": { "command": "rustfmt" } } }));
+        assert_eq!(value, json!({ "network": { "proxy": "q:2", "noProxy": "local" }, "formatters": { "rust": { "command": "rustfmt" } } }));
+    }
+
+    #[test]
+    fn moves_theme_mode_into_appearance() {
+        let mut file = json!({ "themeMode": "dark", "locale": "de" });
+        theme_mode_to_appearance(file.as_object_mut().unwrap());
+        assert_eq!(file, json!({ "appearance": { "mode": "dark" }, "locale": "de" }));
+        let mut unset = json!({ "locale": "de" });
+        theme_mode_to_appearance(unset.as_object_mut().unwrap());
+        assert_eq!(unset, json!({ "locale": "de" }));
+    }
+
+    #[test]
+    fn migrates_unversioned_files() {
+        let (settings, from, problems) = parse(r#"{ "themeMode": "light" }"#).unwrap();
+        assert_eq!(from, 0);
+        assert_eq!(settings.schema_version, SCHEMA_VERSION);
+        assert!(settings.appearance.mode == ThemeMode::Light);
+        assert!(settings.unknown.is_empty());
+        assert!(problems.is_empty());
+    }
+
+    #[test]
+    fn keeps_what_a_newer_release_wrote() {
+        let text = format!(r#"{{ "schemaVersion": {}, "locale": "fr", "tray": {{ "enabled": true }} }}"#, SCHEMA_VERSION + 1);
+        let (settings, from, problems) = parse(&text).unwrap();
+        assert_eq!(from, SCHEMA_VERSION + 1);
+        assert_eq!(settings.locale.as_deref(), Some("fr"));
+        assert!(problems.is_empty());
+        let saved = serde_json::to_value(&settings).unwrap();
+        assert_eq!(saved["schemaVersion"], json!(SCHEMA_VERSION + 1));
+        assert_eq!(saved["tray"], json!({ "enabled": true }));
+    }
+
+    #[test]
+    fn applies_the_keys_that_fit() {
+        let (settings, _, problems) = parse(r#"{ "schemaVersion": 1, "locale": "sv", "notifyWhenDone": "yes" }"#).unwrap();
+        assert_eq!(settings.locale.as_deref(), Some("sv"));
+        assert!(!settings.notify_when_done);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].path, "notifyWhenDone");
+        assert_eq!(problems[0].column, Some(39));
+    }
+
+    #[test]
+    fn lists_changed_keys() {
+        let old = Settings::default();
+        let new = Settings { locale: Some("sv".into()), notify_when_done: true, ..Default::default() };
+        let mut keys = changed_keys(&old, &new);
+        keys.sort();
+        assert_eq!(keys, ["locale", "notifyWhenDone"]);
+        assert!(changed_keys(&new, &new).is_empty());
+    }
+}
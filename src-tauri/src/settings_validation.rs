@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::settings::{self, Settings};
+
+/// Numbers that fit their type but make no sense, with the bounds they
+/// should fall in.
+const RANGES: &[(&str, f64, f64)] = &[
+    ("editor.tabSize", 1.0, 16.0),
+    ("editor.fontSize", 6.0, 72.0),
+    ("files.autoSaveDelay", 100.0, 600_000.0),
+    ("files.longLineThreshold", 80.0, 100_000_000.0),
+    ("performance.workerThreads", 1.0, 256.0),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ProblemKind {
+    /// The file isn't JSON at all, and nothing in it applies
+    Syntax,
+    UnknownKey,
+    Type,
+    Range,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsProblem {
+    pub kind: ProblemKind,
+    /// Like `editor.tabSize` or `search.exclude[2]`; empty for the whole file
+    pub path: String,
+    pub message: String,
+    /// A known key close to an unknown one
+    pub suggestion: Option<String>,
+    /// 1-based, where the key is in the file
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl SettingsProblem {
+    fn new(kind: ProblemKind, path: String, message: String) -> Self {
+        SettingsProblem { kind, path, message, suggestion: None, line: None, column: None }
+    }
+
+    pub fn syntax(e: &serde_json::Error) -> Self {
+        let mut problem = SettingsProblem::new(ProblemKind::Syntax, String::new(), e.to_string());
+        (problem.line, problem.column) = (Some(e.line()), Some(e.column()));
+        problem
+    }
+}
+
+impl std::fmt::Display for SettingsProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, "{}:{}: ", line, column)?;
+        }
+        if !self.path.is_empty() {
+            write!(f, "{}: ", self.path)?;
+        }
+        write!(f, "{}", self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " (did you mean \"{}\"?)", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where each key and array element starts in a JSON text, by path. The
+/// text is known to parse, so this only needs to keep its place.
+struct Locator<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+    found: HashMap<String, (usize, usize)>,
+}
+
+impl Locator<'_> {
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            (self.line, self.column) = (self.line + 1, 1);
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.next();
+        }
+    }
+
+    fn string(&mut self) -> String {
+        let mut text = String::new();
+        self.next();
+        while let Some(c) = self.next() {
+            match c {
+                '"' => break,
+                '\\' => {
+                    // Escapes other than these don't appear in setting names
+                    match self.next() {
+                        Some('n') => text.push('\n'),
+                        Some('t') => text.push('\t'),
+                        Some(c) => text.push(c),
+                        None => break,
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+        text
+    }
+
+    fn value(&mut self, path: &str) {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('{') => {
+                self.next();
+                loop {
+                    self.skip_whitespace();
+                    if self.chars.peek() != Some(&'"') {
+                        self.next();
+                        return;
+                    }
+                    let at = (self.line, self.column);
+                    let key = self.string();
+                    let child = if path.is_empty() { key } else { format!("{}.{}", path, key) };
+                    self.found.insert(child.clone(), at);
+                    self.skip_whitespace();
+                    self.next();
+                    self.value(&child);
+                    self.skip_whitespace();
+                    if self.next() != Some(',') {
+                        return;
+                    }
+                }
+            }
+            Some('[') => {
+                self.next();
+                self.skip_whitespace();
+                if self.chars.peek() == Some(&']') {
+                    self.next();
+                    return;
+                }
+                for i in 0.. {
+                    self.skip_whitespace();
+                    let child = format!("{}[{}]", path, i);
+                    self.found.insert(child.clone(), (self.line, self.column));
+                    self.value(&child);
+                    self.skip_whitespace();
+                    if self.next() != Some(',') {
+                        return;
+                    }
+                }
+            }
+            Some('"') => {
+                self.string();
+            }
+            _ => {
+                while self.chars.peek().is_some_and(|c| !matches!(c, ',' | '}' | ']') && !c.is_whitespace()) {
+                    self.next();
+                }
+            }
+        }
+    }
+}
+
+fn locate(text: &str) -> HashMap<String, (usize, usize)> {
+    let mut locator = Locator { chars: text.chars().peekable(), line: 1, column: 1, found: HashMap::new() };
+    locator.value("");
+    locator.found
+}
+
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substituted.min(previous + 1).min(row[j] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Case and `_` or `-` between words don't count towards a typo.
+fn loosely(name: &str) -> String {
+    name.chars().filter(|c| !matches!(c, '_' | '-')).flat_map(char::to_lowercase).collect()
+}
+
+/// The known key `key` was most likely meant to be: one beside it, or at
+/// the top of the file, a nested one by its last name.
+fn suggest(key: &str, siblings: &Map<String, Value>, top: bool) -> Option<String> {
+    let mut candidates: Vec<(String, String)> = siblings.keys().map(|k| (loosely(k), k.clone())).collect();
+    if top {
+        for (parent, value) in siblings {
+            if let Value::Object(fields) = value {
+                candidates.extend(fields.keys().map(|k| (loosely(k), format!("{}.{}", parent, k))));
+            }
+        }
+    }
+    let wanted = loosely(key);
+    let allowed = (wanted.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|(name, key)| (distance(&wanted, &name), key))
+        .filter(|(d, _)| *d <= allowed)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, key)| key)
+}
+
+fn dotted(path: &[&str]) -> String {
+    path.join(".")
+}
+
+/// Whether `value` at `path` is one the schema takes, tried on its own
+/// over the defaults so that one bad key doesn't hide another.
+fn check(path: &[&str], value: &Value, defaults: &Value) -> Result<(), SettingsProblem> {
+    let patch = path.iter().rev().fold(value.clone(), |inner, key| Value::Object(Map::from_iter([(key.to_string(), inner)])));
+    let mut candidate = defaults.clone();
+    settings::merge(&mut candidate, patch);
+    if let Err(e) = serde_json::from_value::<Settings>(candidate) {
+        return Err(SettingsProblem::new(ProblemKind::Type, dotted(path), e.to_string()));
+    }
+    let bounds = RANGES.iter().find(|(key, _, _)| *key == dotted(path));
+    if let (Some((_, low, high)), Some(n)) = (bounds, value.as_f64()) {
+        if n < *low || n > *high {
+            return Err(SettingsProblem::new(ProblemKind::Range, dotted(path), format!("{} should be between {} and {}", n, low, high)));
+        }
+    }
+    Ok(())
+}
+
+fn walk(
+    file: Map<String, Value>,
+    schema: &Map<String, Value>,
+    path: &mut Vec<String>,
+    report_unknown: bool,
+    defaults: &Value,
+    problems: &mut Vec<SettingsProblem>,
+) -> Map<String, Value> {
+    let mut accepted = Map::new();
+    let top = path.is_empty();
+    for (key, value) in file {
+        path.push(key.clone());
+        let segments: Vec<&str> = path.iter().map(String::as_str).collect();
+        match (schema.get(&key), value) {
+            (None, value) => {
+                if report_unknown {
+                    let mut problem = SettingsProblem::new(ProblemKind::UnknownKey, dotted(&segments), "unknown key".into());
+                    problem.suggestion = suggest(&key, schema, top);
+                    problems.push(problem);
+                }
+                // Kept for a newer release to read; only the top level holds on to them
+                if top {
+                    accepted.insert(key, value);
+                }
+            }
+            // An empty object in the defaults is a map of the user's own names
+            (Some(Value::Object(fields)), Value::Object(inner)) if !fields.is_empty() => {
+                let inner = walk(inner, fields, path, report_unknown, defaults, problems);
+                accepted.insert(key, Value::Object(inner));
+            }
+            (Some(_), value) => match check(&segments, &value, defaults) {
+                Ok(()) => {
+                    accepted.insert(key, value);
+                }
+                Err(problem) => problems.push(problem),
+            },
+        }
+        path.pop();
+    }
+    accepted
+}
+
+/// The keys of `file` that fit the schema, and what's wrong with the rest,
+/// located in `text`. Unknown keys are only reported for a file this build
+/// or an older one wrote; a newer release may know them.
+pub fn validate(text: &str, file: Map<String, Value>, version: u32) -> (Map<String, Value>, Vec<SettingsProblem>) {
+    let defaults = serde_json::to_value(Settings::default()).unwrap_or_default();
+    let schema = defaults.as_object().cloned().unwrap_or_default();
+    let mut problems = Vec::new();
+    let accepted = walk(file, &schema, &mut Vec::new(), version <= settings::SCHEMA_VERSION, &defaults, &mut problems);
+    let found = locate(text);
+    for problem in &mut problems {
+        if let Some((line, column)) = found.get(&problem.path) {
+            (problem.line, problem.column) = (Some(*line), Some(*column));
+        }
+    }
+    (accepted, problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(text: &str) -> Vec<SettingsProblem> {
+        let Ok(Value::Object(file)) = serde_json::from_str(text) else { panic!("not an object") };
+        validate(text, file, settings::SCHEMA_VERSION).1
+    }
+
+    #[test]
+    fn suggests_the_key_meant() {
+        let found = problems("{\n  \"word_warp\": true,\n  \"editor\": { \"tabsize\": 2 }\n}");
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].path, "editor.tabsize");
+        assert_eq!(found[0].suggestion.as_deref(), Some("tabSize"));
+        assert_eq!((found[0].line, found[0].column), (Some(3), Some(15)));
+        assert_eq!(found[1].suggestion.as_deref(), Some("editor.wordWrap"));
+        assert_eq!((found[1].line, found[1].column), (Some(2), Some(3)));
+    }
+
+    #[test]
+    fn keeps_the_keys_that_fit() {
+        let text = r#"{ "locale": "de", "editor": { "tabSize": "two", "fontSize": 400, "insertSpaces": false } }"#;
+        let Ok(Value::Object(file)) = serde_json::from_str(text) else { unreachable!() };
+        let (accepted, found) = validate(text, file, settings::SCHEMA_VERSION);
+        assert_eq!(Value::Object(accepted), serde_json::json!({ "locale": "de", "editor": { "insertSpaces": false } }));
+        let kinds: Vec<(&str, ProblemKind)> = found.iter().map(|p| (p.path.as_str(), p.kind)).collect();
+        assert_eq!(kinds, [("editor.fontSize", ProblemKind::Range), ("editor.tabSize", ProblemKind::Type)]);
+    }
+
+    #[test]
+    fn locates_array_elements() {
+        let found = locate("{ \"search\": {\n  \"exclude\": [\"a\",\n    \"b\"] } }");
+        assert_eq!(found["search.exclude"], (2, 3));
+        assert_eq!(found["search.exclude[1]"], (3, 5));
+    }
+}
@@ -0,0 +1,447 @@
+//! Files on SSH servers, as `sftp://user@host/path`. Connections are pooled
+//! per user, host and port, and everything that touches the network runs on
+//! a blocking thread with a timeout. Passwords and host keys the user has to
+//! decide on go to the window as a `sftp-prompt` and come back through
+//! `sftp_answer`; passwords are used once and never kept.
+
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use ssh2::{CheckResult, ErrorCode, FileStat, HashType, KnownHostFileKind, Session, Sftp};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::document::{self, LineEnding};
+use crate::git;
+
+const DEFAULT_PORT: u16 = 22;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// For each read and write once connected
+const SESSION_TIMEOUT_MS: u32 = 30_000;
+/// How long a prompt waits for the user before the connection gives up
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(300);
+/// Tried in turn when no key is given, as ssh does
+const DEFAULT_KEYS: &[&str] = &["id_ed25519", "id_ecdsa", "id_rsa"];
+const PASSWORD_ATTEMPTS: usize = 3;
+
+/// How to log in. Without one, the agent is tried, then the usual keys in
+/// `~/.ssh`, then a password.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Auth {
+    Agent,
+    KeyFile { path: String },
+    Password,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PromptKind {
+    Password,
+    Passphrase,
+    /// The host isn't in known_hosts yet
+    UnknownHost,
+    /// known_hosts has a different key for the host
+    ChangedHost,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Prompt {
+    id: u64,
+    kind: PromptKind,
+    host: String,
+    user: String,
+    /// The host key's SHA256 fingerprint, for the host prompts
+    fingerprint: Option<String>,
+    /// The key file, for a passphrase
+    key: Option<String>,
+}
+
+/// Prompts waiting on the window, by id.
+#[derive(Default)]
+pub struct Prompts {
+    next: AtomicU64,
+    waiting: Mutex<HashMap<u64, mpsc::Sender<Option<String>>>>,
+}
+
+struct Connection {
+    // Kept alive for the SFTP channel it carries
+    _session: Session,
+    sftp: Sftp,
+}
+
+/// Open connections, by `user@host:port`.
+#[derive(Default)]
+pub struct Connections(Mutex<HashMap<String, Arc<Connection>>>);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteDocument {
+    path: String,
+    content: String,
+    encoding: String,
+    line_ending: LineEnding,
+    had_errors: bool,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteEntry {
+    name: String,
+    path: String,
+    is_dir: bool,
+    size: Option<u64>,
+}
+
+fn key(user: &str, host: &str, port: u16) -> String {
+    format!("{}@{}:{}", user, host, port)
+}
+
+/// How known_hosts names a host: bare on port 22, else `[host]:port`.
+fn known_host_name(host: &str, port: u16) -> String {
+    if port == DEFAULT_PORT {
+        host.to_string()
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+/// Joins remote paths with `/`, whatever the local separator is.
+fn join(dir: &str, name: &str) -> String {
+    if dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Beside `path`, hidden, for the upload to be renamed over it.
+fn temp_path(path: &str) -> String {
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    format!("{}/.{}.skriv-{}.tmp", dir, name, std::process::id())
+}
+
+/// Folders first, then files, each by name.
+fn sort_entries(entries: &mut [RemoteEntry]) {
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase())));
+}
+
+fn fingerprint(session: &Session) -> Option<String> {
+    let hash = session.host_key_hash(HashType::Sha256)?;
+    Some(format!("SHA256:{}", base64::engine::general_purpose::STANDARD_NO_PAD.encode(hash)))
+}
+
+struct Asker<'a> {
+    app: &'a AppHandle,
+    window: String,
+    host: String,
+    user: String,
+}
+
+impl Asker<'_> {
+    /// Asks the window and waits for its answer; `None` if the user
+    /// cancelled or didn't answer in time.
+    fn ask(&self, kind: PromptKind, fingerprint: Option<String>, key: Option<String>) -> Option<String> {
+        let prompts = self.app.state::<Prompts>();
+        let id = prompts.next.fetch_add(1, Ordering::Relaxed);
+        let (sender, answer) = mpsc::channel();
+        prompts.waiting.lock().unwrap().insert(id, sender);
+        let prompt = Prompt { id, kind, host: self.host.clone(), user: self.user.clone(), fingerprint, key };
+        let answered = match self.app.emit_to(&self.window, "sftp-prompt", prompt) {
+            Ok(()) => answer.recv_timeout(PROMPT_TIMEOUT).ok().flatten(),
+            Err(_) => None,
+        };
+        prompts.waiting.lock().unwrap().remove(&id);
+        answered
+    }
+}
+
+/// Checks the host key against `~/.ssh/known_hosts`. A host not in it yet,
+/// or with a different key, is only trusted once the user says so, and is
+/// then written there.
+fn verify_host(session: &Session, asker: &Asker, port: u16, known_hosts: &Path) -> Result<(), String> {
+    let (host_key, key_type) = session.host_key().ok_or("The server sent no host key")?;
+    let mut known = session.known_hosts().map_err(|e| e.to_string())?;
+    if known_hosts.is_file() {
+        known.read_file(known_hosts, KnownHostFileKind::OpenSSH).map_err(|e| format!("Failed to read {}: {}", known_hosts.display(), e))?;
+    }
+    let kind = match known.check_port(&asker.host, port, host_key) {
+        CheckResult::Match => return Ok(()),
+        CheckResult::NotFound => PromptKind::UnknownHost,
+        CheckResult::Mismatch => PromptKind::ChangedHost,
+        CheckResult::Failure => return Err(format!("Failed to check the host key of {}", asker.host)),
+    };
+    let fingerprint = fingerprint(session);
+    log::info!("Host key of {} is {:?}: {}", asker.host, kind, fingerprint.as_deref().unwrap_or("no fingerprint"));
+    if asker.ask(kind, fingerprint, None).is_none() {
+        return Err(format!("The host key of {} wasn't trusted", asker.host));
+    }
+    // Only ever appended to: rewriting the file would drop the lines libssh2
+    // can't read, like @cert-authority ones. A changed key's old line may
+    // stay, as a host matches if any of its lines do.
+    let mut added = session.known_hosts().map_err(|e| e.to_string())?;
+    added.add(&known_host_name(&asker.host, port), host_key, "added by skriv", key_type.into()).map_err(|e| e.to_string())?;
+    let host = added.hosts().map_err(|e| e.to_string())?.pop().ok_or("Failed to add the host key")?;
+    let line = added.write_string(&host, KnownHostFileKind::OpenSSH).map_err(|e| e.to_string())?;
+    append_line(known_hosts, &line).map_err(|e| format!("Failed to write {}: {}", known_hosts.display(), e))
+}
+
+/// Adds `line` at the end of the file, after a newline if the last line
+/// lacks one.
+fn append_line(path: &Path, line: &str) -> std::io::Result<()> {
+    use std::io::{Read, Seek, SeekFrom, Write};
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+    let mut text = String::new();
+    if file.metadata()?.len() > 0 {
+        let mut last = [0];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            text.push('\n');
+        }
+    }
+    text.push_str(line.trim_end_matches('\n'));
+    text.push('\n');
+    file.write_all(text.as_bytes())
+}
+
+fn with_key_file(session: &Session, asker: &Asker, path: &Path) -> bool {
+    if session.userauth_pubkey_file(&asker.user, None, path, None).is_ok() {
+        return true;
+    }
+    // Most likely the key has a passphrase
+    match asker.ask(PromptKind::Passphrase, None, Some(path.to_string_lossy().into_owned())) {
+        Some(passphrase) => session.userauth_pubkey_file(&asker.user, None, path, Some(&passphrase)).is_ok(),
+        None => false,
+    }
+}
+
+fn with_password(session: &Session, asker: &Asker) -> bool {
+    for _ in 0..PASSWORD_ATTEMPTS {
+        let Some(password) = asker.ask(PromptKind::Password, None, None) else { return false };
+        if session.userauth_password(&asker.user, &password).is_ok() {
+            return true;
+        }
+    }
+    false
+}
+
+fn authenticate(session: &Session, asker: &Asker, auth: Option<Auth>, ssh_dir: &Path) -> Result<(), String> {
+    let done = match auth {
+        Some(Auth::Agent) => session.userauth_agent(&asker.user).is_ok(),
+        Some(Auth::KeyFile { path }) => with_key_file(session, asker, Path::new(&path)),
+        Some(Auth::Password) => with_password(session, asker),
+        None => {
+            session.userauth_agent(&asker.user).is_ok()
+                || DEFAULT_KEYS.iter().map(|name| ssh_dir.join(name)).filter(|path| path.is_file()).any(|path| session.userauth_pubkey_file(&asker.user, None, &path, None).is_ok())
+                || with_password(session, asker)
+        }
+    };
+    if done && session.authenticated() {
+        Ok(())
+    } else {
+        Err(format!("Failed to log in to {} as {}", asker.host, asker.user))
+    }
+}
+
+fn connect(asker: &Asker, port: u16, auth: Option<Auth>, ssh_dir: &Path) -> Result<Connection, String> {
+    let address = (asker.host.as_str(), port)
+        .to_socket_addrs()
+        .map_err(|e| format!("Failed to find {}: {}", asker.host, e))?
+        .next()
+        .ok_or_else(|| format!("Failed to find {}", asker.host))?;
+    let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).map_err(|e| format!("Failed to connect to {}: {}", asker.host, e))?;
+    let mut session = Session::new().map_err(|e| e.to_string())?;
+    session.set_timeout(SESSION_TIMEOUT_MS);
+    session.set_tcp_stream(stream);
+    session.handshake().map_err(|e| format!("Failed to start SSH with {}: {}", asker.host, e))?;
+    verify_host(&session, asker, port, &ssh_dir.join("known_hosts"))?;
+    authenticate(&session, asker, auth, ssh_dir)?;
+    let sftp = session.sftp().map_err(|e| format!("{} doesn't offer SFTP: {}", asker.host, e))?;
+    Ok(Connection { _session: session, sftp })
+}
+
+/// Why an operation failed: the session, which is then dropped from the pool
+/// so that the next `sftp_connect` makes a new one, or only the file.
+enum Failure {
+    Session(String),
+    File(String),
+}
+
+impl From<ssh2::Error> for Failure {
+    fn from(e: ssh2::Error) -> Self {
+        match e.code() {
+            ErrorCode::Session(_) => Failure::Session(e.to_string()),
+            ErrorCode::SFTP(_) => Failure::File(e.to_string()),
+        }
+    }
+}
+
+/// Reading and writing a remote file only fails like this when the channel does.
+impl From<std::io::Error> for Failure {
+    fn from(e: std::io::Error) -> Self {
+        Failure::Session(e.to_string())
+    }
+}
+
+/// Runs `f` on the connection, on a blocking thread.
+async fn with_connection<T: Send + 'static>(app: &AppHandle, connection: String, f: impl FnOnce(&Sftp) -> Result<T, Failure> + Send + 'static) -> Result<T, String> {
+    let open = app.state::<Connections>().0.lock().unwrap().get(&connection).cloned();
+    let open = open.ok_or_else(|| format!("Not connected to {}", connection))?;
+    match tauri::async_runtime::spawn_blocking(move || f(&open.sftp)).await.map_err(|e| e.to_string())? {
+        Ok(value) => Ok(value),
+        Err(Failure::File(e)) => Err(e),
+        Err(Failure::Session(e)) => {
+            log::warn!("Dropping the connection to {}: {}", connection, e);
+            app.state::<Connections>().0.lock().unwrap().remove(&connection);
+            Err(e)
+        }
+    }
+}
+
+/// Connects to `host` unless there's an open connection already, and returns
+/// its id for the other commands.
+#[tauri::command]
+pub async fn sftp_connect(app: AppHandle, window: tauri::Window, host: String, port: Option<u16>, user: Option<String>, auth: Option<Auth>) -> Result<String, String> {
+    let port = port.unwrap_or(DEFAULT_PORT);
+    let user = user.filter(|user| !user.is_empty()).or_else(|| std::env::var("USER").or_else(|_| std::env::var("USERNAME")).ok()).ok_or("No user name to log in with")?;
+    let id = key(&user, &host, port);
+    if app.state::<Connections>().0.lock().unwrap().contains_key(&id) {
+        return Ok(id);
+    }
+    let ssh_dir = app.path().home_dir().map_err(|e| e.to_string())?.join(".ssh");
+    let connecting = app.clone();
+    let label = window.label().to_string();
+    let connection = tauri::async_runtime::spawn_blocking(move || {
+        let asker = Asker { app: &connecting, window: label, host, user };
+        connect(&asker, port, auth, &ssh_dir)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .inspect_err(|e| log::warn!("{}", e))?;
+    log::info!("Connected to {}", id);
+    app.state::<Connections>().0.lock().unwrap().insert(id.clone(), Arc::new(connection));
+    Ok(id)
+}
+
+/// The window's answer to a `sftp-prompt`: the password or passphrase, or
+/// anything to trust a host key; `None` cancels.
+#[tauri::command]
+pub fn sftp_answer(prompts: tauri::State<'_, Prompts>, id: u64, answer: Option<String>) {
+    if let Some(sender) = prompts.waiting.lock().unwrap().remove(&id) {
+        let _ = sender.send(answer);
+    }
+}
+
+/// Reads a remote file, decoded as a local one would be.
+#[tauri::command]
+pub async fn sftp_read(app: AppHandle, connection: String, path: String) -> Result<RemoteDocument, String> {
+    let remote = path.clone();
+    let bytes = with_connection(&app, connection, move |sftp| {
+        use std::io::Read;
+        let mut bytes = Vec::new();
+        sftp.open(Path::new(&remote))?.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    })
+    .await?;
+    if git::is_binary(&bytes) {
+        return Err(format!("{} isn't a text file", path));
+    }
+    let (content, encoding, line_ending, had_errors) = document::decode_detached(&bytes, None)?;
+    log::debug!("Read {}: {} bytes as {}", path, bytes.len(), encoding);
+    Ok(RemoteDocument { path, content, encoding, line_ending, had_errors })
+}
+
+/// Uploads beside `path` and renames over it, keeping its permissions, so a
+/// dropped connection leaves the old file whole.
+#[tauri::command]
+pub async fn sftp_write(app: AppHandle, connection: String, path: String, content: String, encoding: Option<String>, line_ending: Option<LineEnding>) -> Result<(), String> {
+    let bytes = document::encode_detached(&content, encoding.as_deref().unwrap_or("UTF-8"), line_ending.unwrap_or(LineEnding::Mixed))?;
+    let shown = path.clone();
+    let written = with_connection(&app, connection, move |sftp| {
+        use std::io::Write;
+        let target = PathBuf::from(&path);
+        let tmp = PathBuf::from(temp_path(&path));
+        let perm = sftp.stat(&target).ok().and_then(|stat| stat.perm);
+        let uploaded = (|| -> Result<(), Failure> {
+            let mut file = sftp.create(&tmp)?;
+            file.write_all(&bytes)?;
+            drop(file);
+            if let Some(perm) = perm {
+                sftp.setstat(&tmp, FileStat { size: None, uid: None, gid: None, perm: Some(perm), atime: None, mtime: None })?;
+            }
+            // Servers without the overwrite extension need the old file gone first
+            sftp.rename(&tmp, &target, None).or_else(|_| sftp.unlink(&target).and_then(|_| sftp.rename(&tmp, &target, None)))?;
+            Ok(())
+        })();
+        if uploaded.is_err() {
+            let _ = sftp.unlink(&tmp);
+        }
+        uploaded
+    })
+    .await;
+    match &written {
+        Ok(()) => log::info!("Saved {}", shown),
+        Err(e) => log::warn!("Failed to save {}: {}", shown, e),
+    }
+    written
+}
+
+/// The folders and files in `dir`, folders first.
+#[tauri::command]
+pub async fn sftp_list(app: AppHandle, connection: String, dir: String) -> Result<Vec<RemoteEntry>, String> {
+    with_connection(&app, connection, move |sftp| {
+        let mut entries: Vec<RemoteEntry> = sftp
+            .readdir(Path::new(&dir))?
+            .into_iter()
+            .filter_map(|(path, stat)| {
+                let name = path.file_name()?.to_string_lossy().into_owned();
+                Some(RemoteEntry { path: join(&dir, &name), name, is_dir: stat.is_dir(), size: stat.size })
+            })
+            .collect();
+        sort_entries(&mut entries);
+        Ok(entries)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_remote_paths_with_slashes() {
+        assert_eq!(join("/etc/nginx", "nginx.conf"), "/etc/nginx/nginx.conf");
+        assert_eq!(join("/", "etc"), "/etc");
+        assert_eq!(temp_path("/etc/nginx/nginx.conf"), format!("/etc/nginx/.nginx.conf.skriv-{}.tmp", std::process::id()));
+        assert_eq!(known_host_name("example.com", 22), "example.com");
+        assert_eq!(known_host_name("example.com", 2222), "[example.com]:2222");
+        let entry = |name: &str, is_dir| RemoteEntry { name: name.into(), path: join("/", name), is_dir, size: None };
+        let mut entries = vec![entry("b.conf", false), entry("sites", true), entry("A.conf", false), entry("conf.d", true)];
+        sort_entries(&mut entries);
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["conf.d", "sites", "A.conf", "b.conf"]);
+    }
+
+    #[test]
+    fn appends_to_known_hosts_without_touching_the_rest() {
+        let path = std::env::temp_dir().join(format!("skriv-known-hosts-{}", std::process::id()));
+        let existing = "@cert-authority *.example.com ssh-ed25519 AAAA\n# a comment\n|1|hashed= ssh-rsa AAAA";
+        std::fs::write(&path, existing).unwrap();
+        append_line(&path, "example.com ssh-ed25519 BBBB added by skriv\n").unwrap();
+        append_line(&path, "[example.com]:2222 ssh-ed25519 CCCC added by skriv").unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text, format!("{}\nexample.com ssh-ed25519 BBBB added by skriv\n[example.com]:2222 ssh-ed25519 CCCC added by skriv\n", existing));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
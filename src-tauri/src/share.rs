@@ -0,0 +1,139 @@
+#[cfg(target_os = "macos")]
+use std::sync::Mutex;
+
+#[cfg(target_os = "macos")]
+use tauri::menu::{MenuItem, MenuItemKind, Submenu};
+#[cfg(target_os = "macos")]
+use tauri::{AppHandle, Manager, Wry};
+
+#[cfg(target_os = "macos")]
+use crate::menu;
+use crate::paths;
+#[cfg(target_os = "macos")]
+use crate::menu_state::MenuState;
+
+pub const SHARE_PREFIX: &str = "share:";
+#[cfg(target_os = "macos")]
+const SUBMENU_ID: &str = "share";
+
+/// Path the Share submenu was last populated for, so it's only rebuilt when
+/// the active document changes.
+#[cfg(target_os = "macos")]
+#[derive(Default)]
+pub struct ShareMenu(Mutex<Option<Option<String>>>);
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::path::Path;
+
+    use objc2::rc::Retained;
+    use objc2::runtime::AnyObject;
+    use objc2_app_kit::NSSharingService;
+    use objc2_foundation::{NSArray, NSString, NSURL};
+
+    // Untitled buffers are offered the services that take text
+    fn items(path: Option<&Path>, text: &str) -> Retained<NSArray> {
+        let item: Retained<AnyObject> = match path.and_then(NSURL::from_file_path) {
+            Some(url) => url.into(),
+            None => NSString::from_str(text).into(),
+        };
+        NSArray::from_retained_slice(&[item])
+    }
+
+    #[allow(deprecated)]
+    fn services(items: &NSArray) -> Vec<Retained<NSSharingService>> {
+        // SAFETY: the items are NSURL or NSString, which conform to NSPasteboardWriting
+        unsafe { NSSharingService::sharingServicesForItems(items) }.to_vec()
+    }
+
+    pub fn titles(path: Option<&Path>) -> Vec<String> {
+        services(&items(path, "")).iter().map(|s| s.title().to_string()).collect()
+    }
+
+    pub fn perform(title: &str, file: &Path, text: Option<&str>) -> Result<(), String> {
+        let items = match text {
+            // An untitled buffer goes out as both its text and the temp file, so each
+            // service can take whichever it understands
+            Some(text) => {
+                let url = NSURL::from_file_path(file).ok_or("Invalid share path")?;
+                let items: [Retained<AnyObject>; 2] = [NSString::from_str(text).into(), url.into()];
+                NSArray::from_retained_slice(&items)
+            }
+            None => items(Some(file), ""),
+        };
+        let service = services(&items)
+            .into_iter()
+            .find(|s| s.title().to_string() == title)
+            .ok_or_else(|| format!("\"{}\" can't share this document", title))?;
+        // SAFETY: as above
+        unsafe { service.performWithItems(&items) };
+        Ok(())
+    }
+}
+
+/// File > Share, filled in for the active document.
+#[cfg(target_os = "macos")]
+pub fn build_submenu(app: &AppHandle, title: &str, state: &MenuState) -> tauri::Result<Submenu<Wry>> {
+    let submenu = Submenu::with_id(app, SUBMENU_ID, title, state.has_document)?;
+    populate(app, &submenu, state.path.as_deref())?;
+    *app.state::<ShareMenu>().0.lock().unwrap() = Some(state.path.clone());
+    Ok(submenu)
+}
+
+#[cfg(target_os = "macos")]
+fn populate(app: &AppHandle, submenu: &Submenu<Wry>, path: Option<&str>) -> tauri::Result<()> {
+    for item in submenu.items()? {
+        submenu.remove(&item)?;
+    }
+    for title in platform::titles(path.map(paths::decode).as_deref()) {
+        let id = format!("{}{}", SHARE_PREFIX, title);
+        submenu.append(&MenuItem::with_id(app, id, &title, true, None::<&str>)?)?;
+    }
+    Ok(())
+}
+
+/// Repopulates the submenu when the active document's path changed.
+#[cfg(target_os = "macos")]
+pub fn refresh(app: &AppHandle, state: &MenuState) {
+    let share = app.state::<ShareMenu>();
+    let mut last = share.0.lock().unwrap();
+    if last.as_ref() == Some(&state.path) {
+        return;
+    }
+    if let Some(MenuItemKind::Submenu(submenu)) = menu::find_item(app, SUBMENU_ID) {
+        if let Err(e) = populate(app, &submenu, state.path.as_deref()) {
+            log::warn!("Failed to update Share menu: {}", e);
+        }
+        *last = Some(state.path.clone());
+    }
+}
+
+/// Shares the document with the named service. Without a `path` the buffer is
+/// written to a temp file first, named after the tab. Sync commands run on the
+/// main thread, which AppKit requires here.
+#[tauri::command]
+pub fn share_document(
+    service: String,
+    path: Option<String>,
+    name: String,
+    content: String,
+) -> Result<(), String> {
+    let (file, text) = match path {
+        Some(path) => (paths::decode(&path), None),
+        None => {
+            let dir = paths::temp_dir().join("skriv-share");
+            std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+            let name = std::path::Path::new(&name).file_name().ok_or("Invalid document name")?;
+            let file = dir.join(name);
+            std::fs::write(&file, &content).map_err(|e| e.to_string())?;
+            (file, Some(content))
+        }
+    };
+    #[cfg(target_os = "macos")]
+    return platform::perform(&service, &file, text.as_deref());
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = (service, file, text);
+        Err("Sharing is only available on macOS".into())
+    }
+}
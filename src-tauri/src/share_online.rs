@@ -0,0 +1,232 @@
+//! File > Share Online: the buffer as a GitHub gist, or on paste.rs, which
+//! needs no account. The link is copied and announced with a notification.
+//! The GitHub token lives in the keychain (see `secrets`) and goes nowhere
+//! but the Authorization header.
+
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, ACCEPT, AUTHORIZATION, RETRY_AFTER, USER_AGENT};
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::AppHandle;
+
+use crate::{clipboard, network, notifications, secrets};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+const GISTS_URL: &str = "https://api.github.com/gists";
+const PASTE_URL: &str = "https://paste.rs";
+/// The keychain account holding the GitHub token
+const GIST_ACCOUNT: &str = "github-gist-token";
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ShareService {
+    Gist,
+    Paste,
+}
+
+/// Secret gists are unlisted but open to anyone with the link. paste.rs
+/// only has that kind.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Visibility {
+    #[default]
+    Secret,
+    Public,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareRequest {
+    filename: String,
+    content: String,
+    #[serde(default)]
+    visibility: Visibility,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Shared {
+    service: ShareService,
+    /// For `delete_share`
+    id: String,
+    url: String,
+    copied: bool,
+}
+
+/// Why sharing failed, by kind, for the window to say what to do about it.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum ShareError {
+    /// Gists need a token, set with `set_share_token`
+    NoToken,
+    /// The token was refused
+    Unauthorized,
+    RateLimited { retry_after: Option<u64> },
+    Network { message: String },
+    Status { status: u16, message: Option<String> },
+    Keychain { message: String },
+    Other { message: String },
+}
+
+fn token() -> Result<String, ShareError> {
+    secrets::get(GIST_ACCOUNT).map_err(|message| ShareError::Keychain { message })?.ok_or(ShareError::NoToken)
+}
+
+fn github(client: &Client, method: Method, url: &str, token: &str) -> RequestBuilder {
+    client
+        .request(method, url)
+        .header(USER_AGENT, "skriv")
+        .header(ACCEPT, "application/vnd.github+json")
+        .header("X-GitHub-Api-Version", "2022-11-28")
+        .header(AUTHORIZATION, format!("Bearer {}", token))
+}
+
+/// Seconds until GitHub takes requests again, from Retry-After or the
+/// rate limit's reset time.
+fn retry_after(headers: &HeaderMap, now: u64) -> Option<u64> {
+    let number = |name| headers.get(name).and_then(|value| value.to_str().ok()).and_then(|value| value.trim().parse::<u64>().ok());
+    number(RETRY_AFTER.as_str()).or_else(|| number("x-ratelimit-reset").map(|reset| reset.saturating_sub(now)))
+}
+
+/// A response that isn't a success, as an error. GitHub's rate limit comes
+/// back as 403 with no requests remaining, or as 429.
+fn status_error(status: StatusCode, headers: &HeaderMap, body: &str, now: u64) -> ShareError {
+    let exhausted = headers.get("x-ratelimit-remaining").is_some_and(|remaining| remaining == "0");
+    if status == StatusCode::UNAUTHORIZED {
+        ShareError::Unauthorized
+    } else if status == StatusCode::TOO_MANY_REQUESTS || (status == StatusCode::FORBIDDEN && exhausted) {
+        ShareError::RateLimited { retry_after: retry_after(headers, now) }
+    } else {
+        let message = serde_json::from_str::<Value>(body).ok().and_then(|body| body["message"].as_str().map(str::to_string));
+        ShareError::Status { status: status.as_u16(), message: message.or_else(|| (!body.trim().is_empty()).then(|| body.trim().chars().take(200).collect())) }
+    }
+}
+
+fn network_error(error: reqwest::Error) -> ShareError {
+    ShareError::Network { message: network::describe(&error) }
+}
+
+/// The body of a successful response, else the error it stands for.
+async fn body(response: reqwest::Response) -> Result<String, ShareError> {
+    let status = response.status();
+    let headers = response.headers().clone();
+    let text = response.text().await.map_err(network_error)?;
+    if status.is_success() {
+        return Ok(text);
+    }
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    Err(status_error(status, &headers, &text, now))
+}
+
+/// Only ids as the services hand them out, since they end up in a URL.
+fn valid_id(id: &str) -> Result<&str, ShareError> {
+    let valid = !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric());
+    valid.then_some(id).ok_or_else(|| ShareError::Other { message: format!("Invalid share id {:?}", id) })
+}
+
+async fn create_gist(client: &Client, request: &ShareRequest) -> Result<(String, String), ShareError> {
+    let body_json = json!({
+        "description": request.filename,
+        "public": matches!(request.visibility, Visibility::Public),
+        "files": { request.filename.as_str(): { "content": request.content } },
+    });
+    let response = github(client, Method::POST, GISTS_URL, &token()?).body(body_json.to_string()).send().await.map_err(network_error)?;
+    let created: Value = serde_json::from_str(&body(response).await?).map_err(|e| ShareError::Other { message: e.to_string() })?;
+    match (created["id"].as_str(), created["html_url"].as_str()) {
+        (Some(id), Some(url)) => Ok((id.to_string(), url.to_string())),
+        _ => Err(ShareError::Other { message: "GitHub's answer had no gist in it".into() }),
+    }
+}
+
+/// paste.rs answers with the link; its last segment is the id.
+async fn create_paste(client: &Client, request: &ShareRequest) -> Result<(String, String), ShareError> {
+    let response = client.post(PASTE_URL).header(USER_AGENT, "skriv").body(request.content.clone()).send().await.map_err(network_error)?;
+    if response.status() == StatusCode::PARTIAL_CONTENT {
+        // It kept only as much as its size limit allows
+        return Err(ShareError::Other { message: "The document is too large for paste.rs".into() });
+    }
+    let url = body(response).await?.trim().to_string();
+    let id = url.rsplit('/').next().unwrap_or_default().to_string();
+    valid_id(&id)?;
+    Ok((id, url))
+}
+
+/// Uploads the buffer and returns where it's now shared.
+#[tauri::command]
+pub async fn create_share(app: AppHandle, window: tauri::Window, service: ShareService, request: ShareRequest) -> Result<Shared, ShareError> {
+    if request.filename.trim().is_empty() {
+        return Err(ShareError::Other { message: "A share needs a file name".into() });
+    }
+    let client = network::client(&app, TIMEOUT).map_err(|message| ShareError::Other { message })?;
+    let (id, url) = match service {
+        ShareService::Gist => create_gist(&client, &request).await,
+        ShareService::Paste => create_paste(&client, &request).await,
+    }
+    .inspect_err(|e| log::warn!("Failed to share {} as {:?}: {:?}", request.filename, service, e))?;
+    log::info!("Shared {} as {:?} {}", request.filename, service, id);
+    let copied = clipboard::write_text(&url).inspect_err(|e| log::warn!("Failed to copy {}: {}", url, e)).is_ok();
+    let body = if copied { format!("{} (link copied)", url) } else { url.clone() };
+    if let Err(e) = notifications::send(&app, &format!("Shared {}", request.filename), &body, None, window.label()) {
+        log::debug!("No notification for the share: {}", e);
+    }
+    Ok(Shared { service, id, url, copied })
+}
+
+/// Takes a share down again, by the id `create_share` returned.
+#[tauri::command]
+pub async fn delete_share(app: AppHandle, service: ShareService, id: String) -> Result<(), ShareError> {
+    let id = valid_id(&id)?;
+    let client = network::client(&app, TIMEOUT).map_err(|message| ShareError::Other { message })?;
+    let request = match service {
+        ShareService::Gist => github(&client, Method::DELETE, &format!("{}/{}", GISTS_URL, id), &token()?),
+        ShareService::Paste => client.delete(format!("{}/{}", PASTE_URL, id)).header(USER_AGENT, "skriv"),
+    };
+    body(request.send().await.map_err(network_error)?).await?;
+    log::info!("Deleted {:?} {}", service, id);
+    Ok(())
+}
+
+/// Stores the token for `service` in the keychain, or forgets it when `None`.
+#[tauri::command]
+pub fn set_share_token(service: ShareService, token: Option<String>) -> Result<(), ShareError> {
+    if service != ShareService::Gist {
+        return Err(ShareError::Other { message: "paste.rs needs no token".into() });
+    }
+    let result = match token.as_deref().map(str::trim).filter(|token| !token.is_empty()) {
+        Some(token) => secrets::set(GIST_ACCOUNT, token),
+        None => secrets::delete(GIST_ACCOUNT),
+    };
+    result.map_err(|message| ShareError::Keychain { message })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn tells_auth_failures_and_rate_limits_apart() {
+        let none = HeaderMap::new();
+        assert_eq!(status_error(StatusCode::UNAUTHORIZED, &none, r#"{"message":"Bad credentials"}"#, 0), ShareError::Unauthorized);
+        let mut limited = HeaderMap::new();
+        limited.insert("x-ratelimit-remaining", HeaderValue::from_static("0"));
+        limited.insert("x-ratelimit-reset", HeaderValue::from_static("1060"));
+        assert_eq!(status_error(StatusCode::FORBIDDEN, &limited, "", 1000), ShareError::RateLimited { retry_after: Some(60) });
+        limited.insert(RETRY_AFTER, HeaderValue::from_static("5"));
+        assert_eq!(status_error(StatusCode::TOO_MANY_REQUESTS, &limited, "", 1000), ShareError::RateLimited { retry_after: Some(5) });
+        assert_eq!(
+            status_error(StatusCode::FORBIDDEN, &none, r#"{"message":"Resource not accessible"}"#, 0),
+            ShareError::Status { status: 403, message: Some("Resource not accessible".into()) }
+        );
+        assert_eq!(status_error(StatusCode::BAD_GATEWAY, &none, "", 0), ShareError::Status { status: 502, message: None });
+    }
+
+    #[test]
+    fn takes_only_plain_ids() {
+        assert_eq!(valid_id("aa5a315d61ae9438b18d"), Ok("aa5a315d61ae9438b18d"));
+        assert!(valid_id("../user").is_err());
+        assert!(valid_id("").is_err());
+    }
+}
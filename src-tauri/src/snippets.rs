@@ -0,0 +1,404 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, FixedOffset};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_opener::OpenerExt;
+
+use crate::{clipboard, paths, workspace};
+
+pub const DIR_NAME: &str = "snippets";
+
+const BUILTIN: &[(&str, &str)] = &[("markdown", include_str!("snippets/markdown.json"))];
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            OneOrMany::One(one) => vec![one],
+            OneOrMany::Many(many) => many,
+        }
+    }
+}
+
+/// A snippet as VS Code writes them. `scope` matters only in its global
+/// files, so it's accepted and ignored.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    prefix: OneOrMany,
+    body: OneOrMany,
+    description: Option<String>,
+    #[serde(rename = "scope")]
+    _scope: Option<IgnoredAny>,
+    #[serde(rename = "isFileTemplate")]
+    _is_file_template: Option<IgnoredAny>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snippet {
+    name: String,
+    prefixes: Vec<String>,
+    /// In TextMate snippet syntax, lines joined with `\n`
+    body: String,
+    description: Option<String>,
+    builtin: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Problem {
+    file: String,
+    message: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LanguageSnippets {
+    snippets: Vec<Snippet>,
+    /// What's wrong with the language's snippet file, snippet by snippet
+    problems: Vec<Problem>,
+}
+
+/// The folder the snippets watcher lives on, once it exists.
+#[derive(Default)]
+pub struct SnippetWatcher(Mutex<Option<RecommendedWatcher>>);
+
+impl SnippetWatcher {
+    pub fn is_running(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
+}
+
+pub fn dir(app: &AppHandle) -> Result<PathBuf, String> {
+    paths::config_dir(app).map(|dir| dir.join(DIR_NAME))
+}
+
+/// VS Code's JSON files, snippets and settings alike, allow comments and
+/// trailing commas. Both are taken out, keeping the line breaks.
+pub fn strip_jsonc(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+    let mut trailing_comma = None;
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                '\\' => out.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                trailing_comma = None;
+                out.push(c);
+            }
+            (',', _) => {
+                trailing_comma = Some(out.len());
+                out.push(c);
+            }
+            ('}' | ']', _) => {
+                if let Some(at) = trailing_comma.take() {
+                    out.replace_range(at..at + 1, " ");
+                }
+                out.push(c);
+            }
+            ('/', Some('/')) => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        out.push('\n');
+                        break;
+                    }
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    // Kept so serde_json's line numbers still match the file
+                    if c == '\n' {
+                        out.push('\n');
+                    }
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            _ => {
+                if !c.is_whitespace() {
+                    trailing_comma = None;
+                }
+                out.push(c);
+            }
+        }
+    }
+    out
+}
+
+/// Catches what would make Monaco insert the body as plain text: `${`
+/// without a tab stop or variable after it, or left open.
+fn check_body(body: &str) -> Result<(), String> {
+    let mut open = 0usize;
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                if !chars.peek().is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    return Err("\"${\" should be followed by a tab stop number or a variable name".into());
+                }
+                open += 1;
+            }
+            '}' if open > 0 => open -= 1,
+            _ => {}
+        }
+    }
+    match open {
+        0 => Ok(()),
+        _ => Err("a \"${\" is never closed".into()),
+    }
+}
+
+/// A file's snippets, with what was wrong with the ones left out.
+fn parse(text: &str, builtin: bool) -> Result<(Vec<Snippet>, Vec<String>), String> {
+    let entries: BTreeMap<String, serde_json::Value> = serde_json::from_str(&strip_jsonc(text)).map_err(|e| e.to_string())?;
+    let mut snippets = Vec::new();
+    let mut problems = Vec::new();
+    for (name, value) in entries {
+        let entry: Entry = match serde_json::from_value(value) {
+            Ok(entry) => entry,
+            Err(e) => {
+                problems.push(format!("\"{}\": {}", name, e));
+                continue;
+            }
+        };
+        let body = entry.body.into_vec().join("\n");
+        if let Err(e) = check_body(&body) {
+            problems.push(format!("\"{}\": {}", name, e));
+            continue;
+        }
+        let prefixes = entry.prefix.into_vec();
+        snippets.push(Snippet { name, prefixes, body, description: entry.description, builtin });
+    }
+    Ok((snippets, problems))
+}
+
+/// Built-in snippets for the language, then the user's `<language>.json`,
+/// whose snippets replace built-in ones of the same name.
+pub fn load(app: &AppHandle, language: &str) -> Result<LanguageSnippets, String> {
+    if language.is_empty() || !language.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) || language.contains("..")
+    {
+        return Err(format!("Invalid language id \"{}\"", language));
+    }
+    let mut snippets = match BUILTIN.iter().find(|(id, _)| *id == language) {
+        Some((_, text)) => parse(text, true).expect("built-in snippets are valid").0,
+        None => Vec::new(),
+    };
+    let mut problems = Vec::new();
+    let file = format!("{}.json", language);
+    let path = dir(app)?.join(&file);
+    match std::fs::read_to_string(&path) {
+        Ok(text) => match parse(&text, false) {
+            Ok((user, issues)) => {
+                snippets.retain(|snippet| !user.iter().any(|u| u.name == snippet.name));
+                snippets.extend(user);
+                problems.extend(issues.into_iter().map(|message| Problem { file: file.clone(), message }));
+            }
+            Err(message) => problems.push(Problem { file: file.clone(), message }),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => problems.push(Problem { file, message: e.to_string() }),
+    }
+    Ok(LanguageSnippets { snippets, problems })
+}
+
+/// What snippet variables resolve against.
+struct Context<'a> {
+    path: Option<&'a Path>,
+    workspace: Option<&'a Path>,
+    now: DateTime<FixedOffset>,
+    clipboard: Option<String>,
+}
+
+/// A variable's value, or `None` for ones left to Monaco or unknown.
+fn variable(name: &str, context: &Context) -> Option<String> {
+    let path = context.path;
+    let text = |path: Option<&Path>| path.map(|path| path.to_string_lossy().into_owned());
+    Some(match name {
+        "TM_FILENAME" => text(path.and_then(|p| p.file_name()).map(Path::new))?,
+        "TM_FILENAME_BASE" => text(path.and_then(|p| p.file_stem()).map(Path::new))?,
+        "TM_FILEPATH" => text(path)?,
+        "TM_DIRECTORY" => text(path.and_then(Path::parent))?,
+        "RELATIVE_FILEPATH" => match context.workspace {
+            Some(root) => text(path.map(|path| clipboard::relative_path(path, root)).as_deref())?,
+            None => text(path)?,
+        },
+        "WORKSPACE_NAME" => text(context.workspace.and_then(|p| p.file_name()).map(Path::new))?,
+        "WORKSPACE_FOLDER" => text(context.workspace)?,
+        "CLIPBOARD" => context.clipboard.clone()?,
+        "CURRENT_YEAR" => context.now.format("%Y").to_string(),
+        "CURRENT_YEAR_SHORT" => context.now.format("%y").to_string(),
+        "CURRENT_MONTH" => context.now.format("%m").to_string(),
+        "CURRENT_MONTH_NAME" => context.now.format("%B").to_string(),
+        "CURRENT_MONTH_NAME_SHORT" => context.now.format("%b").to_string(),
+        "CURRENT_DATE" => context.now.format("%d").to_string(),
+        "CURRENT_DAY_NAME" => context.now.format("%A").to_string(),
+        "CURRENT_DAY_NAME_SHORT" => context.now.format("%a").to_string(),
+        "CURRENT_HOUR" => context.now.format("%H").to_string(),
+        "CURRENT_MINUTE" => context.now.format("%M").to_string(),
+        "CURRENT_SECOND" => context.now.format("%S").to_string(),
+        "CURRENT_SECONDS_UNIX" => context.now.timestamp().to_string(),
+        "CURRENT_TIMEZONE_OFFSET" => context.now.format("%:z").to_string(),
+        _ => return None,
+    })
+}
+
+/// Starts watching the snippets folder if it's there and not yet watched.
+/// Changes emit `snippets-changed` with the languages whose files changed.
+pub fn watch(app: &AppHandle) {
+    let watcher = app.state::<SnippetWatcher>();
+    let mut watcher = watcher.0.lock().unwrap();
+    let Ok(dir) = dir(app) else { return };
+    if watcher.is_some() || !dir.is_dir() {
+        return;
+    }
+    let handle = app.clone();
+    let started = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        let Ok(event) = res else { return };
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        let mut languages: Vec<String> = event
+            .paths
+            .iter()
+            .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|p| Some(p.file_stem()?.to_str()?.to_string()))
+            .collect();
+        languages.dedup();
+        if !languages.is_empty() {
+            let _ = handle.emit("snippets-changed", languages);
+        }
+    })
+    .and_then(|mut started| started.watch(&dir, RecursiveMode::NonRecursive).map(|_| started));
+    match started {
+        Ok(started) => *watcher = Some(started),
+        Err(e) => log::warn!("Failed to watch {}: {}", dir.display(), e),
+    }
+}
+
+pub fn open_folder(app: &AppHandle) -> Result<(), String> {
+    let dir = dir(app)?;
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    watch(app);
+    app.opener().open_path(dir.to_string_lossy(), None::<&str>).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_snippets(app: AppHandle, language: String) -> Result<LanguageSnippets, String> {
+    load(&app, &language)
+}
+
+/// The values of `vars` for a snippet inserted into `path` in the calling
+/// window. Unknown variables are left out for Monaco to handle.
+#[tauri::command]
+pub fn resolve_snippet_variables(
+    app: AppHandle,
+    window: tauri::Window,
+    vars: Vec<String>,
+    path: Option<String>,
+) -> HashMap<String, String> {
+    let path = path.map(PathBuf::from);
+    let workspace = workspace::root(&app, window.label());
+    let clipboard = if vars.iter().any(|v| v == "CLIPBOARD") { clipboard::read_text().ok().flatten() } else { None };
+    let context = Context { path: path.as_deref(), workspace: workspace.as_deref(), now: chrono::Local::now().fixed_offset(), clipboard };
+    vars.into_iter()
+        .filter_map(|name| {
+            let value = variable(&name, &context)?;
+            Some((name, value))
+        })
+        .collect()
+}
+
+#[tauri::command]
+pub fn open_snippets_folder(app: AppHandle) -> Result<(), String> {
+    open_folder(&app)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_snippets_are_valid() {
+        for (language, text) in BUILTIN {
+            let (_, problems) = parse(text, true).unwrap();
+            assert!(problems.is_empty(), "{}: {:?}", language, problems);
+        }
+    }
+
+    #[test]
+    fn reads_vs_code_snippet_files() {
+        let text = r#"{
+            // A comment, as VS Code allows
+            "Note": { "prefix": ["note", "nb"], "body": ["> **Note**", "> $0"] },
+            /* "Old": {} */
+            "Link": { "prefix": "lnk", "body": "[${1:text}](${2:https://})", "description": "A // link, }" },
+        }"#;
+        let (snippets, problems) = parse(text, false).unwrap();
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(snippets[0].name, "Link");
+        assert_eq!(snippets[0].description.as_deref(), Some("A // link, }"));
+        assert_eq!(snippets[1].prefixes, ["note", "nb"]);
+        assert_eq!(snippets[1].body, "> **Note**\n> $0");
+    }
+
+    #[test]
+    fn reports_bad_snippets_by_name() {
+        let text = r#"{
+            "Open": { "prefix": "o", "body": "${1:never closed" },
+            "Empty": { "prefix": "e", "body": "${}" },
+            "Escaped": { "prefix": "x", "body": "\\${not a tab stop" },
+            "Typo": { "prefx": "t", "body": "" }
+        }"#;
+        let (snippets, problems) = parse(text, false).unwrap();
+        assert_eq!(snippets.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), ["Escaped"]);
+        assert_eq!(problems.len(), 3);
+        assert!(problems.iter().any(|p| p.starts_with("\"Open\": a \"${\" is never closed")));
+    }
+
+    #[test]
+    fn fills_variables() {
+        let now = DateTime::parse_from_rfc3339("2026-03-09T08:05:04+01:00").unwrap();
+        let path = Path::new("/notes/journal/today.md");
+        let context = Context { path: Some(path), workspace: Some(Path::new("/notes")), now, clipboard: None };
+        let value = |name: &str| variable(name, &context);
+        assert_eq!(value("TM_FILENAME").as_deref(), Some("today.md"));
+        assert_eq!(value("TM_FILENAME_BASE").as_deref(), Some("today"));
+        assert_eq!(value("RELATIVE_FILEPATH").as_deref(), Some("journal/today.md"));
+        assert_eq!(value("WORKSPACE_NAME").as_deref(), Some("notes"));
+        assert_eq!(value("CURRENT_YEAR").as_deref(), Some("2026"));
+        assert_eq!(value("CURRENT_MONTH_NAME_SHORT").as_deref(), Some("Mar"));
+        assert_eq!(value("CURRENT_TIMEZONE_OFFSET").as_deref(), Some("+01:00"));
+        assert_eq!(value("CLIPBOARD"), None);
+        assert_eq!(value("UUID"), None);
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::{i18n, paths};
+
+pub const FILE_NAME: &str = "spelling.json";
+
+/// Words the user added or told skriv to ignore, from spelling.json in the app
+/// data directory. Both are skipped in every language.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserWords {
+    pub added: BTreeSet<String>,
+    pub ignored: BTreeSet<String>,
+}
+
+impl UserWords {
+    fn contains(&self, word: &str) -> bool {
+        self.added.contains(word) || self.ignored.contains(word)
+    }
+}
+
+fn path(app: &AppHandle) -> Result<PathBuf, String> {
+    paths::data_dir(app).map(|dir| dir.join(FILE_NAME))
+}
+
+pub fn load(app: &AppHandle) -> UserWords {
+    path(app)
+        .ok()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+fn save(app: &AppHandle, words: &UserWords) -> Result<(), String> {
+    let path = path(app)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let json = serde_json::to_string_pretty(words).map_err(|e| e.to_string())?;
+    paths::write_atomic(&path, json).map_err(|e| e.to_string())
+}
+
+/// Consecutive lines of a document, starting at 1-based `line`, so typing only
+/// sends the lines that changed.
+#[derive(Deserialize)]
+pub struct TextChunk {
+    pub line: u32,
+    pub text: String,
+}
+
+/// A misspelled word. Columns are 1-based UTF-16 offsets, like Monaco's, and
+/// the end is exclusive.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Misspelling {
+    pub line: u32,
+    pub start_column: u32,
+    pub end_column: u32,
+    pub word: String,
+}
+
+struct Word<'a> {
+    text: &'a str,
+    /// 1-based UTF-16 column
+    column: u32,
+}
+
+fn is_apostrophe(c: char) -> bool {
+    c == '\'' || c == '\u{2019}'
+}
+
+/// The words in a line worth checking: letters with apostrophes inside them.
+/// Anything touching digits or underscores, and camelCase, is taken for code.
+fn words(line: &str) -> Vec<Word<'_>> {
+    let mut words = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    let mut column = 1;
+    while let Some((start, c)) = chars.next() {
+        if !c.is_alphanumeric() && c != '_' {
+            column += c.len_utf16() as u32;
+            continue;
+        }
+        let start_column = column;
+        let mut end = start + c.len_utf8();
+        let mut code = !c.is_alphabetic();
+        let mut previous = c;
+        column += c.len_utf16() as u32;
+        while let Some(&(i, c)) = chars.peek() {
+            let inner_apostrophe = is_apostrophe(c) && line[i + c.len_utf8()..].chars().next().is_some_and(char::is_alphabetic);
+            if !(c.is_alphanumeric() || c == '_' || inner_apostrophe) {
+                break;
+            }
+            code |= (!c.is_alphabetic() && !inner_apostrophe) || (c.is_uppercase() && previous.is_lowercase());
+            previous = c;
+            end = i + c.len_utf8();
+            column += c.len_utf16() as u32;
+            chars.next();
+        }
+        if !code {
+            words.push(Word { text: &line[start..end], column: start_column });
+        }
+    }
+    words
+}
+
+fn misspellings(chunks: &[TextChunk], user: &UserWords, correct: impl Fn(&str) -> bool) -> Vec<Misspelling> {
+    let mut found = Vec::new();
+    for chunk in chunks {
+        for (offset, line) in chunk.text.lines().enumerate() {
+            for word in words(line) {
+                // Single letters are initials and list markers more often than typos
+                if word.text.chars().count() < 2 || user.contains(word.text) || correct(word.text) {
+                    continue;
+                }
+                found.push(Misspelling {
+                    line: chunk.line + offset as u32,
+                    start_column: word.column,
+                    end_column: word.column + word.text.encode_utf16().count() as u32,
+                    word: word.text.to_string(),
+                });
+            }
+        }
+    }
+    found
+}
+
+/// The document's language when it has one, else the OS locale.
+fn language_or_default(language: Option<String>) -> Option<String> {
+    language.or_else(|| i18n::os_locales().into_iter().next())
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use objc2_app_kit::NSSpellChecker;
+    use objc2_foundation::{NSRange, NSString};
+
+    pub struct Checker {
+        checker: objc2::rc::Retained<NSSpellChecker>,
+        /// `None` lets the checker pick, when it has nothing for the tag
+        language: Option<objc2::rc::Retained<NSString>>,
+    }
+
+    impl Checker {
+        pub fn new(language: Option<&str>) -> Result<Checker, String> {
+            let checker = NSSpellChecker::sharedSpellChecker();
+            let available: Vec<String> = checker.availableLanguages().iter().map(|l| l.to_string()).collect();
+            // Tags look like "en-US" or "sv_SE.UTF-8"; the checker uses "en_US" and "sv"
+            let language = language.and_then(|tag| {
+                let tag = tag.split('.').next().unwrap_or(tag).replace('-', "_");
+                let base = tag.split('_').next().unwrap_or(&tag).to_string();
+                available.iter().find(|l| **l == tag).or_else(|| available.iter().find(|l| **l == base)).cloned()
+            });
+            Ok(Checker { checker, language: language.map(|l| NSString::from_str(&l)) })
+        }
+
+        pub fn check(&self, word: &str) -> bool {
+            let word = NSString::from_str(word);
+            // SAFETY: a null word count is allowed
+            let range = unsafe {
+                self.checker.checkSpellingOfString_startingAt_language_wrap_inSpellDocumentWithTag_wordCount(
+                    &word,
+                    0,
+                    self.language.as_deref(),
+                    false,
+                    0,
+                    std::ptr::null_mut(),
+                )
+            };
+            range.length == 0
+        }
+
+        pub fn suggest(&self, word: &str) -> Vec<String> {
+            let string = NSString::from_str(word);
+            let range = NSRange::new(0, string.length());
+            self.checker
+                .guessesForWordRange_inString_language_inSpellDocumentWithTag(range, &string, self.language.as_deref(), 0)
+                .map(|guesses| guesses.iter().map(|g| g.to_string()).collect())
+                .unwrap_or_default()
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex, OnceLock};
+
+    use spellbook::Dictionary;
+
+    // Where distributions install hunspell dictionaries
+    const DIRS: &[&str] = &["/usr/share/hunspell", "/usr/share/myspell", "/usr/share/myspell/dicts", "/usr/local/share/hunspell"];
+
+    pub struct Checker(Arc<Dictionary>);
+
+    fn dictionary_paths() -> Vec<PathBuf> {
+        let home = std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share/hunspell"));
+        home.into_iter()
+            .chain(DIRS.iter().map(PathBuf::from))
+            .filter_map(|dir| std::fs::read_dir(dir).ok())
+            .flat_map(|entries| entries.filter_map(Result::ok).map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "dic"))
+            .collect()
+    }
+
+    /// `.aff` and `.dic` files are in the encoding the `.aff` file's SET names.
+    fn read(aff: &Path, dic: &Path) -> Result<Dictionary, String> {
+        let aff_bytes = std::fs::read(aff).map_err(|e| e.to_string())?;
+        let dic_bytes = std::fs::read(dic).map_err(|e| e.to_string())?;
+        let encoding = String::from_utf8_lossy(&aff_bytes)
+            .lines()
+            .find_map(|line| line.strip_prefix("SET ").map(|label| label.trim().to_string()))
+            .and_then(|label| encoding_rs::Encoding::for_label(label.as_bytes()))
+            .unwrap_or(encoding_rs::UTF_8);
+        let (aff, _, _) = encoding.decode(&aff_bytes);
+        let (dic, _, _) = encoding.decode(&dic_bytes);
+        Dictionary::new(&aff, &dic).map_err(|e| e.to_string())
+    }
+
+    impl Checker {
+        pub fn new(language: Option<&str>) -> Result<Checker, String> {
+            static LOADED: OnceLock<Mutex<HashMap<PathBuf, Arc<Dictionary>>>> = OnceLock::new();
+            let tag = language.unwrap_or("en_US");
+            let tag = tag.split('.').next().unwrap_or(tag).replace('-', "_");
+            let base = tag.split('_').next().unwrap_or(&tag).to_string();
+            let paths = dictionary_paths();
+            let stem = |path: &PathBuf| path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            let dic = paths
+                .iter()
+                .find(|p| stem(p) == tag)
+                .or_else(|| paths.iter().find(|p| stem(p) == base || stem(p).starts_with(&format!("{}_", base))))
+                .ok_or_else(|| format!("No hunspell dictionary for {}", tag))?;
+
+            let mut loaded = LOADED.get_or_init(Default::default).lock().unwrap();
+            if let Some(dictionary) = loaded.get(dic) {
+                return Ok(Checker(dictionary.clone()));
+            }
+            let dictionary = Arc::new(read(&dic.with_extension("aff"), dic)?);
+            loaded.insert(dic.clone(), dictionary.clone());
+            Ok(Checker(dictionary))
+        }
+
+        pub fn check(&self, word: &str) -> bool {
+            self.0.check(word)
+        }
+
+        pub fn suggest(&self, word: &str) -> Vec<String> {
+            let mut suggestions = Vec::new();
+            self.0.suggest(word, &mut suggestions);
+            suggestions
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+mod platform {
+    pub struct Checker;
+
+    impl Checker {
+        pub fn new(_language: Option<&str>) -> Result<Checker, String> {
+            Err("Spell checking isn't available on this platform".into())
+        }
+
+        pub fn check(&self, _word: &str) -> bool {
+            true
+        }
+
+        pub fn suggest(&self, _word: &str) -> Vec<String> {
+            Vec::new()
+        }
+    }
+}
+
+/// Runs `f`, which uses the platform's checker, where that's allowed.
+/// NSSpellChecker is AppKit and wants the main thread; hunspell loads its
+/// dictionaries off it.
+async fn on_checker_thread<T: Send + 'static>(app: &AppHandle, f: impl FnOnce() -> Result<T, String> + Send + 'static) -> Result<T, String> {
+    #[cfg(target_os = "macos")]
+    {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        app.run_on_main_thread(move || {
+            let _ = sender.send(f());
+        })
+        .map_err(|e| e.to_string())?;
+        receiver.await.map_err(|e| e.to_string())?
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = app;
+        tauri::async_runtime::spawn_blocking(f).await.map_err(|e| e.to_string())?
+    }
+}
+
+/// The misspelled words in `chunks`, checked in `language` (the OS locale
+/// when `None`).
+#[tauri::command]
+pub async fn spellcheck(app: AppHandle, chunks: Vec<TextChunk>, language: Option<String>) -> Result<Vec<Misspelling>, String> {
+    let handle = app.clone();
+    on_checker_thread(&app, move || {
+        let checker = platform::Checker::new(language_or_default(language).as_deref())?;
+        let user = handle.state::<Mutex<UserWords>>();
+        let user = user.lock().unwrap();
+        Ok(misspellings(&chunks, &user, |word| checker.check(word)))
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn spelling_suggestions(app: AppHandle, word: String, language: Option<String>) -> Result<Vec<String>, String> {
+    on_checker_thread(&app, move || {
+        let checker = platform::Checker::new(language_or_default(language).as_deref())?;
+        Ok(checker.suggest(&word))
+    })
+    .await
+}
+
+fn remember(app: &AppHandle, word: String, ignore: bool) -> Result<(), String> {
+    let state = app.state::<Mutex<UserWords>>();
+    let mut words = state.lock().unwrap();
+    if ignore {
+        words.ignored.insert(word);
+    } else {
+        words.added.insert(word);
+    }
+    save(app, &words)
+}
+
+#[tauri::command]
+pub fn add_to_user_dictionary(app: AppHandle, word: String) -> Result<(), String> {
+    remember(&app, word, false)
+}
+
+#[tauri::command]
+pub fn ignore_word(app: AppHandle, word: String) -> Result<(), String> {
+    remember(&app, word, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(line: &str) -> Vec<(&str, u32)> {
+        words(line).into_iter().map(|w| (w.text, w.column)).collect()
+    }
+
+    #[test]
+    fn splits_prose_into_words() {
+        assert_eq!(texts("Don't stop, it’s fine."), [("Don't", 1), ("stop", 7), ("it’s", 13), ("fine", 18)]);
+        assert_eq!(texts("'quoted' words"), [("quoted", 2), ("words", 10)]);
+    }
+
+    #[test]
+    fn skips_code_like_words() {
+        assert_eq!(texts("fooBar snake_case v2 HTTP ok"), [("HTTP", 22), ("ok", 27)]);
+    }
+
+    #[test]
+    fn reports_utf16_columns_from_the_chunk_line() {
+        let chunks = [TextChunk { line: 4, text: "fine\n😀 tpyo here".into() }];
+        let found = misspellings(&chunks, &UserWords::default(), |w| w != "tpyo");
+        assert_eq!(found, [Misspelling { line: 5, start_column: 4, end_column: 8, word: "tpyo".into() }]);
+
+        let user = UserWords { added: ["tpyo".to_string()].into(), ignored: BTreeSet::new() };
+        assert!(misspellings(&chunks, &user, |w| w != "tpyo").is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+//! Word and character counts for the status bar, worked out off the main
+//! thread so that a long document doesn't hold up typing. Each request
+//! carries a generation; one that a newer request for the same tab has
+//! overtaken stops and returns nothing.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::document::{self, DocumentRegistry};
+use crate::highlight::byte_index;
+use crate::large_file;
+use crate::paths;
+use crate::workers::{Priority, WorkerPool};
+
+/// Lines between checks for a newer request.
+const BATCH: usize = 4096;
+const WORDS_PER_MINUTE: usize = 230;
+
+/// The editor's text, or a file open with `open_large_file`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum StatsSource {
+    Content { content: String },
+    Handle { handle: u32 },
+}
+
+/// A selection. Positions are 0-based, with columns in UTF-16 units as
+/// Monaco counts them.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextRange {
+    start_line: usize,
+    start_column: usize,
+    end_line: usize,
+    end_column: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Counts {
+    pub lines: usize,
+    pub words: usize,
+    /// What a reader sees as one character, so `é` written as two code
+    /// points is one. Line breaks aren't counted.
+    pub characters: usize,
+    pub characters_without_whitespace: usize,
+    /// In the document's encoding, without a BOM
+    pub bytes: usize,
+    pub reading_seconds: usize,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DocumentStats {
+    /// The whole document, or all the selections together
+    pub total: Counts,
+    /// One for each selection
+    pub ranges: Vec<Counts>,
+}
+
+/// The latest generation asked for, by tab.
+#[derive(Default)]
+pub struct StatsRequests(Mutex<HashMap<String, u64>>);
+
+impl Counts {
+    fn line(&mut self, text: &str, line_break: &str, encoding: &str) -> Result<(), String> {
+        self.lines += 1;
+        self.words += text.unicode_words().count();
+        for grapheme in text.graphemes(true) {
+            self.characters += 1;
+            if !grapheme.chars().all(char::is_whitespace) {
+                self.characters_without_whitespace += 1;
+            }
+        }
+        self.bytes += document::encoded_len(text, encoding)? + document::encoded_len(line_break, encoding)?;
+        Ok(())
+    }
+
+    fn add(&mut self, other: &Counts) {
+        self.lines += other.lines;
+        self.words += other.words;
+        self.characters += other.characters;
+        self.characters_without_whitespace += other.characters_without_whitespace;
+        self.bytes += other.bytes;
+    }
+
+    fn finish(mut self) -> Self {
+        self.reading_seconds = (self.words * 60).div_ceil(WORDS_PER_MINUTE);
+        self
+    }
+}
+
+enum Lines<'a> {
+    /// Each with its line break
+    Content(Vec<&'a str>),
+    Handle(&'a AppHandle, u32),
+}
+
+impl Lines<'_> {
+    fn content(content: &str) -> Lines<'_> {
+        let mut lines: Vec<&str> = content.split_inclusive('\n').collect();
+        // A document ending in a line break has an empty line after it
+        if content.is_empty() || content.ends_with('\n') {
+            lines.push("");
+        }
+        Lines::Content(lines)
+    }
+
+    /// Lines `first..first + count` and the line break after each.
+    fn read(&self, first: usize, count: usize) -> Result<Vec<(Cow<'_, str>, &'static str)>, String> {
+        match self {
+            Lines::Content(lines) => Ok(lines
+                .iter()
+                .skip(first)
+                .take(count)
+                .map(|line| match line.strip_suffix("\r\n") {
+                    Some(text) => (Cow::Borrowed(text), "\r\n"),
+                    None => line.strip_suffix('\n').map_or((Cow::Borrowed(*line), ""), |text| (Cow::Borrowed(text), "\n")),
+                })
+                .collect()),
+            // The file's breaks aren't kept; the size on disk stands in for the whole
+            Lines::Handle(app, handle) => Ok(large_file::lines(app, *handle, first, count)?.into_iter().map(|line| (Cow::Owned(line), "\n")).collect()),
+        }
+    }
+
+    /// Calls `f` with each line from `first` through `last` or the end,
+    /// until a newer request comes in; false if one did.
+    fn each(&self, first: usize, last: usize, superseded: &dyn Fn() -> bool, mut f: impl FnMut(usize, &str, &str) -> Result<(), String>) -> Result<bool, String> {
+        let mut at = first;
+        while at <= last {
+            if superseded() {
+                return Ok(false);
+            }
+            let batch = self.read(at, BATCH.min(last - at + 1))?;
+            for (i, (text, line_break)) in batch.iter().enumerate() {
+                f(at + i, text, line_break)?;
+            }
+            if batch.len() < BATCH.min(last - at + 1) {
+                break;
+            }
+            at += batch.len();
+        }
+        Ok(true)
+    }
+}
+
+fn stats(lines: &Lines, ranges: Option<&[TextRange]>, encoding: &str, superseded: &dyn Fn() -> bool) -> Result<Option<DocumentStats>, String> {
+    let Some(ranges) = ranges else {
+        let mut total = Counts::default();
+        if !lines.each(0, usize::MAX - 1, superseded, |_, text, line_break| total.line(text, line_break, encoding))? {
+            return Ok(None);
+        }
+        return Ok(Some(DocumentStats { total: total.finish(), ranges: Vec::new() }));
+    };
+    let mut total = Counts::default();
+    let mut counted = Vec::new();
+    for range in ranges {
+        let mut counts = Counts::default();
+        let found = lines.each(range.start_line, range.end_line, superseded, |i, text, line_break| {
+            let start = if i == range.start_line { byte_index(text, range.start_column) } else { 0 };
+            let end = if i == range.end_line { byte_index(text, range.end_column) } else { text.len() };
+            let line_break = if i == range.end_line { "" } else { line_break };
+            counts.line(&text[start..end.max(start)], line_break, encoding)
+        })?;
+        if !found {
+            return Ok(None);
+        }
+        total.add(&counts);
+        counted.push(counts.finish());
+    }
+    Ok(Some(DocumentStats { total: total.finish(), ranges: counted }))
+}
+
+/// Counts for the document, or for each of `ranges` and all of them
+/// together. `path` picks the encoding bytes are counted in; nothing comes
+/// back when a request with a later `generation` has been made for the tab.
+#[tauri::command]
+pub async fn document_stats(
+    app: AppHandle,
+    tab_id: String,
+    generation: u64,
+    source: StatsSource,
+    path: Option<String>,
+    ranges: Option<Vec<TextRange>>,
+) -> Result<Option<DocumentStats>, String> {
+    {
+        let requests = app.state::<StatsRequests>();
+        let mut latest = requests.0.lock().unwrap();
+        let current = latest.entry(tab_id.clone()).or_default();
+        if generation < *current {
+            return Ok(None);
+        }
+        *current = generation;
+    }
+    let pool = app.state::<WorkerPool>().inner().clone();
+    pool.run(Priority::Interactive, move || {
+        let requests = app.state::<StatsRequests>();
+        let superseded = || requests.0.lock().unwrap().get(&tab_id) != Some(&generation);
+        match &source {
+            StatsSource::Content { content } => {
+                let known = path.and_then(|path| document::known_encoding(&app.state::<DocumentRegistry>(), &paths::decode(&path)));
+                let encoding = known.unwrap_or_else(|| "UTF-8".into());
+                stats(&Lines::content(content), ranges.as_deref(), &encoding, &superseded)
+            }
+            StatsSource::Handle { handle } => {
+                let (size, encoding) = large_file::describe(&app, *handle)?;
+                let mut found = stats(&Lines::Handle(&app, *handle), ranges.as_deref(), &encoding, &superseded)?;
+                if let Some(found) = found.as_mut().filter(|_| ranges.is_none()) {
+                    found.total.bytes = size as usize;
+                }
+                Ok(found)
+            }
+        }
+    })
+    .await?
+}
+
+/// Forgets a closed tab's generation.
+#[tauri::command]
+pub fn forget_document_stats(requests: tauri::State<'_, StatsRequests>, tab_id: String) {
+    requests.0.lock().unwrap().remove(&tab_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(content: &str, ranges: Option<&[TextRange]>) -> DocumentStats {
+        stats(&Lines::content(content), ranges, "UTF-8", &|| false).unwrap().unwrap()
+    }
+
+    #[test]
+    fn counts_words_by_unicode_rules() {
+        let total = count("Grüße, wörld! it's 3.5\r\n你好\n", None).total;
+        assert_eq!(total.lines, 3);
+        // "it's" and "3.5" are one word each, and each ideograph is one
+        assert_eq!(total.words, 6);
+        assert_eq!(total.characters, 24);
+        assert_eq!(total.characters_without_whitespace, 21);
+        assert_eq!(total.bytes, "Grüße, wörld! it's 3.5\r\n你好\n".len());
+        assert_eq!(count("", None).total.lines, 1);
+    }
+
+    #[test]
+    fn counts_each_selection_and_all_of_them() {
+        let text = "one two\nthree four\nfive";
+        let ranges = [
+            TextRange { start_line: 0, start_column: 4, end_line: 1, end_column: 5 },
+            TextRange { start_line: 2, start_column: 0, end_line: 2, end_column: 4 },
+        ];
+        let found = count(text, Some(&ranges));
+        assert_eq!(found.ranges.iter().map(|c| c.words).collect::<Vec<_>>(), [2, 1]);
+        assert_eq!(found.ranges[0].bytes, "two\nthree".len());
+        assert_eq!(found.total.lines, 3);
+        assert_eq!(found.total.words, 3);
+        assert!(stats(&Lines::content(text), None, "UTF-8", &|| true).unwrap().is_none());
+    }
+}
@@ -0,0 +1,720 @@
+//! Formatting and validating JSON, YAML and TOML in the backend, for files
+//! too big for the webview's own formatter. Validation problems go out on the
+//! same `diagnostics-updated` channel as the linters, under one source.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use regex::Regex;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::lint::{self, Diagnostic, Severity};
+use crate::workers::{Priority, WorkerPool};
+
+/// The problems panel's name for these
+const SOURCE: &str = "syntax";
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StructuredFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct FormatOptions {
+    /// Spaces per level for JSON, 2 when unset. YAML always uses two and TOML
+    /// doesn't indent.
+    pub tab_size: Option<usize>,
+    pub use_tabs: bool,
+    pub sort_keys: bool,
+}
+
+/// A parse error, with a 1-based line and a column counted in UTF-16 units
+/// like Monaco's.
+#[derive(Debug, PartialEq)]
+struct SyntaxError {
+    line: u32,
+    column: u32,
+    end: Option<(u32, u32)>,
+    message: String,
+}
+
+impl SyntaxError {
+    fn describe(&self) -> String {
+        format!("Line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// The byte offset where 1-based `line` starts.
+fn line_start(content: &str, line: usize) -> usize {
+    if line <= 1 {
+        return 0;
+    }
+    content.match_indices('\n').nth(line - 2).map_or(content.len(), |(i, _)| i + 1)
+}
+
+fn position(content: &str, mut offset: usize) -> (u32, u32) {
+    offset = offset.min(content.len());
+    while !content.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let start = content[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = content[..start].matches('\n').count() + 1;
+    (line as u32, content[start..offset].encode_utf16().count() as u32 + 1)
+}
+
+/// A parser's message without the position it tacks on, keeping any later
+/// ones, like where an unclosed list started.
+fn without_position(message: &str) -> String {
+    static POSITION: OnceLock<Regex> = OnceLock::new();
+    let position = POSITION.get_or_init(|| Regex::new(r" at line \d+ column \d+").expect("valid regex"));
+    position.replacen(message, 1, "").into_owned()
+}
+
+fn json_error(content: &str, e: &serde_json::Error) -> SyntaxError {
+    // serde_json counts the column in bytes, and a line break as column 0
+    // of the next line
+    let start = line_start(content, e.line());
+    let offset = if e.column() == 0 { start.saturating_sub(1) } else { start + e.column() - 1 };
+    let (line, column) = position(content, offset);
+    SyntaxError { line, column, end: None, message: without_position(&e.to_string()) }
+}
+
+fn yaml_error(content: &str, e: &serde_yaml::Error) -> SyntaxError {
+    let (line, column) = match e.location() {
+        // libyaml counts the column in characters
+        Some(location) => {
+            let start = line_start(content, location.line());
+            let rest = &content[start..];
+            let offset = rest.char_indices().nth(location.column().saturating_sub(1)).map_or(rest.len(), |(i, _)| i);
+            position(content, start + offset)
+        }
+        None => (1, 1),
+    };
+    SyntaxError { line, column, end: None, message: without_position(&e.to_string()) }
+}
+
+fn toml_error(content: &str, e: &toml_edit::TomlError) -> SyntaxError {
+    let span = e.span().unwrap_or(0..0);
+    let (line, column) = position(content, span.start);
+    let end = (span.end > span.start).then(|| position(content, span.end));
+    SyntaxError { line, column, end, message: e.message().trim().to_string() }
+}
+
+// JSON
+
+/// Comments turned into spaces, so a strict parser reports the same
+/// positions as in the original.
+fn blank_comments(content: &str) -> Cow<'_, str> {
+    if !content.contains('/') {
+        return Cow::Borrowed(content);
+    }
+    let mut bytes = content.as_bytes().to_vec();
+    let mut i = 0;
+    let mut in_string = false;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if in_string => i += 1,
+            b'"' => in_string = !in_string,
+            b'/' if !in_string && bytes.get(i + 1) == Some(&b'/') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    bytes[i] = b' ';
+                    i += 1;
+                }
+                continue;
+            }
+            b'/' if !in_string && bytes.get(i + 1) == Some(&b'*') => {
+                let end = content[i + 2..].find("*/").map_or(bytes.len(), |end| i + 2 + end + 2);
+                for b in &mut bytes[i..end] {
+                    if *b != b'\n' {
+                        *b = b' ';
+                    }
+                }
+                i = end;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    // Only whole multi-byte characters were replaced, byte by byte
+    Cow::Owned(String::from_utf8(bytes).expect("still UTF-8"))
+}
+
+fn validate_json(content: &str) -> Result<(), SyntaxError> {
+    serde_json::from_str::<IgnoredAny>(&blank_comments(content)).map(|_| ()).map_err(|e| json_error(content, &e))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token<'a> {
+    Open(u8),
+    Close(u8),
+    Comma,
+    Colon,
+    Scalar(&'a str),
+    /// And whether it starts its own line
+    Comment(&'a str, bool),
+}
+
+fn tokenize(content: &str) -> Vec<Token<'_>> {
+    let bytes = content.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let mut own_line = true;
+    while i < bytes.len() {
+        let start = i;
+        match bytes[i] {
+            b'\n' => {
+                own_line = true;
+                i += 1;
+                continue;
+            }
+            b if b.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'{' | b'[' => tokens.push(Token::Open(bytes[i])),
+            b'}' | b']' => tokens.push(Token::Close(bytes[i])),
+            b',' => tokens.push(Token::Comma),
+            b':' => tokens.push(Token::Colon),
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = content[i..].find('\n').map_or(bytes.len(), |end| i + end);
+                tokens.push(Token::Comment(content[start..i].trim_end(), own_line));
+                own_line = false;
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = content[i + 2..].find("*/").map_or(bytes.len(), |end| i + 2 + end + 2);
+                tokens.push(Token::Comment(&content[start..i], own_line));
+                own_line = false;
+                continue;
+            }
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i = (i + 1).min(bytes.len());
+                tokens.push(Token::Scalar(&content[start..i]));
+                own_line = false;
+                continue;
+            }
+            _ => {
+                while i < bytes.len() && !bytes[i].is_ascii_whitespace() && !b"{}[],:/\"".contains(&bytes[i]) {
+                    i += 1;
+                }
+                tokens.push(Token::Scalar(&content[start..i]));
+                own_line = false;
+                continue;
+            }
+        }
+        own_line = false;
+        i += 1;
+    }
+    tokens
+}
+
+enum Node<'a> {
+    Scalar(&'a str),
+    Array(Container<'a>),
+    Object(Container<'a>),
+}
+
+#[derive(Default)]
+struct Container<'a> {
+    entries: Vec<Entry<'a>>,
+    /// Comments after the last entry
+    closing: Vec<&'a str>,
+}
+
+struct Entry<'a> {
+    comments: Vec<&'a str>,
+    key: Option<&'a str>,
+    value: Node<'a>,
+    /// Comments on the same line, after the value
+    trailing: Vec<&'a str>,
+}
+
+struct JsonParser<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn comments(&mut self, into: &mut Vec<&'a str>) {
+        while let Some(Token::Comment(comment, _)) = self.peek() {
+            into.push(comment);
+            self.pos += 1;
+        }
+    }
+
+    fn same_line_comments(&mut self, into: &mut Vec<&'a str>) {
+        while let Some(Token::Comment(comment, false)) = self.peek() {
+            into.push(comment);
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self) -> Option<Node<'a>> {
+        match self.next()? {
+            Token::Scalar(scalar) => Some(Node::Scalar(scalar)),
+            Token::Open(b'{') => Some(Node::Object(self.container(true)?)),
+            Token::Open(_) => Some(Node::Array(self.container(false)?)),
+            _ => None,
+        }
+    }
+
+    fn container(&mut self, object: bool) -> Option<Container<'a>> {
+        let mut container = Container::default();
+        loop {
+            let mut comments = Vec::new();
+            self.comments(&mut comments);
+            if let Token::Close(_) = self.peek()? {
+                self.pos += 1;
+                container.closing = comments;
+                return Some(container);
+            }
+            let key = if object {
+                let Token::Scalar(key) = self.next()? else { return None };
+                self.comments(&mut comments);
+                if self.next()? != Token::Colon {
+                    return None;
+                }
+                self.comments(&mut comments);
+                Some(key)
+            } else {
+                None
+            };
+            let value = self.value()?;
+            let mut trailing = Vec::new();
+            self.same_line_comments(&mut trailing);
+            if self.peek() == Some(Token::Comma) {
+                self.pos += 1;
+                self.same_line_comments(&mut trailing);
+            }
+            container.entries.push(Entry { comments, key, value, trailing });
+        }
+    }
+}
+
+struct JsonWriter {
+    out: String,
+    indent: String,
+}
+
+impl JsonWriter {
+    fn newline(&mut self, depth: usize) {
+        self.out.push('\n');
+        for _ in 0..depth {
+            self.out.push_str(&self.indent);
+        }
+    }
+
+    fn node(&mut self, node: &Node, depth: usize) {
+        let (container, open, close) = match node {
+            Node::Scalar(scalar) => return self.out.push_str(scalar),
+            Node::Array(container) => (container, '[', ']'),
+            Node::Object(container) => (container, '{', '}'),
+        };
+        self.out.push(open);
+        if container.entries.is_empty() && container.closing.is_empty() {
+            self.out.push(close);
+            return;
+        }
+        for (i, entry) in container.entries.iter().enumerate() {
+            for comment in &entry.comments {
+                self.newline(depth + 1);
+                self.out.push_str(comment);
+            }
+            self.newline(depth + 1);
+            if let Some(key) = entry.key {
+                self.out.push_str(key);
+                self.out.push_str(": ");
+            }
+            self.node(&entry.value, depth + 1);
+            if i + 1 < container.entries.len() {
+                self.out.push(',');
+            }
+            for comment in &entry.trailing {
+                self.out.push(' ');
+                self.out.push_str(comment);
+            }
+        }
+        for comment in &container.closing {
+            self.newline(depth + 1);
+            self.out.push_str(comment);
+        }
+        self.newline(depth);
+        self.out.push(close);
+    }
+}
+
+fn sort_json(node: &mut Node) {
+    match node {
+        Node::Scalar(_) => {}
+        Node::Array(container) => container.entries.iter_mut().for_each(|entry| sort_json(&mut entry.value)),
+        Node::Object(container) => {
+            let decoded = |entry: &Entry| entry.key.and_then(|key| serde_json::from_str::<String>(key).ok()).unwrap_or_default();
+            container.entries.sort_by_cached_key(decoded);
+            container.entries.iter_mut().for_each(|entry| sort_json(&mut entry.value));
+        }
+    }
+}
+
+/// Re-indents JSON without parsing its values, so numbers, escapes and
+/// comments come out exactly as written.
+fn format_json(content: &str, opts: &FormatOptions) -> Result<String, String> {
+    validate_json(content).map_err(|e| e.describe())?;
+    let mut parser = JsonParser { tokens: tokenize(content), pos: 0 };
+    let mut leading = Vec::new();
+    parser.comments(&mut leading);
+    let mut root = parser.value().ok_or("Unexpected end of the document")?;
+    if opts.sort_keys {
+        sort_json(&mut root);
+    }
+    let indent = if opts.use_tabs { "\t".to_string() } else { " ".repeat(opts.tab_size.unwrap_or(2)) };
+    let mut writer = JsonWriter { out: String::with_capacity(content.len()), indent };
+    for comment in leading {
+        writer.out.push_str(comment);
+        writer.out.push('\n');
+    }
+    writer.node(&root, 0);
+    let mut trailing = Vec::new();
+    parser.same_line_comments(&mut trailing);
+    for comment in trailing {
+        writer.out.push(' ');
+        writer.out.push_str(comment);
+    }
+    while let Some(Token::Comment(comment, _)) = parser.next() {
+        writer.out.push('\n');
+        writer.out.push_str(comment);
+    }
+    writer.out.push('\n');
+    Ok(writer.out)
+}
+
+// YAML
+
+/// Whether the document has comments, anchors or aliases, which the YAML
+/// parser doesn't keep. Text inside quoted scalars is skipped; block scalars
+/// aren't, so a `#` in one is taken for a comment, which errs on the safe side.
+fn yaml_loses_anything(content: &str) -> bool {
+    for line in content.lines() {
+        let mut quote = None;
+        let mut previous = ' ';
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            let token_start = previous.is_whitespace() || "[{,:-?".contains(previous);
+            match (quote, c) {
+                (Some('"'), '\\') => {
+                    chars.next();
+                }
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"' | '\'') if token_start => quote = Some(c),
+                (None, '#') if previous.is_whitespace() => return true,
+                (None, '&' | '*') if token_start && chars.peek().is_some_and(|next| next.is_alphanumeric()) => return true,
+                _ => {}
+            }
+            previous = c;
+        }
+    }
+    false
+}
+
+fn validate_yaml(content: &str) -> Result<(), SyntaxError> {
+    for document in serde_yaml::Deserializer::from_str(content) {
+        IgnoredAny::deserialize(document).map_err(|e| yaml_error(content, &e))?;
+    }
+    Ok(())
+}
+
+fn sort_yaml(value: &mut serde_yaml::Value) {
+    match value {
+        serde_yaml::Value::Sequence(items) => items.iter_mut().for_each(sort_yaml),
+        serde_yaml::Value::Mapping(mapping) => {
+            let mut entries: Vec<_> = std::mem::take(mapping).into_iter().collect();
+            entries.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+            entries.iter_mut().for_each(|(_, value)| sort_yaml(value));
+            *mapping = entries.into_iter().collect();
+        }
+        serde_yaml::Value::Tagged(tagged) => sort_yaml(&mut tagged.value),
+        _ => {}
+    }
+}
+
+fn format_yaml(content: &str, opts: &FormatOptions) -> Result<String, String> {
+    validate_yaml(content).map_err(|e| e.describe())?;
+    if yaml_loses_anything(content) {
+        return Err("This YAML has comments or anchors, which formatting would lose".into());
+    }
+    let mut documents = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(content) {
+        let mut value = serde_yaml::Value::deserialize(document).map_err(|e| yaml_error(content, &e).describe())?;
+        if opts.sort_keys {
+            sort_yaml(&mut value);
+        }
+        documents.push(serde_yaml::to_string(&value).map_err(|e| e.to_string())?);
+    }
+    Ok(documents.join("---\n"))
+}
+
+// TOML
+
+/// The comments in the whitespace before an item, one per line and
+/// unindented. Runs of blank lines become one; whether one leads is `blank`,
+/// or as it was when that's unset.
+fn tidy_prefix(prefix: &str, blank: Option<bool>) -> String {
+    let mut lines: Vec<&str> = prefix.split('\n').map(str::trim).collect();
+    // What's after the last line break is the item's own indentation
+    lines.pop();
+    let mut out = String::new();
+    let mut gap = false;
+    for line in &lines {
+        if line.is_empty() {
+            gap = true;
+            continue;
+        }
+        if gap && !out.is_empty() {
+            out.push('\n');
+        }
+        gap = false;
+        out.push_str(line);
+        out.push('\n');
+    }
+    if gap && !out.is_empty() {
+        out.push('\n');
+    }
+    if blank.unwrap_or(lines.first().is_some_and(|line| line.is_empty())) {
+        out.insert(0, '\n');
+    }
+    out
+}
+
+/// A same-line comment after an item, one space away.
+fn tidy_suffix(suffix: &str) -> String {
+    let suffix = suffix.trim();
+    if suffix.is_empty() {
+        String::new()
+    } else {
+        format!(" {}", suffix)
+    }
+}
+
+fn raw(decor: Option<&toml_edit::RawString>) -> &str {
+    decor.and_then(|raw| raw.as_str()).unwrap_or("")
+}
+
+/// `at_start` is whether nothing has been written above yet.
+fn tidy_table(table: &mut toml_edit::Table, dotted: bool, at_start: &mut bool, sort: bool) {
+    if sort {
+        table.sort_values();
+    }
+    for (i, (mut key, item)) in table.iter_mut().enumerate() {
+        match item {
+            toml_edit::Item::Value(value) => {
+                // The last part of a dotted key holds what's before all of it
+                let decor = key.leaf_decor_mut();
+                let prefix = tidy_prefix(raw(decor.prefix()), (i == 0 && !dotted).then_some(false));
+                decor.set_prefix(prefix);
+                decor.set_suffix(" ");
+                let decor = value.decor_mut();
+                let suffix = tidy_suffix(raw(decor.suffix()));
+                decor.set_prefix(" ");
+                decor.set_suffix(suffix);
+                *at_start = false;
+            }
+            toml_edit::Item::Table(table) if table.is_dotted() => tidy_table(table, true, at_start, sort),
+            _ => {}
+        }
+    }
+    for (_, item) in table.iter_mut() {
+        match item {
+            toml_edit::Item::Table(table) if !table.is_dotted() => tidy_header(table, at_start, sort),
+            toml_edit::Item::ArrayOfTables(tables) => tables.iter_mut().for_each(|table| tidy_header(table, at_start, sort)),
+            _ => {}
+        }
+    }
+}
+
+fn tidy_header(table: &mut toml_edit::Table, at_start: &mut bool, sort: bool) {
+    if !table.is_implicit() {
+        let decor = table.decor_mut();
+        let prefix = tidy_prefix(raw(decor.prefix()), Some(!*at_start));
+        let suffix = tidy_suffix(raw(decor.suffix()));
+        decor.set_prefix(prefix);
+        decor.set_suffix(suffix);
+        *at_start = false;
+    }
+    tidy_table(table, false, at_start, sort);
+}
+
+fn validate_toml(content: &str) -> Result<(), SyntaxError> {
+    toml_edit::Document::parse(content).map(|_| ()).map_err(|e| toml_error(content, &e))
+}
+
+/// Evens out the spacing around keys, comments and tables, keeping the
+/// comments and leaving values as written.
+fn format_toml(content: &str, opts: &FormatOptions) -> Result<String, String> {
+    let mut document: toml_edit::DocumentMut = content.parse().map_err(|e| toml_error(content, &e).describe())?;
+    tidy_table(document.as_table_mut(), false, &mut true, opts.sort_keys);
+    let trailing = tidy_prefix(&format!("{}\n", raw(Some(document.trailing()))), None);
+    document.set_trailing(trailing);
+    let mut out = document.to_string();
+    out.truncate(out.trim_end().len());
+    out.push('\n');
+    Ok(out)
+}
+
+pub fn format(content: &str, format: StructuredFormat, opts: &FormatOptions) -> Result<String, String> {
+    match format {
+        StructuredFormat::Json => format_json(content, opts),
+        StructuredFormat::Yaml => format_yaml(content, opts),
+        StructuredFormat::Toml => format_toml(content, opts),
+    }
+}
+
+fn validate(content: &str, format: StructuredFormat) -> Result<(), SyntaxError> {
+    match format {
+        StructuredFormat::Json => validate_json(content),
+        StructuredFormat::Yaml => validate_yaml(content),
+        StructuredFormat::Toml => validate_toml(content),
+    }
+}
+
+/// The latest problems of each validated file, and the run they came from.
+#[derive(Default)]
+pub struct Validations {
+    next: AtomicU64,
+    files: Mutex<HashMap<String, (u64, Vec<Diagnostic>)>>,
+}
+
+fn publish(app: &AppHandle, files: &HashMap<String, (u64, Vec<Diagnostic>)>) {
+    let mut paths: Vec<&String> = files.keys().collect();
+    paths.sort();
+    let items: Vec<Diagnostic> = paths.into_iter().flat_map(|path| files[path].1.iter().cloned()).collect();
+    lint::publish(app, SOURCE, &items);
+}
+
+/// Formats a whole JSON, YAML or TOML document. Invalid input is an error
+/// naming where it's wrong.
+#[tauri::command]
+pub async fn structured_format(app: AppHandle, content: String, format: StructuredFormat, opts: Option<FormatOptions>) -> Result<String, String> {
+    let opts = opts.unwrap_or_default();
+    app.state::<WorkerPool>().run(Priority::Interactive, move || self::format(&content, format, &opts)).await?
+}
+
+/// Checks a document, cheaply enough to run as it's typed. With a path, the
+/// problems also replace that file's earlier ones in `diagnostics-updated`.
+#[tauri::command]
+pub async fn structured_validate(app: AppHandle, content: String, format: StructuredFormat, path: Option<String>) -> Result<Vec<Diagnostic>, String> {
+    let run = app.state::<Validations>().next.fetch_add(1, Ordering::Relaxed) + 1;
+    let error = app.state::<WorkerPool>().run(Priority::Interactive, move || validate(&content, format).err()).await?;
+    let items: Vec<Diagnostic> = error
+        .into_iter()
+        .map(|e| Diagnostic {
+            path: path.clone().unwrap_or_default(),
+            line: e.line,
+            column: e.column,
+            end_line: e.end.map(|(line, _)| line),
+            end_column: e.end.map(|(_, column)| column),
+            severity: Severity::Error,
+            message: e.message,
+            code: None,
+        })
+        .collect();
+    if let Some(path) = path {
+        let validations = app.state::<Validations>();
+        let mut files = validations.files.lock().unwrap();
+        // A slower, older run finishing last doesn't get the last word
+        if files.get(&path).map_or(true, |(latest, _)| *latest < run) {
+            let changed = files.get(&path).map_or(!items.is_empty(), |(_, old)| *old != items);
+            files.insert(path, (run, items.clone()));
+            if changed {
+                publish(&app, &files);
+            }
+        }
+    }
+    Ok(items)
+}
+
+/// Drops a closed file's problems.
+#[tauri::command]
+pub fn forget_structured_diagnostics(app: AppHandle, validations: tauri::State<'_, Validations>, path: String) {
+    let mut files = validations.files.lock().unwrap();
+    if files.remove(&path).is_some_and(|(_, items)| !items.is_empty()) {
+        publish(&app, &files);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(sort_keys: bool) -> FormatOptions {
+        FormatOptions { sort_keys, ..Default::default() }
+    }
+
+    #[test]
+    fn formats_json_keeping_values_and_comments() {
+        let content = "// settings\n{\"b\": 1.50, \"a\": [1,2, {}], // the list\n  /* last */ \"c\": \"\\u00e9\"}";
+        assert_eq!(
+            format(content, StructuredFormat::Json, &opts(false)).unwrap(),
+            "// settings\n{\n  \"b\": 1.50,\n  \"a\": [\n    1,\n    2,\n    {}\n  ], // the list\n  /* last */\n  \"c\": \"\\u00e9\"\n}\n"
+        );
+        let sorted = FormatOptions { sort_keys: true, use_tabs: true, ..Default::default() };
+        assert_eq!(format("{\"b\":{\"d\":1,\"c\":2},\"a\":null}", StructuredFormat::Json, &sorted).unwrap(), "{\n\t\"a\": null,\n\t\"b\": {\n\t\t\"c\": 2,\n\t\t\"d\": 1\n\t}\n}\n");
+    }
+
+    #[test]
+    fn reports_positions_in_utf16_columns() {
+        let e = validate("{\n  \"drömmar\": tru\n}", StructuredFormat::Json).unwrap_err();
+        assert_eq!((e.line, e.column), (2, 17));
+        assert_eq!(e.message, "expected ident");
+        // Comments are allowed, and don't move the position
+        let e = validate("{ /* é */ \"a\": 1,, }", StructuredFormat::Json).unwrap_err();
+        assert_eq!((e.line, e.column), (1, 18));
+
+        let e = validate("x: ööö: 1\n", StructuredFormat::Yaml).unwrap_err();
+        assert_eq!((e.line, e.column), (1, 7));
+        let e = validate("a: 1\nb: [å, 2\n", StructuredFormat::Yaml).unwrap_err();
+        assert_eq!(e.message, "did not find expected ',' or ']', while parsing a flow sequence at line 2 column 4");
+        let e = validate("name = \"x\"\nvärde = = 2\n", StructuredFormat::Toml).unwrap_err();
+        assert_eq!((e.line, e.column), (2, 9));
+        assert!(validate("---\na: 1\n---\nb: [2]\n", StructuredFormat::Yaml).is_ok());
+    }
+
+    #[test]
+    fn formats_yaml_unless_it_would_lose_comments() {
+        assert_eq!(format("b:   [1, 2]\na: {x: 'y'}\n", StructuredFormat::Yaml, &opts(true)).unwrap(), "a:\n  x: y\nb:\n- 1\n- 2\n");
+        assert!(format("a: 1 # why\n", StructuredFormat::Yaml, &opts(false)).is_err());
+        assert!(format("base: &base {a: 1}\n", StructuredFormat::Yaml, &opts(false)).is_err());
+        assert!(!yaml_loses_anything("url: 'http://x/#top'\nnote: it's fine\n"));
+    }
+
+    #[test]
+    fn formats_toml_keeping_comments() {
+        let content = "\n\ntitle   =   \"x\"    # the name\n\n\n# owner\n[owner]\nname=\"y\"\n  dob.year =  1979\n[[bin]]\nname=\"z\"\n\n# end\n";
+        assert_eq!(
+            format(content, StructuredFormat::Toml, &opts(false)).unwrap(),
+            "title = \"x\" # the name\n\n# owner\n[owner]\nname = \"y\"\ndob.year = 1979\n\n[[bin]]\nname = \"z\"\n\n# end\n"
+        );
+    }
+}
@@ -0,0 +1,481 @@
+//! CSV and TSV files as tables. The delimiter and quoting are sniffed from
+//! the start of the file, then one pass over the rest counts the rows, infers
+//! each column's type and notes the rows with the wrong number of fields.
+//! Pages of rows are read after that from checkpoints the pass left behind.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::document;
+use crate::large_file::STALE;
+use crate::paths;
+use crate::workers::{Priority, WorkerPool};
+
+/// Bytes the dialect is sniffed from.
+const SAMPLE: u64 = 64 * 1024;
+/// Rows the sniffer compares.
+const SNIFF_ROWS: usize = 50;
+/// Rows between the positions kept for paging.
+const CHECKPOINT: u64 = 1024;
+const DISTINCT_CAP: usize = 1000;
+/// Malformed rows listed; the rest are only counted.
+const MALFORMED_SHOWN: usize = 100;
+const MAX_PAGE: usize = 1000;
+/// In order of preference when two fit equally well.
+const DELIMITERS: &[u8] = b",\t;|";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CsvSource {
+    Path(String),
+    /// A buffer that isn't saved as it is
+    Content(String),
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CsvOptions {
+    /// Sniffed when unset, as are the others
+    pub delimiter: Option<char>,
+    pub quote: Option<char>,
+    pub has_header: Option<bool>,
+    /// Distinct values counted per column before it's just "at least"
+    pub distinct_cap: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ColumnType {
+    /// Nothing but empty fields
+    Empty,
+    Boolean,
+    Integer,
+    Number,
+    Date,
+    Text,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ColumnStats {
+    name: String,
+    kind: ColumnType,
+    empty: u64,
+    distinct: usize,
+    /// `distinct` stopped at the cap
+    capped: bool,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MalformedRow {
+    line: u64,
+    fields: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CsvInspection {
+    handle: u32,
+    delimiter: char,
+    quote: char,
+    has_header: bool,
+    encoding: String,
+    header: Vec<String>,
+    /// Rows after the header
+    rows: u64,
+    columns: Vec<ColumnStats>,
+    /// The first of the rows whose field count differs from the header's
+    malformed: Vec<MalformedRow>,
+    malformed_count: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CsvRow {
+    line: u64,
+    fields: Vec<String>,
+}
+
+enum Source {
+    File { path: PathBuf, size: u64, modified: Option<SystemTime> },
+    Content(Arc<[u8]>),
+}
+
+struct Table {
+    source: Source,
+    delimiter: u8,
+    quote: u8,
+    encoding: String,
+    /// Where every `CHECKPOINT`th row after the header starts
+    checkpoints: Vec<csv::Position>,
+    rows: u64,
+}
+
+#[derive(Default)]
+pub struct CsvTables(Mutex<HashMap<u32, Arc<Table>>>);
+
+static NEXT_HANDLE: AtomicU32 = AtomicU32::new(1);
+
+fn stamp(path: &Path) -> Result<(u64, Option<SystemTime>), String> {
+    let metadata = std::fs::metadata(path).map_err(|e| e.to_string())?;
+    Ok((metadata.len(), metadata.modified().ok()))
+}
+
+fn builder(delimiter: u8, quote: u8) -> csv::ReaderBuilder {
+    let mut builder = csv::ReaderBuilder::new();
+    builder.delimiter(delimiter).quote(quote).has_headers(false).flexible(true);
+    builder
+}
+
+fn decode(field: &[u8], encoding: &str) -> String {
+    match std::str::from_utf8(field) {
+        Ok(text) => text.to_string(),
+        Err(_) => document::decode_part(field, encoding).map_or_else(|_| String::from_utf8_lossy(field).into_owned(), |(text, _)| text),
+    }
+}
+
+/// The delimiter and quote that split the sample into the most rows of the
+/// same length, and that length.
+fn sniff(sample: &[u8], delimiters: &[u8]) -> (u8, u8, usize) {
+    let mut best = (b',', b'"', 1, 0.0);
+    for &delimiter in delimiters {
+        for quote in [b'"', b'\''] {
+            let mut reader = builder(delimiter, quote).from_reader(sample);
+            let counts: Vec<usize> = reader.byte_records().take(SNIFF_ROWS).map_while(Result::ok).map(|record| record.len()).collect();
+            let mut frequency: HashMap<usize, usize> = HashMap::new();
+            for &count in &counts {
+                *frequency.entry(count).or_default() += 1;
+            }
+            // The longest of the most common lengths
+            let Some((&fields, &times)) = frequency.iter().max_by_key(|&(&fields, &times)| (times, fields)) else { continue };
+            let consistency = times as f64 / counts.len() as f64;
+            if fields > 1 && (consistency > best.3 || consistency == best.3 && fields > best.2) {
+                best = (delimiter, quote, fields, consistency);
+            }
+        }
+    }
+    (best.0, best.1, best.2)
+}
+
+fn is_date(text: &str) -> bool {
+    let date = text.split(['T', ' ']).next().unwrap_or("");
+    let parts: Vec<&str> = date.split(['-', '.', '/']).collect();
+    if parts.len() != 3 || !parts.iter().all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit())) {
+        return false;
+    }
+    let lengths: Vec<usize> = parts.iter().map(|part| part.len()).collect();
+    matches!(lengths.as_slice(), [4, 1..=2, 1..=2] | [1..=2, 1..=2, 4])
+}
+
+/// The value of a number, and whether it's a whole one. With a decimal
+/// comma, as exports with `;` between fields have, `1,5` is one and a half.
+fn number(text: &str, decimal_comma: bool) -> Option<(f64, bool)> {
+    if !text.bytes().all(|b| b.is_ascii_digit() || b"+-.,eE".contains(&b)) || !text.bytes().any(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if let Ok(whole) = text.parse::<i64>() {
+        return Some((whole as f64, true));
+    }
+    let text: Cow<str> = if decimal_comma && !text.contains('.') { text.replacen(',', ".", 1).into() } else { text.into() };
+    text.parse::<f64>().ok().map(|value| (value, false))
+}
+
+fn classify(text: &str, decimal_comma: bool) -> (ColumnType, Option<f64>) {
+    match number(text, decimal_comma) {
+        Some((value, true)) => (ColumnType::Integer, Some(value)),
+        Some((value, false)) => (ColumnType::Number, Some(value)),
+        None if ["true", "false", "yes", "no"].iter().any(|word| text.eq_ignore_ascii_case(word)) => (ColumnType::Boolean, None),
+        None if is_date(text) => (ColumnType::Date, None),
+        None => (ColumnType::Text, None),
+    }
+}
+
+#[derive(Default)]
+struct Column {
+    kind: Option<ColumnType>,
+    empty: u64,
+    seen: HashSet<String>,
+    capped: bool,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl Column {
+    fn add(&mut self, text: &str, decimal_comma: bool, cap: usize) {
+        let text = text.trim();
+        if text.is_empty() {
+            self.empty += 1;
+            return;
+        }
+        let (kind, value) = classify(text, decimal_comma);
+        self.kind = Some(match (self.kind, kind) {
+            (None, kind) => kind,
+            (Some(a), b) if a == b => a,
+            (Some(ColumnType::Integer | ColumnType::Number), ColumnType::Integer | ColumnType::Number) => ColumnType::Number,
+            _ => ColumnType::Text,
+        });
+        if let Some(value) = value {
+            self.min = Some(self.min.map_or(value, |min| min.min(value)));
+            self.max = Some(self.max.map_or(value, |max| max.max(value)));
+        }
+        if self.seen.len() < cap {
+            self.seen.insert(text.to_string());
+        } else if !self.seen.contains(text) {
+            self.capped = true;
+        }
+    }
+
+    fn stats(self, name: String) -> ColumnStats {
+        let kind = self.kind.unwrap_or(ColumnType::Empty);
+        let numeric = matches!(kind, ColumnType::Integer | ColumnType::Number);
+        ColumnStats {
+            name,
+            kind,
+            empty: self.empty,
+            distinct: self.seen.len(),
+            capped: self.capped,
+            min: self.min.filter(|_| numeric),
+            max: self.max.filter(|_| numeric),
+        }
+    }
+}
+
+/// A first row of distinct labels, none of them numbers, is a header.
+fn looks_like_header(first: &[String]) -> bool {
+    let unique: HashSet<&str> = first.iter().map(|field| field.trim()).collect();
+    unique.len() == first.len() && first.iter().all(|field| !field.trim().is_empty() && number(field.trim(), true).is_none())
+}
+
+/// Reads the whole table once. The first row is left for the caller when
+/// `has_header` is unset and already in `header`.
+fn inspect<R: Read>(reader: R, table: &mut Table, has_header: Option<bool>, width: usize, cap: usize) -> Result<CsvInspection, String> {
+    let mut reader = builder(table.delimiter, table.quote).from_reader(reader);
+    let mut record = csv::ByteRecord::new();
+    let mut first = Vec::new();
+    let mut first_position = None;
+    if reader.read_byte_record(&mut record).map_err(|e| e.to_string())? {
+        first = record.iter().map(|field| decode(field, &table.encoding)).collect();
+        first_position = record.position().cloned();
+    }
+    let has_header = has_header.unwrap_or_else(|| looks_like_header(&first));
+    let header = if has_header { first.clone() } else { (1..=first.len().max(width)).map(|i| format!("Column {}", i)).collect() };
+    let decimal_comma = table.delimiter != b',';
+    let mut columns: Vec<Column> = header.iter().map(|_| Column::default()).collect();
+    let mut malformed = Vec::new();
+    let mut malformed_count = 0;
+    let mut rows = 0u64;
+    let mut add = |fields: &[String], position: Option<&csv::Position>, table: &mut Table| {
+        if rows % CHECKPOINT == 0 {
+            table.checkpoints.push(position.cloned().unwrap_or_else(csv::Position::new));
+        }
+        rows += 1;
+        if fields.len() != columns.len() {
+            malformed_count += 1;
+            if malformed.len() < MALFORMED_SHOWN {
+                malformed.push(MalformedRow { line: position.map_or(0, |p| p.line()), fields: fields.len() });
+            }
+        }
+        for (column, field) in columns.iter_mut().zip(fields) {
+            column.add(field, decimal_comma, cap);
+        }
+    };
+    if !has_header && first_position.is_some() {
+        add(&first, first_position.as_ref(), table);
+    }
+    let mut fields = Vec::new();
+    while reader.read_byte_record(&mut record).map_err(|e| e.to_string())? {
+        fields.clear();
+        fields.extend(record.iter().map(|field| decode(field, &table.encoding)));
+        add(&fields, record.position(), table);
+    }
+    table.rows = rows;
+    Ok(CsvInspection {
+        handle: 0,
+        delimiter: table.delimiter as char,
+        quote: table.quote as char,
+        has_header,
+        encoding: table.encoding.clone(),
+        columns: columns.into_iter().zip(header.iter().cloned()).map(|(column, name)| column.stats(name)).collect(),
+        header,
+        rows,
+        malformed,
+        malformed_count,
+    })
+}
+
+fn ascii(c: Option<char>, what: &str) -> Result<Option<u8>, String> {
+    match c {
+        Some(c) if c.is_ascii() => Ok(Some(c as u8)),
+        Some(c) => Err(format!("The {} must be a single ASCII character, not {:?}", what, c)),
+        None => Ok(None),
+    }
+}
+
+fn open(source: CsvSource, opts: &CsvOptions) -> Result<(Table, CsvInspection), String> {
+    let (sample, whole, source, tab_first) = match source {
+        CsvSource::Path(path) => {
+            let path = paths::decode(&path);
+            let (size, modified) = stamp(&path)?;
+            let mut sample = Vec::new();
+            File::open(&path).and_then(|file| file.take(SAMPLE).read_to_end(&mut sample)).map_err(|e| e.to_string())?;
+            let tab_first = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("tsv") || ext.eq_ignore_ascii_case("tab"));
+            (sample, size <= SAMPLE, Source::File { path, size, modified }, tab_first)
+        }
+        CsvSource::Content(content) => {
+            let bytes: Arc<[u8]> = content.into_bytes().into();
+            let sample = bytes[..bytes.len().min(SAMPLE as usize)].to_vec();
+            (sample, bytes.len() as u64 <= SAMPLE, Source::Content(bytes), false)
+        }
+    };
+    let (encoding, _) = document::detect_start(&sample, whole);
+    if encoding.starts_with("UTF-16") {
+        return Err("CSV in UTF-16 isn't supported; save it as UTF-8 to inspect it".into());
+    }
+    // A cut-off last row would throw the sniffer
+    let sample = match (whole, sample.iter().rposition(|&b| b == b'\n')) {
+        (false, Some(end)) => &sample[..end],
+        _ => &sample[..],
+    };
+    let delimiters: Vec<u8> = if tab_first { std::iter::once(b'\t').chain(DELIMITERS.iter().copied().filter(|&d| d != b'\t')).collect() } else { DELIMITERS.to_vec() };
+    let (sniffed_delimiter, sniffed_quote, width) = match ascii(opts.delimiter, "delimiter")? {
+        Some(delimiter) => {
+            let (_, quote, width) = sniff(sample, &[delimiter]);
+            (delimiter, quote, width)
+        }
+        None => sniff(sample, &delimiters),
+    };
+    let quote = ascii(opts.quote, "quote")?.unwrap_or(sniffed_quote);
+    let mut table = Table { source, delimiter: sniffed_delimiter, quote, encoding: encoding.to_string(), checkpoints: Vec::new(), rows: 0 };
+    let cap = opts.distinct_cap.unwrap_or(DISTINCT_CAP);
+    let inspection = match &table.source {
+        Source::File { path, .. } => {
+            let file = File::open(path).map_err(|e| e.to_string())?;
+            inspect(file, &mut table, opts.has_header, width, cap)?
+        }
+        Source::Content(bytes) => {
+            let bytes = bytes.clone();
+            inspect(&bytes[..], &mut table, opts.has_header, width, cap)?
+        }
+    };
+    Ok((table, inspection))
+}
+
+fn page<R: Read + Seek>(reader: R, table: &Table, start: u64, count: usize) -> Result<Vec<CsvRow>, String> {
+    let Some(checkpoint) = table.checkpoints.get((start / CHECKPOINT) as usize) else { return Ok(Vec::new()) };
+    let mut reader = builder(table.delimiter, table.quote).from_reader(reader);
+    reader.seek(checkpoint.clone()).map_err(|e| e.to_string())?;
+    let mut record = csv::ByteRecord::new();
+    let mut rows = Vec::with_capacity(count);
+    let mut skip = start % CHECKPOINT;
+    while rows.len() < count && reader.read_byte_record(&mut record).map_err(|e| e.to_string())? {
+        if skip > 0 {
+            skip -= 1;
+            continue;
+        }
+        let line = record.position().map_or(0, |p| p.line());
+        rows.push(CsvRow { line, fields: record.iter().map(|field| decode(field, &table.encoding)).collect() });
+    }
+    Ok(rows)
+}
+
+impl Table {
+    fn rows(&self, start: u64, count: usize) -> Result<Vec<CsvRow>, String> {
+        let count = count.min(MAX_PAGE);
+        match &self.source {
+            Source::File { path, size, modified } => {
+                if stamp(path)? != (*size, *modified) {
+                    return Err(STALE.into());
+                }
+                page(File::open(path).map_err(|e| e.to_string())?, self, start, count)
+            }
+            Source::Content(bytes) => page(Cursor::new(bytes.clone()), self, start, count),
+        }
+    }
+}
+
+/// Sniffs and reads a whole CSV or TSV, from disk or from a buffer. Rows
+/// with the wrong number of fields are listed with their line, not errors.
+#[tauri::command]
+pub async fn csv_inspect(app: tauri::AppHandle, source: CsvSource, opts: Option<CsvOptions>) -> Result<CsvInspection, String> {
+    let opts = opts.unwrap_or_default();
+    // A long pass over a big file; it'd hold up a worker other reads need
+    let (table, mut inspection) = tauri::async_runtime::spawn_blocking(move || open(source, &opts)).await.map_err(|e| e.to_string())??;
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+    inspection.handle = handle;
+    log::info!("Inspected a table of {} rows and {} columns", inspection.rows, inspection.columns.len());
+    app.state::<CsvTables>().0.lock().unwrap().insert(handle, Arc::new(table));
+    Ok(inspection)
+}
+
+/// Up to `count` rows from `start`, 0-based and after the header.
+#[tauri::command]
+pub async fn csv_get_rows(app: tauri::AppHandle, handle: u32, start: u64, count: usize) -> Result<Vec<CsvRow>, String> {
+    let table = app.state::<CsvTables>().0.lock().unwrap().get(&handle).cloned();
+    let table = table.ok_or_else(|| format!("No open table with handle {}", handle))?;
+    app.state::<WorkerPool>().run(Priority::Interactive, move || table.rows(start, count)).await?
+}
+
+#[tauri::command]
+pub fn csv_close(tables: tauri::State<'_, CsvTables>, handle: u32) {
+    tables.0.lock().unwrap().remove(&handle);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content(text: &str, opts: CsvOptions) -> (Table, CsvInspection) {
+        open(CsvSource::Content(text.to_string()), &opts).unwrap()
+    }
+
+    #[test]
+    fn sniffs_european_exports_and_tsv() {
+        let (_, semicolons) = content("Name;Preis;Datum\n\"Müller, A\";1,50;31.12.2024\nB;2,00;01.01.2025\n", CsvOptions::default());
+        assert_eq!((semicolons.delimiter, semicolons.has_header), (';', true));
+        assert_eq!(semicolons.header, ["Name", "Preis", "Datum"]);
+        assert_eq!(semicolons.columns[1].kind, ColumnType::Number);
+        assert_eq!((semicolons.columns[1].min, semicolons.columns[1].max), (Some(1.5), Some(2.0)));
+        assert_eq!(semicolons.columns[2].kind, ColumnType::Date);
+
+        let (_, tabs) = content("1\tone, two\ttrue\n2\tthree\tfalse\n", CsvOptions::default());
+        assert_eq!((tabs.delimiter, tabs.has_header, tabs.rows), ('\t', false, 2));
+        assert_eq!(tabs.header, ["Column 1", "Column 2", "Column 3"]);
+        assert_eq!(tabs.columns.iter().map(|c| c.kind).collect::<Vec<_>>(), [ColumnType::Integer, ColumnType::Text, ColumnType::Boolean]);
+    }
+
+    #[test]
+    fn reports_malformed_rows_by_line() {
+        let (_, inspection) = content("a,b\n1,2\n3\n\"multi\nline\",4,5\n6,7\n", CsvOptions::default());
+        assert_eq!(inspection.rows, 4);
+        assert_eq!(inspection.malformed, [MalformedRow { line: 3, fields: 1 }, MalformedRow { line: 4, fields: 3 }]);
+        assert_eq!(inspection.malformed_count, 2);
+    }
+
+    #[test]
+    fn pages_rows_past_checkpoints() {
+        let mut text = String::from("id,square\n");
+        for i in 0..3000 {
+            text.push_str(&format!("{},{}\n", i, i * i));
+        }
+        let (table, inspection) = content(&text, CsvOptions { distinct_cap: Some(10), ..Default::default() });
+        assert_eq!(inspection.rows, 3000);
+        assert_eq!((inspection.columns[0].distinct, inspection.columns[0].capped), (10, true));
+        assert_eq!(inspection.columns[1].max, Some(2999.0 * 2999.0));
+        let rows = table.rows(2047, 3).unwrap();
+        assert_eq!(rows.iter().map(|row| row.fields[0].as_str()).collect::<Vec<_>>(), ["2047", "2048", "2049"]);
+        assert_eq!(rows[0].line, 2049);
+        assert!(table.rows(3000, 10).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+// Output is sent at most this often, or sooner once a batch gets this big
+const BATCH_INTERVAL: Duration = Duration::from_millis(50);
+const BATCH_BYTES: usize = 64 * 1024;
+// Reads a batch can fall behind by before the child blocks on a full pipe
+const PENDING_READS: usize = 16;
+// How long the exit waits for output still in the pipes
+const EXIT_DRAIN: Duration = Duration::from_millis(200);
+#[cfg(unix)]
+const KILL_GRACE: Duration = Duration::from_secs(2);
+
+/// Process ids of running tasks, by task id.
+#[derive(Default)]
+pub struct Tasks(Mutex<HashMap<u32, u32>>);
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// What the pipe readers and the waiter send to `forward_output`.
+enum Message {
+    Output(Stream, Vec<u8>),
+    Exited(Option<i32>),
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TaskOutput {
+    task_id: u32,
+    stream: Stream,
+    chunk: String,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TaskExited {
+    task_id: u32,
+    /// `None` when the task was killed by a signal
+    code: Option<i32>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TaskOptions {
+    pub cwd: Option<String>,
+    /// Added to skriv's own environment
+    pub env: HashMap<String, String>,
+    /// Runs `cmd` as a shell command line, with `args` as its positional
+    /// parameters. Off unless asked for, so arguments are never reparsed.
+    pub shell: bool,
+}
+
+/// PATH from the user's login shell. Apps started from Finder or the Dock get
+/// launchd's minimal PATH, without Homebrew, cargo and the like.
+#[cfg(target_os = "macos")]
+pub fn login_path() -> Option<&'static str> {
+    use std::sync::OnceLock;
+    static PATH: OnceLock<Option<String>> = OnceLock::new();
+    PATH.get_or_init(|| {
+        const MARKER: &str = "__SKRIV_PATH__";
+        let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/zsh".into());
+        // Interactive too, since PATH is often set in .zshrc. The markers skip
+        // whatever the startup files print
+        let script = format!("printf '{0}%s{0}' \"$PATH\"", MARKER);
+        let mut child = Command::new(shell)
+            .args(["-l", "-i", "-c", &script])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        // Startup files that wait on something mustn't hold up every task
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while child.try_wait().ok()?.is_none() {
+            if Instant::now() > deadline {
+                let _ = child.kill();
+                log::warn!("Timed out reading PATH from the login shell");
+                return None;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let mut output = String::new();
+        child.stdout.take()?.read_to_string(&mut output).ok()?;
+        let path = output.split(MARKER).nth(1)?;
+        (!path.is_empty()).then(|| path.to_string())
+    })
+    .as_deref()
+}
+
+pub(crate) fn command(cmd: &str, args: &[String], opts: &TaskOptions) -> Command {
+    let mut command = if !opts.shell {
+        let mut command = Command::new(cmd);
+        command.args(args);
+        command
+    } else if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(cmd).args(args);
+        command
+    } else {
+        let mut command = Command::new("/bin/sh");
+        command.arg("-c").arg(cmd).arg("sh").args(args);
+        command
+    };
+    #[cfg(target_os = "macos")]
+    if let Some(path) = login_path() {
+        command.env("PATH", path);
+    }
+    command.envs(&opts.env).stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    if let Some(cwd) = &opts.cwd {
+        command.current_dir(cwd);
+    }
+    // Its own process group, so killing the task gets its children too
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // CREATE_NO_WINDOW | CREATE_NEW_PROCESS_GROUP
+        command.creation_flags(0x0800_0000 | 0x0000_0200);
+    }
+    command
+}
+
+/// Splits off the complete UTF-8 in `pending`, keeping a character cut in
+/// half by a read for the next chunk. Invalid bytes before it go out replaced.
+fn take_text(pending: &mut Vec<u8>) -> String {
+    let mut complete = pending.len();
+    let mut at = 0;
+    while let Err(e) = std::str::from_utf8(&pending[at..]) {
+        match e.error_len() {
+            Some(len) => at += e.valid_up_to() + len,
+            None => {
+                complete = at + e.valid_up_to();
+                break;
+            }
+        }
+    }
+    let rest = pending.split_off(complete);
+    let text = String::from_utf8_lossy(pending).into_owned();
+    *pending = rest;
+    text
+}
+
+fn read_into(mut source: impl Read, stream: Stream, sender: mpsc::SyncSender<Message>) {
+    let mut buf = [0; 8192];
+    loop {
+        match source.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                if sender.send(Message::Output(stream, buf[..n].to_vec())).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// The text to emit from each stream's pending bytes: only complete
+/// characters, unless `all` is set at the end.
+fn take_chunks(pending: &mut [(Stream, Vec<u8>); 2], all: bool) -> Vec<(Stream, String)> {
+    pending
+        .iter_mut()
+        .map(|(stream, bytes)| (*stream, if all { String::from_utf8_lossy(&std::mem::take(bytes)).into_owned() } else { take_text(bytes) }))
+        .filter(|(_, chunk)| !chunk.is_empty())
+        .collect()
+}
+
+/// Emits output in batches until both pipes close, and the exit code once
+/// the process is reaped. The exit waits a moment for what's left in the
+/// pipes, but not for a process the task started that still holds them.
+fn forward_output(app: &AppHandle, task_id: u32, receiver: mpsc::Receiver<Message>) {
+    let mut pending: [(Stream, Vec<u8>); 2] = [(Stream::Stdout, Vec::new()), (Stream::Stderr, Vec::new())];
+    let flush = |pending: &mut [(Stream, Vec<u8>); 2], all: bool| {
+        for (stream, chunk) in take_chunks(pending, all) {
+            let _ = app.emit("task-output", TaskOutput { task_id, stream, chunk });
+        }
+    };
+    let exit = |pending: &mut [(Stream, Vec<u8>); 2], code: Option<i32>| {
+        flush(pending, true);
+        let _ = app.emit("task-exited", TaskExited { task_id, code });
+    };
+    let mut last_flush = Instant::now();
+    // The exit code, and when to stop waiting for the pipes
+    let mut exited: Option<(Option<i32>, Instant)> = None;
+    loop {
+        match receiver.recv_timeout(BATCH_INTERVAL) {
+            Ok(Message::Output(stream, bytes)) => {
+                let index = if stream == Stream::Stdout { 0 } else { 1 };
+                pending[index].1.extend_from_slice(&bytes);
+            }
+            Ok(Message::Exited(code)) => exited = Some((code, Instant::now() + EXIT_DRAIN)),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+        if let Some((code, _)) = exited.filter(|(_, deadline)| Instant::now() >= *deadline) {
+            exited = None;
+            exit(&mut pending, code);
+            last_flush = Instant::now();
+        }
+        let size = pending[0].1.len() + pending[1].1.len();
+        if size >= BATCH_BYTES || last_flush.elapsed() >= BATCH_INTERVAL {
+            flush(&mut pending, false);
+            last_flush = Instant::now();
+        }
+    }
+    match exited {
+        Some((code, _)) => exit(&mut pending, code),
+        None => flush(&mut pending, true),
+    }
+}
+
+/// Registers a process started elsewhere, so it can be killed like a task.
+pub(crate) fn track(app: &AppHandle, pid: u32) -> u32 {
+    let task_id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    app.state::<Tasks>().0.lock().unwrap().insert(task_id, pid);
+    task_id
+}
+
+pub(crate) fn untrack(app: &AppHandle, task_id: u32) {
+    app.state::<Tasks>().0.lock().unwrap().remove(&task_id);
+}
+
+fn run(app: AppHandle, cmd: String, args: Vec<String>, opts: TaskOptions) -> Result<u32, String> {
+    let mut child = command(&cmd, &args, &opts).spawn().map_err(|e| format!("Failed to run {}: {}", cmd, e))?;
+    let task_id = track(&app, child.id());
+
+    let (sender, receiver) = mpsc::sync_channel(PENDING_READS);
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let (stdout_sender, stderr_sender) = (sender.clone(), sender.clone());
+    std::thread::spawn(move || read_into(stdout, Stream::Stdout, stdout_sender));
+    std::thread::spawn(move || read_into(stderr, Stream::Stderr, stderr_sender));
+    let waiter = app.clone();
+    std::thread::spawn(move || {
+        let code = child.wait().ok().and_then(|status| status.code());
+        // Forgotten as soon as it's reaped, so a late kill can't hit a reused pid
+        untrack(&waiter, task_id);
+        let _ = sender.send(Message::Exited(code));
+    });
+    std::thread::spawn(move || forward_output(&app, task_id, receiver));
+    Ok(task_id)
+}
+
+/// Starts `cmd` and returns its task id. Output arrives as `task-output`
+/// events and the exit code as `task-exited`.
+#[tauri::command]
+pub async fn run_task(app: AppHandle, cmd: String, args: Vec<String>, opts: Option<TaskOptions>) -> Result<u32, String> {
+    // Blocking, since the first task on macOS waits for the login shell
+    tauri::async_runtime::spawn_blocking(move || run(app, cmd, args, opts.unwrap_or_default()))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Stops the task and every process it started.
+#[tauri::command]
+pub fn kill_task(app: AppHandle, task_id: u32) -> Result<(), String> {
+    kill(&app, task_id)
+}
+
+pub(crate) fn kill(app: &AppHandle, task_id: u32) -> Result<(), String> {
+    let pid = app.state::<Tasks>().0.lock().unwrap().get(&task_id).copied();
+    let Some(pid) = pid else { return Ok(()) };
+    #[cfg(unix)]
+    {
+        let group = -(pid as libc::pid_t);
+        // SAFETY: plain syscalls; the group is the task's own
+        if unsafe { libc::kill(group, libc::SIGTERM) } != 0 {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+        // Whatever ignores SIGTERM gets SIGKILL after a grace period
+        let app = app.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(KILL_GRACE);
+            let tasks = app.state::<Tasks>();
+            let tasks = tasks.0.lock().unwrap();
+            if tasks.contains_key(&task_id) {
+                // SAFETY: plain syscall. The lock keeps the task from being
+                // forgotten meanwhile, and it's forgotten as soon as its
+                // process is reaped, so the group is still the task's own
+                unsafe { libc::kill(group, libc::SIGKILL) };
+            }
+        });
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        let output = Command::new("taskkill")
+            .args(["/PID", &pid.to_string(), "/T", "/F"])
+            .creation_flags(0x0800_0000)
+            .output()
+            .map_err(|e| e.to_string())?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_a_cut_character_for_the_next_chunk() {
+        // "å" is 0xC3 0xA5, cut after its first byte
+        let mut pending = b"ok \xc3".to_vec();
+        assert_eq!(take_text(&mut pending), "ok ");
+        assert_eq!(pending, b"\xc3");
+        pending.extend_from_slice(b"\xa5!");
+        assert_eq!(take_text(&mut pending), "\u{e5}!");
+        assert!(pending.is_empty());
+        // Bytes that can never be UTF-8 go out replaced rather than held
+        let mut pending = b"a\xffb".to_vec();
+        assert_eq!(take_text(&mut pending), "a\u{fffd}b");
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn keeps_a_cut_character_after_invalid_bytes() {
+        let mut pending = b"a\xff b \xc3".to_vec();
+        assert_eq!(take_text(&mut pending), "a\u{fffd} b ");
+        assert_eq!(pending, b"\xc3");
+        pending.extend_from_slice(b"\xa5");
+        assert_eq!(take_text(&mut pending), "\u{e5}");
+    }
+
+    #[test]
+    fn splits_output_by_stream() {
+        let mut pending = [(Stream::Stdout, b"built\n\xe2\x9c".to_vec()), (Stream::Stderr, b"warning\n".to_vec())];
+        let chunks = take_chunks(&mut pending, false);
+        assert!(chunks == [(Stream::Stdout, "built\n".to_string()), (Stream::Stderr, "warning\n".to_string())]);
+        assert_eq!(pending[0].1, b"\xe2\x9c");
+        assert!(take_chunks(&mut pending, false).is_empty());
+        // At the end what's left goes out, even half a character
+        let chunks = take_chunks(&mut pending, true);
+        assert!(chunks == [(Stream::Stdout, "\u{fffd}".to_string())]);
+        assert!(pending.iter().all(|(_, bytes)| bytes.is_empty()));
+    }
+}
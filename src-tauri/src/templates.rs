@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_opener::OpenerExt;
+
+use crate::settings::Settings;
+use crate::{menu, paths};
+
+pub const DIR_NAME: &str = "templates";
+pub const MENU_PREFIX: &str = "template:";
+
+const BUILTIN: &[(&str, &str)] = &[
+    ("meeting-notes.md", include_str!("templates/meeting-notes.md")),
+    ("blog-post.md", include_str!("templates/blog-post.md")),
+    ("mit-license.txt", include_str!("templates/mit-license.txt")),
+];
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateInfo {
+    /// The file name in the templates folder
+    pub id: String,
+    pub name: String,
+    /// The name a new document from it is saved as, before substitution
+    pub filename: String,
+    pub builtin: bool,
+    /// Why a user template can't be used; it's listed anyway so it can be fixed
+    pub error: Option<String>,
+}
+
+struct Template {
+    info: TemplateInfo,
+    body: String,
+}
+
+/// A new document's text and file name, with the variables nothing gave a
+/// value for left in place.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Instantiated {
+    content: String,
+    filename: String,
+    missing: Vec<String>,
+}
+
+/// How a value is written where a variable stood.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Escape {
+    Raw,
+    /// `&`, `<`, `>` and quotes as entities
+    Html,
+    /// For inside a JSON string, without the quotes
+    Json,
+    /// A double-quoted YAML scalar, quotes included
+    Yaml,
+    /// Lowercase letters and digits joined by `-`, for URLs and file names
+    Slug,
+}
+
+impl Escape {
+    fn named(filter: &str) -> Option<Escape> {
+        Some(match filter {
+            "raw" => Escape::Raw,
+            "html" => Escape::Html,
+            "json" => Escape::Json,
+            "yaml" => Escape::Yaml,
+            "slug" => Escape::Slug,
+            _ => return None,
+        })
+    }
+
+    /// Values in markup and JSON templates are escaped unless `|raw` says otherwise.
+    fn default_for(id: &str) -> Escape {
+        match id.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).as_deref() {
+            Some("html" | "htm" | "xml" | "svg") => Escape::Html,
+            Some("json") => Escape::Json,
+            _ => Escape::Raw,
+        }
+    }
+
+    fn apply(self, value: &str) -> String {
+        match self {
+            Escape::Raw => value.to_string(),
+            Escape::Html => value
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+                .replace('\'', "&#39;"),
+            Escape::Json => {
+                let quoted = serde_json::to_string(value).unwrap_or_default();
+                quoted[1..quoted.len() - 1].to_string()
+            }
+            Escape::Yaml => serde_json::to_string(value).unwrap_or_default(),
+            Escape::Slug => {
+                let lower = value.to_lowercase();
+                let words: Vec<&str> = lower.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).collect();
+                words.join("-")
+            }
+        }
+    }
+}
+
+/// Keeps a value from adding folders to a file name or using characters
+/// some systems refuse.
+fn file_name_safe(value: &str) -> String {
+    value.chars().map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() { '-' } else { c }).collect()
+}
+
+/// `text` with `{{name}}` and `{{name|filter}}` replaced from `values`.
+/// `\{{` stands for a literal `{{`. Names nothing has a value for are
+/// left as they are and added to `missing`.
+fn render(text: &str, values: &HashMap<String, String>, default: Escape, in_file_name: bool, missing: &mut Vec<String>) -> Result<String, String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find("{{") {
+        if rest[..at].ends_with('\\') {
+            out.push_str(&rest[..at - 1]);
+            out.push_str("{{");
+            rest = &rest[at + 2..];
+            continue;
+        }
+        out.push_str(&rest[..at]);
+        let Some(len) = rest[at + 2..].find("}}") else {
+            out.push_str(&rest[at..]);
+            return Ok(out);
+        };
+        let whole = &rest[at..at + 2 + len + 2];
+        let inner = &rest[at + 2..at + 2 + len];
+        rest = &rest[at + 2 + len + 2..];
+        let (name, filter) = match inner.split_once('|') {
+            Some((name, filter)) => (name.trim(), Some(filter.trim())),
+            None => (inner.trim(), None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            out.push_str(whole);
+            continue;
+        }
+        let escape = match filter {
+            Some(filter) => Escape::named(filter).ok_or_else(|| format!("Unknown filter \"{}\" in {}", filter, whole))?,
+            None => default,
+        };
+        match values.get(name) {
+            Some(value) => {
+                let value = escape.apply(value);
+                out.push_str(&if in_file_name { file_name_safe(&value) } else { value });
+            }
+            None => {
+                if !missing.iter().any(|m| m == name) {
+                    missing.push(name.to_string());
+                }
+                out.push_str(whole);
+            }
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// A template file: leading `{{! key: value }}` lines give its `name` and
+/// `filename`, and the rest is its text.
+fn parse(id: &str, text: &str, builtin: bool) -> Template {
+    let stem = id.rsplit_once('.').map_or(id, |(stem, _)| stem);
+    let mut info = TemplateInfo { id: id.to_string(), name: stem.to_string(), filename: id.to_string(), builtin, error: None };
+    let mut body = text;
+    while let Some((line, after)) = body.split_once('\n').or(Some((body, ""))).filter(|(line, _)| !line.is_empty()) {
+        let Some(header) = line.trim_end_matches('\r').trim().strip_prefix("{{!").and_then(|l| l.strip_suffix("}}")) else {
+            break;
+        };
+        if let Some((key, value)) = header.split_once(':') {
+            match key.trim() {
+                "name" => info.name = value.trim().to_string(),
+                "filename" => info.filename = value.trim().to_string(),
+                _ => {}
+            }
+        }
+        body = after;
+    }
+    Template { info, body: body.to_string() }
+}
+
+/// The folder the templates watcher lives on, once it exists.
+#[derive(Default)]
+pub struct TemplateWatcher(Mutex<Option<RecommendedWatcher>>);
+
+impl TemplateWatcher {
+    pub fn is_running(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
+}
+
+pub fn dir(app: &AppHandle) -> Result<PathBuf, String> {
+    paths::config_dir(app).map(|dir| dir.join(DIR_NAME))
+}
+
+/// The files in the templates folder, by name, with their text or why it
+/// couldn't be read.
+fn user_templates(app: &AppHandle) -> Vec<(String, Result<String, String>)> {
+    let Ok(entries) = dir(app).and_then(|dir| std::fs::read_dir(dir).map_err(|e| e.to_string())) else {
+        return Vec::new();
+    };
+    let mut files: Vec<(String, Result<String, String>)> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            (!name.starts_with('.')).then(|| (name, std::fs::read_to_string(entry.path()).map_err(|e| e.to_string())))
+        })
+        .collect();
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    files
+}
+
+fn load(app: &AppHandle) -> Vec<Template> {
+    let user = user_templates(app);
+    let mut templates: Vec<Template> = BUILTIN
+        .iter()
+        .filter(|(id, _)| !user.iter().any(|(name, _)| name == id))
+        .map(|(id, text)| parse(id, text, true))
+        .collect();
+    for (id, text) in user {
+        templates.push(match text {
+            Ok(text) => parse(&id, &text, false),
+            Err(e) => {
+                let mut template = parse(&id, "", false);
+                template.info.error = Some(e);
+                template
+            }
+        });
+    }
+    templates
+}
+
+/// Built-in templates, then those in the templates folder; one there with
+/// a built-in one's file name replaces it.
+pub fn list(app: &AppHandle) -> Vec<TemplateInfo> {
+    load(app).into_iter().map(|template| template.info).collect()
+}
+
+/// The template's text and file name, filled in with `vars` on top of
+/// `date`, `time`, `year`, `author` from settings, and `title`, which
+/// defaults to the template's name.
+pub fn instantiate(app: &AppHandle, id: &str, vars: HashMap<String, String>) -> Result<Instantiated, String> {
+    let template = load(app).into_iter().find(|t| t.info.id == id).ok_or_else(|| format!("No template \"{}\"", id))?;
+    if let Some(e) = template.info.error {
+        return Err(e);
+    }
+    let now = chrono::Local::now();
+    let mut values = HashMap::from([
+        ("date".to_string(), now.format("%Y-%m-%d").to_string()),
+        ("time".to_string(), now.format("%H:%M").to_string()),
+        ("year".to_string(), now.format("%Y").to_string()),
+        ("title".to_string(), template.info.name.clone()),
+    ]);
+    if let Some(author) = app.state::<Mutex<Settings>>().lock().unwrap().author.clone().filter(|a| !a.trim().is_empty()) {
+        values.insert("author".to_string(), author);
+    }
+    values.extend(vars);
+    let mut missing = Vec::new();
+    let content = render(&template.body, &values, Escape::default_for(id), false, &mut missing)?;
+    let filename = render(&template.info.filename, &values, Escape::Raw, true, &mut missing)?;
+    Ok(Instantiated { content, filename, missing })
+}
+
+/// Something in the templates folder changed: the New from Template menu
+/// is rebuilt and windows told with `templates-changed`.
+pub fn changed(app: &AppHandle) {
+    menu::rebuild(app);
+    let _ = app.emit("templates-changed", list(app));
+}
+
+/// Starts watching the templates folder if it's there and not yet watched.
+pub fn watch(app: &AppHandle) {
+    let watcher = app.state::<TemplateWatcher>();
+    let mut watcher = watcher.0.lock().unwrap();
+    let Ok(dir) = dir(app) else { return };
+    if watcher.is_some() || !dir.is_dir() {
+        return;
+    }
+    let handle = app.clone();
+    let started = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        let Ok(event) = res else { return };
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        let app = handle.clone();
+        let _ = handle.run_on_main_thread(move || changed(&app));
+    })
+    .and_then(|mut started| started.watch(&dir, RecursiveMode::NonRecursive).map(|_| started));
+    match started {
+        Ok(started) => *watcher = Some(started),
+        Err(e) => log::warn!("Failed to watch {}: {}", dir.display(), e),
+    }
+}
+
+pub fn open_folder(app: &AppHandle) -> Result<(), String> {
+    let dir = dir(app)?;
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    watch(app);
+    app.opener().open_path(dir.to_string_lossy(), None::<&str>).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn list_templates(app: AppHandle) -> Vec<TemplateInfo> {
+    list(&app)
+}
+
+#[tauri::command]
+pub fn instantiate_template(app: AppHandle, id: String, vars: Option<HashMap<String, String>>) -> Result<Instantiated, String> {
+    instantiate(&app, &id, vars.unwrap_or_default())
+}
+
+#[tauri::command]
+pub fn open_templates_folder(app: AppHandle) -> Result<(), String> {
+    open_folder(&app)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn builtin_templates_fill_in() {
+        let values = values(&[("date", "2026-10-14"), ("year", "2026"), ("title", "Q3: Review"), ("author", "Ada")]);
+        for (id, text) in BUILTIN {
+            let template = parse(id, text, true);
+            assert!(!template.info.name.is_empty() && template.info.name != template.info.id, "{}", id);
+            assert!(!template.body.starts_with("{{!"), "{}", id);
+            let mut missing = Vec::new();
+            render(&template.body, &values, Escape::default_for(id), false, &mut missing).unwrap();
+            render(&template.info.filename, &values, Escape::Raw, true, &mut missing).unwrap();
+            assert!(missing.is_empty(), "{}: {:?}", id, missing);
+        }
+        let post = parse("blog-post.md", BUILTIN[1].1, true);
+        let mut missing = Vec::new();
+        assert_eq!(render(&post.info.filename, &values, Escape::Raw, true, &mut missing).unwrap(), "2026-10-14-q3-review.md");
+        assert!(render(&post.body, &values, Escape::Raw, false, &mut missing).unwrap().contains("title: \"Q3: Review\"\n"));
+    }
+
+    #[test]
+    fn reads_headers_and_defaults() {
+        let template = parse("notes.txt", "{{! name: Notes }}\r\n{{! filename: {{title}}.txt }}\nBody {{! not a header }}\n", false);
+        assert_eq!(template.info.name, "Notes");
+        assert_eq!(template.info.filename, "{{title}}.txt");
+        assert_eq!(template.body, "Body {{! not a header }}\n");
+        let plain = parse("todo.md", "- [ ] {{title}}", false);
+        assert_eq!((plain.info.name.as_str(), plain.info.filename.as_str()), ("todo", "todo.md"));
+        assert_eq!(plain.body, "- [ ] {{title}}");
+    }
+
+    #[test]
+    fn escapes_values() {
+        let values = values(&[("title", "Tom & \"Jerry\" <3"), ("path", "a/b:c")]);
+        let mut missing = Vec::new();
+        let mut fill = |text: &str, default: Escape| render(text, &values, default, false, &mut missing).unwrap();
+        assert_eq!(fill("<h1>{{title}}</h1>", Escape::Html), "<h1>Tom &amp; &quot;Jerry&quot; &lt;3</h1>");
+        assert_eq!(fill("<h1>{{ title | raw }}</h1>", Escape::Html), "<h1>Tom & \"Jerry\" <3</h1>");
+        assert_eq!(fill("{\"t\": \"{{title}}\"}", Escape::Json), "{\"t\": \"Tom & \\\"Jerry\\\" <3\"}");
+        assert_eq!(fill("{{title|slug}}", Escape::Raw), "tom-jerry-3");
+        assert_eq!(fill("\\{{title}} {{title}", Escape::Raw), "{{title}} {{title}");
+        assert!(render("{{title|upper}}", &values, Escape::Raw, false, &mut Vec::new()).is_err());
+        let mut missing = Vec::new();
+        assert_eq!(render("{{path}}/{{who}}.md", &values, Escape::Raw, true, &mut missing).unwrap(), "a-b-c/{{who}}.md");
+        assert_eq!(missing, ["who"]);
+    }
+}
@@ -0,0 +1,177 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use tauri::AppHandle;
+
+use crate::paths;
+use crate::workspace;
+use crate::workspace_trust::UNTRUSTED;
+
+/// Terminals Open Terminal Here knows how to start, by the name settings
+/// use for them. The first one found is the default.
+#[cfg(target_os = "macos")]
+const KNOWN: &[&str] = &["Terminal", "iTerm", "Warp", "Ghostty", "WezTerm", "Alacritty", "kitty"];
+#[cfg(target_os = "linux")]
+const KNOWN: &[&str] = &[
+    "x-terminal-emulator",
+    "gnome-terminal",
+    "konsole",
+    "xfce4-terminal",
+    "kitty",
+    "alacritty",
+    "wezterm",
+    "xterm",
+];
+#[cfg(windows)]
+const KNOWN: &[&str] = &["wt", "powershell", "cmd"];
+#[cfg(not(any(target_os = "macos", target_os = "linux", windows)))]
+const KNOWN: &[&str] = &[];
+
+#[cfg(target_os = "macos")]
+fn installed(name: &str) -> bool {
+    let bundle = format!("{}.app", name);
+    let home = std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+    [Path::new("/Applications"), Path::new("/System/Applications/Utilities"), &home.join("Applications")]
+        .iter()
+        .any(|dir| dir.join(&bundle).exists())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn installed(name: &str) -> bool {
+    #[cfg(windows)]
+    const EXTENSIONS: &[&str] = &[".exe", ""];
+    #[cfg(not(windows))]
+    const EXTENSIONS: &[&str] = &[""];
+    let Some(path) = std::env::var_os("PATH") else { return false };
+    // Not is_file, which is false for Windows' app execution aliases like wt
+    let exists = |path: PathBuf| path.symlink_metadata().is_ok_and(|m| !m.is_dir());
+    std::env::split_paths(&path).any(|dir| EXTENSIONS.iter().any(|ext| exists(dir.join(format!("{}{}", name, ext)))))
+}
+
+/// GNOME's configured terminal, which is what the desktop itself opens.
+#[cfg(target_os = "linux")]
+fn desktop_default() -> Option<String> {
+    let output = Command::new("gsettings")
+        .args(["get", "org.gnome.desktop.default-applications.terminal", "exec"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let exec = String::from_utf8_lossy(&output.stdout).trim().trim_matches('\'').to_string();
+    (output.status.success() && !exec.is_empty()).then_some(exec)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn desktop_default() -> Option<String> {
+    None
+}
+
+fn detected() -> Vec<String> {
+    let mut found: Vec<String> = desktop_default().into_iter().filter(|name| installed(name)).collect();
+    for name in KNOWN {
+        if installed(name) && !found.iter().any(|f| f.eq_ignore_ascii_case(name)) {
+            found.push(name.to_string());
+        }
+    }
+    found
+}
+
+#[cfg(target_os = "macos")]
+fn launch(name: &str, dir: &Path) -> Result<(), String> {
+    if name.eq_ignore_ascii_case("iTerm") {
+        // A new window in the running iTerm rather than another instance
+        let script = r#"on run argv
+    tell application "iTerm"
+        activate
+        set newWindow to (create window with default profile)
+        tell current session of newWindow to write text "cd " & quoted form of (item 1 of argv) & " && clear"
+    end tell
+end run"#;
+        return spawn(Command::new("osascript").arg("-e").arg(script).arg(dir));
+    }
+    spawn(Command::new("open").arg("-a").arg(name).arg(dir))
+}
+
+#[cfg(target_os = "linux")]
+fn launch(name: &str, dir: &Path) -> Result<(), String> {
+    let mut command = Command::new(name);
+    // The working directory is enough for most; these want it spelled out
+    match name {
+        "gnome-terminal" | "xfce4-terminal" | "alacritty" => {
+            command.arg("--working-directory").arg(dir);
+        }
+        "konsole" => {
+            command.arg("--workdir").arg(dir);
+        }
+        "kitty" => {
+            command.arg("--directory").arg(dir);
+        }
+        "wezterm" => {
+            command.arg("start").arg("--cwd").arg(dir);
+        }
+        _ => {}
+    }
+    spawn(command.current_dir(dir))
+}
+
+#[cfg(windows)]
+fn launch(name: &str, dir: &Path) -> Result<(), String> {
+    use std::os::windows::process::CommandExt;
+    let mut command = match name.to_ascii_lowercase().as_str() {
+        "wt" => {
+            let mut command = Command::new("wt");
+            command.arg("-d").arg(dir);
+            command
+        }
+        // `start` gives the shell a console of its own
+        shell => {
+            let mut command = Command::new("cmd");
+            command.args(["/C", "start", "", shell]);
+            command.creation_flags(0x0800_0000);
+            command
+        }
+    };
+    spawn(command.current_dir(dir))
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", windows)))]
+fn launch(_name: &str, _dir: &Path) -> Result<(), String> {
+    Err("Opening a terminal isn't supported on this platform".into())
+}
+
+fn spawn(command: &mut Command) -> Result<(), String> {
+    command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+    let mut child = command.spawn().map_err(|e| e.to_string())?;
+    // Reaped in the background; the terminal outlives the launcher anyway
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+fn open_terminal(dir: &Path, app: Option<String>) -> Result<(), String> {
+    // A document's path opens its folder
+    let dir = if dir.is_file() { dir.parent().unwrap_or(dir) } else { dir };
+    if !dir.is_dir() {
+        return Err(format!("{} isn't a folder", dir.display()));
+    }
+    let found = detected();
+    let name = match app {
+        Some(name) if installed(&name) => name,
+        Some(name) => {
+            let listed = if found.is_empty() { "none".to_string() } else { found.join(", ") };
+            return Err(format!("Terminal \"{}\" wasn't found. Detected terminals: {}", name, listed));
+        }
+        None => found.into_iter().next().ok_or("No terminal was found")?,
+    };
+    launch(&name, dir)
+}
+
+/// Opens a terminal at `dir`: `app` if given, else the one in settings, else
+/// the platform's default. One only an untrusted workspace names is
+/// UNTRUSTED.
+#[tauri::command]
+pub async fn open_external_terminal(handle: AppHandle, window: tauri::Window, dir: String, app: Option<String>) -> Result<(), String> {
+    let app = app.or_else(|| workspace::effective(&handle, window.label()).terminal);
+    if app.is_none() && workspace::withheld(&handle, window.label(), "terminal").is_some_and(|terminal| !terminal.is_null()) {
+        return Err(UNTRUSTED.into());
+    }
+    tauri::async_runtime::spawn_blocking(move || open_terminal(&paths::decode(&dir), app)).await.map_err(|e| e.to_string())?
+}
@@ -0,0 +1,402 @@
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, Theme};
+
+use crate::settings::{self, Settings, ThemeMode};
+use crate::{color_theme, menu};
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThemeState {
+    mode: ThemeMode,
+    effective: Theme,
+    /// The color theme in use, which decides `effective` by its kind
+    color_theme: Option<String>,
+    /// Why the chosen color theme was set aside for the mode's default
+    warning: Option<String>,
+}
+
+pub const MENU_ITEMS: &[(&str, ThemeMode)] = &[
+    ("theme_system", ThemeMode::System),
+    ("theme_light", ThemeMode::Light),
+    ("theme_dark", ThemeMode::Dark),
+];
+
+pub fn current_mode(app: &AppHandle) -> ThemeMode {
+    app.state::<Mutex<Settings>>().lock().unwrap().appearance.mode
+}
+
+fn effective(app: &AppHandle, mode: ThemeMode) -> Theme {
+    match mode {
+        ThemeMode::Light => Theme::Light,
+        ThemeMode::Dark => Theme::Dark,
+        ThemeMode::System => system_theme(app),
+    }
+}
+
+/// The OS's own light or dark setting. Where it can't be read directly the
+/// windows' theme stands in, which follows the OS only in System mode.
+fn system_theme(app: &AppHandle) -> Theme {
+    platform::theme()
+        .or_else(|| app.webview_windows().values().find_map(|w| w.theme().ok()))
+        .unwrap_or(Theme::Light)
+}
+
+pub fn chosen_color_theme(app: &AppHandle) -> Option<String> {
+    app.state::<Mutex<Settings>>().lock().unwrap().appearance.color_theme.clone()
+}
+
+/// The mode with the chosen color theme on top. One that can't be read falls
+/// back to the mode's default, saying why.
+fn resolve(app: &AppHandle, mode: ThemeMode) -> ThemeState {
+    let base = ThemeState { mode, effective: effective(app, mode), color_theme: None, warning: None };
+    let Some(id) = chosen_color_theme(app) else { return base };
+    match color_theme::read(app, &id) {
+        Ok(theme) => ThemeState { effective: theme.info.kind, color_theme: Some(id), ..base },
+        Err(e) => {
+            log::warn!("Not using color theme {}: {}", id, e);
+            ThemeState { warning: Some(format!("The theme \"{}\" couldn't be used: {}", id, e)), ..base }
+        }
+    }
+}
+
+/// The mode items are checked while no color theme is in use, as the two
+/// kinds of item pick from the same list.
+fn set_checkmarks(app: &AppHandle, theme: &ThemeState) {
+    for (id, item_mode) in MENU_ITEMS {
+        menu::set_checked(app, id, theme.color_theme.is_none() && *item_mode == theme.mode);
+    }
+    for info in color_theme::list(app) {
+        let id = format!("{}{}", color_theme::MENU_PREFIX, info.id);
+        menu::set_checked(app, &id, theme.color_theme.as_deref() == Some(info.id.as_str()));
+    }
+}
+
+/// Applies the mode, or the color theme's kind, to the native window chrome
+/// and the menu checkmarks.
+pub fn apply(app: &AppHandle, mode: ThemeMode) -> ThemeState {
+    let theme = resolve(app, mode);
+    app.set_theme(match (mode, &theme.color_theme) {
+        (_, Some(_)) => Some(theme.effective),
+        (ThemeMode::System, None) => None,
+        (ThemeMode::Light, None) => Some(Theme::Light),
+        (ThemeMode::Dark, None) => Some(Theme::Dark),
+    });
+    set_checkmarks(app, &theme);
+    theme
+}
+
+/// Applies a mode that's already in the settings and tells the windows.
+pub fn mode_changed(app: &AppHandle, mode: ThemeMode) -> ThemeState {
+    let theme = apply(app, mode);
+    let _ = app.emit("theme-changed", &theme);
+    theme
+}
+
+/// Picking a mode puts the color theme aside.
+pub fn set_mode(app: &AppHandle, mode: ThemeMode) -> Result<ThemeState, String> {
+    settings::modify(app, |settings| settings.appearance = settings::Appearance { mode, color_theme: None })?;
+    Ok(mode_changed(app, mode))
+}
+
+/// Uses a color theme from the themes folder, or `None` for the mode's own.
+pub fn pick_color_theme(app: &AppHandle, id: Option<String>) -> Result<ThemeState, String> {
+    if let Some(id) = &id {
+        color_theme::read(app, id)?;
+    }
+    settings::modify(app, |settings| settings.appearance.color_theme = id)?;
+    Ok(mode_changed(app, current_mode(app)))
+}
+
+#[tauri::command]
+pub fn set_theme_mode(app: AppHandle, mode: ThemeMode) -> Result<ThemeState, String> {
+    set_mode(&app, mode)
+}
+
+#[tauri::command]
+pub fn set_color_theme(app: AppHandle, id: Option<String>) -> Result<ThemeState, String> {
+    pick_color_theme(&app, id)
+}
+
+#[tauri::command]
+pub fn get_theme(app: AppHandle) -> ThemeState {
+    resolve(&app, current_mode(&app))
+}
+
+/// How the OS itself is set up, whichever mode skriv is in.
+#[derive(Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SystemAppearance {
+    theme: Theme,
+    /// `#rrggbb`, on platforms with an accent color
+    accent_color: Option<String>,
+    reduced_motion: bool,
+    increased_contrast: bool,
+}
+
+/// The appearance last sent to the windows, so only changes are emitted.
+#[derive(Default)]
+pub struct LastAppearance(Mutex<Option<SystemAppearance>>);
+
+pub fn system_appearance(app: &AppHandle) -> SystemAppearance {
+    SystemAppearance {
+        theme: system_theme(app),
+        accent_color: platform::accent_color(),
+        reduced_motion: platform::reduced_motion(),
+        increased_contrast: platform::increased_contrast(),
+    }
+}
+
+/// Rereads the OS appearance and, if anything changed, tells every window.
+/// In System mode the effective theme follows along.
+pub fn appearance_changed(app: &AppHandle) {
+    let appearance = system_appearance(app);
+    {
+        let last = app.state::<LastAppearance>();
+        let mut last = last.0.lock().unwrap();
+        if last.as_ref() == Some(&appearance) {
+            return;
+        }
+        *last = Some(appearance.clone());
+    }
+    let _ = app.emit("system-appearance-changed", &appearance);
+    let mode = current_mode(app);
+    let theme = resolve(app, mode);
+    if mode == ThemeMode::System && theme.color_theme.is_none() {
+        set_checkmarks(app, &theme);
+        let _ = app.emit("theme-changed", ThemeState { effective: appearance.theme, ..theme });
+    }
+}
+
+#[tauri::command]
+pub fn get_system_appearance(app: AppHandle) -> SystemAppearance {
+    let appearance = system_appearance(&app);
+    *app.state::<LastAppearance>().0.lock().unwrap() = Some(appearance.clone());
+    appearance
+}
+
+pub use platform::watch;
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::cell::RefCell;
+
+    use objc2::rc::Retained;
+    use objc2::runtime::{AnyObject, NSObject};
+    use objc2::{define_class, msg_send, sel, DefinedClass, MainThreadMarker, MainThreadOnly};
+    use objc2_app_kit::{
+        NSColor, NSColorSpace, NSSystemColorsDidChangeNotification, NSWorkspace,
+        NSWorkspaceAccessibilityDisplayOptionsDidChangeNotification,
+    };
+    use objc2_foundation::{NSDistributedNotificationCenter, NSNotification, NSNotificationCenter, NSString, NSUserDefaults};
+    use tauri::{AppHandle, Theme};
+
+    pub fn theme() -> Option<Theme> {
+        // NSApp's appearance follows skriv's mode; this is the OS's
+        let style = NSUserDefaults::standardUserDefaults().stringForKey(&NSString::from_str("AppleInterfaceStyle"));
+        Some(if style.is_some_and(|style| style.to_string() == "Dark") { Theme::Dark } else { Theme::Light })
+    }
+
+    pub fn accent_color() -> Option<String> {
+        let color = NSColor::controlAccentColor().colorUsingColorSpace(&NSColorSpace::sRGBColorSpace())?;
+        let channel = |c: f64| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Some(format!(
+            "#{:02x}{:02x}{:02x}",
+            channel(color.redComponent()),
+            channel(color.greenComponent()),
+            channel(color.blueComponent())
+        ))
+    }
+
+    pub fn reduced_motion() -> bool {
+        NSWorkspace::sharedWorkspace().accessibilityDisplayShouldReduceMotion()
+    }
+
+    pub fn increased_contrast() -> bool {
+        NSWorkspace::sharedWorkspace().accessibilityDisplayShouldIncreaseContrast()
+    }
+
+    define_class!(
+        // SAFETY: NSObject has no subclassing requirements and the class has no Drop impl
+        #[unsafe(super(NSObject))]
+        #[thread_kind = MainThreadOnly]
+        #[name = "SkrivAppearanceObserver"]
+        #[ivars = AppHandle]
+        struct Observer;
+
+        impl Observer {
+            #[unsafe(method(appearanceChanged:))]
+            fn appearance_changed(&self, _notification: &NSNotification) {
+                super::appearance_changed(self.ivars());
+            }
+        }
+    );
+
+    thread_local! {
+        // Notification centers don't retain their observers
+        static OBSERVER: RefCell<Option<Retained<Observer>>> = const { RefCell::new(None) };
+    }
+
+    /// Observes dark mode, accent color and accessibility display changes.
+    pub fn watch(app: &AppHandle) {
+        let Some(mtm) = MainThreadMarker::new() else { return };
+        let observer = Observer::alloc(mtm).set_ivars(app.clone());
+        let observer: Retained<Observer> = unsafe { msg_send![super(observer), init] };
+        let observer_obj: &AnyObject = &observer;
+        let selector = sel!(appearanceChanged:);
+        // SAFETY: `appearanceChanged:` takes the notification, as observer
+        // methods do, and the observer is kept alive for good
+        unsafe {
+            // Sent even while skriv's mode keeps the windows from following
+            NSDistributedNotificationCenter::defaultCenter().addObserver_selector_name_object(
+                observer_obj,
+                selector,
+                Some(&NSString::from_str("AppleInterfaceThemeChangedNotification")),
+                None,
+            );
+            // Includes accent color changes
+            NSNotificationCenter::defaultCenter().addObserver_selector_name_object(
+                observer_obj,
+                selector,
+                Some(NSSystemColorsDidChangeNotification),
+                None,
+            );
+            NSWorkspace::sharedWorkspace().notificationCenter().addObserver_selector_name_object(
+                observer_obj,
+                selector,
+                Some(NSWorkspaceAccessibilityDisplayOptionsDidChangeNotification),
+                None,
+            );
+        }
+        OBSERVER.with_borrow_mut(|current| *current = Some(observer));
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use tauri::{AppHandle, Theme};
+    use windows::core::{w, BOOL, PCWSTR};
+    use windows::Win32::System::Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_DWORD};
+    use windows::Win32::UI::Accessibility::{HCF_HIGHCONTRASTON, HIGHCONTRASTW};
+    use windows::Win32::UI::WindowsAndMessaging::{
+        SystemParametersInfoW, SPI_GETCLIENTAREAANIMATION, SPI_GETHIGHCONTRAST, SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS,
+    };
+
+    fn dword(key: PCWSTR, value: PCWSTR) -> Option<u32> {
+        let mut data = 0u32;
+        let mut size = std::mem::size_of::<u32>() as u32;
+        // SAFETY: the buffer is a DWORD and its size is passed along
+        let result = unsafe {
+            RegGetValueW(
+                HKEY_CURRENT_USER,
+                key,
+                value,
+                RRF_RT_REG_DWORD,
+                None,
+                Some(&mut data as *mut u32 as *mut _),
+                Some(&mut size),
+            )
+        };
+        result.is_ok().then_some(data)
+    }
+
+    pub fn theme() -> Option<Theme> {
+        let light = dword(w!(r"Software\Microsoft\Windows\CurrentVersion\Themes\Personalize"), w!("AppsUseLightTheme"))?;
+        Some(if light == 0 { Theme::Dark } else { Theme::Light })
+    }
+
+    pub fn accent_color() -> Option<String> {
+        // Stored as 0xAABBGGRR
+        let abgr = dword(w!(r"Software\Microsoft\Windows\DWM"), w!("AccentColor"))?;
+        Some(format!("#{:02x}{:02x}{:02x}", abgr & 0xff, (abgr >> 8) & 0xff, (abgr >> 16) & 0xff))
+    }
+
+    pub fn reduced_motion() -> bool {
+        let mut animations = BOOL(1);
+        // SAFETY: SPI_GETCLIENTAREAANIMATION fills in a BOOL
+        let read = unsafe {
+            SystemParametersInfoW(
+                SPI_GETCLIENTAREAANIMATION,
+                0,
+                Some(&mut animations as *mut BOOL as *mut _),
+                SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+            )
+        };
+        read.is_ok() && !animations.as_bool()
+    }
+
+    pub fn increased_contrast() -> bool {
+        let mut contrast = HIGHCONTRASTW { cbSize: std::mem::size_of::<HIGHCONTRASTW>() as u32, ..Default::default() };
+        // SAFETY: SPI_GETHIGHCONTRAST fills in a HIGHCONTRASTW of the given size
+        let read = unsafe {
+            SystemParametersInfoW(
+                SPI_GETHIGHCONTRAST,
+                contrast.cbSize,
+                Some(&mut contrast as *mut HIGHCONTRASTW as *mut _),
+                SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+            )
+        };
+        read.is_ok() && contrast.dwFlags.contains(HCF_HIGHCONTRASTON)
+    }
+
+    /// Windows announces these changes only as window messages, so they're
+    /// picked up when a window changes theme or gains focus instead.
+    pub fn watch(_app: &AppHandle) {}
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use gtk::prelude::*;
+    use tauri::{AppHandle, Theme};
+
+    /// GTK has no separate OS setting to read; the windows' theme is it.
+    pub fn theme() -> Option<Theme> {
+        None
+    }
+
+    pub fn accent_color() -> Option<String> {
+        None
+    }
+
+    pub fn reduced_motion() -> bool {
+        gtk::Settings::default().is_some_and(|settings| !settings.is_gtk_enable_animations())
+    }
+
+    pub fn increased_contrast() -> bool {
+        gtk::Settings::default()
+            .and_then(|settings| settings.gtk_theme_name())
+            .is_some_and(|name| name.contains("HighContrast"))
+    }
+
+    pub fn watch(app: &AppHandle) {
+        let Some(settings) = gtk::Settings::default() else { return };
+        let handle = app.clone();
+        settings.connect_gtk_enable_animations_notify(move |_| super::appearance_changed(&handle));
+        let handle = app.clone();
+        settings.connect_gtk_theme_name_notify(move |_| super::appearance_changed(&handle));
+    }
+}
+
+#[cfg(not(any(target_os = "macos", windows, target_os = "linux")))]
+mod platform {
+    use tauri::{AppHandle, Theme};
+
+    pub fn theme() -> Option<Theme> {
+        None
+    }
+
+    pub fn accent_color() -> Option<String> {
+        None
+    }
+
+    pub fn reduced_motion() -> bool {
+        false
+    }
+
+    pub fn increased_contrast() -> bool {
+        false
+    }
+
+    pub fn watch(_app: &AppHandle) {}
+}
@@ -0,0 +1,135 @@
+use serde::Deserialize;
+use tauri::AppHandle;
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TouchBarContext {
+    Editor,
+    MarkdownPreview,
+}
+
+impl TouchBarContext {
+    /// Menu item ids shown as buttons, as (id, title). Presses go through the
+    /// menu event handler, so they emit exactly what the menu items do.
+    fn items(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            TouchBarContext::Editor => &[
+                ("save_file", "Save"),
+                ("format_document", "Format"),
+                ("toggle_comment", "Toggle Comment"),
+                ("find_open", "Find"),
+            ],
+            TouchBarContext::MarkdownPreview => &[("save_file", "Save"), ("find_open", "Find")],
+        }
+    }
+}
+
+/// Switches the window's Touch Bar between the editor and preview sets. A
+/// no-op on Macs without a Touch Bar and on other platforms.
+#[tauri::command]
+pub fn set_touchbar_context(app: AppHandle, window_label: String, context: TouchBarContext) -> Result<(), String> {
+    #[cfg(target_os = "macos")]
+    return platform::set(&app, &window_label, context);
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = (app, window_label, context.items());
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub use platform::window_closed;
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    use objc2::rc::Retained;
+    use objc2::runtime::{AnyObject, NSObject, NSObjectProtocol};
+    use objc2::{define_class, msg_send, sel, DefinedClass, MainThreadMarker, MainThreadOnly};
+    use objc2_app_kit::{NSButton, NSCustomTouchBarItem, NSTouchBar, NSTouchBarItem};
+    use objc2_foundation::{NSArray, NSSet, NSString};
+    use objc2_web_kit::WKWebView;
+    use tauri::menu::{MenuEvent, MenuId};
+    use tauri::{AppHandle, Manager};
+
+    use super::TouchBarContext;
+    use crate::menu;
+
+    pub struct Ivars {
+        app: AppHandle,
+        ids: &'static [(&'static str, &'static str)],
+    }
+
+    define_class!(
+        // SAFETY: NSObject has no subclassing requirements and the class has no Drop impl
+        #[unsafe(super(NSObject))]
+        #[thread_kind = MainThreadOnly]
+        #[name = "SkrivTouchBarTarget"]
+        #[ivars = Ivars]
+        struct Target;
+
+        impl Target {
+            #[unsafe(method(performItem:))]
+            fn perform_item(&self, sender: &NSButton) {
+                let ivars = self.ivars();
+                if let Some((id, _)) = usize::try_from(sender.tag()).ok().and_then(|i| ivars.ids.get(i)) {
+                    menu::handle_event(&ivars.app, MenuEvent { id: MenuId::new(id) });
+                }
+            }
+        }
+    );
+
+    thread_local! {
+        // Buttons don't retain their target, so each window's is kept here
+        static TARGETS: RefCell<HashMap<String, Retained<Target>>> = RefCell::new(HashMap::new());
+    }
+
+    pub fn window_closed(label: &str) {
+        TARGETS.with_borrow_mut(|targets| targets.remove(label));
+    }
+
+    pub fn set(app: &AppHandle, label: &str, context: TouchBarContext) -> Result<(), String> {
+        let window = app.get_webview_window(label).ok_or_else(|| format!("No window \"{}\"", label))?;
+        let app = app.clone();
+        let label = label.to_string();
+        window
+            .with_webview(move |webview| {
+                let Some(mtm) = MainThreadMarker::new() else { return };
+                // SAFETY: Tauri hands out the live WKWebView of this webview
+                let webview = unsafe { &*(webview.inner() as *const WKWebView) };
+                // Older macOS releases have no Touch Bar API at all
+                if !webview.respondsToSelector(sel!(setTouchBar:)) {
+                    return;
+                }
+                let ids = context.items();
+                let target = Target::alloc(mtm).set_ivars(Ivars { app, ids });
+                let target: Retained<Target> = unsafe { msg_send![super(target), init] };
+
+                let target_obj: &AnyObject = &target;
+                let mut identifiers = Vec::new();
+                let mut items: Vec<Retained<NSTouchBarItem>> = Vec::new();
+                for (i, (id, title)) in ids.iter().enumerate() {
+                    let identifier = NSString::from_str(&format!("net.feryla.skriv.{}", id));
+                    // SAFETY: `performItem:` takes the sender, as actions do
+                    let button = unsafe {
+                        NSButton::buttonWithTitle_target_action(&NSString::from_str(title), Some(target_obj), Some(sel!(performItem:)), mtm)
+                    };
+                    button.setTag(i as isize);
+                    let item = NSCustomTouchBarItem::initWithIdentifier(NSCustomTouchBarItem::alloc(mtm), &identifier);
+                    item.setView(&button);
+                    items.push(Retained::into_super(item));
+                    identifiers.push(identifier);
+                }
+                let bar = NSTouchBar::new(mtm);
+                bar.setDefaultItemIdentifiers(&NSArray::from_retained_slice(&identifiers));
+                bar.setTemplateItems(&NSSet::from_retained_slice(&items));
+                // Set on the web view itself, since as first responder it would
+                // otherwise supply its own (empty) bar
+                webview.setTouchBar(Some(&bar));
+                TARGETS.with_borrow_mut(|targets| targets.insert(label, target));
+            })
+            .map_err(|e| e.to_string())
+    }
+}
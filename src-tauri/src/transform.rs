@@ -0,0 +1,273 @@
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, Engine, GeneralPurpose, GeneralPurposeConfig};
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Manager};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::i18n;
+use crate::workers::{Priority, WorkerPool};
+
+/// The items of Edit > Transform, in order.
+pub const MENU_ITEMS: [(&str, TextOp); 11] = [
+    ("transform_upper_case", TextOp::UpperCase),
+    ("transform_lower_case", TextOp::LowerCase),
+    ("transform_title_case", TextOp::TitleCase),
+    ("transform_base64_encode", TextOp::Base64Encode),
+    ("transform_base64_decode", TextOp::Base64Decode),
+    ("transform_url_encode", TextOp::UrlEncode),
+    ("transform_url_decode", TextOp::UrlDecode),
+    ("transform_hex_encode", TextOp::HexEncode),
+    ("transform_hex_decode", TextOp::HexDecode),
+    ("transform_sha256", TextOp::Sha256),
+    ("transform_md5", TextOp::Md5),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TextOp {
+    UpperCase,
+    LowerCase,
+    TitleCase,
+    Base64Encode,
+    Base64Decode,
+    UrlEncode,
+    UrlDecode,
+    HexEncode,
+    HexDecode,
+    /// The digest in lowercase hex, of the text's UTF-8 bytes
+    Sha256,
+    Md5,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TransformOutcome {
+    Transformed { text: String },
+    /// `offset` is the byte offset into the UTF-8 input of the first thing
+    /// that couldn't be decoded
+    Invalid { message: String, offset: usize },
+}
+
+struct Invalid(&'static str, usize);
+
+// Both alphabets, with or without padding, since pasted base64 comes in
+// every variety.
+const LENIENT: GeneralPurposeConfig = GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+const STANDARD: GeneralPurpose = GeneralPurpose::new(&alphabet::STANDARD, LENIENT);
+const URL_SAFE: GeneralPurpose = GeneralPurpose::new(&alphabet::URL_SAFE, LENIENT);
+
+// Tells bytes that don't form UTF-8 text where their input was.
+fn utf8(bytes: Vec<u8>, offsets: &[usize]) -> Result<String, Invalid> {
+    String::from_utf8(bytes).map_err(|e| Invalid("It decodes to bytes that aren't UTF-8 text", offsets[e.utf8_error().valid_up_to()]))
+}
+
+fn base64_decode(text: &str) -> Result<String, Invalid> {
+    // Line breaks and indentation from wrapped base64 don't count
+    let (compact, offsets): (Vec<u8>, Vec<usize>) = text.bytes().enumerate().filter(|(_, b)| !b.is_ascii_whitespace()).map(|(i, b)| (b, i)).unzip();
+    let engine = if compact.iter().any(|b| matches!(b, b'-' | b'_')) { URL_SAFE } else { STANDARD };
+    let bytes = engine.decode(&compact).map_err(|e| match e {
+        base64::DecodeError::InvalidByte(i, _) => Invalid("This isn't a base64 character", offsets[i]),
+        base64::DecodeError::InvalidLastSymbol(i, _) => Invalid("The base64 ends in a character it can't end in", offsets[i]),
+        // One character too many for whole bytes; the last group starts there
+        base64::DecodeError::InvalidLength(_) => Invalid("The base64 is cut short", offsets.get(compact.len() / 4 * 4).copied().unwrap_or(text.len())),
+        base64::DecodeError::InvalidPadding => {
+            let first = compact.iter().position(|b| *b == b'=').unwrap_or(compact.len().saturating_sub(1));
+            Invalid("The base64 padding is wrong", offsets.get(first).copied().unwrap_or(0))
+        }
+    })?;
+    // Each decoded byte comes from the base64 characters from 4k/3 on
+    let sources: Vec<usize> = (0..bytes.len()).map(|k| offsets[k * 4 / 3]).collect();
+    utf8(bytes, &sources)
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+fn url_encode(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for b in text.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+fn url_decode(text: &str) -> Result<String, Invalid> {
+    let input = text.as_bytes();
+    let (mut bytes, mut sources) = (Vec::with_capacity(input.len()), Vec::with_capacity(input.len()));
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] == b'%' {
+            let (Some(high), Some(low)) = (input.get(i + 1).copied().and_then(hex_value), input.get(i + 2).copied().and_then(hex_value)) else {
+                return Err(Invalid("'%' isn't followed by two hex digits", i));
+            };
+            bytes.push((high << 4) | low);
+            sources.push(i);
+            i += 3;
+        } else {
+            bytes.push(input[i]);
+            sources.push(i);
+            i += 1;
+        }
+    }
+    utf8(bytes, &sources)
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(text: &str) -> Result<String, Invalid> {
+    let (mut bytes, mut sources) = (Vec::new(), Vec::new());
+    let mut pending: Option<(u8, usize)> = None;
+    for (i, b) in text.bytes().enumerate().filter(|(_, b)| !b.is_ascii_whitespace()) {
+        let Some(digit) = hex_value(b) else {
+            return Err(Invalid("This isn't a hex digit", i));
+        };
+        match pending.take() {
+            Some((high, at)) => {
+                bytes.push((high << 4) | digit);
+                sources.push(at);
+            }
+            None => pending = Some((digit, i)),
+        }
+    }
+    if let Some((_, at)) = pending {
+        return Err(Invalid("This hex digit has no other half", at));
+    }
+    utf8(bytes, &sources)
+}
+
+/// Turkish and Azerbaijani pair i with İ and ı with I, which the default
+/// Unicode casing doesn't.
+fn is_turkic(locale: Option<&str>) -> bool {
+    let language = locale.and_then(|l| l.split(['-', '_']).next()).unwrap_or("");
+    language.eq_ignore_ascii_case("tr") || language.eq_ignore_ascii_case("az")
+}
+
+fn upper_case(text: &str, turkic: bool) -> String {
+    if turkic { text.replace('i', "İ").to_uppercase() } else { text.to_uppercase() }
+}
+
+fn lower_case(text: &str, turkic: bool) -> String {
+    if turkic { text.replace("I\u{307}", "i").replace('I', "ı").replace('İ', "i").to_lowercase() } else { text.to_lowercase() }
+}
+
+// Title case isn't upper case: the DŽ, LJ, NJ and DZ digraphs have forms of
+// their own, and ß or ﬁ only capitalize their first letter.
+fn title_char(c: char, turkic: bool) -> String {
+    match c {
+        '\u{1C4}'..='\u{1C6}' => '\u{1C5}'.into(),
+        '\u{1C7}'..='\u{1C9}' => '\u{1C8}'.into(),
+        '\u{1CA}'..='\u{1CC}' => '\u{1CB}'.into(),
+        '\u{1F1}'..='\u{1F3}' => '\u{1F2}'.into(),
+        'i' if turkic => 'İ'.into(),
+        _ => {
+            let upper: String = c.to_uppercase().collect();
+            let mut chars = upper.chars();
+            chars.next().map(String::from).unwrap_or_default() + &chars.as_str().to_lowercase()
+        }
+    }
+}
+
+/// Capitalizes the first letter of each word and lowercases the rest.
+fn title_case(text: &str, turkic: bool) -> String {
+    text.split_word_bounds()
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) if first.is_alphabetic() => title_char(first, turkic) + &lower_case(chars.as_str(), turkic),
+                _ => word.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// `locale` is a BCP 47 tag like `tr-TR`, for the casing that depends on
+/// the language.
+pub fn apply(content: &str, op: TextOp, locale: Option<&str>) -> TransformOutcome {
+    let turkic = is_turkic(locale);
+    let result = match op {
+        TextOp::UpperCase => Ok(upper_case(content, turkic)),
+        TextOp::LowerCase => Ok(lower_case(content, turkic)),
+        TextOp::TitleCase => Ok(title_case(content, turkic)),
+        TextOp::Base64Encode => Ok(STANDARD.encode(content)),
+        TextOp::Base64Decode => base64_decode(content),
+        TextOp::UrlEncode => Ok(url_encode(content)),
+        TextOp::UrlDecode => url_decode(content),
+        TextOp::HexEncode => Ok(hex_encode(content.as_bytes())),
+        TextOp::HexDecode => hex_decode(content),
+        TextOp::Sha256 => Ok(hex_encode(&Sha256::digest(content))),
+        TextOp::Md5 => Ok(hex_encode(&Md5::digest(content))),
+    };
+    match result {
+        Ok(text) => TransformOutcome::Transformed { text },
+        Err(Invalid(message, offset)) => TransformOutcome::Invalid { message: message.into(), offset },
+    }
+}
+
+/// Encodes, decodes, hashes or recases `content` on a worker. Casing
+/// follows the OS language unless `locale` says otherwise.
+#[tauri::command]
+pub async fn text_transform(app: AppHandle, content: String, op: TextOp, locale: Option<String>) -> Result<TransformOutcome, String> {
+    let locale = locale.or_else(|| i18n::os_locales().into_iter().next());
+    app.state::<WorkerPool>().run(Priority::Interactive, move || apply(&content, op, locale.as_deref())).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(content: &str, op: TextOp, locale: Option<&str>) -> String {
+        match apply(content, op, locale) {
+            TransformOutcome::Transformed { text } => text,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    fn offset(content: &str, op: TextOp) -> usize {
+        match apply(content, op, None) {
+            TransformOutcome::Invalid { offset, .. } => offset,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn round_trips_and_hashes() {
+        for (op, back) in [(TextOp::Base64Encode, TextOp::Base64Decode), (TextOp::UrlEncode, TextOp::UrlDecode), (TextOp::HexEncode, TextOp::HexDecode)] {
+            assert_eq!(text(&text("héllo wörld/?&", op, None), back, None), "héllo wörld/?&");
+        }
+        assert_eq!(text("a b+é", TextOp::UrlEncode, None), "a%20b%2B%C3%A9");
+        assert_eq!(text("aGk_\n  fg", TextOp::Base64Decode, None), "hi?~");
+        assert_eq!(text("abc", TextOp::Sha256, None), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(text("abc", TextOp::Md5, None), "900150983cd24fb0d6963f7d28e17f72");
+    }
+
+    #[test]
+    fn points_at_the_first_invalid_input() {
+        assert_eq!(offset("aGVs\nb*8=", TextOp::Base64Decode), 6);
+        assert_eq!(offset("aGVsb", TextOp::Base64Decode), 4);
+        assert_eq!(offset("ok%2G", TextOp::UrlDecode), 2);
+        assert_eq!(offset("a%C3%28", TextOp::UrlDecode), 1);
+        assert_eq!(offset("68 6x", TextOp::HexDecode), 4);
+        assert_eq!(offset("68 6", TextOp::HexDecode), 3);
+        assert_eq!(offset("41ff", TextOp::HexDecode), 2);
+    }
+
+    #[test]
+    fn cases_by_unicode_and_locale() {
+        assert_eq!(text("straße", TextOp::UpperCase, None), "STRASSE");
+        assert_eq!(text("İstanbul", TextOp::LowerCase, None), "i\u{307}stanbul");
+        assert_eq!(text("istanbul DIŞ", TextOp::UpperCase, Some("tr-TR")), "İSTANBUL DIŞ");
+        assert_eq!(text("İSTANBUL DIŞ", TextOp::LowerCase, Some("tr")), "istanbul dış");
+        assert_eq!(text("ΟΔΟΣ", TextOp::LowerCase, None), "οδο\u{3c2}");
+        assert_eq!(text("the ǆungla's ßig iPHONE", TextOp::TitleCase, None), "The ǅungla's Ssig Iphone");
+        assert_eq!(text("izmir ıLIK", TextOp::TitleCase, Some("az")), "İzmir Ilık");
+    }
+}
@@ -0,0 +1,328 @@
+//! Moving files to the trash, and putting them back. What's trashed is kept
+//! in batches, one per `trash_paths`, with where each item went when the
+//! platform says: the resulting URL on macOS, the file and its info file
+//! in the FreeDesktop trash. The batches are only kept in memory, so undo
+//! never reaches past the session that trashed them. Windows' Recycle Bin
+//! has no API for putting one item back, so there restoring isn't offered.
+
+use std::collections::VecDeque;
+#[cfg(any(target_os = "macos", windows))]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::paths;
+
+/// Batches kept for undo; older ones are forgotten, and stay in the trash.
+const MAX_BATCHES: usize = 20;
+
+struct Item {
+    original: PathBuf,
+    /// Where the item is in the trash, when the platform says
+    location: Option<PathBuf>,
+    /// The FreeDesktop `.trashinfo` file describing it
+    #[cfg_attr(any(target_os = "macos", windows), allow(dead_code))]
+    info: Option<PathBuf>,
+}
+
+struct Batch {
+    id: u64,
+    items: Vec<Item>,
+}
+
+#[derive(Default)]
+pub struct Trash(Mutex<TrashStack>);
+
+#[derive(Default)]
+struct TrashStack {
+    batches: VecDeque<Batch>,
+    next_id: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Failure {
+    path: String,
+    error: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Trashed {
+    /// For `restore_trashed`; `None` when nothing was trashed
+    id: Option<u64>,
+    failed: Vec<Failure>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "status", content = "error", rename_all = "camelCase")]
+pub enum RestoreStatus {
+    Restored,
+    /// Something new is at the original path
+    Conflict,
+    /// Emptied from the trash, or put back another way
+    NotInTrash,
+    /// The platform doesn't say where the item went
+    Unsupported,
+    Failed(String),
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Restore {
+    path: String,
+    #[serde(flatten)]
+    status: RestoreStatus,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FsCreated {
+    path: String,
+}
+
+#[cfg(target_os = "macos")]
+fn trash_item(path: &Path) -> Result<Item, String> {
+    use objc2_foundation::{NSFileManager, NSURL};
+    let url = NSURL::from_file_path(path).ok_or("Invalid path")?;
+    let mut resulting = None;
+    NSFileManager::defaultManager()
+        .trashItemAtURL_resultingItemURL_error(&url, Some(&mut resulting))
+        .map_err(|e| e.localizedDescription().to_string())?;
+    let location = resulting.and_then(|url| url.to_file_path());
+    Ok(Item { original: path.to_path_buf(), location, info: None })
+}
+
+#[cfg(windows)]
+fn trash_item(path: &Path) -> Result<Item, String> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows::core::PCWSTR;
+    use windows::Win32::UI::Shell::{
+        SHFileOperationW, FOF_ALLOWUNDO, FOF_NOCONFIRMATION, FOF_NOERRORUI, FOF_SILENT, FOF_WANTNUKEWARNING, FO_DELETE, SHFILEOPSTRUCTW,
+    };
+    // A list of paths, so two nulls at the end
+    let from: Vec<u16> = path.as_os_str().encode_wide().chain([0, 0]).collect();
+    let flags = FOF_ALLOWUNDO | FOF_NOCONFIRMATION | FOF_NOERRORUI | FOF_SILENT | FOF_WANTNUKEWARNING;
+    let mut operation = SHFILEOPSTRUCTW { wFunc: FO_DELETE, pFrom: PCWSTR(from.as_ptr()), fFlags: flags.0 as u16, ..Default::default() };
+    let code = unsafe { SHFileOperationW(&mut operation) };
+    if code != 0 || operation.fAnyOperationsAborted.as_bool() {
+        return Err(format!("Couldn't move to the Recycle Bin (error {})", code));
+    }
+    Ok(Item { original: path.to_path_buf(), location: None, info: None })
+}
+
+/// The FreeDesktop trash: the item goes to `files/` and an info file with
+/// its original path to `info/`, in the home trash or, on another device,
+/// the `.Trash-$uid` at the top of that device.
+#[cfg(all(unix, not(target_os = "macos")))]
+mod freedesktop {
+    use std::fs::OpenOptions;
+    use std::io::{ErrorKind, Write};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+    use std::path::{Path, PathBuf};
+
+    use super::Item;
+
+    fn device(path: &Path) -> Option<u64> {
+        std::fs::symlink_metadata(path).ok().map(|meta| meta.dev())
+    }
+
+    /// The trash for `path` and the folder its info paths are relative to,
+    /// if any.
+    fn trash_for(path: &Path) -> Result<(PathBuf, Option<PathBuf>), String> {
+        let home = dirs::data_dir().ok_or("No home folder")?.join("Trash");
+        let _ = std::fs::DirBuilder::new().recursive(true).mode(0o700).create(&home);
+        let dev = device(path).ok_or("Not found")?;
+        if device(&home) == Some(dev) {
+            return Ok((home, None));
+        }
+        let mut top = path;
+        while let Some(parent) = top.parent().filter(|parent| device(parent) == Some(dev)) {
+            top = parent;
+        }
+        let uid = unsafe { libc::getuid() };
+        Ok((top.join(format!(".Trash-{}", uid)), Some(top.to_path_buf())))
+    }
+
+    /// Percent-encodes all but unreserved characters and slashes, as info
+    /// files want.
+    fn encode(path: &Path) -> String {
+        let mut encoded = String::new();
+        for &byte in path.as_os_str().as_bytes() {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => encoded.push(byte as char),
+                _ => encoded.push_str(&format!("%{:02X}", byte)),
+            }
+        }
+        encoded
+    }
+
+    pub(super) fn trash_item(path: &Path) -> Result<Item, String> {
+        if !path.is_absolute() {
+            return Err("Not an absolute path".into());
+        }
+        let path = path.to_path_buf();
+        let (trash, top) = trash_for(&path)?;
+        let (files, info) = (trash.join("files"), trash.join("info"));
+        for dir in [&files, &info] {
+            std::fs::DirBuilder::new().recursive(true).mode(0o700).create(dir).map_err(|e| e.to_string())?;
+        }
+        let name = path.file_name().ok_or("Can't trash a filesystem root")?.to_string_lossy().into_owned();
+        let original = match &top {
+            Some(top) => path.strip_prefix(top).unwrap_or(&path),
+            None => &path,
+        };
+        let content = format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encode(original),
+            chrono::Local::now().format("%Y-%m-%dT%H:%M:%S")
+        );
+        // The info file is made first, so that its name is claimed
+        for n in 1.. {
+            let trashed = if n == 1 { name.clone() } else { format!("{}.{}", name, n) };
+            let info_path = info.join(format!("{}.trashinfo", trashed));
+            let mut file = match OpenOptions::new().write(true).create_new(true).open(&info_path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.to_string()),
+            };
+            let location = files.join(&trashed);
+            if location.symlink_metadata().is_ok() {
+                drop(file);
+                let _ = std::fs::remove_file(&info_path);
+                continue;
+            }
+            let moved = file.write_all(content.as_bytes()).map_err(|e| e.to_string()).and_then(|_| {
+                std::fs::rename(&path, &location).map_err(|e| e.to_string())
+            });
+            if let Err(e) = moved {
+                let _ = std::fs::remove_file(&info_path);
+                return Err(e);
+            }
+            return Ok(Item { original: path, location: Some(location), info: Some(info_path) });
+        }
+        unreachable!()
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+use freedesktop::trash_item;
+
+/// Puts one item back where it was.
+fn restore_item(item: &Item) -> RestoreStatus {
+    let Some(location) = &item.location else { return RestoreStatus::Unsupported };
+    if location.symlink_metadata().is_err() {
+        return RestoreStatus::NotInTrash;
+    }
+    #[cfg(all(unix, not(target_os = "macos")))]
+    if item.info.as_ref().is_some_and(|info| !info.exists()) {
+        // Emptied and something else trashed under the same name since
+        return RestoreStatus::NotInTrash;
+    }
+    if item.original.symlink_metadata().is_ok() {
+        return RestoreStatus::Conflict;
+    }
+    if let Some(parent) = item.original.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    if let Err(e) = std::fs::rename(location, &item.original) {
+        return RestoreStatus::Failed(e.to_string());
+    }
+    #[cfg(all(unix, not(target_os = "macos")))]
+    if let Some(info) = &item.info {
+        let _ = std::fs::remove_file(info);
+    }
+    RestoreStatus::Restored
+}
+
+/// Moves `paths` to the trash as one batch for undo.
+#[tauri::command]
+pub async fn trash_paths(app: AppHandle, paths: Vec<String>) -> Result<Trashed, String> {
+    let (items, failed) = tauri::async_runtime::spawn_blocking(move || {
+        let mut items = Vec::new();
+        let mut failed = Vec::new();
+        for path in paths {
+            match trash_item(&paths::decode(&path)) {
+                Ok(item) => items.push(item),
+                Err(error) => failed.push(Failure { path, error }),
+            }
+        }
+        (items, failed)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    if items.is_empty() {
+        return Ok(Trashed { id: None, failed });
+    }
+    let trash = app.state::<Trash>();
+    let mut stack = trash.0.lock().unwrap();
+    stack.next_id += 1;
+    let id = stack.next_id;
+    stack.batches.push_back(Batch { id, items });
+    if stack.batches.len() > MAX_BATCHES {
+        stack.batches.pop_front();
+    }
+    Ok(Trashed { id: Some(id), failed })
+}
+
+/// Puts a batch back, saying for each item how it went. Items that
+/// conflicted or failed stay in the batch, to try again once the way is
+/// clear.
+#[tauri::command]
+pub async fn restore_trashed(app: AppHandle, batch_id: u64) -> Result<Vec<Restore>, String> {
+    let batch = {
+        let trash = app.state::<Trash>();
+        let mut stack = trash.0.lock().unwrap();
+        let index = stack.batches.iter().position(|batch| batch.id == batch_id).ok_or("Nothing to restore")?;
+        stack.batches.remove(index).unwrap()
+    };
+    let (restores, left) = tauri::async_runtime::spawn_blocking(move || {
+        let mut restores = Vec::new();
+        let mut left = Vec::new();
+        for item in batch.items {
+            let status = restore_item(&item);
+            restores.push(Restore { path: paths::encode(&item.original), status: status.clone() });
+            if matches!(status, RestoreStatus::Conflict | RestoreStatus::Failed(_)) {
+                left.push(item);
+            }
+        }
+        (restores, left)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    if !left.is_empty() {
+        app.state::<Trash>().0.lock().unwrap().batches.push_back(Batch { id: batch_id, items: left });
+    }
+    for restore in restores.iter().filter(|restore| restore.status == RestoreStatus::Restored) {
+        let _ = app.emit("fs-created", FsCreated { path: restore.path.clone() });
+    }
+    Ok(restores)
+}
+
+#[cfg(all(test, unix, not(target_os = "macos")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restores_from_the_freedesktop_trash() {
+        let dir = std::env::temp_dir().join(format!("skriv-trash-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a b.txt");
+        std::fs::write(&path, "x").unwrap();
+        let item = trash_item(&path).unwrap();
+        assert!(!path.exists());
+        let info = std::fs::read_to_string(item.info.as_ref().unwrap()).unwrap();
+        assert!(info.contains("a%20b.txt\n"), "{}", info);
+        std::fs::write(&path, "new").unwrap();
+        assert_eq!(restore_item(&item), RestoreStatus::Conflict);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restore_item(&item), RestoreStatus::Restored);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "x");
+        assert_eq!(restore_item(&item), RestoreStatus::NotInTrash);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
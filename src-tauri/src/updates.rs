@@ -0,0 +1,594 @@
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Emitter, Manager, Url};
+use tauri_plugin_updater::{Update, UpdaterExt};
+
+use crate::markdown;
+use crate::network;
+use crate::notifications;
+use crate::power;
+use crate::settings::{self, Settings};
+use crate::window::{self, WindowKind};
+
+// Every channel but stable, which keeps the endpoint in tauri.conf.json, is
+// published as a rolling release tagged with its name
+const CHANNEL_MANIFEST: &str = "https://github.com/Feryla/skriv/releases/download/{channel}/latest.json";
+const SIZE_TIMEOUT: Duration = Duration::from_secs(10);
+// Release notes the manifest left out come from the release itself
+const RELEASE_API: &str = "https://api.github.com/repos/Feryla/skriv/releases/tags/v{version}";
+const NOTES_TIMEOUT: Duration = Duration::from_secs(5);
+// How long quitting waits for the windows to write their session
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(3);
+// Progress events are spaced out this much at most
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateChannel {
+    #[default]
+    Stable,
+    Beta,
+}
+
+impl UpdateChannel {
+    fn name(self) -> &'static str {
+        match self {
+            UpdateChannel::Stable => "stable",
+            UpdateChannel::Beta => "beta",
+        }
+    }
+
+    fn endpoint(self) -> Option<Url> {
+        match self {
+            UpdateChannel::Stable => None,
+            channel => Url::parse(&CHANNEL_MANIFEST.replace("{channel}", channel.name())).ok(),
+        }
+    }
+}
+
+/// Where the update is in check, download, install. Each step only runs
+/// from the one before it.
+#[derive(Default)]
+enum Stage {
+    #[default]
+    Idle,
+    Available(Box<Update>, Option<u64>),
+    Downloading(Box<Update>, Option<u64>, JoinHandle<()>),
+    Downloaded(Box<Update>, Vec<u8>),
+    Installing,
+}
+
+#[derive(Default)]
+pub struct UpdateState {
+    stage: Mutex<Stage>,
+    /// Rendered notes by version, None where the release has none
+    notes: Mutex<HashMap<String, Option<String>>>,
+    /// Takes the windows' acks while quitting waits for their session
+    flushes: Mutex<Option<mpsc::Sender<()>>>,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StageName {
+    None,
+    Available,
+    Downloading,
+    Downloaded,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateInfo {
+    version: String,
+    current_version: String,
+    notes: Option<String>,
+    /// The notes as sanitized HTML, None when there are none to be had
+    notes_html: Option<String>,
+    date: Option<String>,
+    /// Bytes to download, when the server says
+    size: Option<u64>,
+}
+
+impl UpdateInfo {
+    fn new(app: &AppHandle, update: &Update, size: Option<u64>) -> Self {
+        let notes_html = app.state::<UpdateState>().notes.lock().unwrap().get(&update.version).cloned().flatten();
+        UpdateInfo {
+            version: update.version.clone(),
+            current_version: update.current_version.clone(),
+            notes: update.body.clone().filter(|body| !body.trim().is_empty()),
+            notes_html,
+            date: update.raw_json.get("pub_date").and_then(|d| d.as_str()).map(str::to_string),
+            size,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateCheck {
+    channel: UpdateChannel,
+    stage: StageName,
+    update: Option<UpdateInfo>,
+    /// The channel's release when it's older than this build, as after going
+    /// from beta back to stable. Only a reinstall gets there.
+    older_release: Option<String>,
+    install_on_quit: bool,
+    /// A release that failed to install when skriv last quit
+    failed_install: Option<String>,
+}
+
+/// What keeps the launch check from offering an update.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdatePromptState {
+    skipped_version: Option<String>,
+    /// Unix seconds, only while still in the future
+    snoozed_until: Option<u64>,
+}
+
+#[derive(Clone, Serialize)]
+struct Progress {
+    received: u64,
+    total: Option<u64>,
+}
+
+#[derive(Clone, Serialize)]
+struct DownloadDone {
+    version: String,
+    error: Option<String>,
+}
+
+async fn download_size(app: &AppHandle, url: &Url) -> Option<u64> {
+    let client = network::client(app, SIZE_TIMEOUT).ok()?;
+    let response = client.head(url.clone()).send().await.ok()?.error_for_status().ok()?;
+    response.headers().get(reqwest::header::CONTENT_LENGTH)?.to_str().ok()?.parse().ok()
+}
+
+/// The release's body on GitHub. Err when GitHub couldn't be asked, which
+/// isn't remembered, unlike a release that has no notes.
+async fn fetch_release_body(app: &AppHandle, version: &str) -> Result<Option<String>, String> {
+    let client = network::client(app, NOTES_TIMEOUT)?;
+    let response = client
+        .get(RELEASE_API.replace("{version}", version))
+        .header(reqwest::header::USER_AGENT, "skriv")
+        .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let bytes = response.error_for_status().map_err(|e| e.to_string())?.bytes().await.map_err(|e| e.to_string())?;
+    let release: serde_json::Value = serde_json::from_slice(&bytes).map_err(|e| e.to_string())?;
+    Ok(release.get("body").and_then(|b| b.as_str()).filter(|b| !b.trim().is_empty()).map(str::to_string))
+}
+
+/// Renders the update's notes once per version, from the manifest or else
+/// from the GitHub release.
+async fn load_notes(app: &AppHandle, update: &Update) {
+    let state = app.state::<UpdateState>();
+    if state.notes.lock().unwrap().contains_key(&update.version) {
+        return;
+    }
+    let body = match update.body.clone().filter(|body| !body.trim().is_empty()) {
+        Some(body) => Some(body),
+        None => match fetch_release_body(app, &update.version).await {
+            Ok(body) => body,
+            Err(e) => {
+                log::warn!("Failed to fetch the release notes for {}: {}", update.version, e);
+                return;
+            }
+        },
+    };
+    let html = body.map(|body| markdown::notes_html(&body));
+    state.notes.lock().unwrap().insert(update.version.clone(), html);
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Whether the launch check keeps quiet about `version`.
+fn prompt_suppressed(settings: &Settings, version: Option<&str>, now: u64) -> bool {
+    settings.updates_snoozed_until.is_some_and(|until| until > now)
+        || version.is_some_and(|version| settings.skipped_update.as_deref() == Some(version))
+}
+
+/// Checks the channel chosen in settings for a newer release. While one is
+/// downloading or downloaded, that's what's reported instead. A new one is
+/// also sent to every window as `update-available`, unless this is the
+/// launch check and the user skipped that version or snoozed updates.
+#[tauri::command]
+pub async fn check_for_update(app: AppHandle, manual: bool) -> Result<UpdateCheck, String> {
+    let failed_install = if manual { None } else { take_interrupted_update(&app)? };
+    let settings = app.state::<Mutex<Settings>>().lock().unwrap().clone();
+    let channel = settings.update_channel;
+    let install_on_quit = settings.install_updates_on_quit;
+    // A failed install is offered again whatever was skipped or snoozed
+    let quiet = !manual && failed_install.is_none();
+    let check = |stage, update, older_release| UpdateCheck {
+        channel,
+        stage,
+        update,
+        older_release,
+        install_on_quit,
+        failed_install: failed_install.clone(),
+    };
+    if quiet && prompt_suppressed(&settings, None, now()) {
+        return Ok(check(StageName::None, None, None));
+    }
+    {
+        let state = app.state::<UpdateState>();
+        let stage = state.stage.lock().unwrap();
+        let current = match &*stage {
+            Stage::Downloading(update, size, _) => Some((StageName::Downloading, UpdateInfo::new(&app, update, *size))),
+            Stage::Downloaded(update, bytes) => Some((StageName::Downloaded, UpdateInfo::new(&app, update, Some(bytes.len() as u64)))),
+            Stage::Installing => return Err("The update is being installed".into()),
+            _ => None,
+        };
+        if let Some((stage, info)) = current {
+            return Ok(check(stage, Some(info), None));
+        }
+    }
+    let older = Arc::new(Mutex::new(None));
+    let seen = older.clone();
+    let route = network::route(&app);
+    // The update keeps the client setup for its download
+    let mut builder = app
+        .updater_builder()
+        .configure_client(move |client| network::configure(client, &route))
+        .version_comparator(move |current, release| {
+            if release.version < current {
+                *seen.lock().unwrap() = Some(release.version.to_string());
+            }
+            release.version > current
+        });
+    if let Some(endpoint) = channel.endpoint() {
+        builder = builder.endpoints(vec![endpoint]).map_err(|e| e.to_string())?;
+    }
+    log::info!("Checking for updates on the {} channel", channel.name());
+    let update = builder.build().map_err(|e| e.to_string())?.check().await.map_err(|e| {
+        log::warn!("Update check failed: {}", e);
+        e.to_string()
+    })?;
+    let older_release = older.lock().unwrap().take();
+    let Some(update) = update else {
+        log::info!("No newer release");
+        *app.state::<UpdateState>().stage.lock().unwrap() = Stage::Idle;
+        return Ok(check(StageName::None, None, older_release));
+    };
+    log::info!("Found skriv {}", update.version);
+    let size = download_size(&app, &update.download_url).await;
+    load_notes(&app, &update).await;
+    let info = UpdateInfo::new(&app, &update, size);
+    let state = app.state::<UpdateState>();
+    let mut stage = state.stage.lock().unwrap();
+    // A download started from another window while this one was checking wins
+    if matches!(*stage, Stage::Idle | Stage::Available(..)) {
+        *stage = Stage::Available(Box::new(update), size);
+    }
+    drop(stage);
+    // Still kept as available, so checking by hand can offer it right away
+    if quiet && prompt_suppressed(&settings, Some(&info.version), now()) {
+        return Ok(check(StageName::None, None, older_release));
+    }
+    let _ = app.emit("update-available", info.clone());
+    Ok(check(StageName::Available, Some(info), older_release))
+}
+
+fn download_finished(app: &AppHandle, label: &str, result: Result<Vec<u8>, String>) {
+    let state = app.state::<UpdateState>();
+    let mut stage = state.stage.lock().unwrap();
+    let Stage::Downloading(update, size, _) = std::mem::take(&mut *stage) else { return };
+    let version = update.version.clone();
+    let error = match result {
+        Ok(bytes) => {
+            log::info!("Downloaded skriv {}: {} bytes", version, bytes.len());
+            *stage = Stage::Downloaded(update, bytes);
+            None
+        }
+        Err(e) => {
+            log::warn!("Downloading skriv {} failed: {}", version, e);
+            // Nothing is kept of a failed or corrupt download; it can start over
+            *stage = Stage::Available(update, size);
+            Some(e)
+        }
+    };
+    drop(stage);
+    if error.is_none() {
+        let body = if app.state::<Mutex<Settings>>().lock().unwrap().install_updates_on_quit {
+            format!("skriv {} will be installed when you quit", version)
+        } else {
+            format!("skriv {} is ready to install", version)
+        };
+        notifications::completed(app, label, "Update downloaded", &body);
+    }
+    let _ = app.emit("update-download-done", DownloadDone { version, error });
+}
+
+/// Starts downloading the checked update, streaming `update-download-progress`
+/// and ending with `update-download-done`. The signature is verified before
+/// the download counts as finished.
+#[tauri::command]
+pub fn download_update(app: AppHandle, window: tauri::Window) -> Result<(), String> {
+    let state = app.state::<UpdateState>();
+    let mut stage = state.stage.lock().unwrap();
+    let (update, size) = match std::mem::take(&mut *stage) {
+        Stage::Available(update, size) => (update, size),
+        other => {
+            let error = match &other {
+                Stage::Idle => "There's no update to download",
+                Stage::Downloading(..) => "The update is already downloading",
+                Stage::Downloaded(..) => "The update is already downloaded",
+                _ => "The update is being installed",
+            };
+            *stage = other;
+            return Err(error.into());
+        }
+    };
+    log::info!("Downloading skriv {}", update.version);
+    let (handle, label, download) = (app.clone(), window.label().to_string(), update.clone());
+    let task = tauri::async_runtime::spawn(async move {
+        let _awake = power::hold(&handle, "Downloading an update");
+        let mut received = 0;
+        let mut last: Option<Instant> = None;
+        let result = download
+            .download(
+                |chunk, total| {
+                    received += chunk as u64;
+                    if last.map_or(true, |t| t.elapsed() >= PROGRESS_INTERVAL) || total == Some(received) {
+                        last = Some(Instant::now());
+                        let _ = handle.emit("update-download-progress", Progress { received, total });
+                    }
+                },
+                || {},
+            )
+            .await
+            .map_err(|e| e.to_string());
+        download_finished(&handle, &label, result);
+    });
+    *stage = Stage::Downloading(update, size, task);
+    Ok(())
+}
+
+/// Stops a download in progress. Returns false if there was none.
+#[tauri::command]
+pub fn cancel_update_download(app: AppHandle) -> bool {
+    let state = app.state::<UpdateState>();
+    let mut stage = state.stage.lock().unwrap();
+    match std::mem::take(&mut *stage) {
+        Stage::Downloading(update, size, task) => {
+            task.abort();
+            log::info!("Cancelled downloading skriv {}", update.version);
+            *stage = Stage::Available(update, size);
+            true
+        }
+        other => {
+            *stage = other;
+            false
+        }
+    }
+}
+
+/// Installs the downloaded update and relaunches, as the process plugin's
+/// restart does. Only runs once the download has finished.
+#[tauri::command]
+pub fn install_and_restart(app: AppHandle) -> Result<(), String> {
+    let (update, bytes) = {
+        let state = app.state::<UpdateState>();
+        let mut stage = state.stage.lock().unwrap();
+        match std::mem::take(&mut *stage) {
+            Stage::Downloaded(update, bytes) => {
+                *stage = Stage::Installing;
+                (update, bytes)
+            }
+            other => {
+                let error = match &other {
+                    Stage::Downloading(..) => "The update hasn't finished downloading",
+                    Stage::Installing => "The update is already being installed",
+                    _ => "Download the update first",
+                };
+                *stage = other;
+                return Err(error.into());
+            }
+        }
+    };
+    log::info!("Installing skriv {}", update.version);
+    if let Err(e) = update.install(&bytes) {
+        log::warn!("Installing skriv {} failed: {}", update.version, e);
+        *app.state::<UpdateState>().stage.lock().unwrap() = Stage::Available(update, None);
+        return Err(e.to_string());
+    }
+    log::info!("Installed; restarting");
+    app.restart()
+}
+
+#[tauri::command]
+pub fn get_update_channel(settings: tauri::State<'_, Mutex<Settings>>) -> UpdateChannel {
+    settings.lock().unwrap().update_channel
+}
+
+/// Switching drops an update found on the old channel, unless it's already
+/// downloading or downloaded.
+#[tauri::command]
+pub fn set_update_channel(app: AppHandle, channel: UpdateChannel) -> Result<(), String> {
+    let state = app.state::<UpdateState>();
+    let mut stage = state.stage.lock().unwrap();
+    if matches!(*stage, Stage::Available(..)) {
+        *stage = Stage::Idle;
+    }
+    drop(stage);
+    update_settings(&app, |settings| settings.update_channel = channel)
+}
+
+/// The release that was being installed when skriv last quit, if that
+/// didn't get as far as this launch running it. Cleared either way.
+fn take_interrupted_update(app: &AppHandle) -> Result<Option<String>, String> {
+    let Some(version) = app.state::<Mutex<Settings>>().lock().unwrap().interrupted_update.clone() else { return Ok(None) };
+    update_settings(app, |settings| settings.interrupted_update = None)?;
+    let running = app.package_info().version.to_string();
+    Ok(Some(version).filter(|version| *version != running))
+}
+
+fn deferred_update(app: &AppHandle) -> bool {
+    app.state::<Mutex<Settings>>().lock().unwrap().install_updates_on_quit
+        && matches!(*app.state::<UpdateState>().stage.lock().unwrap(), Stage::Downloaded(..))
+}
+
+/// Applies a downloaded update as skriv quits. The updater puts the old app
+/// back if replacing it fails; the marker set first makes the next launch
+/// offer the update again, even after a crash partway.
+fn install_deferred(app: &AppHandle) {
+    let state = app.state::<UpdateState>();
+    let (update, bytes) = {
+        let mut stage = state.stage.lock().unwrap();
+        match std::mem::take(&mut *stage) {
+            Stage::Downloaded(update, bytes) => {
+                *stage = Stage::Installing;
+                (update, bytes)
+            }
+            other => {
+                *stage = other;
+                return;
+            }
+        }
+    };
+    let version = update.version.clone();
+    if let Err(e) = update_settings(app, |settings| settings.interrupted_update = Some(version.clone())) {
+        // Without the marker a failure would go unnoticed, so it waits for next time
+        log::warn!("Not installing the update on quit: {}", e);
+        *state.stage.lock().unwrap() = Stage::Idle;
+        return;
+    }
+    // On Windows the installer takes over and this doesn't return
+    match update.install(&bytes) {
+        Ok(()) => {
+            let _ = update_settings(app, |settings| settings.interrupted_update = None);
+        }
+        Err(e) => log::warn!("Failed to install skriv {} on quit: {}", version, e),
+    }
+    *state.stage.lock().unwrap() = Stage::Idle;
+}
+
+/// Called for every exit request. With a deferred update and editor windows
+/// still open, the exit waits for them to write their session before the
+/// app is replaced, and is requested again after; returns true for that.
+/// Once the last window has closed its session is already saved.
+pub fn exit_requested(app: &AppHandle, code: Option<i32>) -> bool {
+    if !deferred_update(app) {
+        return false;
+    }
+    let state = app.state::<UpdateState>();
+    let mut flushes = state.flushes.lock().unwrap();
+    if flushes.is_some() {
+        return true;
+    }
+    let editors: Vec<_> = app
+        .webview_windows()
+        .into_iter()
+        .filter(|(label, _)| window::kind(app, label) == WindowKind::Editor)
+        .map(|(_, window)| window)
+        .collect();
+    if editors.is_empty() {
+        drop(flushes);
+        install_deferred(app);
+        return false;
+    }
+    let (sender, flushed) = mpsc::channel();
+    *flushes = Some(sender);
+    drop(flushes);
+    for editor in &editors {
+        let _ = editor.emit("flush-session", ());
+    }
+    let (handle, count) = (app.clone(), editors.len());
+    std::thread::spawn(move || {
+        let deadline = Instant::now() + FLUSH_TIMEOUT;
+        for _ in 0..count {
+            if flushed.recv_timeout(deadline.saturating_duration_since(Instant::now())).is_err() {
+                log::warn!("Not every window saved its session before quitting");
+                break;
+            }
+        }
+        *handle.state::<UpdateState>().flushes.lock().unwrap() = None;
+        install_deferred(&handle);
+        handle.exit(code.unwrap_or(0));
+    });
+    true
+}
+
+/// The last chance, for quitting from the macOS app menu, which ends the run
+/// loop without an exit request. The windows can't be asked to save by now,
+/// so their session is as of the last autosave.
+pub fn exiting(app: &AppHandle) {
+    if deferred_update(app) {
+        install_deferred(app);
+    }
+}
+
+/// A window's answer to `flush-session`.
+#[tauri::command]
+pub fn session_flushed(state: tauri::State<'_, UpdateState>) {
+    if let Some(flushes) = &*state.flushes.lock().unwrap() {
+        let _ = flushes.send(());
+    }
+}
+
+#[tauri::command]
+pub fn set_install_updates_on_quit(app: AppHandle, enabled: bool) -> Result<(), String> {
+    update_settings(&app, |settings| settings.install_updates_on_quit = enabled)
+}
+
+fn update_settings(app: &AppHandle, change: impl FnOnce(&mut Settings)) -> Result<(), String> {
+    settings::modify(app, change).map(|_| ())
+}
+
+/// Stops the launch check offering exactly this release. A newer one is
+/// offered again.
+#[tauri::command]
+pub fn skip_version(app: AppHandle, version: String) -> Result<(), String> {
+    update_settings(&app, |settings| settings.skipped_update = Some(version))
+}
+
+/// Keeps the launch check quiet for `hours`, whatever it finds.
+#[tauri::command]
+pub fn snooze_updates(app: AppHandle, hours: u64) -> Result<(), String> {
+    let until = now().saturating_add(hours.saturating_mul(3600));
+    update_settings(&app, |settings| settings.updates_snoozed_until = Some(until))
+}
+
+#[tauri::command]
+pub fn get_update_prompt_state(settings: tauri::State<'_, Mutex<Settings>>) -> UpdatePromptState {
+    let settings = settings.lock().unwrap();
+    UpdatePromptState {
+        skipped_version: settings.skipped_update.clone(),
+        snoozed_until: settings.updates_snoozed_until.filter(|until| *until > now()),
+    }
+}
+
+#[tauri::command]
+pub fn clear_update_prompt_state(app: AppHandle) -> Result<(), String> {
+    update_settings(&app, |settings| {
+        settings.skipped_update = None;
+        settings.updates_snoozed_until = None;
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suppresses_skipped_and_snoozed() {
+        let mut settings = Settings { skipped_update: Some("1.2.0".into()), ..Default::default() };
+        assert!(prompt_suppressed(&settings, Some("1.2.0"), 100));
+        assert!(!prompt_suppressed(&settings, Some("1.3.0"), 100));
+        assert!(!prompt_suppressed(&settings, None, 100));
+        settings.updates_snoozed_until = Some(200);
+        assert!(prompt_suppressed(&settings, Some("1.3.0"), 100));
+        assert!(!prompt_suppressed(&settings, Some("1.3.0"), 200));
+    }
+}
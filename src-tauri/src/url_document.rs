@@ -0,0 +1,143 @@
+//! http(s) URLs opened like files, from the command line or the clipboard.
+//! What comes back is read-only text that has no path, so saving it means
+//! Save As to somewhere local.
+
+use std::time::Duration;
+
+use reqwest::header::{CONTENT_TYPE, USER_AGENT};
+use serde::Serialize;
+use tauri::{AppHandle, Url};
+
+use crate::document::{self, LineEnding};
+use crate::{git, network};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Beyond this the download stops; it's a document, not a file transfer.
+const MAX_BYTES: usize = 32 * 1024 * 1024;
+const MAX_REDIRECTS: usize = 5;
+
+/// Why a URL didn't open, by kind, for the window to say so specifically.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum FetchError {
+    InvalidUrl { message: String },
+    /// The host name didn't resolve
+    Dns { host: String },
+    Timeout,
+    Connect { message: String },
+    TooManyRedirects,
+    Status { status: u16, reason: Option<String> },
+    TooLarge { limit: usize },
+    Binary { content_type: Option<String> },
+    Other { message: String },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UrlDocument {
+    /// Where the content came from, after redirects
+    url: String,
+    content_type: Option<String>,
+    content: String,
+    encoding: String,
+    line_ending: LineEnding,
+    had_errors: bool,
+    read_only: bool,
+}
+
+/// Only web URLs open; anything else is for the file system to handle.
+fn parse(url: &str) -> Result<Url, FetchError> {
+    let parsed = Url::parse(url.trim()).map_err(|e| FetchError::InvalidUrl { message: e.to_string() })?;
+    match parsed.scheme() {
+        "http" | "https" if parsed.host_str().is_some() => Ok(parsed),
+        _ => Err(FetchError::InvalidUrl { message: format!("{} isn't an http(s) URL", url) }),
+    }
+}
+
+/// The `charset` parameter of a Content-Type.
+fn charset(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim().eq_ignore_ascii_case("charset").then(|| value.trim().trim_matches('"'))
+    })
+}
+
+fn classify(error: reqwest::Error, url: &Url) -> FetchError {
+    let message = network::describe(&error);
+    if error.is_timeout() {
+        FetchError::Timeout
+    } else if error.is_redirect() {
+        FetchError::TooManyRedirects
+    } else if error.is_connect() && message.contains("dns error") {
+        FetchError::Dns { host: url.host_str().unwrap_or_default().to_string() }
+    } else if error.is_connect() {
+        FetchError::Connect { message }
+    } else {
+        FetchError::Other { message }
+    }
+}
+
+/// The text in `bytes`, unless they're binary.
+fn decode(url: String, content_type: Option<String>, bytes: &[u8]) -> Result<UrlDocument, FetchError> {
+    if git::is_binary(bytes) {
+        return Err(FetchError::Binary { content_type });
+    }
+    let (content, encoding, line_ending, had_errors) =
+        document::decode_detached(bytes, content_type.as_deref().and_then(charset)).map_err(|message| FetchError::Other { message })?;
+    Ok(UrlDocument { url, content_type, content, encoding, line_ending, had_errors, read_only: true })
+}
+
+/// Downloads `url` through the configured proxy, following up to
+/// `MAX_REDIRECTS` redirects and stopping at `MAX_BYTES`.
+#[tauri::command]
+pub async fn open_url_document(app: AppHandle, url: String) -> Result<UrlDocument, FetchError> {
+    let target = parse(&url)?;
+    let builder = reqwest::Client::builder().timeout(TIMEOUT).redirect(reqwest::redirect::Policy::limited(MAX_REDIRECTS));
+    let client = network::configure(builder, &network::route(&app)).build().map_err(|e| FetchError::Other { message: e.to_string() })?;
+    log::info!("Opening {}", target);
+    let mut response = client.get(target.clone()).header(USER_AGENT, "skriv").send().await.map_err(|e| classify(e, &target))?;
+    let status = response.status();
+    if !status.is_success() {
+        log::warn!("{} answered {}", target, status);
+        return Err(FetchError::Status { status: status.as_u16(), reason: status.canonical_reason().map(str::to_string) });
+    }
+    if response.content_length().is_some_and(|length| length > MAX_BYTES as u64) {
+        return Err(FetchError::TooLarge { limit: MAX_BYTES });
+    }
+    let final_url = response.url().to_string();
+    let content_type = response.headers().get(CONTENT_TYPE).and_then(|value| value.to_str().ok()).map(str::to_string);
+    let mut bytes = Vec::new();
+    // The length may be missing or wrong, so the cap holds while reading too
+    while let Some(chunk) = response.chunk().await.map_err(|e| classify(e, &target))? {
+        if bytes.len() + chunk.len() > MAX_BYTES {
+            return Err(FetchError::TooLarge { limit: MAX_BYTES });
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    log::debug!("Read {}: {} bytes of {}", final_url, bytes.len(), content_type.as_deref().unwrap_or("unknown type"));
+    decode(final_url, content_type, &bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_only_web_urls() {
+        assert!(parse(" https://raw.githubusercontent.com/a/b/main/README.md ").is_ok());
+        assert!(matches!(parse("file:///etc/passwd"), Err(FetchError::InvalidUrl { .. })));
+        assert!(matches!(parse("README.md"), Err(FetchError::InvalidUrl { .. })));
+    }
+
+    #[test]
+    fn decodes_in_the_charset_named_else_as_detected() {
+        assert_eq!(charset("text/plain; charset=\"ISO-8859-1\""), Some("ISO-8859-1"));
+        assert_eq!(charset("text/markdown"), None);
+        let doc = decode("https://x/a.txt".into(), Some("text/plain; charset=windows-1252".into()), b"caf\xE9\r\n").unwrap();
+        assert_eq!((doc.content.as_str(), doc.encoding.as_str()), ("café\r\n", "windows-1252"));
+        assert!(doc.line_ending == LineEnding::Crlf && doc.read_only);
+        assert_eq!(decode("https://x/a".into(), None, "räksmörgås".as_bytes()).unwrap().encoding, "UTF-8");
+        let png = decode("https://x/a.png".into(), Some("image/png".into()), b"\x89PNG\r\n\x1a\n\x00\x00");
+        assert_eq!(png.err(), Some(FetchError::Binary { content_type: Some("image/png".into()) }));
+    }
+}
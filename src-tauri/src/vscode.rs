@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use tauri::{AppHandle, Manager};
+
+use crate::keybindings::{self, Binding, Keybindings};
+use crate::settings::{self, Settings};
+use crate::{paths, snippets};
+
+/// The `when` contexts the keymap knows, as the webview sets them.
+const WHEN_CONTEXTS: &[&str] = &["editorFocus", "editorTextFocus", "editorHasSelection"];
+
+/// VS Code commands that one of skriv's menu items does.
+const COMMANDS: &[(&str, &str)] = &[
+    ("workbench.action.files.newUntitledFile", "new_tab"),
+    ("workbench.action.newWindow", "new_window"),
+    ("workbench.action.files.openFile", "open_file"),
+    ("workbench.action.files.openFileFolder", "open_file"),
+    ("workbench.action.files.save", "save_file"),
+    ("workbench.action.files.saveAs", "save_file_as"),
+    ("workbench.action.showCommands", "command_palette"),
+    ("workbench.action.splitEditor", "split_view"),
+    ("workbench.action.findInFiles", "find_in_files"),
+    ("workbench.action.terminal.openNativeConsole", "open_terminal_here"),
+    ("editor.action.toggleWordWrap", "word_wrap"),
+    ("editor.action.commentLine", "toggle_comment"),
+    ("editor.action.formatDocument", "format_document"),
+    ("editor.action.moveLinesUpAction", "move_line_up"),
+    ("editor.action.moveLinesDownAction", "move_line_down"),
+    ("editor.action.copyLinesDownAction", "duplicate_line"),
+    ("editor.action.joinLines", "join_lines"),
+    ("editor.action.sortLinesAscending", "sort_lines_ascending"),
+    ("editor.action.sortLinesDescending", "sort_lines_descending"),
+    ("actions.find", "find_open"),
+    ("editor.action.nextMatchFindAction", "find_next"),
+    ("editor.action.previousMatchFindAction", "find_previous"),
+    ("editor.action.startFindReplaceAction", "find_replace"),
+];
+
+/// Commands Monaco has under VS Code's names, which the keymap runs as they are.
+const MONACO_PREFIXES: &[&str] = &["editor.action.", "editor.fold", "editor.unfold", "cursor"];
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Imported {
+    /// The VS Code setting or command
+    from: String,
+    /// What it becomes in skriv
+    to: String,
+    /// The setting's value, or the key the command is bound to
+    value: Value,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Skipped {
+    from: String,
+    reason: String,
+}
+
+/// Something already set in skriv that applying the import would change.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportConflict {
+    /// A setting, or a key with the command now bound to it
+    to: String,
+    current: Value,
+    imported: Value,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    report_id: u32,
+    /// VS Code's `User` folder the files were read from
+    source: String,
+    imported: Vec<Imported>,
+    skipped: Vec<Skipped>,
+    conflicts: Vec<ImportConflict>,
+}
+
+/// What `apply_import` writes for a report.
+#[derive(Default)]
+struct Changes {
+    patch: Map<String, Value>,
+    keybindings: Vec<Value>,
+}
+
+/// The latest report's changes, waiting for the user to confirm.
+#[derive(Default)]
+pub struct PendingImports(Mutex<HashMap<u32, Changes>>);
+
+static NEXT_REPORT: AtomicU32 = AtomicU32::new(1);
+
+/// Light or dark, going by the theme's name.
+fn theme_kind(name: &str) -> Option<&'static str> {
+    let name = name.to_lowercase();
+    if name.contains("light") {
+        return Some("light");
+    }
+    let dark = ["dark", "black", "night", "high contrast", "monokai", "dracula", "abyss", "nord"];
+    (dark.iter().any(|word| name.contains(word)) || name == "red").then_some("dark")
+}
+
+/// The skriv setting a VS Code one maps onto, with its value, or why it
+/// doesn't.
+fn map_setting(key: &str, value: &Value) -> Result<(&'static str, Value), String> {
+    let number = || {
+        value.as_f64().filter(|n| (1.0..=u32::MAX as f64).contains(n)).map(|n| json!(n.round() as u32)).ok_or_else(|| format!("{} isn't a positive number", value))
+    };
+    let string = || value.as_str().ok_or_else(|| format!("{} isn't a string", value));
+    match key {
+        "editor.fontSize" => Ok(("editor.fontSize", number()?)),
+        "editor.fontFamily" => Ok(("editor.fontFamily", json!(string()?))),
+        "editor.tabSize" => Ok(("editor.tabSize", number()?)),
+        "editor.insertSpaces" => value.as_bool().map(|b| ("editor.insertSpaces", json!(b))).ok_or_else(|| format!("{} isn't true or false", value)),
+        "editor.wordWrap" => match string()? {
+            "off" => Ok(("editor.wordWrap", json!(false))),
+            "on" | "wordWrapColumn" | "bounded" => Ok(("editor.wordWrap", json!(true))),
+            other => Err(format!("unknown value \"{}\"", other)),
+        },
+        "workbench.colorTheme" => {
+            let name = string()?;
+            let kind = theme_kind(name).ok_or_else(|| format!("can't tell whether \"{}\" is light or dark", name))?;
+            Ok(("appearance.mode", json!(kind)))
+        }
+        "window.autoDetectColorScheme" => match value {
+            Value::Bool(true) => Ok(("appearance.mode", json!("system"))),
+            _ => Err("off, so workbench.colorTheme decides".into()),
+        },
+        "files.autoSave" => match string()? {
+            "off" => Ok(("files.autoSave", json!("off"))),
+            "afterDelay" => Ok(("files.autoSave", json!("afterDelay"))),
+            // skriv only sees its windows lose focus, not each editor
+            "onFocusChange" | "onWindowChange" => Ok(("files.autoSave", json!("onFocusChange"))),
+            other => Err(format!("unknown value \"{}\"", other)),
+        },
+        "files.autoSaveDelay" => Ok(("files.autoSaveDelay", number()?)),
+        "files.associations" => match value.as_object() {
+            Some(map) if map.values().all(Value::is_string) => Ok(("files.associations", value.clone())),
+            _ => Err(format!("{} isn't a map of globs to language ids", value)),
+        },
+        _ if key.starts_with('[') => Err("settings for one language aren't imported".into()),
+        _ => Err("skriv has no such setting".into()),
+    }
+}
+
+/// Sets `path`, dotted, in a merge patch.
+fn set_path(patch: &mut Map<String, Value>, path: &str, value: Value) {
+    let (parent, key) = path.rsplit_once('.').unwrap_or(("", path));
+    let mut object = patch;
+    for part in parent.split('.').filter(|p| !p.is_empty()) {
+        let entry = object.entry(part).or_insert_with(|| Value::Object(Map::new()));
+        let Value::Object(child) = entry else { return };
+        object = child;
+    }
+    object.insert(key.to_string(), value);
+}
+
+fn get_path<'a>(settings: &'a Value, path: &str) -> &'a Value {
+    settings.pointer(&format!("/{}", path.replace('.', "/"))).unwrap_or(&Value::Null)
+}
+
+/// A VS Code keybinding as a keybindings.json entry for skriv, with the
+/// command it binds; why not when it can't be.
+fn map_keybinding(entry: &Value) -> Result<(String, String, Option<String>), String> {
+    let command = entry.get("command").and_then(Value::as_str).unwrap_or_default();
+    if command.starts_with('-') {
+        return Err("removes one of VS Code's own keybindings".into());
+    }
+    if entry.get("args").is_some() {
+        return Err("takes arguments, which skriv can't pass".into());
+    }
+    let target = match COMMANDS.iter().find(|(vscode, _)| *vscode == command) {
+        Some((_, skriv)) => skriv.to_string(),
+        None if MONACO_PREFIXES.iter().any(|prefix| command.starts_with(prefix)) => command.to_string(),
+        None => return Err("skriv has no such command".into()),
+    };
+    let key = entry.get("key").and_then(Value::as_str).unwrap_or_default();
+    let key = keybindings::normalize(key).map_err(|e| format!("key \"{}\": {}", key, e))?;
+    if keybindings::is_menu_item(&target) {
+        if key.contains(' ') {
+            return Err(format!("\"{}\" is two key presses; menu items take one", key));
+        }
+        // Menu shortcuts always apply, so the context doesn't matter
+        return Ok((target, key, None));
+    }
+    let when = entry.get("when").and_then(Value::as_str).map(str::trim).filter(|w| !w.is_empty());
+    if let Some(when) = when {
+        let unknown = when.split("&&").map(|term| term.trim().trim_start_matches('!').trim()).find(|name| !WHEN_CONTEXTS.contains(name));
+        if let Some(name) = unknown {
+            return Err(format!("depends on \"{}\", which skriv doesn't track", name));
+        }
+    }
+    Ok((target, key, when.map(String::from)))
+}
+
+/// Works out the import from VS Code's files without changing anything.
+fn translate(vscode_settings: &Map<String, Value>, vscode_keys: &[Value], current: &Settings, keymap: &[Binding]) -> (ImportReport, Changes) {
+    let mut report = ImportReport {
+        report_id: 0,
+        source: String::new(),
+        imported: Vec::new(),
+        skipped: Vec::new(),
+        conflicts: Vec::new(),
+    };
+    let mut changes = Changes::default();
+    let now = serde_json::to_value(current).unwrap_or_default();
+    let defaults = serde_json::to_value(Settings::default()).unwrap_or_default();
+    let follows_system = vscode_settings.get("window.autoDetectColorScheme") == Some(&Value::Bool(true));
+
+    for (key, value) in vscode_settings {
+        let mapped = match key.as_str() {
+            "workbench.colorTheme" if follows_system => Err("window.autoDetectColorScheme has the system decide".into()),
+            _ => map_setting(key, value),
+        };
+        let (to, value) = match mapped {
+            Ok(mapped) => mapped,
+            Err(reason) => {
+                report.skipped.push(Skipped { from: key.clone(), reason });
+                continue;
+            }
+        };
+        let existing = get_path(&now, to);
+        if existing != get_path(&defaults, to) && *existing != value {
+            report.conflicts.push(ImportConflict { to: to.to_string(), current: existing.clone(), imported: value.clone() });
+        }
+        if to == "appearance.mode" {
+            // A color theme would hide the mode
+            if let Some(theme) = &current.appearance.color_theme {
+                report.conflicts.push(ImportConflict { to: "appearance.colorTheme".into(), current: json!(theme), imported: Value::Null });
+                set_path(&mut changes.patch, "appearance.colorTheme", Value::Null);
+            }
+        }
+        set_path(&mut changes.patch, to, value.clone());
+        report.imported.push(Imported { from: key.clone(), to: to.to_string(), value });
+    }
+
+    for entry in vscode_keys {
+        let from = entry.get("command").and_then(Value::as_str).unwrap_or_default().to_string();
+        let (command, key, when) = match map_keybinding(entry) {
+            Ok(mapped) => mapped,
+            Err(reason) => {
+                report.skipped.push(Skipped { from, reason });
+                continue;
+            }
+        };
+        if let Some(bound) = keymap.iter().find(|b| b.key.eq_ignore_ascii_case(&key) && b.when == when && b.command != command) {
+            report.conflicts.push(ImportConflict { to: key.clone(), current: json!(bound.command), imported: json!(command) });
+        }
+        let mut binding = json!({ "key": key, "command": command });
+        if let Some(when) = when {
+            binding["when"] = json!(when);
+        }
+        changes.keybindings.push(binding);
+        report.imported.push(Imported { from, to: command, value: json!(key) });
+    }
+    (report, changes)
+}
+
+/// A JSONC file of VS Code's; `None` when it isn't there.
+fn read_jsonc(path: &Path) -> Result<Option<Value>, String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    serde_json::from_str(&snippets::strip_jsonc(&text)).map(Some).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Reads VS Code's settings.json and keybindings.json and reports what
+/// would come over. Nothing changes until `apply_import`.
+#[tauri::command]
+pub fn import_from_vscode(app: AppHandle, pending: tauri::State<'_, PendingImports>) -> Result<ImportReport, String> {
+    let dir = paths::vscode_user_dir(&app)?;
+    let vscode_settings = read_jsonc(&dir.join("settings.json"))?;
+    let vscode_keys = read_jsonc(&dir.join("keybindings.json"))?;
+    if vscode_settings.is_none() && vscode_keys.is_none() {
+        return Err(format!("No VS Code settings in {}", dir.display()));
+    }
+    let vscode_settings = match vscode_settings {
+        None => Map::new(),
+        Some(Value::Object(settings)) => settings,
+        Some(_) => return Err("VS Code's settings.json should hold an object".into()),
+    };
+    let vscode_keys = match vscode_keys {
+        None => Vec::new(),
+        Some(Value::Array(keys)) => keys,
+        Some(_) => return Err("VS Code's keybindings.json should hold an array".into()),
+    };
+    let current = app.state::<Mutex<Settings>>().lock().unwrap().clone();
+    let keymap = app.state::<Mutex<Keybindings>>().lock().unwrap().keymap.clone();
+    let (mut report, changes) = translate(&vscode_settings, &vscode_keys, &current, &keymap);
+    report.report_id = NEXT_REPORT.fetch_add(1, Ordering::Relaxed);
+    report.source = dir.to_string_lossy().into_owned();
+    let mut pending = pending.0.lock().unwrap();
+    // Only the report on screen can be applied
+    pending.clear();
+    pending.insert(report.report_id, changes);
+    Ok(report)
+}
+
+/// Writes what the report listed as imported: settings into settings.json,
+/// keybindings at the end of keybindings.json, so they win over the rest.
+#[tauri::command]
+pub fn apply_import(app: AppHandle, pending: tauri::State<'_, PendingImports>, report_id: u32) -> Result<(), String> {
+    let changes = pending.0.lock().unwrap().remove(&report_id).ok_or("The import has expired; run it again")?;
+    if !changes.patch.is_empty() {
+        settings::update_settings(app.clone(), Value::Object(changes.patch))?;
+    }
+    if !changes.keybindings.is_empty() {
+        keybindings::append(&app, changes.keybindings)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(value: Value) -> Map<String, Value> {
+        let Value::Object(object) = value else { unreachable!() };
+        object
+    }
+
+    #[test]
+    fn maps_the_supported_settings() {
+        let vscode = object(json!({
+            "editor.fontSize": 13.5,
+            "editor.tabSize": 2,
+            "editor.wordWrap": "bounded",
+            "workbench.colorTheme": "Solarized Light",
+            "files.autoSave": "onWindowChange",
+            "editor.minimap.enabled": false,
+            "[python]": { "editor.tabSize": 4 }
+        }));
+        let (report, changes) = translate(&vscode, &[], &Settings::default(), &[]);
+        assert_eq!(
+            Value::Object(changes.patch),
+            json!({
+                "editor": { "fontSize": 14, "tabSize": 2, "wordWrap": true },
+                "appearance": { "mode": "light" },
+                "files": { "autoSave": "onFocusChange" }
+            })
+        );
+        let skipped: Vec<&str> = report.skipped.iter().map(|s| s.from.as_str()).collect();
+        assert_eq!(skipped, ["[python]", "editor.minimap.enabled"]);
+        assert!(report.conflicts.is_empty());
+    }
+
+    #[test]
+    fn reports_settings_it_would_change() {
+        let mut current = Settings::default();
+        current.editor.tab_size = Some(8);
+        current.appearance.color_theme = Some("solarized".into());
+        let vscode = object(json!({ "editor.tabSize": 4, "window.autoDetectColorScheme": true, "workbench.colorTheme": "Default Dark+" }));
+        let (report, changes) = translate(&vscode, &[], &current, &[]);
+        let conflicts: Vec<&str> = report.conflicts.iter().map(|c| c.to.as_str()).collect();
+        assert_eq!(conflicts, ["editor.tabSize", "appearance.colorTheme"]);
+        assert_eq!(changes.patch["appearance"], json!({ "mode": "system", "colorTheme": null }));
+        assert!(report.skipped.iter().any(|s| s.from == "workbench.colorTheme"));
+    }
+
+    #[test]
+    fn translates_keybindings() {
+        let keys = [
+            json!({ "key": "ctrl+shift+k", "command": "editor.action.deleteLines", "when": "editorTextFocus" }),
+            json!({ "key": "ctrl+alt+s", "command": "workbench.action.files.saveAs", "when": "editorTextFocus" }),
+            json!({ "key": "ctrl+k ctrl+s", "command": "workbench.action.files.save" }),
+            json!({ "key": "ctrl+g", "command": "workbench.action.gotoLine" }),
+            json!({ "key": "ctrl+d", "command": "-editor.action.addSelectionToNextFindMatch" }),
+            json!({ "key": "ctrl+e", "command": "editor.action.smartSelect.expand", "when": "editorTextFocus && !suggestWidgetVisible" }),
+        ];
+        let bound = Binding { key: keybindings::normalize("ctrl+alt+s").unwrap(), command: "export_pdf".into(), when: None, user: true };
+        let (report, changes) = translate(&Map::new(), &keys, &Settings::default(), &[bound]);
+        let commands: Vec<&Value> = changes.keybindings.iter().map(|b| &b["command"]).collect();
+        assert_eq!(commands, [&json!("editor.action.deleteLines"), &json!("save_file_as")]);
+        assert_eq!(changes.keybindings[0]["when"], json!("editorTextFocus"));
+        assert!(changes.keybindings[1].get("when").is_none());
+        assert_eq!(report.skipped.len(), 4);
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].current, json!("export_pdf"));
+    }
+}
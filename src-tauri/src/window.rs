@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use tauri::{AppHandle, LogicalPosition, Manager, RunEvent, WebviewUrl, WebviewWindowBuilder};
+
+#[cfg(target_os = "macos")]
+use crate::i18n::Translations;
+#[cfg(target_os = "macos")]
+use crate::touchbar;
+use crate::{control, document_watcher, file_lock, lsp, menu_state, open_with, power, pty, quick_note, services, theme, updates, workspace};
+
+// Matches the main window in tauri.conf.json
+const WIDTH: f64 = 1000.0;
+const HEIGHT: f64 = 700.0;
+// How far each new window is shifted from the one it was opened from
+const CASCADE_OFFSET: f64 = 24.0;
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+/// What a window shows, set when it's created. Editor-only menu items are
+/// disabled while any other kind has focus.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum WindowKind {
+    Editor,
+    Other,
+}
+
+#[derive(Default)]
+pub struct WindowKinds(Mutex<HashMap<String, WindowKind>>);
+
+pub fn set_kind(app: &AppHandle, label: &str, kind: WindowKind) {
+    app.state::<WindowKinds>().0.lock().unwrap().insert(label.to_string(), kind);
+}
+
+/// Windows that were never registered count as non-editors.
+pub fn kind(app: &AppHandle, label: &str) -> WindowKind {
+    app.state::<WindowKinds>().0.lock().unwrap().get(label).copied().unwrap_or(WindowKind::Other)
+}
+
+/// Opens a window with a fresh session, cascaded from the focused window
+/// (or any window, when none has focus), and returns its label.
+pub fn open_new(app: &AppHandle) -> tauri::Result<String> {
+    let windows = app.webview_windows();
+    let source = windows
+        .values()
+        .find(|w| w.is_focused().unwrap_or(false))
+        .or_else(|| windows.values().next());
+    let position = match source {
+        Some(window) => {
+            let scale = window.scale_factor()?;
+            let pos: LogicalPosition<f64> = window.outer_position()?.to_logical(scale);
+            Some((pos.x + CASCADE_OFFSET, pos.y + CASCADE_OFFSET))
+        }
+        None => None,
+    };
+
+    let label = loop {
+        let label = format!("window-{}", NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        if !windows.contains_key(&label) {
+            break label;
+        }
+    };
+    set_kind(app, &label, WindowKind::Editor);
+    let builder = WebviewWindowBuilder::new(app, &label, WebviewUrl::default())
+        .title("skriv")
+        .inner_size(WIDTH, HEIGHT)
+        .resizable(true);
+    let builder = match position {
+        Some((x, y)) => builder.position(x, y),
+        None => builder.center(),
+    };
+    builder.build()?;
+    Ok(label)
+}
+
+// Async so window creation doesn't deadlock the webview on Windows
+#[tauri::command]
+pub async fn new_window(app: AppHandle) -> Result<(), String> {
+    open_new(&app).map(|_| ()).map_err(|e| e.to_string())
+}
+
+pub fn handle_event(window: &tauri::Window, event: &tauri::WindowEvent) {
+    let app = window.app_handle();
+    match event {
+        tauri::WindowEvent::Focused(true) => {
+            menu_state::focus_changed(app, window.label());
+            // Catches what the platform doesn't announce, like accent changes on Windows
+            theme::appearance_changed(app);
+        }
+        tauri::WindowEvent::ThemeChanged(_) => theme::appearance_changed(app),
+        tauri::WindowEvent::DragDrop(tauri::DragDropEvent::Drop { paths, .. }) => {
+            open_with::dropped(app, window.label(), paths.clone());
+        }
+        tauri::WindowEvent::Destroyed => {
+            let editors_left = {
+                let kinds = app.state::<WindowKinds>();
+                let mut kinds = kinds.0.lock().unwrap();
+                kinds.remove(window.label());
+                kinds.values().any(|kind| *kind == WindowKind::Editor)
+            };
+            if !editors_left {
+                quick_note::editors_closed(app);
+            }
+            menu_state::window_destroyed(app, window.label());
+            services::window_closed(app, window.label());
+            open_with::window_closed(app, window.label());
+            pty::window_closed(app, window.label());
+            power::window_closed(app, window.label());
+            workspace::window_closed(app, window.label());
+            document_watcher::window_closed(app, window.label());
+            file_lock::window_closed(app, window.label());
+            #[cfg(target_os = "macos")]
+            touchbar::window_closed(window.label());
+        }
+        _ => {}
+    }
+}
+
+pub fn handle_run_event(app: &AppHandle, event: RunEvent) {
+    match event {
+        // On macOS the app keeps running with just the menu bar after its last window closes
+        #[cfg(target_os = "macos")]
+        RunEvent::ExitRequested { code: None, api, .. } => api.prevent_exit(),
+        // Held while a deferred update waits for the windows to save their session
+        RunEvent::ExitRequested { code, api, .. } if updates::exit_requested(app, code) => api.prevent_exit(),
+        #[cfg(target_os = "macos")]
+        RunEvent::Reopen { has_visible_windows: false, .. } => {
+            if let Err(e) = open_new(app) {
+                log::warn!("Failed to open window: {}", e);
+            }
+        }
+        RunEvent::Ready => open_with::launched(app),
+        // Finder's Open With, double-clicks and drops on the Dock icon
+        #[cfg(target_os = "macos")]
+        RunEvent::Opened { urls } => open_with::open(app, urls),
+        RunEvent::Exit => {
+            control::stop(app);
+            lsp::shutdown_all(app);
+            updates::exiting(app);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(target_os = "macos")]
+thread_local! {
+    // Clicks arrive through the app's regular menu event handler
+    static DOCK_ITEM: muda::MenuItem = muda::MenuItem::with_id("new_window", "New Window", true, None);
+    static DOCK_MENU: muda::Menu = {
+        let menu = muda::Menu::new();
+        DOCK_ITEM.with(|item| menu.append(item)).expect("append Dock menu item");
+        menu
+    };
+}
+
+#[cfg(target_os = "macos")]
+pub fn localize_dock_menu(app: &AppHandle) {
+    let label = app.state::<Mutex<Translations>>().lock().unwrap().t("new_window");
+    DOCK_ITEM.with(|item| item.set_text(label));
+}
+
+/// Adds New Window to the Dock menu. Tauri has no API for it, so this adds
+/// `applicationDockMenu:` to the app delegate tao installed.
+#[cfg(target_os = "macos")]
+pub fn install_dock_menu(app: &AppHandle) {
+    use objc2::runtime::{AnyClass, AnyObject, Imp, Sel};
+    use objc2::sel;
+    use objc2_app_kit::NSApplication;
+    use objc2_foundation::MainThreadMarker;
+
+    localize_dock_menu(app);
+
+    extern "C-unwind" fn dock_menu(_: &AnyObject, _: Sel, _: *mut AnyObject) -> *mut std::ffi::c_void {
+        use muda::ContextMenu;
+        DOCK_MENU.with(|menu| menu.ns_menu())
+    }
+
+    let Some(mtm) = MainThreadMarker::new() else { return };
+    let Some(delegate) = NSApplication::sharedApplication(mtm).delegate() else { return };
+    let class = AsRef::<AnyObject>::as_ref(&*delegate).class();
+    let imp: Imp = unsafe {
+        std::mem::transmute(dock_menu as extern "C-unwind" fn(&AnyObject, Sel, *mut AnyObject) -> *mut std::ffi::c_void)
+    };
+    // SAFETY: the signature matches `-(NSMenu *)applicationDockMenu:(NSApplication *)sender`,
+    // and the delegate class outlives the app
+    let added = unsafe {
+        objc2::ffi::class_addMethod(
+            class as *const AnyClass as *mut AnyClass,
+            sel!(applicationDockMenu:),
+            imp,
+            c"@@:@".as_ptr(),
+        )
+    };
+    if !added.as_bool() {
+        log::warn!("Failed to install Dock menu");
+    }
+}
@@ -0,0 +1,261 @@
+//! Word completion for documents too big for Monaco's word-based
+//! suggestions, which copy the whole text to a web worker and rescan it.
+//! Words are counted line by line, so an edit only recounts the lines it
+//! touches.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::highlight::{byte_index, HighlightEdit};
+use crate::workers::{Priority, WorkerPool};
+
+/// Distinct words an index keeps. Past that the least recently seen go,
+/// rarest first.
+const MAX_WORDS: usize = 50_000;
+/// Longer runs, like base64 or hashes, aren't words anyone types.
+const MAX_WORD_CHARS: usize = 64;
+
+struct Word {
+    count: usize,
+    /// The edit it last appeared in
+    seen: u64,
+}
+
+struct WordIndex {
+    /// The window whose tab it is, for completing from its other documents
+    window: String,
+    /// Each with its line break, but for the last
+    lines: Vec<String>,
+    words: HashMap<String, Word>,
+    /// Bumped by each edit
+    clock: u64,
+}
+
+#[derive(Default)]
+pub struct WordIndexes(Mutex<HashMap<String, Arc<Mutex<WordIndex>>>>);
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Completion {
+    pub word: String,
+    /// How often it's in the documents
+    pub count: usize,
+}
+
+/// Words by Unicode's rules, but split at dots and colons too, since
+/// `self.lines` or `std::fs` are separate words as far as code goes.
+fn words_of(line: &str) -> impl Iterator<Item = &str> {
+    line.unicode_words()
+        .flat_map(|word| word.split(['.', ':']))
+        .filter(|word| word.chars().any(char::is_alphabetic) && word.chars().count() <= MAX_WORD_CHARS)
+}
+
+fn split_lines(content: &str) -> Vec<String> {
+    content.split_inclusive('\n').map(String::from).collect()
+}
+
+fn count_line(words: &mut HashMap<String, Word>, clock: u64, line: &str) {
+    for word in words_of(line) {
+        match words.get_mut(word) {
+            Some(entry) => {
+                entry.count += 1;
+                entry.seen = clock;
+            }
+            None => {
+                words.insert(word.to_string(), Word { count: 1, seen: clock });
+            }
+        }
+    }
+}
+
+fn uncount_line(words: &mut HashMap<String, Word>, line: &str) {
+    for word in words_of(line) {
+        // An evicted word may still be in the text
+        let Some(entry) = words.get_mut(word) else { continue };
+        entry.count -= 1;
+        if entry.count == 0 {
+            words.remove(word);
+        }
+    }
+}
+
+fn starts_with_ignoring_case(word: &str, lowercase_prefix: &str) -> bool {
+    let mut lowered = word.chars().flat_map(char::to_lowercase);
+    lowercase_prefix.chars().all(|c| lowered.next() == Some(c))
+}
+
+impl WordIndex {
+    fn new(window: String, content: &str) -> Self {
+        let mut index = WordIndex { window, lines: split_lines(content), words: HashMap::new(), clock: 0 };
+        for line in &index.lines {
+            count_line(&mut index.words, 0, line);
+            if index.words.len() > MAX_WORDS {
+                evict(&mut index.words);
+            }
+        }
+        index
+    }
+
+    fn edit(&mut self, edit: &HighlightEdit) {
+        self.clock += 1;
+        if self.lines.is_empty() {
+            self.lines.push(String::new());
+        }
+        let last = self.lines.len() - 1;
+        let (start_line, end_line) = (edit.start_line.min(last), edit.end_line.min(last));
+        let start = byte_index(&self.lines[start_line], edit.start_column);
+        let end = byte_index(&self.lines[end_line], edit.end_column);
+        let text = format!("{}{}{}", &self.lines[start_line][..start], edit.text, &self.lines[end_line][end..]);
+        for line in &self.lines[start_line..=end_line] {
+            uncount_line(&mut self.words, line);
+        }
+        let inserted = split_lines(&text);
+        for line in &inserted {
+            count_line(&mut self.words, self.clock, line);
+        }
+        self.lines.splice(start_line..=end_line, inserted);
+        if self.words.len() > MAX_WORDS {
+            evict(&mut self.words);
+        }
+    }
+
+    fn add_matches<'a>(&'a self, lowercase_prefix: &str, into: &mut HashMap<&'a str, (usize, u64)>) {
+        for (word, entry) in &self.words {
+            if starts_with_ignoring_case(word, lowercase_prefix) {
+                let found = into.entry(word.as_str()).or_insert((0, 0));
+                found.0 += entry.count;
+                found.1 = found.1.max(entry.seen);
+            }
+        }
+    }
+}
+
+/// Drops a tenth of the words, so that eviction doesn't run on every edit.
+fn evict(words: &mut HashMap<String, Word>) {
+    let mut by_age: Vec<(u64, usize, String)> = words.iter().map(|(word, entry)| (entry.seen, entry.count, word.clone())).collect();
+    let excess = words.len() - MAX_WORDS * 9 / 10;
+    by_age.select_nth_unstable(excess - 1);
+    for (_, _, word) in &by_age[..excess] {
+        words.remove(word);
+    }
+}
+
+/// The words of `index` and `others` starting with `prefix`, ignoring case,
+/// most frequent first. `current` is the word being typed, which counts once
+/// less since it's in the text itself.
+fn complete(index: &WordIndex, others: &[&WordIndex], prefix: &str, current: Option<&str>, limit: usize) -> Vec<Completion> {
+    let prefix = prefix.to_lowercase();
+    let mut found = HashMap::new();
+    index.add_matches(&prefix, &mut found);
+    for other in others {
+        other.add_matches(&prefix, &mut found);
+    }
+    if let Some(count) = current.and_then(|word| found.get_mut(word)) {
+        count.0 -= 1;
+    }
+    let mut ranked: Vec<(&str, (usize, u64))> = found.into_iter().filter(|(_, (count, _))| *count > 0).collect();
+    ranked.sort_by(|(a, (a_count, a_seen)), (b, (b_count, b_seen))| b_count.cmp(a_count).then(b_seen.cmp(a_seen)).then(a.cmp(b)));
+    ranked.into_iter().take(limit).map(|(word, (count, _))| Completion { word: word.to_string(), count }).collect()
+}
+
+fn index(indexes: &WordIndexes, doc_id: &str) -> Result<Arc<Mutex<WordIndex>>, String> {
+    indexes.0.lock().unwrap().get(doc_id).cloned().ok_or_else(|| format!("No word index for {}", doc_id))
+}
+
+/// Indexes the words of a document, replacing any index it had.
+#[tauri::command]
+pub async fn open_word_index(
+    indexes: tauri::State<'_, WordIndexes>,
+    pool: tauri::State<'_, WorkerPool>,
+    window: tauri::Window,
+    doc_id: String,
+    content: String,
+) -> Result<(), String> {
+    let label = window.label().to_string();
+    let index = pool.run(Priority::Interactive, move || WordIndex::new(label, &content)).await?;
+    indexes.0.lock().unwrap().insert(doc_id, Arc::new(Mutex::new(index)));
+    Ok(())
+}
+
+/// Applies one change from the editor, given as for highlighting.
+#[tauri::command]
+pub fn update_word_index(indexes: tauri::State<'_, WordIndexes>, doc_id: String, edit: HighlightEdit) -> Result<(), String> {
+    index(&indexes, &doc_id)?.lock().unwrap().edit(&edit);
+    Ok(())
+}
+
+/// Up to `limit` words for `prefix`, with those of the window's other
+/// indexed documents merged in when `other_documents` is set.
+#[tauri::command]
+pub async fn complete_word(
+    indexes: tauri::State<'_, WordIndexes>,
+    pool: tauri::State<'_, WorkerPool>,
+    doc_id: String,
+    prefix: String,
+    limit: usize,
+    current: Option<String>,
+    other_documents: Option<bool>,
+) -> Result<Vec<Completion>, String> {
+    let index = index(&indexes, &doc_id)?;
+    let window = index.lock().unwrap().window.clone();
+    let others: Vec<Arc<Mutex<WordIndex>>> = match other_documents {
+        Some(true) => {
+            let all = indexes.0.lock().unwrap();
+            all.iter().filter(|(id, other)| **id != doc_id && other.lock().unwrap().window == window).map(|(_, other)| other.clone()).collect()
+        }
+        _ => Vec::new(),
+    };
+    pool.run(Priority::Interactive, move || {
+        let index = index.lock().unwrap();
+        let others: Vec<_> = others.iter().map(|other| other.lock().unwrap()).collect();
+        let others: Vec<&WordIndex> = others.iter().map(|other| &**other).collect();
+        complete(&index, &others, &prefix, current.as_deref(), limit)
+    })
+    .await
+}
+
+/// Drops the index when its tab closes.
+#[tauri::command]
+pub fn close_word_index(indexes: tauri::State<'_, WordIndexes>, doc_id: String) {
+    indexes.0.lock().unwrap().remove(&doc_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(completions: Vec<Completion>) -> Vec<(String, usize)> {
+        completions.into_iter().map(|c| (c.word, c.count)).collect()
+    }
+
+    #[test]
+    fn ranks_by_frequency_across_unicode_words() {
+        let index = WordIndex::new("main".into(), "Über über übel\nself.überall don't\nübel übel 42 _über\n");
+        let found = words(complete(&index, &[], "ÜB", None, 10));
+        assert_eq!(found, [("übel".into(), 3), ("Über".into(), 1), ("über".into(), 1), ("überall".into(), 1)]);
+        assert_eq!(words(complete(&index, &[], "don", Some("don't"), 10)), []);
+        let other = WordIndex::new("main".into(), "überall");
+        assert_eq!(words(complete(&index, &[&other], "überal", None, 1)), [("überall".into(), 2)]);
+    }
+
+    #[test]
+    fn recounts_only_the_edited_lines() {
+        let mut index = WordIndex::new("main".into(), "alpha beta\ngamma\n");
+        // Replaces "beta\ngam" with "alpine\nal"
+        index.edit(&HighlightEdit { start_line: 0, start_column: 6, end_line: 1, end_column: 3, text: "alpine\nal".into() });
+        assert_eq!(index.lines, ["alpha alpine\n", "alma\n"]);
+        assert_eq!(words(complete(&index, &[], "al", None, 10)), [("alma".into(), 1), ("alpha".into(), 1), ("alpine".into(), 1)]);
+        assert!(complete(&index, &[], "b", None, 10).is_empty());
+    }
+
+    #[test]
+    fn evicts_the_least_recently_seen_words() {
+        let content: String = (0..MAX_WORDS).map(|i| format!("w{}\n", i)).collect();
+        let mut index = WordIndex::new("main".into(), &content);
+        index.edit(&HighlightEdit { start_line: 0, start_column: 0, end_line: 0, end_column: 0, text: "fresh w1 ".into() });
+        assert_eq!(index.words.len(), MAX_WORDS * 9 / 10);
+        assert!(index.words.contains_key("fresh") && index.words.contains_key("w1"));
+    }
+}
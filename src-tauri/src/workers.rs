@@ -0,0 +1,195 @@
+//! The threads background work shares, so that highlighting, indexing and
+//! counting don't each take every core. Queued jobs run by priority: an
+//! interactive one goes ahead of anything bulk, and long bulk work is done a
+//! step at a time, so nothing waits longer than one step for a thread.
+
+use std::collections::VecDeque;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Condvar, Mutex};
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+use crate::settings::Settings;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Priority {
+    /// Someone is waiting on it, as for the lines on screen
+    Interactive,
+    /// Work ahead of need, as indexing a file in the background
+    Bulk,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct Queues {
+    interactive: VecDeque<Job>,
+    bulk: VecDeque<Job>,
+    running: usize,
+    /// Threads alive; above `target` for a moment after the pool shrinks
+    threads: usize,
+    target: usize,
+}
+
+#[derive(Default)]
+struct Shared {
+    queues: Mutex<Queues>,
+    ready: Condvar,
+}
+
+#[derive(Clone, Default)]
+pub struct WorkerPool(Arc<Shared>);
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerPoolStats {
+    pub threads: usize,
+    pub running: usize,
+    pub queued_interactive: usize,
+    pub queued_bulk: usize,
+}
+
+/// `performance.workerThreads`, or one less than the cores so that the
+/// webview keeps one; at least one either way.
+pub fn thread_count(settings: &Settings) -> usize {
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    settings.performance.worker_threads.unwrap_or(cores.saturating_sub(1)).max(1)
+}
+
+fn work(shared: Arc<Shared>) {
+    let mut queues = shared.queues.lock().unwrap();
+    loop {
+        if queues.threads > queues.target {
+            queues.threads -= 1;
+            return;
+        }
+        let Some(job) = queues.interactive.pop_front().or_else(|| queues.bulk.pop_front()) else {
+            queues = shared.ready.wait(queues).unwrap();
+            continue;
+        };
+        queues.running += 1;
+        drop(queues);
+        // A job that panics mustn't take the thread with it
+        let _ = std::panic::catch_unwind(AssertUnwindSafe(job));
+        queues = shared.queues.lock().unwrap();
+        queues.running -= 1;
+    }
+}
+
+impl WorkerPool {
+    pub fn new(threads: usize) -> Self {
+        let pool = WorkerPool::default();
+        pool.resize(threads);
+        pool
+    }
+
+    /// Starts threads or lets the extra ones go once their job is done;
+    /// queued jobs are kept either way.
+    pub fn resize(&self, threads: usize) {
+        let mut queues = self.0.queues.lock().unwrap();
+        queues.target = threads.max(1);
+        while queues.threads < queues.target {
+            queues.threads += 1;
+            let shared = self.0.clone();
+            std::thread::spawn(move || work(shared));
+        }
+        self.0.ready.notify_all();
+    }
+
+    pub fn spawn(&self, priority: Priority, job: impl FnOnce() + Send + 'static) {
+        let mut queues = self.0.queues.lock().unwrap();
+        match priority {
+            Priority::Interactive => queues.interactive.push_back(Box::new(job)),
+            Priority::Bulk => queues.bulk.push_back(Box::new(job)),
+        }
+        self.0.ready.notify_one();
+    }
+
+    /// Runs `step` until it returns false, going to the back of the queue
+    /// after each, so that other jobs get a turn in between.
+    pub fn spawn_steps(&self, priority: Priority, mut step: impl FnMut() -> bool + Send + 'static) {
+        let pool = self.clone();
+        self.spawn(priority, move || {
+            if step() {
+                pool.spawn_steps(priority, step);
+            }
+        });
+    }
+
+    /// Runs `job` on the pool and waits for what it returns.
+    pub async fn run<T: Send + 'static>(&self, priority: Priority, job: impl FnOnce() -> T + Send + 'static) -> Result<T, String> {
+        let (sender, mut receiver) = tauri::async_runtime::channel(1);
+        self.spawn(priority, move || {
+            let _ = sender.try_send(job());
+        });
+        receiver.recv().await.ok_or_else(|| "The background job failed".to_string())
+    }
+
+    pub fn stats(&self) -> WorkerPoolStats {
+        let queues = self.0.queues.lock().unwrap();
+        WorkerPoolStats {
+            threads: queues.target,
+            running: queues.running,
+            queued_interactive: queues.interactive.len(),
+            queued_bulk: queues.bulk.len(),
+        }
+    }
+}
+
+/// Resizes the pool to a changed `performance.workerThreads`.
+pub fn settings_changed(app: &AppHandle) {
+    let threads = thread_count(&app.state::<Mutex<Settings>>().lock().unwrap());
+    app.state::<WorkerPool>().resize(threads);
+}
+
+#[tauri::command]
+pub fn get_worker_pool_stats(pool: tauri::State<'_, WorkerPool>) -> WorkerPoolStats {
+    pool.stats()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn runs_interactive_jobs_before_queued_bulk_ones() {
+        let pool = WorkerPool::new(1);
+        let (hold, held) = mpsc::channel::<()>();
+        let (sender, order) = mpsc::channel();
+        let started = sender.clone();
+        pool.spawn(Priority::Bulk, move || {
+            started.send("first").unwrap();
+            held.recv().unwrap();
+        });
+        assert_eq!(order.recv().unwrap(), "first");
+        for (priority, name) in [(Priority::Bulk, "bulk"), (Priority::Interactive, "interactive")] {
+            let sender = sender.clone();
+            pool.spawn(priority, move || sender.send(name).unwrap());
+        }
+        let stats = pool.stats();
+        assert_eq!((stats.threads, stats.queued_interactive, stats.queued_bulk), (1, 1, 1));
+        hold.send(()).unwrap();
+        assert_eq!(order.recv().unwrap(), "interactive");
+        assert_eq!(order.recv().unwrap(), "bulk");
+    }
+
+    #[test]
+    fn resizes_while_running() {
+        let pool = WorkerPool::new(4);
+        let (sender, done) = mpsc::channel();
+        let mut left = 3;
+        pool.spawn_steps(Priority::Bulk, move || {
+            left -= 1;
+            sender.send(left).unwrap();
+            left > 0
+        });
+        assert_eq!(done.iter().collect::<Vec<_>>(), [2, 1, 0]);
+        pool.resize(1);
+        let (sender, done) = mpsc::channel();
+        pool.spawn(Priority::Interactive, move || sender.send(()).unwrap());
+        done.recv().unwrap();
+        assert_eq!(pool.stats().threads, 1);
+    }
+}
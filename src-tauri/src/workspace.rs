@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde_json::{Map, Value};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::file_events::{self, FileChange};
+use crate::settings::{self, Scope, Settings, SettingsChanged};
+use crate::{bookmarks, paths, plugins, recent_workspaces, workspace_trust};
+
+pub const DIR: &str = ".skriv";
+const FILE_NAME: &str = "settings.json";
+
+/// Keys a workspace can set over the user's settings.
+const WORKSPACE_KEYS: &[&str] = &["editor", "search"];
+/// Keys that run programs named in the folder. They're only taken from a
+/// trusted workspace; see `workspace_trust`.
+const TRUSTED_KEYS: &[&str] = &["formatters", "linters", "terminal"];
+
+/// A window's open folder and what its `.skriv/settings.json` sets.
+struct Workspace {
+    root: PathBuf,
+    layer: Map<String, Value>,
+    /// What the file sets that's held back until the folder is trusted
+    withheld: Map<String, Value>,
+    problems: Vec<String>,
+    _watcher: Option<RecommendedWatcher>,
+}
+
+#[derive(Default)]
+pub struct Workspaces(Mutex<HashMap<String, Workspace>>);
+
+fn settings_path(root: &Path) -> PathBuf {
+    root.join(DIR).join(FILE_NAME)
+}
+
+/// Keeps the keys the workspace may set whose values fit the schema, and
+/// says what was left out and why. Those needing trust are set aside
+/// unless it's `trusted`.
+fn validate(file: Map<String, Value>, trusted: bool) -> (Map<String, Value>, Map<String, Value>, Vec<String>) {
+    let mut layer = Map::new();
+    let mut withheld = Map::new();
+    let mut problems = Vec::new();
+    for (key, value) in file {
+        let needs_trust = TRUSTED_KEYS.contains(&key.as_str());
+        if needs_trust && !trusted {
+            problems.push(format!("\"{}\" is only read from trusted workspaces", key));
+            withheld.insert(key, value);
+            continue;
+        }
+        if !needs_trust && !WORKSPACE_KEYS.contains(&key.as_str()) {
+            problems.push(format!("\"{}\" can't be set for a workspace", key));
+            continue;
+        }
+        let mut check = serde_json::to_value(Settings::default()).unwrap_or_default();
+        settings::merge(&mut check, Value::Object(Map::from_iter([(key.clone(), value.clone())])));
+        match serde_json::from_value::<Settings>(check) {
+            Ok(_) => {
+                layer.insert(key, value);
+            }
+            Err(e) => problems.push(format!("\"{}\": {}", key, e)),
+        }
+    }
+    (layer, withheld, problems)
+}
+
+fn read_layer(root: &Path, trusted: bool) -> (Map<String, Value>, Map<String, Value>, Vec<String>) {
+    let text = match std::fs::read_to_string(settings_path(root)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return (Map::new(), Map::new(), Vec::new()),
+        Err(e) => return (Map::new(), Map::new(), vec![e.to_string()]),
+    };
+    match serde_json::from_str(&text) {
+        Ok(Value::Object(file)) => validate(file, trusted),
+        Ok(_) => (Map::new(), Map::new(), vec!["The file should hold an object".into()]),
+        Err(e) => (Map::new(), Map::new(), vec![e.to_string()]),
+    }
+}
+
+/// The user's settings with the layer on top.
+fn overlay(user: &Settings, layer: &Map<String, Value>) -> Settings {
+    if layer.is_empty() {
+        return user.clone();
+    }
+    let Ok(mut value) = serde_json::to_value(user) else { return user.clone() };
+    settings::merge(&mut value, Value::Object(layer.clone()));
+    serde_json::from_value(value).unwrap_or_else(|_| user.clone())
+}
+
+/// The user's settings with those of the window's workspace on top.
+pub fn effective(app: &AppHandle, label: &str) -> Settings {
+    let user = app.state::<Mutex<Settings>>().lock().unwrap().clone();
+    match app.state::<Workspaces>().0.lock().unwrap().get(label) {
+        Some(workspace) => overlay(&user, &workspace.layer),
+        None => user,
+    }
+}
+
+/// Watches the root for `.skriv` coming and going, and `.skriv` itself for
+/// the file.
+fn watch(app: &AppHandle, label: &str, root: &Path) -> Result<RecommendedWatcher, String> {
+    let (app, label, dir, roots) = (app.clone(), label.to_string(), root.join(DIR), vec![root.to_path_buf()]);
+    let mut watcher = notify::recommended_watcher(file_events::debounce(
+        move || roots.clone(),
+        move |changes| {
+            if changes.iter().flat_map(FileChange::paths).any(|p| p.starts_with(&dir) || dir.starts_with(p)) {
+                reload(&app, &label);
+            }
+        },
+    ))
+    .map_err(|e| e.to_string())?;
+    watcher.watch(root, RecursiveMode::NonRecursive).map_err(|e| e.to_string())?;
+    let _ = watcher.watch(&root.join(DIR), RecursiveMode::NonRecursive);
+    Ok(watcher)
+}
+
+/// The paths each open workspace's watcher is on.
+pub fn watched_paths(app: &AppHandle) -> Vec<usize> {
+    let workspaces = app.state::<Workspaces>();
+    let workspaces = workspaces.0.lock().unwrap();
+    workspaces.values().filter(|w| w._watcher.is_some()).map(|w| 1 + usize::from(w.root.join(DIR).is_dir())).collect()
+}
+
+/// Reads the workspace's file again and tells its window what changed.
+fn reload(app: &AppHandle, label: &str) {
+    let before = effective(app, label);
+    {
+        let workspaces = app.state::<Workspaces>();
+        let mut workspaces = workspaces.0.lock().unwrap();
+        let Some(workspace) = workspaces.get_mut(label) else { return };
+        let trusted = workspace_trust::is_trusted(app, &workspace.root);
+        (workspace.layer, workspace.withheld, workspace.problems) = read_layer(&workspace.root, trusted);
+        // `.skriv` may have just been created
+        if let Some(watcher) = workspace._watcher.as_mut() {
+            let _ = watcher.watch(&workspace.root.join(DIR), RecursiveMode::NonRecursive);
+        }
+    }
+    let changed_keys = settings::changed_keys(&before, &effective(app, label));
+    if !changed_keys.is_empty() {
+        let _ = app.emit_to(label, "settings-changed", SettingsChanged { changed_keys, scope: Scope::Workspace, problems: Vec::new() });
+    }
+}
+
+/// Reads every workspace again after the user trusted or stopped trusting
+/// a folder.
+pub fn trust_changed(app: &AppHandle) {
+    let labels: Vec<String> = app.state::<Workspaces>().0.lock().unwrap().keys().cloned().collect();
+    for label in labels {
+        reload(app, &label);
+    }
+}
+
+/// What the window's workspace sets for `key` that's held back until the
+/// folder is trusted.
+pub fn withheld(app: &AppHandle, label: &str, key: &str) -> Option<Value> {
+    app.state::<Workspaces>().0.lock().unwrap().get(label)?.withheld.get(key).cloned()
+}
+
+/// The folder open in the window, if any.
+pub fn root(app: &AppHandle, label: &str) -> Option<PathBuf> {
+    app.state::<Workspaces>().0.lock().unwrap().get(label).map(|w| w.root.clone())
+}
+
+/// The roots of the workspaces open in windows.
+pub fn roots(app: &AppHandle) -> Vec<PathBuf> {
+    app.state::<Workspaces>().0.lock().unwrap().values().map(|w| w.root.clone()).collect()
+}
+
+/// A window with `root` open as its workspace.
+pub fn window_with_root(app: &AppHandle, root: &Path) -> Option<String> {
+    app.state::<Workspaces>().0.lock().unwrap().iter().find(|(_, w)| w.root == root).map(|(label, _)| label.clone())
+}
+
+pub fn window_closed(app: &AppHandle, label: &str) {
+    app.state::<Workspaces>().0.lock().unwrap().remove(label);
+}
+
+/// Makes `root` the window's workspace, `None` closing it, and returns what
+/// was wrong with its `.skriv/settings.json`.
+#[tauri::command]
+pub fn set_workspace(app: AppHandle, window: tauri::Window, root: Option<String>) -> Vec<String> {
+    let label = window.label();
+    let Some(root) = root.as_deref().map(paths::decode) else {
+        window_closed(&app, label);
+        return Vec::new();
+    };
+    let (layer, withheld, problems) = read_layer(&root, workspace_trust::is_trusted(&app, &root));
+    let watcher = watch(&app, label, &root).inspect_err(|e| log::warn!("Failed to watch {}: {}", root.display(), e)).ok();
+    recent_workspaces::opened(&app, &root);
+    let mut restricted: Vec<String> = withheld.keys().cloned().collect();
+    if plugins::in_workspace(&root) {
+        restricted.push("plugins".into());
+    }
+    let workspace = Workspace { root: root.clone(), layer, withheld, problems: problems.clone(), _watcher: watcher };
+    app.state::<Workspaces>().0.lock().unwrap().insert(label.to_string(), workspace);
+    bookmarks::workspace_opened(&app, &root);
+    workspace_trust::opened(&app, label, &root, restricted);
+    problems
+}
+
+/// The user's settings overlaid with those of the window's workspace.
+#[tauri::command]
+pub fn get_effective_settings(app: AppHandle, window_label: String) -> Settings {
+    effective(&app, &window_label)
+}
+
+/// What was left out of the window's workspace settings.
+#[tauri::command]
+pub fn get_workspace_settings_problems(workspaces: tauri::State<'_, Workspaces>, window_label: String) -> Vec<String> {
+    workspaces.0.lock().unwrap().get(&window_label).map(|w| w.problems.clone()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn keeps_only_workspace_keys() {
+        let file = json!({
+            "editor": { "tabSize": 2 },
+            "search": { "exclude": "target" },
+            "formatters": { "rust": { "command": "rustfmt" } },
+            "locale": "de"
+        });
+        let Value::Object(file) = file else { unreachable!() };
+        let (layer, withheld, problems) = validate(file.clone(), false);
+        assert_eq!(Value::Object(layer), json!({ "editor": { "tabSize": 2 } }));
+        assert_eq!(Value::Object(withheld), json!({ "formatters": { "rust": { "command": "rustfmt" } } }));
+        assert_eq!(problems.len(), 3);
+        assert!(problems.iter().any(|p| p.contains("trusted")));
+        let (layer, _, problems) = validate(file, true);
+        assert!(layer.contains_key("formatters"));
+        assert_eq!(problems.len(), 2);
+    }
+
+    #[test]
+    fn overlays_the_user_settings() {
+        let user = Settings { locale: Some("sv".into()), ..Default::default() };
+        let Value::Object(layer) = json!({ "editor": { "insertSpaces": false } }) else { unreachable!() };
+        let effective = overlay(&user, &layer);
+        assert_eq!(effective.editor.insert_spaces, Some(false));
+        assert_eq!(effective.locale.as_deref(), Some("sv"));
+    }
+}
@@ -0,0 +1,153 @@
+//! Which folders may run what they define: formatter, linter and terminal
+//! commands in `.skriv/settings.json`, and plugins in `.skriv/plugins`.
+//! Decisions are kept by canonical path in FILE_NAME in the app data
+//! directory, and a folder without one takes its nearest parent's, so
+//! trusting `~/src` covers every checkout in it. A folder nobody decided on
+//! is untrusted. Features that would run a command only the workspace
+//! defines fail with UNTRUSTED, for the window to ask.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::{paths, workspace};
+
+pub const FILE_NAME: &str = "workspace-trust.json";
+/// The error of a feature held back until the workspace is trusted.
+pub const UNTRUSTED: &str = "workspace_untrusted";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    path: String,
+    trusted: bool,
+}
+
+#[derive(Default)]
+pub struct WorkspaceTrust {
+    entries: Mutex<Vec<Entry>>,
+    /// Untrusted roots the windows were told of this session
+    announced: Mutex<HashSet<PathBuf>>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TrustRequired {
+    root: String,
+    /// What the workspace defines that's held back, like `formatters` or
+    /// `plugins`
+    restricted: Vec<String>,
+}
+
+fn canonical(root: &Path) -> PathBuf {
+    std::fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf())
+}
+
+/// The decision for `root` or its nearest parent with one.
+fn decide(entries: &[Entry], root: &Path) -> bool {
+    root.ancestors().find_map(|dir| entries.iter().find(|e| paths::decode(&e.path) == dir).map(|e| e.trusted)).unwrap_or(false)
+}
+
+/// Records a decision for `root`, which replaces those for folders in it.
+fn set(entries: &mut Vec<Entry>, root: &Path, trusted: bool) {
+    entries.retain(|e| !paths::decode(&e.path).starts_with(root));
+    // Untrusted is what's left anyway
+    if trusted || decide(entries, root) {
+        entries.push(Entry { path: paths::encode(root), trusted });
+    }
+}
+
+fn file(app: &AppHandle) -> Result<PathBuf, String> {
+    paths::data_dir(app).map(|dir| dir.join(FILE_NAME))
+}
+
+pub fn load(app: &AppHandle) -> WorkspaceTrust {
+    let entries = file(app)
+        .ok()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default();
+    WorkspaceTrust { entries: Mutex::new(entries), announced: Mutex::default() }
+}
+
+fn save(app: &AppHandle, entries: &[Entry]) -> Result<(), String> {
+    let path = file(app)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let json = serde_json::to_string_pretty(entries).map_err(|e| e.to_string())?;
+    paths::write_atomic(&path, json).map_err(|e| e.to_string())
+}
+
+pub fn is_trusted(app: &AppHandle, root: &Path) -> bool {
+    decide(&app.state::<WorkspaceTrust>().entries.lock().unwrap(), &canonical(root))
+}
+
+/// Tells the window that its untrusted workspace holds things back, once a
+/// session for each folder.
+pub fn opened(app: &AppHandle, label: &str, root: &Path, restricted: Vec<String>) {
+    if is_trusted(app, root) || !app.state::<WorkspaceTrust>().announced.lock().unwrap().insert(canonical(root)) {
+        return;
+    }
+    let _ = app.emit_to(label, "workspace-trust-required", TrustRequired { root: paths::encode(root), restricted });
+}
+
+#[tauri::command]
+pub fn get_workspace_trust(app: AppHandle, root: String) -> bool {
+    is_trusted(&app, &paths::decode(&root))
+}
+
+/// Trusts `root` and the folders in it, or stops trusting them, and applies
+/// that to the open workspaces.
+#[tauri::command]
+pub fn set_workspace_trust(app: AppHandle, root: String, trusted: bool) -> Result<(), String> {
+    {
+        let trust = app.state::<WorkspaceTrust>();
+        let mut entries = trust.entries.lock().unwrap();
+        set(&mut entries, &canonical(&paths::decode(&root)), trusted);
+        save(&app, &entries)?;
+    }
+    log::info!("{} workspace {}", if trusted { "Trusted" } else { "Restricted" }, root);
+    workspace::trust_changed(&app);
+    let _ = app.emit("plugins-changed", ());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folders_take_their_parents_decision() {
+        let mut entries = Vec::new();
+        let (src, repo) = (Path::new("/home/u/src"), Path::new("/home/u/src/repo"));
+        assert!(!decide(&entries, repo));
+        set(&mut entries, repo, false);
+        assert!(entries.is_empty());
+        set(&mut entries, src, true);
+        assert!(decide(&entries, repo) && decide(&entries, &repo.join("sub")));
+        assert!(!decide(&entries, Path::new("/home/u/srcs")));
+        set(&mut entries, repo, false);
+        assert!(!decide(&entries, repo) && decide(&entries, src));
+        // Trusting the parent again covers the child again
+        set(&mut entries, src, true);
+        assert_eq!(entries, [Entry { path: "/home/u/src".into(), trusted: true }]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn trusts_roots_whose_names_arent_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+        let root = Path::new(std::ffi::OsStr::from_bytes(b"/home/u/caf\xe9"));
+        let mut entries = Vec::new();
+        set(&mut entries, root, true);
+        let stored: Vec<Entry> = serde_json::from_str(&serde_json::to_string(&entries).unwrap()).unwrap();
+        assert!(decide(&stored, root) && decide(&stored, &root.join("repo")));
+        assert!(!decide(&stored, Path::new("/home/u/caf\u{fffd}")));
+        set(&mut entries, root, false);
+        assert!(entries.is_empty());
+    }
+}
@@ -6,10 +6,41 @@ let _monaco: typeof Monaco | null = null;
 export async function loadMonaco(): Promise<typeof Monaco> {
   if (!_monaco) {
     _monaco = await import('monaco-editor');
+    registerToml(_monaco);
   }
   return _monaco;
 }
 
+// Monaco has no TOML of its own. This covers small files; big ones get the
+// backend's grammar.
+function registerToml(monaco: typeof Monaco): void {
+  monaco.languages.register({ id: 'toml', extensions: ['.toml'], aliases: ['TOML'] });
+  monaco.languages.setLanguageConfiguration('toml', {
+    comments: { lineComment: '#' },
+    brackets: [['[', ']'], ['{', '}']],
+    autoClosingPairs: [{ open: '[', close: ']' }, { open: '{', close: '}' }, { open: '"', close: '"' }, { open: "'", close: "'" }],
+  });
+  monaco.languages.setMonarchTokensProvider('toml', {
+    tokenizer: {
+      root: [
+        [/#.*$/, 'comment'],
+        [/^\s*\[\[?[^\]]*\]\]?/, 'type'],
+        [/[A-Za-z0-9_-]+(?=\s*=)/, 'variable'],
+        [/"""/, 'string', '@multiline'],
+        [/"([^"\\]|\\.)*"/, 'string'],
+        [/'[^']*'/, 'string'],
+        [/\b(true|false)\b/, 'keyword'],
+        [/\d{4}-\d{2}-\d{2}([T ][\d:.]+)?(Z|[+-]\d{2}:\d{2})?/, 'number'],
+        [/[+-]?(\d[\d_]*(\.\d+)?([eE][+-]?\d+)?|0x[\da-fA-F_]+|inf|nan)/, 'number'],
+      ],
+      multiline: [
+        [/"""/, 'string', '@pop'],
+        [/./, 'string'],
+      ],
+    },
+  });
+}
+
 // Language mapping based on file extension
 export function getLanguageFromFilename(filename: string): string {
   const name = filename.split('/').pop()?.toLowerCase() || '';
@@ -66,7 +97,7 @@ export function getLanguageFromFilename(filename: string): string {
     // Config
     'ini': 'ini',
     'conf': 'ini',
-    'toml': 'ini',
+    'toml': 'toml',
     'properties': 'ini',
   };
 
@@ -97,6 +128,7 @@ const displayNames: Record<string, string> = {
   shell: 'Shell',
   powershell: 'PowerShell',
   bat: 'Batch',
+  toml: 'TOML',
   ini: 'INI',
   plaintext: 'Plain Text',
 };
@@ -106,6 +138,43 @@ export function getLanguageDisplayName(filename: string): string {
   return displayNames[lang] || lang;
 }
 
+export function getLanguageDisplayNameById(languageId: string): string {
+  return displayNames[languageId] || languageId;
+}
+
+export type ColorTheme = {
+  id: string;
+  name: string;
+  kind: 'light' | 'dark';
+  colors: Record<string, string>;
+  rules: Monaco.editor.ITokenThemeRule[];
+};
+
+// A theme from the themes folder, used over simple-light/simple-dark
+let colorTheme: ColorTheme | null = null;
+
+function themeName(darkMode: boolean): string {
+  if (colorTheme) return 'color-theme';
+  return darkMode ? 'simple-dark' : 'simple-light';
+}
+
+function defineColorTheme() {
+  if (!colorTheme) return;
+  _monaco?.editor.defineTheme('color-theme', {
+    base: colorTheme.kind === 'dark' ? 'vs-dark' : 'vs',
+    inherit: true,
+    rules: colorTheme.rules,
+    colors: colorTheme.colors,
+  });
+}
+
+// Redefining it in place is what lets edits to the file show as they're saved
+export function setColorTheme(theme: ColorTheme | null, darkMode: boolean): void {
+  colorTheme = theme;
+  defineColorTheme();
+  _monaco?.editor.setTheme(themeName(darkMode));
+}
+
 // Define themes and register link opener
 export function setupThemes() {
   _monaco!.editor.registerLinkOpener({
@@ -146,6 +215,7 @@ export function setupThemes() {
       'editorCursor.foreground': '#d4d4d4',
     },
   });
+  defineColorTheme();
 }
 
 // One Monaco model per tab so that undo history (and, via saved view state,
@@ -158,24 +228,596 @@ interface TabModelEntry {
 const tabModels = new Map<string, TabModelEntry>();
 const tabViewStates = new Map<string, Monaco.editor.ICodeEditorViewState>();
 
+export type Indentation = { insertSpaces: boolean; tabSize: number; indentSize: number };
+
 export function getTabModel(
   tabId: string,
   content: string,
   filename: string,
-  onChange: (content: string) => void
+  onChange: (content: string) => void,
+  language?: string,
+  indentation?: Indentation
 ): Monaco.editor.ITextModel {
   let entry = tabModels.get(tabId);
   if (!entry) {
-    const model = _monaco!.editor.createModel(content, getLanguageFromFilename(filename));
-    const changeSub = model.onDidChangeContent(() => onChange(model.getValue()));
+    const model = _monaco!.editor.createModel(content, language ?? getLanguageFromFilename(filename));
+    if (indentation) model.updateOptions(indentation);
+    const changeSub = model.onDidChangeContent((e) => {
+      noteSpellingChanges(tabId, e.changes);
+      forwardHighlightEdits(tabId, e.changes);
+      forwardWordEdits(tabId, e.changes);
+      onChange(model.getValue());
+    });
     entry = { model, changeSub };
     tabModels.set(tabId, entry);
+    for (const [source, items] of tabDiagnostics.get(tabId) ?? []) applyDiagnostics(model, source, items);
+    startBackendHighlighting(tabId, model);
+    startWordIndex(tabId, model);
   }
   return entry.model;
 }
 
+export function setTabLanguage(tabId: string, languageId: string): void {
+  const entry = tabModels.get(tabId);
+  if (entry && _monaco) {
+    stopBackendHighlighting(tabId);
+    _monaco.editor.setModelLanguage(entry.model, languageId);
+    startBackendHighlighting(tabId, entry.model);
+  }
+}
+
+// Replaces the model's text, e.g. after re-reading the file in another encoding
+export function setTabContent(tabId: string, content: string): void {
+  tabModels.get(tabId)?.model.setValue(content);
+}
+
+// Replaces the text as one undoable edit, e.g. after saving trimmed whitespace
+export function replaceTabContent(tabId: string, content: string): void {
+  const model = tabModels.get(tabId)?.model;
+  if (!model) return;
+  model.pushEditOperations([], [{ range: model.getFullModelRange(), text: content }], () => null);
+}
+
+// Indentation from .editorconfig, which wins over what Monaco detects
+export function setTabIndentation(tabId: string, indentation: Indentation | undefined): void {
+  if (indentation) tabModels.get(tabId)?.model.updateOptions(indentation);
+}
+
+export function getTabModelById(tabId: string): Monaco.editor.ITextModel | undefined {
+  return tabModels.get(tabId)?.model;
+}
+
+export function setTabEol(tabId: string, eol: 'lf' | 'crlf'): void {
+  const entry = tabModels.get(tabId);
+  if (entry && _monaco) {
+    const { EndOfLineSequence } = _monaco.editor;
+    entry.model.setEOL(eol === 'crlf' ? EndOfLineSequence.CRLF : EndOfLineSequence.LF);
+  }
+}
+
+export type GitHunk = {
+  kind: 'added' | 'modified' | 'deleted';
+  start: number;
+  end: number;
+  oldStart: number;
+  oldLines: number;
+  original?: string;
+};
+
+// Gutter markers for changes against HEAD, stored on the model so every pane
+// showing the tab gets them
+const gitDecorations = new Map<string, string[]>();
+
+export function setTabGitHunks(tabId: string, hunks: GitHunk[]): void {
+  const model = tabModels.get(tabId)?.model;
+  if (!model || !_monaco) return;
+  const lastLine = model.getLineCount();
+  const decorations = hunks.map((hunk) => {
+    // Deleted hunks mark the line the removed ones followed
+    const start = Math.min(Math.max(hunk.start, 1), lastLine);
+    const end = Math.min(Math.max(hunk.end, start), lastLine);
+    return {
+      range: new _monaco!.Range(start, 1, end, 1),
+      options: {
+        isWholeLine: true,
+        linesDecorationsClassName: `git-gutter-${hunk.kind}${hunk.kind === 'deleted' && hunk.start === 0 ? ' git-gutter-top' : ''}`,
+      },
+    };
+  });
+  gitDecorations.set(tabId, model.deltaDecorations(gitDecorations.get(tabId) ?? [], decorations));
+}
+
+// Hover text for a line of a tab, e.g. its git blame
+export type LineHover = (tabId: string, line: number) => Promise<string | null>;
+
+export function registerLineHover(provider: LineHover): Monaco.IDisposable {
+  return _monaco!.languages.registerHoverProvider('*', {
+    provideHover: async (model, position) => {
+      const tabId = [...tabModels].find(([, entry]) => entry.model === model)?.[0];
+      const text = tabId ? await provider(tabId, position.lineNumber) : null;
+      if (!text) return null;
+      return {
+        range: new _monaco!.Range(position.lineNumber, 1, position.lineNumber, model.getLineMaxColumn(position.lineNumber)),
+        contents: [{ value: text }],
+      };
+    },
+  });
+}
+
+export type Misspelling = { line: number; startColumn: number; endColumn: number; word: string };
+export type SpellingChunk = { line: number; text: string };
+
+// Lines edited since the last spell check, or 'all' once lines were added or
+// removed, since that moves every marker below
+const spellingPending = new Map<string, { from: number; to: number } | 'all'>();
+
+function noteSpellingChanges(tabId: string, changes: Monaco.editor.IModelContentChange[]): void {
+  let pending = spellingPending.get(tabId);
+  for (const { range, text } of changes) {
+    const added = text.split('\n').length - 1;
+    if (pending === 'all' || added !== range.endLineNumber - range.startLineNumber) {
+      pending = 'all';
+      continue;
+    }
+    const from = Math.min(pending?.from ?? Infinity, range.startLineNumber);
+    const to = Math.max(pending?.to ?? 0, range.endLineNumber);
+    pending = { from, to };
+  }
+  if (pending) spellingPending.set(tabId, pending);
+}
+
+// What to send for checking: the edited lines, or the whole text when the tab
+// hasn't been checked yet. Empty when nothing changed.
+export function takeSpellingChunks(tabId: string): SpellingChunk[] {
+  const model = tabModels.get(tabId)?.model;
+  if (!model) return [];
+  const checked = spellingMarkers.has(tabId);
+  const pending = spellingPending.get(tabId);
+  spellingPending.delete(tabId);
+  if (!checked || pending === 'all') return [{ line: 1, text: model.getValue() }];
+  if (!pending) return [];
+  const lines = [];
+  for (let line = pending.from; line <= pending.to; line++) lines.push(model.getLineContent(line));
+  return [{ line: pending.from, text: lines.join('\n') }];
+}
+
+// Squiggles for misspelled words, replacing those on the lines just checked
+const spellingMarkers = new Map<string, Misspelling[]>();
+
+export function setTabMisspellings(tabId: string, chunks: SpellingChunk[], found: Misspelling[]): void {
+  const model = tabModels.get(tabId)?.model;
+  if (!model || !_monaco) return;
+  const checkedLines = (m: Misspelling) =>
+    chunks.some((c) => m.line >= c.line && m.line < c.line + c.text.split('\n').length);
+  const kept = (spellingMarkers.get(tabId) ?? []).filter((m) => !checkedLines(m));
+  const all = [...kept, ...found];
+  spellingMarkers.set(tabId, all);
+  _monaco.editor.setModelMarkers(
+    model,
+    'spelling',
+    all.map((m) => ({
+      severity: _monaco!.MarkerSeverity.Info,
+      message: `Unknown word “${m.word}”`,
+      source: 'spelling',
+      startLineNumber: m.line,
+      endLineNumber: m.line,
+      startColumn: m.startColumn,
+      endColumn: m.endColumn,
+    })),
+  );
+}
+
+export function clearTabMisspellings(tabId: string): void {
+  const model = tabModels.get(tabId)?.model;
+  spellingMarkers.delete(tabId);
+  spellingPending.delete(tabId);
+  if (model && _monaco) _monaco.editor.setModelMarkers(model, 'spelling', []);
+}
+
+// Problems from a linter run, kept for tabs whose model doesn't exist yet
+export type Diagnostic = {
+  path: string;
+  line: number;
+  column: number;
+  endLine: number | null;
+  endColumn: number | null;
+  severity: 'error' | 'warning' | 'info' | 'hint';
+  message: string;
+  code: string | null;
+};
+const tabDiagnostics = new Map<string, Map<string, Diagnostic[]>>();
+
+function applyDiagnostics(model: Monaco.editor.ITextModel, source: string, items: Diagnostic[]): void {
+  const { MarkerSeverity } = _monaco!;
+  const severities = { error: MarkerSeverity.Error, warning: MarkerSeverity.Warning, info: MarkerSeverity.Info, hint: MarkerSeverity.Hint };
+  _monaco!.editor.setModelMarkers(
+    model,
+    `lint:${source}`,
+    items.filter((d) => d.line <= model.getLineCount()).map((d) => {
+      const endLineNumber = d.endLine ?? d.line;
+      return {
+        severity: severities[d.severity],
+        message: d.message,
+        source,
+        code: d.code ?? undefined,
+        startLineNumber: d.line,
+        startColumn: d.column,
+        endLineNumber,
+        // A bare position marks the rest of the word
+        endColumn: d.endColumn ?? model.getWordAtPosition({ lineNumber: d.line, column: d.column })?.endColumn ?? d.column + 1,
+      };
+    }),
+  );
+}
+
+export function setTabDiagnostics(tabId: string, source: string, items: Diagnostic[]): void {
+  const sources = tabDiagnostics.get(tabId) ?? new Map<string, Diagnostic[]>();
+  sources.set(source, items);
+  tabDiagnostics.set(tabId, sources);
+  const model = tabModels.get(tabId)?.model;
+  if (model && _monaco) applyDiagnostics(model, source, items);
+}
+
+// Quick fixes on a misspelled word: the suggestions, then add or ignore it
+export type SpellingFixes = {
+  suggestions: (tabId: string, word: string) => Promise<string[]>;
+  add: (tabId: string, word: string) => void;
+  ignore: (tabId: string, word: string) => void;
+};
+
+export function registerSpellingFixes(fixes: SpellingFixes): Monaco.IDisposable {
+  const commands = [
+    _monaco!.editor.registerCommand('skriv.spelling.add', (_, tabId: string, word: string) => fixes.add(tabId, word)),
+    _monaco!.editor.registerCommand('skriv.spelling.ignore', (_, tabId: string, word: string) => fixes.ignore(tabId, word)),
+  ];
+  const provider = _monaco!.languages.registerCodeActionProvider('*', {
+    provideCodeActions: async (model, _range, context) => {
+      const tabId = [...tabModels].find(([, entry]) => entry.model === model)?.[0];
+      const marker = context.markers.find((m) => m.owner === 'spelling');
+      if (!tabId || !marker) return { actions: [], dispose: () => {} };
+      const word = model.getValueInRange(marker);
+      const suggestions = await fixes.suggestions(tabId, word);
+      const actions: Monaco.languages.CodeAction[] = suggestions.slice(0, 5).map((suggestion) => ({
+        title: `Change to “${suggestion}”`,
+        kind: 'quickfix',
+        diagnostics: [marker],
+        edit: { edits: [{ resource: model.uri, versionId: model.getVersionId(), textEdit: { range: marker, text: suggestion } }] },
+      }));
+      actions.push(
+        { title: `Add “${word}” to Dictionary`, kind: 'quickfix', command: { id: 'skriv.spelling.add', title: '', arguments: [tabId, word] } },
+        { title: `Ignore “${word}”`, kind: 'quickfix', command: { id: 'skriv.spelling.ignore', title: '', arguments: [tabId, word] } },
+      );
+      return { actions, dispose: () => {} };
+    },
+  });
+  return { dispose: () => [...commands, provider].forEach((d) => d.dispose()) };
+}
+
+export type Snippet = { name: string; prefixes: string[]; body: string; description: string | null };
+export type SnippetSource = {
+  snippets: (language: string) => Promise<Snippet[]>;
+  // Values for the variables the backend knows, like WORKSPACE_NAME
+  variables: (tabId: string, names: string[]) => Promise<Record<string, string>>;
+};
+
+const SNIPPET_VARIABLE = /\$(?:\{([A-Z_]+)(?=[}:])|([A-Z_]+))/g;
+
+function escapeSnippetText(text: string): string {
+  return text.replace(/[\\$}]/g, (c) => `\\${c}`);
+}
+
+// Puts known values in for $NAME, ${NAME} and ${NAME:default}, leaving the
+// rest to Monaco's own snippet variables
+function fillSnippetVariables(body: string, values: Record<string, string>): string {
+  let out = '';
+  let i = 0;
+  while (i < body.length) {
+    if (body[i] === '\\') {
+      out += body.slice(i, i + 2);
+      i += 2;
+      continue;
+    }
+    SNIPPET_VARIABLE.lastIndex = i;
+    const match = body[i] === '$' ? SNIPPET_VARIABLE.exec(body) : null;
+    if (!match || match.index !== i) {
+      out += body[i++];
+      continue;
+    }
+    const name = match[1] ?? match[2];
+    let end = i + match[0].length;
+    if (match[1] !== undefined) {
+      // Skip to the brace closing this one, past any default
+      let depth = 1;
+      while (end < body.length && depth > 0) {
+        if (body[end] === '\\') end++;
+        else if (body[end] === '{') depth++;
+        else if (body[end] === '}') depth--;
+        end++;
+      }
+    }
+    out += name in values ? escapeSnippetText(values[name]) : body.slice(i, end);
+    i = end;
+  }
+  return out;
+}
+
+export function registerSnippets(source: SnippetSource): Monaco.IDisposable {
+  return _monaco!.languages.registerCompletionItemProvider('*', {
+    provideCompletionItems: async (model, position) => {
+      const tabId = [...tabModels].find(([, entry]) => entry.model === model)?.[0];
+      const word = model.getWordUntilPosition(position);
+      if (!tabId || !word.word) return { suggestions: [] };
+      const typed = word.word.toLowerCase();
+      const matching = (await source.snippets(tabLanguage(tabId, model))).filter((snippet) =>
+        snippet.prefixes.some((prefix) => prefix.toLowerCase().startsWith(typed))
+      );
+      if (matching.length === 0) return { suggestions: [] };
+      const names = new Set(matching.flatMap((snippet) => [...snippet.body.matchAll(SNIPPET_VARIABLE)].map((m) => m[1] ?? m[2])));
+      const values = names.size > 0 ? await source.variables(tabId, [...names]) : {};
+      const range = new _monaco!.Range(position.lineNumber, word.startColumn, position.lineNumber, word.endColumn);
+      return {
+        suggestions: matching.flatMap((snippet) =>
+          snippet.prefixes.map((prefix) => ({
+            label: prefix,
+            detail: snippet.description ?? snippet.name,
+            documentation: { value: '```\n' + snippet.body + '\n```' },
+            kind: _monaco!.languages.CompletionItemKind.Snippet,
+            insertText: fillSnippetVariables(snippet.body, values),
+            insertTextRules: _monaco!.languages.CompletionItemInsertTextRule.InsertAsSnippet,
+            range,
+          }))
+        ),
+      };
+    },
+  });
+}
+
+// Highlighting done by the backend, for files big enough that tokenizing them
+// here stalls scrolling. Positions are 0-based; token offsets are in bytes.
+export type HighlightEdit = { startLine: number; startColumn: number; endLine: number; endColumn: number; text: string };
+export type HighlightTokens = { line: number; spans: { scope: string; start: number; end: number }[] };
+export type HighlightBackend = {
+  // False when the backend has no grammar for the language
+  open: (tabId: string, language: string, content: string) => Promise<boolean>;
+  edit: (tabId: string, edit: HighlightEdit) => void;
+  highlights: (tabId: string, startLine: number, endLine: number) => Promise<HighlightTokens[]>;
+  foldingRanges: (tabId: string) => Promise<{ start: number; end: number }[]>;
+  close: (tabId: string) => void;
+};
+
+const BACKEND_HIGHLIGHT_SIZE = 1024 * 1024;
+const HIGHLIGHT_TOKEN_TYPES = ['comment', 'regexp', 'string', 'number', 'keyword', 'operator', 'type', 'function', 'property', 'parameter', 'variable'];
+let highlightBackend: HighlightBackend | null = null;
+// The language each backend-highlighted tab is really in
+const backendHighlighted = new Map<string, string>();
+// Tokens read_document brought for a tab's first lines, and once its model
+// exists, what paints them until the backend session is open
+const firstScreens = new Map<string, HighlightTokens[]>();
+const firstPaints = new Map<string, { version: number; lines: HighlightTokens[]; opened: Promise<boolean> }>();
+
+export function setFirstScreen(tabId: string, lines: HighlightTokens[]): void {
+  firstScreens.set(tabId, lines);
+}
+
+function tabLanguage(tabId: string, model: Monaco.editor.ITextModel): string {
+  return backendHighlighted.get(tabId) ?? model.getLanguageId();
+}
+
+async function startBackendHighlighting(tabId: string, model: Monaco.editor.ITextModel): Promise<void> {
+  const language = model.getLanguageId();
+  const firstScreen = firstScreens.get(tabId);
+  firstScreens.delete(tabId);
+  if (!highlightBackend || backendHighlighted.has(tabId) || language === 'plaintext' || model.getValueLength() < BACKEND_HIGHLIGHT_SIZE) return;
+  const version = model.getVersionId();
+  const opened = highlightBackend.open(tabId, language, model.getValue()).catch(() => false);
+  if (firstScreen) return paintFirstScreen(tabId, model, language, { version, lines: firstScreen, opened });
+  if (!(await opened)) return;
+  if (model.isDisposed() || model.getLanguageId() !== language) return highlightBackend.close(tabId);
+  // Edited while the backend was reading it
+  if (model.getVersionId() !== version) return startBackendHighlighting(tabId, model);
+  backendHighlighted.set(tabId, language);
+  // Keeps Monaco's own tokenizer off the file; the tokens come from the provider below
+  _monaco!.editor.setModelLanguage(model, 'plaintext');
+}
+
+// Switches to the backend's tokens straight away, the first screen's until
+// the session is open, and back to Monaco's if it doesn't open after all
+async function paintFirstScreen(tabId: string, model: Monaco.editor.ITextModel, language: string, paint: { version: number; lines: HighlightTokens[]; opened: Promise<boolean> }): Promise<void> {
+  firstPaints.set(tabId, paint);
+  backendHighlighted.set(tabId, language);
+  _monaco!.editor.setModelLanguage(model, 'plaintext');
+  // Edits made meanwhile were forwarded, and reach the session after it opens
+  const opened = await paint.opened;
+  // A language switch or closing the tab stopped it meanwhile
+  if (!firstPaints.delete(tabId) || opened) return;
+  backendHighlighted.delete(tabId);
+  if (!model.isDisposed()) _monaco!.editor.setModelLanguage(model, language);
+}
+
+function stopBackendHighlighting(tabId: string): void {
+  firstScreens.delete(tabId);
+  firstPaints.delete(tabId);
+  if (backendHighlighted.delete(tabId)) highlightBackend?.close(tabId);
+}
+
+function backendEdit({ range, text }: Monaco.editor.IModelContentChange): HighlightEdit {
+  return {
+    startLine: range.startLineNumber - 1,
+    startColumn: range.startColumn - 1,
+    endLine: range.endLineNumber - 1,
+    endColumn: range.endColumn - 1,
+    text,
+  };
+}
+
+function forwardHighlightEdits(tabId: string, changes: Monaco.editor.IModelContentChange[]): void {
+  if (!backendHighlighted.has(tabId)) return;
+  for (const change of changes) highlightBackend?.edit(tabId, backendEdit(change));
+}
+
+// The UTF-16 column of a byte offset into `text`
+function utf16Column(text: string, byte: number): number {
+  let bytes = 0;
+  let column = 0;
+  for (const c of text) {
+    if (bytes >= byte) break;
+    const code = c.codePointAt(0)!;
+    bytes += code < 0x80 ? 1 : code < 0x800 ? 2 : code < 0x10000 ? 3 : 4;
+    column += c.length;
+  }
+  return column;
+}
+
+export function registerHighlighting(backend: HighlightBackend): Monaco.IDisposable {
+  highlightBackend = backend;
+  const tabIdOf = (model: Monaco.editor.ITextModel) =>
+    [...tabModels].find(([id, entry]) => entry.model === model && backendHighlighted.has(id))?.[0];
+  const tokens = _monaco!.languages.registerDocumentRangeSemanticTokensProvider('plaintext', {
+    getLegend: () => ({ tokenTypes: HIGHLIGHT_TOKEN_TYPES, tokenModifiers: [] }),
+    provideDocumentRangeSemanticTokens: async (model, range) => {
+      const tabId = tabIdOf(model);
+      if (!tabId) return null;
+      const paint = firstPaints.get(tabId);
+      let lines: HighlightTokens[];
+      if (paint && model.getVersionId() === paint.version && range.endLineNumber <= paint.lines.length) {
+        lines = paint.lines.slice(range.startLineNumber - 1, range.endLineNumber);
+      } else {
+        if (paint && !(await paint.opened)) return null;
+        lines = await backend.highlights(tabId, range.startLineNumber - 1, range.endLineNumber);
+      }
+      const data: number[] = [];
+      let previousLine = 0;
+      let previousStart = 0;
+      for (const { line, spans } of lines) {
+        if (line >= model.getLineCount()) break;
+        const text = model.getLineContent(line + 1);
+        for (const span of spans) {
+          const start = utf16Column(text, span.start);
+          const type = HIGHLIGHT_TOKEN_TYPES.indexOf(span.scope);
+          if (type < 0) continue;
+          data.push(line - previousLine, line === previousLine ? start - previousStart : start, utf16Column(text, span.end) - start, type, 0);
+          previousLine = line;
+          previousStart = start;
+        }
+      }
+      return { data: new Uint32Array(data) };
+    },
+  });
+  const folding = _monaco!.languages.registerFoldingRangeProvider('plaintext', {
+    provideFoldingRanges: async (model) => {
+      const tabId = tabIdOf(model);
+      if (!tabId) return [];
+      // Folds stop short of the closing line, so a closing brace stays visible
+      return (await backend.foldingRanges(tabId)).map(({ start, end }) => ({ start: start + 1, end }));
+    },
+  });
+  for (const [tabId, { model }] of tabModels) startBackendHighlighting(tabId, model);
+  return {
+    dispose: () => {
+      tokens.dispose();
+      folding.dispose();
+      [...backendHighlighted.keys()].forEach(stopBackendHighlighting);
+      highlightBackend = null;
+    },
+  };
+}
+
+// Word completion from an index in the backend, for files big enough that
+// Monaco's own word-based suggestions stall typing. Edits go there as they
+// do for highlighting.
+export type WordCompletion = { word: string; count: number };
+export type WordBackend = {
+  open: (tabId: string, content: string) => Promise<void>;
+  edit: (tabId: string, edit: HighlightEdit) => void;
+  // `current` is the whole word at the cursor
+  complete: (tabId: string, prefix: string, current: string, limit: number) => Promise<WordCompletion[]>;
+  close: (tabId: string) => void;
+};
+
+const WORD_INDEX_SIZE = BACKEND_HIGHLIGHT_SIZE;
+const WORD_COMPLETIONS = 50;
+let wordBackend: WordBackend | null = null;
+// Tabs big enough to be indexed, which Monaco's word suggestions are off for,
+// and those whose index is open
+const wordIndexed = new Set<string>();
+const wordIndexOpen = new Set<string>();
+
+function startWordIndex(tabId: string, model: Monaco.editor.ITextModel): void {
+  if (!wordBackend || wordIndexed.has(tabId) || model.getValueLength() < WORD_INDEX_SIZE) return;
+  wordIndexed.add(tabId);
+  openWordIndex(tabId, model);
+}
+
+async function openWordIndex(tabId: string, model: Monaco.editor.ITextModel): Promise<void> {
+  const version = model.getVersionId();
+  const opened = await wordBackend!.open(tabId, model.getValue()).then(() => true, () => false);
+  // The tab closed meanwhile
+  if (!wordIndexed.has(tabId)) {
+    if (opened) wordBackend?.close(tabId);
+    return;
+  }
+  if (!opened) return;
+  // Edited while the backend was reading it
+  if (model.getVersionId() !== version) return openWordIndex(tabId, model);
+  wordIndexOpen.add(tabId);
+}
+
+function stopWordIndex(tabId: string): void {
+  wordIndexed.delete(tabId);
+  if (wordIndexOpen.delete(tabId)) wordBackend?.close(tabId);
+}
+
+function forwardWordEdits(tabId: string, changes: Monaco.editor.IModelContentChange[]): void {
+  if (!wordIndexOpen.has(tabId)) return;
+  for (const change of changes) wordBackend?.edit(tabId, backendEdit(change));
+}
+
+function isWordIndexed(model: Monaco.editor.ITextModel | null): boolean {
+  return [...wordIndexed].some((tabId) => tabModels.get(tabId)?.model === model);
+}
+
+export function registerWordCompletion(backend: WordBackend): Monaco.IDisposable {
+  wordBackend = backend;
+  const provider = _monaco!.languages.registerCompletionItemProvider('*', {
+    provideCompletionItems: async (model, position) => {
+      const tabId = [...wordIndexOpen].find((id) => tabModels.get(id)?.model === model);
+      const word = model.getWordUntilPosition(position);
+      if (!tabId || !word.word) return { suggestions: [] };
+      const current = model.getWordAtPosition(position)?.word ?? word.word;
+      const words = await backend.complete(tabId, word.word, current, WORD_COMPLETIONS);
+      const range = new _monaco!.Range(position.lineNumber, word.startColumn, position.lineNumber, word.endColumn);
+      return {
+        suggestions: words.map(({ word }, i) => ({
+          label: word,
+          kind: _monaco!.languages.CompletionItemKind.Text,
+          insertText: word,
+          // Most frequent first, as the backend ranked them
+          sortText: String(i).padStart(4, '0'),
+          range,
+        })),
+        // Typing on asks again, for the words past the limit
+        incomplete: words.length === WORD_COMPLETIONS,
+      };
+    },
+  });
+  for (const [tabId, { model }] of tabModels) startWordIndex(tabId, model);
+  return {
+    dispose: () => {
+      provider.dispose();
+      [...wordIndexed].forEach(stopWordIndex);
+      wordBackend = null;
+    },
+  };
+}
+
 export function disposeTabModel(tabId: string): void {
   const entry = tabModels.get(tabId);
+  stopBackendHighlighting(tabId);
+  stopWordIndex(tabId);
+  gitDecorations.delete(tabId);
+  spellingMarkers.delete(tabId);
+  tabDiagnostics.delete(tabId);
+  spellingPending.delete(tabId);
   if (entry) {
     entry.changeSub.dispose();
     entry.model.dispose();
@@ -194,6 +836,9 @@ export function restoreTabViewState(tabId: string, editor: Monaco.editor.IStanda
   if (viewState) editor.restoreViewState(viewState);
 }
 
+export const DEFAULT_FONT_SIZE = 14;
+export const DEFAULT_FONT_FAMILY = "'JetBrains Mono', 'Fira Code', 'Consolas', monospace";
+
 export function createEditor(
   container: HTMLElement,
   darkMode: boolean,
@@ -202,11 +847,11 @@ export function createEditor(
 ): Monaco.editor.IStandaloneCodeEditor {
   // Created without a model; the active tab's model is attached via setModel.
   const editor = _monaco!.editor.create(container, {
-    theme: darkMode ? 'simple-dark' : 'simple-light',
+    theme: themeName(darkMode),
     automaticLayout: true,
     minimap: { enabled: false },
-    fontSize: 14,
-    fontFamily: "'JetBrains Mono', 'Fira Code', 'Consolas', monospace",
+    fontSize: DEFAULT_FONT_SIZE,
+    fontFamily: DEFAULT_FONT_FAMILY,
     lineNumbers: 'on',
     renderWhitespace: 'selection',
     scrollBeyondLastLine: false,
@@ -215,6 +860,8 @@ export function createEditor(
     insertSpaces: true,
     folding: true,
     columnSelection,
+    // Tokens for files highlighted by the backend
+    'semanticHighlighting.enabled': true,
     // Better find/replace
     find: {
       addExtraSpaceOnTop: false,
@@ -223,6 +870,11 @@ export function createEditor(
     },
   });
 
+  // The words of files indexed in the backend come from there instead
+  editor.onDidChangeModel(() => {
+    editor.updateOptions({ wordBasedSuggestions: isWordIndexed(editor.getModel()) ? 'off' : 'matchingDocuments' });
+  });
+
   // Register "Install CLI" command in the command palette
   editor.addAction({
     id: 'skriv.installCli',
@@ -243,6 +895,18 @@ export function createEditor(
     },
   });
 
+  // Register theme mode commands in the command palette
+  for (const [mode, label] of [['system', 'System'], ['light', 'Light'], ['dark', 'Dark']]) {
+    editor.addAction({
+      id: `skriv.theme.${mode}`,
+      label: `Theme: ${label}`,
+      run: async () => {
+        const { invoke } = await import('@tauri-apps/api/core');
+        await invoke('set_theme_mode', { mode });
+      },
+    });
+  }
+
   return editor;
 }
 
@@ -259,5 +923,5 @@ export function setEditorLanguage(editor: Monaco.editor.IStandaloneCodeEditor, f
 }
 
 export function setEditorTheme(darkMode: boolean): void {
-  _monaco?.editor.setTheme(darkMode ? 'simple-dark' : 'simple-light');
+  _monaco?.editor.setTheme(themeName(darkMode));
 }
@@ -0,0 +1,112 @@
+const isMac = navigator.platform.startsWith('Mac');
+
+// Accelerator key names that don't map to a KeyboardEvent.code by prefixing
+const NAMED_CODES: Record<string, string> = {
+  '\\': 'backslash',
+  '/': 'slash',
+  ',': 'comma',
+  '.': 'period',
+  ';': 'semicolon',
+  "'": 'quote',
+  '[': 'bracketleft',
+  ']': 'bracketright',
+  '`': 'backquote',
+  '-': 'minus',
+  '=': 'equal',
+  up: 'arrowup',
+  down: 'arrowdown',
+  left: 'arrowleft',
+  right: 'arrowright',
+  esc: 'escape',
+  return: 'enter',
+};
+
+function codeMatches(code: string, key: string): boolean {
+  const c = code.toLowerCase();
+  if (/^[a-z]$/.test(key)) return c === `key${key}`;
+  if (/^[0-9]$/.test(key)) return c === `digit${key}`;
+  return c === (NAMED_CODES[key] ?? key);
+}
+
+// Whether a key event matches an accelerator from keybindings.json, such as
+// "CmdOrCtrl+Shift+P". Native menu accelerators don't fire while the webview
+// has focus on every platform, so the webview checks them too.
+export function matchesAccelerator(e: KeyboardEvent, accelerator: string | null | undefined): boolean {
+  if (!accelerator) return false;
+  const parts = accelerator.split('+').map((p) => p.trim().toLowerCase());
+  const key = parts.pop();
+  if (!key) return false;
+  let ctrl = false, meta = false, alt = false, shift = false;
+  for (const modifier of parts) {
+    switch (modifier) {
+      case 'cmdorctrl':
+      case 'commandorcontrol':
+        if (isMac) meta = true;
+        else ctrl = true;
+        break;
+      case 'ctrl':
+      case 'control':
+        ctrl = true;
+        break;
+      case 'cmd':
+      case 'command':
+      case 'super':
+      case 'meta':
+        meta = true;
+        break;
+      case 'alt':
+      case 'option':
+        alt = true;
+        break;
+      case 'shift':
+        shift = true;
+        break;
+    }
+  }
+  if (e.ctrlKey !== ctrl || e.metaKey !== meta || e.altKey !== alt || e.shiftKey !== shift) return false;
+  return codeMatches(e.code, key);
+}
+
+// A keymap entry from the backend. `key` is one press, or two separated by
+// a space.
+export type Binding = { key: string; command: string; when: string | null; user: boolean };
+
+// Whether a `when` clause holds: context names joined with && and negated
+// with !. Names skriv doesn't know count as false.
+export function whenHolds(when: string | null, context: Record<string, boolean>): boolean {
+  if (!when) return true;
+  return when.split('&&').every((term) => {
+    const name = term.trim();
+    return name.startsWith('!') ? !context[name.slice(1).trim()] : !!context[name];
+  });
+}
+
+const MODIFIER_KEYS = ['Control', 'Shift', 'Alt', 'Meta'];
+
+// Follows key presses through the keymap, remembering the first press of a
+// two-press chord until the next one
+export class ChordMatcher {
+  private pending: string | null = null;
+  // The editor and the window both see most key events
+  private last: KeyboardEvent | null = null;
+
+  // The command a press completes, 'pending' when it starts a chord
+  press(e: KeyboardEvent, keymap: Binding[], context: Record<string, boolean>): string | 'pending' | null {
+    if (e === this.last || MODIFIER_KEYS.includes(e.key)) return null;
+    this.last = e;
+    const active = keymap.filter((binding) => whenHolds(binding.when, context));
+    const presses = (binding: Binding) => binding.key.split(' ');
+    if (this.pending) {
+      const first = this.pending;
+      this.pending = null;
+      const hit = active.find((b) => presses(b)[0] === first && matchesAccelerator(e, presses(b)[1]));
+      return hit?.command ?? null;
+    }
+    const chord = active.find((b) => presses(b).length === 2 && matchesAccelerator(e, presses(b)[0]));
+    if (chord) {
+      this.pending = presses(chord)[0];
+      return 'pending';
+    }
+    return active.find((b) => presses(b).length === 1 && matchesAccelerator(e, b.key))?.command ?? null;
+  }
+}
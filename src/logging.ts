@@ -0,0 +1,42 @@
+import { invoke } from '@tauri-apps/api/core';
+
+type Level = 'error' | 'warn' | 'info' | 'debug';
+
+function send(level: Level, message: string, context?: Record<string, unknown>) {
+  // Never through the console, which would forward it again
+  invoke('log_from_frontend', { level, message, context: context ?? null }).catch(() => {});
+}
+
+function describe(value: unknown): string {
+  if (value instanceof Error) return `${value.name}: ${value.message}`;
+  if (typeof value === 'string') return value;
+  try {
+    return JSON.stringify(value);
+  } catch {
+    return String(value);
+  }
+}
+
+// Sends uncaught errors, rejections and console warnings and errors to the
+// backend log, which otherwise only the devtools would show
+export function forwardToBackendLog() {
+  window.addEventListener('error', (event) => {
+    send('error', event.message || describe(event.error), {
+      source: event.filename,
+      line: event.lineno,
+      column: event.colno,
+      stack: event.error instanceof Error ? event.error.stack : undefined,
+    });
+  });
+  window.addEventListener('unhandledrejection', (event) => {
+    const reason = event.reason;
+    send('error', `Unhandled rejection: ${describe(reason)}`, { stack: reason instanceof Error ? reason.stack : undefined });
+  });
+  for (const level of ['error', 'warn'] as const) {
+    const original = console[level].bind(console);
+    console[level] = (...args: unknown[]) => {
+      original(...args);
+      send(level, args.map(describe).join(' '));
+    };
+  }
+}
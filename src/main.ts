@@ -1,7 +1,13 @@
 import App from './App.svelte';
+import QuickNote from './QuickNote.svelte';
 import { mount } from 'svelte';
+import { getCurrentWindow } from '@tauri-apps/api/window';
+import { forwardToBackendLog } from './logging';
 
-const app = mount(App, {
+forwardToBackendLog();
+
+// The quick note window shares the page but not the editor
+const app = mount(getCurrentWindow().label === 'quick-note' ? QuickNote : App, {
   target: document.getElementById('app')!,
 });
 
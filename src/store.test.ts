@@ -6,6 +6,9 @@ import {
   deleteTempFile,
   generateTabId,
   ensureTempDir,
+  loadQuickNote,
+  saveQuickNote,
+  withoutFileTabs,
   type Tab,
   type SessionState,
 } from './store';
@@ -75,6 +78,15 @@ describe('store', () => {
     });
   });
 
+  describe('quick note', () => {
+    it('should start empty and keep what was saved', async () => {
+      expect(await loadQuickNote()).toBe('');
+      await saveQuickNote('call the dentist');
+      expect(getMockFile('/mock/app/data/temp/quick-note.txt')).toBe('call the dentist');
+      expect(await loadQuickNote()).toBe('call the dentist');
+    });
+  });
+
   describe('deleteTempFile', () => {
     it('should delete existing temp file', async () => {
       const path = '/mock/app/data/temp/new 1.txt';
@@ -289,6 +301,22 @@ describe('store', () => {
     });
   });
 
+  describe('withoutFileTabs', () => {
+    it('should keep only untitled tabs', () => {
+      const tab = (id: string, path: string | null): Tab => ({
+        id, name: id, path, tempPath: path ? null : `/mock/app/data/temp/${id}.txt`, content: '', savedContent: '', cursorPos: 0,
+      });
+      const session = makeSession({ tabs: [tab('file1', '/a.md'), tab('temp1', null), tab('file2', '/b.md')], activeTabId: 'file1' });
+
+      const kept = withoutFileTabs(session);
+
+      expect(kept.tabs.map((t) => t.id)).toEqual(['temp1']);
+      expect(kept.panes[0].tabIds).toEqual(['temp1']);
+      expect(kept.panes[0].activeTabId).toBe('temp1');
+      expect(session.tabs).toHaveLength(3);
+    });
+  });
+
   describe('saveSession', () => {
     it('should save session to file', async () => {
       const state = makeSession({
@@ -366,6 +394,25 @@ describe('store', () => {
       expect(JSON.parse(saved!).nextTempNumber).toBe(7);
     });
 
+    it('should leave encrypted tabs out', async () => {
+      const state = makeSession({
+        tabs: [
+          { id: 'plain', name: 'todo.md', path: '/home/user/todo.md', tempPath: null, content: 'a', savedContent: 'a', cursorPos: 0 },
+          { id: 'secret', name: 'journal.age', path: '/home/user/journal.age', tempPath: null, content: 'dear diary', savedContent: 'dear diary', cursorPos: 3, encrypted: true },
+        ],
+        activeTabId: 'secret',
+      });
+
+      await saveSession(state);
+
+      const saved = getMockFile('/mock/app/data/session.json')!;
+      expect(saved).not.toContain('journal');
+      const parsed = JSON.parse(saved);
+      expect(parsed.tabs.map((t: Tab) => t.id)).toEqual(['plain']);
+      expect(parsed.panes[0].activeTabId).toBe('plain');
+      expect(state.tabs).toHaveLength(2);
+    });
+
     it('should preserve tab metadata while stripping content', async () => {
       const state = makeSession({
         tabs: [
@@ -1,5 +1,6 @@
 import { BaseDirectory, exists, mkdir, readTextFile, writeTextFile, remove, rename } from '@tauri-apps/plugin-fs';
-import { appDataDir, join } from '@tauri-apps/api/path';
+import { join } from '@tauri-apps/api/path';
+import { invoke } from '@tauri-apps/api/core';
 
 export interface Tab {
   id: string;
@@ -9,6 +10,23 @@ export interface Tab {
   content: string;
   savedContent: string; // to track dirty state
   cursorPos: number;
+  language?: string; // chosen from the Syntax menu, or detected when the file name alone doesn't tell
+  encoding?: string; // encoding the file was read with, reused on save
+  lineEnding?: 'lf' | 'crlf' | 'mixed'; // as detected on read, or as chosen in File > Line Endings
+  spellLanguage?: string; // spelling language chosen for this document, else the OS locale
+  indentation?: { insertSpaces: boolean; tabSize: number; indentSize: number }; // from .editorconfig
+  url?: string; // downloaded from, read-only until saved to a local file
+  remote?: string; // sftp:// URL it was read from and saves back to
+  encrypted?: boolean; // read from an age file, so left out of the session
+  readOnly?: boolean; // opened while another skriv had the file open
+  wrap?: { path: string; segments: WrapSegment[] }; // a file's long lines broken up, read-only; segments map positions back
+}
+
+// A line of a wrapped tab that continues the one before it
+export interface WrapSegment {
+  line: number;
+  originalLine: number;
+  originalColumn: number;
 }
 
 export interface Pane {
@@ -30,9 +48,17 @@ export interface SessionState {
 
 const SESSION_FILE = 'session.json';
 const TEMP_DIR = 'temp';
+const QUICK_NOTE_FILE = 'quick-note.txt';
+
+// The backend decides where data lives, beside the executable when portable
+let dataDirPromise: Promise<string> | null = null;
+export function dataDir(): Promise<string> {
+  dataDirPromise ??= invoke<string>('get_data_dir');
+  return dataDirPromise;
+}
 
 export async function ensureTempDir(): Promise<string> {
-  const appData = await appDataDir();
+  const appData = await dataDir();
   const tempPath = await join(appData, TEMP_DIR);
   
   if (!(await exists(tempPath))) {
@@ -44,7 +70,7 @@ export async function ensureTempDir(): Promise<string> {
 
 export async function loadSession(): Promise<SessionState> {
   try {
-    const appData = await appDataDir();
+    const appData = await dataDir();
     const sessionPath = await join(appData, SESSION_FILE);
     
     if (await exists(sessionPath)) {
@@ -98,9 +124,28 @@ export async function loadSession(): Promise<SessionState> {
   };
 }
 
-export async function saveSession(state: SessionState): Promise<void> {
+function keepTabs(session: SessionState, keep: (tab: Tab) => boolean): SessionState {
+  const tabs = session.tabs.filter(keep);
+  const kept = new Set(tabs.map((t) => t.id));
+  const panes = session.panes.map((p) => {
+    const tabIds = p.tabIds.filter((id) => kept.has(id));
+    const activeTabId = p.activeTabId && kept.has(p.activeTabId) ? p.activeTabId : (tabIds[0] ?? null);
+    return { ...p, tabIds, activeTabId };
+  });
+  return { ...session, tabs, panes };
+}
+
+// Keeps only the untitled tabs, whose content lives nowhere else, for a
+// launch that shouldn't bring back the files that were open
+export function withoutFileTabs(session: SessionState): SessionState {
+  return keepTabs(session, (t) => !t.path);
+}
+
+export async function saveSession(session: SessionState): Promise<void> {
+  // Encrypted documents leave no trace here, not even their names
+  const state = keepTabs(session, (t) => !t.encrypted);
   try {
-    const appData = await appDataDir();
+    const appData = await dataDir();
     
     // Ensure app data dir exists
     if (!(await exists(appData))) {
@@ -138,9 +183,10 @@ export async function saveSession(state: SessionState): Promise<void> {
   }
 }
 
-export async function createTempFile(number: number): Promise<string> {
+// `prefix` keeps temp files of secondary windows apart from the main session's
+export async function createTempFile(number: number, prefix?: string): Promise<string> {
   const tempDir = await ensureTempDir();
-  return await join(tempDir, `new ${number}.txt`);
+  return await join(tempDir, prefix ? `${prefix} new ${number}.txt` : `new ${number}.txt`);
 }
 
 export async function deleteTempFile(path: string): Promise<void> {
@@ -153,6 +199,26 @@ export async function deleteTempFile(path: string): Promise<void> {
   }
 }
 
+// The quick note window's text, kept with the temp files so it survives
+// hiding the window and quitting
+export async function loadQuickNote(): Promise<string> {
+  try {
+    const path = await join(await ensureTempDir(), QUICK_NOTE_FILE);
+    return (await exists(path)) ? await readTextFile(path) : '';
+  } catch (e) {
+    console.error('Failed to load quick note:', e);
+    return '';
+  }
+}
+
+export async function saveQuickNote(content: string): Promise<void> {
+  try {
+    await writeTextFile(await join(await ensureTempDir(), QUICK_NOTE_FILE), content);
+  } catch (e) {
+    console.error('Failed to save quick note:', e);
+  }
+}
+
 export function generateTabId(): string {
   return Math.random().toString(36).substring(2, 9);
 }
@@ -5,10 +5,16 @@ export const mockFileSystem: Map<string, string> = new Map();
 
 // Mock Tauri path API
 vi.mock('@tauri-apps/api/path', () => ({
-  appDataDir: vi.fn().mockResolvedValue('/mock/app/data'),
   join: vi.fn((...paths: string[]) => Promise.resolve(paths.join('/'))),
 }));
 
+// Mock the backend's data dir
+vi.mock('@tauri-apps/api/core', () => ({
+  invoke: vi.fn((command: string) =>
+    command === 'get_data_dir' ? Promise.resolve('/mock/app/data') : Promise.reject(new Error(`Unmocked command: ${command}`)),
+  ),
+}));
+
 // Mock Tauri fs plugin
 vi.mock('@tauri-apps/plugin-fs', () => ({
   exists: vi.fn((path: string) => Promise.resolve(mockFileSystem.has(path))),